@@ -1,88 +1,186 @@
-use clap::{Parser, ValueEnum};
-use parser::{Operation, ParseError, bin_format, csv_format, text_format};
+use clap::Parser;
+use parser::gzip::decompress_if_gzip;
+use parser::{DiffTextOptions, Operation, OperationFilter, ParseOptions, bin_format};
+use parser_cli::Format;
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{BufReader, Read};
-
-#[derive(Debug, Clone, ValueEnum)]
-enum Format {
-    Bin,
-    Csv,
-    Txt,
-}
+use std::io::{self, BufRead, BufReader};
 
 #[derive(Parser)]
 #[command(name = "comparer")]
 #[command(about = "Compare two YPBank operation files")]
 struct Args {
-    #[arg(long, help = "First file path")]
+    #[arg(long, help = "First file path, or - for stdin")]
     file1: String,
 
     #[arg(long, help = "First file format")]
     format1: Format,
 
-    #[arg(long, help = "Second file path")]
+    #[arg(long, help = "Second file path, or - for stdin")]
     file2: String,
 
     #[arg(long, help = "Second file format")]
     format2: Format,
+
+    #[arg(long, help = "Ignore operations with AMOUNT below this value")]
+    min_amount: Option<i64>,
+
+    #[arg(long, help = "Ignore operations with TIMESTAMP (ms) before this value")]
+    since: Option<u64>,
+
+    #[arg(long, help = "Ignore operations with TIMESTAMP (ms) after this value")]
+    until: Option<u64>,
+
+    #[arg(
+        long,
+        help = "In bin files, skip corrupt records up to the next YPBN magic instead of failing; \
+                skipped regions are listed on stderr"
+    )]
+    skip_corrupt: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "For records present in both files, print every differing field with both values"
+    )]
+    verbose: bool,
+
+    #[arg(long, value_name = "N", help = "Print at most N differences")]
+    limit: Option<usize>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["verbose", "limit"],
+        help = "Print the differences as JSON: records only on one side and changed fields"
+    )]
+    json: bool,
 }
 
+impl Args {
+    fn filter(&self) -> Option<OperationFilter> {
+        if self.min_amount.is_none() && self.since.is_none() && self.until.is_none() {
+            return None;
+        }
+
+        let mut filter = OperationFilter::new();
+        if let Some(min) = self.min_amount {
+            filter = filter.min_amount(min);
+        }
+        if let Some(since) = self.since {
+            filter = filter.since(since);
+        }
+        if let Some(until) = self.until {
+            filter = filter.until(until);
+        }
+        Some(filter)
+    }
+}
+
+/// File path that means stdin
+const STDIN: &str = "-";
+
+/// Exit code when the files differ
+const EXIT_DIFFERENT: i32 = 1;
+/// Exit code when a file can't be read or parsed
+const EXIT_ERROR: i32 = 2;
+
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(EXIT_DIFFERENT),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
     }
 }
 
-fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// Returns `true` when the files hold the same operations
+fn run() -> Result<bool, Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if args.file1 == STDIN && args.file2 == STDIN {
+        return Err("only one of --file1 and --file2 can be - (stdin)".into());
+    }
+
+    // Read both files, errors already carry the path
+    let mut operations1 = read(&args.file1, args.format1.clone(), args.skip_corrupt)?;
+    let mut operations2 = read(&args.file2, args.format2.clone(), args.skip_corrupt)?;
+    let (name1, name2) = (input_name(&args.file1), input_name(&args.file2));
 
-    // Read first file
-    let file1 = File::open(&args.file1).map_err(|err| {
-        eprintln!("Can't open file1 by specific path: {}", &args.file1);
-        err
-    })?;
-    let reader1 = BufReader::new(file1);
-    let operations1 = parse_file(reader1, &args.format1)?;
-
-    // Read second file
-    let file2 = File::open(&args.file2).map_err(|err| {
-        eprintln!("Can't open file2 by specific path: {}", &args.file2);
-        err
-    })?;
-    let reader2 = BufReader::new(file2);
-    let operations2 = parse_file(reader2, &args.format2)?;
-
-    // Compare
-    if operations1.len() != operations2.len() {
-        println!(
-            "Files differ: {} has {} operations, {} has {} operations",
-            args.file1,
-            operations1.len(),
-            args.file2,
-            operations2.len()
+    // Filter both sides the same way and say how much was dropped
+    if let Some(filter) = args.filter() {
+        let excluded1 = filter.retain(&mut operations1);
+        let excluded2 = filter.retain(&mut operations2);
+        let note = format!(
+            "Excluded by filters: {} from '{}', {} from '{}'",
+            excluded1, name1, excluded2, name2
         );
-        return Ok(());
+        // With --json, stdout carries only the JSON
+        if args.json {
+            eprintln!("{}", note);
+        } else {
+            println!("{}", note);
+        }
     }
 
-    for operation in operations1.difference(&operations2) {
-        println!("Operation with tx_id {} differs", operation.tx_id);
-        return Ok(());
+    let diff = parser::diff(&operations1, &operations2);
+    if args.json {
+        println!("{}", diff.to_json());
+    } else {
+        let options = DiffTextOptions {
+            left_name: name1.to_string(),
+            right_name: name2.to_string(),
+            verbose: args.verbose,
+            limit: args.limit,
+        };
+        print!("{}", diff.to_text(&options));
     }
+    Ok(diff.is_identical())
+}
 
-    println!(
-        "The operation records in '{}' and '{}' are identical.",
-        args.file1, args.file2
-    );
+/// Reads a file; with `skip_corrupt` bin files are read with recovery
+fn read(
+    path: &str,
+    format: Format,
+    skip_corrupt: bool,
+) -> Result<HashSet<Operation>, Box<dyn std::error::Error>> {
+    let reader = open_input(path)?;
+    let name = input_name(path);
+    if !skip_corrupt || !matches!(format, Format::Bin) {
+        let format = parser::Format::from(format);
+        return Ok(format
+            .parse_all_with_options(reader, &ParseOptions::default())
+            .map_err(|e| format!("{}: {}", name, e))?);
+    }
 
-    Ok(())
+    let (operations, errors) =
+        bin_format::parse_all_with_recovery(reader, &ParseOptions::default())
+            .map_err(|e| format!("{}: {}", name, e))?;
+    if !errors.is_empty() {
+        let skipped: u64 = errors.iter().map(|e| e.skipped).sum();
+        eprintln!(
+            "Skipped {} corrupt regions ({} bytes) in '{}':",
+            errors.len(),
+            skipped,
+            name
+        );
+        for error in &errors {
+            eprintln!("  {}", error);
+        }
+    }
+    Ok(operations)
 }
 
-fn parse_file<R: Read>(reader: R, format: &Format) -> Result<HashSet<Operation>, ParseError> {
-    match format {
-        Format::Bin => bin_format::parse_all(reader),
-        Format::Csv => csv_format::parse_all(reader),
-        Format::Txt => text_format::parse_all(reader),
+/// Opens a file, or stdin for `-`, decompressing gzip on the fly
+fn open_input(path: &str) -> Result<Box<dyn BufRead>, String> {
+    if path == STDIN {
+        return decompress_if_gzip(io::stdin().lock()).map_err(|e| format!("stdin: {}", e));
     }
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    decompress_if_gzip(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Input name for messages
+fn input_name(path: &str) -> &str {
+    if path == STDIN { "stdin" } else { path }
 }
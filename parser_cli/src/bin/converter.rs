@@ -1,72 +1,1090 @@
 use clap::{Parser, ValueEnum};
-use parser::{Operation, ParseError, bin_format, csv_format, text_format};
+use parser::bin_format;
+use parser::gzip::decompress_if_gzip;
+#[cfg(feature = "count-allocations")]
+use parser::metrics::CountingAllocator;
+use parser::metrics::PrometheusTextSink;
+use parser::timestamp::{TimeZoneSpec, parse_rfc3339};
+use parser::transform::{
+    MissingUserPolicy, Pipeline, RedactDescription, RemapUsers, RescaleTimestamps,
+    TruncateDescription, read_user_map,
+};
+use parser::verify::verify_conversion_with_options;
+use parser::{
+    AmountStyle, CsvOptions, MergePolicy, Operation, OperationFilter, OperationFormat,
+    OperationStatus, OperationType, ParseError, ParseOptions, Progress, RedactPolicy, SortField,
+    SortKey, TimestampUnit, WriteOptions, merge_ordered, parse_all_lossy, partition,
+    sort_operations, write_file,
+};
+use parser_cli::Format;
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Путь входа, означающий stdin
+const STDIN: &str = "-";
+
+/// Код выхода, если часть записей ушла в карантин
+const EXIT_QUARANTINED: i32 = 2;
+
+// Нужен для счетчиков выделений памяти в --metrics-out; без фичи они нулевые
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Unit {
+    Millis,
+    Seconds,
+    Auto,
+}
+
+impl From<Unit> for TimestampUnit {
+    fn from(unit: Unit) -> Self {
+        match unit {
+            Unit::Millis => TimestampUnit::Millis,
+            Unit::Seconds => TimestampUnit::Seconds,
+            Unit::Auto => TimestampUnit::Auto,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum MissingUser {
+    Error,
+    Keep,
+    Drop,
+}
+
+impl From<MissingUser> for MissingUserPolicy {
+    fn from(missing: MissingUser) -> Self {
+        match missing {
+            MissingUser::Error => MissingUserPolicy::Error,
+            MissingUser::Keep => MissingUserPolicy::Keep,
+            MissingUser::Drop => MissingUserPolicy::Drop,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum TxType {
+    Deposit,
+    Transfer,
+    Withdrawal,
+}
+
+impl From<TxType> for OperationType {
+    fn from(tx_type: TxType) -> Self {
+        match tx_type {
+            TxType::Deposit => OperationType::Deposit,
+            TxType::Transfer => OperationType::Transfer,
+            TxType::Withdrawal => OperationType::Withdrawal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Status {
+    Success,
+    Failure,
+    Pending,
+}
+
+impl From<Status> for OperationStatus {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Success => OperationStatus::Success,
+            Status::Failure => OperationStatus::Failure,
+            Status::Pending => OperationStatus::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SortBy {
+    TxId,
+    Timestamp,
+    Amount,
+}
+
+impl From<SortBy> for SortField {
+    fn from(sort_by: SortBy) -> Self {
+        match sort_by {
+            SortBy::TxId => SortField::TxId,
+            SortBy::Timestamp => SortField::Timestamp,
+            SortBy::Amount => SortField::Amount,
+        }
+    }
+}
 
 #[derive(Debug, Clone, ValueEnum)]
-enum Format {
-    Bin,
-    Csv,
-    Txt,
+enum Policy {
+    /// Stop on the first conflict
+    Fail,
+    /// Keep the record from the input given first
+    PreferFirst,
+    /// Keep the record from the input given last
+    PreferLast,
+    /// Keep the record with the later TIMESTAMP (the earlier input on a tie)
+    PreferNewest,
+}
+
+impl From<Policy> for MergePolicy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::Fail => MergePolicy::FailOnConflict,
+            Policy::PreferFirst => MergePolicy::PreferFirst,
+            Policy::PreferLast => MergePolicy::PreferLast,
+            Policy::PreferNewest => MergePolicy::PreferNewestTimestamp,
+        }
+    }
 }
 
 #[derive(Parser)]
 #[command(name = "converter")]
 #[command(about = "Convert YPBank operation files between formats")]
 struct Args {
-    #[arg(short, long, help = "Input file path")]
-    input: String,
+    #[arg(
+        short,
+        long,
+        required = true,
+        num_args = 1..,
+        help = "Input file path, or - for stdin; repeat it (or pass a glob) to join several \
+                files into one output, in the order given"
+    )]
+    input: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Input format (detected from the content or the file extension if omitted)"
+    )]
+    input_format: Option<Format>,
+
+    #[arg(
+        long,
+        required_unless_present_any = ["inspect", "print_digest"],
+        help = "Output format"
+    )]
+    output_format: Option<Format>,
+
+    #[arg(
+        short,
+        long,
+        help = "Output file path (stdout if omitted); written to a temp file and renamed on success"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "output",
+        help = "Overwrite the output file if it exists"
+    )]
+    force: bool,
 
-    #[arg(long, help = "Input format")]
-    input_format: Format,
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = [
+            "inspect", "print_digest", "user_map", "transform",
+            "filter_type", "filter_status", "user", "since", "until", "redact",
+        ],
+        help = "Re-read the output file and check it matches the input; remove it on mismatch"
+    )]
+    verify: bool,
 
-    #[arg(long, help = "Output format")]
-    output_format: Format,
+    #[arg(
+        long,
+        conflicts_with_all = ["inspect", "verify"],
+        help = "Convert only valid records; write rejected ones here with the reason (exit code 2 if any)"
+    )]
+    quarantine: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["quarantine", "inspect", "verify"],
+        help = "Convert only valid records and skip the rest, with a count on stderr (--verbose lists them)"
+    )]
+    skip_invalid: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "fail",
+        help = "With several inputs, what to do when they disagree on the fields of a TX_ID"
+    )]
+    merge_policy: Policy,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["inspect", "verify"],
+        help = "With several inputs, skip a file that can't be opened or parsed and convert the \
+                rest (exit code 2 if any); pair with --skip-invalid to also skip bad records"
+    )]
+    continue_on_error: bool,
+
+    #[arg(
+        long,
+        requires = "tx_id",
+        help = "Print one record as debug JSON instead of converting"
+    )]
+    inspect: bool,
+
+    #[arg(long, help = "TX_ID of the record to --inspect")]
+    tx_id: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with = "inspect",
+        help = "Print the format-independent SHA-256 digest of the input instead of converting"
+    )]
+    print_digest: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "millis",
+        help = "TIMESTAMP unit on the csv/txt side (auto: values below 10^12 are seconds; writes millis)"
+    )]
+    timestamp_unit: Unit,
+
+    #[arg(
+        long,
+        help = "AMOUNT on the csv/txt side is a decimal like 1050.25 (at most two fraction digits) instead of minor units"
+    )]
+    decimal_amounts: bool,
+
+    #[arg(
+        long,
+        help = "Write csv/txt TIMESTAMP as RFC 3339 in this zone: UTC, +03:00 or Europe/Moscow"
+    )]
+    timezone: Option<TimeZoneSpec>,
+
+    #[arg(
+        long,
+        default_value = ",",
+        value_parser = parse_delimiter,
+        help = "Field delimiter on the csv side: one character, or \"tab\" for TSV"
+    )]
+    delimiter: char,
+
+    #[arg(
+        long,
+        help = "Fail on unknown txt keys and csv columns instead of silently dropping them"
+    )]
+    strict: bool,
+
+    #[arg(
+        long,
+        help = "Read unknown TX_TYPE and STATUS values (from a newer system) as is instead of \
+                failing; they are written back unchanged"
+    )]
+    lenient: bool,
+
+    #[arg(
+        long,
+        help = "Append a CRC32 to every bin record (v2 layout) so bit rot is caught on read"
+    )]
+    checksum: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Print details about the input (schema version, record count) to stderr"
+    )]
+    verbose: bool,
+
+    #[arg(long, help = "CSV file with old_id,new_id rows to rewrite user ids")]
+    user_map: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "error",
+        help = "What to do with user ids missing from --user-map"
+    )]
+    user_map_missing: MissingUser,
+
+    #[arg(
+        long,
+        value_name = "NAME=ARG",
+        help = "Transform each record, in the order given (repeatable): remap-users=FILE, \
+                redact=TEXT, truncate=BYTES, rescale=seconds|auto, min-amount=N, max-amount=N, \
+                since=MS, until=MS"
+    )]
+    transform: Vec<String>,
+
+    #[arg(
+        long,
+        requires = "redact_key",
+        help = "Anonymize for sharing: pseudonymize user ids and hash descriptions with --redact-key, \
+                drop extensions"
+    )]
+    redact: bool,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        requires = "redact",
+        help = "Secret for --redact; the same key maps each user to the same pseudonym"
+    )]
+    redact_key: Option<String>,
+
+    #[arg(
+        long,
+        requires = "redact",
+        help = "With --redact, round TIMESTAMP down to the hour"
+    )]
+    redact_hour: bool,
+
+    #[arg(
+        long,
+        value_name = "MINOR_UNITS",
+        requires = "redact",
+        value_parser = clap::value_parser!(i64).range(1..),
+        help = "With --redact, round AMOUNT to a multiple of this (a nonzero amount stays nonzero)"
+    )]
+    redact_amount_step: Option<i64>,
+
+    #[arg(long, value_enum, help = "Keep only records of this TX_TYPE")]
+    filter_type: Option<TxType>,
+
+    #[arg(long, value_enum, help = "Keep only records with this STATUS")]
+    filter_status: Option<Status>,
+
+    #[arg(
+        long,
+        help = "Keep only records involving this user, as FROM_USER_ID or TO_USER_ID"
+    )]
+    user: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = parse_time,
+        help = "Keep only records with TIMESTAMP at or after TIME (ms or RFC 3339)"
+    )]
+    since: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = parse_time,
+        help = "Keep only records with TIMESTAMP at or before TIME (ms or RFC 3339)"
+    )]
+    until: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Sort records before writing (stable; by default the input order is kept)"
+    )]
+    sort_by: Option<SortBy>,
+
+    #[arg(long, requires = "sort_by", help = "Sort in descending order")]
+    descending: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "quarantine",
+        help = "Write parse throughput and allocation counters here in Prometheus text format \
+                (allocation counters need the count-allocations feature)"
+    )]
+    metrics_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["quarantine", "skip_invalid", "inspect", "verify"],
+        help = "Draw a progress bar on stderr while reading each input"
+    )]
+    progress: bool,
+}
+
+impl Args {
+    /// Условия --filter-type, --filter-status, --user, --since и --until
+    fn filter(&self) -> OperationFilter {
+        let mut filter = OperationFilter::new();
+        if let Some(tx_type) = self.filter_type.clone() {
+            filter = filter.tx_type(tx_type.into());
+        }
+        if let Some(status) = self.filter_status.clone() {
+            filter = filter.status(status.into());
+        }
+        if let Some(user) = self.user {
+            filter = filter.user(user);
+        }
+        if let Some(since) = self.since {
+            filter = filter.since(since);
+        }
+        if let Some(until) = self.until {
+            filter = filter.until(until);
+        }
+        filter
+    }
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    match run() {
+        Ok(true) => std::process::exit(EXIT_QUARANTINED),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// Возвращает `true`, если часть записей ушла в карантин или часть входов пропущена
+fn run() -> Result<bool, Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    // Читаем с файла
-    let file = File::open(&args.input).map_err(|err| {
-        eprintln!("Can't open file by specific path: {}", &args.input);
-        err
-    })?;
-    let reader = BufReader::new(file);
-    let operations = parse_input(reader, &args.input_format)?;
+    let inputs = args.input.clone();
+    if inputs.iter().filter(|input| *input == STDIN).count() > 1 {
+        return Err("- (stdin) can be given as --input only once".into());
+    }
+    if inputs.len() > 1 && (args.inspect || args.verify || args.quarantine.is_some()) {
+        return Err("--inspect, --verify and --quarantine take a single --input".into());
+    }
+
+    if let (true, Some(tx_id)) = (args.inspect, args.tx_id) {
+        let (input_format, reader) = open_detected(&inputs[0], args.input_format.clone())?;
+        return inspect(reader, &inputs[0], input_format, tx_id, args.lenient).map(|()| false);
+    }
+    if let Some(output) = &args.output {
+        for input in &inputs {
+            check_output_path(Path::new(input), output, args.force)?;
+        }
+    }
+    if args.verify && inputs[0] == STDIN {
+        return Err("--verify re-reads the input, which is not possible with stdin".into());
+    }
+    let filter = args.filter();
+    let timestamp_unit = TimestampUnit::from(args.timestamp_unit.clone());
+    let amount_style = if args.decimal_amounts {
+        AmountStyle::Decimal
+    } else {
+        AmountStyle::MinorUnits
+    };
+    let metrics = args
+        .metrics_out
+        .as_ref()
+        .map(|_| Arc::new(PrometheusTextSink::new()));
+    let csv = CsvOptions {
+        delimiter: args.delimiter,
+    };
+    let parse_options = ParseOptions {
+        timestamp_unit,
+        amount_style,
+        csv,
+        strict: args.strict,
+        lenient: args.lenient,
+        metrics: metrics
+            .clone()
+            .map(|sink| sink as Arc<dyn parser::metrics::MetricsSink>),
+        ..Default::default()
+    };
+
+    // Каждый вход со своим форматом; с --continue-on-error нечитаемый вход пропускается
+    let mut per_input = Vec::with_capacity(inputs.len());
+    let mut quarantined = false;
+    let mut skipped_inputs = 0;
+    for input in &inputs {
+        match read_input(input, &args, &parse_options) {
+            Ok((operations, q)) => {
+                per_input.push(operations);
+                quarantined |= q;
+            }
+            Err(e) if args.continue_on_error => {
+                eprintln!("Skipping {}: {}", input_name(input), e);
+                per_input.push(Vec::new());
+                skipped_inputs += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if skipped_inputs > 0 {
+        eprintln!("Skipped {} of {} inputs", skipped_inputs, inputs.len());
+    }
+    let operations = if per_input.len() == 1 {
+        per_input.pop().unwrap_or_default()
+    } else {
+        join_inputs(per_input, &inputs, args.merge_policy.clone().into())?
+    };
+
+    if let (Some(path), Some(sink)) = (&args.metrics_out, &metrics) {
+        sink.write_to(BufWriter::new(File::create(path)?))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    // Отбор идет первым, по исходным id; затем --user-map, затем --transform по порядку
+    let policy = MissingUserPolicy::from(args.user_map_missing);
+    let mut pipeline = Pipeline::new();
+    if !filter.is_empty() {
+        pipeline = pipeline.then(filter);
+    }
+    if let Some(path) = &args.user_map {
+        pipeline = pipeline.then(remap_transform(path, policy)?);
+    }
+    for spec in &args.transform {
+        pipeline = add_transform(pipeline, spec, policy)?;
+    }
+    // Обезличивание последним: отбор и --transform видят настоящие данные
+    if let (true, Some(key)) = (args.redact, &args.redact_key) {
+        pipeline = pipeline.then(RedactPolicy {
+            timestamp_bucket: args.redact_hour.then_some(RedactPolicy::HOUR),
+            amount_step: args.redact_amount_step,
+            ..RedactPolicy::new(key.as_str())
+        });
+    }
+    let mut operations = if pipeline.is_empty() {
+        operations
+    } else {
+        pipeline.apply_all(operations)?
+    };
+    let quarantined = quarantined || skipped_inputs > 0;
+
+    if args.print_digest {
+        let operations: HashSet<Operation> = operations.into_iter().collect();
+        println!("{}", parser::digest_hex(&operations));
+        return Ok(quarantined);
+    }
+    if let Some(sort_by) = args.sort_by.clone() {
+        let key = if args.descending {
+            SortKey::descending(sort_by.into())
+        } else {
+            SortKey::ascending(sort_by.into())
+        };
+        operations = sort_operations(operations, key);
+    }
+    let Some(output_format) = args.output_format.clone() else {
+        unreachable!("--output-format is required without --inspect or --print-digest");
+    };
+
+    let write_options = WriteOptions {
+        timestamp_unit,
+        amount_style,
+        time_zone: args.timezone,
+        csv,
+        checksum: args.checksum,
+        ..Default::default()
+    };
+    let output_format = parser::Format::from(output_format);
+
+    if let Some(output) = &args.output {
+        write_file(output, &operations, output_format, &write_options)?;
+        if args.verify {
+            let (input_format, _) = open_detected(&inputs[0], args.input_format.clone())?;
+            verify_output(
+                &inputs[0],
+                input_format,
+                output,
+                output_format,
+                &parse_options,
+            )?;
+        }
+        return Ok(quarantined);
+    }
 
     // Пишем сразу в stdout
     let stdout = io::stdout();
-    let writer = BufWriter::new(stdout.lock());
-    write_output(writer, &operations, &args.output_format)?;
+    let mut writer = BufWriter::new(stdout.lock());
+    output_format.write_all_with_options(&mut writer, &operations, &write_options)?;
+
+    Ok(quarantined)
+}
+
+/// Добавляет в цепочку встроенный шаг по описанию `имя=аргумент`
+fn add_transform(
+    pipeline: Pipeline,
+    spec: &str,
+    policy: MissingUserPolicy,
+) -> Result<Pipeline, Box<dyn std::error::Error>> {
+    let (name, arg) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("--transform {}: expected NAME=ARG", spec))?;
+    let number = |what: &str| -> Result<i64, String> {
+        arg.parse()
+            .map_err(|_| format!("--transform {}: {} must be a number", spec, what))
+    };
+    let millis = |what: &str| -> Result<u64, String> {
+        arg.parse()
+            .map_err(|_| format!("--transform {}: {} must be milliseconds", spec, what))
+    };
+
+    let pipeline = match name {
+        "remap-users" => pipeline.then(remap_transform(arg, policy)?),
+        "redact" => pipeline.then(RedactDescription {
+            replacement: arg.to_string(),
+        }),
+        "truncate" => pipeline.then(TruncateDescription {
+            max_len: arg
+                .parse()
+                .map_err(|_| format!("--transform {}: length must be a number", spec))?,
+            ellipsis: "...".to_string(),
+        }),
+        "rescale" => pipeline.then(RescaleTimestamps {
+            unit: match arg {
+                "seconds" => TimestampUnit::Seconds,
+                "auto" => TimestampUnit::Auto,
+                _ => return Err(format!("--transform {}: expected seconds or auto", spec).into()),
+            },
+        }),
+        "min-amount" => pipeline.then(OperationFilter::new().min_amount(number("amount")?)),
+        "max-amount" => pipeline.then(OperationFilter::new().max_amount(number("amount")?)),
+        "since" => pipeline.then(OperationFilter::new().since(millis("since")?)),
+        "until" => pipeline.then(OperationFilter::new().until(millis("until")?)),
+        _ => return Err(format!("--transform {}: unknown transform '{}'", spec, name).into()),
+    };
+    Ok(pipeline)
+}
+
+/// Открывает вход и определяет его формат
+///
+/// Первые байты нужны для определения формата; потом они читаются заново.
+fn open_detected(
+    input: &str,
+    flag: Option<Format>,
+) -> Result<(parser::Format, impl Read + use<>), Box<dyn std::error::Error>> {
+    let mut reader = open_input(input)?;
+    let mut prefix = Vec::new();
+    (&mut reader)
+        .take(parser::SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("{}: {}", input_name(input), e))?;
+    let format = choose_input_format(input, &prefix, flag)?;
+    Ok((format, Cursor::new(prefix).chain(reader)))
+}
+
+/// Читает один вход так, как велят --quarantine и --skip-invalid
+///
+/// # Возвращает
+/// Операции в порядке файла и признак того, что что-то ушло в карантин
+fn read_input(
+    input: &str,
+    args: &Args,
+    parse_options: &ParseOptions,
+) -> Result<(Vec<Operation>, bool), Box<dyn std::error::Error>> {
+    let (input_format, reader) = open_detected(input, args.input_format.clone())?;
+
+    if let Some(path) = &args.quarantine {
+        return read_with_quarantine(
+            reader,
+            input,
+            input_format,
+            parse_options,
+            path,
+            args.verbose,
+        );
+    }
+    if args.skip_invalid {
+        let operations =
+            read_skipping_invalid(reader, input, input_format, parse_options, args.verbose)?;
+        return Ok((operations, false));
+    }
+
+    // Читаем в порядке записей, в ошибке — путь или stdin
+    let parsed = if args.progress {
+        let options = ParseOptions {
+            known_size: input_size(input),
+            ..parse_options.clone()
+        };
+        let parsed = input_format.parse_all_ordered_with_progress(reader, &options, |progress| {
+            draw_progress(input, progress);
+            ControlFlow::Continue(())
+        });
+        eprintln!();
+        parsed
+    } else {
+        input_format.parse_all_ordered(reader, parse_options)
+    };
+    let (operations, report) = parsed.map_err(|e| format!("{}: {}", input_name(input), e))?;
+    for warning in &report.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if args.verbose {
+        if args.input.len() > 1 {
+            eprintln!("Input: {}", input_name(input));
+        }
+        eprintln!("Schema version: {}", report.schema_version);
+        eprintln!("Records: {}", report.records);
+        if let Some(declared) = report.declared_records {
+            eprintln!("Records declared in the file header: {}", declared);
+        }
+        eprintln!(
+            "Read {} bytes in {:.3}s ({:.0} records/s)",
+            report.bytes,
+            report.duration.as_secs_f64(),
+            report.records_per_sec()
+        );
+    }
+    Ok((operations, false))
+}
+
+/// Склеивает входы по порядку, повторы TX_ID между ними решает `policy`
+fn join_inputs(
+    per_input: Vec<Vec<Operation>>,
+    inputs: &[String],
+    policy: MergePolicy,
+) -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+    let result = match merge_ordered(per_input, policy) {
+        Ok(result) => result,
+        Err(ParseError::MergeConflict {
+            tx_id,
+            first,
+            second,
+            fields,
+        }) => {
+            return Err(format!(
+                "tx_id {} differs between {} and {} ({}); pass --merge-policy to pick a side",
+                tx_id,
+                input_name(&inputs[first]),
+                input_name(&inputs[second]),
+                fields.join(", ")
+            )
+            .into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    for conflict in &result.conflicts {
+        let differences: Vec<String> = conflict.differences.iter().map(|d| d.to_string()).collect();
+        eprintln!(
+            "tx_id {}: kept {}, dropped {} ({})",
+            conflict.tx_id,
+            input_name(&inputs[conflict.kept]),
+            input_name(&inputs[conflict.dropped]),
+            differences.join(", ")
+        );
+    }
+    Ok(result.operations)
+}
+
+fn remap_transform(
+    path: &str,
+    policy: MissingUserPolicy,
+) -> Result<RemapUsers, Box<dyn std::error::Error>> {
+    let map = read_user_map(File::open(path).map_err(|e| format!("{}: {}", path, e))?)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    Ok(RemapUsers { map, policy })
+}
+
+/// Читает только годные записи, отбракованные пишет в `quarantine` с причинами
+///
+/// # Возвращает
+/// Годные операции в порядке файла (повторы TX_ID отброшены) и признак того,
+/// что что-то ушло в карантин
+fn read_with_quarantine(
+    reader: impl Read,
+    input: &str,
+    format: parser::Format,
+    options: &ParseOptions,
+    quarantine: &Path,
+    verbose: bool,
+) -> Result<(Vec<Operation>, bool), Box<dyn std::error::Error>> {
+    let mut outcome =
+        partition(reader, format, options).map_err(|e| format!("{}: {}", input_name(input), e))?;
+    for rejected in &mut outcome.rejected {
+        rejected.provenance.source = Some(input_name(input).to_string());
+    }
+
+    let file = File::create(quarantine).map_err(|e| format!("{}: {}", quarantine.display(), e))?;
+    outcome
+        .write_quarantine(BufWriter::new(file))
+        .map_err(|e| format!("{}: {}", quarantine.display(), e))?;
 
+    if verbose {
+        eprintln!("Accepted: {}", outcome.accepted.len());
+    }
+    if !outcome.is_clean() {
+        eprintln!(
+            "Quarantined {} records to {}",
+            outcome.rejected.len(),
+            quarantine.display()
+        );
+    }
+
+    let quarantined = !outcome.is_clean();
+    let mut seen = HashSet::new();
+    let accepted = outcome
+        .accepted
+        .into_iter()
+        .filter(|op| seen.insert(op.tx_id))
+        .collect();
+    Ok((accepted, quarantined))
+}
+
+/// Читает только годные записи; сколько пропущено, пишет в stderr
+///
+/// С `verbose` каждая пропущенная запись печатается с местом и причиной.
+fn read_skipping_invalid(
+    reader: impl Read,
+    input: &str,
+    format: parser::Format,
+    options: &ParseOptions,
+    verbose: bool,
+) -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+    let outcome = parse_all_lossy(reader, format, options)
+        .map_err(|e| format!("{}: {}", input_name(input), e))?;
+
+    if verbose {
+        for (location, error) in &outcome.errors {
+            eprintln!("{}: {}: {}", input_name(input), location, error);
+        }
+    }
+    eprintln!(
+        "Skipped {} of {} records",
+        group_thousands(outcome.errors.len()),
+        group_thousands(outcome.records)
+    );
+    Ok(outcome.operations)
+}
+
+/// `1000204` -> `1,000,204`
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Проверяет, что вывод не затрет чужой файл или сам вход
+///
+/// Проверка до чтения входа, чтобы не тратить время на конвертацию впустую.
+fn check_output_path(input: &Path, output: &Path, force: bool) -> Result<(), String> {
+    if !output.exists() {
+        return Ok(());
+    }
+    let same = match (input.canonicalize(), output.canonicalize()) {
+        (Ok(input), Ok(output)) => input == output,
+        _ => false,
+    };
+    if same {
+        return Err(format!(
+            "{}: output is the same file as the input",
+            output.display()
+        ));
+    }
+    if !force {
+        return Err(format!(
+            "{}: file exists, pass --force to overwrite it",
+            output.display()
+        ));
+    }
     Ok(())
 }
 
-fn parse_input<R: Read>(reader: R, format: &Format) -> Result<HashSet<Operation>, ParseError> {
-    match format {
-        Format::Bin => bin_format::parse_all(reader),
-        Format::Csv => csv_format::parse_all(reader),
-        Format::Txt => text_format::parse_all(reader),
+/// Время для --since/--until: миллисекунды или RFC 3339
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(millis) = value.parse() {
+        return Ok(millis);
     }
+    parse_rfc3339(value).map_err(|_| {
+        format!(
+            "expected milliseconds or RFC 3339 (2021-09-01T00:00:00Z), got {:?}",
+            value
+        )
+    })
 }
 
-fn write_output<W: Write>(
-    writer: W,
-    operations: &HashSet<Operation>,
-    format: &Format,
-) -> Result<(), ParseError> {
-    match format {
-        Format::Bin => bin_format::write_all(writer, operations),
-        Format::Csv => csv_format::write_all(writer, operations),
-        Format::Txt => text_format::write_all(writer, operations),
+/// Разделитель CSV из аргумента: один символ или `tab`
+fn parse_delimiter(value: &str) -> Result<char, String> {
+    if value.eq_ignore_ascii_case("tab") || value == "\\t" {
+        return Ok('\t');
+    }
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!(
+            "expected one character or \"tab\", got {:?}",
+            value
+        )),
+    }
+}
+
+/// Перечитывает только что записанный файл и сверяет его с исходным
+///
+/// При несовпадении (или если файл не читается) удаляет результат, чтобы его
+/// не приняли за корректную копию.
+fn verify_output(
+    input: &str,
+    input_format: parser::Format,
+    output: &Path,
+    output_format: parser::Format,
+    options: &ParseOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    let result = open(Path::new(input)).and_then(|original| {
+        let converted = open(output)?;
+        verify_conversion_with_options(original, input_format, converted, output_format, options)
+            .map_err(|e| format!("verification failed: {}", e))
+    });
+
+    match result {
+        Ok(report) if report.is_ok() => {
+            eprintln!("Verified: {}", report);
+            Ok(())
+        }
+        Ok(report) => {
+            let _ = std::fs::remove_file(output);
+            Err(format!(
+                "{} removed, verification failed: {}",
+                output.display(),
+                report
+            )
+            .into())
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(output);
+            Err(format!("{} removed, {}", output.display(), e).into())
+        }
     }
 }
+
+/// Находит запись по TX_ID и печатает ее в виде отладочного JSON
+fn inspect(
+    reader: impl Read,
+    input: &str,
+    format: parser::Format,
+    tx_id: u64,
+    lenient: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let options = ParseOptions {
+        lenient,
+        ..Default::default()
+    };
+    let found = match format {
+        // Бинарник читаем потоком и останавливаемся на первой подходящей записи
+        parser::Format::Bin => {
+            let mut found = None;
+            for op in bin_format::iter_operations_with_options(reader, &options) {
+                let op = op.map_err(|e| format!("{}: {}", input_name(input), e))?;
+                if op.tx_id == tx_id {
+                    found = Some(op);
+                    break;
+                }
+            }
+            found
+        }
+        _ => format
+            .parse_all_with_options(reader, &options)
+            .map_err(|e| format!("{}: {}", input_name(input), e))?
+            .into_iter()
+            .find(|op| op.tx_id == tx_id),
+    };
+
+    match found {
+        Some(op) => {
+            println!("{}", op.to_debug_json());
+            Ok(())
+        }
+        None => Err(format!("{}: no operation with tx_id {}", input_name(input), tx_id).into()),
+    }
+}
+
+/// Формат входа: `--input-format`, если задан, иначе по содержимому, а затем по расширению
+///
+/// Содержимое надежнее расширения: `.csv`, в котором лежит бинарник, читается как бинарник.
+/// Если флаг расходится с тем, что удалось определить, побеждает флаг, а в stderr
+/// уходит предупреждение.
+fn choose_input_format(
+    input: &str,
+    prefix: &[u8],
+    flag: Option<Format>,
+) -> Result<parser::Format, String> {
+    let by_content = parser::detect_format(prefix);
+    let by_extension = match input {
+        STDIN => None,
+        _ => parser::Format::from_extension(Path::new(input)),
+    };
+    let detected = by_content.as_ref().ok().copied().or(by_extension);
+
+    match (flag.map(parser::Format::from), detected) {
+        (Some(flag), Some(detected)) => {
+            if flag != detected {
+                eprintln!(
+                    "Warning: {} looks like {}, reading it as {} as --input-format says",
+                    input_name(input),
+                    detected.name(),
+                    flag.name()
+                );
+            }
+            Ok(flag)
+        }
+        (Some(flag), None) => Ok(flag),
+        (None, Some(detected)) => Ok(detected),
+        (None, None) => {
+            let content = match by_content {
+                Err(ParseError::InvalidFormat(reason)) => reason,
+                Err(e) => e.to_string(),
+                Ok(_) => unreachable!("detected content is used above"),
+            };
+            Err(format!(
+                "{}: can't detect the input format. By content: {}. By extension: not one of \
+                 .bin, .csv, .txt, .json, .jsonl. Pass --input-format",
+                input_name(input),
+                content
+            ))
+        }
+    }
+}
+
+/// Открывает вход: файл или stdin для `-`; сжатый gzip распаковывается на лету
+///
+/// stdin не перематывается, но все парсеры читают через `Read`, так что этого хватает.
+fn open_input(input: &str) -> Result<Box<dyn BufRead>, String> {
+    if input == STDIN {
+        return decompress_if_gzip(io::stdin().lock()).map_err(|e| format!("stdin: {}", e));
+    }
+    let file = File::open(input).map_err(|e| format!("{}: {}", input, e))?;
+    decompress_if_gzip(BufReader::new(file)).map_err(|e| format!("{}: {}", input, e))
+}
+
+/// Длина входного файла для доли в --progress; у stdin и сжатого файла ее нет
+fn input_size(input: &str) -> Option<u64> {
+    if input == STDIN || parser::gzip::is_gzip_path(Path::new(input)) {
+        return None;
+    }
+    std::fs::metadata(input).ok().map(|meta| meta.len())
+}
+
+/// Перерисовывает строку --progress: полоса с процентами, если длина входа
+/// известна, иначе только счетчики
+fn draw_progress(input: &str, progress: Progress) {
+    const WIDTH: usize = 30;
+    match progress.fraction() {
+        Some(fraction) => {
+            let filled = (fraction * WIDTH as f64) as usize;
+            eprint!(
+                "\r{} [{}{}] {:3.0}% {} records",
+                input_name(input),
+                "#".repeat(filled),
+                ".".repeat(WIDTH - filled),
+                fraction * 100.0,
+                progress.records
+            );
+        }
+        None => eprint!(
+            "\r{}: {} records, {} bytes",
+            input_name(input),
+            progress.records,
+            progress.bytes
+        ),
+    }
+}
+
+/// Имя входа для сообщений об ошибках
+fn input_name(input: &str) -> &str {
+    if input == STDIN { "stdin" } else { input }
+}
@@ -0,0 +1,249 @@
+use clap::Parser;
+use parser::testgen::rand::{SeedableRng, rngs::StdRng};
+use parser::testgen::{GenProfile, generate_operation};
+use parser::timestamp::parse_rfc3339;
+use parser::{FormatWriter, OperationStatus, OperationType};
+use parser_cli::Format;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name = "generator")]
+#[command(about = "Generate valid synthetic YPBank operations for fixtures and load tests")]
+struct Args {
+    #[arg(short, long, help = "Number of operations")]
+    count: u64,
+
+    #[arg(long, visible_alias = "format", help = "Output format")]
+    output_format: Format,
+
+    #[arg(
+        short,
+        long,
+        help = "Write here instead of stdout; an existing file is replaced"
+    )]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "RNG seed: the same seed and options give byte-identical output"
+    )]
+    seed: u64,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "TX_ID of the first operation, the rest follow in order"
+    )]
+    first_tx_id: u64,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        help = "User ids are drawn from 1..=USERS"
+    )]
+    users: u64,
+
+    #[arg(long, default_value_t = 1, help = "Smallest AMOUNT in minor units")]
+    min_amount: i64,
+
+    #[arg(
+        long,
+        default_value_t = 1_000_000,
+        help = "Largest AMOUNT in minor units"
+    )]
+    max_amount: i64,
+
+    #[arg(
+        long,
+        value_parser = parse_timestamp,
+        help = "Earliest TIMESTAMP, milliseconds or RFC 3339 [default: 2021-01-01T00:00:00Z]"
+    )]
+    start_ts: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_timestamp,
+        help = "Latest TIMESTAMP, milliseconds or RFC 3339 [default: 2021-12-31T23:59:59.999Z]"
+    )]
+    end_ts: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Share of TRANSFER operations (0.0 to 1.0); deposits and withdrawals split the rest"
+    )]
+    transfer_ratio: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Share of FAILURE operations (0.0 to 1.0); SUCCESS and PENDING split the rest"
+    )]
+    failure_ratio: Option<f64>,
+
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "Share of descriptions with unicode, quotes, commas and newlines (0.0 to 1.0)"
+    )]
+    tricky: f64,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let profile = profile(&args)?;
+    let format = parser::Format::from(args.output_format.clone());
+    let started = Instant::now();
+
+    let bytes = match &args.output {
+        Some(path) => {
+            let file = BufWriter::new(File::create(path)?);
+            let result = generate(&args, &profile, format, file);
+            if result.is_err() {
+                let _ = fs::remove_file(path);
+            }
+            result.map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => generate(&args, &profile, format, BufWriter::new(io::stdout().lock()))?,
+    };
+
+    eprintln!(
+        "Generated {} operations, {} bytes in {:.3}s",
+        args.count,
+        bytes,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Пишет операции по одной, не собирая их в памяти
+///
+/// # Возвращает
+/// Сколько байт записано
+fn generate<W: Write>(
+    args: &Args,
+    profile: &GenProfile,
+    format: parser::Format,
+    writer: W,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    // Тот же ГСЧ и тот же порядок вызовов, что у generate_operations: файл
+    // совпадает с пачкой из библиотеки при том же зерне
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut writer = FormatWriter::new(CountingWriter::new(writer), format);
+    for i in 0..args.count {
+        let mut operation = generate_operation(&mut rng, profile);
+        operation.tx_id = profile.first_tx_id.wrapping_add(i);
+        writer.write(&operation)?;
+    }
+    Ok(writer.finish()?.bytes)
+}
+
+fn profile(args: &Args) -> Result<GenProfile, String> {
+    let defaults = GenProfile::default();
+    if args.min_amount > args.max_amount {
+        return Err("--min-amount is larger than --max-amount".to_string());
+    }
+    let start_ts = args.start_ts.unwrap_or(*defaults.timestamp.start());
+    let end_ts = args.end_ts.unwrap_or(*defaults.timestamp.end());
+    if start_ts > end_ts {
+        return Err("--start-ts is later than --end-ts".to_string());
+    }
+    for (name, ratio) in [
+        ("--tricky", Some(args.tricky)),
+        ("--transfer-ratio", args.transfer_ratio),
+        ("--failure-ratio", args.failure_ratio),
+    ] {
+        if ratio.is_some_and(|ratio| !(0.0..=1.0).contains(&ratio)) {
+            return Err(format!("{} must be between 0.0 and 1.0", name));
+        }
+    }
+
+    let mut type_weights = defaults.type_weights;
+    if let Some(ratio) = args.transfer_ratio {
+        let index = value_index(&OperationType::ALL, &OperationType::Transfer);
+        type_weights = with_share(type_weights, index, ratio);
+    }
+    let mut status_weights = defaults.status_weights;
+    if let Some(ratio) = args.failure_ratio {
+        let index = value_index(&OperationStatus::ALL, &OperationStatus::Failure);
+        status_weights = with_share(status_weights, index, ratio);
+    }
+
+    Ok(GenProfile {
+        type_weights,
+        status_weights,
+        users: args.users,
+        amount: args.min_amount..=args.max_amount,
+        timestamp: start_ts..=end_ts,
+        tricky_descriptions: args.tricky,
+        first_tx_id: args.first_tx_id,
+        ..defaults
+    })
+}
+
+fn value_index<T: PartialEq>(all: &[T], value: &T) -> usize {
+    all.iter()
+        .position(|v| v == value)
+        .expect("value is one of ALL")
+}
+
+/// Веса, где у `index` доля `ratio`, а остальное делится между прочими
+/// значениями в прежних пропорциях
+fn with_share(weights: [u32; 3], index: usize, ratio: f64) -> [u32; 3] {
+    const SCALE: f64 = 1_000_000.0;
+    let others: u32 = weights
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, &weight)| weight)
+        .sum();
+    let mut shares = [0; 3];
+    for (i, &weight) in weights.iter().enumerate() {
+        let share = if i == index {
+            ratio
+        } else {
+            (1.0 - ratio) * f64::from(weight) / f64::from(others)
+        };
+        shares[i] = (share * SCALE).round() as u32;
+    }
+    shares
+}
+
+fn parse_timestamp(s: &str) -> Result<u64, String> {
+    match s.parse() {
+        Ok(millis) => Ok(millis),
+        Err(_) => parse_rfc3339(s).map_err(|e| e.to_string()),
+    }
+}
+
+/// Считает записанные байты для сводки
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, bytes: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
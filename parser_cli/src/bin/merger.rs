@@ -0,0 +1,162 @@
+use clap::{Parser, ValueEnum};
+use parser::gzip::decompress_if_gzip;
+use parser::{
+    MergePolicy, Operation, ParseError, ParseOptions, Provenance, SNIFF_LEN, SortField, SortKey,
+    WriteOptions, detect_format, merge_with_provenance, read_file_with_provenance, sort_operations,
+    write_file,
+};
+use parser_cli::Format;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Policy {
+    /// Stop on the first conflict
+    Fail,
+    /// Keep the record from the input given first
+    PreferFirst,
+    /// Keep the record from the input given last
+    PreferLast,
+    /// Keep the record with the later TIMESTAMP (the earlier input on a tie)
+    PreferNewest,
+}
+
+impl From<Policy> for MergePolicy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::Fail => MergePolicy::FailOnConflict,
+            Policy::PreferFirst => MergePolicy::PreferFirst,
+            Policy::PreferLast => MergePolicy::PreferLast,
+            Policy::PreferNewest => MergePolicy::PreferNewestTimestamp,
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "merger")]
+#[command(about = "Merge YPBank operation files into one, one record per TX_ID")]
+struct Args {
+    #[arg(
+        short,
+        long,
+        required = true,
+        num_args = 1..,
+        help = "Input file paths (repeatable; the format of each is detected)"
+    )]
+    input: Vec<String>,
+
+    #[arg(long, help = "Output format")]
+    output_format: Format,
+
+    #[arg(
+        short,
+        long,
+        help = "Write here atomically instead of stdout; an existing file is replaced"
+    )]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "fail",
+        help = "What to do when inputs disagree on the fields of a TX_ID"
+    )]
+    policy: Policy,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let mut inputs = Vec::new();
+    for path in &args.input {
+        let format = input_format(path)?;
+        inputs.push(read_file_with_provenance(
+            path,
+            format,
+            &ParseOptions::default(),
+        )?);
+    }
+    let result = match merge_with_provenance(inputs, args.policy.into()) {
+        Ok(result) => result,
+        Err(ParseError::MergeConflict {
+            tx_id,
+            first,
+            second,
+            fields,
+        }) => {
+            return Err(format!(
+                "tx_id {} differs between {} and {} ({}); pass --policy to pick a side",
+                tx_id,
+                args.input[first],
+                args.input[second],
+                fields.join(", ")
+            )
+            .into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    for conflict in &result.conflicts {
+        let differences: Vec<String> = conflict.differences.iter().map(|d| d.to_string()).collect();
+        eprintln!(
+            "tx_id {}: kept {}, dropped {} ({})",
+            conflict.tx_id,
+            origin(&args.input[conflict.kept], &conflict.kept_provenance),
+            origin(&args.input[conflict.dropped], &conflict.dropped_provenance),
+            differences.join(", ")
+        );
+    }
+    eprintln!(
+        "Merged {} records from {} files: {} identical duplicates, {} conflicts resolved",
+        result.operations.len(),
+        args.input.len(),
+        result.identical,
+        result.conflicts.len()
+    );
+
+    // По возрастанию TX_ID: одни и те же входы всегда дают одни и те же байты
+    let operations: Vec<Operation> = result.operations.into_iter().map(|(op, _)| op).collect();
+    let operations = sort_operations(operations, SortKey::ascending(SortField::TxId));
+    let format = parser::Format::from(args.output_format);
+    match &args.output {
+        Some(path) => write_file(path, &operations, format, &WriteOptions::default())?,
+        None => {
+            let mut writer = BufWriter::new(io::stdout().lock());
+            format.write_all_with_options(&mut writer, &operations, &WriteOptions::default())?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Формат входа: по расширению, а если не вышло — по первым байтам (gzip распаковывается)
+fn input_format(path: &str) -> Result<parser::Format, Box<dyn std::error::Error>> {
+    if let Some(format) = parser::Format::from_extension(Path::new(path)) {
+        return Ok(format);
+    }
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let reader =
+        decompress_if_gzip(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    let mut prefix = Vec::new();
+    reader
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    Ok(detect_format(&prefix).map_err(|e| format!("{}: can't detect format ({})", path, e))?)
+}
+
+/// Откуда запись: путь, строка и номер записи, если они известны
+fn origin(input: &str, provenance: &Option<Provenance>) -> String {
+    match provenance {
+        Some(provenance) => provenance.to_string(),
+        None => input.to_string(),
+    }
+}
@@ -0,0 +1,355 @@
+use clap::Parser;
+use parser::gzip::decompress_if_gzip;
+use parser::stats::{
+    BalanceCsvOptions, DistributionOptions, GapOptions, QuantileMode, Window,
+    amount_distribution_with_options, duplicate_report, find_gaps_in_ids,
+    find_suspicious_duplicates, write_balances_csv,
+};
+use parser::timestamp::{TimeZoneSpec, to_rfc3339_in};
+use parser::{
+    Ledger, ParseOptions, PendingPolicy, SNIFF_LEN, Summary, detect_format, read_file, read_file_as,
+};
+use parser_cli::Format;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+
+#[derive(Parser)]
+#[command(name = "stats")]
+#[command(about = "Summarize YPBank operation files")]
+struct Args {
+    #[arg(short, long, help = "Input file path")]
+    input: String,
+
+    #[arg(
+        long,
+        visible_alias = "format",
+        help = "Input format (detected from the file if omitted)"
+    )]
+    input_format: Option<Format>,
+
+    #[arg(long, help = "Print per-user balances as USER_ID,BALANCE CSV")]
+    balances: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "balances",
+        help = "Print the N largest balances as USER_ID,BALANCE CSV, largest first"
+    )]
+    top_balances: Option<usize>,
+
+    #[arg(
+        long,
+        requires = "top_balances",
+        help = "Count PENDING operations in --top-balances as if they succeeded"
+    )]
+    include_pending: bool,
+
+    #[arg(short, long, help = "Write the report to this file instead of stdout")]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        help = "Format balances in major units with this many decimal places"
+    )]
+    decimal_places: Option<u32>,
+
+    #[arg(long, help = "Omit the USER_ID,BALANCE header line")]
+    no_header: bool,
+
+    #[arg(
+        long,
+        help = "Print groups of tx_ids with the same description close in time"
+    )]
+    find_duplicates: bool,
+
+    #[arg(
+        long,
+        default_value_t = 60_000,
+        help = "Max TIMESTAMP gap (ms) between neighbours in a duplicate group"
+    )]
+    max_gap_ms: u64,
+
+    #[arg(
+        long,
+        requires = "find_duplicates",
+        help = "Report records repeated with all fields equal, as TX_ID,COUNT,FIRST_RECORD,LAST_RECORD"
+    )]
+    exact: bool,
+
+    #[arg(long, help = "Duplicates must also have the same AMOUNT")]
+    same_amount: bool,
+
+    #[arg(long, help = "Duplicates must also have the same FROM/TO users")]
+    same_users: bool,
+
+    #[arg(
+        long,
+        help = "Print ranges of missing tx_ids (first-last) and a summary line"
+    )]
+    check_gaps: bool,
+
+    #[arg(
+        long,
+        requires = "check_gaps",
+        help = "tx_id numbering is expected to start here (default: smallest tx_id)"
+    )]
+    expect_from: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Print amount percentiles and bucket counts per TX_TYPE as CSV"
+    )]
+    distribution: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "0,1000,10000,100000",
+        help = "Increasing bucket edges for --distribution"
+    )]
+    buckets: Vec<i64>,
+
+    #[arg(
+        long,
+        value_name = "SAMPLE_SIZE",
+        requires = "distribution",
+        help = "Approximate percentiles from a sample of this size per TX_TYPE (for huge inputs)"
+    )]
+    approximate: Option<usize>,
+
+    #[arg(long, help = "Print the summary or --distribution as JSON")]
+    json: bool,
+
+    #[arg(
+        long,
+        help = "Show the summary period in this zone: UTC, +03:00 or Europe/Moscow (default UTC)"
+    )]
+    timezone: Option<TimeZoneSpec>,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if args.find_duplicates && args.exact {
+        return exact_duplicates(&args);
+    }
+    if !(args.distribution
+        || args.check_gaps
+        || args.find_duplicates
+        || args.balances
+        || args.top_balances.is_some())
+    {
+        return summary(&args);
+    }
+
+    let operations = match args.input_format.clone() {
+        Some(format) => read_file_as(&args.input, format.into())?,
+        None => read_file(&args.input)?,
+    };
+
+    let mut writer = open_output(&args)?;
+
+    if args.distribution {
+        let options = DistributionOptions {
+            quantiles: match args.approximate {
+                Some(sample_size) => QuantileMode::Approximate { sample_size },
+                None => QuantileMode::Exact,
+            },
+        };
+        let distribution = amount_distribution_with_options(&operations, &args.buckets, options)?;
+        if args.json {
+            writeln!(writer, "{}", distribution.to_json())?;
+        } else {
+            distribution.to_csv(&mut writer)?;
+        }
+    } else if args.check_gaps {
+        let options = GapOptions {
+            expect_contiguous_from: args.expect_from,
+        };
+        let report = find_gaps_in_ids(operations.iter().map(|op| op.tx_id), options);
+        for gap in &report.gaps {
+            if gap.end - gap.start == 1 {
+                writeln!(writer, "{}", gap.start)?;
+            } else {
+                writeln!(writer, "{}-{}", gap.start, gap.end - 1)?;
+            }
+        }
+        writeln!(
+            writer,
+            "Expected: {}, present: {}, missing: {}",
+            report.expected,
+            report.present,
+            report.missing()
+        )?;
+    } else if args.find_duplicates {
+        let window = Window {
+            max_time_gap_ms: args.max_gap_ms,
+            same_amount: args.same_amount,
+            same_users: args.same_users,
+        };
+        for group in find_suspicious_duplicates(&operations, window) {
+            let ids: Vec<String> = group.iter().map(|id| id.to_string()).collect();
+            writeln!(writer, "{}", ids.join(","))?;
+        }
+    } else {
+        let pending = if args.include_pending {
+            PendingPolicy::Apply
+        } else {
+            PendingPolicy::Count
+        };
+        let options = BalanceCsvOptions {
+            header: !args.no_header,
+            decimal_places: args.decimal_places,
+        };
+        let ledger = Ledger::from_operations_with(&operations, pending);
+        match args.top_balances {
+            Some(n) => ledger.write_top_csv(&mut writer, n, options)?,
+            None => write_balances_csv(&mut writer, ledger.balances(), options)?,
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Точные повторы: читаем записи по порядку, без схлопывания по TX_ID
+fn exact_duplicates(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let (format, reader) = open_input(args)?;
+    let operations = format
+        .parse_all_vec(reader)
+        .map_err(|e| format!("{}: {}", args.input, e))?;
+
+    let mut writer = open_output(args)?;
+    writeln!(writer, "TX_ID,COUNT,FIRST_RECORD,LAST_RECORD")?;
+    for record in duplicate_report(&operations) {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            record.operation.tx_id, record.count, record.first, record.last
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Сводка по умолчанию: записи читаются потоком, в память файл не грузится
+fn summary(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let (format, reader) = open_input(args)?;
+    let summary = Summary::from_reader(reader, format, &ParseOptions::default())
+        .map_err(|e| format!("{}: {}", args.input, e))?;
+
+    let mut writer = open_output(args)?;
+    if args.json {
+        writeln!(writer, "{}", summary.to_json())?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    writeln!(writer, "Operations: {}", summary.operations)?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:<10} {:>10} {:>20} {:>16}",
+        "TX_TYPE", "COUNT", "TOTAL", "MEAN"
+    )?;
+    let rows = summary.by_type.iter().map(|totals| {
+        (
+            totals.tx_type.as_str(),
+            totals.count,
+            totals.total_amount,
+            totals.mean_amount(),
+        )
+    });
+    let all = (
+        "ALL",
+        summary.operations,
+        summary.total_amount,
+        summary.mean_amount(),
+    );
+    for (label, count, total, mean) in rows.chain([all]) {
+        let mean = match mean {
+            Some(mean) => format!("{:.2}", mean),
+            None => "-".to_string(),
+        };
+        writeln!(
+            writer,
+            "{:<10} {:>10} {:>20} {:>16}",
+            label, count, total, mean
+        )?;
+    }
+    if let (Some(min), Some(max)) = (summary.min_amount, summary.max_amount) {
+        writeln!(writer, "Amount range: {} .. {}", min, max)?;
+    }
+    writeln!(writer)?;
+    let statuses: Vec<String> = summary
+        .by_status
+        .iter()
+        .map(|(status, count)| format!("{} {}", status.as_str(), count))
+        .collect();
+    writeln!(writer, "Status: {}", statuses.join(", "))?;
+    // Время хранится в UTC, пояс нужен только для показа
+    if let (Some(first), Some(last)) = (summary.first_timestamp, summary.last_timestamp) {
+        let tz = args.timezone.unwrap_or_default();
+        writeln!(
+            writer,
+            "Period: {} .. {}",
+            to_rfc3339_in(first, &tz),
+            to_rfc3339_in(last, &tz)
+        )?;
+    }
+    writeln!(
+        writer,
+        "Distinct users: {} (senders {}, receivers {})",
+        summary.distinct_users(),
+        summary.distinct_from_users(),
+        summary.distinct_to_users()
+    )?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Открывает вход (сжатый gzip распаковывается) и определяет формат: флаг,
+/// расширение, затем первые байты
+fn open_input(args: &Args) -> Result<(parser::Format, impl Read), Box<dyn std::error::Error>> {
+    let path = Path::new(&args.input);
+    let file = File::open(path).map_err(|e| format!("{}: {}", args.input, e))?;
+    let mut reader =
+        decompress_if_gzip(BufReader::new(file)).map_err(|e| format!("{}: {}", args.input, e))?;
+
+    let mut prefix = Vec::new();
+    (&mut reader)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("{}: {}", args.input, e))?;
+    let format = match args.input_format.clone() {
+        Some(format) => format.into(),
+        None => match parser::Format::from_extension(path) {
+            Some(format) => format,
+            None => detect_format(&prefix).map_err(|e| {
+                format!(
+                    "{}: can't detect format ({}), pass --input-format",
+                    args.input, e
+                )
+            })?,
+        },
+    };
+    Ok((format, Cursor::new(prefix).chain(reader)))
+}
+
+fn open_output(args: &Args) -> io::Result<Box<dyn Write>> {
+    Ok(match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
@@ -0,0 +1,271 @@
+use clap::Parser;
+use parser::consistency::{self, ConsistencyRules};
+use parser::gzip::decompress_if_gzip;
+use parser::stats::{GapOptions, GapReport, find_gaps_in_ids};
+use parser::{ParseError, ParseOptions, PartitionOutcome, partition};
+use parser_cli::Format;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+/// Имя входа, означающее stdin
+const STDIN: &str = "-";
+
+/// Код выхода, если в файле нашлись проблемы
+const EXIT_INVALID: i32 = 1;
+/// Код выхода, если файл не удалось прочитать
+const EXIT_ERROR: i32 = 2;
+
+#[derive(Parser)]
+#[command(name = "validator")]
+#[command(about = "Check a YPBank operation file and report every problem in it")]
+struct Args {
+    #[arg(short, long, help = "Input file path, or - for stdin")]
+    input: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Input format (detected from the content or extension if omitted)"
+    )]
+    format: Option<Format>,
+
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "json",
+        help = "Also check records against each other: conflicting duplicates, SUCCESS and \
+                FAILURE for one tx_id, outlying timestamps, transfer users seen nowhere else"
+    )]
+    deep: bool,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "deep",
+        help = "With --deep, the first TIMESTAMP the file claims to cover"
+    )]
+    since: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "deep",
+        help = "With --deep, the last TIMESTAMP the file claims to cover"
+    )]
+    until: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Report missing tx_ids in the sequential numbering as ranges (first-last)"
+    )]
+    check_gaps: bool,
+
+    #[arg(
+        long,
+        requires = "check_gaps",
+        help = "With --check-gaps, tx_id numbering is expected to start here \
+                (default: smallest tx_id)"
+    )]
+    expect_from: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Flag descriptions longer than this many UTF-8 bytes, the same limit \
+                the writers take as max_description_len"
+    )]
+    max_description_len: Option<usize>,
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(EXIT_INVALID),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Возвращает `true`, если проблем не нашлось
+fn run() -> Result<bool, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    // Первые байты нужны для определения формата; потом они читаются заново
+    let mut reader = open_input(&args.input)?;
+    let mut prefix = Vec::new();
+    (&mut reader)
+        .take(parser::SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("{}: {}", input_name(&args.input), e))?;
+    let format = match args.format {
+        Some(format) => format.into(),
+        None => detect_format(&args.input, &prefix)?,
+    };
+    let reader = Cursor::new(prefix).chain(reader);
+
+    let mut outcome = partition(reader, format, &ParseOptions::default())
+        .map_err(|e| format!("{}: {}", input_name(&args.input), e))?;
+    for record in &mut outcome.rejected {
+        record.provenance.source = Some(input_name(&args.input).to_string());
+    }
+
+    let gaps = args.check_gaps.then(|| {
+        let options = GapOptions {
+            expect_contiguous_from: args.expect_from,
+        };
+        find_gaps_in_ids(outcome.accepted.iter().map(|op| op.tx_id), options)
+    });
+    // Записи с описанием длиннее предела и ошибка по каждой
+    let long_descriptions: Vec<(u64, ParseError)> = match args.max_description_len {
+        Some(max_len) => outcome
+            .accepted
+            .iter()
+            .filter_map(|op| Some((op.tx_id, op.check_description_len(max_len).err()?)))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if args.json {
+        println!(
+            "{}",
+            json_report(&outcome, gaps.as_ref(), &long_descriptions)
+        );
+    } else {
+        print_report(&outcome);
+        if let Some(report) = &gaps {
+            print_gaps(report);
+        }
+        if let Some(max_len) = args.max_description_len {
+            for (tx_id, e) in &long_descriptions {
+                println!("tx_id {}: {}", tx_id, e);
+            }
+            println!(
+                "Descriptions over {} bytes: {}",
+                max_len,
+                long_descriptions.len()
+            );
+        }
+    }
+    let mut valid = outcome.is_clean()
+        && outcome.duplicate_tx_ids().is_empty()
+        && gaps.is_none_or(|report| report.gaps.is_empty())
+        && long_descriptions.is_empty();
+
+    if args.deep {
+        let rules = ConsistencyRules {
+            time_range: (args.since.is_some() || args.until.is_some())
+                .then(|| args.since.unwrap_or(0)..=args.until.unwrap_or(u64::MAX)),
+            ..ConsistencyRules::default()
+        };
+        let report = consistency::check(&outcome.accepted, &rules);
+        if !report.is_clean() {
+            println!("{}", report);
+        }
+        println!("Cross-record findings: {}", report.findings.len());
+        // Предупреждения только печатаются, на код выхода влияют ошибки
+        valid &= !report.has_errors();
+    }
+    Ok(valid)
+}
+
+/// Печатает отчет: по строке на проблему и итог в конце
+fn print_report(outcome: &PartitionOutcome) {
+    for record in &outcome.rejected {
+        println!("{}: {}", record.provenance, record.reason());
+    }
+    let duplicates = outcome.duplicate_tx_ids();
+    for (tx_id, count) in &duplicates {
+        println!("Duplicate tx_id {} ({} records)", tx_id, count);
+    }
+    println!(
+        "Records: {}, invalid: {}, duplicate tx_ids: {}",
+        outcome.records(),
+        outcome.rejected.len(),
+        duplicates.len()
+    );
+}
+
+/// Отчет [`PartitionOutcome::to_json`], дополненный пропусками TX_ID и длинными описаниями
+fn json_report(
+    outcome: &PartitionOutcome,
+    gaps: Option<&GapReport>,
+    long_descriptions: &[(u64, ParseError)],
+) -> serde_json::Value {
+    let mut report: serde_json::Value =
+        serde_json::from_str(&outcome.to_json()).expect("to_json produces valid JSON");
+    if let Some(gaps) = gaps {
+        let ranges: Vec<serde_json::Value> = gaps
+            .gaps
+            .iter()
+            .map(|gap| serde_json::json!({ "first": gap.start, "last": gap.end - 1 }))
+            .collect();
+        report["gaps"] = serde_json::json!({
+            "missing": ranges,
+            "expected": gaps.expected,
+            "present": gaps.present,
+        });
+    }
+    if !long_descriptions.is_empty() {
+        report["long_descriptions"] = long_descriptions
+            .iter()
+            .map(|(tx_id, e)| serde_json::json!({ "tx_id": tx_id, "error": e.to_string() }))
+            .collect();
+    }
+    report
+}
+
+/// Печатает пропуски в нумерации TX_ID: по строке на диапазон и итог
+fn print_gaps(report: &GapReport) {
+    for gap in &report.gaps {
+        if gap.end - gap.start == 1 {
+            println!("Missing tx_id {}", gap.start);
+        } else {
+            println!("Missing tx_ids {}-{}", gap.start, gap.end - 1);
+        }
+    }
+    println!(
+        "Expected tx_ids: {}, present: {}, missing: {}",
+        report.expected,
+        report.present,
+        report.missing()
+    );
+}
+
+/// Определяет формат по содержимому, а если не вышло — по расширению
+fn detect_format(input: &str, prefix: &[u8]) -> Result<parser::Format, String> {
+    let by_content = match parser::detect_format(prefix) {
+        Ok(format) => return Ok(format),
+        Err(ParseError::InvalidFormat(reason)) => reason,
+        Err(e) => e.to_string(),
+    };
+    let by_extension = match input {
+        STDIN => None,
+        _ => parser::Format::from_extension(Path::new(input)),
+    };
+    by_extension.ok_or_else(|| {
+        format!(
+            "{}: can't detect the format. By content: {}. Pass --format",
+            input_name(input),
+            by_content
+        )
+    })
+}
+
+/// Открывает вход: файл или stdin для `-`; сжатый gzip распаковывается на лету
+fn open_input(input: &str) -> Result<Box<dyn BufRead>, String> {
+    if input == STDIN {
+        return decompress_if_gzip(io::stdin().lock()).map_err(|e| format!("stdin: {}", e));
+    }
+    let file = File::open(input).map_err(|e| format!("{}: {}", input, e))?;
+    decompress_if_gzip(BufReader::new(file)).map_err(|e| format!("{}: {}", input, e))
+}
+
+/// Имя входа для сообщений об ошибках
+fn input_name(input: &str) -> &str {
+    if input == STDIN { "stdin" } else { input }
+}
@@ -0,0 +1,650 @@
+use clap::Parser;
+use parser::annotations::{Annotation, annotation_keys, read_annotations, write_annotations};
+use parser::timestamp::{TimeZoneSpec, to_rfc3339_in};
+use parser::{Operation, ParseOptions, WriteOptions};
+use parser_cli::Format;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Сколько записей читается с диска за раз
+const PAGE_SIZE: usize = 256;
+/// Сколько страниц держим в памяти; старые вытесняются
+const MAX_PAGES: usize = 16;
+
+#[derive(Parser)]
+#[command(name = "viewer")]
+#[command(about = "Browse YPBank operation files in the terminal")]
+struct Args {
+    #[arg(short, long, help = "Input file path")]
+    input: PathBuf,
+
+    #[arg(long, help = "Input format (detected from the file if omitted)")]
+    input_format: Option<Format>,
+
+    #[arg(
+        long,
+        default_value = "viewer_export.csv",
+        help = "Where `e` writes the current view as CSV"
+    )]
+    export: PathBuf,
+
+    #[arg(long, help = "NDJSON sidecar with per-operation annotations")]
+    annotations: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "UTC",
+        help = "Show times in this zone: UTC, an offset like +03:00 or a name like Europe/Moscow"
+    )]
+    timezone: TimeZoneSpec,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let format = match args.input_format.clone() {
+        Some(format) => format.into(),
+        None => {
+            // Формат по содержимому: расширение может врать
+            let mut prefix = Vec::new();
+            File::open(&args.input)
+                .map_err(|e| format!("{}: {}", args.input.display(), e))?
+                .take(parser::SNIFF_LEN as u64)
+                .read_to_end(&mut prefix)?;
+            parser::detect_format(&prefix).map_err(|e| {
+                format!(
+                    "{}: can't detect format ({}), pass --input-format",
+                    args.input.display(),
+                    e
+                )
+            })?
+        }
+    };
+    eprintln!("Indexing {}...", args.input.display());
+    let store = Store::open(&args.input, format)?;
+    let notes = match &args.annotations {
+        Some(path) => Notes::load(path)?,
+        None => Notes::default(),
+    };
+    let orphans = notes.orphans(&store)?;
+
+    let mut app = App::new(store, notes, args.export, args.timezone);
+    if !orphans.is_empty() {
+        app.status = format!(
+            "{} annotated tx_ids are not in the file: {:?}",
+            orphans.len(),
+            orphans
+        );
+    }
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// Файл с индексом смещений записей и ограниченным кэшем страниц
+///
+/// В памяти всегда только смещения (8 байт на запись) и не больше
+/// `MAX_PAGES` страниц по `PAGE_SIZE` операций.
+struct Store {
+    path: PathBuf,
+    format: parser::Format,
+    reader: BufReader<File>,
+    offsets: Vec<u64>,
+    pages: HashMap<usize, Vec<Operation>>,
+    recent: VecDeque<usize>,
+}
+
+impl Store {
+    /// Один потоковый проход по файлу: запоминаем, где начинается каждая запись
+    fn open(path: &Path, format: parser::Format) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut offsets = Vec::new();
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        format
+            .for_each_record(
+                BufReader::new(file),
+                &ParseOptions::default(),
+                |_, provenance| {
+                    offsets.push(provenance.byte_offset.unwrap_or_default());
+                    Ok(())
+                },
+            )
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(Store {
+            path: path.to_path_buf(),
+            format,
+            reader: BufReader::new(File::open(path)?),
+            offsets,
+            pages: HashMap::new(),
+            recent: VecDeque::new(),
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Запись по номеру в файле; страница подгружается через seek по индексу
+    fn get(&mut self, index: usize) -> parser::Result<&Operation> {
+        let page = index / PAGE_SIZE;
+        if !self.pages.contains_key(&page) {
+            let start = page * PAGE_SIZE;
+            let end = (start + PAGE_SIZE).min(self.len());
+            self.reader.seek(SeekFrom::Start(self.offsets[start]))?;
+
+            let mut operations = Vec::with_capacity(end - start);
+            for _ in start..end {
+                operations.push(
+                    self.format
+                        .parse_one(&mut self.reader, &ParseOptions::default())?,
+                );
+            }
+
+            if self.recent.len() >= MAX_PAGES
+                && let Some(oldest) = self.recent.pop_front()
+            {
+                self.pages.remove(&oldest);
+            }
+            self.pages.insert(page, operations);
+            self.recent.push_back(page);
+        }
+
+        Ok(&self.pages[&page][index % PAGE_SIZE])
+    }
+
+    /// Полный потоковый проход (для поиска и сортировки), мимо кэша
+    fn scan(&self, mut f: impl FnMut(usize, &Operation)) -> parser::Result<()> {
+        let file = File::open(&self.path)?;
+        self.format.for_each_record(
+            BufReader::new(file),
+            &ParseOptions::default(),
+            |operation, provenance| {
+                f(provenance.record_index as usize, &operation);
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// Заметки из `--annotations`, сгруппированные по TX_ID
+#[derive(Default)]
+struct Notes {
+    by_id: HashMap<u64, Vec<Annotation>>,
+    /// Имена заметок — по колонке на каждое
+    keys: Vec<String>,
+}
+
+impl Notes {
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let annotations = read_annotations(BufReader::new(file))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let keys = annotation_keys(&annotations);
+        let mut by_id: HashMap<u64, Vec<Annotation>> = HashMap::new();
+        for annotation in annotations {
+            by_id.entry(annotation.tx_id).or_default().push(annotation);
+        }
+        Ok(Notes { by_id, keys })
+    }
+
+    fn for_tx(&self, tx_id: u64) -> &[Annotation] {
+        self.by_id
+            .get(&tx_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Значение заметки; при повторах — последнее в файле, как в `AnnotatedOperation::value`
+    fn value(&self, tx_id: u64, key: &str) -> String {
+        self.for_tx(tx_id)
+            .iter()
+            .rev()
+            .find(|annotation| annotation.key == key)
+            .map(|annotation| annotation.value.replace('\n', "\\n"))
+            .unwrap_or_default()
+    }
+
+    /// TX_ID из заметок, которых нет в файле (один потоковый проход)
+    fn orphans(&self, store: &Store) -> parser::Result<Vec<u64>> {
+        let mut orphans: BTreeSet<u64> = self.by_id.keys().copied().collect();
+        if !orphans.is_empty() {
+            store.scan(|_, op| {
+                orphans.remove(&op.tx_id);
+            })?;
+        }
+        Ok(orphans.into_iter().collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    TxId,
+    Type,
+    From,
+    To,
+    Amount,
+    Timestamp,
+    Status,
+    Description,
+}
+
+impl Column {
+    const ALL: [Column; 8] = [
+        Column::TxId,
+        Column::Type,
+        Column::From,
+        Column::To,
+        Column::Amount,
+        Column::Timestamp,
+        Column::Status,
+        Column::Description,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Column::TxId => "TX_ID",
+            Column::Type => "TX_TYPE",
+            Column::From => "FROM_USER_ID",
+            Column::To => "TO_USER_ID",
+            Column::Amount => "AMOUNT",
+            Column::Timestamp => "TIMESTAMP",
+            Column::Status => "STATUS",
+            Column::Description => "DESCRIPTION",
+        }
+    }
+
+    /// Числовой ключ сортировки; по описанию не сортируем, чтобы не держать все строки в памяти
+    fn key(self, op: &Operation) -> Option<i128> {
+        match self {
+            Column::TxId => Some(op.tx_id as i128),
+            // Незнакомые типы и статусы — после всех известных
+            Column::Type => Some(op.tx_type.to_u8().map_or(256, i128::from)),
+            Column::From => Some(op.from_user_id as i128),
+            Column::To => Some(op.to_user_id as i128),
+            Column::Amount => Some(op.amount as i128),
+            Column::Timestamp => Some(op.timestamp as i128),
+            Column::Status => Some(op.status.to_u8().map_or(256, i128::from)),
+            Column::Description => None,
+        }
+    }
+
+    fn cell(self, op: &Operation) -> String {
+        match self {
+            Column::TxId => op.tx_id.to_string(),
+            Column::Type => op.tx_type.as_str().to_string(),
+            Column::From => op.from_user_id.to_string(),
+            Column::To => op.to_user_id.to_string(),
+            Column::Amount => op.amount.to_string(),
+            Column::Timestamp => op.timestamp.to_string(),
+            Column::Status => op.status.as_str().to_string(),
+            Column::Description => op.description.replace('\n', "\\n"),
+        }
+    }
+
+    fn width(self) -> Constraint {
+        match self {
+            Column::TxId | Column::From | Column::To => Constraint::Length(20),
+            Column::Type => Constraint::Length(10),
+            Column::Amount => Constraint::Length(20),
+            Column::Timestamp => Constraint::Length(14),
+            Column::Status => Constraint::Length(8),
+            Column::Description => Constraint::Min(10),
+        }
+    }
+}
+
+struct App {
+    store: Store,
+    notes: Notes,
+    export: PathBuf,
+    /// Пояс для показа времени в карточке записи
+    time_zone: TimeZoneSpec,
+    /// Номера записей текущего вида; `None` — все записи в порядке файла
+    view: Option<Vec<usize>>,
+    /// Колонка сортировки и направление (true — по убыванию)
+    sort: Option<(Column, bool)>,
+    filter: String,
+    /// Строка поиска, пока ее набирают после `/`
+    input: Option<String>,
+    selected: usize,
+    table: TableState,
+    status: String,
+    quit: bool,
+}
+
+impl App {
+    fn new(store: Store, notes: Notes, export: PathBuf, time_zone: TimeZoneSpec) -> Self {
+        App {
+            store,
+            notes,
+            export,
+            time_zone,
+            view: None,
+            sort: None,
+            filter: String::new(),
+            input: None,
+            selected: 0,
+            table: TableState::default(),
+            status: "/ search  1-8 sort  0 file order  e export  q quit".to_string(),
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn std::error::Error>> {
+        while !self.quit {
+            let mut failure = None;
+            terminal.draw(|frame| {
+                if let Err(e) = self.draw(frame) {
+                    failure = Some(e);
+                }
+            })?;
+            if let Some(e) = failure {
+                return Err(e.into());
+            }
+
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.on_key(key.code);
+            }
+        }
+        Ok(())
+    }
+
+    fn view_len(&self) -> usize {
+        match &self.view {
+            Some(view) => view.len(),
+            None => self.store.len(),
+        }
+    }
+
+    fn record_at(&self, position: usize) -> usize {
+        match &self.view {
+            Some(view) => view[position],
+            None => position,
+        }
+    }
+
+    fn on_key(&mut self, code: KeyCode) {
+        if let Some(input) = &mut self.input {
+            match code {
+                KeyCode::Enter => {
+                    let query = self.input.take().unwrap_or_default();
+                    self.apply_filter(query);
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        let len = self.view_len();
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.move_to(self.selected.saturating_add(1)),
+            KeyCode::Up | KeyCode::Char('k') => self.move_to(self.selected.saturating_sub(1)),
+            KeyCode::PageDown => self.move_to(self.selected.saturating_add(PAGE_SIZE / 8)),
+            KeyCode::PageUp => self.move_to(self.selected.saturating_sub(PAGE_SIZE / 8)),
+            KeyCode::Home | KeyCode::Char('g') => self.move_to(0),
+            KeyCode::End | KeyCode::Char('G') => self.move_to(len.saturating_sub(1)),
+            KeyCode::Char('/') => self.input = Some(String::new()),
+            KeyCode::Char('0') => {
+                self.sort = None;
+                self.refresh_view();
+            }
+            KeyCode::Char(c @ '1'..='8') => {
+                let column = Column::ALL[c as usize - '1' as usize];
+                let descending = matches!(self.sort, Some((current, false)) if current == column);
+                self.sort = Some((column, descending));
+                self.refresh_view();
+            }
+            KeyCode::Char('e') => self.export(),
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, position: usize) {
+        self.selected = position.min(self.view_len().saturating_sub(1));
+    }
+
+    fn apply_filter(&mut self, query: String) {
+        self.filter = query;
+        self.refresh_view();
+    }
+
+    /// Пересобирает вид: фильтр, затем сортировка; оба — потоковыми проходами по файлу
+    fn refresh_view(&mut self) {
+        let result = self.build_view();
+        match result {
+            Ok(view) => {
+                self.view = view;
+                self.selected = 0;
+                self.table = TableState::default();
+                self.status = format!("{} of {} records", self.view_len(), self.store.len());
+            }
+            Err(e) => self.status = format!("Error: {}", e),
+        }
+    }
+
+    fn build_view(&self) -> Result<Option<Vec<usize>>, String> {
+        let mut view: Option<Vec<usize>> = None;
+
+        if !self.filter.is_empty() {
+            let needle = self.filter.to_lowercase();
+            let tx_id = self.filter.trim().parse::<u64>().ok();
+            let mut matches = Vec::new();
+            self.store
+                .scan(|index, op| {
+                    if Some(op.tx_id) == tx_id || op.description.to_lowercase().contains(&needle) {
+                        matches.push(index);
+                    }
+                })
+                .map_err(|e| e.to_string())?;
+            view = Some(matches);
+        }
+
+        if let Some((column, descending)) = self.sort {
+            if column == Column::Description {
+                return Err(format!("sorting by {} is not supported", column.title()));
+            }
+            let mut keys = vec![0i128; self.store.len()];
+            self.store
+                .scan(|index, op| keys[index] = column.key(op).unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+
+            let mut sorted = view.unwrap_or_else(|| (0..self.store.len()).collect());
+            sorted.sort_by_key(|&index| keys[index]);
+            if descending {
+                sorted.reverse();
+            }
+            view = Some(sorted);
+        }
+
+        Ok(view)
+    }
+
+    fn export(&mut self) {
+        let result = (|| -> Result<usize, Box<dyn std::error::Error>> {
+            let mut operations = Vec::with_capacity(self.view_len());
+            for position in 0..self.view_len() {
+                let index = self.record_at(position);
+                operations.push(self.store.get(index)?.clone());
+            }
+
+            let mut writer = BufWriter::new(File::create(&self.export)?);
+            parser::Format::Csv.write_all_with_options(
+                &mut writer,
+                &operations,
+                &WriteOptions::default(),
+            )?;
+
+            // Заметки к выгруженным операциям — в свой файл рядом, CSV остается YPBankCsv
+            if !self.notes.by_id.is_empty() {
+                let notes = operations.iter().flat_map(|op| self.notes.for_tx(op.tx_id));
+                let file = File::create(self.export.with_extension("annotations.ndjson"))?;
+                write_annotations(BufWriter::new(file), notes)?;
+            }
+            Ok(operations.len())
+        })();
+
+        self.status = match result {
+            Ok(count) => format!("Exported {} records to {}", count, self.export.display()),
+            Err(e) => format!("Export failed: {}", e),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) -> parser::Result<()> {
+        let [table_area, detail_area, status_area] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(14),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_table(frame, table_area)?;
+        self.draw_detail(frame, detail_area)?;
+
+        let status = match &self.input {
+            Some(input) => format!("/{}", input),
+            None => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+        Ok(())
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) -> parser::Result<()> {
+        // Рамка и заголовок занимают три строки
+        let height = area.height.saturating_sub(3).max(1) as usize;
+        let len = self.view_len();
+
+        // Окно строк вокруг выбранной; читаем только их
+        let mut first = self.table.offset();
+        if self.selected < first {
+            first = self.selected;
+        } else if self.selected >= first + height {
+            first = self.selected + 1 - height;
+        }
+        let last = (first + height).min(len);
+
+        let mut rows = Vec::with_capacity(last - first);
+        for position in first..last {
+            let index = self.record_at(position);
+            let op = self.store.get(index)?;
+            let mut cells: Vec<String> = Column::ALL.map(|column| column.cell(op)).into();
+            cells.extend(
+                self.notes
+                    .keys
+                    .iter()
+                    .map(|key| self.notes.value(op.tx_id, key)),
+            );
+            rows.push(Row::new(cells));
+        }
+
+        let mut titles: Vec<String> = Column::ALL
+            .map(|column| match self.sort {
+                Some((sorted, false)) if sorted == column => format!("{} ▲", column.title()),
+                Some((sorted, true)) if sorted == column => format!("{} ▼", column.title()),
+                _ => column.title().to_string(),
+            })
+            .into();
+        titles.extend(self.notes.keys.iter().cloned());
+        let header = Row::new(titles).style(Style::default().add_modifier(Modifier::BOLD));
+
+        let mut widths: Vec<Constraint> = Column::ALL.map(Column::width).into();
+        widths.extend(self.notes.keys.iter().map(|_| Constraint::Length(16)));
+
+        let title = if self.filter.is_empty() {
+            format!(" {} ({} records) ", self.store.path.display(), len)
+        } else {
+            format!(
+                " {} ({} of {} records matching '{}') ",
+                self.store.path.display(),
+                len,
+                self.store.len(),
+                self.filter
+            )
+        };
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        // Состояние таблицы — относительно окна, а не всего вида
+        let mut state =
+            TableState::default().with_selected((len > 0).then(|| self.selected - first));
+        frame.render_stateful_widget(table, area, &mut state);
+        self.table = TableState::default().with_offset(first);
+        Ok(())
+    }
+
+    fn draw_detail(&mut self, frame: &mut Frame, area: Rect) -> parser::Result<()> {
+        let block = Block::default().borders(Borders::ALL).title(" Record ");
+        if self.view_len() == 0 {
+            frame.render_widget(Paragraph::new("No records").block(block), area);
+            return Ok(());
+        }
+
+        let index = self.record_at(self.selected);
+        let op = self.store.get(index)?;
+        let validation = match op.validate_all() {
+            Ok(()) => "valid".to_string(),
+            Err(report) => report
+                .issues
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        };
+
+        let mut lines = vec![
+            Line::from(format!("Record #{} in file", index + 1)),
+            Line::from(format!("TX_ID:        {}", op.tx_id)),
+            Line::from(format!("TX_TYPE:      {}", op.tx_type.as_str())),
+            Line::from(format!("FROM_USER_ID: {}", op.from_user_id)),
+            Line::from(format!("TO_USER_ID:   {}", op.to_user_id)),
+            Line::from(format!("AMOUNT:       {}", op.amount)),
+            Line::from(format!(
+                "TIMESTAMP:    {} ({})",
+                op.timestamp,
+                to_rfc3339_in(op.timestamp, &self.time_zone)
+            )),
+            Line::from(format!("STATUS:       {}", op.status.as_str())),
+            Line::from(format!("DESCRIPTION:  {:?}", op.description)),
+            Line::from(format!("Validation:   {}", validation)),
+        ];
+        for annotation in self.notes.for_tx(op.tx_id) {
+            lines.push(Line::from(format!(
+                "Note:         {} = {:?} ({}, {})",
+                annotation.key,
+                annotation.value,
+                annotation.author,
+                to_rfc3339_in(annotation.ts, &self.time_zone)
+            )));
+        }
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+        Ok(())
+    }
+}
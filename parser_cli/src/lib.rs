@@ -0,0 +1,27 @@
+//! Общее для утилит YPBank: аргументы командной строки, которые повторяются во всех бинарниках
+
+use clap::ValueEnum;
+
+/// Формат файла в аргументах `--format`, `--input-format` и т.п.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Format {
+    #[value(alias = "binary")]
+    Bin,
+    Csv,
+    #[value(alias = "text")]
+    Txt,
+    Json,
+    Jsonl,
+}
+
+impl From<Format> for parser::Format {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Bin => parser::Format::Bin,
+            Format::Csv => parser::Format::Csv,
+            Format::Txt => parser::Format::Txt,
+            Format::Json => parser::Format::Json,
+            Format::Jsonl => parser::Format::Jsonl,
+        }
+    }
+}
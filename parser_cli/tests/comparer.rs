@@ -0,0 +1,187 @@
+//! Runs the comparer binary on temp files and checks its output and exit code
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const HEADER: &str = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n";
+
+fn row(tx_id: u64, amount: i64, status: &str) -> String {
+    format!(
+        "{},DEPOSIT,0,7,{},1633036800000,{},\"Deposit {}\"\n",
+        tx_id, amount, status, tx_id
+    )
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("comparer_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn compare(name: &str, csv1: &str, csv2: &str, extra: &[&str]) -> Output {
+    let dir = test_dir(name);
+    let (file1, file2) = (dir.join("a.csv"), dir.join("b.csv"));
+    fs::write(&file1, csv1).unwrap();
+    fs::write(&file2, csv2).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_comparer"))
+        .arg("--file1")
+        .arg(&file1)
+        .args(["--format1", "csv", "--file2"])
+        .arg(&file2)
+        .args(["--format2", "csv"])
+        .args(extra)
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn identical_files_exit_zero() {
+    let csv = format!(
+        "{}{}{}",
+        HEADER,
+        row(1, 100, "SUCCESS"),
+        row(2, 200, "SUCCESS")
+    );
+    let output = compare("identical", &csv, &csv, &[]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("identical"));
+}
+
+#[test]
+fn reports_missing_and_changed_records() {
+    let csv1 = format!(
+        "{}{}{}{}",
+        HEADER,
+        row(1, 100, "SUCCESS"),
+        row(2, 200, "SUCCESS"),
+        row(3, 300, "SUCCESS")
+    );
+    let csv2 = format!(
+        "{}{}{}{}",
+        HEADER,
+        row(1, 100, "SUCCESS"),
+        row(2, 250, "FAILURE"),
+        row(4, 400, "SUCCESS")
+    );
+
+    let output = compare("changed", &csv1, &csv2, &[]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert!(out.contains("1 only in"), "{}", out);
+    assert!(out.contains("a.csv': tx_id 3"), "{}", out);
+    assert!(out.contains("b.csv': tx_id 4"), "{}", out);
+    assert!(out.contains("Differs: tx_id 2 (AMOUNT, STATUS)"), "{}", out);
+
+    let output = compare("changed_verbose", &csv1, &csv2, &["--verbose"]);
+    let out = stdout(&output);
+    assert!(out.contains("  AMOUNT: 200 != 250"), "{}", out);
+    assert!(out.contains("  STATUS: SUCCESS != FAILURE"), "{}", out);
+}
+
+#[test]
+fn limit_caps_the_listing() {
+    let csv1: String = (1..=5).map(|tx_id| row(tx_id, 100, "SUCCESS")).collect();
+    let csv2: String = (1..=5).map(|tx_id| row(tx_id, 999, "SUCCESS")).collect();
+
+    let output = compare(
+        "limit",
+        &format!("{}{}", HEADER, csv1),
+        &format!("{}{}", HEADER, csv2),
+        &["--limit", "2"],
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert_eq!(out.matches("Differs:").count(), 2, "{}", out);
+    assert!(out.contains("... and 3 more"), "{}", out);
+}
+
+#[test]
+fn parse_errors_exit_two() {
+    let csv = format!("{}{}", HEADER, row(1, 100, "SUCCESS"));
+    let broken = format!(
+        "{}1,DEPOSIT,0,7,not-a-number,1633036800000,SUCCESS,\"x\"\n",
+        HEADER
+    );
+
+    let output = compare("broken", &csv, &broken, &[]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error:"));
+}
+
+#[test]
+fn reads_one_side_from_stdin() {
+    let csv = format!("{}{}", HEADER, row(1, 100, "SUCCESS"));
+    let dir = test_dir("stdin");
+    let file = dir.join("a.csv");
+    fs::write(&file, &csv).unwrap();
+
+    let run = |file1: &str, file2: &Path, input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_comparer"))
+            .args(["--file1", file1, "--format1", "csv", "--file2"])
+            .arg(file2)
+            .args(["--format2", "csv"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Fails with a broken pipe if the comparer bails out before reading stdin
+        let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+        child.wait_with_output().unwrap()
+    };
+
+    let output = run("-", &file, &csv);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+
+    let changed = format!("{}{}", HEADER, row(1, 999, "SUCCESS"));
+    let output = run("-", &file, &changed);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stdout(&output).contains("only in 'stdin'"),
+        "{}",
+        stdout(&output)
+    );
+
+    let output = run("-", Path::new("-"), &csv);
+    assert_eq!(output.status.code(), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn json_lists_each_bucket() {
+    let csv1 = format!(
+        "{}{}{}",
+        HEADER,
+        row(1, 100, "SUCCESS"),
+        row(2, 200, "SUCCESS")
+    );
+    let csv2 = format!(
+        "{}{}{}",
+        HEADER,
+        row(2, 200, "FAILURE"),
+        row(3, 300, "SUCCESS")
+    );
+
+    let output = compare("json", &csv1, &csv2, &["--json", "--since", "0"]);
+    assert_eq!(output.status.code(), Some(1));
+    let value: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(value["identical"], false);
+    assert_eq!(value["only_in_left"][0]["TX_ID"], 1);
+    assert_eq!(value["only_in_right"][0]["TX_ID"], 3);
+    assert_eq!(value["changed"][0]["fields"][0]["field"], "STATUS");
+    assert_eq!(value["changed"][0]["fields"][0]["right"], "FAILURE");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Excluded by filters"));
+}
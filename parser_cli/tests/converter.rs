@@ -0,0 +1,623 @@
+//! Runs the converter binary and checks how it treats the --output path
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const CSV: &str = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                   1,DEPOSIT,0,7,100,1633036800000,SUCCESS,\"Salary\"\n";
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("converter_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn convert(input: &Path, output: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_converter"))
+        .arg("--input")
+        .arg(input)
+        .args([
+            "--input-format",
+            "csv",
+            "--output-format",
+            "bin",
+            "--output",
+        ])
+        .arg(output)
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn writes_new_output_file() {
+    let dir = test_dir("new");
+    let (input, output) = (dir.join("in.csv"), dir.join("out.bin"));
+    fs::write(&input, CSV).unwrap();
+
+    let result = convert(&input, &output, &[]);
+
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert!(fs::read(&output).unwrap().starts_with(b"YPBN"));
+    assert!(result.stdout.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn refuses_to_overwrite_without_force() {
+    let dir = test_dir("overwrite");
+    let (input, output) = (dir.join("in.csv"), dir.join("out.bin"));
+    fs::write(&input, CSV).unwrap();
+    fs::write(&output, "keep me").unwrap();
+
+    let result = convert(&input, &output, &[]);
+    assert!(!result.status.success());
+    assert!(stderr(&result).contains("--force"), "{}", stderr(&result));
+    assert_eq!(fs::read_to_string(&output).unwrap(), "keep me");
+
+    let result = convert(&input, &output, &["--force"]);
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert!(fs::read(&output).unwrap().starts_with(b"YPBN"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn refuses_to_write_over_the_input() {
+    let dir = test_dir("same");
+    let input = dir.join("in.csv");
+    fs::write(&input, CSV).unwrap();
+
+    // Another spelling of the same path is caught too
+    let same = dir.join(".").join("in.csv");
+    let result = convert(&input, &same, &["--force"]);
+
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("same file as the input"),
+        "{}",
+        stderr(&result)
+    );
+    assert_eq!(fs::read_to_string(&input).unwrap(), CSV);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn failed_conversion_leaves_no_output() {
+    let dir = test_dir("failed");
+    let (input, output) = (dir.join("in.csv"), dir.join("out.bin"));
+    fs::write(
+        &input,
+        format!("{}2,DEPOSIT,0,7,oops,1,SUCCESS,\"x\"\n", CSV),
+    )
+    .unwrap();
+
+    let result = convert(&input, &output, &[]);
+
+    assert!(!result.status.success());
+    assert!(!output.exists());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Runs the converter with `--input -` and `input` on stdin
+fn convert_stdin(input: &[u8], args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_converter"))
+        .args(["--input", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Fails with a broken pipe if the converter bails out before reading stdin
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn reads_csv_and_bin_from_stdin() {
+    let bin = convert_stdin(
+        CSV.as_bytes(),
+        &["--input-format", "csv", "--output-format", "bin"],
+    );
+    assert!(bin.status.success(), "{}", stderr(&bin));
+    assert!(bin.stdout.starts_with(b"YPBN"));
+
+    // A pipe can't seek: the bin parser has to manage with plain reads
+    let csv = convert_stdin(
+        &bin.stdout,
+        &["--input-format", "bin", "--output-format", "csv"],
+    );
+    assert!(csv.status.success(), "{}", stderr(&csv));
+    assert_eq!(String::from_utf8_lossy(&csv.stdout), CSV);
+}
+
+#[test]
+fn stdin_errors_say_stdin() {
+    let result = convert_stdin(
+        b"not a csv file",
+        &["--input-format", "csv", "--output-format", "bin"],
+    );
+
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("Error: stdin: "),
+        "{}",
+        stderr(&result)
+    );
+}
+
+const TXT: &str = "TX_ID: 1\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 7\nAMOUNT: 100\n\
+                   TIMESTAMP: 1633036800000\nSTATUS: SUCCESS\nDESCRIPTION: \"Salary\"\n";
+
+/// Converts `path` to csv on stdout without --input-format
+fn to_csv_detected(path: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_converter"))
+        .arg("--input")
+        .arg(path)
+        .args(["--output-format", "csv"])
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn bin_bytes() -> Vec<u8> {
+    convert_stdin(
+        CSV.as_bytes(),
+        &["--input-format", "csv", "--output-format", "bin"],
+    )
+    .stdout
+}
+
+#[test]
+fn detects_format_by_extension_and_content() {
+    let dir = test_dir("detect");
+    let bin = bin_bytes();
+    let cases: [(&str, &[u8]); 6] = [
+        ("a.bin", &bin),
+        ("a.csv", CSV.as_bytes()),
+        ("a.txt", TXT.as_bytes()),
+        // Wrong or missing extension: the content decides
+        ("bin_as.csv", &bin),
+        ("text_as.bin", TXT.as_bytes()),
+        ("no_extension", CSV.as_bytes()),
+    ];
+
+    for (name, content) in cases {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+
+        let result = to_csv_detected(&path, &[]);
+
+        assert!(result.status.success(), "{}: {}", name, stderr(&result));
+        assert_eq!(String::from_utf8_lossy(&result.stdout), CSV, "{}", name);
+        assert!(stderr(&result).is_empty(), "{}: {}", name, stderr(&result));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn undetectable_input_lists_what_was_tried() {
+    let dir = test_dir("undetectable");
+    let path = dir.join("notes.dat");
+    fs::write(&path, "hello there").unwrap();
+
+    let result = to_csv_detected(&path, &[]);
+
+    assert!(!result.status.success());
+    let err = stderr(&result);
+    assert!(err.contains("can't detect the input format"), "{}", err);
+    assert!(
+        err.contains("By extension") && err.contains("YPBN/YPBF magic"),
+        "{}",
+        err
+    );
+
+    let empty = dir.join("empty.dat");
+    fs::write(&empty, "\u{FEFF}\n").unwrap();
+    let result = to_csv_detected(&empty, &[]);
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("By content: Empty input"),
+        "{}",
+        stderr(&result)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn explicit_format_wins_with_a_warning() {
+    let dir = test_dir("explicit");
+    let path = dir.join("a.csv");
+    fs::write(&path, CSV).unwrap();
+
+    let result = to_csv_detected(&path, &["--input-format", "txt"]);
+
+    // Read as text, the CSV has no records at all
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert!(
+        stderr(&result).contains("looks like csv, reading it as txt"),
+        "{}",
+        stderr(&result)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn filters_combine_into_one_conversion() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,WITHDRAWAL,42,0,100,1631664000000,FAILURE,\"Match\"\n\
+                 2,WITHDRAWAL,42,0,100,1631664000000,SUCCESS,\"Wrong status\"\n\
+                 3,WITHDRAWAL,42,0,100,1633046400000,FAILURE,\"October\"\n\
+                 4,DEPOSIT,0,42,100,1631664000000,FAILURE,\"Wrong type\"\n\
+                 5,WITHDRAWAL,7,0,100,1631664000000,FAILURE,\"Other user\"\n\
+                 6,WITHDRAWAL,42,0,100,1630454400000,FAILURE,\"First millisecond\"\n";
+
+    let result = convert_stdin(
+        input.as_bytes(),
+        &[
+            "--input-format",
+            "csv",
+            "--output-format",
+            "csv",
+            "--filter-type",
+            "withdrawal",
+            "--filter-status",
+            "failure",
+            "--user",
+            "42",
+            "--since",
+            "2021-09-01T00:00:00Z",
+            "--until",
+            "1633046399999",
+        ],
+    );
+
+    assert!(result.status.success(), "{}", stderr(&result));
+    let out = String::from_utf8_lossy(&result.stdout);
+    let ids: Vec<&str> = out
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["1", "6"], "{}", out);
+}
+
+#[test]
+fn sorts_by_amount_descending_with_stable_ties() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,TRANSFER,1,2,-50,1,SUCCESS,\"a\"\n\
+                 2,TRANSFER,1,2,100,1,SUCCESS,\"b\"\n\
+                 3,TRANSFER,1,2,-200,1,SUCCESS,\"c\"\n\
+                 4,TRANSFER,1,2,100,1,SUCCESS,\"d\"\n";
+
+    let result = convert_stdin(
+        input.as_bytes(),
+        &[
+            "--input-format",
+            "csv",
+            "--output-format",
+            "csv",
+            "--sort-by",
+            "amount",
+            "--descending",
+        ],
+    );
+
+    assert!(result.status.success(), "{}", stderr(&result));
+    let out = String::from_utf8_lossy(&result.stdout);
+    let ids: Vec<&str> = out
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["2", "4", "1", "3"], "{}", out);
+}
+
+#[test]
+fn skip_invalid_converts_the_rest_and_counts_skipped() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,DEPOSIT,0,7,100,1633036800000,SUCCESS,\"Good\"\n\
+                 2,DEPOSIT,0,7,lots,1633036800000,SUCCESS,\"Bad amount\"\n\
+                 3,DEPOSIT,0,7,300,1633036800000,SUCCESS,\"Good\"\n\
+                 4,GIFT,0,7,100,1633036800000,SUCCESS,\"Unknown type\"\n\
+                 5,DEPOSIT,9,7,100,1633036800000,SUCCESS,\"From a user\"\n\
+                 6,DEPOSIT,0,7,600,1633036800000,SUCCESS,\"Good\"\n";
+    let args = [
+        "--input-format",
+        "csv",
+        "--output-format",
+        "csv",
+        "--skip-invalid",
+    ];
+
+    let result = convert_stdin(input.as_bytes(), &args);
+
+    assert!(result.status.success(), "{}", stderr(&result));
+    let out = String::from_utf8_lossy(&result.stdout);
+    let ids: Vec<&str> = out
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["1", "3", "6"], "{}", out);
+    assert_eq!(stderr(&result), "Skipped 3 of 6 records\n");
+
+    // --verbose names each skipped record
+    let result = convert_stdin(input.as_bytes(), &[&args[..], &["--verbose"]].concat());
+    let err = stderr(&result);
+    assert!(
+        err.contains("stdin: line 3, record #1, field AMOUNT: "),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("line 6, record #4, field FROM_USER_ID: "),
+        "{}",
+        err
+    );
+
+    // Without the flag the first bad record stops the conversion
+    let result = convert_stdin(input.as_bytes(), &args[..4]);
+    assert!(!result.status.success());
+}
+
+#[test]
+fn lenient_keeps_unknown_type_and_status() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,REFUND,0,7,100,1633036800000,REVERSED,\"From a newer system\"\n";
+    let args = ["--input-format", "csv", "--output-format", "csv"];
+
+    let result = convert_stdin(input.as_bytes(), &args);
+    assert!(!result.status.success());
+    assert!(stderr(&result).contains("TX_TYPE"), "{}", stderr(&result));
+
+    let result = convert_stdin(input.as_bytes(), &[&args[..], &["--lenient"]].concat());
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), input);
+}
+
+#[test]
+fn decimal_amounts_read_and_write_as_decimals() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,DEPOSIT,0,7,1050.25,1633036800000,SUCCESS,\"Salary\"\n\
+                 2,WITHDRAWAL,7,0,0.5,1633036800000,SUCCESS,\"Fee\"\n";
+    let args = [
+        "--input-format",
+        "csv",
+        "--output-format",
+        "txt",
+        "--sort-by",
+        "tx-id",
+    ];
+
+    let result = convert_stdin(
+        input.as_bytes(),
+        &[&args[..], &["--decimal-amounts"]].concat(),
+    );
+    assert!(result.status.success(), "{}", stderr(&result));
+    let out = String::from_utf8_lossy(&result.stdout);
+    assert!(out.contains("AMOUNT: 1050.25\n"), "{}", out);
+    assert!(out.contains("AMOUNT: 0.50\n"), "{}", out);
+
+    // Without the flag the decimal is not an integer amount
+    let result = convert_stdin(input.as_bytes(), &args);
+    assert!(!result.status.success());
+    assert!(stderr(&result).contains("AMOUNT"), "{}", stderr(&result));
+}
+
+#[test]
+fn redact_pseudonymizes_consistently_per_key() {
+    let input = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                 1,DEPOSIT,0,7,149,1633039999999,SUCCESS,\"Salary for Ivan\"\n\
+                 2,TRANSFER,7,8,100,1633036800000,SUCCESS,\"Rent\"\n";
+    let redact = |key: &str| {
+        let result = convert_stdin(
+            input.as_bytes(),
+            &[
+                "--input-format",
+                "csv",
+                "--output-format",
+                "csv",
+                "--redact",
+                "--redact-key",
+                key,
+                "--redact-hour",
+                "--redact-amount-step",
+                "100",
+            ],
+        );
+        assert!(result.status.success(), "{}", stderr(&result));
+        let out = String::from_utf8_lossy(&result.stdout).into_owned();
+        out.lines()
+            .skip(1)
+            .map(|line| line.split(',').map(str::to_string).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+    };
+
+    let rows = redact("k1");
+    assert_eq!(rows.len(), 2);
+    // The deposit keeps its external side; user 7 gets the same pseudonym in both records
+    assert_eq!(rows[0][2], "0");
+    assert_ne!(rows[0][3], "7");
+    assert_eq!(rows[0][3], rows[1][2]);
+    assert_ne!(rows[1][3], "0");
+    assert_eq!(rows[0][4], "100");
+    assert_eq!(rows[0][5], "1633039200000");
+    assert!(!rows[0][7].contains("Ivan"));
+
+    assert_eq!(redact("k1"), rows);
+    assert_ne!(redact("k2")[0][3], rows[0][3]);
+
+    // --redact without a key is refused
+    let result = convert_stdin(
+        input.as_bytes(),
+        &[
+            "--input-format",
+            "csv",
+            "--output-format",
+            "csv",
+            "--redact",
+        ],
+    );
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("--redact-key"),
+        "{}",
+        stderr(&result)
+    );
+}
+
+#[test]
+fn joins_bin_csv_and_txt_inputs_in_order() {
+    let dir = test_dir("join");
+    let (bin, csv, txt) = (dir.join("a.bin"), dir.join("b.csv"), dir.join("c.txt"));
+    // a.bin holds tx_id 1 (from CSV)
+    fs::write(&bin, bin_bytes()).unwrap();
+    fs::write(
+        &csv,
+        "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+         3,DEPOSIT,0,7,300,1633036800000,SUCCESS,\"Third\"\n\
+         2,DEPOSIT,0,7,200,1633036800000,SUCCESS,\"Second\"\n",
+    )
+    .unwrap();
+    fs::write(
+        &txt,
+        "TX_ID: 4\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 7\nAMOUNT: 400\n\
+         TIMESTAMP: 1633036800000\nSTATUS: SUCCESS\nDESCRIPTION: \"Fourth\"\n\n\
+         TX_ID: 1\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 7\nAMOUNT: 100\n\
+         TIMESTAMP: 1633036800000\nSTATUS: SUCCESS\nDESCRIPTION: \"Salary\"\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_converter"))
+            .arg("--input")
+            .args([&bin, &csv, &txt])
+            .args(["--output-format", "csv"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Input order, then record order; the identical tx_id 1 from c.txt collapses
+    let result = run(&[]);
+    assert!(result.status.success(), "{}", stderr(&result));
+    let out = String::from_utf8_lossy(&result.stdout);
+    let ids: Vec<&str> = out
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["1", "3", "2", "4"], "{}", out);
+
+    // A conflicting tx_id names both files until a policy picks a side
+    let changed = fs::read_to_string(&txt)
+        .unwrap()
+        .replace("AMOUNT: 100", "AMOUNT: 150");
+    fs::write(&txt, changed).unwrap();
+    let result = run(&[]);
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("tx_id 1 differs between") && stderr(&result).contains("c.txt"),
+        "{}",
+        stderr(&result)
+    );
+    let result = run(&["--merge-policy", "prefer-last"]);
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert!(
+        String::from_utf8_lossy(&result.stdout).contains("\n1,DEPOSIT,0,7,150,"),
+        "{}",
+        String::from_utf8_lossy(&result.stdout)
+    );
+
+    // A broken file is named with its line; --continue-on-error converts the rest
+    fs::write(
+        &csv,
+        "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+         3,DEPOSIT,0,7,300,1633036800000,SUCCESS,\"Third\"\n\
+         2,DEPOSIT,0,7,oops,1633036800000,SUCCESS,\"Second\"\n",
+    )
+    .unwrap();
+    let result = run(&["--merge-policy", "prefer-last"]);
+    assert!(!result.status.success());
+    assert!(
+        stderr(&result).contains("b.csv: ") && stderr(&result).contains("line 3"),
+        "{}",
+        stderr(&result)
+    );
+    let result = run(&["--merge-policy", "prefer-last", "--continue-on-error"]);
+    assert_eq!(result.status.code(), Some(2), "{}", stderr(&result));
+    assert!(
+        stderr(&result).contains("Skipped 1 of 3 inputs"),
+        "{}",
+        stderr(&result)
+    );
+    let out = String::from_utf8_lossy(&result.stdout);
+    assert_eq!(out.lines().count(), 3, "{}", out);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn progress_bar_goes_to_stderr() {
+    let dir = test_dir("progress");
+    let input = dir.join("in.csv");
+    fs::write(&input, CSV).unwrap();
+
+    // A file has a known size: the bar ends at 100%
+    let result = to_csv_detected(&input, &["--progress"]);
+    assert!(result.status.success(), "{}", stderr(&result));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), CSV);
+    assert!(
+        stderr(&result).contains("100% 1 records"),
+        "{}",
+        stderr(&result)
+    );
+
+    // stdin has none: only the counters
+    let piped = convert_stdin(
+        CSV.as_bytes(),
+        &[
+            "--input-format",
+            "csv",
+            "--output-format",
+            "csv",
+            "--progress",
+        ],
+    );
+    assert!(piped.status.success(), "{}", stderr(&piped));
+    assert!(
+        stderr(&piped).contains("stdin: 1 records, "),
+        "{}",
+        stderr(&piped)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reads_and_writes_gzip_by_extension() {
+    let dir = test_dir("gzip");
+    let input = dir.join("in.csv");
+    let output = dir.join("out.bin.gz");
+    fs::write(&input, CSV).unwrap();
+
+    let result = convert(&input, &output, &[]);
+    assert!(result.status.success(), "{}", stderr(&result));
+    let compressed = fs::read(&output).unwrap();
+    assert!(compressed.starts_with(&[0x1f, 0x8b]));
+
+    // The format under .gz is detected from the extension, and on stdin from the content
+    let back = to_csv_detected(&output, &[]);
+    assert!(back.status.success(), "{}", stderr(&back));
+    assert_eq!(String::from_utf8_lossy(&back.stdout), CSV);
+    let piped = convert_stdin(&compressed, &["--output-format", "csv"]);
+    assert!(piped.status.success(), "{}", stderr(&piped));
+    assert_eq!(String::from_utf8_lossy(&piped.stdout), CSV);
+    let _ = fs::remove_dir_all(&dir);
+}
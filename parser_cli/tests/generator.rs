@@ -0,0 +1,132 @@
+//! Runs the generator binary and reads its output back with the library
+
+use parser::{Format, OperationStatus, OperationType};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("generator_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn generate(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn generates_valid_operations_in_every_format() {
+    let dir = test_dir("formats");
+    for (format, name) in [
+        (Format::Bin, "bin"),
+        (Format::Csv, "csv"),
+        (Format::Txt, "txt"),
+    ] {
+        let path = dir.join(format!("ops.{}", name));
+        let output = generate(&[
+            "--count",
+            "500",
+            "--output-format",
+            name,
+            "--output",
+            path.to_str().unwrap(),
+            "--tricky",
+            "0.5",
+            "--first-tx-id",
+            "1000",
+        ]);
+        assert!(output.status.success(), "{:?}", output);
+        let summary = String::from_utf8_lossy(&output.stderr);
+        assert!(summary.contains("Generated 500 operations"), "{}", summary);
+
+        let operations = format
+            .parse_all_vec(fs::read(&path).unwrap().as_slice())
+            .unwrap();
+        assert_eq!(operations.len(), 500, "{}", name);
+        for (i, op) in operations.iter().enumerate() {
+            assert_eq!(op.tx_id, 1000 + i as u64);
+            op.validate().unwrap();
+        }
+    }
+}
+
+#[test]
+fn ratios_and_time_window_shape_the_sample() {
+    let dir = test_dir("ratios");
+    let path = dir.join("sample.bin");
+    let output = generate(&[
+        "--count",
+        "4000",
+        "--format",
+        "binary",
+        "--output",
+        path.to_str().unwrap(),
+        "--seed",
+        "42",
+        "--transfer-ratio",
+        "0.7",
+        "--failure-ratio",
+        "0.1",
+        "--start-ts",
+        "2024-03-01T00:00:00Z",
+        "--end-ts",
+        "1709337599999",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let operations = Format::Bin
+        .parse_all_vec(fs::read(&path).unwrap().as_slice())
+        .unwrap();
+    assert_eq!(operations.len(), 4000);
+    let share = |count: usize| count as f64 / operations.len() as f64;
+    let transfers = share(
+        operations
+            .iter()
+            .filter(|op| op.tx_type == OperationType::Transfer)
+            .count(),
+    );
+    let failures = share(
+        operations
+            .iter()
+            .filter(|op| op.status == OperationStatus::Failure)
+            .count(),
+    );
+    assert!((transfers - 0.7).abs() < 0.05, "{}", transfers);
+    assert!((failures - 0.1).abs() < 0.03, "{}", failures);
+    for op in &operations {
+        op.validate().unwrap();
+        assert!((1709251200000..=1709337599999).contains(&op.timestamp));
+    }
+}
+
+#[test]
+fn same_seed_gives_same_output() {
+    let run = |seed: &str| {
+        let output = generate(&["--count", "50", "--output-format", "csv", "--seed", seed]);
+        assert!(output.status.success(), "{:?}", output);
+        output.stdout
+    };
+    assert_eq!(run("3"), run("3"));
+    assert_ne!(run("3"), run("4"));
+}
+
+#[test]
+fn rejects_an_empty_amount_range() {
+    let output = generate(&[
+        "--count",
+        "1",
+        "--output-format",
+        "csv",
+        "--min-amount",
+        "10",
+        "--max-amount",
+        "5",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--min-amount"));
+}
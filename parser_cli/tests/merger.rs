@@ -0,0 +1,155 @@
+//! Runs the merger binary on overlapping files and checks the merged output
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const HEADER: &str = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n";
+
+fn row(tx_id: u64, amount: i64, timestamp: u64) -> String {
+    format!(
+        "{},DEPOSIT,0,7,{},{},SUCCESS,\"Deposit {}\"\n",
+        tx_id, amount, timestamp, tx_id
+    )
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("merger_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn merge(inputs: &[&Path], extra: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_merger"));
+    for input in inputs {
+        command.arg("--input").arg(input);
+    }
+    command
+        .args(["--output-format", "csv"])
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Two files sharing tx_id 2 (identical) and tx_id 3 (different AMOUNT, b is newer)
+fn write_inputs(dir: &Path) -> (PathBuf, PathBuf) {
+    let (a, b) = (dir.join("a.csv"), dir.join("b.csv"));
+    fs::write(
+        &a,
+        format!(
+            "{}{}{}{}",
+            HEADER,
+            row(1, 100, 10),
+            row(2, 200, 20),
+            row(3, 300, 30)
+        ),
+    )
+    .unwrap();
+    fs::write(
+        &b,
+        format!(
+            "{}{}{}{}",
+            HEADER,
+            row(3, 333, 35),
+            row(2, 200, 20),
+            row(4, 400, 40)
+        ),
+    )
+    .unwrap();
+    (a, b)
+}
+
+#[test]
+fn conflict_fails_by_default() {
+    let dir = test_dir("fail");
+    let (a, b) = write_inputs(&dir);
+
+    let output = merge(&[&a, &b], &[]);
+
+    assert!(!output.status.success());
+    let err = stderr(&output);
+    assert!(err.contains("tx_id 3 differs between"), "{}", err);
+    assert!(err.contains("(AMOUNT, TIMESTAMP)"), "{}", err);
+    assert!(output.stdout.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn policies_pick_a_side_and_report_it() {
+    let dir = test_dir("policy");
+    let (a, b) = write_inputs(&dir);
+
+    for (policy, kept) in [
+        ("prefer-first", row(3, 300, 30)),
+        ("prefer-newest", row(3, 333, 35)),
+    ] {
+        let output = merge(&[&a, &b], &["--policy", policy]);
+
+        assert!(output.status.success(), "{}: {}", policy, stderr(&output));
+        let expected = format!(
+            "{}{}{}{}{}",
+            HEADER,
+            row(1, 100, 10),
+            row(2, 200, 20),
+            kept,
+            row(4, 400, 40)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected,
+            "{}",
+            policy
+        );
+        let err = stderr(&output);
+        assert!(err.contains("tx_id 3: kept"), "{}", err);
+        // Provenance of both records: path, line and record number
+        let (first, second) = (
+            format!("{}:4 (record #2, byte 156)", a.display()),
+            format!("{}:2 (record #0, byte 74)", b.display()),
+        );
+        let (kept_at, dropped_at) = if policy == "prefer-first" {
+            (&first, &second)
+        } else {
+            (&second, &first)
+        };
+        assert!(
+            err.contains(&format!("kept {}, dropped {}", kept_at, dropped_at)),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("4 records from 2 files: 1 identical duplicates, 1 conflicts resolved"),
+            "{}",
+            err
+        );
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn writes_output_file_in_any_input_format() {
+    let dir = test_dir("output");
+    let (a, _) = write_inputs(&dir);
+    let txt = dir.join("c.txt");
+    fs::write(
+        &txt,
+        "TX_ID: 9\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 7\nAMOUNT: 900\n\
+         TIMESTAMP: 90\nSTATUS: SUCCESS\nDESCRIPTION: \"Deposit 9\"\n",
+    )
+    .unwrap();
+    let out = dir.join("merged.csv");
+
+    let output = merge(&[&a, &txt], &["--output", out.to_str().unwrap()]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    let merged = fs::read_to_string(&out).unwrap();
+    assert_eq!(merged.lines().count(), 5, "{}", merged);
+    assert!(merged.ends_with(&row(9, 900, 90)), "{}", merged);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,173 @@
+//! Runs the stats binary on small files of every format and checks the summary
+
+use parser::{Format, Operation, OperationStatus, OperationType, WriteOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stats_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn operation(tx_id: u64, tx_type: OperationType, amount: i64) -> Operation {
+    let builder = match tx_type {
+        OperationType::Deposit => Operation::deposit(7, amount),
+        OperationType::Withdrawal => Operation::withdrawal(8, amount),
+        _ => Operation::transfer(7, 8, amount),
+    };
+    builder
+        .tx_id(tx_id)
+        .timestamp(1633036800000 + tx_id * 1000)
+        .status(if tx_id == 4 {
+            OperationStatus::Failure
+        } else {
+            OperationStatus::Success
+        })
+        .description(format!("Operation {}", tx_id))
+        .build()
+        .unwrap()
+}
+
+fn operations() -> Vec<Operation> {
+    vec![
+        operation(1, OperationType::Deposit, 100),
+        operation(2, OperationType::Deposit, 300),
+        operation(3, OperationType::Transfer, i64::MAX),
+        operation(4, OperationType::Transfer, i64::MAX),
+        operation(5, OperationType::Withdrawal, 50),
+    ]
+}
+
+fn stats(path: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stats"))
+        .arg("--input")
+        .arg(path)
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn json_summary_in_every_format() {
+    let dir = test_dir("json");
+    for (format, name) in [
+        (Format::Bin, "ops.bin"),
+        (Format::Csv, "ops.csv"),
+        (Format::Txt, "ops.txt"),
+        (Format::Json, "ops.json"),
+        (Format::Jsonl, "ops.jsonl"),
+    ] {
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, &operations(), &WriteOptions::default())
+            .unwrap();
+        let path = dir.join(name);
+        fs::write(&path, buf).unwrap();
+
+        let output = stats(&path, &["--json"]);
+
+        assert!(output.status.success(), "{}: {}", name, stderr(&output));
+        let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(value["operations"], 5, "{}", name);
+        assert_eq!(value["by_type"][0]["tx_type"], "DEPOSIT", "{}", name);
+        assert_eq!(value["by_type"][0]["count"], 2, "{}", name);
+        assert_eq!(value["by_type"][0]["total_amount"], "400", "{}", name);
+        assert_eq!(value["by_type"][0]["mean_amount"], 200.0, "{}", name);
+        // Two i64::MAX amounts: the total is past i64 but still exact
+        assert_eq!(
+            value["by_type"][1]["total_amount"],
+            (2 * i128::from(i64::MAX)).to_string(),
+            "{}",
+            name
+        );
+        assert_eq!(value["by_status"]["FAILURE"], 1, "{}", name);
+        assert_eq!(value["first_timestamp"], 1633036801000u64, "{}", name);
+        assert_eq!(value["last_timestamp"], 1633036805000u64, "{}", name);
+        assert_eq!(value["distinct_users"], 2, "{}", name);
+        assert_eq!(value["min_amount"], 50, "{}", name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn table_summary() {
+    let dir = test_dir("table");
+    let path = dir.join("ops.data");
+    let mut buf = Vec::new();
+    Format::Csv
+        .write_all_with_options(&mut buf, &operations(), &WriteOptions::default())
+        .unwrap();
+    fs::write(&path, buf).unwrap();
+
+    // No known extension: the format comes from the content
+    let output = stats(&path, &[]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = String::from_utf8_lossy(&output.stdout);
+    assert!(out.starts_with("Operations: 5\n"), "{}", out);
+    assert!(out.contains("WITHDRAWAL"), "{}", out);
+    assert!(
+        out.contains("Status: SUCCESS 4, FAILURE 1, PENDING 0"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Period: 2021-09-30T21:20:01.000Z .. 2021-09-30T21:20:05.000Z"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Distinct users: 2 (senders 2, receivers 2)"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains(&format!("Amount range: 50 .. {}", i64::MAX)),
+        "{}",
+        out
+    );
+
+    let output = stats(&path, &["--format", "txt"]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).starts_with("Operations: 0\n"),
+        "{}",
+        stderr(&output)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn top_balances_sum_past_i64() {
+    let dir = test_dir("top");
+    let mut ops = operations();
+    ops[3].status = OperationStatus::Success;
+    let mut buf = Vec::new();
+    Format::Csv
+        .write_all_with_options(&mut buf, &ops, &WriteOptions::default())
+        .unwrap();
+    let path = dir.join("ops.csv");
+    fs::write(&path, buf).unwrap();
+
+    // User 8 receives i64::MAX twice, user 7 sends it twice
+    let output = stats(&path, &["--top-balances", "5"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let max = i128::from(i64::MAX);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("USER_ID,BALANCE\n8,{}\n7,{}\n", 2 * max - 50, 400 - 2 * max)
+    );
+
+    let output = stats(&path, &["--top-balances", "1", "--no-header"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("8,{}\n", 2 * max - 50)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,355 @@
+//! Runs the validator binary on broken files of every format
+
+use parser::{Format, Operation, OperationStatus, WriteOptions};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("validator_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn deposit(tx_id: u64, amount: i64) -> Operation {
+    Operation::deposit(7, amount)
+        .tx_id(tx_id)
+        .timestamp(1633036800000)
+        .status(OperationStatus::Success)
+        .description(format!("Deposit {}", tx_id))
+        .build()
+        .unwrap()
+}
+
+/// Four records: a good one, one to break, one to turn into a DEPOSIT from
+/// a user (fails validation) and a repeated tx_id 1
+///
+/// Writers refuse invalid records, so the fixtures are patched after writing.
+fn operations() -> Vec<Operation> {
+    vec![
+        deposit(1, 100),
+        deposit(2, 200),
+        deposit(3, 300),
+        deposit(1, 400),
+    ]
+}
+
+fn encode(format: Format, operations: &[Operation]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    format
+        .write_all_with_options(&mut buf, operations, &WriteOptions::default())
+        .unwrap();
+    buf
+}
+
+/// The fixture for `format` with the second record unparseable and the
+/// third one a DEPOSIT from user 5
+fn broken(format: Format) -> Vec<u8> {
+    let replacements: [(&str, &str); 2] = match format {
+        Format::Bin => {
+            let full = encode(format, &operations());
+            let first = encode(format, &operations()[..1]).len();
+            let second = encode(format, &operations()[..2]).len();
+            // FROM_USER_ID 0 and TO_USER_ID 7 as big-endian u64s; only the
+            // third record is searched, so the first match is its own
+            let users = [0u64.to_be_bytes(), 7u64.to_be_bytes()].concat();
+            let mut rest = full[second..].to_vec();
+            let at = rest.windows(16).position(|w| w == users).unwrap();
+            rest[at + 7] = 5;
+            // Garbage in place of the second record
+            return [&full[..first], b"garbage", &rest].concat();
+        }
+        Format::Csv => [(",7,200,", ",7,lots,"), ("3,DEPOSIT,0,", "3,DEPOSIT,5,")],
+        Format::Txt => [
+            ("AMOUNT: 200", "AMOUNT: lots"),
+            (
+                "TX_ID: 3\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0",
+                "TX_ID: 3\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 5",
+            ),
+        ],
+        Format::Json | Format::Jsonl => [
+            ("\"AMOUNT\": 200", "\"AMOUNT\": \"lots\""),
+            (
+                "\"TX_ID\": 3, \"TX_TYPE\": \"DEPOSIT\", \"FROM_USER_ID\": 0",
+                "\"TX_ID\": 3, \"TX_TYPE\": \"DEPOSIT\", \"FROM_USER_ID\": 5",
+            ),
+        ],
+    };
+    let mut text = String::from_utf8(encode(format, &operations())).unwrap();
+    for (from, to) in replacements {
+        assert_eq!(text.matches(from).count(), 1, "{}", text);
+        text = text.replace(from, to);
+    }
+    text.into_bytes()
+}
+
+fn validate(path: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_validator"))
+        .arg("--input")
+        .arg(path)
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn clean_file_exits_zero() {
+    let dir = test_dir("clean");
+    let path = dir.join("ops.csv");
+    fs::write(&path, encode(Format::Csv, &operations()[..2])).unwrap();
+
+    let output = validate(&path, &[]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert!(
+        stdout(&output).contains("Records: 2, invalid: 0, duplicate tx_ids: 0"),
+        "{}",
+        stdout(&output)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reports_every_problem_in_each_format() {
+    let dir = test_dir("broken");
+    for (format, name) in [
+        (Format::Bin, "ops.bin"),
+        (Format::Csv, "ops.csv"),
+        (Format::Txt, "ops.txt"),
+        (Format::Json, "ops.json"),
+        (Format::Jsonl, "ops.jsonl"),
+    ] {
+        let path = dir.join(name);
+        fs::write(&path, broken(format)).unwrap();
+
+        let output = validate(&path, &[]);
+
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        let out = stdout(&output);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4, "{}: {}", name, out);
+        assert!(lines[0].contains("(record #1"), "{}: {}", name, out);
+        assert!(lines[1].contains("(record #2"), "{}: {}", name, out);
+        assert!(lines[1].contains("FROM_USER_ID"), "{}: {}", name, out);
+        assert_eq!(lines[2], "Duplicate tx_id 1 (2 records)", "{}", name);
+        assert_eq!(
+            lines[3], "Records: 4, invalid: 2, duplicate tx_ids: 1",
+            "{}",
+            name
+        );
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn positions_point_into_the_file() {
+    let dir = test_dir("positions");
+    let path = dir.join("ops.csv");
+    fs::write(&path, broken(Format::Csv)).unwrap();
+
+    let out = stdout(&validate(&path, &[]));
+
+    // The header is line 1, the broken record is the second one
+    assert!(
+        out.starts_with(&format!("{}:3 (record #1, byte ", path.display())),
+        "{}",
+        out
+    );
+    assert!(out.lines().next().unwrap().contains("AMOUNT"), "{}", out);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn json_report() {
+    let dir = test_dir("json");
+    let path = dir.join("ops.txt");
+    fs::write(&path, broken(Format::Txt)).unwrap();
+
+    let output = validate(&path, &["--json"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["format"], "txt");
+    assert_eq!(value["records"], 4);
+    assert_eq!(value["valid"], 2);
+    assert_eq!(value["rejected"][0]["record"], 1);
+    assert!(value["rejected"][0]["line"].is_u64());
+    assert_eq!(value["duplicate_tx_ids"][0]["tx_id"], 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reads_stdin_with_explicit_format() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_validator"))
+        .args(["--input", "-", "--format", "bin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Fails with a broken pipe if the validator bails out before reading stdin
+    let _ = child.stdin.take().unwrap().write_all(&broken(Format::Bin));
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stdout(&output).starts_with("stdin (record #1"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn unreadable_file_exits_two() {
+    let output = validate(Path::new("/nonexistent/ops.csv"), &[]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error:"));
+}
+
+#[test]
+fn deep_checks_records_against_each_other() {
+    let dir = test_dir("deep");
+    let path = dir.join("ops.csv");
+    let header = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n";
+    let rows = "1,DEPOSIT,0,7,100,1633036800000,SUCCESS,\"\"\n\
+                2,TRANSFER,7,8,50,1633036801000,SUCCESS,\"\"\n";
+    fs::write(&path, format!("{}{}", header, rows)).unwrap();
+
+    // User 8 appears only in the transfer: a warning, the file still passes
+    let output = validate(&path, &["--deep"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert!(
+        stdout(&output).contains("warning: orphan-user: tx_id 2: transfer user 8"),
+        "{}",
+        stdout(&output)
+    );
+    assert!(
+        stdout(&output).contains("Cross-record findings: 1"),
+        "{}",
+        stdout(&output)
+    );
+
+    // Without --deep the same file has nothing to report
+    let output = validate(&path, &[]);
+    assert!(!stdout(&output).contains("orphan"), "{}", stdout(&output));
+
+    // The same tx_id as SUCCESS and FAILURE is an error
+    let failed = "1,DEPOSIT,0,7,100,1633036800000,FAILURE,\"\"\n";
+    fs::write(&path, format!("{}{}{}", header, rows, failed)).unwrap();
+    let output = validate(&path, &["--deep", "--until", "1633036800500"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let out = stdout(&output);
+    assert!(
+        out.contains("error: contradictory-status: tx_id 1 is both SUCCESS and FAILURE"),
+        "{}",
+        out
+    );
+    assert!(out.contains("outside the claimed range"), "{}", out);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn check_gaps_reports_missing_tx_ids() {
+    let dir = test_dir("gaps");
+    let path = dir.join("ops.csv");
+    let ops = [
+        deposit(1, 100),
+        deposit(2, 200),
+        deposit(5, 500),
+        deposit(7, 700),
+    ];
+    fs::write(&path, encode(Format::Csv, &ops)).unwrap();
+
+    let output = validate(&path, &["--check-gaps"]);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let out = stdout(&output);
+    assert!(
+        out.contains("Missing tx_ids 3-4\nMissing tx_id 6\n"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Expected tx_ids: 7, present: 4, missing: 3"),
+        "{}",
+        out
+    );
+
+    // Numbering from 5 on has a single hole; the same file without the flag is clean
+    let output = validate(&path, &["--check-gaps", "--expect-from", "5"]);
+    assert!(
+        stdout(&output).contains("missing: 1"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(validate(&path, &[]).status.code(), Some(0));
+
+    fs::write(&path, encode(Format::Csv, &ops[..2])).unwrap();
+    let output = validate(&path, &["--check-gaps"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert!(!stdout(&output).contains("Missing"), "{}", stdout(&output));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn max_description_len_flags_long_descriptions() {
+    let dir = test_dir("description");
+    let path = dir.join("ops.txt");
+    let mut long = deposit(2, 200);
+    // 12 bytes in 6 characters
+    long.description = "ЖЖЖЖЖЖ".to_string();
+    fs::write(&path, encode(Format::Txt, &[deposit(1, 100), long])).unwrap();
+
+    let output = validate(&path, &["--max-description-len", "10"]);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let out = stdout(&output);
+    assert!(
+        out.contains(
+            "tx_id 2: Invalid field 'DESCRIPTION': 12 bytes exceeds the limit of 10 bytes"
+        ),
+        "{}",
+        out
+    );
+    assert!(out.contains("Descriptions over 10 bytes: 1"), "{}", out);
+
+    let output = validate(&path, &["--max-description-len", "12"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn json_report_includes_gaps_and_long_descriptions() {
+    let dir = test_dir("json_checks");
+    let path = dir.join("ops.jsonl");
+    let mut long = deposit(4, 400);
+    long.description = "x".repeat(20);
+    fs::write(&path, encode(Format::Jsonl, &[deposit(1, 100), long])).unwrap();
+
+    let output = validate(
+        &path,
+        &["--json", "--check-gaps", "--max-description-len", "16"],
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["records"], 2);
+    assert_eq!(value["gaps"]["missing"][0]["first"], 2);
+    assert_eq!(value["gaps"]["missing"][0]["last"], 3);
+    assert_eq!(value["gaps"]["expected"], 4);
+    assert_eq!(value["long_descriptions"][0]["tx_id"], 4);
+    assert!(
+        value["long_descriptions"][0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("20 bytes")
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
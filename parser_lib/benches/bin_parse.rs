@@ -0,0 +1,42 @@
+//! Разбор YPBankBin: через `Read` против разбора среза (как у файла в памяти)
+//!
+//! `cargo bench --bench bin_parse --features testgen`
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use parser::bin_format;
+use parser::testgen::rand::{SeedableRng, rngs::StdRng};
+use parser::testgen::{GenProfile, generate_operations};
+use std::hint::black_box;
+
+const RECORDS: usize = 1_000_000;
+
+fn synthetic_dump() -> Vec<u8> {
+    let operations = generate_operations(
+        &mut StdRng::seed_from_u64(1),
+        RECORDS,
+        &GenProfile::default(),
+    );
+    let mut buf = Vec::new();
+    for op in &operations {
+        bin_format::write_operation(&mut buf, op).unwrap();
+    }
+    buf
+}
+
+fn bench_bin_parse(c: &mut Criterion) {
+    let dump = synthetic_dump();
+
+    let mut group = c.benchmark_group("bin_parse_1m");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(dump.len() as u64));
+    group.bench_function("reader", |b| {
+        b.iter(|| bin_format::parse_all(black_box(dump.as_slice())).unwrap())
+    });
+    group.bench_function("slice", |b| {
+        b.iter(|| bin_format::parse_all_from_slice(black_box(&dump)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_bin_parse);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Разбор YPBankCsv: последовательный, а с фичей `parallel` — и параллельный
+//! (потоком и по срезу)
+//!
+//! `cargo bench --bench csv_parse --features testgen,parallel`
+//!
+//! До/после правки горячего пути: `cargo bench --bench csv_parse -- --save-baseline before`
+//! на старом коммите, затем `cargo bench --bench csv_parse -- --baseline before`.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use parser::WriteOptions;
+use parser::csv_format;
+use parser::testgen::rand::{SeedableRng, rngs::StdRng};
+use parser::testgen::{GenProfile, generate_operations};
+use std::hint::black_box;
+
+const RECORDS: usize = 1_000_000;
+
+fn synthetic_csv() -> Vec<u8> {
+    // Каждое десятое описание — с кавычками, запятыми и переводами строк
+    let operations = generate_operations(
+        &mut StdRng::seed_from_u64(1),
+        RECORDS,
+        &GenProfile::default(),
+    );
+    let mut buf = Vec::new();
+    csv_format::write_all_with_options(&mut buf, &operations, &WriteOptions::default()).unwrap();
+    buf
+}
+
+fn bench_csv_parse(c: &mut Criterion) {
+    let csv = synthetic_csv();
+
+    let mut group = c.benchmark_group("csv_parse_1m");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(csv.len() as u64));
+    group.bench_function("sequential", |b| {
+        b.iter(|| csv_format::parse_all(black_box(csv.as_slice())).unwrap())
+    });
+    #[cfg(feature = "parallel")]
+    group.bench_function("parallel_reader", |b| {
+        b.iter(|| csv_format::parse_all_parallel(black_box(csv.as_slice())).unwrap())
+    });
+    #[cfg(feature = "parallel")]
+    group.bench_function("parallel_slice", |b| {
+        b.iter(|| csv_format::parse_all_parallel_from_slice(black_box(&csv)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_csv_parse);
+criterion_main!(benches);
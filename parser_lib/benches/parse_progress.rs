@@ -0,0 +1,61 @@
+//! Цена обработчика хода разбора: разбор с отчетом против того же разбора
+//! с [`parser::Progress`] (обработчик только смотрит на долю прочитанного)
+//!
+//! `cargo bench --bench parse_progress --features testgen`
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use parser::testgen::rand::{SeedableRng, rngs::StdRng};
+use parser::testgen::{GenProfile, generate_operations};
+use parser::{Format, ParseOptions, WriteOptions};
+use std::hint::black_box;
+use std::ops::ControlFlow;
+
+const RECORDS: usize = 1_000_000;
+
+fn synthetic(format: Format) -> Vec<u8> {
+    let operations = generate_operations(
+        &mut StdRng::seed_from_u64(1),
+        RECORDS,
+        &GenProfile::default(),
+    );
+    let mut buf = Vec::new();
+    format
+        .write_all_with_options(&mut buf, &operations, &WriteOptions::default())
+        .unwrap();
+    buf
+}
+
+fn bench_parse_progress(c: &mut Criterion) {
+    for (format, name) in [(Format::Bin, "bin"), (Format::Csv, "csv")] {
+        let data = synthetic(format);
+        let options = ParseOptions {
+            known_size: Some(data.len() as u64),
+            ..Default::default()
+        };
+
+        let mut group = c.benchmark_group(format!("{}_progress_1m", name));
+        group.sample_size(10);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function("report", |b| {
+            b.iter(|| {
+                format
+                    .parse_all_with_report(black_box(data.as_slice()), &options)
+                    .unwrap()
+            })
+        });
+        group.bench_function("progress", |b| {
+            b.iter(|| {
+                format
+                    .parse_all_with_progress(black_box(data.as_slice()), &options, |progress| {
+                        black_box(progress.fraction());
+                        ControlFlow::Continue(())
+                    })
+                    .unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse_progress);
+criterion_main!(benches);
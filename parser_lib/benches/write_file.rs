@@ -0,0 +1,50 @@
+//! Запись 1M операций в файл без буфера у вызывающего (`File` как есть)
+//!
+//! `cargo bench --bench write_file --features testgen`
+//!
+//! Сравнить с другим коммитом: `-- --save-baseline before` там и `-- --baseline before` здесь.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use parser::testgen::rand::{SeedableRng, rngs::StdRng};
+use parser::testgen::{GenProfile, generate_operations};
+use parser::{Format, Operation, WriteOptions};
+use std::fs::File;
+use std::hint::black_box;
+
+const RECORDS: usize = 1_000_000;
+
+fn synthetic_operations() -> Vec<Operation> {
+    generate_operations(
+        &mut StdRng::seed_from_u64(1),
+        RECORDS,
+        &GenProfile::default(),
+    )
+}
+
+fn bench_write_file(c: &mut Criterion) {
+    let operations = synthetic_operations();
+    let path = std::env::temp_dir().join(format!("write_file_bench_{}", std::process::id()));
+
+    let mut group = c.benchmark_group("write_file_1m");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    for (name, format) in [
+        ("bin", Format::Bin),
+        ("csv", Format::Csv),
+        ("txt", Format::Txt),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let file = File::create(&path).unwrap();
+                format
+                    .write_all_with_options(file, black_box(&operations), &WriteOptions::default())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, bench_write_file);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Перегенерация эталонных файлов `golden/` (только при намеренном изменении формата)
+//!
+//! `cargo run --example write_goldens --features test-utils [-- <dir>]`, по умолчанию `golden/` этого крейта
+
+use parser::conformance::write_goldens;
+use std::path::PathBuf;
+
+fn main() -> parser::Result<()> {
+    let dir = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden"));
+    for path in write_goldens(&dir)? {
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
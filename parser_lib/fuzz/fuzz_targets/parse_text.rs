@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parser::text_format;
+
+// Любой набор байт должен давать Ok или Err, но не панику и не OOM
+fuzz_target!(|data: &[u8]| {
+    let _ = text_format::parse_all(data);
+});
@@ -0,0 +1,37 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parser::{Operation, bin_format, csv_format, text_format};
+use std::collections::HashSet;
+use std::io::Cursor;
+
+fn assert_same(expected: &Operation, actual: &Operation) {
+    assert_eq!(expected.tx_id, actual.tx_id);
+    assert_eq!(expected.tx_type, actual.tx_type);
+    assert_eq!(expected.from_user_id, actual.from_user_id);
+    assert_eq!(expected.to_user_id, actual.to_user_id);
+    assert_eq!(expected.amount, actual.amount);
+    assert_eq!(expected.timestamp, actual.timestamp);
+    assert_eq!(expected.status, actual.status);
+    assert_eq!(expected.description, actual.description);
+}
+
+// Operation приходит уже валидной: Arbitrary из фичи `arbitrary`
+fuzz_target!(|op: Operation| {
+    let operations: HashSet<Operation> = [op.clone()].into_iter().collect();
+
+    let mut buf = Vec::new();
+    bin_format::write_all(&mut buf, &operations).unwrap();
+    let parsed = bin_format::parse_all(Cursor::new(&buf)).unwrap();
+    assert_same(&op, parsed.get(&op).unwrap());
+
+    let mut buf = Vec::new();
+    csv_format::write_all(&mut buf, &operations).unwrap();
+    let parsed = csv_format::parse_all(Cursor::new(&buf)).unwrap();
+    assert_same(&op, parsed.get(&op).unwrap());
+
+    let mut buf = Vec::new();
+    text_format::write_all(&mut buf, &operations).unwrap();
+    let parsed = text_format::parse_all(Cursor::new(&buf)).unwrap();
+    assert_same(&op, parsed.get(&op).unwrap());
+});
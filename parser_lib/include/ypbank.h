@@ -0,0 +1,48 @@
+#ifndef YPBANK_H
+#define YPBANK_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define YPB_OK 0
+
+#define YPB_ERROR -1
+
+#define YPB_FORMAT_AUTO 0
+
+#define YPB_FORMAT_BIN 1
+
+#define YPB_FORMAT_CSV 2
+
+#define YPB_FORMAT_TXT 3
+
+#define YPB_FORMAT_JSON 4
+
+#define YPB_FORMAT_JSONL 5
+
+typedef struct YpbOperations YpbOperations;
+
+typedef struct {
+  uint64_t tx_id;
+  uint8_t tx_type;
+  uint64_t from_user_id;
+  uint64_t to_user_id;
+  int64_t amount;
+  uint64_t timestamp;
+  uint8_t status;
+  const char *description;
+} YpbOperation;
+
+int ypb_parse_file(const char *path, int format, YpbOperations **out);
+
+size_t ypb_count(const YpbOperations *handle);
+
+int ypb_get(const YpbOperations *handle, size_t index, YpbOperation *out);
+
+const char *ypb_last_error_message(void);
+
+void ypb_free(YpbOperations *handle);
+
+#endif  /* YPBANK_H */
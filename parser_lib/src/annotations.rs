@@ -0,0 +1,214 @@
+//! Заметки к операциям в отдельном файле рядом с данными (фича `serde`)
+//!
+//! Формат — NDJSON: по одному объекту
+//! `{"tx_id": .., "key": .., "value": .., "author": .., "ts": ..}` на строку.
+//! Пустые строки пропускаются. Сами файлы операций заметки не меняют.
+
+use crate::error::{ParseError, Result};
+use crate::operation::Operation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+
+/// Одна заметка к операции
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// TX_ID операции, к которой относится заметка
+    pub tx_id: u64,
+    /// Имя заметки ("reconciliation", "dispute", ...)
+    pub key: String,
+    /// Значение
+    pub value: String,
+    /// Кто оставил заметку
+    pub author: String,
+    /// Когда, миллисекунды от эпохи Unix (как TIMESTAMP операций)
+    pub ts: u64,
+}
+
+/// Операция вместе со своими заметками
+///
+/// Если в наборе операций нет такого TX_ID, `operation` равно `None`:
+/// заметка осиротела (запись потеряна или TX_ID в заметке с ошибкой).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedOperation {
+    /// TX_ID операции
+    pub tx_id: u64,
+    /// Сама операция; `None` — заметки без операции
+    pub operation: Option<Operation>,
+    /// Заметки в порядке файла
+    pub annotations: Vec<Annotation>,
+}
+
+impl AnnotatedOperation {
+    /// Заметки ссылаются на TX_ID, которого нет среди операций
+    pub fn is_orphan(&self) -> bool {
+        self.operation.is_none()
+    }
+
+    /// Значение заметки с именем `key`; при повторах — последнее в файле
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.annotations
+            .iter()
+            .rev()
+            .find(|annotation| annotation.key == key)
+            .map(|annotation| annotation.value.as_str())
+    }
+}
+
+/// Читает заметки из NDJSON
+///
+/// # Возвращает
+/// * `Ok(Vec<Annotation>)` - Заметки в порядке файла
+/// * `Err(ParseError::InvalidFormat)` - Строка не разобралась, с ее номером
+pub fn read_annotations<R: BufRead>(reader: R) -> Result<Vec<Annotation>> {
+    let mut annotations = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let annotation = serde_json::from_str(&line).map_err(|e| {
+            ParseError::InvalidFormat(format!("annotation line {}: {}", index + 1, e))
+        })?;
+        annotations.push(annotation);
+    }
+    Ok(annotations)
+}
+
+/// Записывает заметки в NDJSON, по одной на строку
+pub fn write_annotations<'a, W: Write>(
+    mut writer: W,
+    annotations: impl IntoIterator<Item = &'a Annotation>,
+) -> Result<()> {
+    for annotation in annotations {
+        let line = serde_json::to_string(annotation).expect("annotation always serializes");
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Сводит операции с заметками по TX_ID
+///
+/// # Возвращает
+/// Сначала все операции в порядке `operations` (у неаннотированных список
+/// заметок пуст), затем осиротевшие заметки, сгруппированные по TX_ID
+/// по возрастанию — у них [`AnnotatedOperation::is_orphan`].
+pub fn join<'a>(
+    operations: impl IntoIterator<Item = &'a Operation>,
+    annotations: &[Annotation],
+) -> Vec<AnnotatedOperation> {
+    let mut by_id: HashMap<u64, Vec<Annotation>> = HashMap::new();
+    for annotation in annotations {
+        by_id
+            .entry(annotation.tx_id)
+            .or_default()
+            .push(annotation.clone());
+    }
+
+    let mut joined: Vec<AnnotatedOperation> = operations
+        .into_iter()
+        .map(|op| AnnotatedOperation {
+            tx_id: op.tx_id,
+            operation: Some(op.clone()),
+            // Повторы TX_ID получают одни и те же заметки
+            annotations: by_id.get(&op.tx_id).cloned().unwrap_or_default(),
+        })
+        .collect();
+
+    let present: BTreeSet<u64> = joined.iter().map(|joined| joined.tx_id).collect();
+    let orphans: BTreeMap<u64, Vec<Annotation>> = by_id
+        .into_iter()
+        .filter(|(tx_id, _)| !present.contains(tx_id))
+        .collect();
+    joined.extend(
+        orphans
+            .into_iter()
+            .map(|(tx_id, annotations)| AnnotatedOperation {
+                tx_id,
+                operation: None,
+                annotations,
+            }),
+    );
+
+    joined
+}
+
+/// Имена заметок по алфавиту, без повторов — например, для колонок вывода
+pub fn annotation_keys(annotations: &[Annotation]) -> Vec<String> {
+    let keys: BTreeSet<&str> = annotations
+        .iter()
+        .map(|annotation| annotation.key.as_str())
+        .collect();
+    keys.into_iter().map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::golden_fixture;
+
+    fn note(tx_id: u64, key: &str, value: &str) -> Annotation {
+        Annotation {
+            tx_id,
+            key: key.to_string(),
+            value: value.to_string(),
+            author: "auditor \"A\"".to_string(),
+            ts: 1633036800000,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let annotations = vec![
+            note(1, "status", "confirmed with branch"),
+            note(2, "status", "disputed\nsee ticket"),
+            note(1, "reviewer", "Анна"),
+        ];
+
+        let mut buf = Vec::new();
+        write_annotations(&mut buf, &annotations).unwrap();
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 3);
+
+        buf.extend_from_slice(b"\n\n");
+        let parsed = read_annotations(buf.as_slice()).unwrap();
+        assert_eq!(parsed, annotations);
+    }
+
+    #[test]
+    fn test_bad_line_reports_number() {
+        let input = "{\"tx_id\":1,\"key\":\"k\",\"value\":\"v\",\"author\":\"a\",\"ts\":0}\n\
+                     {\"tx_id\":\"two\"}\n";
+
+        let err = read_annotations(input.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_join_flags_orphans() {
+        let operations = golden_fixture();
+        let first = operations[0].tx_id;
+        let missing = (1..)
+            .find(|id| operations.iter().all(|op| op.tx_id != *id))
+            .unwrap();
+        let annotations = vec![
+            note(first, "status", "disputed"),
+            note(missing, "status", "lost?"),
+            note(first, "status", "confirmed with branch"),
+        ];
+
+        let joined = join(&operations, &annotations);
+
+        assert_eq!(joined.len(), operations.len() + 1);
+        assert_eq!(joined[0].annotations.len(), 2);
+        assert_eq!(joined[0].value("status"), Some("confirmed with branch"));
+        assert!(joined[1].annotations.is_empty());
+        assert!(joined[..operations.len()].iter().all(|op| !op.is_orphan()));
+
+        let orphan = joined.last().unwrap();
+        assert!(orphan.is_orphan());
+        assert_eq!(orphan.tx_id, missing);
+        assert_eq!(orphan.value("status"), Some("lost?"));
+        assert_eq!(annotation_keys(&annotations), vec!["status".to_string()]);
+    }
+}
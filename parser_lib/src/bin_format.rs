@@ -1,61 +1,338 @@
-use crate::error::{ParseError, Result};
-use crate::operation::{Operation, OperationStatus, OperationType};
-use std::collections::HashSet;
-use std::io::{Read, Write};
+use crate::dedup::{DedupOutcome, DuplicatePolicy};
+use crate::error::{Location, ParseError, Result};
+use crate::format::Format;
+use crate::format::UTF8_BOM;
+use crate::operation::{
+    Extension, FullOperation, Operation, OperationStatus, OperationType, canonicalize_description,
+    escape_description,
+};
+use crate::options::{BinOptions, ParseOptions, WriteOptions};
+use crate::partition::ParseOutcome;
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{Meter, ParseReport};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::FusedIterator;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const MAGIC: [u8; 4] = [b'Y', b'P', b'B', b'N']; // магическое 'YPBN'
+/// Поля записи после RECORD_SIZE без самого описания: TX_ID..STATUS и DESC_LEN
+const FIXED_FIELDS_SIZE: u64 = 8 + 1 + 8 + 8 + 8 + 8 + 1 + 4;
+/// CRC32 в конце записи v2
+const CHECKSUM_SIZE: u64 = 4;
+/// Заголовок расширения в блоке TLV: тег u16 и длина значения u32
+const EXTENSION_HEADER_SIZE: usize = 2 + 4;
+/// Записи без байта версии (до его появления): на его месте старший байт RECORD_SIZE,
+/// который у записей меньше 16 МиБ всегда ноль
+const VERSION_LEGACY: u8 = 0;
+/// Поля без контрольной суммы ([`write_operation`])
+const VERSION_PLAIN: u8 = 1;
+/// Поля и CRC32 ([`write_operation_v2`])
+const VERSION_CHECKSUM: u8 = 2;
+/// Необязательный заголовок файла ([`write_all_with_header`]): метка и число записей u64
+const FILE_MAGIC: [u8; 4] = [b'Y', b'P', b'B', b'F'];
+const FILE_HEADER_LEN: usize = FILE_MAGIC.len() + 8;
+/// Больше заранее не резервируем: заголовок может быть битым
+const MAX_PREALLOCATED: u64 = 1 << 20;
+/// Сжатый контейнер ([`write_all_compressed`]): за меткой — поток zstd с обычным
+/// файлом YPBankBin внутри, с заголовком файла
+const COMPRESSED_MAGIC: [u8; 4] = [b'Y', b'P', b'B', b'Z'];
+/// Уровень zstd для [`write_all_compressed`]: по умолчанию у самого zstd
+#[cfg(feature = "zstd")]
+const COMPRESSION_LEVEL: i32 = 3;
 
 /// Походили по бинарнику и собираем операцию по отступам
+///
+/// После MAGIC идет байт версии раскладки: 1 — просто поля, 2 — поля и CRC32
+/// (см. [`write_operation_v2`]), он проверяется до разбора значений полей, и
+/// несовпадение дает [`ParseError::ChecksumMismatch`]. Неизвестная версия дает
+/// [`ParseError::UnsupportedVersion`]. Старые файлы без байта версии читаются
+/// как раньше: у них на этом месте ноль (старший байт RECORD_SIZE), а CRC32
+/// узнается по RECORD_SIZE, который на 4 байта больше полей.
+///
+/// Поля должны занять ровно RECORD_SIZE байт, иначе [`ParseError::InvalidRecordSize`];
+/// в записи v2 остаток между описанием и CRC32 — блок расширений ([`Operation::extensions`]).
+/// Запись читается целиком по RECORD_SIZE, так что и после этой ошибки, и после
+/// записи неизвестной версии поток остается на начале следующей записи.
+///
+/// Лимиты размеров — по умолчанию, см. [`parse_operation_with_options`].
 pub fn parse_operation<R: Read>(reader: &mut R) -> Result<Operation> {
-    // Read and verify MAGIC
-    let mut magic = [0u8; 4];
-    reader.read_exact(&mut magic)?;
+    parse_operation_with_options(reader, &BinOptions::default())
+}
 
-    if magic != MAGIC {
-        return Err(ParseError::InvalidMagic);
+/// То же, что [`parse_operation`], но с лимитами размеров из `options`
+///
+/// RECORD_SIZE и DESC_LEN больше лимита дают [`ParseError::InvalidField`]
+/// до чтения тела записи, так что битый заголовок не заставит читать гигабайты.
+pub fn parse_operation_with_options<R: Read>(
+    reader: &mut R,
+    options: &BinOptions,
+) -> Result<Operation> {
+    read_operation(reader, options, false)
+}
+
+/// [`parse_operation_with_options`]; с `lenient` незнакомые TX_TYPE и STATUS не ошибка
+fn read_operation<R: Read>(
+    reader: &mut R,
+    options: &BinOptions,
+    lenient: bool,
+) -> Result<Operation> {
+    let header = read_record_header(reader)?;
+    check_record_size(header.record_size, options)?;
+
+    // Даже в пределах лимита читаем через take: буфер растет по мере прихода байт
+    let record_size = u64::from(header.record_size);
+    let mut body = Vec::new();
+    reader.take(record_size).read_to_end(&mut body)?;
+    if body.len() as u64 != record_size {
+        let header_len = header_len(header.version);
+        return Err(truncated(
+            header_len + record_size as usize,
+            header_len + body.len(),
+        ));
     }
 
-    // Read RECORD_SIZE
-    let mut size_buf = [0u8; 4];
-    reader.read_exact(&mut size_buf)?;
-    let _record_size = u32::from_be_bytes(size_buf);
+    parse_body(&header, &body, options, lenient)
+}
 
-    let mut buf = [0u8; 8];
-    reader.read_exact(&mut buf)?;
-    let tx_id = u64::from_be_bytes(buf);
+/// Разбор записи с начала `buf` без промежуточных копий и чтения по полям
+///
+/// Для файлов, отображенных в память: поля берутся прямо из среза по смещениям,
+/// выход за RECORD_SIZE или за конец среза — ошибка. Проверки те же, что у
+/// [`parse_operation`].
+///
+/// # Возвращает
+/// * `Ok((Operation, usize))` - Операция и сколько байт заняла запись вместе с заголовком
+/// * `Err(ParseError)` - Если запись битая; срез, который кончается внутри MAGIC, дает
+///   `UnexpectedEof`, а после MAGIC — [`ParseError::TruncatedRecord`]
+pub fn parse_operation_from_slice(buf: &[u8]) -> Result<(Operation, usize)> {
+    parse_operation_from_slice_with_options(buf, &BinOptions::default())
+}
 
-    let mut type_buf = [0u8; 1];
-    reader.read_exact(&mut type_buf)?;
-    let tx_type = OperationType::from_u8(type_buf[0])?;
+/// То же, что [`parse_operation_from_slice`], но с лимитами размеров из `options`
+pub fn parse_operation_from_slice_with_options(
+    buf: &[u8],
+    options: &BinOptions,
+) -> Result<(Operation, usize)> {
+    decode_from_slice(buf, options, false)
+}
 
-    reader.read_exact(&mut buf)?;
-    let from_user_id = u64::from_be_bytes(buf);
+/// [`parse_operation_from_slice_with_options`]; с `lenient` незнакомые TX_TYPE и STATUS не ошибка
+fn decode_from_slice(
+    buf: &[u8],
+    options: &BinOptions,
+    lenient: bool,
+) -> Result<(Operation, usize)> {
+    let mut rest = buf;
+    let header = read_record_header(&mut rest)?;
+    check_record_size(header.record_size, options)?;
 
-    reader.read_exact(&mut buf)?;
-    let to_user_id = u64::from_be_bytes(buf);
+    let header_len = buf.len() - rest.len();
+    let record_size = header.record_size as usize;
+    let Some(body) = rest.get(..record_size) else {
+        return Err(truncated(header_len + record_size, buf.len()));
+    };
 
-    reader.read_exact(&mut buf)?;
-    let amount = i64::from_be_bytes(buf);
+    let operation = parse_body(&header, body, options, lenient)?;
+    Ok((operation, header_len + record_size))
+}
 
-    reader.read_exact(&mut buf)?;
-    let timestamp = u64::from_be_bytes(buf);
+/// Разбирает весь буфер через [`parse_operation_from_slice`]
+///
+/// Как и у [`parse_all`], конец среза на границе записи — конец данных, а
+/// недописанная последняя запись — [`ParseError::TruncatedRecord`]. Заголовок
+/// файла, если он есть, сверяется с числом записей.
+pub fn parse_all_from_slice(buf: &[u8]) -> Result<HashSet<Operation>> {
+    parse_all_from_slice_with_options(buf, &ParseOptions::default())
+}
 
-    reader.read_exact(&mut type_buf)?;
-    let status = OperationStatus::from_u8(type_buf[0])?;
+/// То же, что [`parse_all_from_slice`], но с настройками (токен отмены, лимиты размеров)
+pub fn parse_all_from_slice_with_options(
+    buf: &[u8],
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    let len = buf.len();
+    let (declared, mut buf) = split_file_header(buf)?;
+    let capacity = declared.unwrap_or(0).min(MAX_PREALLOCATED) as usize;
+    let mut operations = HashSet::with_capacity(capacity);
+    let mut records = 0;
 
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf)?;
-    let desc_len = u32::from_be_bytes(len_buf) as usize;
+    while !buf.is_empty() {
+        options.check_cancelled()?;
 
-    let mut desc_bytes = vec![0u8; desc_len];
-    reader.read_exact(&mut desc_bytes)?;
-    let raw_description = String::from_utf8(desc_bytes).map_err(|e| ParseError::InvalidField {
-        field: "DESCRIPTION".to_string(),
-        reason: format!("Invalid UTF-8: {}", e),
-    })?;
+        match decode_from_slice(buf, &options.bin, options.lenient) {
+            Ok((operation, consumed)) => {
+                operations.insert(operation);
+                records += 1;
+                buf = &buf[consumed..];
+            }
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                let offset = (len - buf.len()) as u64;
+                return Err(shift_offset(e, offset).at(Location {
+                    record_index: Some(records as u64),
+                    byte_offset: Some(offset),
+                    ..Location::default()
+                }));
+            }
+        }
+    }
+
+    check_declared(declared, records)?;
+    Ok(operations)
+}
+
+/// Число записей из заголовка файла, если он есть
+///
+/// Для индикатора прогресса ("12000000 / 48000000"). Заголовок пишет
+/// [`write_all_with_header`]; без него поток возвращается туда, где был.
+///
+/// # Возвращает
+/// * `Ok(Some(count))` - Заголовок прочитан, поток стоит на первой записи
+/// * `Ok(None)` - Заголовка нет, позиция потока не изменилась
+pub fn read_file_header<R: Read + Seek>(reader: &mut R) -> Result<Option<u64>> {
+    let mut head = Vec::with_capacity(FILE_HEADER_LEN);
+    reader.take(FILE_HEADER_LEN as u64).read_to_end(&mut head)?;
+    let (declared, rest) = split_file_header(&head)?;
+    reader.seek(SeekFrom::Current(-(rest.len() as i64)))?;
+    Ok(declared)
+}
+
+/// Отделяет заголовок файла от записей
+fn split_file_header(buf: &[u8]) -> Result<(Option<u64>, &[u8])> {
+    if buf.starts_with(&COMPRESSED_MAGIC) {
+        return Err(ParseError::InvalidFormat(
+            "zstd-compressed YPBankBin (YPBZ) can only be read as a whole stream, \
+             e.g. with parse_all"
+                .to_string(),
+        ));
+    }
+    if !buf.starts_with(&FILE_MAGIC) {
+        return Ok((None, buf));
+    }
+    match buf.get(FILE_MAGIC.len()..FILE_HEADER_LEN) {
+        Some(count) => Ok((
+            Some(u64::from_be_bytes(count.try_into().unwrap())),
+            &buf[FILE_HEADER_LEN..],
+        )),
+        None => Err(short_read("file header", FILE_HEADER_LEN as u64, buf.len() as u64).into()),
+    }
+}
+
+/// [`split_file_header`] для потока: без заголовка прочитанное возвращается в поток
+fn take_file_header<R: Read>(reader: &mut Pushback<R>) -> Result<Option<u64>> {
+    let mut head = Vec::with_capacity(FILE_HEADER_LEN);
+    (&mut *reader)
+        .take(FILE_HEADER_LEN as u64)
+        .read_to_end(&mut head)?;
+    let (declared, rest) = split_file_header(&head)?;
+    reader.unread(rest.to_vec());
+    Ok(declared)
+}
 
-    // Чистим ковычки
-    let description = normalize_description(&raw_description);
+/// Разбирает тело записи: ровно RECORD_SIZE байт после заголовка
+fn parse_body(
+    header: &RecordHeader,
+    body: &[u8],
+    options: &BinOptions,
+    lenient: bool,
+) -> Result<Operation> {
+    if header.version > VERSION_CHECKSUM {
+        return Err(ParseError::UnsupportedVersion {
+            found: u32::from(header.version),
+            supported: u32::from(VERSION_CHECKSUM),
+        });
+    }
+
+    let declared = body.len() as u64;
+    let fixed = FIXED_FIELDS_SIZE as usize;
+    let Some(fields) = body.get(..fixed) else {
+        return Err(ParseError::InvalidRecordSize {
+            declared: header.record_size,
+            actual: FIXED_FIELDS_SIZE,
+        });
+    };
+
+    let u64_at = |at: usize| u64::from_be_bytes(fields[at..at + 8].try_into().unwrap());
+    let tx_id = u64_at(0);
+    let tx_type = fields[8];
+    let from_user_id = u64_at(9);
+    let to_user_id = u64_at(17);
+    let amount = u64_at(25) as i64;
+    let timestamp = u64_at(33);
+    let status = fields[41];
+    let desc_len = u32::from_be_bytes(fields[42..46].try_into().unwrap()) as usize;
+    if desc_len > options.max_description_len {
+        return Err(ParseError::InvalidField {
+            field: "DESCRIPTION".to_string(),
+            reason: format!(
+                "length {} exceeds the limit of {} bytes",
+                desc_len, options.max_description_len
+            ),
+        });
+    }
+
+    let actual = FIXED_FIELDS_SIZE + desc_len as u64;
+    let has_checksum = match header.version {
+        VERSION_LEGACY => declared == actual + CHECKSUM_SIZE,
+        version => version == VERSION_CHECKSUM,
+    };
+    let expected_size = if has_checksum {
+        actual + CHECKSUM_SIZE
+    } else {
+        actual
+    };
+    // Блок расширений бывает только в v2: в остальных раскладках лишние байты — ошибка
+    let extended = header.version == VERSION_CHECKSUM && declared > expected_size;
+    if declared != expected_size && !extended {
+        return Err(ParseError::InvalidRecordSize {
+            declared: header.record_size,
+            actual: expected_size,
+        });
+    }
+
+    let fields_end = fixed + desc_len;
+    let extensions_end = body.len()
+        - if has_checksum {
+            CHECKSUM_SIZE as usize
+        } else {
+            0
+        };
+    if has_checksum {
+        let actual = crc32fast::hash(&body[..extensions_end]);
+        let expected = u32::from_be_bytes(body[extensions_end..].try_into().unwrap());
+        if expected != actual {
+            return Err(ParseError::ChecksumMismatch {
+                tx_id,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let (tx_type, status) = if lenient {
+        (
+            OperationType::from_u8_lenient(tx_type),
+            OperationStatus::from_u8_lenient(status),
+        )
+    } else {
+        (
+            OperationType::from_u8(tx_type)?,
+            OperationStatus::from_u8(status)?,
+        )
+    };
+    let raw_description =
+        std::str::from_utf8(&body[fixed..fields_end]).map_err(|e| ParseError::InvalidField {
+            field: "DESCRIPTION".to_string(),
+            reason: format!("Invalid UTF-8: {}", e),
+        })?;
+
+    // Чистим кавычки и экранирование
+    let description = canonicalize_description(raw_description);
+    let extensions = decode_extensions(&body[fields_end..extensions_end], options)?;
 
     let operation = Operation {
         tx_id,
@@ -66,235 +343,1494 @@ pub fn parse_operation<R: Read>(reader: &mut R) -> Result<Operation> {
         timestamp,
         status,
         description,
+        extensions,
     };
 
     operation.validate()?;
     Ok(operation)
 }
 
-/// Для лишн ковычек
-fn normalize_description(s: &str) -> String {
-    let trimmed = s.trim();
+/// Разбирает блок расширений: подряд тег u16, длина u32 и значение, все big-endian
+fn decode_extensions(mut block: &[u8], options: &BinOptions) -> Result<Vec<Extension>> {
+    if block.len() > options.max_extensions_len {
+        return Err(ParseError::InvalidField {
+            field: "EXTENSIONS".to_string(),
+            reason: format!(
+                "{} bytes exceed the limit of {} bytes",
+                block.len(),
+                options.max_extensions_len
+            ),
+        });
+    }
+
+    let mut extensions = Vec::new();
+    while !block.is_empty() {
+        let Some((head, rest)) = block.split_at_checked(EXTENSION_HEADER_SIZE) else {
+            return Err(ParseError::InvalidField {
+                field: "EXTENSIONS".to_string(),
+                reason: format!("{} trailing bytes are not an extension", block.len()),
+            });
+        };
+        let tag = u16::from_be_bytes([head[0], head[1]]);
+        let len = u32::from_be_bytes(head[2..].try_into().unwrap()) as usize;
+        let Some((value, rest)) = rest.split_at_checked(len) else {
+            return Err(ParseError::InvalidField {
+                field: "EXTENSIONS".to_string(),
+                reason: format!(
+                    "tag {}: length {} overruns the record by {} bytes",
+                    tag,
+                    len,
+                    len - rest.len()
+                ),
+            });
+        };
+        extensions.push(Extension::new(tag, value));
+        block = rest;
+    }
+    Ok(extensions)
+}
+
+/// Пропускает запись по RECORD_SIZE, не разбирая ее поля
+///
+/// Для индексации и восстановления после битых записей: поток встает на
+/// начало следующей записи.
+///
+/// Записи любой версии, в том числе неизвестной, пропускаются одинаково.
+///
+/// # Возвращает
+/// Сколько байт заняла запись вместе с заголовком
+pub fn skip_operation<R: Read>(reader: &mut R) -> Result<u64> {
+    let header = read_record_header(reader)?;
+    let header_len = header_len(header.version);
+    let record_size = u64::from(header.record_size);
+    let skipped = io::copy(&mut reader.take(record_size), &mut io::sink())?;
+    if skipped != record_size {
+        return Err(truncated(
+            header_len + record_size as usize,
+            header_len + skipped as usize,
+        ));
+    }
+    Ok(header_len as u64 + record_size)
+}
 
-    let unquoted = if trimmed.starts_with('"') && trimmed.ends_with('"') && trimmed.len() >= 2 {
-        &trimmed[1..trimmed.len() - 1]
+/// Обходит записи, не разбирая их полей: TX_ID и смещение каждой
+///
+/// Для [`crate::index`]: из тела читается только TX_ID, остальное пропускается
+/// по RECORD_SIZE, как в [`skip_operation`]. Заголовок файла, если он есть,
+/// пропускается, а число записей в нем сверяется в конце.
+///
+/// # Возвращает
+/// Длину потока в байтах
+pub(crate) fn scan_records<R: Read>(
+    reader: R,
+    mut on_record: impl FnMut(u64, u64) -> Result<()>,
+) -> Result<u64> {
+    let mut reader = Pushback::new(reader);
+    let declared = take_file_header(&mut reader)?;
+    let mut position = if declared.is_some() {
+        FILE_HEADER_LEN as u64
     } else {
-        trimmed
+        0
     };
+    let mut records = 0;
 
-    unescape_string(unquoted)
-}
-
-/// Для лишн ковычек
-fn unescape_string(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
-    let mut chars = s.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if ch == '\\' {
-            if let Some(&next_ch) = chars.peek() {
-                match next_ch {
-                    '"' => {
-                        result.push('"');
-                        chars.next();
-                    }
-                    '\\' => {
-                        result.push('\\');
-                        chars.next();
-                    }
-                    'n' => {
-                        result.push('\n');
-                        chars.next();
-                    }
-                    't' => {
-                        result.push('\t');
-                        chars.next();
-                    }
-                    'r' => {
-                        result.push('\r');
-                        chars.next();
-                    }
-                    _ => {
-                        result.push(ch);
-                    }
-                }
-            } else {
-                result.push(ch);
+    loop {
+        let mut counting = CountingReader {
+            inner: &mut reader,
+            count: 0,
+        };
+        match skip_record_keyed(&mut counting) {
+            Ok(tx_id) => {
+                on_record(tx_id, position)?;
+                position += counting.count;
+                records += 1;
             }
-        } else {
-            result.push(ch);
+            Err(ParseError::Io(e))
+                if e.kind() == io::ErrorKind::UnexpectedEof && counting.count == 0 =>
+            {
+                check_declared(declared, records)?;
+                return Ok(position);
+            }
+            Err(e) => {
+                return Err(shift_offset(e, position).at(Location {
+                    record_index: Some(records as u64),
+                    byte_offset: Some(position),
+                    ..Location::default()
+                }));
+            }
+        }
+    }
+}
+
+/// Пропускает запись, как [`skip_operation`], но прочитав ее TX_ID
+fn skip_record_keyed<R: Read>(reader: &mut R) -> Result<u64> {
+    let header = read_record_header(reader)?;
+    if header.version > VERSION_CHECKSUM {
+        return Err(ParseError::UnsupportedVersion {
+            found: u32::from(header.version),
+            supported: u32::from(VERSION_CHECKSUM),
+        });
+    }
+    let header_len = header_len(header.version);
+    let record_size = u64::from(header.record_size);
+    if record_size < FIXED_FIELDS_SIZE {
+        return Err(ParseError::InvalidRecordSize {
+            declared: header.record_size,
+            actual: FIXED_FIELDS_SIZE,
+        });
+    }
+    let expected = header_len + record_size as usize;
+
+    let mut tx_id = [0u8; 8];
+    read_in_record(reader, &mut tx_id, header_len, expected)?;
+    let rest = record_size - tx_id.len() as u64;
+    let skipped = io::copy(&mut reader.take(rest), &mut io::sink())?;
+    if skipped != rest {
+        return Err(truncated(
+            expected,
+            header_len + tx_id.len() + skipped as usize,
+        ));
+    }
+    Ok(u64::from_be_bytes(tx_id))
+}
+
+/// Версия раскладки и RECORD_SIZE из заголовка записи
+struct RecordHeader {
+    version: u8,
+    record_size: u32,
+}
+
+/// Длина заголовка (MAGIC, байт версии, RECORD_SIZE) по байту после MAGIC
+fn header_len(version: u8) -> usize {
+    match version {
+        VERSION_LEGACY => MAGIC.len() + 4,
+        _ => MAGIC.len() + 1 + 4,
+    }
+}
+
+/// Читает и проверяет MAGIC, затем версию и RECORD_SIZE
+///
+/// Конец потока до конца MAGIC — `UnexpectedEof`, то есть конец данных;
+/// после MAGIC — уже [`ParseError::TruncatedRecord`].
+fn read_record_header<R: Read>(reader: &mut R) -> Result<RecordHeader> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    if magic.starts_with(&UTF8_BOM) {
+        return Err(ParseError::InvalidFormat(
+            "Stream starts with a UTF-8 BOM: this looks like a CSV or text file, not YPBankBin"
+                .to_string(),
+        ));
+    }
+    if magic != MAGIC {
+        return Err(ParseError::InvalidMagic);
+    }
+
+    let mut version = [0u8; 1];
+    read_in_record(reader, &mut version, MAGIC.len(), header_len(VERSION_PLAIN))?;
+    let version = version[0];
+
+    // У старой записи прочитанный ноль — уже первый байт RECORD_SIZE
+    let mut size_buf = [0u8; 4];
+    let size = match version {
+        VERSION_LEGACY => &mut size_buf[1..],
+        _ => &mut size_buf[..],
+    };
+    read_in_record(reader, size, MAGIC.len() + 1, header_len(version))?;
+    Ok(RecordHeader {
+        version,
+        record_size: u32::from_be_bytes(size_buf),
+    })
+}
+
+/// RECORD_SIZE не больше, чем могут занять поля с описанием и расширениями максимальной длины и CRC32
+fn check_record_size(record_size: u32, options: &BinOptions) -> Result<()> {
+    let max = (FIXED_FIELDS_SIZE + CHECKSUM_SIZE)
+        .saturating_add(options.max_description_len as u64)
+        .saturating_add(options.max_extensions_len as u64);
+    if u64::from(record_size) > max {
+        return Err(ParseError::InvalidField {
+            field: "RECORD_SIZE".to_string(),
+            reason: format!("{} exceeds the limit of {} bytes", record_size, max),
+        });
+    }
+    Ok(())
+}
+
+/// `read_exact` внутри записи, когда из нее уже прочитано `before` байт
+///
+/// Обрыв дает [`ParseError::TruncatedRecord`] с `expected` байт записи.
+fn read_in_record<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    before: usize,
+    expected: usize,
+) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Err(truncated(expected, before + filled)),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
         }
     }
+    Ok(())
+}
+
+/// Обрыв записи, смещение которой считается от места, где стоял поток
+fn truncated(expected: usize, got: usize) -> ParseError {
+    ParseError::TruncatedRecord {
+        offset: 0,
+        expected,
+        got,
+    }
+}
+
+/// Переносит смещение [`ParseError::TruncatedRecord`] на начало записи в потоке
+fn shift_offset(error: ParseError, start: u64) -> ParseError {
+    match error {
+        ParseError::TruncatedRecord {
+            offset,
+            expected,
+            got,
+        } => ParseError::TruncatedRecord {
+            offset: start + offset,
+            expected,
+            got,
+        },
+        other => other,
+    }
+}
 
-    result
+/// Обрыв заголовка файла: `UnexpectedEof`, но с тем, сколько не хватило
+fn short_read(what: &str, expected: u64, got: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("{}: expected {} bytes, got {}", what, expected, got),
+    )
 }
 
 /// Запись экзм операции в бинарник
+///
+/// Запись собирается в памяти и уходит в `writer` одним `write_all`.
+/// Операция с расширениями пишется в раскладке v2 ([`write_operation_v2`]):
+/// в v1 для них нет места.
 pub fn write_operation<W: Write>(writer: &mut W, operation: &Operation) -> Result<()> {
+    let mut buf = Vec::new();
+    encode_layout(&mut buf, operation, false)?;
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Запись операции в раскладке v2: после описания идет CRC32 тела записи
+///
+/// CRC32 (IEEE, как в zip и gzip) считается по байтам от TX_ID до конца
+/// DESCRIPTION и пишется big-endian; RECORD_SIZE учитывает и его.
+///
+/// Непустые [`Operation::extensions`] идут блоком TLV между описанием и CRC32:
+/// тег u16, длина значения u32 и само значение, все big-endian. CRC32 покрывает
+/// и этот блок. Записи без расширений выглядят так же, как до их появления.
+/// Байт версии — 2, читатели, знающие только версию 1, такую запись
+/// пропустят с [`ParseError::UnsupportedVersion`].
+pub fn write_operation_v2<W: Write>(writer: &mut W, operation: &Operation) -> Result<()> {
+    let mut buf = Vec::new();
+    encode_layout(&mut buf, operation, true)?;
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Дописывает в `buf` запись целиком: MAGIC, версию, RECORD_SIZE и тело
+fn encode_layout(buf: &mut Vec<u8>, operation: &Operation, checksum: bool) -> Result<()> {
     operation.validate()?;
 
-    // Вот хз я пишу без ковычек и эскейпинга
-    let desc_bytes = operation.description.as_bytes();
+    // Пишем в кавычках и с эскейпингом, как в исходных файлах, чтобы чтение было без потерь
+    let quoted = escape_description(&operation.description);
+    let desc_bytes = quoted.as_bytes();
     let desc_len = desc_bytes.len() as u32;
 
+    // Расширениям есть место только в v2
+    let checksum = checksum || !operation.extensions.is_empty();
+    let version = if checksum {
+        VERSION_CHECKSUM
+    } else {
+        VERSION_PLAIN
+    };
+    buf.reserve(
+        MAGIC.len() + 1 + 4 + (FIXED_FIELDS_SIZE + CHECKSUM_SIZE) as usize + desc_bytes.len(),
+    );
+    buf.extend_from_slice(&MAGIC);
+    buf.push(version);
+    // RECORD_SIZE станет известен после тела
+    let size_at = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+
+    let body_start = buf.len();
+    buf.extend_from_slice(&operation.tx_id.to_be_bytes());
+    buf.push(operation.tx_type.to_u8()?);
+    buf.extend_from_slice(&operation.from_user_id.to_be_bytes());
+    buf.extend_from_slice(&operation.to_user_id.to_be_bytes());
+    buf.extend_from_slice(&operation.amount.to_be_bytes());
+    buf.extend_from_slice(&operation.timestamp.to_be_bytes());
+    buf.push(operation.status.to_u8()?);
+    buf.extend_from_slice(&desc_len.to_be_bytes());
+    buf.extend_from_slice(desc_bytes);
+    for extension in &operation.extensions {
+        let len = u32::try_from(extension.value.len()).map_err(|_| ParseError::InvalidField {
+            field: "EXTENSIONS".to_string(),
+            reason: format!(
+                "tag {}: {} bytes do not fit a u32 length",
+                extension.tag,
+                extension.value.len()
+            ),
+        })?;
+        buf.extend_from_slice(&extension.tag.to_be_bytes());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(&extension.value);
+    }
+
     // Тип пэддинг)
-    let record_size: u32 = 8 + 1 + 8 + 8 + 8 + 8 + 1 + 4 + desc_len;
-
-    writer.write_all(&MAGIC)?;
-    writer.write_all(&record_size.to_be_bytes())?;
-    writer.write_all(&operation.tx_id.to_be_bytes())?;
-    writer.write_all(&[operation.tx_type.to_u8()])?;
-    writer.write_all(&operation.from_user_id.to_be_bytes())?;
-    writer.write_all(&operation.to_user_id.to_be_bytes())?;
-    writer.write_all(&operation.amount.to_be_bytes())?;
-    writer.write_all(&operation.timestamp.to_be_bytes())?;
-    writer.write_all(&[operation.status.to_u8()])?;
-    writer.write_all(&desc_len.to_be_bytes())?;
-    writer.write_all(desc_bytes)?;
+    if checksum {
+        let crc = crc32fast::hash(&buf[body_start..]);
+        buf.extend_from_slice(&crc.to_be_bytes());
+    }
 
+    let record_size = (buf.len() - body_start) as u32;
+    buf[size_at..body_start].copy_from_slice(&record_size.to_be_bytes());
     Ok(())
 }
 
 /// Ходим по бинарнику, разбиваем по блокам и парсим операцию
-pub fn parse_all<R: Read>(mut reader: R) -> Result<HashSet<Operation>> {
-    let mut operations = HashSet::new();
+///
+/// Конец потока на границе записи — конец данных, а запись, оборванная после
+/// своего MAGIC, — [`ParseError::TruncatedRecord`] со смещением ее начала.
+pub fn parse_all<R: Read>(reader: R) -> Result<HashSet<Operation>> {
+    parse_all_with_options(reader, &ParseOptions::default())
+}
+
+/// Разбор с пропуском битых записей, см. [`crate::parse_all_lossy`]
+pub fn parse_all_lossy<R: Read>(reader: R, options: &ParseOptions) -> Result<ParseOutcome> {
+    crate::partition::parse_all_lossy(reader, Format::Bin, options)
+}
+
+/// То же, что [`parse_all`], но с настройками (например, токеном отмены)
+pub fn parse_all_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    Format::Bin.parse_all_with_options(reader, options)
+}
+
+/// То же, что [`parse_all_with_options`], плюс отчет
+///
+/// TIMESTAMP в бинарнике всегда в миллисекундах, `options.timestamp_unit` не учитывается.
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    // С заголовком файла набор сразу нужного размера, без перехеширования по ходу
+    parse_each_with_state(
+        reader,
+        options,
+        |declared| HashSet::with_capacity(declared.unwrap_or(0).min(MAX_PREALLOCATED) as usize),
+        |operations, operation, _| {
+            operations.insert(operation);
+            Ok(())
+        },
+    )
+}
+
+/// Читает все операции в порядке файла, см. [`Format::parse_all_vec`]
+pub fn parse_all_vec<R: Read>(reader: R) -> Result<Vec<Operation>> {
+    Format::Bin.parse_all_vec(reader)
+}
+
+/// То же, что [`parse_all_vec`], но с настройками разбора
+pub fn parse_all_vec_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Operation>> {
+    Format::Bin.parse_all_vec_with_options(reader, options)
+}
+
+/// Разбор с политикой для повторов TX_ID, см. [`Format::parse_all_with_policy`]
+pub fn parse_all_with_policy<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    Format::Bin.parse_all_with_policy(reader, options, policy)
+}
+
+/// Число повторов каждой записи, см. [`Format::parse_all_counted`]
+pub fn parse_all_counted<R: Read>(reader: R) -> Result<HashMap<FullOperation, u64>> {
+    Format::Bin.parse_all_counted(reader)
+}
+
+/// Записи вместе с происхождением, см. [`Format::parse_all_with_provenance`]
+pub fn parse_all_with_provenance<R: Read>(
+    reader: R,
+    source: Option<&str>,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    Format::Bin.parse_all_with_provenance(reader, source, options)
+}
+
+/// Потоковый разбор: отдает записи по одной, ошибка из `on_operation` прерывает разбор
+pub(crate) fn parse_each<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let ((), report) = parse_each_with_state(
+        reader,
+        options,
+        |_| (),
+        |(), operation, position| on_operation(operation, position),
+    )?;
+    Ok(report)
+}
+
+/// [`parse_each`] с состоянием, которое создается по числу записей из заголовка файла
+fn parse_each_with_state<R: Read, T>(
+    reader: R,
+    options: &ParseOptions,
+    init: impl FnOnce(Option<u64>) -> T,
+    mut on_operation: impl FnMut(&mut T, Operation, RecordPosition) -> Result<()>,
+) -> Result<(T, ParseReport)> {
+    let meter = Meter::start("bin", options);
+    let reader = open_container(meter.reader(reader))?;
+    let mut iter = iter_operations_with_options(reader, options);
+    iter.start()?;
+    let mut state = init(iter.declared_records());
+    let report = parse_records(iter, options, |operation, position| {
+        meter.record();
+        on_operation(&mut state, operation, position)
+    })?;
+    Ok((state, meter.finish(report)))
+}
+
+/// Сам разбор для [`parse_each`], без замеров
+fn parse_records<R: Read>(
+    mut iter: OperationIter<R>,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let mut report = ParseReport::default();
 
     loop {
-        match parse_operation(&mut reader) {
-            Ok(op) => {
-                operations.insert(op);
+        options.check_cancelled()?;
+
+        let position = RecordPosition {
+            line: None,
+            byte_offset: Some(iter.position()),
+        };
+        match iter.next() {
+            Some(op) => {
+                let op = op?;
+                report.check_rules(&op, options);
+                report.records += 1;
+                on_operation(op, position)?;
             }
-            Err(ParseError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e),
+            None => break,
         }
     }
 
-    Ok(operations)
+    report.declared_records = iter.declared_records();
+    Ok(report)
 }
 
-/// Итерируемся по операциям и записываем в бинарник
-pub fn write_all<W: Write>(mut writer: W, operations: &HashSet<Operation>) -> Result<()> {
-    for operation in operations {
-        write_operation(&mut writer, operation)?;
-    }
-    Ok(())
+/// Ленивый разбор: операции по одной в порядке файла, без накопления в памяти
+///
+/// Заголовок файла читается при первом `next()`; если он есть, число записей
+/// сверяется в конце потока и расхождение отдается последним элементом.
+pub fn iter_operations<R: Read>(reader: R) -> OperationIter<R> {
+    OperationIter::new(reader)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::operation::{Operation, OperationStatus, OperationType};
-    use std::io::Cursor;
+/// То же, что [`iter_operations`], но с лимитами из [`ParseOptions::bin`]
+/// и мягким режимом из [`ParseOptions::lenient`]
+pub fn iter_operations_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> OperationIter<R> {
+    OperationIter::new(reader)
+        .with_options(options.bin)
+        .lenient(options.lenient)
+}
 
-    #[test]
-    fn test_unescape_string() {
-        assert_eq!(unescape_string(r#"Record number 1"#), "Record number 1");
-        assert_eq!(
-            unescape_string(r#"\"Record number 1\""#),
-            r#""Record number 1""#
-        );
-        assert_eq!(unescape_string(r#"Line1\nLine2"#), "Line1\nLine2");
-        assert_eq!(unescape_string(r#"Tab\there"#), "Tab\there");
-        assert_eq!(unescape_string(r#"Backslash\\"#), r#"Backslash\"#);
+/// Число записей в заголовке файла должно совпасть с прочитанным
+fn check_declared(declared: Option<u64>, records: usize) -> Result<()> {
+    match declared {
+        Some(declared) if declared != records as u64 => Err(ParseError::InvalidFormat(format!(
+            "File header declares {} records, found {}",
+            declared, records
+        ))),
+        _ => Ok(()),
     }
+}
 
-    #[test]
-    fn test_normalize_description() {
-        assert_eq!(normalize_description(r#""Нормализуй 1""#), "Нормализуй 1");
-        assert_eq!(
-            normalize_description(r#""\"Нормализуй 1\"""#),
-            r#""Нормализуй 1""#
-        );
-        assert_eq!(normalize_description("Нормализуй 1"), "Нормализуй 1");
-        assert_eq!(normalize_description(r#"  "trimmed"  "#), "trimmed");
-    }
+/// Потоковый разбор для карантина: каждая запись вместе с ее сырыми байтами
+///
+/// Записи режутся по MAGIC и RECORD_SIZE, поэтому битая запись не мешает
+/// читать следующие. Мусор без MAGIC отдается одним куском с
+/// [`ParseError::InvalidMagic`], чтение продолжается со следующего MAGIC.
+/// Если за битой записью нет MAGIC, ее RECORD_SIZE, скорее всего, сам испорчен
+/// и захватил чужие записи: тогда запись обрезается по первому MAGIC внутри нее,
+/// а остальное читается заново. Заголовок файла пропускается, число записей
+/// в нем не сверяется: часть записей все равно уходит в брак.
+/// Ошибкой всего разбора считаются только ошибки ввода-вывода и из `on_record`.
+pub(crate) fn parse_each_raw<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let mut reader = Pushback::new(BufReader::new(open_container(reader)?));
+    let mut offset = match take_file_header(&mut reader)? {
+        Some(_) => FILE_HEADER_LEN as u64,
+        None => 0,
+    };
+    // MAGIC следующей записи уже прочитан при поиске после мусора
+    let mut magic_read = false;
 
-    #[test]
-    fn test_round_trip_simple() {
-        let op = Operation {
-            tx_id: 12345,
-            tx_type: OperationType::Deposit,
-            from_user_id: 0,
-            to_user_id: 67890,
-            amount: 1000,
-            timestamp: 1633036860000,
-            status: OperationStatus::Success,
-            description: "Simple".to_string(),
-        };
+    loop {
+        options.check_cancelled()?;
 
-        let mut buf = Vec::new();
-        write_operation(&mut buf, &op).unwrap();
+        let position = RecordPosition {
+            line: None,
+            byte_offset: Some(offset),
+        };
+        let mut raw = Vec::new();
+        if magic_read {
+            raw.extend_from_slice(&MAGIC);
+        } else {
+            (&mut reader)
+                .take(MAGIC.len() as u64)
+                .read_to_end(&mut raw)?;
+            if raw.is_empty() {
+                break;
+            }
+            if raw != MAGIC {
+                magic_read = skip_to_magic(&mut reader, &mut raw)?;
+                offset += raw.len() as u64;
+                on_record(Err(ParseError::InvalidMagic), raw, position)?;
+                if magic_read {
+                    continue;
+                }
+                break;
+            }
+        }
+        magic_read = false;
 
-        let mut cursor = Cursor::new(buf);
-        let parsed = parse_operation(&mut cursor).unwrap();
+        (&mut reader).take(1).read_to_end(&mut raw)?;
+        (&mut reader)
+            .take(header_rest(&raw))
+            .read_to_end(&mut raw)?;
+        // Тело слишком большой записи не читаем: parse_operation отклонит ее по
+        // заголовку, а то, что за ним, уйдет в мусор до следующего MAGIC
+        if let Some(record_size) = body_len(&raw, &options.bin) {
+            (&mut reader).take(record_size).read_to_end(&mut raw)?;
+        }
 
-        assert_eq!(op, parsed);
-        assert_eq!(parsed.description, "Simple");
+        let result = decode_from_slice(&raw, &options.bin, options.lenient)
+            .map(|(op, _)| op)
+            .map_err(|e| shift_offset(e, offset));
+        if result.is_err() {
+            resync(&mut reader, &mut raw)?;
+        }
+        offset += raw.len() as u64;
+        on_record(result, raw, position)?;
     }
 
-    #[test]
-    fn test_parse_escaped_description() {
-        let op_with_escaped = Operation {
-            tx_id: 1000000000000000,
-            tx_type: OperationType::Deposit,
-            from_user_id: 0,
-            to_user_id: 9223372036854775807,
-            amount: 100,
-            timestamp: 1633036860000,
-            status: OperationStatus::Failure,
-            description: r#"\"Лишн ковычк 1\""#.to_string(),
-        };
+    Ok(())
+}
 
-        let mut buf = Vec::new();
-        write_operation(&mut buf, &op_with_escaped).unwrap();
+/// Сколько байт заголовка записи осталось дочитать за MAGIC и байтом версии в `raw`
+fn header_rest(raw: &[u8]) -> u64 {
+    let header_len = raw.get(MAGIC.len()).map_or(raw.len(), |&v| header_len(v));
+    (header_len - raw.len()) as u64
+}
 
-        let mut cursor = Cursor::new(buf);
-        let parsed = parse_operation(&mut cursor).unwrap();
+/// RECORD_SIZE из прочитанного заголовка в `raw`
+///
+/// `None`, если заголовок оборван или RECORD_SIZE больше лимита: тогда тело
+/// не читаем, а ошибку назовет разбор среза.
+fn body_len(raw: &[u8], options: &BinOptions) -> Option<u64> {
+    let header_len = header_len(*raw.get(MAGIC.len())?);
+    let size = raw.get(header_len - 4..header_len)?;
+    let record_size = u32::from_be_bytes(size.try_into().unwrap());
+    check_record_size(record_size, options).ok()?;
+    Some(u64::from(record_size))
+}
 
-        assert_eq!(parsed.description, r#""Лишн ковычк 1""#);
+/// Дочитывает мусор до следующего MAGIC; сам MAGIC в `garbage` не остается
+///
+/// # Возвращает
+/// `true`, если MAGIC найден (и уже прочитан), `false` на конце потока
+fn skip_to_magic<R: Read>(reader: &mut R, garbage: &mut Vec<u8>) -> Result<bool> {
+    let mut byte = [0u8; 1];
+    while reader.read(&mut byte)? == 1 {
+        garbage.push(byte[0]);
+        if garbage.ends_with(&MAGIC) {
+            garbage.truncate(garbage.len() - MAGIC.len());
+            return Ok(true);
+        }
     }
+    Ok(false)
+}
 
-    #[test]
-    fn test_round_trip_with_quotes() {
-        let op = Operation {
-            tx_id: 12345,
-            tx_type: OperationType::Deposit,
-            from_user_id: 0,
-            to_user_id: 67890,
-            amount: 1000,
-            timestamp: 1633036860000,
-            status: OperationStatus::Success,
-            description: r#"Ковычк должны остаться "quotes""#.to_string(),
-        };
+/// После битой записи проверяет, что следом идет MAGIC (или конец потока)
+///
+/// Если нет, а внутри `raw` после начала есть MAGIC, хвост `raw` с него
+/// возвращается в поток: его прочитают как следующие записи.
+fn resync<R: Read>(reader: &mut Pushback<R>, raw: &mut Vec<u8>) -> Result<()> {
+    let mut next = Vec::with_capacity(MAGIC.len());
+    (&mut *reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut next)?;
 
-        let mut buf = Vec::new();
-        write_operation(&mut buf, &op).unwrap();
+    if !next.is_empty() && next != MAGIC {
+        let inner = raw
+            .windows(MAGIC.len())
+            .skip(1)
+            .position(|window| window == MAGIC);
+        if let Some(start) = inner {
+            next.splice(0..0, raw.drain(start + 1..));
+        }
+    }
+    reader.unread(next);
+    Ok(())
+}
 
-        let mut cursor = Cursor::new(buf);
-        let parsed = parse_operation(&mut cursor).unwrap();
+/// Поток записей: как есть или распакованный из сжатого контейнера
+enum Container<R> {
+    Plain(Pushback<R>),
+    #[cfg(feature = "zstd")]
+    Compressed(zstd::stream::read::Decoder<'static, BufReader<R>>),
+}
 
-        assert_eq!(op, parsed);
-        assert_eq!(parsed.description, r#"Ковычк должны остаться "quotes""#);
+/// Смотрит на первые байты: сжатый контейнер ([`COMPRESSED_MAGIC`]) распаковывается
+/// на лету, остальное читается как есть
+///
+/// Смещения в ошибках сжатого контейнера — в распакованных байтах.
+fn open_container<R: Read>(mut reader: R) -> Result<Container<R>> {
+    let mut head = Vec::with_capacity(COMPRESSED_MAGIC.len());
+    (&mut reader)
+        .take(COMPRESSED_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    if head == COMPRESSED_MAGIC {
+        #[cfg(feature = "zstd")]
+        return Ok(Container::Compressed(zstd::stream::read::Decoder::new(
+            reader,
+        )?));
+        #[cfg(not(feature = "zstd"))]
+        return Err(ParseError::InvalidFormat(
+            "zstd-compressed YPBankBin (YPBZ) needs the `zstd` feature".to_string(),
+        ));
     }
+    let mut plain = Pushback::new(reader);
+    plain.unread(head);
+    Ok(Container::Plain(plain))
+}
 
-    #[test]
-    fn test_round_trip_unicode() {
-        let op = Operation {
-            tx_id: 12345,
-            tx_type: OperationType::Deposit,
-            from_user_id: 0,
-            to_user_id: 67890,
-            amount: 1000,
-            timestamp: 1633036860000,
-            status: OperationStatus::Success,
-            description: "Ну по-русски 🎉".to_string(),
-        };
+impl<R: Read> Read for Container<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Container::Plain(reader) => reader.read(buf),
+            #[cfg(feature = "zstd")]
+            Container::Compressed(reader) => reader.read(buf),
+        }
+    }
+}
 
-        let mut buf = Vec::new();
-        write_operation(&mut buf, &op).unwrap();
+/// Поток, в который можно вернуть уже прочитанные байты
+struct Pushback<R> {
+    pending: Vec<u8>,
+    pos: usize,
+    inner: R,
+}
 
-        let mut cursor = Cursor::new(buf);
-        let parsed = parse_operation(&mut cursor).unwrap();
+impl<R> Pushback<R> {
+    fn new(inner: R) -> Self {
+        Pushback {
+            pending: Vec::new(),
+            pos: 0,
+            inner,
+        }
+    }
+
+    /// `bytes` будут прочитаны раньше всего, что еще не прочитано
+    fn unread(&mut self, mut bytes: Vec<u8>) {
+        bytes.extend_from_slice(&self.pending[self.pos..]);
+        self.pending = bytes;
+        self.pos = 0;
+    }
+}
+
+impl<R: Read> Read for Pushback<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.pending.len() {
+            let n = (self.pending.len() - self.pos).min(buf.len());
+            buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Участок бинарника, пропущенный при [`parse_all_with_recovery`]
+#[derive(Debug)]
+pub struct RecordError {
+    /// Смещение начала участка от начала потока
+    pub offset: u64,
+    /// Сколько байт пропущено
+    pub skipped: u64,
+    /// Почему участок не разобрался
+    pub error: ParseError,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "byte {}: skipped {} bytes: {}",
+            self.offset, self.skipped, self.error
+        )
+    }
+}
+
+/// Разбор с восстановлением: битые записи пропускаются до следующего MAGIC
+///
+/// Годится для больших дампов, где одна испорченная запись не должна стоить
+/// всего, что идет за ней. Механика та же, что у карантина ([`crate::partition`]).
+///
+/// # Возвращает
+/// * `Ok((operations, errors))` - Разобранные операции и пропущенные участки по порядку
+/// * `Err(ParseError)` - Только ошибки ввода-вывода и отмена
+pub fn parse_all_with_recovery<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, Vec<RecordError>)> {
+    let mut operations = HashSet::new();
+    let mut errors = Vec::new();
+    parse_each_raw(reader, options, |result, raw, position| {
+        match result {
+            Ok(operation) => {
+                operations.insert(operation);
+            }
+            Err(error) => errors.push(RecordError {
+                offset: position.byte_offset.unwrap_or_default(),
+                skipped: raw.len() as u64,
+                error,
+            }),
+        }
+        Ok(())
+    })?;
+
+    Ok((operations, errors))
+}
+
+/// Потоковое чтение бинарника по одной операции
+///
+/// Помнит, сколько байт занимают уже отданные записи ([`OperationIter::position`]),
+/// так что можно сохранить чекпоинт и потом продолжить через [`parse_from`].
+/// Недописанная последняя запись — ошибка [`ParseError::TruncatedRecord`], как
+/// у [`parse_all`]; для хвоста растущего файла ее можно считать концом через
+/// [`OperationIter::allow_truncated_tail`]. После первой ошибки итератор больше
+/// ничего не отдает.
+pub struct OperationIter<R> {
+    reader: Pushback<R>,
+    position: u64,
+    options: BinOptions,
+    /// Незнакомые TX_TYPE и STATUS — [`OperationType::Unknown`], а не ошибка
+    lenient: bool,
+    /// Число записей из заголовка файла
+    declared: Option<u64>,
+    /// Сколько записей отдано с начала файла
+    records: usize,
+    /// Итератор начал с начала потока, а не с [`parse_from`]: номер записи в ошибке известен
+    from_start: bool,
+    /// Недописанная запись — конец, а не ошибка
+    allow_truncated_tail: bool,
+    /// Заголовок файла уже искали
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read> OperationIter<R> {
+    /// Итератор с начала потока
+    pub fn new(reader: R) -> Self {
+        Self::with_position(reader, 0)
+    }
+
+    fn with_position(reader: R, position: u64) -> Self {
+        OperationIter {
+            reader: Pushback::new(reader),
+            position,
+            options: BinOptions::default(),
+            lenient: false,
+            declared: None,
+            records: 0,
+            from_start: position == 0,
+            allow_truncated_tail: false,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Читает заголовок файла, если итератор стоит в начале потока
+    fn start(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if self.position == 0 {
+            self.declared = take_file_header(&mut self.reader)?;
+            if self.declared.is_some() {
+                self.position = FILE_HEADER_LEN as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Лимиты размеров записей вместо умолчаний
+    pub fn with_options(mut self, options: BinOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Незнакомый байт TX_TYPE или STATUS читать как `Unknown`, см. [`ParseOptions::lenient`]
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Недописанную последнюю запись считать концом потока, а не ошибкой
+    ///
+    /// Для растущего файла: [`OperationIter::position`] остается на начале
+    /// недописанной записи, и следующий прогон через [`parse_from`] дочитает ее.
+    pub fn allow_truncated_tail(mut self) -> Self {
+        self.allow_truncated_tail = true;
+        self
+    }
+
+    /// Смещение конца последней целиком отданной записи
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Число записей из заголовка файла; известно после первого `next()`
+    pub fn declared_records(&self) -> Option<u64> {
+        self.declared
+    }
+
+    /// Возвращает исходный поток
+    ///
+    /// До первой записи поток может быть уже прочитан вперед на длину заголовка файла.
+    pub fn into_inner(self) -> R {
+        self.reader.inner
+    }
+}
+
+impl<R: Read> Iterator for OperationIter<R> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Err(e) = self.start() {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        let mut counting = CountingReader {
+            inner: &mut self.reader,
+            count: 0,
+        };
+
+        match read_operation(&mut counting, &self.options, self.lenient) {
+            Ok(op) => {
+                self.position += counting.count;
+                self.records += 1;
+                Some(Ok(op))
+            }
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => self.finish(),
+            Err(ParseError::TruncatedRecord { .. }) if self.allow_truncated_tail => self.finish(),
+            Err(e) => {
+                self.done = true;
+                Some(Err(shift_offset(e, self.position).at(Location {
+                    record_index: self.from_start.then_some(self.records as u64),
+                    byte_offset: Some(self.position),
+                    ..Location::default()
+                })))
+            }
+        }
+    }
+}
+
+impl<R> OperationIter<R> {
+    /// Конец потока: остается только сверить число записей с заголовком файла
+    fn finish(&mut self) -> Option<Result<Operation>> {
+        self.done = true;
+        check_declared(self.declared, self.records).err().map(Err)
+    }
+}
+
+impl<R: Read> FusedIterator for OperationIter<R> {}
+
+/// Считает прочитанные байты
+struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Продолжает чтение с сохраненного смещения
+///
+/// Смещение должно указывать на начало записи (проверяется по MAGIC),
+/// либо на конец потока — тогда итератор просто ничего не отдаст.
+///
+/// # Возвращает
+/// * `Ok(OperationIter)` - Итератор, чей `position()` отсчитывается от начала потока
+/// * `Err(ParseError)` - Если смещение попало не на границу записи
+pub fn parse_from<R: Read + Seek>(mut reader: R, offset: u64) -> Result<OperationIter<R>> {
+    reader.seek(SeekFrom::Start(offset))?;
+
+    // Хвост растущего файла может быть дописан не до конца: достаточно совпадения начала MAGIC
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    (&mut reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    if !MAGIC.starts_with(&magic) {
+        return Err(ParseError::InvalidFormat(format!(
+            "Offset {} is not a record boundary",
+            offset
+        )));
+    }
+
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok(OperationIter::with_position(reader, offset))
+}
+
+/// Итерируемся по операциям и записываем в бинарник
+pub fn write_all<W: Write>(writer: W, operations: &HashSet<Operation>) -> Result<()> {
+    write_all_with_options(writer, operations, &WriteOptions::default())
+}
+
+/// Как [`write_all`], но с заголовком файла: метка `YPBF` и число записей
+///
+/// Читатели по нему заранее резервируют память и проверяют, что файл не обрезан
+/// (число записей должно совпасть). Файлы без заголовка читаются как раньше.
+pub fn write_all_with_header<W: Write>(
+    mut writer: W,
+    operations: &HashSet<Operation>,
+) -> Result<()> {
+    writer.write_all(&file_header(operations.len()))?;
+    write_all(writer, operations)
+}
+
+/// Заголовок файла: [`FILE_MAGIC`] и число записей
+fn file_header(records: usize) -> [u8; FILE_HEADER_LEN] {
+    let mut header = [0; FILE_HEADER_LEN];
+    header[..FILE_MAGIC.len()].copy_from_slice(&FILE_MAGIC);
+    header[FILE_MAGIC.len()..].copy_from_slice(&(records as u64).to_be_bytes());
+    header
+}
+
+/// Пишет сжатый контейнер: метка `YPBZ` и поток zstd, в котором обычный файл
+/// с заголовком ([`write_all_with_header`]) и записями по `options`
+///
+/// [`parse_all`] и остальные функции разбора всего потока узнают контейнер по
+/// метке и распаковывают сами (с фичей `zstd`); число записей из заголовка
+/// сверяется как обычно. Читать по смещениям ([`parse_from`], [`crate::BinIndex`],
+/// разбор среза) такой файл нельзя — только целиком.
+#[cfg(feature = "zstd")]
+pub fn write_all_compressed<'a, W: Write>(
+    mut writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let operations: Vec<&Operation> = operations.into_iter().collect();
+    writer.write_all(&COMPRESSED_MAGIC)?;
+    let mut encoder = zstd::stream::write::Encoder::new(writer, COMPRESSION_LEVEL)?;
+    encoder.write_all(&file_header(operations.len()))?;
+    write_all_with_options(&mut encoder, operations, options)?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// То же, что [`write_all`], но с настройками (например, пределом длины описания)
+///
+/// Операции пишутся в порядке `operations`, так что можно передать и отсортированный срез.
+/// `writer` оборачивается в [`BufWriter`], так что голый `File` не означает
+/// системный вызов на запись; все дописывается и сбрасывается до возврата.
+pub fn write_all_with_options<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut writer = BinWriter::new(BufWriter::new(writer)).with_options(options.clone());
+    for operation in operations {
+        writer.write(operation)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Итоги [`append_operations`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppendOutcome {
+    /// Сколько байт недописанной последней записи отрезано перед дозаписью
+    pub truncated: u64,
+    /// Сколько записей дописано
+    pub appended: usize,
+}
+
+/// Дописывает операции в конец существующего бинарного файла
+///
+/// Сначала проходит записи файла по RECORD_SIZE, не читая тел, и проверяет
+/// хвост: запись, оборванную концом файла (после падения прошлой дозаписи),
+/// отрезает, а последнюю целую запись разбирает полностью. Мусор не на границе
+/// записи — ошибка, файл не трогается. Если у файла есть заголовок с числом
+/// записей, число обновляется.
+///
+/// Версия новых записей (v1 или v2 с CRC32, см. [`WriteOptions::checksum`])
+/// должна совпасть с версией последней записи файла, иначе ошибка до записи.
+/// Все операции проверяются и кодируются до того, как файл изменится.
+///
+/// Нужен именно `File`: обрезать хвост можно только через `set_len`.
+pub fn append_operations<'a>(
+    file: &mut File,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<AppendOutcome> {
+    let mut encoded = Vec::new();
+    let mut new_versions = Vec::new();
+    for operation in operations {
+        let start = encoded.len();
+        encode_record(&mut encoded, operation, options)?;
+        new_versions.push((operation.tx_id, encoded[start + MAGIC.len()]));
+    }
+
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail = find_tail(file, len)?;
+    if let Some(last) = tail.last_record {
+        file.seek(SeekFrom::Start(last))?;
+        let mut reader = BufReader::new(&mut *file);
+        parse_operation_with_options(&mut reader, &BinOptions::default()).map_err(|e| {
+            shift_offset(e, last).at(Location {
+                byte_offset: Some(last),
+                ..Location::default()
+            })
+        })?;
+    }
+    if let Some(version) = tail.version
+        && let Some(&(tx_id, new)) = new_versions.iter().find(|&&(_, new)| new != version)
+    {
+        return Err(ParseError::InvalidFormat(format!(
+            "Can't append tx_id {} as a v{} record to a file of v{} records",
+            tx_id, new, version
+        )));
+    }
+
+    if tail.good_end < len {
+        file.set_len(tail.good_end)?;
+    }
+    file.seek(SeekFrom::Start(tail.good_end))?;
+    file.write_all(&encoded)?;
+    if tail.has_header {
+        let records = tail.records + new_versions.len() as u64;
+        file.seek(SeekFrom::Start(FILE_MAGIC.len() as u64))?;
+        file.write_all(&records.to_be_bytes())?;
+    }
+    file.flush()?;
+    if options.sync {
+        file.sync_all()?;
+    }
+
+    Ok(AppendOutcome {
+        truncated: len - tail.good_end,
+        appended: new_versions.len(),
+    })
+}
+
+/// Что известно о хвосте файла после прохода по заголовкам записей
+struct Tail {
+    /// Конец последней целой записи
+    good_end: u64,
+    /// Начало последней целой записи
+    last_record: Option<u64>,
+    /// Версия последней целой записи
+    version: Option<u8>,
+    records: u64,
+    has_header: bool,
+}
+
+/// Проходит записи по RECORD_SIZE с начала файла длиной `len`
+///
+/// Запись, которой не хватило файла, — недописанный хвост; байты, которые не
+/// могут быть началом записи, — ошибка.
+fn find_tail<R: Read + Seek>(reader: &mut R, len: u64) -> Result<Tail> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut head = Vec::with_capacity(FILE_HEADER_LEN);
+    (&mut *reader)
+        .take(FILE_HEADER_LEN as u64)
+        .read_to_end(&mut head)?;
+    let has_header = split_file_header(&head)?.0.is_some();
+
+    let mut tail = Tail {
+        good_end: if has_header {
+            FILE_HEADER_LEN as u64
+        } else {
+            0
+        },
+        last_record: None,
+        version: None,
+        records: 0,
+        has_header,
+    };
+    while tail.good_end < len {
+        let pos = tail.good_end;
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = Vec::with_capacity(header_len(VERSION_PLAIN));
+        (&mut *reader)
+            .take(header_len(VERSION_PLAIN) as u64)
+            .read_to_end(&mut header)?;
+
+        let magic_len = header.len().min(MAGIC.len());
+        if !MAGIC.starts_with(&header[..magic_len]) {
+            return Err(ParseError::InvalidFormat(format!(
+                "Offset {} is not a record boundary: the file is corrupt, not just cut short",
+                pos
+            )));
+        }
+        let Some(&version) = header.get(MAGIC.len()) else {
+            break;
+        };
+        let header_len = header_len(version);
+        if header.len() < header_len {
+            break;
+        }
+        let size = &header[header_len - 4..header_len];
+        let record_size = u32::from_be_bytes(size.try_into().unwrap());
+        let end = pos + header_len as u64 + u64::from(record_size);
+        if end > len {
+            break;
+        }
+
+        tail.good_end = end;
+        tail.last_record = Some(pos);
+        tail.version = Some(version);
+        tail.records += 1;
+    }
+    Ok(tail)
+}
+
+/// Асинхронный [`parse_operation_with_options`]
+///
+/// Запись читается в буфер целиком (тело — только если RECORD_SIZE в пределах
+/// лимита) и разбирается [`parse_operation_from_slice_with_options`], так что
+/// проверки и ошибки те же, что у синхронного разбора.
+#[cfg(feature = "async")]
+pub async fn parse_operation_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    options: &BinOptions,
+) -> Result<Operation> {
+    let raw = read_record_async(reader, options).await?;
+    parse_operation_from_slice_with_options(&raw, options).map(|(operation, _)| operation)
+}
+
+/// Асинхронный [`parse_all_with_options`]: чистый конец потока — конец данных,
+/// недописанная запись — [`ParseError::TruncatedRecord`]
+#[cfg(feature = "async")]
+pub async fn parse_all_async<R: AsyncRead + Unpin>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    let mut head = Vec::with_capacity(FILE_HEADER_LEN);
+    (&mut reader)
+        .take(FILE_HEADER_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    let (declared, rest) = split_file_header(&head)?;
+    let mut offset = (head.len() - rest.len()) as u64;
+    // Без заголовка файла прочитанное — начало первой записи
+    let mut reader = AsyncReadExt::chain(rest, reader);
+
+    let capacity = declared.unwrap_or(0).min(MAX_PREALLOCATED) as usize;
+    let mut operations = HashSet::with_capacity(capacity);
+    let mut records = 0;
+    loop {
+        options.check_cancelled()?;
+
+        let raw = read_record_async(&mut reader, &options.bin).await?;
+        match decode_from_slice(&raw, &options.bin, options.lenient) {
+            Ok((operation, _)) => {
+                operations.insert(operation);
+                records += 1;
+                offset += raw.len() as u64;
+            }
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                return Err(shift_offset(e, offset).at(Location {
+                    record_index: Some(records as u64),
+                    byte_offset: Some(offset),
+                    ..Location::default()
+                }));
+            }
+        }
+    }
+
+    check_declared(declared, records)?;
+    Ok(operations)
+}
+
+/// Читает сырые байты одной записи; оборванная запись отдается как есть
+#[cfg(feature = "async")]
+async fn read_record_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    options: &BinOptions,
+) -> Result<Vec<u8>> {
+    let mut raw = Vec::new();
+    (&mut *reader)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut raw)
+        .await?;
+    if raw == MAGIC {
+        (&mut *reader).take(1).read_to_end(&mut raw).await?;
+        (&mut *reader)
+            .take(header_rest(&raw))
+            .read_to_end(&mut raw)
+            .await?;
+        if let Some(record_size) = body_len(&raw, options) {
+            (&mut *reader)
+                .take(record_size)
+                .read_to_end(&mut raw)
+                .await?;
+        }
+    }
+    Ok(raw)
+}
+
+/// Асинхронный [`write_operation`]: запись собирается в буфер и уходит одним `write_all`
+///
+/// Поток не сбрасывается: это делает вызывающий, когда записи кончатся.
+#[cfg(feature = "async")]
+pub async fn write_operation_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    operation: &Operation,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    encode_layout(&mut buf, operation, false)?;
+    writer.write_all(&buf).await?;
+    Ok(())
+}
+
+/// Асинхронный [`write_all_with_options`]
+///
+/// Записи кодирует тот же [`BinWriter`] в буфер, в поток они уходят по одной.
+#[cfg(feature = "async")]
+pub async fn write_all_async<'a, W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut encoder = BinWriter::new(Vec::new()).with_options(options.clone());
+    for operation in operations {
+        encoder.write(operation)?;
+        writer.write_all(&encoder.writer).await?;
+        encoder.writer.clear();
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Пишет операции по одной, по мере их появления
+///
+/// Каждая запись уходит в поток одним `write_all`, но сам поток писатель не
+/// буферизует: для файла передайте [`BufWriter`]. Ошибку сброса буфера видно
+/// только из [`BinWriter::finish`] (или [`BinWriter::flush`]), при простом
+/// drop она теряется.
+pub struct BinWriter<W> {
+    writer: W,
+    options: WriteOptions,
+    /// Запись собирается здесь, буфер переиспользуется
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BinWriter<W> {
+    /// Писатель с настройками по умолчанию
+    pub fn new(writer: W) -> Self {
+        BinWriter {
+            writer,
+            options: WriteOptions::default(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Настройки записи (CRC32, предел длины описания)
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Проверяет операцию и пишет ее запись
+    pub fn write(&mut self, operation: &Operation) -> Result<()> {
+        self.buf.clear();
+        encode_record(&mut self.buf, operation, &self.options)?;
+        self.writer.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Сбрасывает уже записанное в нижележащий поток
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Возвращает поток, сбросив в него записанное
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// [`write_operation`] с учетом настроек записи
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    encode_record(&mut buf, operation, options)?;
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Дописывает запись в `buf` с учетом настроек записи
+fn encode_record(buf: &mut Vec<u8>, operation: &Operation, options: &WriteOptions) -> Result<()> {
+    let operation = options.limit_description(operation)?;
+    encode_layout(buf, &operation, options.checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::{Operation, OperationStatus, OperationType};
+    use std::io::Cursor;
+
+    /// Собирает запись с описанием "как есть", минуя экранирование писателя
+    fn encode_with_raw_description(op: &Operation, raw: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, op).unwrap();
+        buf.truncate(55);
+
+        let record_size = (46 + raw.len()) as u32;
+        buf[5..9].copy_from_slice(&record_size.to_be_bytes());
+        buf[51..55].copy_from_slice(&(raw.len() as u32).to_be_bytes());
+        buf.extend_from_slice(raw);
+        buf
+    }
+
+    #[test]
+    fn test_round_trip_simple() {
+        let op = Operation {
+            tx_id: 12345,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: "Simple".to_string(),
+            extensions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        let parsed = parse_operation(&mut cursor).unwrap();
+
+        assert_eq!(op, parsed);
+        assert_eq!(parsed.description, "Simple");
+    }
+
+    #[test]
+    fn test_parse_escaped_description() {
+        let op_with_escaped = Operation {
+            tx_id: 1000000000000000,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 9223372036854775807,
+            amount: 100,
+            timestamp: 1633036860000,
+            status: OperationStatus::Failure,
+            description: String::new(),
+            extensions: Vec::new(),
+        };
+
+        // Описание, записанное чужим писателем: экранированные кавычки без внешних
+        let buf = encode_with_raw_description(&op_with_escaped, r#"\"Лишн кавычк 1\""#.as_bytes());
+
+        let mut cursor = Cursor::new(buf);
+        let parsed = parse_operation(&mut cursor).unwrap();
+
+        assert_eq!(parsed.description, r#""Лишн кавычк 1""#);
+    }
+
+    #[test]
+    fn test_round_trip_with_quotes() {
+        let op = Operation {
+            tx_id: 12345,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: r#"Кавычк должны остаться "quotes""#.to_string(),
+            extensions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        let parsed = parse_operation(&mut cursor).unwrap();
+
+        assert_eq!(op, parsed);
+        assert_eq!(parsed.description, r#"Кавычк должны остаться "quotes""#);
+    }
+
+    #[test]
+    fn test_round_trip_unicode() {
+        let op = Operation {
+            tx_id: 12345,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: "Ну по-русски 🎉".to_string(),
+            extensions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        let parsed = parse_operation(&mut cursor).unwrap();
 
         assert_eq!(op, parsed);
         assert_eq!(parsed.description, "Ну по-русски 🎉");
@@ -311,6 +1847,7 @@ mod tests {
             timestamp: 1633036860000,
             status: OperationStatus::Success,
             description: String::new(),
+            extensions: Vec::new(),
         };
 
         let mut buf = Vec::new();
@@ -322,4 +1859,1049 @@ mod tests {
         assert_eq!(op, parsed);
         assert_eq!(parsed.description, "");
     }
+
+    #[test]
+    fn test_written_description_is_quoted() {
+        let op = Operation {
+            tx_id: 12345,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: "Record \"1\"\n".to_string(),
+            extensions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+
+        assert_eq!(&buf[55..], br#""Record \"1\"\n""#);
+    }
+
+    #[test]
+    fn test_huge_description_len_is_not_allocated() {
+        let op = Operation {
+            tx_id: 12345,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: "Short".to_string(),
+            extensions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+
+        // Подменяем DESC_LEN на u32::MAX, тело записи короткое
+        buf[51..55].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut cursor = Cursor::new(buf.clone());
+        let err = parse_operation(&mut cursor).unwrap_err();
+        assert!(
+            matches!(err, ParseError::InvalidField { ref field, .. } if field == "DESCRIPTION")
+        );
+
+        // Без лимита DESC_LEN упирается в RECORD_SIZE
+        let options = BinOptions {
+            max_description_len: usize::MAX,
+            max_extensions_len: 0,
+        };
+        let err = parse_operation_with_options(&mut Cursor::new(&buf), &options).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::InvalidRecordSize { declared: 53, actual } if actual == 46 + u32::MAX as u64
+        ));
+
+        // Обрыв посреди записи называет размеры вместе с заголовком
+        let err = parse_operation(&mut &buf[..29]).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::TruncatedRecord {
+                offset: 0,
+                expected: 62,
+                got: 29
+            }
+        ));
+        assert!(
+            err.to_string().contains("expected 62 bytes, got 29"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_record_size_over_limit_is_rejected() {
+        let options = BinOptions {
+            max_description_len: 4,
+            max_extensions_len: 0,
+        };
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+
+        let err = parse_operation_with_options(&mut Cursor::new(&buf), &options).unwrap_err();
+        assert!(
+            matches!(err, ParseError::InvalidField { ref field, .. } if field == "RECORD_SIZE")
+        );
+
+        // Тот же лимит через ParseOptions
+        let parse_options = ParseOptions {
+            bin: options,
+            ..Default::default()
+        };
+        let err = parse_all_with_options(buf.as_slice(), &parse_options).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidField { field, .. } if field == "RECORD_SIZE")
+        );
+    }
+
+    fn numbered_operation(tx_id: u64) -> Operation {
+        Operation {
+            tx_id,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 67890,
+            amount: 1000,
+            timestamp: 1633036860000,
+            status: OperationStatus::Success,
+            description: format!("Record {}", tx_id),
+            extensions: Vec::new(),
+        }
+    }
+
+    fn encode_all(tx_ids: &[u64]) -> (Vec<u8>, Vec<u64>) {
+        let mut buf = Vec::new();
+        let mut ends = Vec::new();
+        for &tx_id in tx_ids {
+            write_operation(&mut buf, &numbered_operation(tx_id)).unwrap();
+            ends.push(buf.len() as u64);
+        }
+        (buf, ends)
+    }
+
+    #[test]
+    fn test_iter_tracks_position() {
+        let (buf, ends) = encode_all(&[1, 2, 3]);
+        let mut iter = OperationIter::new(Cursor::new(buf));
+        assert_eq!(iter.position(), 0);
+
+        for (i, end) in ends.iter().enumerate() {
+            let op = iter.next().unwrap().unwrap();
+            assert_eq!(op.tx_id, i as u64 + 1);
+            assert_eq!(iter.position(), *end);
+        }
+        assert!(iter.next().is_none());
+    }
+
+    /// Поток из одной записи, повторенной `left` раз; в памяти только сама запись
+    struct Repeated {
+        record: Vec<u8>,
+        pos: usize,
+        left: u64,
+    }
+
+    impl Read for Repeated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Ok(0);
+            }
+            let n = buf.len().min(self.record.len() - self.pos);
+            buf[..n].copy_from_slice(&self.record[self.pos..self.pos + n]);
+            self.pos += n;
+            if self.pos == self.record.len() {
+                self.pos = 0;
+                self.left -= 1;
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_iter_streams_without_reading_ahead() {
+        let (record, ends) = encode_all(&[1]);
+        let repeated = |left| Repeated {
+            record: record.clone(),
+            pos: 0,
+            left,
+        };
+
+        // Бесконечный поток: итератор отдает первые записи, не дочитывая его
+        let first: Vec<u64> = iter_operations(repeated(u64::MAX))
+            .take(3)
+            .map(|op| op.unwrap().tx_id)
+            .collect();
+        assert_eq!(first, vec![1, 1, 1]);
+
+        let mut iter = iter_operations(repeated(100_000));
+        assert_eq!(iter.by_ref().filter(|op| op.is_ok()).count(), 100_000);
+        assert_eq!(iter.position(), 100_000 * ends[0]);
+    }
+
+    #[test]
+    fn test_parse_from_resumes_after_checkpoint() {
+        let (buf, ends) = encode_all(&[1, 2, 3]);
+
+        let iter = parse_from(Cursor::new(buf), ends[0]).unwrap();
+        let tx_ids: Vec<u64> = iter.map(|op| op.unwrap().tx_id).collect();
+
+        assert_eq!(tx_ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_parse_from_rejects_offset_inside_record() {
+        let (buf, _) = encode_all(&[1, 2]);
+
+        let err = parse_from(Cursor::new(buf), 5).err().unwrap();
+
+        assert!(matches!(err, ParseError::InvalidFormat(_)));
+    }
+
+    #[test]
+    fn test_resume_growing_file() {
+        let (full, ends) = encode_all(&[1, 2, 3]);
+
+        // Первый прогон: третья запись еще дописывается
+        let partial = full[..ends[1] as usize + 10].to_vec();
+        let mut iter = OperationIter::new(Cursor::new(partial)).allow_truncated_tail();
+        let first_run: Vec<u64> = iter.by_ref().map(|op| op.unwrap().tx_id).collect();
+        let checkpoint = iter.position();
+
+        assert_eq!(first_run, vec![1, 2]);
+        assert_eq!(checkpoint, ends[1]);
+
+        // Второй прогон после того, как файл дописан
+        let mut iter = parse_from(Cursor::new(full), checkpoint).unwrap();
+        let second_run: Vec<u64> = iter.by_ref().map(|op| op.unwrap().tx_id).collect();
+
+        assert_eq!(second_run, vec![3]);
+        assert_eq!(iter.position(), ends[2]);
+    }
+
+    #[test]
+    fn test_parse_from_at_end_yields_nothing() {
+        let (buf, ends) = encode_all(&[1]);
+
+        let mut iter = parse_from(Cursor::new(buf), ends[0]).unwrap();
+
+        assert!(iter.next().is_none());
+        assert_eq!(iter.position(), ends[0]);
+    }
+
+    /// Две записи подряд, у первой RECORD_SIZE сдвинут на `delta` (и дописан хвост при росте)
+    fn encode_with_record_size_delta(delta: i64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+        let size = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]);
+        let size = (size as i64 + delta) as u32;
+        buf[5..9].copy_from_slice(&size.to_be_bytes());
+        if delta > 0 {
+            buf.extend(std::iter::repeat_n(0u8, delta as usize));
+        }
+        write_operation(&mut buf, &numbered_operation(2)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_record_size_too_small() {
+        let buf = encode_with_record_size_delta(-3);
+        let mut cursor = Cursor::new(buf);
+
+        let err = parse_operation(&mut cursor).unwrap_err();
+
+        let expected_actual = 46 + "\"Record 1\"".len() as u64;
+        assert!(matches!(
+            err,
+            ParseError::InvalidRecordSize { declared, actual }
+                if actual == expected_actual && u64::from(declared) == expected_actual - 3
+        ));
+        assert!(err.to_string().contains("RECORD_SIZE is"));
+    }
+
+    #[test]
+    fn test_record_size_too_large_is_reported() {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+        let size = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]);
+        buf[5..9].copy_from_slice(&(size + 100).to_be_bytes());
+
+        // Хвоста нет: поток кончается раньше, чем обещал RECORD_SIZE
+        let len = buf.len();
+        let err = parse_operation(&mut Cursor::new(buf)).unwrap_err();
+
+        assert!(matches!(
+            err,
+            ParseError::TruncatedRecord { expected, got, .. } if expected == len + 100 && got == len
+        ));
+    }
+
+    #[test]
+    fn test_record_padding_is_skipped_with_error() {
+        let buf = encode_with_record_size_delta(5);
+        let mut cursor = Cursor::new(buf);
+
+        let err = parse_operation(&mut cursor).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::InvalidRecordSize { declared, actual } if u64::from(declared) == actual + 5
+        ));
+
+        // Хвост дочитан, следующая запись разбирается
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 2);
+    }
+
+    #[test]
+    fn test_skip_operation() {
+        let (buf, ends) = encode_all(&[1, 2]);
+        let mut cursor = Cursor::new(buf);
+
+        assert_eq!(skip_operation(&mut cursor).unwrap(), ends[0]);
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 2);
+        assert!(matches!(
+            skip_operation(&mut cursor),
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_v2() {
+        let mut op = numbered_operation(7);
+        op.description = "Перевод \"v2\"".to_string();
+
+        let mut buf = Vec::new();
+        write_operation_v2(&mut buf, &op).unwrap();
+        let mut v1 = Vec::new();
+        write_operation(&mut v1, &op).unwrap();
+        assert_eq!(buf.len(), v1.len() + 4);
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(parse_operation(&mut cursor).unwrap(), op);
+        assert_eq!(cursor.position(), v1.len() as u64 + 4);
+    }
+
+    #[test]
+    fn test_v2_flipped_amount_is_checksum_mismatch() {
+        let mut buf = Vec::new();
+        write_operation_v2(&mut buf, &numbered_operation(7)).unwrap();
+        // AMOUNT: MAGIC(4) + VERSION(1) + RECORD_SIZE(4) + TX_ID(8) + TYPE(1) + FROM(8) + TO(8)
+        buf[34 + 7] ^= 0x01;
+
+        let err = parse_operation(&mut Cursor::new(buf)).unwrap_err();
+
+        assert!(matches!(
+            err,
+            ParseError::ChecksumMismatch { tx_id: 7, expected, actual } if expected != actual
+        ));
+    }
+
+    fn extended_operation() -> Operation {
+        let mut op = numbered_operation(7);
+        op.extensions = vec![
+            Extension::new(Extension::CURRENCY, "USD"),
+            // Тег из будущей версии: читается и пишется как есть
+            Extension::new(0xBEEF, vec![0, 1, 2, 0xFF]),
+            Extension::new(Extension::EXTERNAL_REF, "ext-42"),
+        ];
+        op
+    }
+
+    #[test]
+    fn test_extensions_round_trip_byte_for_byte() {
+        let op = extended_operation();
+
+        // Даже через write_operation запись с расширениями — v2
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+        assert_eq!(buf[4], VERSION_CHECKSUM);
+        let mut plain = Vec::new();
+        write_operation_v2(&mut plain, &numbered_operation(7)).unwrap();
+        assert_eq!(buf.len(), plain.len() + 3 * 6 + 3 + 4 + 6);
+
+        let mut cursor = Cursor::new(buf.as_slice());
+        let parsed = parse_operation(&mut cursor).unwrap();
+        assert_eq!(cursor.position(), buf.len() as u64);
+        assert!(parsed.eq_full(&op));
+        assert_eq!(parsed.extension(Extension::CURRENCY), Some(&b"USD"[..]));
+        assert_eq!(parsed.extension(0xBEEF), Some(&[0, 1, 2, 0xFF][..]));
+        assert_eq!(parsed.extension(3), None);
+
+        let mut rewritten = Vec::new();
+        write_operation(&mut rewritten, &parsed).unwrap();
+        assert_eq!(rewritten, buf);
+        assert_eq!(
+            parse_operation_from_slice(&buf).unwrap().0.extensions,
+            op.extensions
+        );
+
+        // Без расширений ничего не меняется, v1 читается с пустым списком
+        assert!(
+            parse_operation(&mut plain.as_slice())
+                .unwrap()
+                .extensions
+                .is_empty()
+        );
+        let mut v1 = Vec::new();
+        write_operation(&mut v1, &numbered_operation(7)).unwrap();
+        assert_eq!(v1[4], VERSION_PLAIN);
+        assert!(
+            parse_operation(&mut v1.as_slice())
+                .unwrap()
+                .extensions
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_broken_extension_block_is_an_error() {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &extended_operation()).unwrap();
+        // Длина последнего расширения больше, чем осталось в записи; CRC32 пересчитан
+        let crc_at = buf.len() - 4;
+        let len_at = crc_at - "ext-42".len() - 4;
+        buf[len_at..crc_at - "ext-42".len()].copy_from_slice(&7u32.to_be_bytes());
+        let crc = crc32fast::hash(&buf[9..crc_at]);
+        buf[crc_at..].copy_from_slice(&crc.to_be_bytes());
+
+        let err = parse_operation(&mut buf.as_slice()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::InvalidField { field, reason }
+                if field == "EXTENSIONS" && reason.contains("tag 2")),
+            "{}",
+            err
+        );
+
+        // Лимит размера блока
+        let options = BinOptions {
+            max_extensions_len: 8,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &extended_operation()).unwrap();
+        let err = parse_operation_with_options(&mut buf.as_slice(), &options).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::InvalidField { field, .. } if field == "EXTENSIONS"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_v1_and_v2_records_mix() {
+        let options = WriteOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+        write_record(&mut buf, &numbered_operation(2), &options).unwrap();
+        write_operation(&mut buf, &numbered_operation(3)).unwrap();
+
+        let mut ids: Vec<u64> = parse_all(buf.as_slice())
+            .unwrap()
+            .iter()
+            .map(|op| op.tx_id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+
+    /// Запись `op` без байта версии, как ее писали раньше
+    fn encode_legacy(op: &Operation) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, op).unwrap();
+        assert_eq!(buf[4], VERSION_PLAIN);
+        buf.remove(4);
+        buf
+    }
+
+    #[test]
+    fn test_writes_version_byte() {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+        let second = buf.len();
+        write_operation_v2(&mut buf, &numbered_operation(2)).unwrap();
+
+        assert_eq!(&buf[..5], b"YPBN\x01");
+        assert_eq!(&buf[second..second + 5], b"YPBN\x02");
+    }
+
+    #[test]
+    fn test_legacy_records_parse_and_skip() {
+        let mut buf = encode_legacy(&numbered_operation(1));
+        let legacy_len = buf.len() as u64;
+        buf.extend(encode_legacy(&numbered_operation(2)));
+        write_operation(&mut buf, &numbered_operation(3)).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(skip_operation(&mut cursor).unwrap(), legacy_len);
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 2);
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 3);
+    }
+
+    #[test]
+    fn test_unknown_version_is_skipped_with_error() {
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &numbered_operation(1)).unwrap();
+        buf[4] = 9;
+        write_operation(&mut buf, &numbered_operation(2)).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        let err = parse_operation(&mut cursor).unwrap_err();
+
+        assert!(matches!(
+            err,
+            ParseError::UnsupportedVersion {
+                found: 9,
+                supported: 2
+            }
+        ));
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 2);
+    }
+
+    fn recovered_ids(buf: &[u8]) -> (Vec<u64>, Vec<RecordError>) {
+        let (operations, errors) = parse_all_with_recovery(buf, &ParseOptions::default()).unwrap();
+        let mut ids: Vec<u64> = operations.iter().map(|op| op.tx_id).collect();
+        ids.sort();
+        (ids, errors)
+    }
+
+    #[test]
+    fn test_recovery_skips_corrupt_record() {
+        let (mut buf, ends) = encode_all(&[1, 2, 3, 4]);
+        // TX_TYPE второй записи
+        buf[ends[0] as usize + 17] = 0xFF;
+
+        let (ids, errors) = recovered_ids(&buf);
+
+        assert_eq!(ids, vec![1, 3, 4]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, ends[0]);
+        assert_eq!(errors[0].skipped, ends[1] - ends[0]);
+    }
+
+    #[test]
+    fn test_recovery_resyncs_after_corrupt_record_size() {
+        let (mut buf, ends) = encode_all(&[1, 2, 3, 4]);
+        // RECORD_SIZE второй записи захватывает третью и часть четвертой
+        let start = ends[0] as usize;
+        let size = (ends[2] - ends[0] + 10) as u32;
+        buf[start + 5..start + 9].copy_from_slice(&size.to_be_bytes());
+
+        let (ids, errors) = recovered_ids(&buf);
+
+        assert_eq!(ids, vec![1, 3, 4]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, ends[0]);
+        assert_eq!(errors[0].skipped, ends[1] - ends[0]);
+    }
+
+    #[test]
+    fn test_recovery_skips_garbage_between_records() {
+        let (buf, ends) = encode_all(&[1, 2]);
+        let mut corrupted = buf[..ends[0] as usize].to_vec();
+        corrupted.extend_from_slice(b"garbage");
+        corrupted.extend_from_slice(&buf[ends[0] as usize..]);
+
+        let (ids, errors) = recovered_ids(&corrupted);
+
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].error, ParseError::InvalidMagic));
+        assert_eq!((errors[0].offset, errors[0].skipped), (ends[0], 7));
+    }
+
+    #[test]
+    fn test_slice_parse_matches_reader_parse() {
+        let (mut buf, ends) = encode_all(&[1, 2]);
+        write_operation_v2(&mut buf, &numbered_operation(3)).unwrap();
+        buf.extend(encode_legacy(&numbered_operation(4)));
+
+        let (first, consumed) = parse_operation_from_slice(&buf).unwrap();
+        assert_eq!(first, numbered_operation(1));
+        assert_eq!(consumed as u64, ends[0]);
+
+        assert_eq!(
+            parse_all_from_slice(&buf).unwrap(),
+            parse_all(buf.as_slice()).unwrap()
+        );
+        assert_eq!(parse_all_from_slice(&buf).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_all_reports_truncated_tail() {
+        let (buf, ends) = encode_all(&[1, 2]);
+        let second = ends[0] as usize;
+        let record_len = (ends[1] - ends[0]) as usize;
+
+        // Обрыв посреди описания второй записи
+        let err = parse_all(&buf[..ends[1] as usize - 3]).unwrap_err();
+        assert!(matches!(
+            err.without_context(),
+            &ParseError::TruncatedRecord { offset, expected, got }
+                if offset == ends[0] && expected == record_len && got == record_len - 3
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "record #1: Truncated record at byte {}: expected {} bytes, got {}",
+                ends[0],
+                record_len,
+                record_len - 3
+            )
+        );
+
+        // Сразу после MAGIC: ждали хотя бы заголовок записи
+        let err = parse_all(&buf[..second + MAGIC.len()]).unwrap_err();
+        assert!(matches!(
+            err.without_context(),
+            &ParseError::TruncatedRecord { offset, expected: 9, got: 4 } if offset == ends[0]
+        ));
+
+        // Ровно на границе записи — обычный конец
+        let parsed = parse_all(&buf[..second]).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parse_all(buf.as_slice()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_slice_parse_checks_bounds() {
+        let (buf, ends) = encode_all(&[1, 2]);
+
+        // Срез обрывается внутри второй записи
+        let truncated = &buf[..ends[1] as usize - 3];
+        let record_len = (ends[1] - ends[0]) as usize;
+        let err = parse_operation_from_slice(&truncated[ends[0] as usize..]).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::TruncatedRecord { offset: 0, expected, got }
+                if expected == record_len && got == record_len - 3
+        ));
+        // Весь буфер называет смещение самой записи
+        let err = parse_all_from_slice(truncated).unwrap_err();
+        assert_eq!(err.location().unwrap().record_index, Some(1));
+        assert!(matches!(
+            err.without_context(),
+            &ParseError::TruncatedRecord { offset, .. } if offset == ends[0]
+        ));
+        // Обрыв внутри MAGIC — еще конец данных
+        assert_eq!(
+            parse_all_from_slice(&buf[..ends[0] as usize + 2])
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // DESC_LEN выходит за RECORD_SIZE
+        let mut corrupted = buf.clone();
+        corrupted[54] = corrupted[54].wrapping_add(1);
+        let err = parse_operation_from_slice(&corrupted).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidRecordSize { .. }));
+    }
+
+    fn ops(tx_ids: &[u64]) -> HashSet<Operation> {
+        tx_ids
+            .iter()
+            .map(|&tx_id| numbered_operation(tx_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_file_header_round_trip() {
+        let operations = ops(&[1, 2, 3]);
+        let mut buf = Vec::new();
+        write_all_with_header(&mut buf, &operations).unwrap();
+        assert_eq!(&buf[..12], b"YPBF\0\0\0\0\0\0\0\x03");
+
+        let (parsed, report) =
+            parse_all_with_report(buf.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(parsed, operations);
+        assert_eq!(report.declared_records, Some(3));
+        assert_eq!(report.bytes, buf.len() as u64);
+
+        assert_eq!(parse_all_from_slice(&buf).unwrap(), operations);
+        let (recovered, errors) =
+            parse_all_with_recovery(buf.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(recovered, operations);
+        assert!(errors.is_empty());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compressed_container_round_trip() {
+        let operations: Vec<Operation> = (1..=2000).map(numbered_operation).collect();
+        let mut plain = Vec::new();
+        write_all_with_options(&mut plain, &operations, &WriteOptions::default()).unwrap();
+        let options = WriteOptions {
+            checksum: true,
+            ..WriteOptions::default()
+        };
+        let mut compressed = Vec::new();
+        write_all_compressed(&mut compressed, &operations, &options).unwrap();
+        assert!(compressed.starts_with(b"YPBZ"));
+        assert!(
+            compressed.len() * 2 < plain.len(),
+            "{} vs {}",
+            compressed.len(),
+            plain.len()
+        );
+        assert_eq!(
+            crate::detect_format(&compressed).unwrap(),
+            crate::Format::Bin
+        );
+
+        // Метку узнают все функции разбора всего потока, порядок записей сохраняется
+        let (parsed, report) =
+            parse_all_with_report(compressed.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.len(), operations.len());
+        assert_eq!(report.declared_records, Some(2000));
+        assert_eq!(report.bytes, compressed.len() as u64);
+        assert_eq!(parse_all_vec(compressed.as_slice()).unwrap(), operations);
+        let (recovered, errors) =
+            parse_all_with_recovery(compressed.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(recovered.len(), operations.len());
+        assert!(errors.is_empty());
+
+        // Обрезанный контейнер — ошибка, а не молча меньше записей
+        compressed.truncate(compressed.len() / 2);
+        assert!(parse_all(compressed.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_compressed_container_needs_whole_stream() {
+        let mut container = b"YPBZ".to_vec();
+        container.extend_from_slice(&[0; 16]);
+
+        // По смещениям сжатый файл не читается: понятная ошибка вместо InvalidMagic
+        let err = iter_operations(container.as_slice())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().contains("YPBZ"), "{}", err);
+        assert!(parse_all_from_slice(&container).is_err());
+
+        #[cfg(not(feature = "zstd"))]
+        {
+            let err = parse_all(container.as_slice()).unwrap_err();
+            assert!(err.to_string().contains("`zstd` feature"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_file_header_count_mismatch() {
+        let mut buf = Vec::new();
+        write_all_with_header(&mut buf, &ops(&[1, 2])).unwrap();
+        // Заголовок обещает на запись больше, чем есть
+        buf[FILE_MAGIC.len()..FILE_HEADER_LEN].copy_from_slice(&3u64.to_be_bytes());
+
+        let err = parse_all(buf.as_slice()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::InvalidFormat(msg) if msg.contains("declares 3 records, found 2")),
+            "{}",
+            err
+        );
+        assert!(matches!(
+            parse_all_from_slice(&buf),
+            Err(ParseError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_iter_operations_checks_file_header_at_eof() {
+        assert!(iter_operations(io::empty()).next().is_none());
+
+        let mut buf = Vec::new();
+        write_all_with_header(&mut buf, &ops(&[1, 2, 3])).unwrap();
+        let mut iter = iter_operations(buf.as_slice());
+        assert_eq!(iter.declared_records(), None);
+        assert_eq!(iter.by_ref().filter(|op| op.is_ok()).count(), 3);
+        assert_eq!(iter.declared_records(), Some(3));
+        assert_eq!(iter.position(), buf.len() as u64);
+
+        // Недописанная запись — ошибка, как у parse_all
+        buf.truncate(buf.len() - 5);
+        let mut iter = iter_operations(buf.as_slice());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        let err = iter.next().unwrap().unwrap_err();
+        assert!(matches!(
+            err.without_context(),
+            ParseError::TruncatedRecord { .. }
+        ));
+        assert!(iter.next().is_none());
+
+        // С allow_truncated_tail это конец потока, но заголовок обещал три записи
+        let mut iter = iter_operations(buf.as_slice()).allow_truncated_tail();
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ParseError::InvalidFormat(_)))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_iter_operations_stops_after_corrupt_record() {
+        let (mut buf, ends) = encode_all(&[1, 2, 3]);
+        buf[ends[0] as usize] = b'X';
+
+        let mut iter = iter_operations(buf.as_slice());
+        assert_eq!(iter.next().unwrap().unwrap().tx_id, 1);
+        let err = iter.next().unwrap().unwrap_err();
+        assert!(matches!(err.without_context(), ParseError::InvalidMagic));
+        // Место — начало второй записи
+        let location = err.location().unwrap();
+        assert_eq!(location.record_index, Some(1));
+        assert_eq!(location.byte_offset, Some(ends[0]));
+        assert_eq!(
+            err.to_string(),
+            format!("record #1, byte {}: Invalid magic header", ends[0])
+        );
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_read_file_header() {
+        let mut buf = Vec::new();
+        write_all_with_header(&mut buf, &ops(&[1, 2])).unwrap();
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(read_file_header(&mut cursor).unwrap(), Some(2));
+        assert_eq!(cursor.position(), 12);
+        assert!(parse_operation(&mut cursor).is_ok());
+
+        // Без заголовка позиция не меняется
+        let (plain, _) = encode_all(&[1]);
+        let mut cursor = Cursor::new(plain);
+        assert_eq!(read_file_header(&mut cursor).unwrap(), None);
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(parse_operation(&mut cursor).unwrap().tx_id, 1);
+    }
+
+    fn append_fixture(name: &str, contents: &[u8]) -> (std::path::PathBuf, File) {
+        let dir = std::env::temp_dir().join(format!("parser_append_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.bin", name));
+        std::fs::write(&path, contents).unwrap();
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+        (path, file)
+    }
+
+    fn tx_ids(path: &std::path::Path) -> Vec<u64> {
+        let buf = std::fs::read(path).unwrap();
+        let operations = parse_all_vec(buf.as_slice()).unwrap();
+        operations.iter().map(|op| op.tx_id).collect()
+    }
+
+    #[test]
+    fn test_append_to_empty_and_clean_file() {
+        let (path, mut file) = append_fixture("clean", b"");
+        let first = [numbered_operation(1), numbered_operation(2)];
+        let outcome = append_operations(&mut file, &first, &WriteOptions::default()).unwrap();
+        assert_eq!(
+            outcome,
+            AppendOutcome {
+                truncated: 0,
+                appended: 2
+            }
+        );
+
+        let outcome = append_operations(
+            &mut file,
+            &[numbered_operation(3)],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(outcome.truncated, 0);
+        assert_eq!(tx_ids(&path), [1, 2, 3]);
+        let (expected, _) = encode_all(&[1, 2, 3]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_append_truncates_torn_record() {
+        let (clean, _) = encode_all(&[1, 2]);
+        let (third, _) = encode_all(&[3]);
+        // Оборвано посреди тела, посреди заголовка записи и посреди MAGIC
+        for cut in [20, 7, 2] {
+            let mut torn = clean.clone();
+            torn.extend_from_slice(&third[..cut]);
+            let (path, mut file) = append_fixture(&format!("torn_{}", cut), &torn);
+
+            let outcome = append_operations(
+                &mut file,
+                &[numbered_operation(4)],
+                &WriteOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(outcome.truncated, cut as u64);
+            assert_eq!(outcome.appended, 1);
+            assert_eq!(tx_ids(&path), [1, 2, 4]);
+        }
+    }
+
+    #[test]
+    fn test_append_updates_file_header() {
+        let mut buf = Vec::new();
+        write_all_with_header(&mut buf, &ops(&[1, 2])).unwrap();
+        let (path, mut file) = append_fixture("header", &buf);
+        append_operations(
+            &mut file,
+            &[numbered_operation(3)],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        let (parsed, report) =
+            parse_all_with_report(buf.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(parsed, ops(&[1, 2, 3]));
+        assert_eq!(report.declared_records, Some(3));
+    }
+
+    #[test]
+    fn test_append_refuses_and_leaves_file_untouched() {
+        let (clean, _) = encode_all(&[1, 2]);
+        let checksum = WriteOptions {
+            checksum: true,
+            ..WriteOptions::default()
+        };
+        let new = [numbered_operation(3)];
+        let refused = |name: &str, contents: &[u8], options: &WriteOptions| {
+            let (path, mut file) = append_fixture(name, contents);
+            let err = append_operations(&mut file, &new, options).unwrap_err();
+            assert_eq!(std::fs::read(&path).unwrap(), contents, "{}", name);
+            err.to_string()
+        };
+
+        // v2 с CRC32 в файл из записей v1
+        let err = refused("version", &clean, &checksum);
+        assert!(
+            err.contains("as a v2 record to a file of v1 records"),
+            "{}",
+            err
+        );
+
+        // Мусор вместо следующей записи — это порча, а не оборванный хвост
+        let mut garbage = clean.clone();
+        garbage.extend_from_slice(b"garbage");
+        let err = refused("garbage", &garbage, &WriteOptions::default());
+        assert!(err.contains("is not a record boundary"), "{}", err);
+
+        // Последняя запись целая по длине, но не разбирается
+        let mut broken = Vec::new();
+        write_operation_v2(&mut broken, &numbered_operation(1)).unwrap();
+        let last = broken.len() - 1;
+        broken[last] ^= 0xFF;
+        let err = refused("crc", &broken, &checksum);
+        assert!(err.contains("CRC") || err.contains("hecksum"), "{}", err);
+
+        // Плохая новая операция: файл тоже не тронут
+        let mut invalid = numbered_operation(3);
+        invalid.from_user_id = 5;
+        let (path, mut file) = append_fixture("invalid", &clean);
+        assert!(append_operations(&mut file, &[invalid], &WriteOptions::default()).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), clean);
+    }
+
+    #[cfg(feature = "async")]
+    fn full(operations: HashSet<Operation>) -> HashSet<FullOperation> {
+        operations.into_iter().map(FullOperation).collect()
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_matches_sync() {
+        let fixture = include_bytes!("../golden/fixture.bin");
+        let options = ParseOptions::default();
+        let expected = parse_all(&fixture[..]).unwrap();
+
+        let parsed = parse_all_async(&fixture[..], &options).await.unwrap();
+        assert_eq!(full(parsed), full(expected.clone()));
+
+        // Те же байты, что у синхронного писателя, в том числе v2
+        let operations = parse_all_vec(&fixture[..]).unwrap();
+        for checksum in [false, true] {
+            let write_options = WriteOptions {
+                checksum,
+                ..WriteOptions::default()
+            };
+            let mut sync_out = Vec::new();
+            write_all_with_options(&mut sync_out, &operations, &write_options).unwrap();
+            let mut async_out = Vec::new();
+            write_all_async(&mut async_out, &operations, &write_options)
+                .await
+                .unwrap();
+            assert_eq!(async_out, sync_out);
+        }
+
+        // По одной записи — те же байты, что у write_operation, и тот же разбор
+        let mut sync_out = Vec::new();
+        let mut async_out = Vec::new();
+        for operation in &operations {
+            write_operation(&mut sync_out, operation).unwrap();
+            write_operation_async(&mut async_out, operation)
+                .await
+                .unwrap();
+        }
+        assert_eq!(async_out, sync_out);
+        let mut reader = async_out.as_slice();
+        let first = parse_operation_async(&mut reader, &BinOptions::default())
+            .await
+            .unwrap();
+        assert!(first.eq_full(&operations[0]));
+
+        // Маленький буфер duplex: писатель ждет, пока читатель разберет записи
+        let (mut client, server) = tokio::io::duplex(64);
+        let write = async move {
+            write_all_async(&mut client, &operations, &WriteOptions::default()).await
+        };
+        let (written, read) = tokio::join!(write, parse_all_async(server, &options));
+        written.unwrap();
+        assert_eq!(full(read.unwrap()), full(expected));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_errors_match_sync() {
+        let (buf, ends) = encode_all(&[1, 2]);
+        let options = ParseOptions::default();
+
+        let mut reader = buf.as_slice();
+        let bin = BinOptions::default();
+        assert_eq!(
+            parse_operation_async(&mut reader, &bin)
+                .await
+                .unwrap()
+                .tx_id,
+            1
+        );
+        assert_eq!(
+            parse_operation_async(&mut reader, &bin)
+                .await
+                .unwrap()
+                .tx_id,
+            2
+        );
+        assert!(matches!(
+            parse_operation_async(&mut reader, &bin).await,
+            Err(ParseError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+
+        let truncated = &buf[..ends[1] as usize - 3];
+        let mut corrupted = buf.clone();
+        corrupted[ends[0] as usize] = b'X';
+        let mut with_header = Vec::new();
+        write_all_with_header(&mut with_header, &ops(&[1, 2])).unwrap();
+        with_header[FILE_MAGIC.len()..FILE_HEADER_LEN].copy_from_slice(&3u64.to_be_bytes());
+
+        for input in [truncated, &corrupted, &with_header] {
+            let expected = parse_all(input).unwrap_err();
+            let err = parse_all_async(input, &options).await.unwrap_err();
+            assert_eq!(err.to_string(), expected.to_string());
+        }
+    }
 }
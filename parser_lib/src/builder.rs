@@ -0,0 +1,172 @@
+//! Сборка операций без struct literal
+//!
+//! Пользователей задает только конструктор нужного типа, поэтому DEPOSIT от
+//! пользователя или WITHDRAWAL кому-то не собрать. Остальные правила
+//! проверяет [`OperationBuilder::build`].
+
+use crate::error::Result;
+use crate::operation::{Operation, OperationStatus, OperationType};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Недостроенная операция, см. [`Operation::deposit`], [`Operation::withdrawal`], [`Operation::transfer`]
+///
+/// По умолчанию TX_ID и TIMESTAMP — 0, статус PENDING, описание пустое.
+#[derive(Debug, Clone)]
+#[must_use = "операция появится только после build()"]
+pub struct OperationBuilder {
+    operation: Operation,
+}
+
+impl Operation {
+    /// Пополнение счета `to_user_id`
+    pub fn deposit(to_user_id: u64, amount: i64) -> OperationBuilder {
+        OperationBuilder::new(OperationType::Deposit, 0, to_user_id, amount)
+    }
+
+    /// Снятие средств со счета `from_user_id`
+    pub fn withdrawal(from_user_id: u64, amount: i64) -> OperationBuilder {
+        OperationBuilder::new(OperationType::Withdrawal, from_user_id, 0, amount)
+    }
+
+    /// Перевод от `from_user_id` к `to_user_id`; оба должны быть ненулевыми
+    pub fn transfer(from_user_id: u64, to_user_id: u64, amount: i64) -> OperationBuilder {
+        OperationBuilder::new(OperationType::Transfer, from_user_id, to_user_id, amount)
+    }
+}
+
+impl OperationBuilder {
+    /// Любой тип с пользователями как есть: для тестов, перебирающих типы;
+    /// снаружи крейта — только конструкторы по типу
+    pub(crate) fn new(
+        tx_type: OperationType,
+        from_user_id: u64,
+        to_user_id: u64,
+        amount: i64,
+    ) -> Self {
+        Self {
+            operation: Operation {
+                tx_id: 0,
+                tx_type,
+                from_user_id,
+                to_user_id,
+                amount,
+                timestamp: 0,
+                status: OperationStatus::Pending,
+                description: String::new(),
+                extensions: Vec::new(),
+            },
+        }
+    }
+
+    /// Идентификатор транзакции
+    pub fn tx_id(mut self, tx_id: u64) -> Self {
+        self.operation.tx_id = tx_id;
+        self
+    }
+
+    /// Время в миллисекундах с начала эпохи
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.operation.timestamp = timestamp;
+        self
+    }
+
+    /// Время моментом chrono в любом поясе (фича `chrono`)
+    ///
+    /// # Возвращает
+    /// Ошибку, если момент раньше 1970 года: TIMESTAMP беззнаковый
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_datetime<Tz: chrono::TimeZone>(
+        self,
+        datetime: &chrono::DateTime<Tz>,
+    ) -> Result<Self> {
+        Ok(self.timestamp(crate::timestamp::from_datetime(datetime)?))
+    }
+
+    /// Текущее время по системным часам
+    pub fn timestamp_now(self) -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            // Часы до 1970 года — считаем началом эпохи
+            .unwrap_or(0);
+        self.timestamp(millis)
+    }
+
+    /// Статус вместо PENDING
+    pub fn status(mut self, status: OperationStatus) -> Self {
+        self.operation.status = status;
+        self
+    }
+
+    /// Описание как есть, без кавычек и экранирования
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.operation.description = description.into();
+        self
+    }
+
+    /// Проверяет операцию через [`Operation::validate`] и отдает ее
+    pub fn build(self) -> Result<Operation> {
+        self.operation.validate()?;
+        Ok(self.operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposit() {
+        let op = Operation::deposit(7, 100)
+            .tx_id(1)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("salary")
+            .build()
+            .unwrap();
+
+        assert!(op.eq_full(&Operation {
+            tx_id: 1,
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 7,
+            amount: 100,
+            timestamp: 1633036800000,
+            status: OperationStatus::Success,
+            description: "salary".to_string(),
+            extensions: Vec::new(),
+        }));
+    }
+
+    #[test]
+    fn test_withdrawal_and_transfer() {
+        let op = Operation::withdrawal(8, 50).build().unwrap();
+        assert_eq!(op.tx_type, OperationType::Withdrawal);
+        assert_eq!((op.from_user_id, op.to_user_id), (8, 0));
+
+        let op = Operation::transfer(7, 8, 25).build().unwrap();
+        assert_eq!(op.tx_type, OperationType::Transfer);
+        assert_eq!((op.from_user_id, op.to_user_id), (7, 8));
+        assert_eq!(op.amount, 25);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let op = Operation::deposit(7, 100).build().unwrap();
+
+        assert_eq!(op.tx_id, 0);
+        assert_eq!(op.timestamp, 0);
+        assert_eq!(op.status, OperationStatus::Pending);
+        assert_eq!(op.description, "");
+
+        // 2021 год: часы точно идут дальше
+        let op = Operation::deposit(7, 100).timestamp_now().build().unwrap();
+        assert!(op.timestamp > 1633036800000);
+    }
+
+    #[test]
+    fn test_transfer_with_zero_user_fails() {
+        assert!(Operation::transfer(0, 8, 25).build().is_err());
+        assert!(Operation::transfer(7, 0, 25).build().is_err());
+    }
+}
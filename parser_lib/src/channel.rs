@@ -0,0 +1,186 @@
+//! Разбор в отдельном потоке с отдачей операций через ограниченный канал
+
+use crate::error::{ParseError, Result};
+use crate::format::Format;
+use crate::operation::Operation;
+use crate::options::ParseOptions;
+use crate::report::ParseReport;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Емкость канала в [`spawn_parser`] по умолчанию
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Приемная сторона канала [`spawn_parser`]
+///
+/// Итератор по `Result<Operation>`: после ошибки разбора она приходит последним
+/// элементом, затем канал закрывается. Drop приемника останавливает парсер.
+pub struct OperationReceiver {
+    inner: Receiver<Result<Operation>>,
+}
+
+impl OperationReceiver {
+    /// Ждет следующую операцию; `None`, когда парсер закончил
+    pub fn recv(&self) -> Option<Result<Operation>> {
+        self.inner.recv().ok()
+    }
+}
+
+impl Iterator for OperationReceiver {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+/// Поток с парсером, запущенный [`spawn_parser`]
+pub struct ParserHandle {
+    thread: JoinHandle<Result<ParseReport>>,
+}
+
+impl ParserHandle {
+    /// Ждет окончания разбора
+    ///
+    /// # Возвращает
+    /// * `Ok(ParseReport)` - Поток разобран целиком
+    /// * `Err(ParseError::Cancelled)` - Приемник закрыли раньше или сработал токен отмены
+    /// * `Err(ParseError)` - Ошибка разбора (та же, что пришла в канал)
+    pub fn join(self) -> Result<ParseReport> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+
+    /// Закончил ли поток работу (тогда [`ParserHandle::join`] не блокирует)
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+/// Запускает потоковый разбор в отдельном потоке с каналом на [`DEFAULT_CAPACITY`] операций
+pub fn spawn_parser<R: Read + Send + 'static>(
+    reader: R,
+    format: Format,
+    options: ParseOptions,
+) -> (OperationReceiver, ParserHandle) {
+    spawn_parser_with_capacity(reader, format, options, DEFAULT_CAPACITY)
+}
+
+/// То же, что [`spawn_parser`], но с заданной емкостью канала
+///
+/// Когда в канале `capacity` непрочитанных операций, парсер ждет потребителя.
+/// Если приемник закрыт, парсер останавливается на следующей записи.
+pub fn spawn_parser_with_capacity<R: Read + Send + 'static>(
+    reader: R,
+    format: Format,
+    options: ParseOptions,
+    capacity: usize,
+) -> (OperationReceiver, ParserHandle) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let thread = thread::spawn(move || run(reader, format, &options, sender));
+
+    (
+        OperationReceiver { inner: receiver },
+        ParserHandle { thread },
+    )
+}
+
+fn run<R: Read>(
+    reader: R,
+    format: Format,
+    options: &ParseOptions,
+    sender: SyncSender<Result<Operation>>,
+) -> Result<ParseReport> {
+    let result = format.parse_each(reader, options, |operation, _| {
+        sender
+            .send(Ok(operation))
+            .map_err(|_| ParseError::Cancelled)
+    });
+
+    if let Err(e) = &result {
+        // Приемник уже может быть закрыт: тогда ошибку узнают только из join
+        let _ = sender.send(Err(e.duplicate()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn encoded(format: Format, count: u64) -> Vec<u8> {
+        let ops: Vec<Operation> = (1..=count)
+            .map(|tx_id| {
+                Operation::deposit(1, 100)
+                    .tx_id(tx_id)
+                    .timestamp(1633036800000)
+                    .status(OperationStatus::Success)
+                    .description("Deposit")
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, &ops, &Default::default())
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_clean_completion() {
+        for format in [Format::Bin, Format::Csv, Format::Txt] {
+            let input = encoded(format, 50);
+            let (receiver, handle) = spawn_parser_with_capacity(
+                std::io::Cursor::new(input),
+                format,
+                Default::default(),
+                4,
+            );
+
+            let ids: Vec<u64> = receiver.map(|op| op.unwrap().tx_id).collect();
+            assert_eq!(ids, (1..=50).collect::<Vec<_>>());
+            assert_eq!(handle.join().unwrap().records, 50);
+        }
+    }
+
+    #[test]
+    fn test_error_goes_to_channel_and_join() {
+        let mut input = encoded(Format::Bin, 2);
+        input.extend_from_slice(b"XXXX garbage after two records");
+
+        let (receiver, handle) =
+            spawn_parser(std::io::Cursor::new(input), Format::Bin, Default::default());
+        let items: Vec<Result<Operation>> = receiver.collect();
+
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok() && items[1].is_ok());
+        assert!(
+            matches!(&items[2], Err(e) if matches!(e.without_context(), ParseError::InvalidMagic))
+        );
+        assert!(
+            matches!(handle.join(), Err(e) if matches!(e.without_context(), ParseError::InvalidMagic))
+        );
+    }
+
+    #[test]
+    fn test_dropping_receiver_stops_parser() {
+        let input = encoded(Format::Csv, 10_000);
+        let (mut receiver, handle) = spawn_parser_with_capacity(
+            std::io::Cursor::new(input),
+            Format::Csv,
+            Default::default(),
+            1,
+        );
+
+        assert_eq!(receiver.next().unwrap().unwrap().tx_id, 1);
+        drop(receiver);
+
+        // Парсер висел на полном канале: теперь он должен выйти, а не ждать вечно
+        assert!(matches!(handle.join(), Err(ParseError::Cancelled)));
+    }
+}
@@ -0,0 +1,473 @@
+//! Проверки соответствия форматов (фича `test-utils`)
+//!
+//! Набор граничных операций и прогон "записали -> прочитали -> сравнили все поля",
+//! который можно переиспользовать для собственных реализаций [`OperationFormat`].
+//!
+//! Здесь же эталонные (golden) файлы: [`golden_fixture`], записанный
+//! [`Format::write_all_sorted`], лежит в `golden/` и должен совпадать побайтно.
+//! Партнеры считают контрольные суммы наших файлов, поэтому любое изменение
+//! сериализации должно быть осознанным: эталоны перегенерирует
+//! `cargo run --example write_goldens --features test-utils`.
+
+use crate::format::{Format, OperationFormat};
+use crate::operation::{Operation, OperationStatus, OperationType};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Провал проверки round-trip для одной операции
+#[derive(Debug, Clone)]
+pub struct ConformanceFailure {
+    /// Имя формата, на котором упала проверка
+    pub format: String,
+    /// Исходная операция
+    pub case: Operation,
+    /// Что именно пошло не так
+    pub reason: String,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] tx_id {}: {}",
+            self.format, self.case.tx_id, self.reason
+        )
+    }
+}
+
+/// Прогоняет каждую операцию через запись и чтение форматом и сравнивает все поля
+///
+/// # Аргументы
+/// * `format` - Проверяемый формат
+/// * `cases` - Операции для проверки (каждая пишется отдельным файлом)
+///
+/// # Возвращает
+/// Список провалов; пустой список означает, что формат прошел проверку
+pub fn check_round_trip<F: OperationFormat>(
+    format: &F,
+    cases: impl Iterator<Item = Operation>,
+) -> Vec<ConformanceFailure> {
+    let mut failures = Vec::new();
+
+    for case in cases {
+        if let Err(reason) = round_trip_one(format, &case) {
+            failures.push(ConformanceFailure {
+                format: format.name().to_string(),
+                case,
+                reason,
+            });
+        }
+    }
+
+    failures
+}
+
+fn round_trip_one<F: OperationFormat>(format: &F, case: &Operation) -> Result<(), String> {
+    let operations: HashSet<Operation> = [case.clone()].into_iter().collect();
+
+    let mut buf = Vec::new();
+    format
+        .write_all(&mut buf, &operations)
+        .map_err(|e| format!("write failed: {}", e))?;
+
+    let parsed = format
+        .parse_all(&mut Cursor::new(buf))
+        .map_err(|e| format!("parse failed: {}", e))?;
+
+    if parsed.len() != 1 {
+        return Err(format!("expected 1 operation, got {}", parsed.len()));
+    }
+    let actual = parsed
+        .get(case)
+        .ok_or_else(|| "tx_id changed after round trip".to_string())?;
+
+    let differing = differing_fields(case, actual);
+    if differing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("fields differ: {}", differing.join(", ")))
+    }
+}
+
+fn differing_fields(expected: &Operation, actual: &Operation) -> Vec<String> {
+    expected
+        .differing_fields(actual)
+        .into_iter()
+        .map(|field| match field {
+            "DESCRIPTION" => format!(
+                "DESCRIPTION ({:?} != {:?})",
+                expected.description, actual.description
+            ),
+            _ => field.to_string(),
+        })
+        .collect()
+}
+
+/// Стандартный набор граничных операций
+///
+/// Покрывает все сочетания типа и статуса, крайние числовые значения
+/// и "неудобные" описания: пустое, огромное, юникод, кавычки, экранирование,
+/// переводы строк и разделители форматов.
+pub fn edge_cases() -> Vec<Operation> {
+    let mut cases = Vec::new();
+    let mut next_id = 1u64;
+
+    let types = [
+        OperationType::Deposit,
+        OperationType::Transfer,
+        OperationType::Withdrawal,
+    ];
+    let statuses = [
+        OperationStatus::Success,
+        OperationStatus::Failure,
+        OperationStatus::Pending,
+    ];
+
+    for tx_type in &types {
+        for status in &statuses {
+            let mut op = base_operation(next_id, tx_type.clone());
+            op.status = status.clone();
+            cases.push(op);
+            next_id += 1;
+        }
+    }
+
+    let numeric: [(u64, i64, u64); 5] = [
+        (u64::MAX, i64::MAX, u64::MAX),
+        (0, i64::MIN, 0),
+        (next_id, 0, 1),
+        (next_id + 1, -1, 1633036800000),
+        (next_id + 2, 1, 1633036800000),
+    ];
+    for (tx_id, amount, timestamp) in numeric {
+        for tx_type in &types {
+            let mut op = base_operation(tx_id, tx_type.clone());
+            op.amount = amount;
+            op.timestamp = timestamp;
+            if op.from_user_id != 0 {
+                op.from_user_id = u64::MAX;
+            }
+            if op.to_user_id != 0 {
+                op.to_user_id = u64::MAX;
+            }
+            cases.push(op);
+        }
+    }
+    next_id += 3;
+
+    let descriptions = [
+        String::new(),
+        "x".repeat(64 * 1024),
+        "Ну по-русски 🎉 日本語 ünïcödé".to_string(),
+        r#""quoted""#.to_string(),
+        r#"Payment, invoice "42""#.to_string(),
+        "\"".to_string(),
+        "\"\"".to_string(),
+        r#"\"already escaped\""#.to_string(),
+        r"back\slash".to_string(),
+        r"trailing backslash\".to_string(),
+        r"\n is not a newline".to_string(),
+        "line1\nline2".to_string(),
+        "crlf\r\nline".to_string(),
+        "tab\there".to_string(),
+        "  leading and trailing spaces  ".to_string(),
+        "a,b,,c".to_string(),
+        "# not a comment".to_string(),
+        "KEY: value".to_string(),
+        "\n".to_string(),
+        " ".to_string(),
+    ];
+    for description in descriptions {
+        let mut op = base_operation(next_id, OperationType::Transfer);
+        op.description = description;
+        cases.push(op);
+        next_id += 1;
+    }
+
+    cases
+}
+
+/// Канонический набор операций для эталонных файлов
+///
+/// Небольшой, с уникальными TX_ID: все типы и статусы, крайние числа
+/// и описания, которые проверяют кавычки, экранирование и переводы строк.
+pub fn golden_fixture() -> Vec<Operation> {
+    let mut ops = Vec::new();
+
+    let mut next_id = 1u64;
+    for tx_type in OperationType::ALL {
+        for status in OperationStatus::ALL {
+            let mut op = base_operation(next_id, tx_type.clone());
+            op.status = status;
+            op.amount = next_id as i64 * 1000;
+            op.timestamp += next_id * 60_000;
+            ops.push(op);
+            next_id += 1;
+        }
+    }
+
+    let mut extreme = base_operation(u64::MAX, OperationType::Transfer);
+    extreme.from_user_id = u64::MAX;
+    extreme.to_user_id = 1;
+    extreme.amount = i64::MAX;
+    extreme.timestamp = u64::MAX;
+    ops.push(extreme);
+
+    let mut negative = base_operation(0, OperationType::Withdrawal);
+    negative.amount = i64::MIN;
+    negative.timestamp = 0;
+    ops.push(negative);
+
+    let descriptions = [
+        "",
+        r#"Payment, invoice "42""#,
+        r"back\slash",
+        "line1\nline2\r\n\ttab",
+        "Перевод 🎉",
+        "  spaced  ",
+    ];
+    for (i, description) in descriptions.into_iter().enumerate() {
+        let mut op = base_operation(100 + i as u64, OperationType::Deposit);
+        op.description = description.to_string();
+        ops.push(op);
+    }
+
+    ops
+}
+
+/// Путь эталонного файла формата в каталоге `dir`
+pub fn golden_path(dir: &Path, format: Format) -> PathBuf {
+    dir.join(format!("fixture.{}", format.name()))
+}
+
+/// Перезаписывает эталонные файлы всех форматов в `dir`
+///
+/// Нужен только когда формат меняется намеренно; после этого изменения
+/// эталонов надо закоммитить вместе с кодом.
+pub fn write_goldens(dir: &Path) -> crate::Result<Vec<PathBuf>> {
+    let operations: HashSet<Operation> = golden_fixture().into_iter().collect();
+    fs::create_dir_all(dir)?;
+
+    let mut written = Vec::new();
+    for format in [
+        Format::Bin,
+        Format::Csv,
+        Format::Txt,
+        #[cfg(feature = "serde")]
+        Format::Json,
+        #[cfg(feature = "serde")]
+        Format::Jsonl,
+    ] {
+        let mut buf = Vec::new();
+        format.write_all_sorted(&mut buf, &operations)?;
+
+        let path = golden_path(dir, format);
+        fs::write(&path, buf)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+fn base_operation(tx_id: u64, tx_type: OperationType) -> Operation {
+    let (from_user_id, to_user_id) = match tx_type {
+        OperationType::Deposit => (0, 42),
+        OperationType::Transfer | OperationType::Unknown(_) => (42, 43),
+        OperationType::Withdrawal => (42, 0),
+    };
+
+    Operation {
+        tx_id,
+        tx_type,
+        from_user_id,
+        to_user_id,
+        amount: 100,
+        timestamp: 1633036800000,
+        status: OperationStatus::Success,
+        description: format!("Edge case {}", tx_id),
+        extensions: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+
+    fn assert_conforms(format: Format) {
+        let failures = check_round_trip(&format, edge_cases().into_iter());
+        let rendered: Vec<String> = failures.iter().map(|f| f.to_string()).collect();
+        assert!(rendered.is_empty(), "{}", rendered.join("\n"));
+    }
+
+    #[test]
+    fn test_bin_passes_battery() {
+        assert_conforms(Format::Bin);
+    }
+
+    #[test]
+    fn test_csv_passes_battery() {
+        assert_conforms(Format::Csv);
+    }
+
+    #[test]
+    fn test_text_passes_battery() {
+        assert_conforms(Format::Txt);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_passes_battery() {
+        assert_conforms(Format::Json);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_jsonl_passes_battery() {
+        assert_conforms(Format::Jsonl);
+    }
+
+    fn assert_matches_golden(format: Format, golden: &[u8]) {
+        let operations: HashSet<Operation> = golden_fixture().into_iter().collect();
+        let mut buf = Vec::new();
+        format.write_all_sorted(&mut buf, &operations).unwrap();
+
+        if buf != golden {
+            let offset = buf
+                .iter()
+                .zip(golden)
+                .position(|(a, b)| a != b)
+                .unwrap_or(buf.len().min(golden.len()));
+            panic!(
+                "{} serialization changed: output differs from golden/fixture.{} at byte {} \
+                 (got {} bytes, golden has {}). If the format change is intentional, run \
+                 `cargo run --example write_goldens --features test-utils` \
+                 from parser_lib and commit the new goldens.",
+                format.name(),
+                format.name(),
+                offset,
+                buf.len(),
+                golden.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_bin_matches_golden() {
+        assert_matches_golden(Format::Bin, include_bytes!("../golden/fixture.bin"));
+    }
+
+    #[test]
+    fn test_csv_matches_golden() {
+        assert_matches_golden(Format::Csv, include_bytes!("../golden/fixture.csv"));
+    }
+
+    #[test]
+    fn test_text_matches_golden() {
+        assert_matches_golden(Format::Txt, include_bytes!("../golden/fixture.txt"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_matches_golden() {
+        assert_matches_golden(Format::Json, include_bytes!("../golden/fixture.json"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_jsonl_matches_golden() {
+        assert_matches_golden(Format::Jsonl, include_bytes!("../golden/fixture.jsonl"));
+    }
+
+    #[test]
+    fn test_goldens_parse_back_to_fixture() {
+        let goldens: &[(Format, &[u8])] = &[
+            (Format::Bin, include_bytes!("../golden/fixture.bin")),
+            (Format::Csv, include_bytes!("../golden/fixture.csv")),
+            (Format::Txt, include_bytes!("../golden/fixture.txt")),
+            #[cfg(feature = "serde")]
+            (Format::Json, include_bytes!("../golden/fixture.json")),
+            #[cfg(feature = "serde")]
+            (Format::Jsonl, include_bytes!("../golden/fixture.jsonl")),
+        ];
+
+        for &(format, golden) in goldens {
+            let parsed = format.parse_all_vec(golden).unwrap();
+            let mut expected = golden_fixture();
+            expected.sort_by_key(|op| op.tx_id);
+
+            assert_eq!(parsed.len(), expected.len(), "{}", format.name());
+            for (actual, expected) in parsed.iter().zip(&expected) {
+                let differing = differing_fields(expected, actual);
+                assert!(differing.is_empty(), "{}: {:?}", format.name(), differing);
+            }
+        }
+    }
+
+    #[test]
+    fn test_legacy_bin_golden_still_parses() {
+        // Эталон бинарника до появления байта версии; перегенерировать его нельзя
+        let legacy: &[u8] = include_bytes!("../golden/fixture_legacy.bin");
+        let expected: HashSet<Operation> = golden_fixture().into_iter().collect();
+
+        let parsed = Format::Bin.parse_all_vec(legacy).unwrap();
+
+        assert_eq!(parsed.len(), expected.len());
+        for op in &parsed {
+            let expected = expected.get(op).unwrap();
+            assert!(
+                differing_fields(expected, op).is_empty(),
+                "tx_id {}",
+                op.tx_id
+            );
+        }
+    }
+
+    #[test]
+    fn test_edge_cases_are_valid() {
+        for op in edge_cases() {
+            op.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reports_lossy_format() {
+        struct Lossy;
+
+        impl OperationFormat for Lossy {
+            fn name(&self) -> &str {
+                "lossy"
+            }
+
+            fn parse_all(
+                &self,
+                reader: &mut dyn std::io::Read,
+            ) -> crate::Result<HashSet<Operation>> {
+                let mut ops = Format::Bin.parse_all(reader)?;
+                ops = ops
+                    .into_iter()
+                    .map(|mut op| {
+                        op.description.clear();
+                        op
+                    })
+                    .collect();
+                Ok(ops)
+            }
+
+            fn write_all(
+                &self,
+                writer: &mut dyn std::io::Write,
+                operations: &HashSet<Operation>,
+            ) -> crate::Result<()> {
+                Format::Bin.write_all(writer, operations)
+            }
+        }
+
+        let failures = check_round_trip(&Lossy, edge_cases().into_iter().take(1));
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].reason.contains("DESCRIPTION"));
+    }
+}
@@ -0,0 +1,477 @@
+//! Проверки, которые видят только несколько записей вместе
+//!
+//! [`Operation::validate`] смотрит на одну запись; здесь — противоречия между
+//! записями: один TX_ID с разным содержимым или с SUCCESS и FAILURE сразу, время
+//! далеко от остального файла, участники переводов, которых больше нигде нет.
+//! Все группировки — сортировкой, так что [`check`] работает за O(n log n).
+
+use crate::operation::{Operation, OperationStatus, OperationType};
+use crate::validation::Severity;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Пять лет в миллисекундах: время дальше от медианы файла — явно чужое
+pub const DEFAULT_MAX_TIME_FROM_MEDIAN: u64 = 5 * 365 * 86_400_000;
+
+/// Какие проверки [`check`] включены
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyRules {
+    /// Записи с одним TX_ID расходятся в чем-то, кроме статуса
+    pub conflicting_duplicates: bool,
+    /// Один TX_ID и в SUCCESS, и в FAILURE
+    pub contradictory_status: bool,
+    /// Заявленный период файла в миллисекундах; `None` — не проверять
+    pub time_range: Option<RangeInclusive<u64>>,
+    /// Сколько миллисекунд TIMESTAMP может отстоять от медианы файла; `None` — не проверять
+    pub max_time_from_median: Option<u64>,
+    /// У перевода есть участник, который больше не встречается ни в одной записи
+    pub orphan_users: bool,
+}
+
+impl Default for ConsistencyRules {
+    /// Все проверки, кроме заявленного периода: его знает только вызывающий
+    fn default() -> Self {
+        ConsistencyRules {
+            conflicting_duplicates: true,
+            contradictory_status: true,
+            time_range: None,
+            max_time_from_median: Some(DEFAULT_MAX_TIME_FROM_MEDIAN),
+            orphan_users: true,
+        }
+    }
+}
+
+/// Какая проверка сработала
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ConsistencyRule {
+    ConflictingDuplicate,
+    ContradictoryStatus,
+    TimestampOutOfRange,
+    OrphanUser,
+}
+
+impl ConsistencyRule {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConsistencyRule::ConflictingDuplicate => "conflicting-duplicate",
+            ConsistencyRule::ContradictoryStatus => "contradictory-status",
+            ConsistencyRule::TimestampOutOfRange => "timestamp-out-of-range",
+            ConsistencyRule::OrphanUser => "orphan-user",
+        }
+    }
+
+    /// Противоречивые дубликаты — ошибки, остальное только подозрительно
+    pub fn severity(&self) -> Severity {
+        match self {
+            ConsistencyRule::ConflictingDuplicate | ConsistencyRule::ContradictoryStatus => {
+                Severity::Error
+            }
+            ConsistencyRule::TimestampOutOfRange | ConsistencyRule::OrphanUser => Severity::Warning,
+        }
+    }
+}
+
+/// Одна находка
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: ConsistencyRule,
+    pub severity: Severity,
+    /// Затронутые TX_ID
+    pub tx_ids: Vec<u64>,
+    /// Индексы затронутых записей во входном срезе, по возрастанию
+    pub records: Vec<usize>,
+    pub reason: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.severity.as_str(),
+            self.rule.as_str(),
+            self.reason
+        )
+    }
+}
+
+/// Все находки [`check`]: по проверкам в порядке [`ConsistencyRule`],
+/// внутри — по первой затронутой записи
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ConsistencyReport {
+    /// Находок нет
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Есть ли находки с [`Severity::Error`]
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    /// Находки одной проверки
+    pub fn of_rule(&self, rule: ConsistencyRule) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.rule == rule)
+    }
+}
+
+/// По находке на строку
+impl fmt::Display for ConsistencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Проверяет набор записей на противоречия между ними
+///
+/// # Аргументы
+/// * `ops` - Записи в порядке файла, с повторами TX_ID (например, `PartitionOutcome::accepted`)
+/// * `rules` - Какие проверки включены
+pub fn check(ops: &[Operation], rules: &ConsistencyRules) -> ConsistencyReport {
+    let mut report = ConsistencyReport::default();
+    if rules.conflicting_duplicates || rules.contradictory_status {
+        check_duplicates(ops, rules, &mut report.findings);
+    }
+    if rules.time_range.is_some() || rules.max_time_from_median.is_some() {
+        check_timestamps(ops, rules, &mut report.findings);
+    }
+    if rules.orphan_users {
+        check_orphan_users(ops, &mut report.findings);
+    }
+    report
+        .findings
+        .sort_by_key(|finding| (finding.rule, finding.records.first().copied()));
+    report
+}
+
+fn finding(
+    rule: ConsistencyRule,
+    tx_ids: Vec<u64>,
+    records: Vec<usize>,
+    reason: String,
+) -> Finding {
+    Finding {
+        rule,
+        severity: rule.severity(),
+        tx_ids,
+        records,
+        reason,
+    }
+}
+
+/// Поля, по которым сравниваются записи с одним TX_ID (статус — отдельная проверка)
+const COMPARED_FIELDS: [&str; 6] = [
+    "TX_TYPE",
+    "FROM_USER_ID",
+    "TO_USER_ID",
+    "AMOUNT",
+    "TIMESTAMP",
+    "DESCRIPTION",
+];
+
+/// Отличается ли каждое из [`COMPARED_FIELDS`]
+fn field_differences(a: &Operation, b: &Operation) -> [bool; COMPARED_FIELDS.len()] {
+    [
+        a.tx_type != b.tx_type,
+        a.from_user_id != b.from_user_id,
+        a.to_user_id != b.to_user_id,
+        a.amount != b.amount,
+        a.timestamp != b.timestamp,
+        a.description != b.description,
+    ]
+}
+
+/// Группы записей с одним TX_ID: сортировка индексов устойчивая, порядок файла внутри сохраняется
+fn check_duplicates(ops: &[Operation], rules: &ConsistencyRules, findings: &mut Vec<Finding>) {
+    let mut order: Vec<usize> = (0..ops.len()).collect();
+    order.sort_by_key(|&i| ops[i].tx_id);
+
+    for group in order.chunk_by(|&a, &b| ops[a].tx_id == ops[b].tx_id) {
+        if group.len() < 2 {
+            continue;
+        }
+        let tx_id = ops[group[0]].tx_id;
+        let records = group.to_vec();
+
+        if rules.conflicting_duplicates {
+            let first = &ops[group[0]];
+            let mut differ = [false; COMPARED_FIELDS.len()];
+            for &i in &group[1..] {
+                for (d, x) in differ.iter_mut().zip(field_differences(first, &ops[i])) {
+                    *d |= x;
+                }
+            }
+            let differs: Vec<&str> = COMPARED_FIELDS
+                .iter()
+                .zip(differ)
+                .filter(|&(_, d)| d)
+                .map(|(&field, _)| field)
+                .collect();
+            if !differs.is_empty() {
+                findings.push(finding(
+                    ConsistencyRule::ConflictingDuplicate,
+                    vec![tx_id],
+                    records.clone(),
+                    format!(
+                        "tx_id {}: {} records differ in {}",
+                        tx_id,
+                        group.len(),
+                        differs.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if rules.contradictory_status {
+            let has = |status: OperationStatus| group.iter().any(|&i| ops[i].status == status);
+            if has(OperationStatus::Success) && has(OperationStatus::Failure) {
+                findings.push(finding(
+                    ConsistencyRule::ContradictoryStatus,
+                    vec![tx_id],
+                    records,
+                    format!("tx_id {} is both SUCCESS and FAILURE", tx_id),
+                ));
+            }
+        }
+    }
+}
+
+/// Заявленный период и расстояние от медианы
+fn check_timestamps(ops: &[Operation], rules: &ConsistencyRules, findings: &mut Vec<Finding>) {
+    let median = rules.max_time_from_median.and_then(|_| {
+        let mut timestamps: Vec<u64> = ops.iter().map(|op| op.timestamp).collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied()
+    });
+
+    for (i, op) in ops.iter().enumerate() {
+        let reason = match (&rules.time_range, rules.max_time_from_median, median) {
+            (Some(range), _, _) if !range.contains(&op.timestamp) => format!(
+                "tx_id {}: TIMESTAMP {} is outside the claimed range {}..={}",
+                op.tx_id,
+                op.timestamp,
+                range.start(),
+                range.end()
+            ),
+            (_, Some(max), Some(median)) if op.timestamp.abs_diff(median) > max => format!(
+                "tx_id {}: TIMESTAMP {} is {} ms away from the file median {}",
+                op.tx_id,
+                op.timestamp,
+                op.timestamp.abs_diff(median),
+                median
+            ),
+            _ => continue,
+        };
+        findings.push(finding(
+            ConsistencyRule::TimestampOutOfRange,
+            vec![op.tx_id],
+            vec![i],
+            reason,
+        ));
+    }
+}
+
+/// Пользователи, встреченные ровно в одной записи, и эта запись — перевод
+fn check_orphan_users(ops: &[Operation], findings: &mut Vec<Finding>) {
+    // (пользователь, запись); перевод самому себе — одно появление
+    let mut appearances: Vec<(u64, usize)> = Vec::with_capacity(ops.len() * 2);
+    for (i, op) in ops.iter().enumerate() {
+        for user_id in [op.from_user_id, op.to_user_id] {
+            if user_id != 0 && appearances.last() != Some(&(user_id, i)) {
+                appearances.push((user_id, i));
+            }
+        }
+    }
+    appearances.sort_unstable();
+
+    let mut orphans: Vec<(usize, u64)> = appearances
+        .chunk_by(|a, b| a.0 == b.0)
+        .filter(|group| group.len() == 1)
+        .map(|group| (group[0].1, group[0].0))
+        .filter(|&(i, _)| ops[i].tx_type == OperationType::Transfer)
+        .collect();
+    orphans.sort_unstable();
+
+    for group in orphans.chunk_by(|a, b| a.0 == b.0) {
+        let i = group[0].0;
+        let users: Vec<String> = group.iter().map(|(_, user)| user.to_string()).collect();
+        findings.push(finding(
+            ConsistencyRule::OrphanUser,
+            vec![ops[i].tx_id],
+            vec![i],
+            format!(
+                "tx_id {}: transfer user {} appears in no other record",
+                ops[i].tx_id,
+                users.join(", ")
+            ),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::OperationBuilder;
+
+    const T: u64 = 1_633_036_800_000;
+
+    fn op(tx_id: u64, tx_type: OperationType, from: u64, to: u64, amount: i64) -> Operation {
+        OperationBuilder::new(tx_type, from, to, amount)
+            .tx_id(tx_id)
+            .timestamp(T + tx_id * 1000)
+            .status(OperationStatus::Success)
+            .build()
+            .unwrap()
+    }
+
+    /// Чистый набор: пользователи 1 и 2 встречаются по нескольку раз
+    fn clean() -> Vec<Operation> {
+        vec![
+            op(1, OperationType::Deposit, 0, 1, 500),
+            op(2, OperationType::Deposit, 0, 2, 100),
+            op(3, OperationType::Transfer, 1, 2, 200),
+            op(4, OperationType::Withdrawal, 2, 0, 50),
+        ]
+    }
+
+    fn only(report: &ConsistencyReport, rule: ConsistencyRule) -> &Finding {
+        assert_eq!(report.findings.len(), 1, "{}", report);
+        let finding = &report.findings[0];
+        assert_eq!(finding.rule, rule);
+        assert_eq!(finding.severity, rule.severity());
+        finding
+    }
+
+    #[test]
+    fn test_clean_set_has_no_findings() {
+        let mut ops = clean();
+        // Точный повтор и PENDING -> SUCCESS — не противоречия
+        ops.push(ops[2].clone());
+        let mut pending = ops[3].clone();
+        pending.status = OperationStatus::Pending;
+        ops.push(pending);
+        assert!(check(&ops, &ConsistencyRules::default()).is_clean());
+        assert!(check(&[], &ConsistencyRules::default()).is_clean());
+    }
+
+    #[test]
+    fn test_conflicting_duplicate() {
+        let mut ops = clean();
+        let mut other = ops[2].clone();
+        other.amount = 999;
+        other.timestamp += 1;
+        ops.push(other);
+
+        let report = check(&ops, &ConsistencyRules::default());
+        let finding = only(&report, ConsistencyRule::ConflictingDuplicate);
+        assert_eq!(finding.tx_ids, vec![3]);
+        assert_eq!(finding.records, vec![2, 4]);
+        assert!(finding.reason.contains("AMOUNT, TIMESTAMP"), "{}", finding);
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn test_contradictory_status() {
+        let mut ops = clean();
+        let mut failed = ops[1].clone();
+        failed.status = OperationStatus::Failure;
+        ops.insert(0, failed);
+
+        let report = check(&ops, &ConsistencyRules::default());
+        let finding = only(&report, ConsistencyRule::ContradictoryStatus);
+        assert_eq!(finding.tx_ids, vec![2]);
+        assert_eq!(finding.records, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_timestamp_out_of_range() {
+        // Секунды вместо миллисекунд — далеко от медианы
+        let mut ops = clean();
+        ops.push(Operation {
+            timestamp: T / 1000,
+            ..op(5, OperationType::Deposit, 0, 1, 10)
+        });
+        let report = check(&ops, &ConsistencyRules::default());
+        assert_eq!(
+            only(&report, ConsistencyRule::TimestampOutOfRange).tx_ids,
+            vec![5]
+        );
+        assert!(!report.has_errors());
+
+        // Заявленный период ловит то, что близко к медиане
+        let rules = ConsistencyRules {
+            time_range: Some(T..=T + 3000),
+            ..ConsistencyRules::default()
+        };
+        let report = check(&clean(), &rules);
+        let finding = only(&report, ConsistencyRule::TimestampOutOfRange);
+        assert_eq!(finding.records, vec![3]);
+        assert!(finding.reason.contains("claimed range"), "{}", finding);
+    }
+
+    #[test]
+    fn test_orphan_user() {
+        let mut ops = clean();
+        ops.push(op(5, OperationType::Transfer, 1, 77, 10));
+        // Одиночный пользователь пополнения — не перевод, не находка
+        ops.push(op(6, OperationType::Deposit, 0, 88, 10));
+
+        let report = check(&ops, &ConsistencyRules::default());
+        let finding = only(&report, ConsistencyRule::OrphanUser);
+        assert_eq!(finding.tx_ids, vec![5]);
+        assert!(finding.reason.contains("user 77"), "{}", finding);
+    }
+
+    #[test]
+    fn test_rules_toggle_individually() {
+        let mut ops = clean();
+        let mut failed = ops[2].clone();
+        failed.status = OperationStatus::Failure;
+        failed.amount += 1;
+        ops.push(failed);
+        ops.push(Operation {
+            timestamp: 1,
+            ..op(5, OperationType::Transfer, 1, 77, 10)
+        });
+
+        let all = check(&ops, &ConsistencyRules::default());
+        let rules: Vec<ConsistencyRule> = all.findings.iter().map(|f| f.rule).collect();
+        assert_eq!(
+            rules,
+            vec![
+                ConsistencyRule::ConflictingDuplicate,
+                ConsistencyRule::ContradictoryStatus,
+                ConsistencyRule::TimestampOutOfRange,
+                ConsistencyRule::OrphanUser,
+            ]
+        );
+
+        let none = ConsistencyRules {
+            conflicting_duplicates: false,
+            contradictory_status: false,
+            time_range: None,
+            max_time_from_median: None,
+            orphan_users: false,
+        };
+        assert!(check(&ops, &none).is_clean());
+        let orphans_only = ConsistencyRules {
+            orphan_users: true,
+            ..none
+        };
+        assert_eq!(check(&ops, &orphans_only).findings.len(), 1);
+    }
+}
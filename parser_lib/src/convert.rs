@@ -0,0 +1,157 @@
+use crate::error::Result;
+use crate::format::{Format, OperationFormat};
+use crate::operation::Operation;
+use crate::options::ParseOptions;
+use crate::report::per_sec;
+use crate::transform::Pipeline;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Итоги конвертации
+#[derive(Debug, Clone, Default)]
+pub struct ConvertStats {
+    /// Сколько операций записано
+    pub records: usize,
+    /// Сколько операций выбросили шаги [`Pipeline`]
+    pub dropped: usize,
+    /// Сколько байт прочитано из входа
+    pub bytes: u64,
+    /// Сколько длилась конвертация целиком, с записью
+    pub duration: Duration,
+}
+
+impl ConvertStats {
+    /// Записанных операций в секунду
+    pub fn records_per_sec(&self) -> f64 {
+        per_sec(self.records, self.duration)
+    }
+}
+
+/// Читаем операции в одном формате и пишем в другом
+pub fn convert<R: Read, W: Write>(
+    reader: R,
+    input: Format,
+    writer: W,
+    output: Format,
+) -> Result<ConvertStats> {
+    convert_with_options(reader, input, writer, output, &ParseOptions::default())
+}
+
+/// То же, что [`convert`], но с настройками разбора (например, токеном отмены)
+pub fn convert_with_options<R: Read, W: Write>(
+    reader: R,
+    input: Format,
+    writer: W,
+    output: Format,
+    options: &ParseOptions,
+) -> Result<ConvertStats> {
+    convert_with_pipeline(reader, input, writer, output, options, &Pipeline::new())
+}
+
+/// Конвертация с преобразованием каждой записи на лету
+///
+/// Записи проходят `pipeline` по мере разбора, до схлопывания повторов TX_ID
+/// (остается первая уцелевшая запись). Ошибка шага прерывает конвертацию,
+/// в `writer` при этом ничего не пишется.
+pub fn convert_with_pipeline<R: Read, W: Write>(
+    reader: R,
+    input: Format,
+    mut writer: W,
+    output: Format,
+    options: &ParseOptions,
+    pipeline: &Pipeline,
+) -> Result<ConvertStats> {
+    let started = Instant::now();
+    let mut operations: HashSet<Operation> = HashSet::new();
+    let mut dropped = 0;
+    let report = input.parse_each(reader, options, |operation, _| {
+        match pipeline.apply(operation)? {
+            Some(operation) => {
+                operations.insert(operation);
+            }
+            None => dropped += 1,
+        }
+        Ok(())
+    })?;
+
+    // Между чтением и записью тоже проверяем: разбор мог закончиться уже после отмены
+    options.check_cancelled()?;
+    output.write_all(&mut writer, &operations)?;
+
+    Ok(ConvertStats {
+        records: operations.len(),
+        dropped,
+        bytes: report.bytes,
+        duration: started.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::{Operation, OperationStatus};
+    use crate::text_format;
+    use crate::transform::RedactDescription;
+
+    #[test]
+    fn test_convert_text_to_csv() {
+        let op = Operation::withdrawal(3, 500)
+            .tx_id(7)
+            .timestamp(1633036800000)
+            .description("ATM, \"Lenina\"")
+            .build()
+            .unwrap();
+        let operations: HashSet<Operation> = [op.clone()].into_iter().collect();
+
+        let mut text = Vec::new();
+        text_format::write_all(&mut text, &operations).unwrap();
+
+        let mut csv = Vec::new();
+        let stats = convert(text.as_slice(), Format::Txt, &mut csv, Format::Csv).unwrap();
+
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.bytes, text.len() as u64);
+        assert!(stats.records_per_sec() >= 0.0);
+        let parsed = Format::Csv.parse_all(&mut csv.as_slice()).unwrap();
+        assert_eq!(parsed.get(&op).unwrap().description, op.description);
+    }
+
+    #[test]
+    fn test_convert_with_pipeline() {
+        let ops: HashSet<Operation> = (1..=3)
+            .map(|tx_id| {
+                Operation::deposit(5, tx_id as i64 * 100)
+                    .tx_id(tx_id)
+                    .timestamp(1633036800000)
+                    .status(OperationStatus::Success)
+                    .description("Salary")
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut csv = Vec::new();
+        Format::Csv.write_all(&mut csv, &ops).unwrap();
+
+        let pipeline = Pipeline::new()
+            .then(crate::OperationFilter::new().min_amount(200))
+            .then(RedactDescription {
+                replacement: "***".to_string(),
+            });
+        let mut bin = Vec::new();
+        let stats = convert_with_pipeline(
+            csv.as_slice(),
+            Format::Csv,
+            &mut bin,
+            Format::Bin,
+            &ParseOptions::default(),
+            &pipeline,
+        )
+        .unwrap();
+
+        assert_eq!((stats.records, stats.dropped), (2, 1));
+        let parsed = Format::Bin.parse_all(&mut bin.as_slice()).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.iter().all(|op| op.description == "***"));
+    }
+}
@@ -1,53 +1,994 @@
-use crate::error::{ParseError, Result};
-use crate::operation::{Operation, OperationStatus, OperationType};
-use std::collections::HashSet;
-use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(feature = "parallel")]
+use crate::dedup::Deduplicator;
+use crate::dedup::{DedupOutcome, DuplicatePolicy};
+use crate::error::{Location, ParseError, Result};
+use crate::footer::Footer;
+use crate::format::Format;
+use crate::format::skip_bom;
+#[cfg(feature = "async")]
+use crate::format::skip_bom_async;
+use crate::migration::{self, SchemaVersion, Upgraded};
+use crate::operation::{
+    FIELD_NAMES, FullOperation, Operation, OperationStatus, OperationType, escape_description,
+};
+use crate::options::{CsvOptions, ParseOptions, WriteOptions};
+use crate::partition::ParseOutcome;
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{Meter, ParseReport};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::iter::FusedIterator;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
 
-const HEADER: &str = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION";
+const FOOTER_TAG: &str = "#TOTAL";
+const VERSION_PRAGMA: &str = "#VERSION:";
 
-/// Нофинг интерестинг, ходим по строкам, парсим
+/// Нофинг интерестинг, ходим по записям, парсим
 pub fn parse_all<R: Read>(reader: R) -> Result<HashSet<Operation>> {
-    let buf_reader = BufReader::new(reader);
-    let mut lines = buf_reader.lines();
+    parse_all_with_options(reader, &ParseOptions::default())
+}
 
-    let header = lines.next().ok_or(ParseError::UnexpectedEof)??;
+/// Разбор с пропуском битых записей, см. [`crate::parse_all_lossy`]
+pub fn parse_all_lossy<R: Read>(reader: R, options: &ParseOptions) -> Result<ParseOutcome> {
+    crate::partition::parse_all_lossy(reader, Format::Csv, options)
+}
 
-    if header != HEADER {
-        return Err(ParseError::InvalidFormat(format!(
-            "Invalid CSV header. Expected: {}",
-            HEADER
-        )));
+/// То же, что [`parse_all`], но с настройками (например, токеном отмены)
+pub fn parse_all_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    Format::Csv.parse_all_with_options(reader, options)
+}
+
+/// То же, что [`parse_all_with_options`], плюс отчет с предупреждениями
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    Format::Csv.parse_all_with_report(reader, options)
+}
+
+/// Читает все операции в порядке файла, см. [`Format::parse_all_vec`]
+pub fn parse_all_vec<R: Read>(reader: R) -> Result<Vec<Operation>> {
+    Format::Csv.parse_all_vec(reader)
+}
+
+/// То же, что [`parse_all_vec`], но с настройками разбора
+pub fn parse_all_vec_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Operation>> {
+    Format::Csv.parse_all_vec_with_options(reader, options)
+}
+
+/// Разбор с политикой для повторов TX_ID, см. [`Format::parse_all_with_policy`]
+pub fn parse_all_with_policy<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    Format::Csv.parse_all_with_policy(reader, options, policy)
+}
+
+/// Число повторов каждой записи, см. [`Format::parse_all_counted`]
+pub fn parse_all_counted<R: Read>(reader: R) -> Result<HashMap<FullOperation, u64>> {
+    Format::Csv.parse_all_counted(reader)
+}
+
+/// Записи вместе с происхождением, см. [`Format::parse_all_with_provenance`]
+pub fn parse_all_with_provenance<R: Read>(
+    reader: R,
+    source: Option<&str>,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    Format::Csv.parse_all_with_provenance(reader, source, options)
+}
+
+/// Читает одну запись с текущей позиции потока (без заголовка)
+///
+/// Поток должен стоять на начале записи, например по [`Provenance::byte_offset`].
+/// Пустые строки перед записью пропускаются; EOF дает [`ParseError::UnexpectedEof`].
+/// Заголовка здесь не видно, поэтому колонки ожидаются в каноническом порядке.
+pub fn parse_one<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Operation> {
+    let mut record = String::new();
+    loop {
+        let (lines, _) = read_record(reader, &mut record)?;
+        if lines == 0 {
+            return Err(ParseError::UnexpectedEof);
+        }
+        if !record.trim().is_empty() {
+            break;
+        }
     }
 
-    let mut operations = HashSet::new();
+    let operation = parse_line(
+        &record,
+        &Columns::canonical(options.csv.delimiter),
+        options,
+        &mut ParseReport::default(),
+    )?
+    .operation;
+    operation.validate()?;
+    Ok(operation)
+}
+
+/// Потоковый разбор: отдает записи по одной, ошибка из `on_operation` прерывает разбор
+pub(crate) fn parse_each<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let meter = Meter::start("csv", options);
+    let report = parse_records(meter.reader(reader), options, |operation, position| {
+        meter.record();
+        on_operation(operation, position)
+    })?;
+    Ok(meter.finish(report))
+}
+
+/// Сам разбор для [`parse_each`], без замеров
+fn parse_records<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let mut iter = iter_operations_with_options(reader, options);
+    while let Some(record) = iter.next_record() {
+        let (operation, position) = record?;
+        on_operation(operation, position)?;
+    }
+
+    Ok(iter.report)
+}
+
+/// Ленивый разбор: операции по одной в порядке файла, без накопления в памяти
+///
+/// Заголовок читается и проверяется при первом `next()`. После первой ошибки
+/// (в том числе несошедшегося футера в конце) итератор больше ничего не отдает.
+pub fn iter_operations<R: Read>(reader: R) -> CsvIter<R> {
+    iter_operations_with_options(reader, &ParseOptions::default())
+}
 
-    for (line_num, line) in lines.enumerate() {
-        let line = line?;
+/// То же, что [`iter_operations`], но с настройками разбора
+pub fn iter_operations_with_options<R: Read>(reader: R, options: &ParseOptions) -> CsvIter<R> {
+    CsvIter {
+        reader: BufReader::new(reader),
+        options: options.clone(),
+        report: ParseReport::default(),
+        body: None,
+        done: false,
+    }
+}
+
+/// Итератор из [`iter_operations`]
+pub struct CsvIter<R> {
+    reader: BufReader<R>,
+    options: ParseOptions,
+    report: ParseReport,
+    /// `None` до чтения заголовка
+    body: Option<Body>,
+    done: bool,
+}
+
+impl<R: Read> CsvIter<R> {
+    /// Отчет о уже прочитанных записях (счетчик, предупреждения, версия схемы)
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    /// Следующая запись вместе с ее началом в потоке
+    fn next_record(&mut self) -> Option<Result<(Operation, RecordPosition)>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
 
-        if line.trim().is_empty() {
+    fn read_record(&mut self) -> Result<Option<(Operation, RecordPosition)>> {
+        let body = match &mut self.body {
+            Some(body) => body,
+            None => {
+                let header = read_header(&mut self.reader, &self.options)?;
+                header.version.check_supported(&self.options)?;
+                self.report.schema_version = header.version;
+                self.body
+                    .insert(Body::new(header.columns, header.lines, header.bytes))
+            }
+        };
+
+        let record = body.next_record(&mut self.reader, &self.options, &mut self.report)?;
+        if record.is_none() {
+            Footer::verify(self.report.footer, body.totals)?;
+        }
+        Ok(record)
+    }
+}
+
+impl<R: Read> Iterator for CsvIter<R> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+            .map(|result| result.map(|(operation, _)| operation))
+    }
+}
+
+impl<R: Read> FusedIterator for CsvIter<R> {}
+
+/// Потоковый разбор для карантина: каждая запись вместе с ее сырыми байтами
+///
+/// Ошибка разбора записи уходит в `on_record`, а не прерывает чтение;
+/// прерывают только заголовок, ввод-вывод и ошибки из `on_record`.
+/// Футер `#TOTAL` пропускается: после отбраковки итоги все равно не сойдутся.
+pub(crate) fn parse_each_raw<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let mut buf_reader = BufReader::new(reader);
+    let header = read_header(&mut buf_reader, options)?;
+    header.version.check_supported(options)?;
+
+    let mut report = ParseReport::default();
+    let mut line_num = header.lines;
+    let mut byte_offset = header.bytes;
+    let mut record = String::new();
+
+    loop {
+        options.check_cancelled()?;
+
+        let (lines, bytes) = read_record(&mut buf_reader, &mut record)?;
+        if lines == 0 {
+            break;
+        }
+        let position = RecordPosition {
+            line: Some(line_num as u64 + 1),
+            byte_offset: Some(byte_offset),
+        };
+        line_num += lines;
+        byte_offset += bytes;
+
+        if record.trim().is_empty() || strip_footer(&record, options.csv.delimiter).is_some() {
             continue;
         }
 
-        let operation: Operation = parse_line(&line)
-            .map_err(|e| ParseError::InvalidFormat(format!("Line {}: {}", line_num + 2, e)))?;
+        let result = parse_line(&record, &header.columns, options, &mut report)
+            .map(|upgraded| upgraded.operation);
+        let mut raw = record.clone().into_bytes();
+        raw.push(b'\n');
+        on_record(result, raw, position)?;
+    }
+
+    Ok(())
+}
+
+/// Асинхронный [`parse_all_with_options`]
+///
+/// Читает по записи через `read_line` (запись с переводом строки в кавычках
+/// занимает несколько строк), а разбирает их тот же код, что и синхронный
+/// разбор: ошибки, их места и сверка футера совпадают.
+#[cfg(feature = "async")]
+pub async fn parse_all_async<R: AsyncRead + Unpin>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    let mut reader = tokio::io::BufReader::new(reader);
+    let header = read_header_async(&mut reader, options).await?;
+    header.version.check_supported(options)?;
 
-        operation.validate()?;
-        operations.insert(operation);
+    let mut report = ParseReport::default();
+    let mut body = Body::new(header.columns, header.lines, header.bytes);
+    let mut operations = HashSet::new();
+    loop {
+        options.check_cancelled()?;
+
+        let (lines, bytes) = read_record_async(&mut reader, &mut body.record).await?;
+        if lines == 0 {
+            break;
+        }
+        if let Some((operation, _)) = body.accept(lines, bytes, options, &mut report)? {
+            operations.insert(operation);
+        }
     }
+    Footer::verify(report.footer, body.totals)?;
 
     Ok(operations)
 }
 
-fn parse_line(line: &str) -> Result<Operation> {
-    let parts: Vec<&str> = split_csv_line(line);
+/// Параллельный разбор потока: файл не обязан помещаться в память
+///
+/// Записи читаются по порядку блоками в несколько мегабайт (по границам
+/// записей), каждый блок режется на куски и парсится в пуле rayon, как в
+/// [`parse_all_parallel_from_slice`]. Пока пул разбирает блок, чтение стоит,
+/// так что в памяти не больше одного блока. Результат и первая ошибка
+/// (с номером строки в файле) совпадают с [`parse_all`]. Разделитель, отмена
+/// и прочие настройки — в [`parse_all_parallel_with_options`].
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel<R: Read>(reader: R) -> Result<HashSet<Operation>> {
+    parse_all_parallel_with_options(reader, &ParseOptions::default(), DuplicatePolicy::KeepFirst)
+        .map(|outcome| outcome.operations)
+}
+
+/// То же, что [`parse_all_parallel`], но с настройками разбора и политикой для повторов TX_ID
+///
+/// Результат и первая ошибка совпадают с [`parse_all_with_policy`]: куски
+/// сливаются в порядке файла, так что "первая" и "последняя" запись те же.
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    let block_size = PARALLEL_CHUNK * rayon::current_num_threads() * 4;
+    parse_all_parallel_blocks(reader, block_size, PARALLEL_CHUNK, options, policy)
+}
+
+/// Параллельный разбор уже загруженного в память (или mmap) CSV
+///
+/// Тело режется на куски по границам записей (перевод строки вне кавычек),
+/// куски парсятся в пуле rayon и сливаются по порядку. Результат и первая
+/// ошибка совпадают с [`parse_all`]: при повторе TX_ID побеждает запись,
+/// встретившаяся в файле раньше.
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel_from_slice(bytes: &[u8]) -> Result<HashSet<Operation>> {
+    parse_all_parallel_from_slice_with_options(
+        bytes,
+        &ParseOptions::default(),
+        DuplicatePolicy::KeepFirst,
+    )
+    .map(|outcome| outcome.operations)
+}
+
+/// То же, что [`parse_all_parallel_from_slice`], но с настройками разбора и политикой для повторов TX_ID
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel_from_slice_with_options(
+    bytes: &[u8],
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    let chunk_size = (bytes.len() / (rayon::current_num_threads() * 4)).max(PARALLEL_CHUNK);
+    parse_all_parallel_chunked(bytes, chunk_size, options, policy)
+}
+
+/// Меньше кусок не делаем: накладные расходы пула съедят выигрыш
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK: usize = 64 * 1024;
+
+/// Результат куска: операции, их итоги и футер `#TOTAL`, если он попал в этот кусок
+#[cfg(feature = "parallel")]
+type ParsedChunk = (Vec<Operation>, Footer, Option<Footer>);
+
+#[cfg(feature = "parallel")]
+fn parse_all_parallel_chunked(
+    bytes: &[u8],
+    chunk_size: usize,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    let mut reader = bytes;
+    let header = read_header(&mut reader, options)?;
+    let body = reader;
+
+    header.version.check_supported(options)?;
+    let mut merge = ChunkMerge::new(policy);
+    for chunk in parse_chunks(body, chunk_size, &header, 0, 0, options) {
+        merge.push(chunk)?;
+    }
+    merge.finish()
+}
+
+#[cfg(feature = "parallel")]
+fn parse_all_parallel_blocks<R: Read>(
+    reader: R,
+    block_size: usize,
+    chunk_size: usize,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    let mut reader = BufReader::new(reader);
+    let header = read_header(&mut reader, options)?;
+    header.version.check_supported(options)?;
+
+    let mut merge = ChunkMerge::new(policy);
+    let mut block = Vec::new();
+    let mut lines = 0;
+    let mut bytes = 0;
+    loop {
+        options.check_cancelled()?;
+        block.clear();
+        let block_lines = read_block(&mut reader, &mut block, block_size)?;
+        if block.is_empty() {
+            break;
+        }
+        for chunk in parse_chunks(&block, chunk_size, &header, lines, bytes, options) {
+            merge.push(chunk)?;
+        }
+        lines += block_lines;
+        bytes += block.len() as u64;
+    }
+    merge.finish()
+}
+
+/// Дочитывает в `block` целые строки, пока он не дорастет до `block_size` байт
+/// и последняя строка не закроет поле в кавычках. Возвращает число строк
+#[cfg(feature = "parallel")]
+fn read_block<R: BufRead>(reader: &mut R, block: &mut Vec<u8>, block_size: usize) -> Result<usize> {
+    let mut lines = 0;
+    let mut in_quotes = false;
+    loop {
+        let start = block.len();
+        if reader.read_until(b'\n', block)? == 0 {
+            break;
+        }
+        lines += 1;
+        in_quotes ^= block[start..].iter().filter(|&&b| b == b'"').count() % 2 == 1;
+        if block.len() >= block_size && !in_quotes {
+            break;
+        }
+    }
+    Ok(lines)
+}
+
+/// Парсит `body` кусками в пуле rayon; результаты — в порядке файла
+///
+/// `lines` и `bytes` — сколько строк и байт тела (после заголовка) идет до `body`.
+#[cfg(feature = "parallel")]
+fn parse_chunks(
+    body: &[u8],
+    chunk_size: usize,
+    header: &Header,
+    lines: usize,
+    bytes: u64,
+    options: &ParseOptions,
+) -> Vec<Result<ParsedChunk>> {
+    use rayon::prelude::*;
+
+    split_chunks(body, chunk_size)
+        .par_iter()
+        .map(|&(start, end, lines_before)| {
+            let mut chunk = &body[start..end];
+            let mut operations = Vec::new();
+            let mut report = ParseReport::default();
+            let totals = parse_body(
+                &mut chunk,
+                &header.columns,
+                header.lines + lines + lines_before,
+                header.bytes + bytes + start as u64,
+                options,
+                &mut report,
+                |operation, _| {
+                    operations.push(operation);
+                    Ok(())
+                },
+            )?;
+            Ok((operations, totals, report.footer))
+        })
+        .collect()
+}
+
+/// Сливает куски по порядку: первая по файлу ошибка побеждает, какой бы поток ее ни нашел
+#[cfg(feature = "parallel")]
+struct ChunkMerge {
+    operations: Deduplicator,
+    totals: Footer,
+    declared: Option<Footer>,
+}
+
+#[cfg(feature = "parallel")]
+impl ChunkMerge {
+    fn new(policy: DuplicatePolicy) -> Self {
+        ChunkMerge {
+            operations: Deduplicator::new(policy),
+            totals: Footer::default(),
+            declared: None,
+        }
+    }
+
+    fn push(&mut self, chunk: Result<ParsedChunk>) -> Result<()> {
+        // Кусок считает записи с 0: номер в ошибке сдвигаем на записи кусков до него
+        let (chunk, chunk_totals, chunk_footer) = chunk.map_err(|mut e| {
+            if let ParseError::WithContext { location, .. } = &mut e
+                && let Some(index) = &mut location.record_index
+            {
+                *index += self.totals.records;
+            }
+            e
+        })?;
+        if self.declared.is_some() && chunk_totals.records > 0 {
+            return Err(ParseError::InvalidFormat(
+                "Records after the #TOTAL footer".to_string(),
+            ));
+        }
+        self.totals.merge(chunk_totals);
+        self.declared = self.declared.or(chunk_footer);
+
+        for operation in chunk {
+            self.operations.push(operation)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<DedupOutcome> {
+        Footer::verify(self.declared, self.totals)?;
+        Ok(self.operations.finish())
+    }
+}
+
+/// Режет тело на куски примерно по `chunk_size` байт, только по переводу строки вне кавычек.
+/// Возвращает (начало, конец, число строк до начала куска)
+#[cfg(feature = "parallel")]
+fn split_chunks(body: &[u8], chunk_size: usize) -> Vec<(usize, usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut lines_before = 0;
+    let mut lines_in_chunk = 0;
+    let mut in_quotes = false;
+
+    for (i, &b) in body.iter().enumerate() {
+        match b {
+            b'"' => in_quotes = !in_quotes,
+            b'\n' => {
+                lines_in_chunk += 1;
+                if !in_quotes && i + 1 - start >= chunk_size {
+                    chunks.push((start, i + 1, lines_before));
+                    start = i + 1;
+                    lines_before += lines_in_chunk;
+                    lines_in_chunk = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if start < body.len() {
+        chunks.push((start, body.len(), lines_before));
+    }
+
+    chunks
+}
+
+/// Что было до первой записи: прагма `#VERSION` и заголовок
+struct Header {
+    /// Версия из прагмы или [`SchemaVersion::V1`]
+    version: SchemaVersion,
+    /// Где в строке данных лежит каждое поле
+    columns: Columns,
+    /// Сколько строк заняли прагма и заголовок
+    lines: usize,
+    /// Сколько байт заняли прагма и заголовок с переводами строк
+    bytes: u64,
+}
+
+/// Пропускает BOM, читает необязательную прагму `#VERSION: n` и разбирает заголовок
+fn read_header<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Header> {
+    options.csv.check()?;
+    let mut header = String::new();
+
+    let skipped = skip_bom(reader)?;
+    let (mut lines, mut bytes) = read_record(reader, &mut header)?;
+    bytes += skipped;
+    if lines == 0 {
+        return Err(ParseError::UnexpectedEof);
+    }
+
+    let version = pragma_version(&header)?;
+    if version.is_some() {
+        let (header_lines, header_bytes) = read_record(reader, &mut header)?;
+        if header_lines == 0 {
+            return Err(ParseError::UnexpectedEof);
+        }
+        lines += header_lines;
+        bytes += header_bytes;
+    }
+
+    Header::new(version, &header, lines, bytes, options)
+}
+
+/// [`read_header`] для асинхронного потока
+#[cfg(feature = "async")]
+async fn read_header_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> Result<Header> {
+    options.csv.check()?;
+    let mut header = String::new();
+
+    let skipped = skip_bom_async(reader).await?;
+    let (mut lines, mut bytes) = read_record_async(reader, &mut header).await?;
+    bytes += skipped;
+    if lines == 0 {
+        return Err(ParseError::UnexpectedEof);
+    }
+
+    let version = pragma_version(&header)?;
+    if version.is_some() {
+        let (header_lines, header_bytes) = read_record_async(reader, &mut header).await?;
+        if header_lines == 0 {
+            return Err(ParseError::UnexpectedEof);
+        }
+        lines += header_lines;
+        bytes += header_bytes;
+    }
+
+    Header::new(version, &header, lines, bytes, options)
+}
+
+/// Версия из прагмы `#VERSION: n`, если первая строка — она; заголовок тогда в следующей
+fn pragma_version(first: &str) -> Result<Option<SchemaVersion>> {
+    first
+        .strip_prefix(VERSION_PRAGMA)
+        .map(SchemaVersion::parse_pragma)
+        .transpose()
+}
+
+impl Header {
+    fn new(
+        version: Option<SchemaVersion>,
+        header: &str,
+        lines: usize,
+        bytes: u64,
+        options: &ParseOptions,
+    ) -> Result<Self> {
+        let version = version.unwrap_or(SchemaVersion::V1);
+        Ok(Header {
+            version,
+            columns: Columns::from_header(header, version, lines, options)?,
+            lines,
+            bytes,
+        })
+    }
+}
+
+/// Раскладка строки данных по заголовку файла: разделитель и позиции полей [`Operation`]
+///
+/// Колонки могут идти в любом порядке и в любом регистре; лишние колонки
+/// пропускаются.
+#[derive(Clone)]
+struct Columns {
+    delimiter: char,
+    /// Индекс колонки для каждого поля из [`FIELD_NAMES`]
+    index: [usize; 8],
+    /// Сколько всего колонок в заголовке
+    width: usize,
+    /// Позиции и имена колонок не из [`FIELD_NAMES`]; их значения уходят в [`Upgraded::extras`]
+    extra: Vec<(usize, String)>,
+    /// Версия схемы файла, по ней выбирается хук миграции
+    version: SchemaVersion,
+}
+
+impl Columns {
+    /// Порядок [`FIELD_NAMES`]
+    fn canonical(delimiter: char) -> Self {
+        Columns {
+            delimiter,
+            index: std::array::from_fn(|i| i),
+            width: FIELD_NAMES.len(),
+            extra: Vec::new(),
+            version: SchemaVersion::V1,
+        }
+    }
+
+    /// # Аргументы
+    /// * `line` - Номер строки заголовка в файле, для ошибок
+    /// * `options` - Разделитель и `strict`: со `strict` лишняя колонка — ошибка
+    fn from_header(
+        header: &str,
+        version: SchemaVersion,
+        line: usize,
+        options: &ParseOptions,
+    ) -> Result<Self> {
+        let delimiter = options.csv.delimiter;
+        let mut index = [None; 8];
+        let mut extra = Vec::new();
+        let names: Vec<_> = CsvFields::new(header, delimiter).collect();
+
+        for (position, name) in names.iter().enumerate() {
+            let name = name.trim();
+            let Some(field) = FIELD_NAMES
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(name))
+            else {
+                if options.strict {
+                    return Err(ParseError::InvalidField {
+                        field: name.to_string(),
+                        reason: "unknown CSV column".to_string(),
+                    }
+                    .at(Location {
+                        line: Some(line as u64),
+                        ..Location::default()
+                    }));
+                }
+                extra.push((position, name.to_string()));
+                continue;
+            };
+            if index[field].is_some() {
+                return Err(ParseError::InvalidFormat(format!(
+                    "Duplicate CSV column {}",
+                    FIELD_NAMES[field]
+                )));
+            }
+            index[field] = Some(position);
+        }
+
+        let missing: Vec<&str> = FIELD_NAMES
+            .iter()
+            .zip(&index)
+            .filter(|(_, position)| position.is_none())
+            .map(|(name, _)| *name)
+            .collect();
+        if !missing.is_empty() {
+            return Err(ParseError::InvalidFormat(format!(
+                "Invalid CSV header: missing columns {}",
+                missing.join(", ")
+            )));
+        }
+
+        Ok(Columns {
+            delimiter,
+            index: index.map(|position| position.unwrap_or_default()),
+            width: names.len(),
+            extra,
+            version,
+        })
+    }
+}
+
+/// Парсит кусок записей для параллельного разбора; `line_num` и `byte_offset` — сколько строк
+/// и байт файла уже прочитано
+///
+/// Футер `#TOTAL` кладет в `report.footer`, но не сверяет: кусок файла
+/// при параллельном разборе не знает итогов целиком.
+///
+/// # Возвращает
+/// Итоги по прочитанным записям
+#[cfg(feature = "parallel")]
+fn parse_body<R: BufRead>(
+    reader: &mut R,
+    columns: &Columns,
+    line_num: usize,
+    byte_offset: u64,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<Footer> {
+    let mut body = Body::new(columns.clone(), line_num, byte_offset);
+    while let Some((operation, position)) = body.next_record(reader, options, report)? {
+        on_operation(operation, position)?;
+    }
+
+    Ok(body.totals)
+}
+
+/// Где мы в теле CSV после заголовка и что уже насчитали
+struct Body {
+    columns: Columns,
+    line_num: usize,
+    byte_offset: u64,
+    record: String,
+    totals: Footer,
+}
+
+impl Body {
+    fn new(columns: Columns, line_num: usize, byte_offset: u64) -> Self {
+        Body {
+            columns,
+            line_num,
+            byte_offset,
+            record: String::new(),
+            totals: Footer::default(),
+        }
+    }
+
+    /// Следующая запись; футер `#TOTAL` кладется в `report`, а не отдается.
+    /// `None` — конец потока
+    fn next_record<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        options: &ParseOptions,
+        report: &mut ParseReport,
+    ) -> Result<Option<(Operation, RecordPosition)>> {
+        loop {
+            options.check_cancelled()?;
+
+            let (lines, bytes) = read_record(reader, &mut self.record)?;
+            if lines == 0 {
+                return Ok(None);
+            }
+            if let Some(record) = self.accept(lines, bytes, options, report)? {
+                return Ok(Some(record));
+            }
+        }
+    }
+
+    /// Разбирает запись, уже прочитанную в `self.record` из `lines` строк и `bytes` байт.
+    /// `None` — пустая строка или футер
+    fn accept(
+        &mut self,
+        lines: usize,
+        bytes: u64,
+        options: &ParseOptions,
+        report: &mut ParseReport,
+    ) -> Result<Option<(Operation, RecordPosition)>> {
+        let start_line = self.line_num + 1;
+        let position = RecordPosition {
+            line: Some(start_line as u64),
+            byte_offset: Some(self.byte_offset),
+        };
+        self.line_num += lines;
+        self.byte_offset += bytes;
+
+        if self.record.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let delimiter = self.columns.delimiter;
+        if let Some(rest) = strip_footer(&self.record, delimiter) {
+            let footer =
+                parse_footer(rest, delimiter).map_err(|e| e.at(position.location(None)))?;
+            report.footer = Some(footer);
+            return Ok(None);
+        }
+        if report.footer.is_some() {
+            return Err(
+                ParseError::InvalidFormat("record after the #TOTAL footer".to_string())
+                    .at(position.location(None)),
+            );
+        }
+
+        let record_index = Some(self.totals.records);
+        let upgraded = parse_line(&self.record, &self.columns, options, report)
+            .and_then(|upgraded| upgraded.operation.validate().map(|()| upgraded))
+            .map_err(|e| e.at(position.location(record_index)))?;
+        let operation = upgraded.operation;
+        if !upgraded.extras.is_empty() {
+            report.extras.insert(operation.tx_id, upgraded.extras);
+        }
+
+        report.check_rules(&operation, options);
+        report.records += 1;
+        self.totals.add(&operation);
+        Ok(Some((operation, position)))
+    }
+}
+
+/// Остаток строки после `#TOTAL` и разделителя, если это футер
+fn strip_footer(record: &str, delimiter: char) -> Option<&str> {
+    record.strip_prefix(FOOTER_TAG)?.strip_prefix(delimiter)
+}
+
+/// Разбирает `<count>,<sum>` после `#TOTAL,`
+fn parse_footer(rest: &str, delimiter: char) -> Result<Footer> {
+    let invalid = || {
+        ParseError::InvalidFormat(format!(
+            "Invalid footer {}{}{}",
+            FOOTER_TAG, delimiter, rest
+        ))
+    };
+
+    let (records, total_amount) = rest.split_once(delimiter).ok_or_else(invalid)?;
+    Ok(Footer {
+        records: records.trim().parse().map_err(|_| invalid())?,
+        total_amount: total_amount.trim().parse().map_err(|_| invalid())?,
+    })
+}
+
+/// Читает одну запись: строку, а если поле в кавычках не закрылось — и следующие за ней.
+/// Возвращает число прочитанных строк (0 на EOF) и байт, в `record` кладет запись без перевода строки в конце
+fn read_record<R: BufRead>(reader: &mut R, record: &mut String) -> Result<(usize, u64)> {
+    record.clear();
+    let mut lines = 0;
+    let mut bytes = 0;
+
+    loop {
+        let read = reader.read_line(record)?;
+        if read == 0 {
+            break;
+        }
+        lines += 1;
+        bytes += read as u64;
+
+        if !ends_inside_quotes(record) {
+            break;
+        }
+    }
+
+    trim_line_end(record);
+    Ok((lines, bytes))
+}
+
+/// [`read_record`] для асинхронного потока
+#[cfg(feature = "async")]
+async fn read_record_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    record: &mut String,
+) -> Result<(usize, u64)> {
+    record.clear();
+    let mut lines = 0;
+    let mut bytes = 0;
+
+    loop {
+        let read = reader.read_line(record).await?;
+        if read == 0 {
+            break;
+        }
+        lines += 1;
+        bytes += read as u64;
+
+        if !ends_inside_quotes(record) {
+            break;
+        }
+    }
+
+    trim_line_end(record);
+    Ok((lines, bytes))
+}
 
-    if parts.len() != 8 {
+/// Убирает перевод строки (`\n` или `\r\n`) в конце записи
+fn trim_line_end(record: &mut String) {
+    if record.ends_with('\n') {
+        record.pop();
+        if record.ends_with('\r') {
+            record.pop();
+        }
+    }
+}
+
+fn ends_inside_quotes(s: &str) -> bool {
+    s.bytes().filter(|&b| b == b'"').count() % 2 == 1
+}
+
+/// Разбирает строку данных; лишние колонки и хуки миграции уводят ее
+/// в [`migration::assemble`], как запись текстового формата
+fn parse_line(
+    line: &str,
+    columns: &Columns,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+) -> Result<Upgraded> {
+    // Поля раскладываем сразу по местам, без промежуточного Vec;
+    // копируются только описания с "" или мусором после кавычки
+    let mut parts: [Cow<'_, str>; 8] = Default::default();
+    let mut extras = Vec::new();
+    let mut width = 0;
+    for (position, value) in CsvFields::new(line, columns.delimiter).enumerate() {
+        width += 1;
+        if let Some(field) = columns.index.iter().position(|&i| i == position) {
+            parts[field] = value;
+        } else if let Some((_, name)) = columns.extra.iter().find(|(i, _)| *i == position) {
+            extras.push((name, value));
+        }
+    }
+
+    if width != columns.width {
         return Err(ParseError::InvalidFormat(format!(
-            "Expected 8 fields, got {}",
-            parts.len()
+            "Expected {} fields, got {}",
+            columns.width, width
         )));
     }
 
+    if !extras.is_empty() || options.migrations.is_some() {
+        // Описание экранируем, чтобы разбор текстовой записи вернул его как есть
+        let mut fields: HashMap<String, String> = FIELD_NAMES
+            .iter()
+            .zip(parts)
+            .map(|(name, value)| (name.to_string(), value.into_owned()))
+            .collect();
+        if let Some(description) = fields.get_mut("DESCRIPTION") {
+            *description = escape_description(description);
+        }
+        for (name, value) in extras {
+            fields.insert(name.clone(), value.into_owned());
+        }
+        return migration::assemble(&fields, columns.version, options, report);
+    }
+
     let tx_id = parts[0]
         .parse::<u64>()
         .map_err(|e| ParseError::InvalidField {
@@ -55,7 +996,7 @@ fn parse_line(line: &str) -> Result<Operation> {
             reason: e.to_string(),
         })?;
 
-    let tx_type = OperationType::from_str(parts[1])?;
+    let tx_type = OperationType::parse_field(&parts[1], options)?;
 
     let from_user_id = parts[2]
         .parse::<u64>()
@@ -71,74 +1012,504 @@ fn parse_line(line: &str) -> Result<Operation> {
             reason: e.to_string(),
         })?;
 
-    let amount = parts[4]
-        .parse::<i64>()
-        .map_err(|e| ParseError::InvalidField {
-            field: "AMOUNT".to_string(),
-            reason: e.to_string(),
-        })?;
+    let amount = options.amount_style.parse(&parts[4])?;
 
-    let timestamp = parts[5]
-        .parse::<u64>()
-        .map_err(|e| ParseError::InvalidField {
-            field: "TIMESTAMP".to_string(),
-            reason: e.to_string(),
-        })?;
+    let timestamp = report.parse_timestamp(tx_id, &parts[5], options)?;
 
-    let status = OperationStatus::from_str(parts[6])?;
+    let status = OperationStatus::parse_field(&parts[6], options)?;
 
-    let description = parts[7].trim_matches('"').to_string();
+    let description = std::mem::take(&mut parts[7]).into_owned();
 
-    Ok(Operation {
-        tx_id,
-        tx_type,
-        from_user_id,
-        to_user_id,
-        amount,
-        timestamp,
-        status,
-        description,
+    Ok(Upgraded {
+        operation: Operation {
+            tx_id,
+            tx_type,
+            from_user_id,
+            to_user_id,
+            amount,
+            timestamp,
+            status,
+            description,
+            extensions: Vec::new(),
+        },
+        extras: Default::default(),
     })
 }
 
-fn split_csv_line(line: &str) -> Vec<&str> {
-    let mut parts = Vec::new();
-    let mut start = 0;
-    let mut in_quotes = false;
+/// Поля строки по разделителю вне кавычек (RFC 4180): поле в кавычках
+/// может содержать разделители и переводы строк, а "" внутри него — это одна кавычка
+///
+/// Поля отдаются срезами строки; копия нужна только полю с "" или с мусором
+/// после закрывающей кавычки.
+struct CsvFields<'a> {
+    /// Непрочитанный остаток; `None` — последнее поле уже отдано
+    rest: Option<&'a str>,
+    delimiter: char,
+}
 
-    for (i, c) in line.char_indices() {
-        if c == '"' {
-            in_quotes = !in_quotes;
-        } else if c == ',' && !in_quotes {
-            parts.push(&line[start..i]);
-            start = i + 1;
+impl<'a> CsvFields<'a> {
+    fn new(line: &'a str, delimiter: char) -> Self {
+        CsvFields {
+            rest: Some(line),
+            delimiter,
         }
     }
-    parts.push(&line[start..]);
+}
+
+impl<'a> Iterator for CsvFields<'a> {
+    type Item = Cow<'a, str>;
 
-    parts
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let (field, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => split_quoted(quoted, self.delimiter),
+            None => match rest.find(self.delimiter) {
+                Some(i) => (
+                    Cow::Borrowed(&rest[..i]),
+                    Some(&rest[i + self.delimiter.len_utf8()..]),
+                ),
+                None => (Cow::Borrowed(rest), None),
+            },
+        };
+        self.rest = rest;
+        Some(field)
+    }
+}
+
+/// Поле в кавычках (без открывающей кавычки) и остаток строки после разделителя за ним
+fn split_quoted(quoted: &str, delimiter: char) -> (Cow<'_, str>, Option<&str>) {
+    let bytes = quoted.as_bytes();
+    let mut escaped = false;
+    // Незакрытая кавычка — поле до конца строки
+    let mut end = quoted.len();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'"' {
+            if bytes.get(i + 1) != Some(&b'"') {
+                end = i;
+                break;
+            }
+            escaped = true;
+            i += 1;
+        }
+        i += 1;
+    }
+
+    // Мусор между закрывающей кавычкой и разделителем оставляем как есть
+    let after = quoted.get(end + 1..).unwrap_or("");
+    let tail_end = after.find(delimiter).unwrap_or(after.len());
+    let tail = &after[..tail_end];
+
+    let value = &quoted[..end];
+    let field = if !escaped && tail.is_empty() {
+        Cow::Borrowed(value)
+    } else {
+        let mut owned = if escaped {
+            value.replace("\"\"", "\"")
+        } else {
+            value.to_string()
+        };
+        owned.push_str(tail);
+        Cow::Owned(owned)
+    };
+    let rest = (tail_end < after.len()).then(|| &after[tail_end + delimiter.len_utf8()..]);
+    (field, rest)
+}
+
+/// Оборачиваем в кавычки, удваивая кавычки внутри
+fn quote_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
 }
 
 /// Пишем всё в csv
-pub fn write_all<W: Write>(mut writer: W, operations: &HashSet<Operation>) -> Result<()> {
-    writeln!(writer, "{}", HEADER)?;
+pub fn write_all<W: Write>(writer: W, operations: &HashSet<Operation>) -> Result<()> {
+    write_all_with_options(writer, operations, &WriteOptions::default())
+}
+
+/// То же, что [`write_all`], но с настройками (например, единицами TIMESTAMP)
+///
+/// Операции пишутся в порядке `operations`, так что можно передать и отсортированный срез.
+/// `writer` оборачивается в [`BufWriter`], так что голый `File` не означает
+/// системный вызов на запись; все дописывается и сбрасывается до возврата.
+pub fn write_all_with_options<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut writer = CsvWriter::new(BufWriter::new(writer)).with_options(options.clone());
+    for operation in operations {
+        writer.write(operation)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
 
+/// Асинхронный [`write_all_with_options`]
+///
+/// Строки кодирует тот же [`CsvWriter`] в буфер, в поток они уходят по записи.
+#[cfg(feature = "async")]
+pub async fn write_all_async<'a, W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut encoder = CsvWriter::new(Vec::new()).with_options(options.clone());
     for operation in operations {
-        operation.validate()?;
+        encoder.write(operation)?;
+        writer.write_all(&encoder.writer).await?;
+        encoder.writer.clear();
+    }
+    writer.write_all(&encoder.finish()?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Пишет операции по одной, по мере их появления
+///
+/// Заголовок пишется один раз, перед первой записью (или в [`CsvWriter::finish`],
+/// если записей не было); футер, если он включен, — в `finish`. Без `finish`
+/// футера не будет, а ошибка сброса потока потеряется.
+///
+/// Строка уходит в поток одним `write_all`; буферизацию потока (например,
+/// [`BufWriter`] поверх файла) выбирает вызывающий.
+pub struct CsvWriter<W> {
+    writer: W,
+    options: WriteOptions,
+    totals: Footer,
+    /// Запись собирается здесь, буфер переиспользуется
+    buf: Vec<u8>,
+    header_written: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Писатель с настройками по умолчанию
+    pub fn new(writer: W) -> Self {
+        CsvWriter {
+            writer,
+            options: WriteOptions::default(),
+            totals: Footer::default(),
+            buf: Vec::new(),
+            header_written: false,
+        }
+    }
+
+    /// Настройки записи (разделитель, футер, единицы TIMESTAMP)
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Проверяет операцию и пишет ее строкой CSV
+    pub fn write(&mut self, operation: &Operation) -> Result<()> {
+        self.write_header()?;
+        self.buf.clear();
+        write_record(&mut self.buf, operation, &self.options, &mut self.totals)?;
+        self.writer.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Сбрасывает уже записанное в нижележащий поток
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Дописывает футер и возвращает поток
+    pub fn finish(mut self) -> Result<W> {
+        self.write_header()?;
+        write_footer(&mut self.writer, self.totals, &self.options)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if !self.header_written {
+            write_header(&mut self.writer, &self.options.csv)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+/// Пишет заголовок через разделитель из `csv`
+pub(crate) fn write_header<W: Write>(writer: &mut W, csv: &CsvOptions) -> Result<()> {
+    csv.check()?;
+    let mut header = FIELD_NAMES.join(csv.delimiter.encode_utf8(&mut [0; 4]));
+    header.push('\n');
+    writer.write_all(header.as_bytes())?;
+    Ok(())
+}
 
+/// Пишет одну строку CSV, проверив операцию, и добавляет ее в итоги
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+    totals: &mut Footer,
+) -> Result<()> {
+    let operation = options.limit_description(operation)?;
+    operation.validate()?;
+    totals.add(&operation);
+
+    let d = options.csv.delimiter;
+    writeln!(
+        writer,
+        "{}{d}{}{d}{}{d}{}{d}{}{d}{}{d}{}{d}{}",
+        operation.tx_id,
+        operation.tx_type.as_str(),
+        operation.from_user_id,
+        operation.to_user_id,
+        options.amount_style.format(operation.amount),
+        options.format_timestamp(operation.timestamp),
+        operation.status.as_str(),
+        quote_field(&operation.description)
+    )?;
+    Ok(())
+}
+
+/// Пишет `#TOTAL`, если он включен в настройках
+pub(crate) fn write_footer<W: Write>(
+    writer: &mut W,
+    totals: Footer,
+    options: &WriteOptions,
+) -> Result<()> {
+    if options.footer {
         writeln!(
             writer,
-            "{},{},{},{},{},{},{},\"{}\"",
-            operation.tx_id,
-            operation.tx_type.as_str(),
-            operation.from_user_id,
-            operation.to_user_id,
-            operation.amount,
-            operation.timestamp,
-            operation.status.as_str(),
-            operation.description
+            "{}{d}{}{d}{}",
+            FOOTER_TAG,
+            totals.records,
+            totals.total_amount,
+            d = options.csv.delimiter
         )?;
     }
-
     Ok(())
 }
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+    use crate::options::CancelToken;
+
+    fn chunked(bytes: &[u8], chunk_size: usize) -> Result<HashSet<Operation>> {
+        let options = ParseOptions::default();
+        parse_all_parallel_chunked(bytes, chunk_size, &options, DuplicatePolicy::KeepFirst)
+            .map(|outcome| outcome.operations)
+    }
+
+    fn blocks(bytes: &[u8], block_size: usize, chunk_size: usize) -> Result<HashSet<Operation>> {
+        let options = ParseOptions::default();
+        parse_all_parallel_blocks(
+            bytes,
+            block_size,
+            chunk_size,
+            &options,
+            DuplicatePolicy::KeepFirst,
+        )
+        .map(|outcome| outcome.operations)
+    }
+
+    fn generate_nasty_csv(rows: u64) -> String {
+        let mut csv = format!("{}\n", FIELD_NAMES.join(","));
+
+        for i in 0..rows {
+            let description = match i % 6 {
+                0 => format!("Plain {}", i),
+                1 => format!("Payment, invoice \"{}\"", i),
+                2 => format!("multi\nline,\n\"{}\"", i),
+                3 => format!("Юникод 🎉 {}", i),
+                4 => String::new(),
+                _ => format!("crlf\r\n{}", i),
+            };
+            // Каждая сотая запись — повтор TX_ID с другой суммой, должна победить первая
+            let tx_id = if i % 100 == 99 { i - 1 } else { i };
+            let line_end = if i % 7 == 0 { "\r\n" } else { "\n" };
+
+            csv.push_str(&format!(
+                "{},DEPOSIT,0,{},{},1633036800000,SUCCESS,{}{}",
+                tx_id,
+                i + 1,
+                i * 10,
+                quote_field(&description),
+                line_end
+            ));
+            if i % 1000 == 0 {
+                csv.push('\n');
+            }
+        }
+
+        csv
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let csv = generate_nasty_csv(100_000);
+
+        let sequential = parse_all(csv.as_bytes()).unwrap();
+        let parallel = chunked(csv.as_bytes(), 4096).unwrap();
+
+        assert_eq!(sequential.len(), parallel.len());
+        for op in &sequential {
+            let other = parallel.get(op).unwrap();
+            assert_eq!(op.amount, other.amount);
+            assert_eq!(op.to_user_id, other.to_user_id);
+            assert_eq!(op.description, other.description);
+        }
+    }
+
+    #[test]
+    fn test_parallel_reports_first_error_like_sequential() {
+        let mut csv = generate_nasty_csv(20_000);
+        csv.push_str("1,DEPOSIT,0,1,not_a_number,1633036800000,SUCCESS,\"bad\"\n");
+        csv.push_str("2,DEPOSIT,0,1,100,1633036800000,UNKNOWN,\"bad too\"\n");
+
+        let sequential = parse_all(csv.as_bytes()).unwrap_err().to_string();
+        let parallel = chunked(csv.as_bytes(), 1024).unwrap_err().to_string();
+
+        assert_eq!(sequential, parallel);
+        assert!(parallel.contains("AMOUNT"));
+    }
+
+    #[test]
+    fn test_parallel_reader_matches_sequential() {
+        let csv = generate_nasty_csv(50_000);
+
+        let sequential = parse_all(csv.as_bytes()).unwrap();
+        // Маленькие блоки: границы блоков попадают и в многострочные описания
+        let parallel = blocks(csv.as_bytes(), 64 * 1024, 4096).unwrap();
+        assert_eq!(parse_all_parallel(csv.as_bytes()).unwrap(), sequential);
+
+        assert_eq!(sequential.len(), parallel.len());
+        for op in &sequential {
+            let other = parallel.get(op).unwrap();
+            assert_eq!(op.amount, other.amount);
+            assert_eq!(op.to_user_id, other.to_user_id);
+            assert_eq!(op.description, other.description);
+        }
+    }
+
+    #[test]
+    fn test_parallel_reader_reports_first_error_like_sequential() {
+        let csv = generate_nasty_csv(20_000);
+        // Две ошибки в разных блоках: побеждать должна ранняя
+        let bad = "1,DEPOSIT,0,1,not_a_number,1633036800000,SUCCESS,\"bad\"\n";
+        let mut broken = csv.clone();
+        let cut = csv.find("\n5000,DEPOSIT").unwrap() + 1;
+        broken.insert_str(cut, bad);
+        broken.push_str("2,DEPOSIT,0,1,100,1633036800000,UNKNOWN,\"bad too\"\n");
+
+        let sequential = parse_all(broken.as_bytes()).unwrap_err();
+        let parallel = blocks(broken.as_bytes(), 16 * 1024, 1024).unwrap_err();
+
+        assert_eq!(sequential.to_string(), parallel.to_string());
+        assert_eq!(sequential.location(), parallel.location());
+        assert!(parallel.to_string().contains("AMOUNT"));
+    }
+
+    #[test]
+    fn test_parallel_uses_options_and_policy() {
+        let mut csv = format!("{}\n", FIELD_NAMES.join(";"));
+        for i in 1..=2_000u64 {
+            csv.push_str(&format!(
+                "{};DEPOSIT;0;7;{};1633036800000;SUCCESS;\"a; b\"\n",
+                i, i
+            ));
+        }
+        // Повтор TX_ID 1 через много кусков от первой записи
+        csv.push_str("1;DEPOSIT;0;7;999;1633036800000;SUCCESS;\"again\"\n");
+        let options = ParseOptions {
+            csv: CsvOptions { delimiter: ';' },
+            ..Default::default()
+        };
+
+        for policy in [
+            DuplicatePolicy::KeepFirst,
+            DuplicatePolicy::KeepLast,
+            DuplicatePolicy::Collect,
+        ] {
+            let sequential = parse_all_with_policy(csv.as_bytes(), &options, policy).unwrap();
+            let parallel =
+                parse_all_parallel_chunked(csv.as_bytes(), 1024, &options, policy).unwrap();
+            let amount = |outcome: &DedupOutcome| {
+                outcome
+                    .operations
+                    .iter()
+                    .find(|op| op.tx_id == 1)
+                    .unwrap()
+                    .amount
+            };
+            assert_eq!(parallel.operations.len(), 2_000);
+            assert_eq!(amount(&parallel), amount(&sequential), "{:?}", policy);
+            assert_eq!(parallel.duplicates.len(), sequential.duplicates.len());
+        }
+
+        let parallel =
+            parse_all_parallel_with_options(csv.as_bytes(), &options, DuplicatePolicy::Error);
+        assert!(matches!(
+            parallel,
+            Err(ParseError::DuplicateTxId { tx_id: 1 })
+        ));
+
+        // С настройками по умолчанию `;` не разделитель
+        assert!(chunked(csv.as_bytes(), 1024).is_err());
+    }
+
+    #[test]
+    fn test_parallel_reader_takes_tsv_and_cancel() {
+        let mut tsv = format!("{}\n", FIELD_NAMES.join("\t"));
+        for i in 1..=5_000u64 {
+            tsv.push_str(&format!(
+                "{}\tDEPOSIT\t0\t7\t{}\t1633036800000\tSUCCESS\t\"a, b\"\n",
+                i, i
+            ));
+        }
+        let mut options = ParseOptions {
+            csv: CsvOptions { delimiter: '\t' },
+            ..Default::default()
+        };
+
+        let sequential = parse_all_with_options(tsv.as_bytes(), &options).unwrap();
+        let parallel =
+            parse_all_parallel_with_options(tsv.as_bytes(), &options, DuplicatePolicy::KeepFirst)
+                .unwrap();
+        assert_eq!(parallel.operations, sequential);
+
+        let token = CancelToken::new();
+        token.cancel();
+        options.cancel = Some(token);
+        assert!(matches!(
+            parse_all_parallel_with_options(tsv.as_bytes(), &options, DuplicatePolicy::KeepFirst),
+            Err(ParseError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_chunks_never_split_quoted_field() {
+        let body = b"1,\"a\nb\"\n2,\"c\"\n3,d\n";
+        let chunks = split_chunks(body, 1);
+
+        assert_eq!(chunks, vec![(0, 8, 0), (8, 14, 2), (14, 18, 3)]);
+    }
+
+    #[test]
+    fn test_parallel_verifies_footer() {
+        let csv = generate_nasty_csv(2_000);
+        let operations = parse_all(csv.as_bytes()).unwrap();
+        let footer = Footer::of(&parse_all_vec(csv.as_bytes()).unwrap());
+
+        let good = format!("{}#TOTAL,{},{}\n", csv, footer.records, footer.total_amount);
+        let parallel = chunked(good.as_bytes(), 1024).unwrap();
+        assert_eq!(parallel, operations);
+
+        let bad = format!(
+            "{}#TOTAL,{},{}\n",
+            csv,
+            footer.records + 1,
+            footer.total_amount
+        );
+        assert!(matches!(
+            chunked(bad.as_bytes(), 1024),
+            Err(ParseError::FooterMismatch { .. })
+        ));
+    }
+}
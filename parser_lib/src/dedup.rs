@@ -0,0 +1,163 @@
+//! Повторы TX_ID: что делать, если две записи файла делят один TX_ID
+//!
+//! `parse_all` складывает записи в `HashSet`, где равенство только по TX_ID,
+//! и молча оставляет первую. Функции `parse_all_with_policy` форматов
+//! позволяют вместо этого упасть, оставить последнюю или вернуть повторы отдельно.
+
+use crate::error::{ParseError, Result};
+use crate::operation::Operation;
+use std::collections::HashSet;
+
+/// Что делать с записью, чей TX_ID уже встречался
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DuplicatePolicy {
+    /// Остановить разбор с [`ParseError::DuplicateTxId`]
+    #[default]
+    Error,
+    /// Оставить первую запись (как `parse_all`)
+    KeepFirst,
+    /// Оставить последнюю запись
+    KeepLast,
+    /// Оставить первую, остальные вернуть в [`DedupOutcome::duplicates`]
+    Collect,
+}
+
+/// Итоги `parse_all_with_policy`
+#[derive(Debug, Default)]
+pub struct DedupOutcome {
+    /// По одной записи на TX_ID
+    pub operations: HashSet<Operation>,
+    /// Отброшенные повторы в порядке файла; заполняется только с [`DuplicatePolicy::Collect`]
+    pub duplicates: Vec<Operation>,
+}
+
+/// Собирает записи по одной, применяя политику к повторам
+pub(crate) struct Deduplicator {
+    policy: DuplicatePolicy,
+    outcome: DedupOutcome,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(policy: DuplicatePolicy) -> Self {
+        Deduplicator {
+            policy,
+            outcome: DedupOutcome::default(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, operation: Operation) -> Result<()> {
+        if !self.outcome.operations.contains(&operation) {
+            self.outcome.operations.insert(operation);
+            return Ok(());
+        }
+
+        match self.policy {
+            DuplicatePolicy::Error => {
+                return Err(ParseError::DuplicateTxId {
+                    tx_id: operation.tx_id,
+                });
+            }
+            DuplicatePolicy::KeepFirst => {}
+            DuplicatePolicy::KeepLast => {
+                self.outcome.operations.replace(operation);
+            }
+            DuplicatePolicy::Collect => self.outcome.duplicates.push(operation),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> DedupOutcome {
+        self.outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+    use crate::options::ParseOptions;
+    use crate::{bin_format, csv_format, text_format};
+
+    fn deposit(tx_id: u64, amount: i64) -> Operation {
+        Operation::deposit(7, amount)
+            .tx_id(tx_id)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description(format!("deposit {}", amount))
+            .build()
+            .unwrap()
+    }
+
+    /// Записи 1, 2, 1, 1 с разными суммами в каждом из трех форматов
+    fn encoded() -> Vec<(&'static str, Vec<u8>)> {
+        let ops = [
+            deposit(1, 100),
+            deposit(2, 200),
+            deposit(1, 300),
+            deposit(1, 400),
+        ];
+        let options = Default::default();
+        let mut bin = Vec::new();
+        bin_format::write_all_with_options(&mut bin, &ops, &options).unwrap();
+        let mut csv = Vec::new();
+        csv_format::write_all_with_options(&mut csv, &ops, &options).unwrap();
+        let mut txt = Vec::new();
+        text_format::write_all_with_options(&mut txt, &ops, &options).unwrap();
+        vec![("bin", bin), ("csv", csv), ("txt", txt)]
+    }
+
+    fn parse(name: &str, buf: &[u8], policy: DuplicatePolicy) -> Result<DedupOutcome> {
+        let options = ParseOptions::default();
+        match name {
+            "bin" => bin_format::parse_all_with_policy(buf, &options, policy),
+            "csv" => csv_format::parse_all_with_policy(buf, &options, policy),
+            _ => text_format::parse_all_with_policy(buf, &options, policy),
+        }
+    }
+
+    fn amount_of(outcome: &DedupOutcome, tx_id: u64) -> i64 {
+        outcome
+            .operations
+            .iter()
+            .find(|op| op.tx_id == tx_id)
+            .unwrap()
+            .amount
+    }
+
+    #[test]
+    fn test_error_policy_names_the_tx_id() {
+        for (name, buf) in encoded() {
+            let err = parse(name, &buf, DuplicatePolicy::Error).unwrap_err();
+            assert!(
+                matches!(err, ParseError::DuplicateTxId { tx_id: 1 }),
+                "{}: {}",
+                name,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_keep_first_and_keep_last() {
+        for (name, buf) in encoded() {
+            let first = parse(name, &buf, DuplicatePolicy::KeepFirst).unwrap();
+            assert_eq!(first.operations.len(), 2, "{}", name);
+            assert_eq!(amount_of(&first, 1), 100, "{}", name);
+            assert!(first.duplicates.is_empty(), "{}", name);
+
+            let last = parse(name, &buf, DuplicatePolicy::KeepLast).unwrap();
+            assert_eq!(amount_of(&last, 1), 400, "{}", name);
+            assert_eq!(amount_of(&last, 2), 200, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_collect_returns_dropped_records_in_file_order() {
+        for (name, buf) in encoded() {
+            let outcome = parse(name, &buf, DuplicatePolicy::Collect).unwrap();
+            assert_eq!(amount_of(&outcome, 1), 100, "{}", name);
+            let dropped: Vec<i64> = outcome.duplicates.iter().map(|op| op.amount).collect();
+            assert_eq!(dropped, [300, 400], "{}", name);
+        }
+    }
+}
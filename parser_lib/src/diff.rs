@@ -0,0 +1,287 @@
+//! Разница двух наборов операций: чего нет с одной из сторон и что изменилось
+//!
+//! Записи сопоставляются по TX_ID, а затем сравниваются по всем полям
+//! ([`Operation::diff_fields`]): `==` у [`Operation`] смотрит только на TX_ID.
+//! На этом модуле построен `comparer`.
+
+use crate::operation::{FieldDiff, Operation};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Итог [`diff`]; все списки отсортированы по TX_ID
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffResult {
+    /// Записи, TX_ID которых есть только слева
+    pub only_in_left: Vec<Operation>,
+    /// Записи, TX_ID которых есть только справа
+    pub only_in_right: Vec<Operation>,
+    /// Записи с одним TX_ID и разными полями: левая, правая и различия
+    pub changed: Vec<(Operation, Operation, Vec<FieldDiff>)>,
+}
+
+/// Как [`DiffResult::to_text`] называет стороны и сколько выводит
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffTextOptions {
+    pub left_name: String,
+    pub right_name: String,
+    /// Для измененных записей печатать каждое поле с обоими значениями, а не только имена
+    pub verbose: bool,
+    /// Не больше стольких строк с различиями; `None` — все
+    pub limit: Option<usize>,
+}
+
+impl Default for DiffTextOptions {
+    fn default() -> Self {
+        DiffTextOptions {
+            left_name: "left".to_string(),
+            right_name: "right".to_string(),
+            verbose: false,
+            limit: None,
+        }
+    }
+}
+
+/// Сравнивает два набора по TX_ID и по всем полям
+pub fn diff(left: &HashSet<Operation>, right: &HashSet<Operation>) -> DiffResult {
+    let mut result = DiffResult {
+        only_in_left: left.difference(right).cloned().collect(),
+        only_in_right: right.difference(left).cloned().collect(),
+        changed: left
+            .iter()
+            .filter_map(|l| right.get(l).map(|r| (l, r)))
+            .filter(|(l, r)| !l.eq_full(r))
+            .map(|(l, r)| (l.clone(), r.clone(), l.diff_fields(r)))
+            .collect(),
+    };
+    result.only_in_left.sort_by_key(|op| op.tx_id);
+    result.only_in_right.sort_by_key(|op| op.tx_id);
+    result.changed.sort_by_key(|(op, _, _)| op.tx_id);
+    result
+}
+
+impl DiffResult {
+    /// Наборы совпадают по всем полям
+    pub fn is_identical(&self) -> bool {
+        self.only_in_left.is_empty() && self.only_in_right.is_empty() && self.changed.is_empty()
+    }
+
+    /// Сколько всего различий: по одному на запись
+    pub fn len(&self) -> usize {
+        self.only_in_left.len() + self.only_in_right.len() + self.changed.len()
+    }
+
+    /// То же, что [`DiffResult::is_identical`]
+    pub fn is_empty(&self) -> bool {
+        self.is_identical()
+    }
+
+    /// Отчет для человека: итоговая строка, затем по строке на различие
+    /// (сначала только слева, потом только справа, потом измененные)
+    pub fn to_text(&self, options: &DiffTextOptions) -> String {
+        let (left, right) = (&options.left_name, &options.right_name);
+        if self.is_identical() {
+            return format!(
+                "The operation records in '{}' and '{}' are identical.\n",
+                left, right
+            );
+        }
+
+        let mut text = format!(
+            "Files differ: {} only in '{}', {} only in '{}', {} with different fields\n",
+            self.only_in_left.len(),
+            left,
+            self.only_in_right.len(),
+            right,
+            self.changed.len()
+        );
+        let only_left = self
+            .only_in_left
+            .iter()
+            .map(|op| format!("Only in '{}': tx_id {}", left, op.tx_id));
+        let only_right = self
+            .only_in_right
+            .iter()
+            .map(|op| format!("Only in '{}': tx_id {}", right, op.tx_id));
+        let changed = self.changed.iter().map(|(op, _, diffs)| {
+            if options.verbose {
+                let fields: Vec<String> = diffs.iter().map(|d| format!("\n  {}", d)).collect();
+                format!("Differs: tx_id {}{}", op.tx_id, fields.concat())
+            } else {
+                let fields: Vec<&str> = diffs.iter().map(|d| d.field).collect();
+                format!("Differs: tx_id {} ({})", op.tx_id, fields.join(", "))
+            }
+        });
+        let lines = only_left.chain(only_right).chain(changed);
+
+        let limit = options.limit.unwrap_or(usize::MAX);
+        for line in lines.take(limit) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        if self.len() > limit {
+            text.push_str(&format!("... and {} more\n", self.len() - limit));
+        }
+        text
+    }
+
+    /// Разница в виде JSON (фича `serde`): записи как в формате JSON, поля
+    /// измененных — `field`, `left`, `right`
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let changed: Vec<serde_json::Value> = self
+            .changed
+            .iter()
+            .map(|(left, right, diffs)| {
+                let fields: Vec<serde_json::Value> = diffs
+                    .iter()
+                    .map(|d| {
+                        serde_json::json!({
+                            "field": d.field,
+                            "left": d.left,
+                            "right": d.right,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({ "left": left, "right": right, "fields": fields })
+            })
+            .collect();
+        let value = serde_json::json!({
+            "identical": self.is_identical(),
+            "only_in_left": self.only_in_left,
+            "only_in_right": self.only_in_right,
+            "changed": changed,
+        });
+        serde_json::to_string_pretty(&value).expect("json! value always serializes")
+    }
+}
+
+/// [`DiffResult::to_text`] с настройками по умолчанию
+impl fmt::Display for DiffResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_text(&DiffTextOptions::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn deposit(tx_id: u64, amount: i64) -> Operation {
+        Operation::deposit(7, amount)
+            .tx_id(tx_id)
+            .timestamp(1_633_036_800_000)
+            .status(OperationStatus::Success)
+            .description(format!("Deposit {}", tx_id))
+            .build()
+            .unwrap()
+    }
+
+    fn set(ops: impl IntoIterator<Item = Operation>) -> HashSet<Operation> {
+        ops.into_iter().collect()
+    }
+
+    #[test]
+    fn test_identical_sets() {
+        let ops = set([deposit(1, 100), deposit(2, 200)]);
+        let result = diff(&ops, &ops.clone());
+        assert!(result.is_identical());
+        assert_eq!(result.len(), 0);
+        assert_eq!(
+            result.to_string(),
+            "The operation records in 'left' and 'right' are identical.\n"
+        );
+        assert!(diff(&HashSet::new(), &HashSet::new()).is_identical());
+    }
+
+    #[test]
+    fn test_each_bucket() {
+        let mut failed = deposit(2, 250);
+        failed.status = OperationStatus::Failure;
+        let left = set([deposit(3, 300), deposit(1, 100), deposit(2, 200)]);
+        let right = set([
+            deposit(1, 100),
+            failed.clone(),
+            deposit(5, 500),
+            deposit(4, 400),
+        ]);
+
+        let result = diff(&left, &right);
+        assert!(!result.is_identical());
+        assert_eq!(result.len(), 4);
+
+        let ids = |ops: &[Operation]| ops.iter().map(|op| op.tx_id).collect::<Vec<_>>();
+        assert_eq!(ids(&result.only_in_left), vec![3]);
+        assert_eq!(ids(&result.only_in_right), vec![4, 5]);
+
+        assert_eq!(result.changed.len(), 1);
+        let (l, r, fields) = &result.changed[0];
+        assert!(l.eq_full(&deposit(2, 200)));
+        assert!(r.eq_full(&failed));
+        let names: Vec<&str> = fields.iter().map(|d| d.field).collect();
+        assert_eq!(names, vec!["AMOUNT", "STATUS"]);
+
+        // Слева направо и справа налево — зеркально
+        let mirrored = diff(&right, &left);
+        assert_eq!(ids(&mirrored.only_in_left), vec![4, 5]);
+        assert_eq!(mirrored.changed[0].2[0].left, "250");
+    }
+
+    #[test]
+    fn test_to_text() {
+        let left = set([deposit(1, 100), deposit(2, 200), deposit(3, 300)]);
+        let right = set([deposit(1, 100), deposit(2, 250), deposit(4, 400)]);
+        let result = diff(&left, &right);
+
+        let options = DiffTextOptions {
+            left_name: "a.csv".to_string(),
+            right_name: "b.csv".to_string(),
+            ..DiffTextOptions::default()
+        };
+        assert_eq!(
+            result.to_text(&options),
+            "Files differ: 1 only in 'a.csv', 1 only in 'b.csv', 1 with different fields\n\
+             Only in 'a.csv': tx_id 3\n\
+             Only in 'b.csv': tx_id 4\n\
+             Differs: tx_id 2 (AMOUNT)\n"
+        );
+
+        let verbose = DiffTextOptions {
+            verbose: true,
+            limit: Some(1),
+            ..options
+        };
+        let text = result.to_text(&verbose);
+        assert!(!text.contains("AMOUNT"), "{}", text);
+        assert!(
+            text.ends_with("Only in 'a.csv': tx_id 3\n... and 2 more\n"),
+            "{}",
+            text
+        );
+
+        let verbose = DiffTextOptions {
+            limit: None,
+            ..verbose
+        };
+        assert!(
+            result
+                .to_text(&verbose)
+                .ends_with("Differs: tx_id 2\n  AMOUNT: 200 != 250\n")
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_json() {
+        let left = set([deposit(1, 100), deposit(2, 200)]);
+        let right = set([deposit(1, 150)]);
+        let value: serde_json::Value =
+            serde_json::from_str(&diff(&left, &right).to_json()).unwrap();
+
+        assert_eq!(value["identical"], false);
+        assert_eq!(value["only_in_left"][0]["TX_ID"], 2);
+        assert_eq!(value["only_in_right"], serde_json::json!([]));
+        assert_eq!(value["changed"][0]["fields"][0]["field"], "AMOUNT");
+        assert_eq!(value["changed"][0]["right"]["AMOUNT"], 150);
+    }
+}
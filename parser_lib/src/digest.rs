@@ -0,0 +1,147 @@
+//! Отпечаток набора операций, не зависящий от формата файла и порядка записей
+//!
+//! Операции сортируются по TX_ID, каждая сериализуется в каноническом виде
+//! (все поля big-endian, описание в каноническом виде с длиной) и все вместе
+//! хешируются SHA-256 из крейта sha2 (фича `digest`).
+
+use crate::operation::{Operation, canonicalize_description};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Метка версии канонической сериализации: при ее изменении отпечатки меняются намеренно
+const DOMAIN: &[u8] = b"YPBank operation set digest v1\0";
+
+/// SHA-256 канонической сериализации набора операций
+///
+/// Одинаковый набор дает одинаковый отпечаток, из какого бы формата
+/// и в каком бы порядке его ни прочитали; изменение любого поля меняет отпечаток.
+pub fn digest(ops: &HashSet<Operation>) -> [u8; 32] {
+    let mut sorted: Vec<&Operation> = ops.iter().collect();
+    sorted.sort_by_key(|op| op.tx_id);
+
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update((sorted.len() as u64).to_be_bytes());
+    for op in sorted {
+        hasher.update(op.tx_id.to_be_bytes());
+        update_code(&mut hasher, op.tx_type.known_code(), op.tx_type.as_str());
+        hasher.update(op.from_user_id.to_be_bytes());
+        hasher.update(op.to_user_id.to_be_bytes());
+        hasher.update(op.amount.to_be_bytes());
+        hasher.update(op.timestamp.to_be_bytes());
+        update_code(&mut hasher, op.status.known_code(), op.status.as_str());
+        let description = canonicalize_description(&op.description);
+        hasher.update((description.len() as u64).to_be_bytes());
+        hasher.update(description.as_bytes());
+        // Расширения — только если они есть: отпечатки наборов без них не меняются
+        if !op.extensions.is_empty() {
+            hasher.update([u8::MAX]);
+            hasher.update((op.extensions.len() as u64).to_be_bytes());
+            for extension in &op.extensions {
+                hasher.update(extension.tag.to_be_bytes());
+                hasher.update((extension.value.len() as u64).to_be_bytes());
+                hasher.update(&extension.value);
+            }
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Байт известного типа или статуса; незнакомое значение — метка 0xFF, длина и строка как есть
+fn update_code(hasher: &mut Sha256, code: Option<u8>, value: &str) {
+    match code {
+        Some(code) => hasher.update([code]),
+        None => {
+            hasher.update([u8::MAX]);
+            hasher.update((value.len() as u64).to_be_bytes());
+            hasher.update(value.as_bytes());
+        }
+    }
+}
+
+/// [`digest`] в виде 64 шестнадцатеричных символов в нижнем регистре
+pub fn digest_hex(ops: &HashSet<Operation>) -> String {
+    to_hex(&digest(ops))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::edge_cases;
+    use crate::format::{Format, OperationFormat};
+    use crate::operation::{Extension, OperationStatus, OperationType, escape_description};
+
+    #[test]
+    fn test_digest_ignores_format_and_order() {
+        let ops: HashSet<Operation> = edge_cases().into_iter().collect();
+        let expected = digest(&ops);
+
+        for format in [Format::Bin, Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format.write_all(&mut buf, &ops).unwrap();
+            let parsed = format.parse_all(&mut buf.as_slice()).unwrap();
+            assert_eq!(digest(&parsed), expected, "{}", format.name());
+        }
+
+        // Тот же набор, собранный в другом порядке
+        let mut unique: Vec<Operation> = ops.iter().cloned().collect();
+        unique.sort_by_key(|op| std::cmp::Reverse(op.tx_id));
+        let reordered: HashSet<Operation> = unique.into_iter().collect();
+        assert_eq!(digest(&reordered), expected);
+    }
+
+    #[test]
+    fn test_digest_uses_canonical_description() {
+        let text = "TX_ID: 7\nTX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 5\nAMOUNT: 100\n\
+                    TIMESTAMP: 1633036800000\nSTATUS: SUCCESS\n\
+                    DESCRIPTION: \"say \\\"hi\\\"\\tthen\\\\go\"\n";
+        let from_text = Format::Txt.parse_all(&mut text.as_bytes()).unwrap();
+        let parsed = from_text.iter().next().unwrap();
+        assert_eq!(parsed.description, "say \"hi\"\tthen\\go");
+
+        // То же описание, но как оно лежит в бинарнике: в кавычках и с экранированием
+        let mut escaped = parsed.clone();
+        escaped.description = escape_description(&parsed.description);
+        assert_ne!(escaped.description, parsed.description);
+        let from_bin: HashSet<Operation> = [escaped].into_iter().collect();
+        assert_eq!(digest(&from_bin), digest(&from_text));
+
+        let mut buf = Vec::new();
+        Format::Bin.write_all(&mut buf, &from_text).unwrap();
+        let round_trip = Format::Bin.parse_all(&mut buf.as_slice()).unwrap();
+        assert_eq!(digest(&round_trip), digest(&from_text));
+    }
+
+    #[test]
+    fn test_digest_changes_on_any_field() {
+        let ops: HashSet<Operation> = edge_cases().into_iter().collect();
+        let expected = digest_hex(&ops);
+        let victim = ops.iter().find(|op| op.tx_id == 1).unwrap().clone();
+
+        let mutations: [fn(&mut Operation); 7] = [
+            |op| op.amount += 1,
+            |op| op.timestamp += 1,
+            |op| op.to_user_id += 1,
+            |op| op.status = OperationStatus::Pending,
+            |op| op.description.push('!'),
+            |op| op.tx_type = OperationType::Withdrawal,
+            |op| {
+                op.extensions
+                    .push(Extension::new(Extension::CURRENCY, "USD"))
+            },
+        ];
+        for mutate in mutations {
+            let mut changed = ops.clone();
+            let mut op = victim.clone();
+            mutate(&mut op);
+            changed.replace(op);
+            assert_ne!(digest_hex(&changed), expected);
+        }
+
+        assert_eq!(expected.len(), 64);
+    }
+}
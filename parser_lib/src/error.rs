@@ -1,14 +1,122 @@
+use crate::footer::Footer;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
+/// Ошибка разбора или записи
+///
+/// Ошибка записи при разборе потока (`parse_all*`, итераторы, `parse_each`)
+/// всегда приходит обернутой в [`ParseError::WithContext`] с местом во входе,
+/// какой бы ни была сама ошибка. Как есть приходят только ввод-вывод и отмена:
+/// они не про запись. Разбор одной записи (`parse_operation`, `parse_one`) места
+/// не знает и тоже отдает ошибку как есть. Сравнивать вид ошибки удобнее через
+/// [`ParseError::without_context`]: `matches!(e.without_context(), ParseError::InvalidMagic)`.
 #[derive(Debug)]
 pub enum ParseError {
     Io(io::Error),
     InvalidFormat(String),
-    InvalidField { field: String, reason: String },
+    InvalidField {
+        field: String,
+        reason: String,
+    },
     UnexpectedEof,
+    /// Во входе нет данных, см. [`crate::detect_format`]
+    EmptyInput,
     InvalidMagic,
-    InvalidRecordSize,
+    /// RECORD_SIZE бинарной записи не совпадает с тем, сколько занимают ее поля
+    InvalidRecordSize {
+        declared: u32,
+        actual: u64,
+    },
+    /// CRC32 записи v2 не сошелся с ее телом
+    ChecksumMismatch {
+        tx_id: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// Бинарный поток оборвался посреди записи, уже после ее MAGIC
+    TruncatedRecord {
+        /// Смещение начала записи; у разбора одной записи — от места, где стоял поток
+        offset: u64,
+        /// Сколько байт записи нужно было: весь заголовок, пока RECORD_SIZE не прочитан,
+        /// иначе заголовок вместе с телом
+        expected: usize,
+        /// Сколько байт записи было в потоке
+        got: usize,
+    },
+    Cancelled,
+    /// Вторая запись с тем же TX_ID при [`crate::DuplicatePolicy::Error`]
+    DuplicateTxId {
+        tx_id: u64,
+    },
+    /// Входы [`crate::merge`] расходятся в записи с одним TX_ID при [`crate::MergePolicy::FailOnConflict`]
+    MergeConflict {
+        tx_id: u64,
+        /// Номера входов с 0
+        first: usize,
+        second: usize,
+        /// Поля, которыми записи отличаются
+        fields: Vec<&'static str>,
+    },
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+    FooterMismatch {
+        expected: Footer,
+        actual: Footer,
+    },
+    File {
+        path: PathBuf,
+        source: Box<ParseError>,
+    },
+    Transform {
+        name: String,
+        tx_id: u64,
+        source: Box<ParseError>,
+    },
+    /// Ошибка записи вместе с местом во входе, см. [`ParseError::location`]
+    WithContext {
+        location: Location,
+        source: Box<ParseError>,
+    },
+}
+
+/// Где во входе случилась ошибка
+///
+/// CSV, текст и JSON знают строку, бинарный формат — смещение в байтах;
+/// номер записи знают все.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Номер строки с 1; у текста — строка поля с ошибкой, если оно известно
+    pub line: Option<u64>,
+    /// Порядковый номер записи с 0
+    pub record_index: Option<u64>,
+    /// Смещение начала записи от начала потока в байтах
+    pub byte_offset: Option<u64>,
+    /// Поле с ошибкой
+    pub field: Option<String>,
+}
+
+/// `line 1234, record #7, field AMOUNT`: только известные части;
+/// смещение в байтах — только если нет строки
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(line) = self.line {
+            parts.push(format!("line {}", line));
+        }
+        if let Some(index) = self.record_index {
+            parts.push(format!("record #{}", index));
+        }
+        if let Some(offset) = self.byte_offset.filter(|_| self.line.is_none()) {
+            parts.push(format!("byte {}", offset));
+        }
+        if let Some(field) = &self.field {
+            parts.push(format!("field {}", field));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -20,13 +128,201 @@ impl fmt::Display for ParseError {
                 write!(f, "Invalid field '{}': {}", field, reason)
             }
             ParseError::UnexpectedEof => write!(f, "Unexpected end of file"),
+            ParseError::EmptyInput => write!(f, "Empty input"),
             ParseError::InvalidMagic => write!(f, "Invalid magic header"),
-            ParseError::InvalidRecordSize => write!(f, "Invalid record size"),
+            ParseError::InvalidRecordSize { declared, actual } => write!(
+                f,
+                "Invalid record size: RECORD_SIZE is {}, fields take {} bytes",
+                declared, actual
+            ),
+            ParseError::ChecksumMismatch {
+                tx_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch for tx_id {}: record says {:08x}, body gives {:08x}",
+                tx_id, expected, actual
+            ),
+            ParseError::TruncatedRecord {
+                offset,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Truncated record at byte {}: expected {} bytes, got {}",
+                offset, expected, got
+            ),
+            ParseError::Cancelled => write!(f, "Operation cancelled"),
+            ParseError::DuplicateTxId { tx_id } => write!(f, "Duplicate TX_ID {}", tx_id),
+            ParseError::MergeConflict {
+                tx_id,
+                first,
+                second,
+                fields,
+            } => write!(
+                f,
+                "Conflicting records for TX_ID {} in inputs {} and {}: {}",
+                tx_id,
+                first,
+                second,
+                fields.join(", ")
+            ),
+            ParseError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Unsupported schema version {} (this reader supports up to {})",
+                found, supported
+            ),
+            ParseError::FooterMismatch { expected, actual } => write!(
+                f,
+                "Footer mismatch: footer says {}, file has {}",
+                expected, actual
+            ),
+            ParseError::File { path, source } => write!(f, "{}: {}", path.display(), source),
+            ParseError::Transform {
+                name,
+                tx_id,
+                source,
+            } => write!(
+                f,
+                "Transform '{}' failed on tx_id {}: {}",
+                name, tx_id, source
+            ),
+            ParseError::WithContext { location, source } => write!(f, "{}: {}", location, source),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            ParseError::File { source, .. }
+            | ParseError::Transform { source, .. }
+            | ParseError::WithContext { source, .. } => Some(source.as_ref()),
+            _ => None,
         }
     }
 }
 
-impl std::error::Error for ParseError {}
+impl ParseError {
+    /// Копия ошибки, чтобы отдать ее в два места (канал и [`crate::channel::ParserHandle::join`])
+    ///
+    /// `io::Error` не клонируется: копия сохраняет его вид и текст.
+    pub(crate) fn duplicate(&self) -> ParseError {
+        match self {
+            ParseError::Io(e) => ParseError::Io(io::Error::new(e.kind(), e.to_string())),
+            ParseError::InvalidFormat(msg) => ParseError::InvalidFormat(msg.clone()),
+            ParseError::InvalidField { field, reason } => ParseError::InvalidField {
+                field: field.clone(),
+                reason: reason.clone(),
+            },
+            ParseError::UnexpectedEof => ParseError::UnexpectedEof,
+            ParseError::EmptyInput => ParseError::EmptyInput,
+            ParseError::InvalidMagic => ParseError::InvalidMagic,
+            ParseError::InvalidRecordSize { declared, actual } => ParseError::InvalidRecordSize {
+                declared: *declared,
+                actual: *actual,
+            },
+            ParseError::ChecksumMismatch {
+                tx_id,
+                expected,
+                actual,
+            } => ParseError::ChecksumMismatch {
+                tx_id: *tx_id,
+                expected: *expected,
+                actual: *actual,
+            },
+            ParseError::TruncatedRecord {
+                offset,
+                expected,
+                got,
+            } => ParseError::TruncatedRecord {
+                offset: *offset,
+                expected: *expected,
+                got: *got,
+            },
+            ParseError::Cancelled => ParseError::Cancelled,
+            ParseError::DuplicateTxId { tx_id } => ParseError::DuplicateTxId { tx_id: *tx_id },
+            ParseError::MergeConflict {
+                tx_id,
+                first,
+                second,
+                fields,
+            } => ParseError::MergeConflict {
+                tx_id: *tx_id,
+                first: *first,
+                second: *second,
+                fields: fields.clone(),
+            },
+            ParseError::UnsupportedVersion { found, supported } => ParseError::UnsupportedVersion {
+                found: *found,
+                supported: *supported,
+            },
+            ParseError::FooterMismatch { expected, actual } => ParseError::FooterMismatch {
+                expected: *expected,
+                actual: *actual,
+            },
+            ParseError::File { path, source } => ParseError::File {
+                path: path.clone(),
+                source: Box::new(source.duplicate()),
+            },
+            ParseError::Transform {
+                name,
+                tx_id,
+                source,
+            } => ParseError::Transform {
+                name: name.clone(),
+                tx_id: *tx_id,
+                source: Box::new(source.duplicate()),
+            },
+            ParseError::WithContext { location, source } => ParseError::WithContext {
+                location: location.clone(),
+                source: Box::new(source.duplicate()),
+            },
+        }
+    }
+
+    /// Место во входе, если парсер его приложил
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ParseError::WithContext { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Сама ошибка без места во входе
+    pub fn without_context(&self) -> &ParseError {
+        match self {
+            ParseError::WithContext { source, .. } => source.without_context(),
+            other => other,
+        }
+    }
+
+    /// Прикладывает место во входе к ошибке записи
+    ///
+    /// Поле берется из [`ParseError::InvalidField`]. Ввод-вывод и отмену не
+    /// оборачивает, уже приложенное место не меняет: внутреннее точнее.
+    pub(crate) fn at(self, mut location: Location) -> ParseError {
+        match self {
+            ParseError::Io(_) | ParseError::Cancelled | ParseError::WithContext { .. } => self,
+            source => {
+                match &source {
+                    ParseError::InvalidField { field, .. } => {
+                        location.field.get_or_insert_with(|| field.clone());
+                    }
+                    // Смещение обрыв называет сам, второй раз в тексте оно не нужно
+                    ParseError::TruncatedRecord { .. } => location.byte_offset = None,
+                    _ => {}
+                }
+                ParseError::WithContext {
+                    location,
+                    source: Box::new(source),
+                }
+            }
+        }
+    }
+}
 
 impl From<io::Error> for ParseError {
     fn from(err: io::Error) -> Self {
@@ -0,0 +1,407 @@
+//! C ABI для сервисов не на Rust: чтение файла операций из C/C++
+//!
+//! Собирается с фичей `ffi` как cdylib:
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//! Заголовок `include/ypbank.h` генерирует cbindgen по `cbindgen.toml`.
+//!
+//! Паника не выходит за границу: функции ловят ее через `catch_unwind` и
+//! возвращают [`YPB_ERROR`]. Текст последней ошибки потока отдает
+//! [`ypb_last_error_message`]. Все строки — UTF-8 с завершающим NUL.
+
+use crate::error::Result;
+use crate::file::{read_file_ordered, with_path};
+use crate::format::{Format, SNIFF_LEN, detect_format};
+use crate::operation::Operation;
+use crate::options::ParseOptions;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::fs::File;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+/// Вызов удался
+pub const YPB_OK: c_int = 0;
+/// Вызов не удался, текст — в [`ypb_last_error_message`]
+pub const YPB_ERROR: c_int = -1;
+
+/// Формат по содержимому, а если не вышло — по расширению
+pub const YPB_FORMAT_AUTO: c_int = 0;
+pub const YPB_FORMAT_BIN: c_int = 1;
+pub const YPB_FORMAT_CSV: c_int = 2;
+pub const YPB_FORMAT_TXT: c_int = 3;
+/// JSON и JSON Lines есть, только если библиотека собрана с фичей `serde`
+pub const YPB_FORMAT_JSON: c_int = 4;
+pub const YPB_FORMAT_JSONL: c_int = 5;
+
+/// Операция для C
+///
+/// TX_TYPE и STATUS — байты как в YPBankBin: 0 DEPOSIT, 1 TRANSFER, 2 WITHDRAWAL;
+/// 0 SUCCESS, 1 FAILURE, 2 PENDING. `description` принадлежит хэндлу и живет до
+/// [`ypb_free`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct YpbOperation {
+    pub tx_id: u64,
+    pub tx_type: u8,
+    pub from_user_id: u64,
+    pub to_user_id: u64,
+    pub amount: i64,
+    pub timestamp: u64,
+    pub status: u8,
+    pub description: *const c_char,
+}
+
+/// Прочитанные операции в порядке записей файла; для C — непрозрачный хэндл
+pub struct YpbOperations {
+    operations: Vec<Operation>,
+    descriptions: Vec<CString>,
+}
+
+impl YpbOperations {
+    fn new(operations: Vec<Operation>) -> std::result::Result<Self, String> {
+        let descriptions = operations
+            .iter()
+            .map(|op| {
+                CString::new(op.description.as_str()).map_err(|_| {
+                    format!(
+                        "tx_id {}: DESCRIPTION contains a NUL byte and can't be passed to C",
+                        op.tx_id
+                    )
+                })
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(YpbOperations {
+            operations,
+            descriptions,
+        })
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // NUL посреди сообщения (например, из пути) обрезал бы его в C
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Выполняет тело функции C ABI: ошибку и панику превращает в [`YPB_ERROR`]
+fn ffi_call(f: impl FnOnce() -> std::result::Result<(), String>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => YPB_OK,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            YPB_ERROR
+        }
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(format!("panic: {}", message));
+            YPB_ERROR
+        }
+    }
+}
+
+fn read_operations(path: &Path, format: c_int) -> Result<Vec<Operation>> {
+    let format = match format {
+        YPB_FORMAT_AUTO => detect_file_format(path)?,
+        YPB_FORMAT_BIN => Format::Bin,
+        YPB_FORMAT_CSV => Format::Csv,
+        YPB_FORMAT_TXT => Format::Txt,
+        #[cfg(feature = "serde")]
+        YPB_FORMAT_JSON => Format::Json,
+        #[cfg(feature = "serde")]
+        YPB_FORMAT_JSONL => Format::Jsonl,
+        other => {
+            return Err(crate::error::ParseError::InvalidFormat(format!(
+                "unknown format code {}, expected YPB_FORMAT_AUTO..YPB_FORMAT_JSONL",
+                other
+            )));
+        }
+    };
+    let (operations, _) = read_file_ordered(path, format, &ParseOptions::default())?;
+    Ok(operations)
+}
+
+/// Как у конвертера: содержимое надежнее расширения
+fn detect_file_format(path: &Path) -> Result<Format> {
+    with_path(path, || {
+        let mut prefix = Vec::new();
+        File::open(path)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut prefix)?;
+        detect_format(&prefix).or_else(|e| Format::from_extension(path).ok_or(e))
+    })
+}
+
+/// Читает файл и кладет хэндл с операциями в `*out`
+///
+/// `format` — одна из констант `YPB_FORMAT_*`. При ошибке `*out` — NULL.
+///
+/// # Safety
+/// `path` — NULL или строка с завершающим NUL, `out` — NULL или указатель, куда
+/// можно записать хэндл.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ypb_parse_file(
+    path: *const c_char,
+    format: c_int,
+    out: *mut *mut YpbOperations,
+) -> c_int {
+    ffi_call(|| {
+        if out.is_null() {
+            return Err("out must not be NULL".to_string());
+        }
+        // SAFETY: out не NULL и по контракту указывает на место под хэндл
+        unsafe { *out = ptr::null_mut() };
+        if path.is_null() {
+            return Err("path must not be NULL".to_string());
+        }
+        // SAFETY: path не NULL и по контракту завершается NUL
+        let path = unsafe { CStr::from_ptr(path) }
+            .to_str()
+            .map_err(|_| "path is not valid UTF-8".to_string())?;
+        let operations = read_operations(Path::new(path), format).map_err(|e| e.to_string())?;
+        let handle = YpbOperations::new(operations)?;
+        // SAFETY: см. выше
+        unsafe { *out = Box::into_raw(Box::new(handle)) };
+        Ok(())
+    })
+}
+
+/// Сколько операций в хэндле; у NULL — 0
+///
+/// # Safety
+/// `handle` — NULL или хэндл из [`ypb_parse_file`], еще не переданный в [`ypb_free`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ypb_count(handle: *const YpbOperations) -> usize {
+    // SAFETY: по контракту handle — NULL или живой хэндл
+    match unsafe { handle.as_ref() } {
+        Some(handle) => handle.operations.len(),
+        None => 0,
+    }
+}
+
+/// Копирует операцию с номером `index` (с 0) в `*out`
+///
+/// # Safety
+/// `handle` — NULL или живой хэндл из [`ypb_parse_file`], `out` — NULL или
+/// указатель на [`YpbOperation`]. `out->description` живет, пока жив хэндл.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ypb_get(
+    handle: *const YpbOperations,
+    index: usize,
+    out: *mut YpbOperation,
+) -> c_int {
+    ffi_call(|| {
+        // SAFETY: по контракту handle — NULL или живой хэндл
+        let handle = unsafe { handle.as_ref() }.ok_or("handle must not be NULL")?;
+        if out.is_null() {
+            return Err("out must not be NULL".to_string());
+        }
+        let op = handle.operations.get(index).ok_or_else(|| {
+            format!(
+                "index {} is out of range, the handle has {} operations",
+                index,
+                handle.operations.len()
+            )
+        })?;
+        let operation = YpbOperation {
+            tx_id: op.tx_id,
+            // Разбор строгий, незнакомых значений в хэндле нет
+            tx_type: op.tx_type.known_code().unwrap_or(u8::MAX),
+            from_user_id: op.from_user_id,
+            to_user_id: op.to_user_id,
+            amount: op.amount,
+            timestamp: op.timestamp,
+            status: op.status.known_code().unwrap_or(u8::MAX),
+            description: handle.descriptions[index].as_ptr(),
+        };
+        // SAFETY: out не NULL и по контракту указывает на YpbOperation
+        unsafe { *out = operation };
+        Ok(())
+    })
+}
+
+/// Текст последней ошибки в этом потоке или NULL, если ошибок не было
+///
+/// Строка принадлежит библиотеке и живет до следующей ошибки в этом же потоке.
+#[unsafe(no_mangle)]
+pub extern "C" fn ypb_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Освобождает хэндл вместе со всеми описаниями; NULL можно
+///
+/// # Safety
+/// `handle` — NULL или хэндл из [`ypb_parse_file`], освобождаемый один раз.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ypb_free(handle: *mut YpbOperations) {
+    if !handle.is_null() {
+        // SAFETY: хэндл создан Box::into_raw в ypb_parse_file и еще не освобожден
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::write_file;
+    use crate::operation::OperationStatus;
+    use crate::options::WriteOptions;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parser_ffi_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn operations() -> Vec<Operation> {
+        vec![
+            Operation::withdrawal(7, 500)
+                .tx_id(2)
+                .timestamp(1633036800000)
+                .description("Банкомат, \"центр\"")
+                .build()
+                .unwrap(),
+            Operation::deposit(7, 1000)
+                .tx_id(1)
+                .timestamp(1633036700000)
+                .status(OperationStatus::Success)
+                .build()
+                .unwrap(),
+        ]
+    }
+
+    fn parse(path: &Path, format: c_int) -> (c_int, *mut YpbOperations) {
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let mut handle = ptr::NonNull::dangling().as_ptr();
+        let code = unsafe { ypb_parse_file(path.as_ptr(), format, &mut handle) };
+        (code, handle)
+    }
+
+    fn last_error() -> String {
+        let message = ypb_last_error_message();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_get_and_free() {
+        let dir = test_dir("ok");
+        let expected = operations();
+        // Формат угадывается по содержимому, даже если расширение врет
+        for (name, format, code) in [
+            ("ops.csv", Format::Csv, YPB_FORMAT_AUTO),
+            ("ops", Format::Bin, YPB_FORMAT_AUTO),
+            ("bin_inside.csv", Format::Bin, YPB_FORMAT_AUTO),
+            ("ops.data", Format::Txt, YPB_FORMAT_TXT),
+        ] {
+            let path = dir.join(name);
+            write_file(&path, &expected, format, &WriteOptions::default()).unwrap();
+
+            let (result, handle) = parse(&path, code);
+            assert_eq!(result, YPB_OK, "{}", name);
+            assert_eq!(unsafe { ypb_count(handle) }, 2);
+
+            let mut op = YpbOperation {
+                tx_id: 0,
+                tx_type: 0,
+                from_user_id: 0,
+                to_user_id: 0,
+                amount: 0,
+                timestamp: 0,
+                status: 0,
+                description: ptr::null(),
+            };
+            for (i, expected) in expected.iter().enumerate() {
+                assert_eq!(unsafe { ypb_get(handle, i, &mut op) }, YPB_OK);
+                assert_eq!(op.tx_id, expected.tx_id, "{}", name);
+                assert_eq!(op.tx_type, expected.tx_type.to_u8().unwrap());
+                assert_eq!(op.from_user_id, expected.from_user_id);
+                assert_eq!(op.to_user_id, expected.to_user_id);
+                assert_eq!(op.amount, expected.amount);
+                assert_eq!(op.timestamp, expected.timestamp);
+                assert_eq!(op.status, expected.status.to_u8().unwrap());
+                let description = unsafe { CStr::from_ptr(op.description) };
+                assert_eq!(description.to_str().unwrap(), expected.description);
+            }
+            unsafe { ypb_free(handle) };
+        }
+        unsafe { ypb_free(ptr::null_mut()) };
+        assert_eq!(unsafe { ypb_count(ptr::null()) }, 0);
+    }
+
+    #[test]
+    fn test_errors_are_reported_not_panicked() {
+        assert!(ypb_last_error_message().is_null());
+        let dir = test_dir("errors");
+
+        let missing = dir.join("missing.bin");
+        let (result, handle) = parse(&missing, YPB_FORMAT_AUTO);
+        assert_eq!(result, YPB_ERROR);
+        assert!(handle.is_null());
+        assert!(last_error().contains("missing.bin"));
+
+        let path = dir.join("ops.csv");
+        write_file(&path, &operations(), Format::Csv, &WriteOptions::default()).unwrap();
+        let (result, handle) = parse(&path, 42);
+        assert_eq!(result, YPB_ERROR);
+        assert!(handle.is_null());
+        assert!(last_error().contains("unknown format code 42"));
+
+        let (result, handle) = parse(&path, YPB_FORMAT_BIN);
+        assert_eq!(result, YPB_ERROR);
+        assert!(handle.is_null());
+
+        let (result, handle) = parse(&path, YPB_FORMAT_CSV);
+        assert_eq!(result, YPB_OK);
+        let mut op = std::mem::MaybeUninit::<YpbOperation>::uninit();
+        assert_eq!(unsafe { ypb_get(handle, 2, op.as_mut_ptr()) }, YPB_ERROR);
+        assert!(last_error().contains("index 2 is out of range, the handle has 2"));
+        assert_eq!(unsafe { ypb_get(handle, 0, ptr::null_mut()) }, YPB_ERROR);
+        assert_eq!(
+            unsafe { ypb_get(ptr::null(), 0, op.as_mut_ptr()) },
+            YPB_ERROR
+        );
+        unsafe { ypb_free(handle) };
+
+        let code = unsafe { ypb_parse_file(ptr::null(), YPB_FORMAT_AUTO, ptr::null_mut()) };
+        assert_eq!(code, YPB_ERROR);
+        assert!(last_error().contains("out must not be NULL"));
+    }
+
+    #[test]
+    fn test_nul_in_description_is_an_error() {
+        let dir = test_dir("nul");
+        let path = dir.join("ops.csv");
+        let mut ops = operations();
+        ops[0].description = "a\0b".to_string();
+        write_file(&path, &ops, Format::Csv, &WriteOptions::default()).unwrap();
+
+        let (result, handle) = parse(&path, YPB_FORMAT_AUTO);
+        assert_eq!(result, YPB_ERROR);
+        assert!(handle.is_null());
+        assert!(last_error().contains("tx_id 2: DESCRIPTION contains a NUL byte"));
+    }
+
+    #[test]
+    fn test_panic_does_not_cross_the_boundary() {
+        assert_eq!(ffi_call(|| panic!("boom")), YPB_ERROR);
+        assert_eq!(last_error(), "panic: boom");
+    }
+}
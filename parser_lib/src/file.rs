@@ -0,0 +1,454 @@
+use crate::bin_format::{self, AppendOutcome};
+use crate::error::{ParseError, Result};
+use crate::format::{Format, OperationFormat, SNIFF_LEN, detect_format};
+use crate::operation::Operation;
+use crate::options::{ParseOptions, WriteOptions};
+use crate::provenance::Provenance;
+use crate::report::ParseReport;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// Читает файл, определяя формат по расширению, а если не вышло — по содержимому
+///
+/// # Возвращает
+/// * `Ok(HashSet<Operation>)` - Операции из файла
+/// * `Err(ParseError::File)` - Любая ошибка, с путем к файлу
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<HashSet<Operation>> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let mut reader = open_buffered(path)?;
+
+        let format = match Format::from_extension(path) {
+            Some(format) => format,
+            None => {
+                let mut prefix = Vec::new();
+                (&mut reader)
+                    .take(SNIFF_LEN as u64)
+                    .read_to_end(&mut prefix)?;
+                let format = detect_format(&prefix)?;
+                return format.parse_all(&mut Cursor::new(prefix).chain(reader));
+            }
+        };
+
+        format.parse_all(&mut reader)
+    })
+}
+
+/// Читает файл заданного формата
+pub fn read_file_as<P: AsRef<Path>>(path: P, format: Format) -> Result<HashSet<Operation>> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let mut reader = open_buffered(path)?;
+        format.parse_all(&mut reader)
+    })
+}
+
+/// Читает файл заданного формата с настройками разбора
+///
+/// # Возвращает
+/// Операции и отчет с предупреждениями (например, о пересчете TIMESTAMP из секунд)
+pub fn read_file_with_report<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let reader = open_buffered(path)?;
+        format.parse_all_with_report(reader, options)
+    })
+}
+
+/// Читает файл заданного формата в порядке записей, см. [`Format::parse_all_ordered`]
+pub fn read_file_ordered<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<(Vec<Operation>, ParseReport)> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let reader = open_buffered(path)?;
+        format.parse_all_ordered(reader, options)
+    })
+}
+
+/// Читает файл в порядке записей, помечая каждую запись путем, строкой и смещением
+pub fn read_file_with_provenance<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    let path = path.as_ref();
+    let source = path.display().to_string();
+    with_path(path, || {
+        let reader = open_buffered(path)?;
+        format.parse_all_with_provenance(reader, Some(&source), options)
+    })
+}
+
+/// Итог [`parse_files_parallel`]
+#[cfg(feature = "parallel")]
+#[derive(Debug, Default)]
+pub struct ParallelParse {
+    /// Операции всех прочитанных файлов; при повторе TX_ID остается запись
+    /// из файла, который раньше в списке
+    pub operations: HashSet<Operation>,
+    /// Сколько файлов прочитано без ошибок
+    pub files_read: usize,
+    /// Ошибки нечитаемых файлов в порядке списка, каждая — [`ParseError::File`] с путем
+    pub errors: Vec<ParseError>,
+}
+
+/// Читает много файлов сразу в пуле rayon и сливает операции
+///
+/// `format` — формат всех файлов; `None` — у каждого свой, как в [`read_file`].
+/// Нечитаемый файл не прерывает остальные: его ошибка уходит в
+/// [`ParallelParse::errors`], а операции остальных сливаются как обычно.
+#[cfg(feature = "parallel")]
+pub fn parse_files_parallel<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    format: Option<Format>,
+) -> ParallelParse {
+    use rayon::prelude::*;
+
+    let parsed: Vec<Result<HashSet<Operation>>> = paths
+        .par_iter()
+        .map(|path| match format {
+            Some(format) => read_file_as(path, format),
+            None => read_file(path),
+        })
+        .collect();
+
+    let mut result = ParallelParse::default();
+    for file in parsed {
+        match file {
+            Ok(operations) => {
+                result.files_read += 1;
+                // extend не заменяет уже вставленные записи
+                result.operations.extend(operations);
+            }
+            Err(e) => result.errors.push(e),
+        }
+    }
+    result
+}
+
+/// Записывает операции в файл атомарно
+///
+/// Пишет через буфер во временный файл рядом с целевым, делает fsync
+/// (если `options.sync`) и переименовывает поверх `path`. При ошибке
+/// временный файл удаляется, а старое содержимое `path` остается нетронутым.
+/// Операции пишутся в порядке `operations`.
+pub fn write_file<'a, P: AsRef<Path>>(
+    path: P,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    format: Format,
+    options: &WriteOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let tmp_path = temp_path(path);
+
+        let gzip = is_gzip_path(path);
+        let result = write_temp(&tmp_path, operations, format, options, gzip)
+            .and_then(|()| fs::rename(&tmp_path, path).map_err(ParseError::from));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return result;
+        }
+
+        if options.sync {
+            sync_parent_dir(path)?;
+        }
+        Ok(())
+    })
+}
+
+/// Дописывает операции в бинарный файл, см. [`bin_format::append_operations`]
+///
+/// Файла нет — он создается.
+pub fn append_file<'a, P: AsRef<Path>>(
+    path: P,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<AppendOutcome> {
+    let path = path.as_ref();
+    with_path(path, || {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        bin_format::append_operations(&mut file, operations, options)
+    })
+}
+
+/// Открывает файл на чтение через буфер; сжатый gzip (фича `gzip`) распаковывается на лету
+fn open_buffered(path: &Path) -> Result<Box<dyn BufRead>> {
+    let reader = BufReader::new(File::open(path)?);
+    #[cfg(feature = "gzip")]
+    return Ok(crate::gzip::decompress_if_gzip(reader)?);
+    #[cfg(not(feature = "gzip"))]
+    Ok(Box::new(reader))
+}
+
+/// Путь `*.gz`; без фичи `gzip` такие файлы пишутся как есть
+#[cfg(feature = "gzip")]
+fn is_gzip_path(path: &Path) -> bool {
+    crate::gzip::is_gzip_path(path)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn is_gzip_path(_path: &Path) -> bool {
+    false
+}
+
+fn write_temp<'a>(
+    tmp_path: &Path,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    format: Format,
+    options: &WriteOptions,
+    gzip: bool,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(tmp_path)?);
+    if gzip {
+        #[cfg(feature = "gzip")]
+        {
+            writer = crate::gzip::write_all(writer, operations, format, options)?;
+        }
+    } else {
+        format.write_all_with_options(&mut writer, operations, options)?;
+    }
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    if options.sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp-{}", name, std::process::id()))
+}
+
+/// После rename нужно синхронизировать и каталог, иначе новая запись в нем может потеряться
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+pub(crate) fn with_path<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f().map_err(|e| ParseError::File {
+        path: path.to_path_buf(),
+        source: Box::new(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parser_file_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample() -> HashSet<Operation> {
+        [Operation::transfer(1, 2, 300)
+            .tx_id(42)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("To a friend, \"thanks\"")
+            .build()
+            .unwrap()]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_write_and_read_by_extension() {
+        let dir = test_dir("ext");
+        let ops = sample();
+
+        for (name, format) in [
+            ("a.bin", Format::Bin),
+            ("a.csv", Format::Csv),
+            ("a.TXT", Format::Txt),
+        ] {
+            let path = dir.join(name);
+            write_file(&path, &ops, format, &WriteOptions::default()).unwrap();
+            let parsed = read_file(&path).unwrap();
+            let expected = ops.iter().next().unwrap();
+            let actual = parsed.get(expected).unwrap();
+            assert_eq!(actual.description, expected.description);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_sniffs_unknown_extension() {
+        let dir = test_dir("sniff");
+        let ops = sample();
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let path = dir.join("export.dat");
+            write_file(&path, &ops, format, &WriteOptions::default()).unwrap();
+            assert_eq!(read_file(&path).unwrap().len(), 1);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parse_files_parallel_merges_and_reports_each_error() {
+        let dir = test_dir("parallel");
+        let mut paths = Vec::new();
+        for (i, (name, format)) in [
+            ("part0.bin", Format::Bin),
+            ("part1.csv", Format::Csv),
+            ("part2.txt", Format::Txt),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let ops: HashSet<Operation> = sample()
+                .into_iter()
+                .map(|mut op| {
+                    op.tx_id = i as u64;
+                    op
+                })
+                .collect();
+            let path = dir.join(name);
+            write_file(&path, &ops, format, &WriteOptions::default()).unwrap();
+            paths.push(path);
+        }
+        // Тот же TX_ID 0, что в первом файле: остается запись из первого
+        let mut later = sample().into_iter().next().unwrap();
+        later.tx_id = 0;
+        later.amount = 999;
+        let duplicate = dir.join("dup.csv");
+        write_file(&duplicate, [&later], Format::Csv, &WriteOptions::default()).unwrap();
+        paths.push(duplicate);
+        let broken = dir.join("broken.csv");
+        fs::write(&broken, "not a csv file").unwrap();
+        paths.push(broken.clone());
+        paths.push(dir.join("missing.bin"));
+
+        let result = parse_files_parallel(&paths, None);
+        assert_eq!(result.files_read, 4);
+        let mut ids: Vec<u64> = result.operations.iter().map(|op| op.tx_id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(result.operations.get(&later).unwrap().amount, 300);
+
+        let failed: Vec<&Path> = result
+            .errors
+            .iter()
+            .map(|e| match e {
+                ParseError::File { path, .. } => path.as_path(),
+                other => panic!("{}", other),
+            })
+            .collect();
+        assert_eq!(
+            failed,
+            vec![broken.as_path(), dir.join("missing.bin").as_path()]
+        );
+
+        // С заданным форматом бинарник не читается как CSV
+        let forced = parse_files_parallel(&paths[..2], Some(Format::Csv));
+        assert_eq!(forced.files_read, 1);
+        assert_eq!(forced.errors.len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gz_files_are_compressed_and_read_back() {
+        let dir = test_dir("gzip");
+        let ops = sample();
+
+        for name in ["a.bin.gz", "a.csv.gz", "a.txt.gz", "a.json.gz"] {
+            let path = dir.join(name);
+            let format = Format::from_extension(&path).unwrap();
+            write_file(&path, &ops, format, &WriteOptions::default()).unwrap();
+            assert!(
+                fs::read(&path)
+                    .unwrap()
+                    .starts_with(&crate::gzip::GZIP_MAGIC)
+            );
+
+            let parsed = read_file(&path).unwrap();
+            assert_eq!(parsed, ops, "{}", name);
+            let (ordered, _) = read_file_ordered(&path, format, &ParseOptions::default()).unwrap();
+            assert_eq!(ordered.len(), 1);
+        }
+
+        // Сжатый файл без .gz: формат находится по распакованному содержимому
+        let renamed = dir.join("export.dat");
+        fs::rename(dir.join("a.csv.gz"), &renamed).unwrap();
+        assert_eq!(read_file(&renamed).unwrap(), ops);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_errors_include_path() {
+        let dir = test_dir("missing");
+        let path = dir.join("nope.csv");
+
+        let err = read_file(&path).unwrap_err();
+
+        assert!(matches!(&err, ParseError::File { path: p, .. } if p == &path));
+        assert!(err.to_string().contains("nope.csv"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_write_keeps_old_file() {
+        let dir = test_dir("atomic");
+        let path = dir.join("out.csv");
+        write_file(&path, &sample(), Format::Csv, &WriteOptions::default()).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let mut invalid = sample().into_iter().next().unwrap();
+        invalid.from_user_id = 0;
+        let invalid: HashSet<Operation> = [invalid].into_iter().collect();
+
+        assert!(write_file(&path, &invalid, Format::Csv, &WriteOptions::default()).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
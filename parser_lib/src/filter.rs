@@ -0,0 +1,267 @@
+//! Отбор операций по условиям
+//!
+//! Все границы диапазонов включительные с обеих сторон: `amount_range(100, 200)`
+//! пропускает и 100, и 200. Условия объединяются через И, незаданное условие
+//! пропускает любую операцию; повторный вызов того же метода заменяет условие.
+
+use crate::error::Result;
+use crate::operation::{Operation, OperationStatus, OperationType};
+
+/// Набор условий отбора операций
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationFilter {
+    tx_type: Option<OperationType>,
+    status: Option<OperationStatus>,
+    user: Option<u64>,
+    from_user: Option<u64>,
+    to_user: Option<u64>,
+    min_amount: Option<i64>,
+    max_amount: Option<i64>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+impl OperationFilter {
+    /// Фильтр без условий: пропускает все
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Нет ни одного условия
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// TX_TYPE равен `tx_type`
+    pub fn tx_type(mut self, tx_type: OperationType) -> Self {
+        self.tx_type = Some(tx_type);
+        self
+    }
+
+    /// STATUS равен `status`
+    pub fn status(mut self, status: OperationStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Пользователь `user_id` с любой стороны: FROM_USER_ID или TO_USER_ID
+    pub fn user(mut self, user_id: u64) -> Self {
+        self.user = Some(user_id);
+        self
+    }
+
+    /// FROM_USER_ID равен `user_id`
+    pub fn from_user(mut self, user_id: u64) -> Self {
+        self.from_user = Some(user_id);
+        self
+    }
+
+    /// TO_USER_ID равен `user_id`
+    pub fn to_user(mut self, user_id: u64) -> Self {
+        self.to_user = Some(user_id);
+        self
+    }
+
+    /// AMOUNT не меньше `min`
+    pub fn min_amount(mut self, min: i64) -> Self {
+        self.min_amount = Some(min);
+        self
+    }
+
+    /// AMOUNT не больше `max`
+    pub fn max_amount(mut self, max: i64) -> Self {
+        self.max_amount = Some(max);
+        self
+    }
+
+    /// AMOUNT в `[min, max]`
+    pub fn amount_range(self, min: i64, max: i64) -> Self {
+        self.min_amount(min).max_amount(max)
+    }
+
+    /// TIMESTAMP не раньше `since` (мс)
+    pub fn since(mut self, since: u64) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// TIMESTAMP не позже `until` (мс)
+    pub fn until(mut self, until: u64) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// TIMESTAMP в `[since, until]` (мс)
+    pub fn timestamp_range(self, since: u64, until: u64) -> Self {
+        self.since(since).until(until)
+    }
+
+    /// Подходит ли операция под все заданные условия
+    pub fn matches(&self, op: &Operation) -> bool {
+        self.tx_type
+            .as_ref()
+            .is_none_or(|tx_type| op.tx_type == *tx_type)
+            && self
+                .status
+                .as_ref()
+                .is_none_or(|status| op.status == *status)
+            && self
+                .user
+                .is_none_or(|user| op.from_user_id == user || op.to_user_id == user)
+            && self.from_user.is_none_or(|user| op.from_user_id == user)
+            && self.to_user.is_none_or(|user| op.to_user_id == user)
+            && self.min_amount.is_none_or(|min| op.amount >= min)
+            && self.max_amount.is_none_or(|max| op.amount <= max)
+            && self.since.is_none_or(|since| op.timestamp >= since)
+            && self.until.is_none_or(|until| op.timestamp <= until)
+    }
+
+    /// Отбор на лету поверх потокового разбора (`iter_operations` форматов)
+    ///
+    /// Ошибки разбора проходят как есть, чтобы вызывающий их увидел.
+    pub fn apply<I>(&self, operations: I) -> impl Iterator<Item = Result<Operation>> + use<I>
+    where
+        I: IntoIterator<Item = Result<Operation>>,
+    {
+        let filter = self.clone();
+        operations.into_iter().filter(move |result| match result {
+            Ok(op) => filter.matches(op),
+            Err(_) => true,
+        })
+    }
+
+    /// Оставляет в коллекции только подходящие операции
+    ///
+    /// # Возвращает
+    /// Сколько операций было отброшено
+    pub fn retain<C>(&self, operations: &mut C) -> usize
+    where
+        C: Extend<Operation> + IntoIterator<Item = Operation> + Default,
+    {
+        let all = std::mem::take(operations);
+        let mut excluded = 0;
+        operations.extend(all.into_iter().filter(|op| {
+            let keep = self.matches(op);
+            if !keep {
+                excluded += 1;
+            }
+            keep
+        }));
+        excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_format;
+    use std::collections::HashSet;
+
+    fn op(tx_id: u64, amount: i64, timestamp: u64) -> Operation {
+        Operation::deposit(1, amount)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(OperationStatus::Success)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        assert!(OperationFilter::new().matches(&op(1, i64::MIN, 0)));
+    }
+
+    #[test]
+    fn test_bounds_are_inclusive() {
+        let filter = OperationFilter::new()
+            .amount_range(100, 200)
+            .timestamp_range(1000, 2000);
+
+        assert!(filter.matches(&op(1, 100, 1000)));
+        assert!(filter.matches(&op(2, 200, 2000)));
+        assert!(!filter.matches(&op(3, 99, 1500)));
+        assert!(!filter.matches(&op(4, 201, 1500)));
+        assert!(!filter.matches(&op(5, 150, 999)));
+        assert!(!filter.matches(&op(6, 150, 2001)));
+    }
+
+    #[test]
+    fn test_retain_counts_excluded() {
+        let mut ops: HashSet<Operation> = [op(1, 0, 10), op(2, 5, 20), op(3, 5, 30)]
+            .into_iter()
+            .collect();
+
+        let excluded = OperationFilter::new()
+            .min_amount(1)
+            .until(20)
+            .retain(&mut ops);
+        assert_eq!(excluded, 2);
+        assert_eq!(ops.iter().map(|op| op.tx_id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    fn withdrawal(tx_id: u64, user: u64, status: OperationStatus) -> Operation {
+        Operation::withdrawal(user, 100)
+            .tx_id(tx_id)
+            // 2021-09-15
+            .timestamp(1631664000000)
+            .status(status)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_type_status_and_users_combine_with_and() {
+        // Все неудачные снятия пользователя 42 в сентябре 2021
+        let filter = OperationFilter::new()
+            .tx_type(OperationType::Withdrawal)
+            .status(OperationStatus::Failure)
+            .user(42)
+            .timestamp_range(1630454400000, 1633046399999);
+
+        assert!(filter.matches(&withdrawal(1, 42, OperationStatus::Failure)));
+        assert!(!filter.matches(&withdrawal(2, 42, OperationStatus::Success)));
+        assert!(!filter.matches(&withdrawal(3, 7, OperationStatus::Failure)));
+        assert!(!filter.matches(&op(4, 100, 1631664000000)));
+        let mut october = withdrawal(5, 42, OperationStatus::Failure);
+        october.timestamp = 1633046400000;
+        assert!(!filter.matches(&october));
+    }
+
+    #[test]
+    fn test_user_matches_either_side() {
+        let deposit = op(1, 100, 0);
+        let withdrawal = withdrawal(2, 1, OperationStatus::Success);
+
+        assert!(OperationFilter::new().user(1).matches(&deposit));
+        assert!(OperationFilter::new().user(1).matches(&withdrawal));
+        assert!(OperationFilter::new().to_user(1).matches(&deposit));
+        assert!(!OperationFilter::new().to_user(1).matches(&withdrawal));
+        assert!(OperationFilter::new().from_user(1).matches(&withdrawal));
+        assert!(!OperationFilter::new().from_user(1).matches(&deposit));
+        // Повторный вызов заменяет условие, а не добавляет "или"
+        assert!(!OperationFilter::new().user(1).user(2).matches(&deposit));
+    }
+
+    #[test]
+    fn test_is_empty() {
+        assert!(OperationFilter::new().is_empty());
+        assert!(!OperationFilter::new().user(0).is_empty());
+    }
+
+    #[test]
+    fn test_apply_filters_a_stream_and_keeps_errors() {
+        let csv = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                   1,WITHDRAWAL,42,0,100,1631664000000,FAILURE,\"ATM\"\n\
+                   2,WITHDRAWAL,42,0,100,1631664000000,SUCCESS,\"ATM\"\n\
+                   3,WITHDRAWAL,42,0,lots,1631664000000,FAILURE,\"ATM\"\n";
+        let filter = OperationFilter::new().status(OperationStatus::Failure);
+
+        let results: Vec<_> = filter
+            .apply(csv_format::iter_operations(csv.as_bytes()))
+            .collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().tx_id, 1);
+        assert!(results[1].is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Контрольная строка в конце файла: число записей и сумма AMOUNT
+//!
+//! Позволяет заметить обрезанный файл без полного сравнения с источником.
+//! CSV пишет `#TOTAL,<count>,<sum>`, текстовый формат — комментарии
+//! `# RECORDS: n` и `# TOTAL_AMOUNT: s`. Бинарный формат футера не имеет.
+
+use crate::error::{ParseError, Result};
+use crate::operation::Operation;
+use std::fmt;
+
+/// Итоги по записям файла
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Footer {
+    /// Сколько записей (с повторами TX_ID)
+    pub records: u64,
+    /// Сумма AMOUNT; в i128, чтобы не переполниться на больших файлах
+    pub total_amount: i128,
+}
+
+impl Footer {
+    /// Итоги по набору операций
+    pub fn of<'a>(operations: impl IntoIterator<Item = &'a Operation>) -> Self {
+        let mut footer = Footer::default();
+        for operation in operations {
+            footer.add(operation);
+        }
+        footer
+    }
+
+    pub(crate) fn add(&mut self, operation: &Operation) {
+        self.records += 1;
+        self.total_amount += i128::from(operation.amount);
+    }
+
+    #[cfg(feature = "parallel")]
+    pub(crate) fn merge(&mut self, other: Footer) {
+        self.records += other.records;
+        self.total_amount += other.total_amount;
+    }
+
+    /// Сверяет объявленный в файле футер с посчитанным; без футера проверять нечего
+    pub(crate) fn verify(declared: Option<Footer>, actual: Footer) -> Result<()> {
+        match declared {
+            Some(expected) if expected != actual => {
+                Err(ParseError::FooterMismatch { expected, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Footer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records, total amount {}",
+            self.records, self.total_amount
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    #[test]
+    fn test_footer_sums_without_overflow() {
+        let op = |amount| {
+            Operation::deposit(1, amount)
+                .tx_id(1)
+                .status(OperationStatus::Success)
+                .build()
+                .unwrap()
+        };
+        let ops = [op(i64::MAX), op(i64::MAX), op(-1)];
+
+        let footer = Footer::of(&ops);
+        assert_eq!(footer.records, 3);
+        assert_eq!(footer.total_amount, 2 * i128::from(i64::MAX) - 1);
+
+        assert!(Footer::verify(None, footer).is_ok());
+        assert!(Footer::verify(Some(footer), footer).is_ok());
+        assert!(matches!(
+            Footer::verify(Some(Footer::default()), footer),
+            Err(ParseError::FooterMismatch { .. })
+        ));
+    }
+}
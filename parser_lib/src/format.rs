@@ -0,0 +1,604 @@
+use crate::dedup::{DedupOutcome, Deduplicator, DuplicatePolicy};
+use crate::error::{ParseError, Result};
+use crate::footer::Footer;
+use crate::operation::{FullOperation, Operation};
+use crate::options::{ParseOptions, WriteOptions};
+use crate::progress::{Progress, ProgressTicker};
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{CountingReader, ParseReport};
+use crate::{bin_format, csv_format, text_format};
+#[cfg(feature = "serde")]
+use crate::{json_format, jsonl_format};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// Общий интерфейс формата операций
+///
+/// Нужен, чтобы код поверх форматов (конвертация, проверки соответствия)
+/// не зависел от конкретного модуля. Сторонний формат достаточно
+/// реализовать через этот трейт.
+pub trait OperationFormat {
+    /// Короткое имя формата для сообщений ("bin", "csv", "txt")
+    fn name(&self) -> &str;
+
+    /// Читает все операции из потока
+    fn parse_all(&self, reader: &mut dyn Read) -> Result<HashSet<Operation>>;
+
+    /// Записывает все операции в поток
+    fn write_all(&self, writer: &mut dyn Write, operations: &HashSet<Operation>) -> Result<()>;
+}
+
+/// Метка порядка байт UTF-8, которую Excel под Windows ставит в начало файла
+pub(crate) const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// Пропускает BOM в начале текстового потока
+///
+/// # Возвращает
+/// Сколько байт пропущено (0 или 3), чтобы смещения записей остались смещениями в файле
+pub(crate) fn skip_bom<R: BufRead>(reader: &mut R) -> Result<u64> {
+    if reader.fill_buf()?.starts_with(&UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        return Ok(UTF8_BOM.len() as u64);
+    }
+    Ok(0)
+}
+
+/// [`skip_bom`] для асинхронного потока
+#[cfg(feature = "async")]
+pub(crate) async fn skip_bom_async<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<u64> {
+    use tokio::io::AsyncBufReadExt;
+
+    if reader.fill_buf().await?.starts_with(&UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        return Ok(UTF8_BOM.len() as u64);
+    }
+    Ok(0)
+}
+
+/// Встроенные форматы библиотеки
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// YPBankBin
+    Bin,
+    /// YPBankCsv
+    Csv,
+    /// YPBankText
+    Txt,
+    /// YPBankJson, только с фичей `serde`
+    #[cfg(feature = "serde")]
+    Json,
+    /// YPBankJsonl (JSON Lines), только с фичей `serde`
+    #[cfg(feature = "serde")]
+    Jsonl,
+}
+
+/// Сколько первых байт достаточно для [`detect_format`] и [`Format::sniff`]
+pub const SNIFF_LEN: usize = 512;
+
+/// Определяет формат по первым байтам потока, когда имени файла нет
+///
+/// Поток не трогает: прочитайте до [`SNIFF_LEN`] байт, определите формат и
+/// разбирайте `Cursor::new(prefix).chain(reader)`. CSV отличается от текста по
+/// первой строке данных: у CSV это заголовок с колонкой TX_ID через `,`, `;` или
+/// табуляцию, у текста — `KEY: value`. BOM и комментарии перед ней пропускаются.
+///
+/// # Возвращает
+/// * `Ok(Format)` - Определенный формат
+/// * `Err(ParseError::EmptyInput)` - В префиксе нет ничего, кроме BOM и пробелов
+/// * `Err(ParseError::InvalidFormat)` - Содержимое не похоже ни на один формат
+pub fn detect_format(prefix: &[u8]) -> Result<Format> {
+    if let Some(format) = Format::sniff(prefix) {
+        return Ok(format);
+    }
+    let content = prefix.strip_prefix(&UTF8_BOM).unwrap_or(prefix);
+    if content.iter().all(u8::is_ascii_whitespace) {
+        return Err(ParseError::EmptyInput);
+    }
+    Err(ParseError::InvalidFormat(
+        "Can't detect format: expected YPBN/YPBF magic, a CSV header with TX_ID, \
+         JSON or a KEY: value line"
+            .to_string(),
+    ))
+}
+
+/// Строка похожа на заголовок CSV: несколько колонок, одна из них TX_ID
+fn is_csv_header(line: &str) -> bool {
+    [',', ';', '\t'].into_iter().any(|delimiter| {
+        let mut columns = line.split(delimiter);
+        let tx_id = |column: &str| {
+            column
+                .trim()
+                .trim_matches('"')
+                .eq_ignore_ascii_case("TX_ID")
+        };
+        line.contains(delimiter) && columns.any(tx_id)
+    })
+}
+
+impl Format {
+    /// Формат по расширению файла (`.bin`, `.csv`, `.txt`, `.json`, `.jsonl`/`.ndjson`, без учета регистра)
+    ///
+    /// С фичей `gzip` смотрит под `.gz`: `day.csv.gz` — CSV.
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        // day.csv.gz — формат по расширению под .gz
+        #[cfg(feature = "gzip")]
+        if ext == "gz" {
+            return Format::from_extension(Path::new(path.file_stem()?));
+        }
+        match ext.as_str() {
+            "bin" => Some(Format::Bin),
+            "csv" => Some(Format::Csv),
+            "txt" => Some(Format::Txt),
+            #[cfg(feature = "serde")]
+            "json" => Some(Format::Json),
+            #[cfg(feature = "serde")]
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+
+    /// Угадывает формат по первым байтам: MAGIC (записи, заголовка файла или сжатого контейнера), заголовок CSV, `[` JSON, `{` JSONL или строка `KEY: value`
+    pub fn sniff(prefix: &[u8]) -> Option<Format> {
+        if prefix.starts_with(b"YPBN") || prefix.starts_with(b"YPBF") || prefix.starts_with(b"YPBZ")
+        {
+            return Some(Format::Bin);
+        }
+
+        let prefix = prefix.strip_prefix(&UTF8_BOM).unwrap_or(prefix);
+        let text = match std::str::from_utf8(prefix) {
+            Ok(text) => text,
+            // Префикс мог обрезать многобайтовый символ
+            Err(e) => std::str::from_utf8(&prefix[..e.valid_up_to()]).ok()?,
+        };
+
+        #[cfg(feature = "serde")]
+        if text.trim_start().starts_with('[') {
+            return Some(Format::Json);
+        }
+        #[cfg(feature = "serde")]
+        if text.trim_start().starts_with('{') {
+            return Some(Format::Jsonl);
+        }
+
+        // Комментарии (в том числе `#VERSION`) бывают и в CSV, и в тексте
+        let first_line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+        if is_csv_header(first_line) {
+            return Some(Format::Csv);
+        }
+        match first_line.split_once(':') {
+            Some((key, _))
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_uppercase() || c == '_') =>
+            {
+                Some(Format::Txt)
+            }
+            _ => None,
+        }
+    }
+
+    /// Разбор потока этим форматом с настройками
+    pub fn parse_all_with_options<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<HashSet<Operation>> {
+        self.parse_all_with_report(reader, options)
+            .map(|(operations, _)| operations)
+    }
+
+    /// Разбор с настройками и отчетом о предупреждениях
+    ///
+    /// Бинарный формат сам предупреждений не выдает, в его отчете только
+    /// находки [`ParseOptions::validation`].
+    pub fn parse_all_with_report<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<(HashSet<Operation>, ParseReport)> {
+        // Бинарник с заголовком файла заранее знает число записей
+        if let Format::Bin = self {
+            return bin_format::parse_all_with_report(reader, options);
+        }
+        let mut operations = HashSet::new();
+        let report = self.parse_each(reader, options, |operation, _| {
+            operations.insert(operation);
+            Ok(())
+        })?;
+
+        Ok((operations, report))
+    }
+
+    /// Разбор в порядке записей, с отчетом
+    ///
+    /// Повторы TX_ID отбрасываются, как в [`Format::parse_all_with_report`]:
+    /// остается первая запись. Вместе с [`Format::write_all_with_options`]
+    /// дает конвертацию, которая не перемешивает записи.
+    pub fn parse_all_ordered<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<(Vec<Operation>, ParseReport)> {
+        let mut seen = HashSet::new();
+        let mut operations = Vec::new();
+        let report = self.parse_each(reader, options, |operation, _| {
+            if seen.insert(operation.tx_id) {
+                operations.push(operation);
+            }
+            Ok(())
+        })?;
+
+        Ok((operations, report))
+    }
+
+    /// Разбор с отчетом, как [`Format::parse_all_with_report`], и с ходом разбора
+    ///
+    /// `on_progress` зовется раз в [`Progress::EVERY_RECORDS`] записей или
+    /// [`Progress::EVERY_BYTES`] байт и еще раз в конце; долю прочитанного он
+    /// получает, если задана [`ParseOptions::known_size`]. [`ControlFlow::Break`]
+    /// прерывает разбор с [`ParseError::Cancelled`].
+    pub fn parse_all_with_progress<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        on_progress: impl FnMut(Progress) -> ControlFlow<()>,
+    ) -> Result<(HashSet<Operation>, ParseReport)> {
+        let mut operations = HashSet::new();
+        let report = self.parse_each_with_progress(reader, options, on_progress, |operation| {
+            operations.insert(operation);
+        })?;
+        Ok((operations, report))
+    }
+
+    /// [`Format::parse_all_ordered`] с ходом разбора, как в [`Format::parse_all_with_progress`]
+    pub fn parse_all_ordered_with_progress<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        on_progress: impl FnMut(Progress) -> ControlFlow<()>,
+    ) -> Result<(Vec<Operation>, ParseReport)> {
+        let mut seen = HashSet::new();
+        let mut operations = Vec::new();
+        let report = self.parse_each_with_progress(reader, options, on_progress, |operation| {
+            if seen.insert(operation.tx_id) {
+                operations.push(operation);
+            }
+        })?;
+        Ok((operations, report))
+    }
+
+    /// Потоковый разбор с обработчиком хода разбора
+    fn parse_each_with_progress<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        mut on_progress: impl FnMut(Progress) -> ControlFlow<()>,
+        mut on_operation: impl FnMut(Operation),
+    ) -> Result<ParseReport> {
+        let bytes = Cell::new(0);
+        let mut ticker = ProgressTicker::new(options.known_size);
+        let report = self.parse_each(
+            CountingReader::new(reader, &bytes),
+            options,
+            |operation, _| {
+                on_operation(operation);
+                ticker.record(bytes.get(), &mut on_progress)
+            },
+        )?;
+        ticker.finish(bytes.get(), &mut on_progress);
+        Ok(report)
+    }
+
+    /// Запись этим форматом с настройками
+    pub fn write_all_with_options<'a, W: Write>(
+        &self,
+        writer: W,
+        operations: impl IntoIterator<Item = &'a Operation>,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        match self {
+            Format::Bin => bin_format::write_all_with_options(writer, operations, options),
+            Format::Csv => csv_format::write_all_with_options(writer, operations, options),
+            Format::Txt => text_format::write_all_with_options(writer, operations, options),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::write_all_with_options(writer, operations, options),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::write_all_with_options(writer, operations, options),
+        }
+    }
+
+    /// Запись в порядке возрастания TX_ID
+    ///
+    /// Один и тот же набор операций всегда дает одни и те же байты,
+    /// независимо от порядка обхода `HashSet`.
+    pub fn write_all_sorted<W: Write>(
+        &self,
+        writer: W,
+        operations: &HashSet<Operation>,
+    ) -> Result<()> {
+        let mut sorted: Vec<&Operation> = operations.iter().collect();
+        sorted.sort_by_key(|op| op.tx_id);
+        self.write_all_with_options(writer, sorted, &WriteOptions::default())
+    }
+
+    /// Разбор потока этим форматом в порядке записей, с повторами TX_ID
+    pub fn parse_all_vec<R: Read>(&self, reader: R) -> Result<Vec<Operation>> {
+        self.parse_all_vec_with_options(reader, &ParseOptions::default())
+    }
+
+    /// То же, что [`Format::parse_all_vec`], но с настройками разбора
+    pub fn parse_all_vec_with_options<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+    ) -> Result<Vec<Operation>> {
+        let mut operations = Vec::new();
+        self.parse_each(reader, options, |operation, _| {
+            operations.push(operation);
+            Ok(())
+        })?;
+
+        Ok(operations)
+    }
+
+    /// Разбор с политикой для записей с повторяющимся TX_ID, см. [`DuplicatePolicy`]
+    pub fn parse_all_with_policy<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        policy: DuplicatePolicy,
+    ) -> Result<DedupOutcome> {
+        let mut dedup = Deduplicator::new(policy);
+        self.parse_each(reader, options, |operation, _| dedup.push(operation))?;
+        Ok(dedup.finish())
+    }
+
+    /// Сколько раз встречается каждая одинаковая (по всем полям) запись
+    ///
+    /// В памяти по одному экземпляру на каждую различную запись: для файла почти
+    /// без повторов это столько же, сколько [`Format::parse_all_vec`].
+    pub fn parse_all_counted<R: Read>(&self, reader: R) -> Result<HashMap<FullOperation, u64>> {
+        let mut counts = HashMap::new();
+        self.parse_each(reader, &ParseOptions::default(), |operation, _| {
+            *counts.entry(FullOperation(operation)).or_insert(0) += 1;
+            Ok(())
+        })?;
+
+        Ok(counts)
+    }
+
+    /// Читает одну запись с текущей позиции потока
+    ///
+    /// Вместе с [`Provenance::byte_offset`] из [`Format::for_each_record`] дает
+    /// произвольный доступ: запомнить смещения, потом перейти к нужной записи
+    /// через `seek` и прочитать только ее.
+    pub fn parse_one<R: BufRead>(
+        &self,
+        reader: &mut R,
+        options: &ParseOptions,
+    ) -> Result<Operation> {
+        match self {
+            Format::Bin => bin_format::parse_operation_with_options(reader, &options.bin),
+            Format::Csv => csv_format::parse_one(reader, options),
+            Format::Txt => text_format::parse_one(reader, options),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::parse_one(reader, options),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::parse_one(reader, options),
+        }
+    }
+
+    /// Потоковый разбор без накопления: каждая запись с происхождением уходит в `f`
+    ///
+    /// Ошибка из `f` прерывает разбор и возвращается как есть.
+    pub fn for_each_record<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        mut f: impl FnMut(Operation, Provenance) -> Result<()>,
+    ) -> Result<ParseReport> {
+        let mut record_index = 0;
+        self.parse_each(reader, options, |operation, position| {
+            let provenance = Provenance::new(None, record_index, position);
+            record_index += 1;
+            f(operation, provenance)
+        })
+    }
+
+    /// Потоковый разбор: записи по одной в `on_operation`, в конце отчет
+    pub(crate) fn parse_each<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+    ) -> Result<ParseReport> {
+        match self {
+            Format::Bin => bin_format::parse_each(reader, options, on_operation),
+            Format::Csv => csv_format::parse_each(reader, options, on_operation),
+            Format::Txt => text_format::parse_each(reader, options, on_operation),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::parse_each(reader, options, on_operation),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::parse_each(reader, options, on_operation),
+        }
+    }
+
+    /// Потоковый разбор с сырыми байтами каждой записи; ошибка записи не прерывает разбор
+    pub(crate) fn parse_each_raw<R: Read>(
+        &self,
+        reader: R,
+        options: &ParseOptions,
+        on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+    ) -> Result<()> {
+        match self {
+            Format::Bin => bin_format::parse_each_raw(reader, options, on_record),
+            Format::Csv => csv_format::parse_each_raw(reader, options, on_record),
+            Format::Txt => text_format::parse_each_raw(reader, options, on_record),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::parse_each_raw(reader, options, on_record),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::parse_each_raw(reader, options, on_record),
+        }
+    }
+
+    /// Разбор в порядке записей вместе с происхождением каждой записи
+    ///
+    /// Номер строки есть у всех форматов, кроме бинарного; смещение в байтах — у всех.
+    ///
+    /// # Аргументы
+    /// * `source` - Имя источника для [`Provenance::source`], например путь к файлу
+    pub fn parse_all_with_provenance<R: Read>(
+        &self,
+        reader: R,
+        source: Option<&str>,
+        options: &ParseOptions,
+    ) -> Result<Vec<(Operation, Provenance)>> {
+        let mut operations = Vec::new();
+        self.parse_each(reader, options, |operation, position| {
+            let provenance = Provenance::new(source, operations.len() as u64, position);
+            operations.push((operation, provenance));
+            Ok(())
+        })?;
+
+        Ok(operations)
+    }
+}
+
+impl OperationFormat for Format {
+    fn name(&self) -> &str {
+        match self {
+            Format::Bin => "bin",
+            Format::Csv => "csv",
+            Format::Txt => "txt",
+            #[cfg(feature = "serde")]
+            Format::Json => "json",
+            #[cfg(feature = "serde")]
+            Format::Jsonl => "jsonl",
+        }
+    }
+
+    fn parse_all(&self, reader: &mut dyn Read) -> Result<HashSet<Operation>> {
+        self.parse_all_with_options(reader, &ParseOptions::default())
+    }
+
+    fn write_all(&self, writer: &mut dyn Write, operations: &HashSet<Operation>) -> Result<()> {
+        match self {
+            Format::Bin => bin_format::write_all(writer, operations),
+            Format::Csv => csv_format::write_all(writer, operations),
+            Format::Txt => text_format::write_all(writer, operations),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::write_all(writer, operations),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::write_all(writer, operations),
+        }
+    }
+}
+
+/// Пишет операции по одной в любом встроенном формате
+///
+/// То же, что [`bin_format::BinWriter`], [`csv_format::CsvWriter`] и
+/// [`text_format::TextWriter`], но формат выбирается во время выполнения; байты
+/// те же, что у [`Format::write_all_with_options`]. Начало (заголовок CSV, `[` JSON)
+/// пишется перед первой записью, конец (футер, `]`) — в [`FormatWriter::finish`],
+/// так что писатель нужно завершать им, а не бросать.
+///
+/// Запись уходит в поток одним `write_all`; сам поток не буферизуется.
+pub struct FormatWriter<W> {
+    writer: W,
+    format: Format,
+    options: WriteOptions,
+    totals: Footer,
+    /// Запись собирается здесь, буфер переиспользуется
+    buf: Vec<u8>,
+    started: bool,
+}
+
+impl<W: Write> FormatWriter<W> {
+    /// Писатель с настройками по умолчанию
+    pub fn new(writer: W, format: Format) -> Self {
+        FormatWriter {
+            writer,
+            format,
+            options: WriteOptions::default(),
+            totals: Footer::default(),
+            buf: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Настройки записи
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Проверяет операцию и пишет ее запись
+    pub fn write(&mut self, operation: &Operation) -> Result<()> {
+        self.start()?;
+        self.buf.clear();
+        let (buf, options, totals) = (&mut self.buf, &self.options, &mut self.totals);
+        match self.format {
+            Format::Bin => {
+                bin_format::write_record(buf, operation, options)?;
+                totals.add(operation);
+            }
+            Format::Csv => csv_format::write_record(buf, operation, options, totals)?,
+            Format::Txt => text_format::write_record(buf, operation, options, totals)?,
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::write_record(buf, operation, options, totals)?,
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::write_record(buf, operation, options, totals)?,
+        }
+        self.writer.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Число и сумма уже записанных операций
+    pub fn totals(&self) -> Footer {
+        self.totals
+    }
+
+    /// Сбрасывает уже записанное в нижележащий поток
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Дописывает конец файла и возвращает поток
+    pub fn finish(mut self) -> Result<W> {
+        self.start()?;
+        match self.format {
+            Format::Bin => {}
+            #[cfg(feature = "serde")]
+            Format::Jsonl => {}
+            Format::Csv => csv_format::write_footer(&mut self.writer, self.totals, &self.options)?,
+            Format::Txt => text_format::write_footer(&mut self.writer, self.totals, &self.options)?,
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::write_end(&mut self.writer, self.totals)?,
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            match self.format {
+                Format::Csv => csv_format::write_header(&mut self.writer, &self.options.csv)?,
+                #[cfg(feature = "serde")]
+                Format::Json => json_format::write_start(&mut self.writer)?,
+                #[cfg(feature = "serde")]
+                Format::Jsonl => {}
+                Format::Bin | Format::Txt => {}
+            }
+            self.started = true;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Прозрачное сжатие gzip (фича `gzip`)
+//!
+//! Функции модуля `file` ([`crate::read_file`] и остальные) распаковывают файл,
+//! если он начинается с [`GZIP_MAGIC`], а формат берут из расширения под `.gz`
+//! (`day.csv.gz` — CSV) или из распакованного содержимого. [`crate::write_file`]
+//! в путь `*.gz` пишет сжатый файл. Для потоков — [`parse_all`] и [`write_all`].
+
+use crate::error::Result;
+use crate::format::Format;
+use crate::operation::Operation;
+use crate::options::{ParseOptions, WriteOptions};
+use crate::report::ParseReport;
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Первые байты любого потока gzip
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// У пути расширение `.gz` (в любом регистре)
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+/// Распаковывает поток, если он начинается с [`GZIP_MAGIC`], иначе отдает как есть
+///
+/// Смотрит только в буфер `reader`, ничего из него не забирая. Склеенные
+/// потоки gzip (как после `cat a.gz b.gz`) читаются подряд.
+pub fn decompress_if_gzip<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Разбор сжатого потока форматом `format`
+pub fn parse_all<R: Read>(
+    reader: R,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    format.parse_all_with_options(MultiGzDecoder::new(reader), options)
+}
+
+/// То же, что [`parse_all`], но с отчетом; байты в отчете — распакованные
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    format.parse_all_with_report(MultiGzDecoder::new(reader), options)
+}
+
+/// Пишет операции форматом `format` в сжатый поток и завершает его
+///
+/// # Возвращает
+/// Исходный поток: сжатые данные дописаны в него целиком, вместе с концом gzip
+pub fn write_all<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    format: Format,
+    options: &WriteOptions,
+) -> Result<W> {
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    format.write_all_with_options(&mut encoder, operations, options)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::golden_fixture;
+    use crate::operation::FullOperation;
+
+    fn full(ops: impl IntoIterator<Item = Operation>) -> HashSet<FullOperation> {
+        ops.into_iter().map(FullOperation).collect()
+    }
+
+    #[test]
+    fn test_round_trip_every_format() {
+        let ops = golden_fixture();
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let compressed = write_all(Vec::new(), &ops, format, &WriteOptions::default()).unwrap();
+            assert!(compressed.starts_with(&GZIP_MAGIC), "{:?}", format);
+
+            let parsed =
+                parse_all(compressed.as_slice(), format, &ParseOptions::default()).unwrap();
+            assert_eq!(full(parsed), full(ops.clone()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_decompress_if_gzip_passes_plain_input_through() {
+        let ops = golden_fixture();
+        let mut plain = Vec::new();
+        Format::Csv
+            .write_all_with_options(&mut plain, &ops, &WriteOptions::default())
+            .unwrap();
+        let mut compressed =
+            write_all(Vec::new(), &ops[..1], Format::Csv, &WriteOptions::default()).unwrap();
+
+        let mut out = Vec::new();
+        decompress_if_gzip(plain.as_slice())
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, plain);
+
+        // Два склеенных потока читаются как один: заголовок CSV во втором — просто строка
+        let second =
+            write_all(Vec::new(), &ops[..1], Format::Txt, &WriteOptions::default()).unwrap();
+        compressed.extend_from_slice(&second);
+        let mut out = String::new();
+        decompress_if_gzip(compressed.as_slice())
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        assert!(out.starts_with("TX_ID,TX_TYPE"), "{}", out);
+        assert!(out.contains("\nTX_ID: "), "{}", out);
+
+        assert!(is_gzip_path(Path::new("day.csv.GZ")));
+        assert!(!is_gzip_path(Path::new("day.csv")));
+    }
+}
@@ -0,0 +1,426 @@
+//! Индекс YPBankBin: смещение записи по TX_ID для случайного доступа к большим файлам
+//!
+//! [`build_index`] один раз проходит файл, не разбирая описаний, а
+//! [`get_operation`] затем читает одну запись по смещению. Индекс сохраняется
+//! рядом с файлом ([`BinIndex::save`]) в компактном виде со своим MAGIC и CRC32
+//! и помнит длину проиндексированного файла: изменившийся файл (дописанный,
+//! обрезанный, замененный) обнаруживается по ней, а не читается мимо записей.
+
+use crate::bin_format;
+use crate::dedup::DuplicatePolicy;
+use crate::error::{ParseError, Result};
+use crate::file::with_path;
+use crate::operation::Operation;
+use crc32fast::Hasher;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// MAGIC файла индекса: 'YPBI'
+const INDEX_MAGIC: [u8; 4] = [b'Y', b'P', b'B', b'I'];
+const INDEX_VERSION: u8 = 1;
+/// MAGIC, версия, длина файла, число элементов
+const INDEX_HEADER_LEN: usize = 4 + 1 + 8 + 8;
+/// TX_ID и смещение
+const ENTRY_LEN: usize = 8 + 8;
+
+/// Смещения записей YPBankBin по TX_ID
+///
+/// Элементы отсортированы по TX_ID, у повторов — в порядке файла.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinIndex {
+    /// TX_ID по возрастанию
+    tx_ids: Vec<u64>,
+    /// Смещение записи для TX_ID с тем же номером
+    offsets: Vec<u64>,
+    file_len: u64,
+}
+
+/// Индексирует поток YPBankBin с начала
+///
+/// Повтор TX_ID — ошибка [`ParseError::DuplicateTxId`], см. [`build_index_with_policy`].
+pub fn build_index<R: Read>(reader: R) -> Result<BinIndex> {
+    build_index_with_policy(reader, DuplicatePolicy::Error)
+}
+
+/// То же, что [`build_index`], но с политикой повторов TX_ID
+///
+/// [`DuplicatePolicy::KeepFirst`] и [`DuplicatePolicy::KeepLast`] оставляют одно
+/// смещение, [`DuplicatePolicy::Collect`] — все (см. [`BinIndex::lookup_all`]).
+pub fn build_index_with_policy<R: Read>(reader: R, policy: DuplicatePolicy) -> Result<BinIndex> {
+    let mut entries = Vec::new();
+    let file_len = bin_format::scan_records(reader, |tx_id, offset| {
+        entries.push((tx_id, offset));
+        Ok(())
+    })?;
+
+    // Сортировка устойчивая: повторы стоят рядом в порядке файла. Так обходимся
+    // без отдельной таблицы увиденных TX_ID на весь файл
+    entries.sort_by_key(|&(tx_id, _)| tx_id);
+    match policy {
+        DuplicatePolicy::Error => {
+            if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                return Err(ParseError::DuplicateTxId { tx_id: pair[0].0 });
+            }
+        }
+        DuplicatePolicy::KeepFirst => entries.dedup_by_key(|&mut (tx_id, _)| tx_id),
+        DuplicatePolicy::KeepLast => entries.dedup_by(|later, kept| {
+            let duplicate = later.0 == kept.0;
+            if duplicate {
+                kept.1 = later.1;
+            }
+            duplicate
+        }),
+        DuplicatePolicy::Collect => {}
+    }
+    Ok(BinIndex::from_entries(entries, file_len))
+}
+
+/// Индексирует файл YPBankBin
+pub fn index_file<P: AsRef<Path>>(path: P, policy: DuplicatePolicy) -> Result<BinIndex> {
+    let path = path.as_ref();
+    with_path(path, || {
+        build_index_with_policy(BufReader::new(File::open(path)?), policy)
+    })
+}
+
+/// Путь индекса рядом с файлом: `archive.bin` — `archive.bin.idx`
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut sidecar = path.as_ref().as_os_str().to_owned();
+    sidecar.push(".idx");
+    PathBuf::from(sidecar)
+}
+
+impl BinIndex {
+    fn from_entries(entries: Vec<(u64, u64)>, file_len: u64) -> Self {
+        let (tx_ids, offsets) = entries.into_iter().unzip();
+        BinIndex {
+            tx_ids,
+            offsets,
+            file_len,
+        }
+    }
+
+    /// Смещение записи с этим TX_ID (первой из повторов)
+    pub fn lookup(&self, tx_id: u64) -> Option<u64> {
+        self.lookup_all(tx_id).first().copied()
+    }
+
+    /// Смещения всех записей с этим TX_ID в порядке файла
+    pub fn lookup_all(&self, tx_id: u64) -> &[u64] {
+        let start = self.tx_ids.partition_point(|&id| id < tx_id);
+        let end = self.tx_ids.partition_point(|&id| id <= tx_id);
+        &self.offsets[start..end]
+    }
+
+    /// Число элементов индекса
+    pub fn len(&self) -> usize {
+        self.tx_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx_ids.is_empty()
+    }
+
+    /// Длина проиндексированного файла в байтах
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// Проверяет, что поток той же длины, что и проиндексированный файл
+    ///
+    /// # Возвращает
+    /// * `Err(ParseError::InvalidFormat)` - Файл изменился после построения индекса
+    pub fn check_fresh<R: Seek>(&self, reader: &mut R) -> Result<()> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len != self.file_len {
+            return Err(ParseError::InvalidFormat(format!(
+                "Stale index: it was built for {} bytes, the file has {}; rebuild the index",
+                self.file_len, len
+            )));
+        }
+        Ok(())
+    }
+
+    /// Пишет индекс: заголовок, элементы по возрастанию TX_ID и CRC32 всего перед ним
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = CrcWriter {
+            inner: BufWriter::new(writer),
+            crc: Hasher::new(),
+        };
+        writer.write_all(&INDEX_MAGIC)?;
+        writer.write_all(&[INDEX_VERSION])?;
+        writer.write_all(&self.file_len.to_be_bytes())?;
+        writer.write_all(&(self.len() as u64).to_be_bytes())?;
+        for (tx_id, offset) in self.tx_ids.iter().zip(&self.offsets) {
+            writer.write_all(&tx_id.to_be_bytes())?;
+            writer.write_all(&offset.to_be_bytes())?;
+        }
+        let crc = writer.crc.finalize();
+        writer.inner.write_all(&crc.to_be_bytes())?;
+        writer.inner.flush()?;
+        Ok(())
+    }
+
+    /// Читает индекс, записанный [`BinIndex::write_to`], проверив MAGIC, версию и CRC32
+    pub fn read_from<R: Read>(reader: R) -> Result<BinIndex> {
+        let mut reader = BufReader::new(reader);
+        let mut crc = Hasher::new();
+        let mut read = |buf: &mut [u8]| -> Result<()> {
+            reader.read_exact(buf)?;
+            crc.update(buf);
+            Ok(())
+        };
+
+        let mut header = [0u8; INDEX_HEADER_LEN];
+        read(&mut header)?;
+        if header[..4] != INDEX_MAGIC {
+            return Err(ParseError::InvalidFormat(
+                "Not a YPBankBin index: expected YPBI magic".to_string(),
+            ));
+        }
+        if header[4] != INDEX_VERSION {
+            return Err(ParseError::UnsupportedVersion {
+                found: u32::from(header[4]),
+                supported: u32::from(INDEX_VERSION),
+            });
+        }
+        let u64_at = |at: usize| u64::from_be_bytes(header[at..at + 8].try_into().unwrap());
+        let file_len = u64_at(5);
+        let count = u64_at(13);
+
+        // Число элементов не проверено CRC, пока не дочитали: не доверяем ему память
+        let mut entries = Vec::with_capacity(count.min(1 << 20) as usize);
+        let mut entry = [0u8; ENTRY_LEN];
+        for _ in 0..count {
+            read(&mut entry)?;
+            entries.push((
+                u64::from_be_bytes(entry[..8].try_into().unwrap()),
+                u64::from_be_bytes(entry[8..].try_into().unwrap()),
+            ));
+        }
+
+        let expected = crc.finalize();
+        let mut stored = [0u8; 4];
+        reader.read_exact(&mut stored)?;
+        let actual = u32::from_be_bytes(stored);
+        if actual != expected {
+            return Err(ParseError::InvalidFormat(format!(
+                "Index checksum mismatch: stored {:08x}, computed {:08x}",
+                actual, expected
+            )));
+        }
+        if !entries.is_sorted_by_key(|&(tx_id, _)| tx_id) {
+            return Err(ParseError::InvalidFormat(
+                "Index entries are not sorted by tx_id".to_string(),
+            ));
+        }
+        Ok(BinIndex::from_entries(entries, file_len))
+    }
+
+    /// Сохраняет индекс в файл, обычно [`sidecar_path`]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        with_path(path, || self.write_to(File::create(path)?))
+    }
+
+    /// Загружает индекс из файла
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BinIndex> {
+        let path = path.as_ref();
+        with_path(path, || BinIndex::read_from(File::open(path)?))
+    }
+}
+
+/// Читает операцию по TX_ID через индекс
+///
+/// Сначала сверяет длину потока с [`BinIndex::file_len`] (в том числе при промахе),
+/// затем читает запись по смещению и проверяет, что у нее тот же TX_ID.
+///
+/// # Возвращает
+/// * `Ok(Some(Operation))` - Запись найдена (первая из повторов)
+/// * `Ok(None)` - TX_ID нет в индексе
+/// * `Err(ParseError::InvalidFormat)` - Индекс устарел
+pub fn get_operation<R: Read + Seek>(
+    reader: &mut R,
+    index: &BinIndex,
+    tx_id: u64,
+) -> Result<Option<Operation>> {
+    index.check_fresh(reader)?;
+    let Some(offset) = index.lookup(tx_id) else {
+        return Ok(None);
+    };
+    reader.seek(SeekFrom::Start(offset))?;
+    let operation = bin_format::parse_operation(reader)?;
+    if operation.tx_id != tx_id {
+        return Err(ParseError::InvalidFormat(format!(
+            "Stale index: record at offset {} has tx_id {}, expected {}; rebuild the index",
+            offset, operation.tx_id, tx_id
+        )));
+    }
+    Ok(Some(operation))
+}
+
+/// Считает CRC32 всего, что через него записано
+struct CrcWriter<W> {
+    inner: W,
+    crc: Hasher,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+    use std::io::Cursor;
+
+    fn operation(tx_id: u64, description: &str) -> Operation {
+        Operation::deposit(7, tx_id as i64 * 100)
+            .tx_id(tx_id)
+            .timestamp(1633036800000 + tx_id)
+            .status(OperationStatus::Success)
+            .description(description)
+            .build()
+            .unwrap()
+    }
+
+    /// Записи вразнобой по TX_ID, разной длины и версий
+    fn archive(tx_ids: &[u64]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (i, &tx_id) in tx_ids.iter().enumerate() {
+            let op = operation(tx_id, &"x".repeat(i * 7));
+            if i % 2 == 0 {
+                bin_format::write_operation(&mut buf, &op).unwrap();
+            } else {
+                bin_format::write_operation_v2(&mut buf, &op).unwrap();
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn test_lookup_and_get_operation() {
+        let buf = archive(&[30, 10, 50, 20, 40]);
+        let index = build_index(buf.as_slice()).unwrap();
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.file_len(), buf.len() as u64);
+        assert_eq!(index.lookup(30), Some(0));
+
+        let mut cursor = Cursor::new(&buf);
+        for tx_id in [40, 10, 50, 30, 20] {
+            let op = get_operation(&mut cursor, &index, tx_id).unwrap().unwrap();
+            assert!(op.eq_full(&operation(tx_id, &op.description)));
+        }
+        assert_eq!(index.lookup(35), None);
+        assert!(get_operation(&mut cursor, &index, 35).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_file_header_is_skipped() {
+        let ops = [operation(2, "b"), operation(1, "a")].into_iter().collect();
+        let mut buf = Vec::new();
+        bin_format::write_all_with_header(&mut buf, &ops).unwrap();
+
+        let index = build_index(buf.as_slice()).unwrap();
+        let mut cursor = Cursor::new(&buf);
+        let op = get_operation(&mut cursor, &index, 1).unwrap().unwrap();
+        assert_eq!(op.description, "a");
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let buf = archive(&[1, 2, 1, 3, 1]);
+        assert!(matches!(
+            build_index(buf.as_slice()),
+            Err(ParseError::DuplicateTxId { tx_id: 1 })
+        ));
+
+        let all = build_index_with_policy(buf.as_slice(), DuplicatePolicy::Collect).unwrap();
+        let offsets = all.lookup_all(1);
+        assert_eq!(offsets.len(), 3);
+        assert!(offsets.is_sorted());
+        assert_eq!(all.lookup(1), Some(0));
+
+        let first = build_index_with_policy(buf.as_slice(), DuplicatePolicy::KeepFirst).unwrap();
+        let last = build_index_with_policy(buf.as_slice(), DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(first.lookup_all(1), &offsets[..1]);
+        assert_eq!(last.lookup_all(1), &offsets[2..]);
+        assert_eq!(last.len(), 3);
+    }
+
+    #[test]
+    fn test_stale_index_is_detected() {
+        let buf = archive(&[1, 2, 3]);
+        let index = build_index(buf.as_slice()).unwrap();
+
+        // Дописанная запись меняет длину: ошибка даже при промахе
+        let mut appended = buf.clone();
+        bin_format::write_operation(&mut appended, &operation(4, "")).unwrap();
+        for tx_id in [1, 4] {
+            let err = get_operation(&mut Cursor::new(&appended), &index, tx_id).unwrap_err();
+            assert!(err.to_string().contains("Stale index"), "{}", err);
+        }
+
+        // Та же длина, но записи переставлены: ловится по TX_ID записи
+        let swapped = archive(&[2, 1, 3]);
+        assert_eq!(swapped.len(), buf.len());
+        let err = get_operation(&mut Cursor::new(&swapped), &index, 2).unwrap_err();
+        assert!(err.to_string().contains("has tx_id"), "{}", err);
+    }
+
+    #[test]
+    fn test_sidecar_round_trip_and_checksum() {
+        let buf = archive(&[5, 3, 9, 3]);
+        let index = build_index_with_policy(buf.as_slice(), DuplicatePolicy::Collect).unwrap();
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(saved.len(), INDEX_HEADER_LEN + 4 * ENTRY_LEN + 4);
+        assert_eq!(BinIndex::read_from(saved.as_slice()).unwrap(), index);
+
+        let mut corrupted = saved.clone();
+        corrupted[INDEX_HEADER_LEN + 3] ^= 1;
+        let err = BinIndex::read_from(corrupted.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+
+        let err = BinIndex::read_from(&buf[..]).unwrap_err();
+        assert!(err.to_string().contains("YPBI"), "{}", err);
+        assert!(BinIndex::read_from(&saved[..saved.len() - 1]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("parser_index_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive_path = dir.join("archive.bin");
+        std::fs::write(&archive_path, &buf).unwrap();
+        let sidecar = sidecar_path(&archive_path);
+        assert_eq!(sidecar, dir.join("archive.bin.idx"));
+        index_file(&archive_path, DuplicatePolicy::Collect)
+            .unwrap()
+            .save(&sidecar)
+            .unwrap();
+        assert_eq!(BinIndex::load(&sidecar).unwrap(), index);
+    }
+
+    #[test]
+    fn test_broken_record_is_an_error() {
+        let buf = archive(&[1, 2, 3]);
+        let err = build_index(&buf[..buf.len() - 3]).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::TruncatedRecord { .. }),
+            "{}",
+            err
+        );
+        let mut garbage = buf.clone();
+        garbage.extend_from_slice(b"junk");
+        assert!(build_index(garbage.as_slice()).is_err());
+    }
+}
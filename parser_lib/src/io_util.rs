@@ -0,0 +1,348 @@
+//! Вспомогательная запись в файлы: ротация по числу записей или по дням
+
+use crate::bin_format;
+use crate::csv_format;
+use crate::error::{ParseError, Result};
+use crate::file::with_path;
+use crate::footer::Footer;
+use crate::format::Format;
+#[cfg(feature = "serde")]
+use crate::json_format;
+#[cfg(feature = "serde")]
+use crate::jsonl_format;
+use crate::operation::Operation;
+use crate::options::WriteOptions;
+use crate::text_format;
+use crate::timestamp::to_rfc3339;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MILLIS_PER_DAY: u64 = 86_400_000;
+
+/// Когда начинать новый файл
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationPolicy {
+    /// Не больше `n` записей в файле
+    MaxRecords(usize),
+    /// Один файл на календарный день (UTC) по TIMESTAMP операций
+    Daily,
+}
+
+/// Писатель, который сам переключается на новый файл по [`RotationPolicy`]
+///
+/// Имя файла строится из шаблона: `{date}` заменяется датой (`YYYY-MM-DD`, UTC)
+/// первой операции файла, `{seq}` — порядковым номером файла с 1. Каждый файл
+/// закрывается целиком (заголовок CSV, футер, если он включен в [`WriteOptions`]),
+/// так что читается отдельно от остальных. Существующие файлы не перезаписываются.
+///
+/// В конце нужно вызвать [`RotatingWriter::finish`]: при простом drop текущий
+/// файл закрывается "как получится", без возврата ошибок.
+pub struct RotatingWriter {
+    dir: PathBuf,
+    template: String,
+    format: Format,
+    policy: RotationPolicy,
+    options: WriteOptions,
+    seq: u64,
+    current: Option<OpenFile>,
+    finished: Vec<PathBuf>,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    day: u64,
+    totals: Footer,
+}
+
+impl RotatingWriter {
+    /// # Аргументы
+    /// * `dir` - Каталог для файлов
+    /// * `template` - Шаблон имени файла с `{date}` и/или `{seq}`, например `ops-{date}-{seq}.bin`
+    /// * `format` - Формат файлов
+    /// * `policy` - Когда переключаться на новый файл
+    pub fn new<P: AsRef<Path>>(
+        dir: P,
+        template: &str,
+        format: Format,
+        policy: RotationPolicy,
+    ) -> Self {
+        RotatingWriter {
+            dir: dir.as_ref().to_path_buf(),
+            template: template.to_string(),
+            format,
+            policy,
+            options: WriteOptions::default(),
+            seq: 0,
+            current: None,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Настройки записи для всех файлов (например, футер)
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Пишет операцию, при необходимости закрыв текущий файл и открыв следующий
+    pub fn write(&mut self, operation: &Operation) -> Result<()> {
+        // Проверяем до ротации, чтобы не оставлять пустых файлов из-за плохой записи
+        operation.validate()?;
+
+        let day = operation.timestamp / MILLIS_PER_DAY;
+        let rotate = match (&self.current, self.policy) {
+            (None, _) => true,
+            (Some(file), RotationPolicy::MaxRecords(n)) => file.totals.records >= n.max(1) as u64,
+            (Some(file), RotationPolicy::Daily) => file.day != day,
+        };
+        if rotate {
+            self.close_current()?;
+            self.open_next(operation)?;
+        }
+
+        let Some(file) = self.current.as_mut() else {
+            unreachable!("a file is always open after rotation");
+        };
+        let (format, options) = (self.format, &self.options);
+        with_path(&file.path, || match format {
+            Format::Bin => bin_format::write_record(&mut file.writer, operation, options)
+                .map(|()| file.totals.add(operation)),
+            Format::Csv => {
+                csv_format::write_record(&mut file.writer, operation, options, &mut file.totals)
+            }
+            Format::Txt => {
+                text_format::write_record(&mut file.writer, operation, options, &mut file.totals)
+            }
+            #[cfg(feature = "serde")]
+            Format::Json => {
+                json_format::write_record(&mut file.writer, operation, options, &mut file.totals)
+            }
+            #[cfg(feature = "serde")]
+            Format::Jsonl => {
+                jsonl_format::write_record(&mut file.writer, operation, options, &mut file.totals)
+            }
+        })
+    }
+
+    /// Закрытые на данный момент файлы
+    pub fn files(&self) -> &[PathBuf] {
+        &self.finished
+    }
+
+    /// Закрывает текущий файл
+    ///
+    /// # Возвращает
+    /// Все записанные файлы по порядку
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.close_current()?;
+        Ok(std::mem::take(&mut self.finished))
+    }
+
+    fn open_next(&mut self, first: &Operation) -> Result<()> {
+        self.seq += 1;
+        let date = to_rfc3339(first.timestamp);
+        let date = date.split_once('T').map_or(date.as_str(), |(date, _)| date);
+        let name = self
+            .template
+            .replace("{date}", date)
+            .replace("{seq}", &self.seq.to_string());
+        let path = self.dir.join(name);
+
+        let file = with_path(&path, || {
+            File::create_new(&path).map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => ParseError::InvalidFormat(
+                    "file already exists; add {seq} to the file name template".to_string(),
+                ),
+                _ => ParseError::from(e),
+            })
+        })?;
+        let mut writer = BufWriter::new(file);
+        match self.format {
+            Format::Csv => with_path(&path, || {
+                csv_format::write_header(&mut writer, &self.options.csv)
+            })?,
+            #[cfg(feature = "serde")]
+            Format::Json => with_path(&path, || json_format::write_start(&mut writer))?,
+            #[cfg(feature = "serde")]
+            Format::Jsonl => {}
+            Format::Bin | Format::Txt => {}
+        }
+
+        self.current = Some(OpenFile {
+            path,
+            writer,
+            day: first.timestamp / MILLIS_PER_DAY,
+            totals: Footer::default(),
+        });
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> Result<()> {
+        let Some(mut file) = self.current.take() else {
+            return Ok(());
+        };
+
+        with_path(&file.path, || {
+            match self.format {
+                Format::Bin => {}
+                #[cfg(feature = "serde")]
+                Format::Jsonl => {}
+                Format::Csv => {
+                    csv_format::write_footer(&mut file.writer, file.totals, &self.options)?
+                }
+                Format::Txt => {
+                    text_format::write_footer(&mut file.writer, file.totals, &self.options)?
+                }
+                #[cfg(feature = "serde")]
+                Format::Json => json_format::write_end(&mut file.writer, file.totals)?,
+            }
+            file.writer.flush()?;
+            if self.options.sync {
+                file.writer.get_ref().sync_all()?;
+            }
+            Ok(())
+        })?;
+
+        self.finished.push(file.path);
+        Ok(())
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        let _ = self.close_current();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+    use std::fs;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("parser-rotating-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn op(tx_id: u64, timestamp: u64) -> Operation {
+        Operation::deposit(1, 100)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(OperationStatus::Success)
+            .description(format!("Op {}", tx_id))
+            .build()
+            .unwrap()
+    }
+
+    fn ids_in(path: &Path, format: Format) -> Vec<u64> {
+        let bytes = fs::read(path).unwrap();
+        format
+            .parse_all_vec(bytes.as_slice())
+            .unwrap()
+            .iter()
+            .map(|op| op.tx_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_rotates_by_record_count() {
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let dir = test_dir(&format!("count-{}", format_name(format)));
+            let options = WriteOptions {
+                footer: true,
+                sync: false,
+                ..Default::default()
+            };
+            let mut writer = RotatingWriter::new(
+                &dir,
+                "part-{seq}.out",
+                format,
+                RotationPolicy::MaxRecords(2),
+            )
+            .with_options(options);
+            for i in 1..=5 {
+                writer.write(&op(i, 1633046400000 + i)).unwrap();
+            }
+            let files = writer.finish().unwrap();
+
+            assert_eq!(files.len(), 3);
+            assert_eq!(files[0], dir.join("part-1.out"));
+            let ids: Vec<Vec<u64>> = files.iter().map(|p| ids_in(p, format)).collect();
+            assert_eq!(ids, vec![vec![1, 2], vec![3, 4], vec![5]]);
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rotates_daily() {
+        let dir = test_dir("daily");
+        let day = 1633046400000; // 2021-10-01T00:00:00Z
+
+        let mut writer =
+            RotatingWriter::new(&dir, "ops-{date}.csv", Format::Csv, RotationPolicy::Daily);
+        writer.write(&op(1, day - 1)).unwrap();
+        writer.write(&op(2, day)).unwrap();
+        writer.write(&op(3, day + 86_399_999)).unwrap();
+        writer.write(&op(4, day + 86_400_000)).unwrap();
+        assert_eq!(writer.files().len(), 2);
+        let files = writer.finish().unwrap();
+
+        let names: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "ops-2021-09-30.csv",
+                "ops-2021-10-01.csv",
+                "ops-2021-10-02.csv"
+            ]
+        );
+        assert_eq!(ids_in(&files[1], Format::Csv), vec![2, 3]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite() {
+        let dir = test_dir("overwrite");
+        let mut writer =
+            RotatingWriter::new(&dir, "ops.bin", Format::Bin, RotationPolicy::MaxRecords(1));
+        writer.write(&op(1, 0)).unwrap();
+
+        let err = writer.write(&op(2, 0)).unwrap_err();
+        assert!(matches!(err, ParseError::File { .. }));
+        assert_eq!(ids_in(&dir.join("ops.bin"), Format::Bin), vec![1]);
+
+        drop(writer);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn format_name(format: Format) -> &'static str {
+        match format {
+            Format::Bin => "bin",
+            Format::Csv => "csv",
+            Format::Txt => "txt",
+            #[cfg(feature = "serde")]
+            Format::Json => "json",
+            #[cfg(feature = "serde")]
+            Format::Jsonl => "jsonl",
+        }
+    }
+}
@@ -0,0 +1,652 @@
+//! YPBankJson: массив объектов с теми же именами полей, что в заголовке CSV
+//!
+//! ```text
+//! [
+//!   {"TX_ID": 1, "TX_TYPE": "DEPOSIT", "FROM_USER_ID": 0, "TO_USER_ID": 5, "AMOUNT": 100,
+//!    "TIMESTAMP": 1633036800000, "STATUS": "SUCCESS", "DESCRIPTION": "Salary"}
+//! ]
+//! ```
+//!
+//! Числовые поля — числа JSON, остальные — строки. TIMESTAMP может быть и
+//! строкой RFC 3339 с поясом (так его пишет [`WriteOptions::time_zone`]).
+//! Незнакомые ключи пропускаются, вложенные объекты и массивы в значениях не
+//! допускаются. Прагмы `#VERSION` и футера у JSON нет: [`WriteOptions::footer`]
+//! здесь не действует. Читает serde_json, потоково: массив не грузится в память
+//! целиком. Модуль есть только с фичей `serde`.
+
+use crate::dedup::{DedupOutcome, DuplicatePolicy};
+use crate::error::{Location, ParseError, Result};
+use crate::footer::Footer;
+use crate::format::Format;
+use crate::operation::{FullOperation, Operation, OperationStatus, OperationType};
+use crate::options::{ParseOptions, WriteOptions};
+use crate::partition::ParseOutcome;
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{Meter, ParseReport};
+use serde::Deserialize;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde_json::value::RawValue;
+use serde_json::{Map, Value, error::Category};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Читаем с json файла
+pub fn parse_all<R: Read>(reader: R) -> Result<HashSet<Operation>> {
+    parse_all_with_options(reader, &ParseOptions::default())
+}
+
+/// Разбор с пропуском битых записей, см. [`crate::parse_all_lossy`]
+pub fn parse_all_lossy<R: Read>(reader: R, options: &ParseOptions) -> Result<ParseOutcome> {
+    crate::partition::parse_all_lossy(reader, Format::Json, options)
+}
+
+/// То же, что [`parse_all`], но с настройками (например, токеном отмены)
+pub fn parse_all_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    Format::Json.parse_all_with_options(reader, options)
+}
+
+/// То же, что [`parse_all_with_options`], плюс отчет с предупреждениями
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    Format::Json.parse_all_with_report(reader, options)
+}
+
+/// Читает все операции в порядке файла, см. [`Format::parse_all_vec`]
+pub fn parse_all_vec<R: Read>(reader: R) -> Result<Vec<Operation>> {
+    Format::Json.parse_all_vec(reader)
+}
+
+/// То же, что [`parse_all_vec`], но с настройками разбора
+pub fn parse_all_vec_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Operation>> {
+    Format::Json.parse_all_vec_with_options(reader, options)
+}
+
+/// Разбор с политикой для повторов TX_ID, см. [`Format::parse_all_with_policy`]
+pub fn parse_all_with_policy<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    Format::Json.parse_all_with_policy(reader, options, policy)
+}
+
+/// Число повторов каждой записи, см. [`Format::parse_all_counted`]
+pub fn parse_all_counted<R: Read>(reader: R) -> Result<HashMap<FullOperation, u64>> {
+    Format::Json.parse_all_counted(reader)
+}
+
+/// Записи вместе с происхождением, см. [`Format::parse_all_with_provenance`]
+pub fn parse_all_with_provenance<R: Read>(
+    reader: R,
+    source: Option<&str>,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    Format::Json.parse_all_with_provenance(reader, source, options)
+}
+
+/// Читает один объект с текущей позиции потока
+///
+/// Поток должен стоять на `{` записи (или на пробелах перед ней), например
+/// по [`Provenance::byte_offset`]. Все, что после объекта, не читается.
+pub fn parse_one<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Operation> {
+    let mut json = serde_json::Deserializer::from_reader(reader);
+    let fields = Fields::deserialize(&mut json).map_err(|e| syntax(e, 1))?;
+    let operation = parse_record(&fields, options, &mut ParseReport::default())?;
+    operation.validate()?;
+    Ok(operation)
+}
+
+/// Потоковый разбор: отдает записи по одной, ошибка из `on_operation` прерывает разбор
+pub(crate) fn parse_each<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let meter = Meter::start("json", options);
+    let report = parse_records(meter.reader(reader), options, |operation, position| {
+        meter.record();
+        on_operation(operation, position)
+    })?;
+    Ok(meter.finish(report))
+}
+
+/// Сам разбор для [`parse_each`], без замеров
+fn parse_records<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let mut report = ParseReport::default();
+
+    for_each_element(reader, options, |fields: Fields, position| {
+        let record_index = Some(report.records as u64);
+        let operation = parse_record(&fields, options, &mut report)
+            .and_then(|operation| operation.validate().map(|()| operation))
+            .map_err(|e| e.at(position.location(record_index)))?;
+        report.check_rules(&operation, options);
+        report.records += 1;
+        on_operation(operation, position)
+    })?;
+
+    Ok(report)
+}
+
+/// Потоковый разбор для карантина: каждый объект вместе с его исходным текстом
+///
+/// Ошибка в значениях полей уходит в `on_record`. Синтаксическая ошибка JSON
+/// прерывает разбор: после нее границу следующего объекта уже не найти.
+pub(crate) fn parse_each_raw<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let mut report = ParseReport::default();
+
+    for_each_element(reader, options, |raw: Box<RawValue>, position| {
+        let fields: Fields =
+            serde_json::from_str(raw.get()).map_err(|e| syntax(e, position.line.unwrap_or(1)))?;
+        let mut raw = raw.get().as_bytes().to_vec();
+        raw.push(b'\n');
+        let result = parse_record(&fields, options, &mut report);
+        on_record(result, raw, position)
+    })
+}
+
+/// Разбирает объект, занимающий строку `line_num` целиком (для JSON Lines)
+///
+/// Синтаксическая ошибка уже содержит номер строки, остальные ошибки — как есть.
+pub(crate) fn parse_line(
+    line: &str,
+    line_num: u64,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+) -> Result<Operation> {
+    let fields: Fields = serde_json::from_str(line).map_err(|e| syntax(e, line_num))?;
+    parse_record(&fields, options, report)
+}
+
+/// Поля объекта; при повторе ключа остается последнее значение
+type Fields = Map<String, Value>;
+
+/// Обходит массив верхнего уровня, отдавая каждый элемент вместе с его позицией
+///
+/// Массив читает serde_json, не загружая файл целиком. Ошибка из `f`
+/// прерывает разбор и возвращается как есть.
+fn for_each_element<R: Read, T: DeserializeOwned>(
+    reader: R,
+    options: &ParseOptions,
+    mut f: impl FnMut(T, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let cursor = Cursor::default();
+    let mut json = serde_json::Deserializer::from_reader(Counting {
+        reader: BufReader::new(reader),
+        cursor: &cursor,
+    });
+    let mut failure = None;
+    let elements = Elements {
+        options,
+        cursor: &cursor,
+        f: &mut f,
+        failure: &mut failure,
+        element: PhantomData,
+    };
+    let result = json.deserialize_seq(elements).and_then(|()| json.end());
+    match failure {
+        Some(e) => Err(e),
+        None => result.map_err(|e| syntax(e, 1)),
+    }
+}
+
+/// Сколько байт и строк прочитано из потока
+#[derive(Default)]
+struct Cursor {
+    offset: Cell<u64>,
+    newlines: Cell<u64>,
+}
+
+impl Cursor {
+    /// Начало значения, первый байт которого serde_json уже прочитал, заглядывая вперед
+    fn peeked_start(&self) -> RecordPosition {
+        RecordPosition {
+            line: Some(self.newlines.get() + 1),
+            byte_offset: Some(self.offset.get().saturating_sub(1)),
+        }
+    }
+}
+
+/// Поток, который ведет [`Cursor`]; serde_json читает из него по байту
+struct Counting<'a, R> {
+    reader: R,
+    cursor: &'a Cursor,
+}
+
+impl<R: Read> Read for Counting<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        let newlines = buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+        self.cursor.offset.set(self.cursor.offset.get() + n as u64);
+        self.cursor
+            .newlines
+            .set(self.cursor.newlines.get() + newlines);
+        Ok(n)
+    }
+}
+
+/// Обход элементов массива для [`for_each_element`]
+struct Elements<'a, T, F> {
+    options: &'a ParseOptions,
+    cursor: &'a Cursor,
+    f: &'a mut F,
+    failure: &'a mut Option<ParseError>,
+    element: PhantomData<T>,
+}
+
+impl<T, F> Elements<'_, T, F> {
+    /// Запоминает нашу ошибку; serde_json получает заглушку, чтобы остановиться
+    fn fail<E: de::Error>(&mut self, e: ParseError) -> E {
+        *self.failure = Some(e);
+        E::custom("stopped")
+    }
+}
+
+impl<'de, T, F> Visitor<'de> for Elements<'_, T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(T, RecordPosition) -> Result<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of operations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> std::result::Result<(), A::Error> {
+        loop {
+            if let Err(e) = self.options.check_cancelled() {
+                return Err(self.fail(e));
+            }
+            let Some((element, position)) = seq.next_element_seed(Element {
+                cursor: self.cursor,
+                element: PhantomData::<T>,
+            })?
+            else {
+                return Ok(());
+            };
+            if let Err(e) = (self.f)(element, position) {
+                return Err(self.fail(e));
+            }
+        }
+    }
+}
+
+/// Один элемент массива вместе с позицией его начала
+struct Element<'a, T> {
+    cursor: &'a Cursor,
+    element: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for Element<'_, T> {
+    type Value = (T, RecordPosition);
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        let position = self.cursor.peeked_start();
+        Ok((T::deserialize(deserializer)?, position))
+    }
+}
+
+/// Ошибка serde_json; строки в ней считаются от `first_line`
+fn syntax(e: serde_json::Error, first_line: u64) -> ParseError {
+    match e.classify() {
+        Category::Eof => ParseError::UnexpectedEof,
+        Category::Io => ParseError::Io(e.into()),
+        Category::Syntax | Category::Data => {
+            // Строку и столбец serde_json дописывает в сообщение сам, а у нас они в Location
+            let message = e.to_string();
+            let suffix = format!(" at line {} column {}", e.line(), e.column());
+            let message = message.strip_suffix(&suffix).unwrap_or(&message);
+            ParseError::InvalidFormat(format!("invalid JSON: {}", message)).at(Location {
+                line: Some(first_line + e.line().saturating_sub(1) as u64),
+                ..Location::default()
+            })
+        }
+    }
+}
+
+/// Собирает операцию из полей объекта
+fn parse_record(
+    fields: &Fields,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+) -> Result<Operation> {
+    let tx_id = number(fields, "TX_ID")?;
+    let tx_type = OperationType::parse_field(string(fields, "TX_TYPE")?, options)?;
+    let from_user_id = number(fields, "FROM_USER_ID")?;
+    let to_user_id = number(fields, "TO_USER_ID")?;
+    let amount = number(fields, "AMOUNT")?;
+
+    let timestamp = match field(fields, "TIMESTAMP")? {
+        Value::Number(raw) => report.parse_timestamp(tx_id, &raw.to_string(), options)?,
+        Value::String(raw) => report.parse_timestamp(tx_id, raw, options)?,
+        other => return Err(wrong_type("TIMESTAMP", "a number or a string", other)),
+    };
+
+    let status = OperationStatus::parse_field(string(fields, "STATUS")?, options)?;
+    let description = string(fields, "DESCRIPTION")?.to_string();
+
+    Ok(Operation {
+        tx_id,
+        tx_type,
+        from_user_id,
+        to_user_id,
+        amount,
+        timestamp,
+        status,
+        description,
+        extensions: Vec::new(),
+    })
+}
+
+fn field<'a>(fields: &'a Fields, name: &str) -> Result<&'a Value> {
+    fields.get(name).ok_or_else(|| ParseError::InvalidField {
+        field: name.to_string(),
+        reason: "missing".to_string(),
+    })
+}
+
+fn number<T>(fields: &Fields, name: &str) -> Result<T>
+where
+    T: FromStr<Err = std::num::ParseIntError>,
+{
+    match field(fields, name)? {
+        // Через текст, чтобы 1.5 и выход за диапазон типа давали одинаковую ошибку
+        Value::Number(raw) => {
+            let raw = raw.to_string();
+            raw.parse()
+                .map_err(|e: std::num::ParseIntError| ParseError::InvalidField {
+                    field: name.to_string(),
+                    reason: format!("{}: {}", raw, e),
+                })
+        }
+        other => Err(wrong_type(name, "a number", other)),
+    }
+}
+
+fn string<'a>(fields: &'a Fields, name: &str) -> Result<&'a str> {
+    match field(fields, name)? {
+        Value::String(value) => Ok(value),
+        other => Err(wrong_type(name, "a string", other)),
+    }
+}
+
+fn wrong_type(name: &str, expected: &str, got: &Value) -> ParseError {
+    let got = match got {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    };
+    ParseError::InvalidField {
+        field: name.to_string(),
+        reason: format!("expected {}, got {}", expected, got),
+    }
+}
+
+/// Записываем всё в json
+pub fn write_all<W: Write>(writer: W, operations: &HashSet<Operation>) -> Result<()> {
+    write_all_with_options(writer, operations, &WriteOptions::default())
+}
+
+/// То же, что [`write_all`], но с настройками (например, единицами TIMESTAMP)
+///
+/// Операции пишутся в порядке `operations`, по объекту на строку, через [`BufWriter`].
+pub fn write_all_with_options<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    write_start(&mut writer)?;
+    let mut totals = Footer::default();
+
+    for operation in operations {
+        write_record(&mut writer, operation, options, &mut totals)?;
+    }
+
+    write_end(&mut writer, totals)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Открывает массив
+pub(crate) fn write_start<W: Write>(writer: &mut W) -> Result<()> {
+    write!(writer, "[")?;
+    Ok(())
+}
+
+/// Пишет один объект на своей строке, проверив операцию, и добавляет его в итоги
+///
+/// Перед всеми объектами, кроме первого (по `totals`), ставит запятую.
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+    totals: &mut Footer,
+) -> Result<()> {
+    let operation = options.limit_description(operation)?;
+    operation.validate()?;
+
+    write!(writer, "{}\n  ", if totals.records > 0 { "," } else { "" })?;
+    totals.add(&operation);
+    write_object(writer, &operation, options)
+}
+
+/// Пишет объект операции в одну строку, без проверок и разделителей
+pub(crate) fn write_object<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+) -> Result<()> {
+    let timestamp = match options.time_zone {
+        Some(_) => quote(&options.format_timestamp(operation.timestamp)),
+        None => options.format_timestamp(operation.timestamp),
+    };
+    write!(
+        writer,
+        "{{\"TX_ID\": {}, \"TX_TYPE\": \"{}\", \"FROM_USER_ID\": {}, \"TO_USER_ID\": {}, \
+         \"AMOUNT\": {}, \"TIMESTAMP\": {}, \"STATUS\": \"{}\", \"DESCRIPTION\": {}}}",
+        operation.tx_id,
+        operation.tx_type.as_str(),
+        operation.from_user_id,
+        operation.to_user_id,
+        operation.amount,
+        timestamp,
+        operation.status.as_str(),
+        quote(&operation.description)
+    )?;
+    Ok(())
+}
+
+/// Закрывает массив
+pub(crate) fn write_end<W: Write>(writer: &mut W, totals: Footer) -> Result<()> {
+    writeln!(writer, "{}]", if totals.records > 0 { "\n" } else { "" })?;
+    Ok(())
+}
+
+/// Строка JSON в кавычках; не-ASCII остается как есть
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(tx_id: u64, description: &str) -> Operation {
+        Operation::transfer(1, 2, 100 * tx_id as i64)
+            .tx_id(tx_id)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description(description)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_unicode_and_empty_descriptions() {
+        let operations = vec![
+            op(1, ""),
+            op(2, "Перевод другу 🎉"),
+            op(3, "quotes \" and \\ backslash, comma"),
+            op(4, "multi\nline\r\n\ttab \u{1}"),
+        ];
+
+        let mut buf = Vec::new();
+        write_all_with_options(&mut buf, &operations, &WriteOptions::default()).unwrap();
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert!(text.starts_with("[\n  {\"TX_ID\": 1, "), "{}", text);
+        assert!(text.contains("\"DESCRIPTION\": \"Перевод другу 🎉\""));
+
+        let parsed = parse_all_vec(buf.as_slice()).unwrap();
+        assert_eq!(parsed.len(), operations.len());
+        for (parsed, original) in parsed.iter().zip(&operations) {
+            assert_eq!(
+                FullOperation(parsed.clone()),
+                FullOperation(original.clone())
+            );
+        }
+
+        let mut empty = Vec::new();
+        write_all(&mut empty, &HashSet::new()).unwrap();
+        assert_eq!(empty, b"[]\n");
+        assert!(parse_all(empty.as_slice()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reads_foreign_json() {
+        // Другой порядок ключей, лишний ключ, \u-экранирование с суррогатной парой
+        let input = r#" [ {"DESCRIPTION":"Привет 🎉",
+            "STATUS":"PENDING","TIMESTAMP":"2021-10-01T03:00:00+03:00","AMOUNT":-5,
+            "TO_USER_ID":0,"FROM_USER_ID":7,"TX_TYPE":"WITHDRAWAL","TX_ID":18446744073709551615,
+            "source":null} ] "#;
+
+        let operations =
+            parse_all_with_provenance(input.as_bytes(), None, &ParseOptions::default()).unwrap();
+
+        let (operation, provenance) = &operations[0];
+        assert_eq!(operation.tx_id, u64::MAX);
+        assert_eq!(operation.description, "Привет 🎉");
+        assert_eq!(operation.timestamp, 1633046400000);
+        assert_eq!(operation.amount, -5);
+        assert_eq!(provenance.byte_offset, Some(3));
+
+        let mut reader = &input.as_bytes()[3..];
+        assert_eq!(
+            parse_one(&mut reader, &ParseOptions::default())
+                .unwrap()
+                .tx_id,
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let field_of = |input: &str| match parse_all(input.as_bytes()).unwrap_err() {
+            ParseError::WithContext { location, source } => match *source {
+                ParseError::InvalidField { field, .. } => {
+                    assert_eq!(location.field.as_ref(), Some(&field));
+                    field
+                }
+                other => panic!("{}: {:?}", input, other),
+            },
+            other => panic!("{}: {:?}", input, other),
+        };
+        let record = |fields: &str| {
+            format!(
+                "[{{\"TX_ID\": 1, \"TX_TYPE\": \"DEPOSIT\", \"FROM_USER_ID\": 0, \
+                 \"TO_USER_ID\": 2, \"TIMESTAMP\": 1633036800000, \"STATUS\": \"SUCCESS\", {}}}]",
+                fields
+            )
+        };
+
+        assert_eq!(
+            field_of(&record("\"AMOUNT\": \"100\", \"DESCRIPTION\": \"\"")),
+            "AMOUNT"
+        );
+        assert_eq!(
+            field_of(&record("\"AMOUNT\": 1.5, \"DESCRIPTION\": \"\"")),
+            "AMOUNT"
+        );
+        assert_eq!(field_of(&record("\"AMOUNT\": 100")), "DESCRIPTION");
+        assert_eq!(
+            field_of(&record("\"AMOUNT\": 100, \"DESCRIPTION\": null")),
+            "DESCRIPTION"
+        );
+        assert_eq!(
+            field_of(&record(
+                "\"AMOUNT\": 100, \"DESCRIPTION\": \"\", \"TX_TYPE\": \"GIFT\""
+            )),
+            "TX_TYPE"
+        );
+
+        for broken in ["", "{}", "[{\"TX_ID\": 1", "[{\"a\": {}}]", "[] []"] {
+            assert!(parse_all(broken.as_bytes()).is_err(), "{}", broken);
+        }
+    }
+
+    #[test]
+    fn test_syntax_error_has_line_without_duplicating_it() {
+        let mut buf = Vec::new();
+        write_all_with_options(
+            &mut buf,
+            &[op(1, "a"), op(2, "b")],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        // У второй записи (строка 3) нет `}`: ошибка там, где вместо нее пришла `]`
+        let broken = text.replacen("\"b\"}", "\"b\"", 1);
+
+        match parse_all(broken.as_bytes()).unwrap_err() {
+            ParseError::WithContext { location, source } => {
+                assert_eq!(location.line, Some(4));
+                let message = source.to_string();
+                assert!(message.contains("invalid JSON"), "{}", message);
+                assert!(!message.contains("line"), "{}", message);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
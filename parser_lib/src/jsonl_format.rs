@@ -0,0 +1,373 @@
+//! YPBankJsonl (JSON Lines / NDJSON): по объекту операции на строку
+//!
+//! Объект тот же, что у [`crate::json_format`], только без обрамляющего массива,
+//! поэтому файл пишется и читается построчно, не держа его в памяти целиком.
+//! Пустые строки пропускаются.
+
+use crate::dedup::{DedupOutcome, DuplicatePolicy};
+use crate::error::{ParseError, Result};
+use crate::footer::Footer;
+use crate::format::Format;
+use crate::json_format;
+use crate::operation::{FullOperation, Operation};
+use crate::options::{ParseOptions, WriteOptions};
+use crate::partition::ParseOutcome;
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{Meter, ParseReport};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+/// Читаем с jsonl файла
+pub fn parse_all<R: Read>(reader: R) -> Result<HashSet<Operation>> {
+    parse_all_with_options(reader, &ParseOptions::default())
+}
+
+/// Разбор с пропуском битых записей, см. [`crate::parse_all_lossy`]
+pub fn parse_all_lossy<R: Read>(reader: R, options: &ParseOptions) -> Result<ParseOutcome> {
+    crate::partition::parse_all_lossy(reader, Format::Jsonl, options)
+}
+
+/// То же, что [`parse_all`], но с настройками (например, токеном отмены)
+pub fn parse_all_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    Format::Jsonl.parse_all_with_options(reader, options)
+}
+
+/// То же, что [`parse_all_with_options`], плюс отчет с предупреждениями
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    Format::Jsonl.parse_all_with_report(reader, options)
+}
+
+/// Читает все операции в порядке файла, см. [`Format::parse_all_vec`]
+pub fn parse_all_vec<R: Read>(reader: R) -> Result<Vec<Operation>> {
+    Format::Jsonl.parse_all_vec(reader)
+}
+
+/// То же, что [`parse_all_vec`], но с настройками разбора
+pub fn parse_all_vec_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Operation>> {
+    Format::Jsonl.parse_all_vec_with_options(reader, options)
+}
+
+/// Разбор с политикой для повторов TX_ID, см. [`Format::parse_all_with_policy`]
+pub fn parse_all_with_policy<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    Format::Jsonl.parse_all_with_policy(reader, options, policy)
+}
+
+/// Число повторов каждой записи, см. [`Format::parse_all_counted`]
+pub fn parse_all_counted<R: Read>(reader: R) -> Result<HashMap<FullOperation, u64>> {
+    Format::Jsonl.parse_all_counted(reader)
+}
+
+/// Записи вместе с происхождением, см. [`Format::parse_all_with_provenance`]
+pub fn parse_all_with_provenance<R: Read>(
+    reader: R,
+    source: Option<&str>,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    Format::Jsonl.parse_all_with_provenance(reader, source, options)
+}
+
+/// Ленивый разбор: операции по одной, без накопления в памяти
+pub fn parse_iter<R: Read>(reader: R) -> JsonlIter<R> {
+    parse_iter_with_options(reader, &ParseOptions::default())
+}
+
+/// То же, что [`parse_iter`], но с настройками разбора
+pub fn parse_iter_with_options<R: Read>(reader: R, options: &ParseOptions) -> JsonlIter<R> {
+    JsonlIter {
+        reader: BufReader::new(reader),
+        options: options.clone(),
+        report: ParseReport::default(),
+        line: String::new(),
+        line_num: 0,
+        byte_offset: 0,
+        done: false,
+    }
+}
+
+/// Итератор из [`parse_iter`]
+///
+/// Строки независимы, поэтому после ошибки в записи итератор идет дальше,
+/// к следующей строке. Ошибка чтения и отмена его завершают.
+pub struct JsonlIter<R> {
+    reader: BufReader<R>,
+    options: ParseOptions,
+    report: ParseReport,
+    line: String,
+    line_num: u64,
+    byte_offset: u64,
+    done: bool,
+}
+
+impl<R: Read> JsonlIter<R> {
+    /// Отчет о уже прочитанных записях (счетчик и предупреждения)
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    /// Следующая запись вместе с ее началом в потоке
+    fn next_record(&mut self) -> Option<Result<(Operation, RecordPosition)>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record();
+        if matches!(
+            result,
+            None | Some(Err(ParseError::Io(_) | ParseError::Cancelled))
+        ) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_record(&mut self) -> Option<Result<(Operation, RecordPosition)>> {
+        loop {
+            if let Err(e) = self.options.check_cancelled() {
+                return Some(Err(e));
+            }
+            self.line.clear();
+            let read = match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(read) => read,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let position = RecordPosition {
+                line: Some(self.line_num + 1),
+                byte_offset: Some(self.byte_offset),
+            };
+            self.line_num += 1;
+            self.byte_offset += read as u64;
+
+            if self.line.trim().is_empty() {
+                continue;
+            }
+
+            let record_index = self.report.records as u64;
+            let result =
+                json_format::parse_line(&self.line, self.line_num, &self.options, &mut self.report)
+                    .and_then(|operation| operation.validate().map(|()| operation))
+                    .map_err(|e| e.at(position.location(Some(record_index))))
+                    .map(|operation| {
+                        self.report.check_rules(&operation, &self.options);
+                        self.report.records += 1;
+                        (operation, position)
+                    });
+            return Some(result);
+        }
+    }
+}
+
+impl<R: Read> Iterator for JsonlIter<R> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+            .map(|result| result.map(|(operation, _)| operation))
+    }
+}
+
+/// Читает одну запись с текущей позиции потока
+///
+/// Поток должен стоять на начале строки, например по [`Provenance::byte_offset`].
+/// Пустые строки перед записью пропускаются.
+pub fn parse_one<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Operation> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ParseError::UnexpectedEof);
+        }
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let operation = json_format::parse_line(&line, 1, options, &mut ParseReport::default())?;
+    operation.validate()?;
+    Ok(operation)
+}
+
+/// Потоковый разбор: отдает записи по одной, ошибка из `on_operation` прерывает разбор
+pub(crate) fn parse_each<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let meter = Meter::start("jsonl", options);
+    let report = parse_records(meter.reader(reader), options, |operation, position| {
+        meter.record();
+        on_operation(operation, position)
+    })?;
+    Ok(meter.finish(report))
+}
+
+/// Сам разбор для [`parse_each`], без замеров
+fn parse_records<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let mut iter = parse_iter_with_options(reader, options);
+    while let Some(record) = iter.next_record() {
+        let (operation, position) = record?;
+        on_operation(operation, position)?;
+    }
+
+    Ok(iter.report)
+}
+
+/// Потоковый разбор для карантина: каждая строка вместе с ее текстом
+///
+/// Ошибка в строке уходит в `on_record`, чтение продолжается со следующей.
+pub(crate) fn parse_each_raw<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let mut report = ParseReport::default();
+    let mut buf_reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut line_num = 0u64;
+    let mut byte_offset = 0u64;
+
+    loop {
+        options.check_cancelled()?;
+        line.clear();
+        let read = buf_reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        let position = RecordPosition {
+            line: Some(line_num + 1),
+            byte_offset: Some(byte_offset),
+        };
+        line_num += 1;
+        byte_offset += read as u64;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = json_format::parse_line(&line, line_num, options, &mut report)
+            .map_err(|e| e.at(position.location(None)));
+        let mut raw = line.clone().into_bytes();
+        if !raw.ends_with(b"\n") {
+            raw.push(b'\n');
+        }
+        on_record(result, raw, position)?;
+    }
+
+    Ok(())
+}
+
+/// Записываем всё в jsonl
+pub fn write_all<W: Write>(writer: W, operations: &HashSet<Operation>) -> Result<()> {
+    write_all_with_options(writer, operations, &WriteOptions::default())
+}
+
+/// То же, что [`write_all`], но с настройками (например, единицами TIMESTAMP)
+///
+/// Операции пишутся по мере обхода, так что `operations` может быть
+/// ленивым итератором по огромной выгрузке. `writer` оборачивается в [`BufWriter`].
+pub fn write_all_with_options<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let mut totals = Footer::default();
+
+    for operation in operations {
+        write_record(&mut writer, operation, options, &mut totals)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Пишет одну строку, проверив операцию, и добавляет ее в итоги
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+    totals: &mut Footer,
+) -> Result<()> {
+    let operation = options.limit_description(operation)?;
+    operation.validate()?;
+    totals.add(&operation);
+
+    json_format::write_object(writer, &operation, options)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn op(tx_id: u64, description: &str) -> Operation {
+        Operation::deposit(2, 100)
+            .tx_id(tx_id)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description(description)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_one_line_per_operation_and_blank_lines_skipped() {
+        let operations = vec![op(1, "multi\nline 🎉"), op(2, ""), op(1, "repeat")];
+
+        let mut buf = Vec::new();
+        write_all_with_options(&mut buf, &operations, &WriteOptions::default()).unwrap();
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 3);
+
+        let mut input = b"\n".to_vec();
+        input.extend_from_slice(&buf);
+        input.extend_from_slice(b"  \r\n");
+        let parsed = parse_all_vec(input.as_slice()).unwrap();
+        let descriptions: Vec<&str> = parsed.iter().map(|op| op.description.as_str()).collect();
+        assert_eq!(descriptions, ["multi\nline 🎉", "", "repeat"]);
+        assert_eq!(parse_all(input.as_slice()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_bad_line_reports_number_and_iterator_goes_on() {
+        let mut buf = Vec::new();
+        write_all_with_options(&mut buf, &[op(1, "a")], &WriteOptions::default()).unwrap();
+        let good = String::from_utf8(buf).unwrap();
+        let input = format!(
+            "{good}\n{}\n{{\"TX_ID\": 3,\n{good}",
+            good.trim_end()
+                .replace("\"AMOUNT\": 100", "\"AMOUNT\": \"100\"")
+        );
+
+        let err = parse_all(input.as_bytes()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("line 3, record #1, field AMOUNT: "),
+            "{}",
+            err
+        );
+
+        let results: Vec<Result<Operation>> = parse_iter(input.as_bytes()).collect();
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok() && results[3].is_ok());
+        let err = results[2].as_ref().unwrap_err();
+        assert_eq!(err.location().unwrap().line, Some(4), "{}", err);
+    }
+}
@@ -0,0 +1,487 @@
+//! Балансы пользователей по файлу операций
+//!
+//! [`Ledger`] отвечает на вопрос «сколько у пользователя по этому файлу»: суммирует
+//! в i128, так что не переполняется на любом файле, и ничего не отклоняет.
+//! [`apply`] прогоняет операции с проверкой бизнес-правил: операции, нарушающие
+//! правила, не применяются, а попадают в список отклоненных с причиной.
+
+use crate::error;
+use crate::operation::{Operation, OperationStatus, OperationType};
+use crate::stats::{BALANCES_HEADER, BalanceCsvOptions, format_amount};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+/// Правила прогона
+#[derive(Debug, Clone, Default)]
+pub struct LedgerOptions {
+    /// Отклонять операции, после которых баланс отправителя станет отрицательным
+    pub disallow_overdraft: bool,
+}
+
+/// Почему операция не применена
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// На счете не хватает средств (при `disallow_overdraft`)
+    Overdraft {
+        user_id: u64,
+        balance: i64,
+        amount: i64,
+    },
+    /// Баланс вышел бы за пределы i64
+    Overflow { user_id: u64 },
+    /// Отрицательная сумма: направление перевода неоднозначно
+    NegativeAmount,
+    /// Незнакомый тип ([`OperationType::Unknown`]): неизвестно, чьи балансы он меняет
+    UnknownType(String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::Overdraft {
+                user_id,
+                balance,
+                amount,
+            } => write!(
+                f,
+                "user {} has balance {}, cannot debit {}",
+                user_id, balance, amount
+            ),
+            RejectReason::Overflow { user_id } => {
+                write!(f, "balance of user {} overflows", user_id)
+            }
+            RejectReason::NegativeAmount => write!(f, "negative amount"),
+            RejectReason::UnknownType(tx_type) => write!(f, "unknown type {}", tx_type),
+        }
+    }
+}
+
+/// Отклоненная операция
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub tx_id: u64,
+    pub reason: RejectReason,
+}
+
+/// Итог прогона
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LedgerResult {
+    /// Балансы после всех примененных операций (пользователь 0 — внешняя сторона, его нет)
+    pub balances: HashMap<u64, i64>,
+    /// Сколько операций применено
+    pub applied: usize,
+    /// Отклоненные операции в порядке прогона
+    pub rejected: Vec<Rejection>,
+}
+
+/// Прогоняет операции по порядку, поддерживая балансы пользователей
+///
+/// # Аргументы
+/// * `ops_in_time_order` - Операции в хронологическом порядке (например, из `parse_all_vec`)
+/// * `options` - Правила прогона
+///
+/// # Возвращает
+/// Итоговые балансы и отклоненные операции. Операции не в статусе SUCCESS
+/// пропускаются: они не меняют балансы и не считаются отклоненными.
+pub fn apply(ops_in_time_order: &[Operation], options: LedgerOptions) -> LedgerResult {
+    let mut result = LedgerResult::default();
+
+    for op in ops_in_time_order {
+        if op.status != OperationStatus::Success {
+            continue;
+        }
+
+        match apply_one(&mut result.balances, op, &options) {
+            Ok(()) => result.applied += 1,
+            Err(reason) => result.rejected.push(Rejection {
+                tx_id: op.tx_id,
+                reason,
+            }),
+        }
+    }
+
+    result
+}
+
+/// Применяет одну операцию целиком или не меняет ничего
+fn apply_one(
+    balances: &mut HashMap<u64, i64>,
+    op: &Operation,
+    options: &LedgerOptions,
+) -> Result<(), RejectReason> {
+    if op.amount < 0 {
+        return Err(RejectReason::NegativeAmount);
+    }
+
+    let (debit, credit) = match op.tx_type {
+        OperationType::Deposit => (None, Some(op.to_user_id)),
+        OperationType::Withdrawal => (Some(op.from_user_id), None),
+        OperationType::Transfer => (Some(op.from_user_id), Some(op.to_user_id)),
+        OperationType::Unknown(ref tx_type) => {
+            return Err(RejectReason::UnknownType(tx_type.clone()));
+        }
+    };
+
+    let mut updates: Vec<(u64, i64)> = Vec::with_capacity(2);
+
+    if let Some(user_id) = debit.filter(|&id| id != 0) {
+        let balance = balances.get(&user_id).copied().unwrap_or(0);
+        let new_balance = balance
+            .checked_sub(op.amount)
+            .ok_or(RejectReason::Overflow { user_id })?;
+        if options.disallow_overdraft && new_balance < 0 {
+            return Err(RejectReason::Overdraft {
+                user_id,
+                balance,
+                amount: op.amount,
+            });
+        }
+        updates.push((user_id, new_balance));
+    }
+
+    if let Some(user_id) = credit.filter(|&id| id != 0) {
+        // Перевод самому себе: кредитуем уже списанный баланс
+        let balance = match updates.first() {
+            Some(&(id, balance)) if id == user_id => balance,
+            _ => balances.get(&user_id).copied().unwrap_or(0),
+        };
+        let new_balance = balance
+            .checked_add(op.amount)
+            .ok_or(RejectReason::Overflow { user_id })?;
+        updates.push((user_id, new_balance));
+    }
+
+    balances.extend(updates);
+    Ok(())
+}
+
+/// Что [`Ledger`] делает с операциями PENDING
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PendingPolicy {
+    /// Только считать: баланс — по завершенным операциям
+    #[default]
+    Count,
+    /// Применять как успешные: баланс с учетом того, что еще в пути
+    Apply,
+}
+
+/// Чистые позиции пользователей по успешным операциям
+///
+/// DEPOSIT зачисляет TO_USER_ID, WITHDRAWAL списывает с FROM_USER_ID,
+/// TRANSFER переносит сумму. Пользователь 0 — внешняя сторона, счета у него нет.
+/// FAILURE, операции незнакомых типов и статусов не меняют балансы, а только считаются.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    balances: HashMap<u64, i128>,
+    applied: usize,
+    pending: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl Ledger {
+    /// Балансы по операциям в любом порядке; PENDING только считаются
+    pub fn from_operations<'a>(ops: impl IntoIterator<Item = &'a Operation>) -> Ledger {
+        Ledger::from_operations_with(ops, PendingPolicy::Count)
+    }
+
+    /// То же, что [`Ledger::from_operations`], но PENDING по политике `pending`
+    pub fn from_operations_with<'a>(
+        ops: impl IntoIterator<Item = &'a Operation>,
+        pending: PendingPolicy,
+    ) -> Ledger {
+        let mut ledger = Ledger::default();
+        for op in ops {
+            ledger.add(op, pending);
+        }
+        ledger
+    }
+
+    fn add(&mut self, op: &Operation, pending: PendingPolicy) {
+        match op.status {
+            OperationStatus::Success => {}
+            OperationStatus::Pending => {
+                self.pending += 1;
+                if pending == PendingPolicy::Count {
+                    return;
+                }
+            }
+            OperationStatus::Failure => {
+                self.failed += 1;
+                return;
+            }
+            OperationStatus::Unknown(_) => {
+                self.skipped += 1;
+                return;
+            }
+        }
+
+        let amount = i128::from(op.amount);
+        let (debit, credit) = match op.tx_type {
+            OperationType::Deposit => (None, Some(op.to_user_id)),
+            OperationType::Withdrawal => (Some(op.from_user_id), None),
+            OperationType::Transfer => (Some(op.from_user_id), Some(op.to_user_id)),
+            // Чьи балансы меняет незнакомый тип, неизвестно: не трогаем ничьи
+            OperationType::Unknown(_) => {
+                self.skipped += 1;
+                return;
+            }
+        };
+        if let Some(user_id) = debit.filter(|&id| id != 0) {
+            *self.balances.entry(user_id).or_insert(0) -= amount;
+        }
+        if let Some(user_id) = credit.filter(|&id| id != 0) {
+            *self.balances.entry(user_id).or_insert(0) += amount;
+        }
+        self.applied += 1;
+    }
+
+    /// Баланс пользователя; 0, если его нет в файле
+    pub fn balance(&self, user_id: u64) -> i128 {
+        self.balances.get(&user_id).copied().unwrap_or(0)
+    }
+
+    /// Все балансы (пользователя 0 среди них нет)
+    pub fn balances(&self) -> &HashMap<u64, i128> {
+        &self.balances
+    }
+
+    /// Пользователи с отрицательным балансом, по возрастанию id
+    pub fn users_below_zero(&self) -> Vec<(u64, i128)> {
+        let mut below: Vec<(u64, i128)> = self
+            .balances
+            .iter()
+            .filter(|&(_, &balance)| balance < 0)
+            .map(|(&user_id, &balance)| (user_id, balance))
+            .collect();
+        below.sort_unstable();
+        below
+    }
+
+    /// `n` самых больших балансов по убыванию; при равенстве — по возрастанию id
+    pub fn top(&self, n: usize) -> Vec<(u64, i128)> {
+        let mut top: Vec<(u64, i128)> = self.balances.iter().map(|(&k, &v)| (k, v)).collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// Сколько операций изменили балансы
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Сколько операций PENDING (применены они или нет, зависит от [`PendingPolicy`])
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Сколько операций FAILURE
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Сколько операций пропущено из-за незнакомого типа или статуса
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Пишет [`Ledger::top`] как CSV `USER_ID,BALANCE`, в формате
+    /// [`crate::stats::write_balances_csv`]
+    pub fn write_top_csv<W: Write>(
+        &self,
+        mut writer: W,
+        n: usize,
+        options: BalanceCsvOptions,
+    ) -> error::Result<()> {
+        if options.header {
+            writeln!(writer, "{}", BALANCES_HEADER)?;
+        }
+        for (user_id, balance) in self.top(n) {
+            writeln!(
+                writer,
+                "{},{}",
+                user_id,
+                format_amount(balance, options.decimal_places)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::OperationBuilder;
+
+    fn op(tx_id: u64, tx_type: OperationType, from: u64, to: u64, amount: i64) -> Operation {
+        OperationBuilder::new(tx_type, from, to, amount)
+            .tx_id(tx_id)
+            .timestamp(tx_id)
+            .status(OperationStatus::Success)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_without_rules_matches_balance_math() {
+        let ops = vec![
+            op(1, OperationType::Deposit, 0, 1, 100),
+            op(2, OperationType::Transfer, 1, 2, 150),
+            op(3, OperationType::Withdrawal, 2, 0, 20),
+        ];
+
+        let result = apply(&ops, LedgerOptions::default());
+        assert_eq!(result.applied, 3);
+        assert!(result.rejected.is_empty());
+        assert_eq!(result.balances, HashMap::from([(1, -50), (2, 130)]));
+        let widened: HashMap<u64, i128> = result
+            .balances
+            .iter()
+            .map(|(&user_id, &balance)| (user_id, i128::from(balance)))
+            .collect();
+        assert_eq!(&widened, Ledger::from_operations(&ops).balances());
+    }
+
+    #[test]
+    fn test_overdraft_is_rejected_and_not_applied() {
+        let mut pending = op(4, OperationType::Withdrawal, 1, 0, 1000);
+        pending.status = OperationStatus::Pending;
+        let ops = vec![
+            op(1, OperationType::Deposit, 0, 1, 100),
+            op(2, OperationType::Transfer, 1, 2, 150),
+            op(3, OperationType::Transfer, 1, 2, 60),
+            pending,
+            op(5, OperationType::Withdrawal, 2, 0, 61),
+        ];
+
+        let result = apply(
+            &ops,
+            LedgerOptions {
+                disallow_overdraft: true,
+            },
+        );
+        assert_eq!(result.applied, 2);
+        assert_eq!(result.balances, HashMap::from([(1, 40), (2, 60)]));
+        assert_eq!(
+            result.rejected,
+            vec![
+                Rejection {
+                    tx_id: 2,
+                    reason: RejectReason::Overdraft {
+                        user_id: 1,
+                        balance: 100,
+                        amount: 150
+                    }
+                },
+                Rejection {
+                    tx_id: 5,
+                    reason: RejectReason::Overdraft {
+                        user_id: 2,
+                        balance: 60,
+                        amount: 61
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overflow_and_negative_amounts_are_rejected() {
+        let ops = vec![
+            op(1, OperationType::Deposit, 0, 1, i64::MAX),
+            op(2, OperationType::Deposit, 0, 1, 1),
+            op(3, OperationType::Deposit, 0, 2, -5),
+        ];
+
+        let result = apply(&ops, LedgerOptions::default());
+        assert_eq!(result.balances, HashMap::from([(1, i64::MAX)]));
+        assert_eq!(
+            result.rejected,
+            vec![
+                Rejection {
+                    tx_id: 2,
+                    reason: RejectReason::Overflow { user_id: 1 }
+                },
+                Rejection {
+                    tx_id: 3,
+                    reason: RejectReason::NegativeAmount
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ledger_hand_computed_scenario() {
+        let mut pending = op(5, OperationType::Transfer, 2, 3, 40);
+        pending.status = OperationStatus::Pending;
+        let mut failed = op(6, OperationType::Withdrawal, 1, 0, 999);
+        failed.status = OperationStatus::Failure;
+        let ops = vec![
+            op(1, OperationType::Deposit, 0, 1, 500),
+            op(2, OperationType::Deposit, 0, 2, 100),
+            op(3, OperationType::Transfer, 1, 2, 200),
+            op(4, OperationType::Withdrawal, 2, 0, 350),
+            pending,
+            failed,
+            op(7, OperationType::Unknown("REFUND".to_string()), 1, 2, 1),
+        ];
+
+        // 1: 500 - 200 = 300; 2: 100 + 200 - 350 = -50; 3 — только в PENDING
+        let ledger = Ledger::from_operations(&ops);
+        assert_eq!(ledger.balance(1), 300);
+        assert_eq!(ledger.balance(2), -50);
+        assert_eq!(ledger.balance(3), 0);
+        assert_eq!(ledger.balance(0), 0);
+        assert_eq!(ledger.balances(), &HashMap::from([(1, 300), (2, -50)]));
+        assert_eq!(ledger.users_below_zero(), vec![(2, -50)]);
+        assert_eq!(
+            (
+                ledger.applied(),
+                ledger.pending(),
+                ledger.failed(),
+                ledger.skipped()
+            ),
+            (4, 1, 1, 1)
+        );
+        assert_eq!(ledger.top(1), vec![(1, 300)]);
+
+        // С PENDING перевод 2 -> 3 уже учтен
+        let projected = Ledger::from_operations_with(&ops, PendingPolicy::Apply);
+        assert_eq!(projected.balance(2), -90);
+        assert_eq!(projected.balance(3), 40);
+        assert_eq!(projected.applied(), 5);
+        assert_eq!(projected.top(5), vec![(1, 300), (3, 40), (2, -90)]);
+    }
+
+    #[test]
+    fn test_ledger_does_not_overflow() {
+        let ops = vec![
+            op(1, OperationType::Deposit, 0, 1, i64::MAX),
+            op(2, OperationType::Deposit, 0, 1, i64::MAX),
+            op(3, OperationType::Withdrawal, 2, 0, i64::MAX),
+            op(4, OperationType::Withdrawal, 2, 0, i64::MAX),
+        ];
+
+        let ledger = Ledger::from_operations(&ops);
+        assert_eq!(ledger.balance(1), 2 * i128::from(i64::MAX));
+        assert_eq!(ledger.balance(2), -2 * i128::from(i64::MAX));
+
+        let mut csv = Vec::new();
+        ledger
+            .write_top_csv(
+                &mut csv,
+                1,
+                BalanceCsvOptions {
+                    header: true,
+                    decimal_places: Some(2),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "USER_ID,BALANCE\n1,184467440737095516.14\n"
+        );
+    }
+}
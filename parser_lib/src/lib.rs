@@ -6,14 +6,107 @@
 //! - Text format (YPBankText)
 //!
 
+#[cfg(feature = "serde")]
+pub mod annotations;
 pub mod bin_format;
+pub mod builder;
+pub mod channel;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod conformance;
+pub mod consistency;
+pub mod convert;
 pub mod csv_format;
+pub mod dedup;
+pub mod diff;
+#[cfg(feature = "digest")]
+pub mod digest;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod file;
+pub mod filter;
+pub mod footer;
+pub mod format;
+#[cfg(feature = "gzip")]
+pub mod gzip;
+pub mod index;
+pub mod io_util;
+#[cfg(feature = "serde")]
+pub mod json_format;
+#[cfg(feature = "serde")]
+pub mod jsonl_format;
+pub mod ledger;
+pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod migration;
+pub mod money;
 pub mod operation;
+pub mod options;
+pub mod order;
+pub mod partition;
+pub mod progress;
+pub mod provenance;
+#[cfg(feature = "redact")]
+pub mod redact;
+pub mod report;
+pub mod schema;
+pub mod stats;
+pub mod summary;
+#[cfg(feature = "testgen")]
+pub mod testgen;
 pub mod text_format;
+pub mod timestamp;
+pub mod transform;
+pub mod validation;
+#[cfg(feature = "digest")]
+pub mod verify;
 
-pub use error::{ParseError, Result};
-pub use operation::{Operation, OperationStatus, OperationType};
+pub use builder::OperationBuilder;
+pub use channel::{OperationReceiver, ParserHandle, spawn_parser};
+pub use consistency::{ConsistencyReport, ConsistencyRules};
+pub use convert::{ConvertStats, convert, convert_with_options, convert_with_pipeline};
+pub use dedup::{DedupOutcome, DuplicatePolicy};
+pub use diff::{DiffResult, DiffTextOptions, diff};
+#[cfg(feature = "digest")]
+pub use digest::{digest, digest_hex};
+pub use error::{Location, ParseError, Result};
+#[cfg(feature = "parallel")]
+pub use file::{ParallelParse, parse_files_parallel};
+pub use file::{
+    append_file, read_file, read_file_as, read_file_ordered, read_file_with_provenance,
+    read_file_with_report, write_file,
+};
+pub use filter::OperationFilter;
+pub use footer::Footer;
+pub use format::{Format, FormatWriter, OperationFormat, SNIFF_LEN, detect_format};
+pub use index::{BinIndex, build_index, build_index_with_policy, get_operation};
+pub use ledger::{Ledger, PendingPolicy};
+pub use merge::{
+    MergeConflict, MergePolicy, MergeResult, merge, merge_ordered, merge_with_provenance,
+};
+pub use migration::SchemaVersion;
+pub use money::{format_decimal_amount, parse_decimal_amount};
+pub use operation::{Extension, FullOperation, Operation, OperationStatus, OperationType};
+pub use options::{
+    AmountStyle, BinOptions, CancelToken, CsvOptions, DescriptionPolicy, ParseOptions,
+    TimestampUnit, WriteOptions,
+};
+pub use order::{SortField, SortKey, SortOrder, group_by_type, group_by_user, sort_operations};
+pub use partition::{ParseOutcome, PartitionOutcome, parse_all_lossy, partition};
+pub use progress::Progress;
+pub use provenance::Provenance;
+#[cfg(feature = "redact")]
+pub use redact::{DescriptionRedaction, RedactPolicy, redact_operations};
+pub use report::{ParseReport, ParseWarning};
+pub use schema::json_schema;
+pub use stats::{BalanceCsvOptions, BalanceDelta, TransitionViolation};
+pub use summary::Summary;
+pub use timestamp::TimeZoneSpec;
+pub use transform::{Pipeline, Transform};
+pub use validation::{Severity, ValidationIssue, ValidationReport, ValidationRules};
+#[cfg(feature = "digest")]
+pub use verify::{VerificationReport, verify_conversion};
 
 #[cfg(test)]
 mod tests {
@@ -30,6 +123,7 @@ mod tests {
             timestamp: 1633036800000,
             status: OperationStatus::Success,
             description: "Test deposit".to_string(),
+            extensions: Vec::new(),
         }
     }
 
@@ -59,6 +153,208 @@ mod tests {
         assert_eq!(operations, parsed);
     }
 
+    /// Считает вызовы `write`, как системные вызовы у голого `File`
+    #[derive(Default)]
+    struct CountingWriter {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writers_do_not_issue_tiny_writes() {
+        let operations: Vec<Operation> = (0..1000)
+            .map(|i| {
+                let mut op = create_test_operation();
+                op.tx_id = i;
+                op
+            })
+            .collect();
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut writer = CountingWriter::default();
+            format
+                .write_all_with_options(&mut writer, &operations, &WriteOptions::default())
+                .unwrap();
+
+            // Буфер в 8 КиБ: сотня килобайт — это десятки вызовов, а не тысячи
+            assert!(writer.writes < 100, "{:?}: {}", format, writer.writes);
+            let parsed = format.parse_all_vec(writer.bytes.as_slice()).unwrap();
+            assert_eq!(parsed, operations, "{:?}", format);
+        }
+
+        // Потоковые писатели не буферизуют, но запись уходит одним вызовом
+        let mut bin = bin_format::BinWriter::new(CountingWriter::default());
+        let mut csv = csv_format::CsvWriter::new(CountingWriter::default());
+        let mut txt = text_format::TextWriter::new(CountingWriter::default());
+        for op in &operations[..10] {
+            bin.write(op).unwrap();
+            csv.write(op).unwrap();
+            txt.write(op).unwrap();
+        }
+        assert_eq!(bin.finish().unwrap().writes, 10);
+        // Плюс заголовок
+        assert_eq!(csv.finish().unwrap().writes, 11);
+        assert_eq!(txt.finish().unwrap().writes, 10);
+    }
+
+    #[test]
+    fn test_csv_round_trip_quoted_descriptions() {
+        let descriptions = [
+            "Payment, invoice \"42\"",
+            "\"",
+            "\"\"quoted\"\"",
+            "  leading and trailing  ",
+            "",
+            "multi\nline, \"with\" quotes\r\n",
+        ];
+        let operations: Vec<Operation> = descriptions
+            .iter()
+            .enumerate()
+            .map(|(i, description)| {
+                let mut op = create_test_operation();
+                op.tx_id = i as u64;
+                op.description = description.to_string();
+                op
+            })
+            .collect();
+
+        let mut buf = Vec::new();
+        csv_format::write_all_with_options(&mut buf, &operations, &WriteOptions::default())
+            .unwrap();
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert!(
+            text.contains(",\"Payment, invoice \"\"42\"\"\"\n"),
+            "{}",
+            text
+        );
+
+        let parsed = csv_format::parse_all_vec(buf.as_slice()).unwrap();
+        let parsed: Vec<&str> = parsed.iter().map(|op| op.description.as_str()).collect();
+        assert_eq!(parsed, descriptions);
+    }
+
+    #[test]
+    fn test_csv_quoted_fields_anywhere_in_line() {
+        let csv = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                   \"1\",\"DEPOSIT\",0,\"2\",\"1,5\"0,1633036800000,SUCCESS,plain\n";
+        // Запятая внутри кавычек — не разделитель, даже в числовом поле
+        let err = csv_format::parse_all(csv.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("AMOUNT"), "{}", err);
+
+        let csv = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                   \"1\",\"DEPOSIT\",0,\"2\",\"15\"0,1633036800000,\"SUCCESS\",\"a \"\"b\"\"\" c\n";
+        let parsed = csv_format::parse_all_vec(csv.as_bytes()).unwrap();
+
+        // Мусор после закрывающей кавычки приклеивается к полю
+        assert_eq!((parsed[0].tx_id, parsed[0].amount), (1, 150));
+        assert_eq!(parsed[0].status, OperationStatus::Success);
+        assert_eq!(parsed[0].description, "a \"b\" c");
+
+        let short = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                     1,DEPOSIT,0,2,150,1633036800000,\"SUCCESS,plain\"\n";
+        let err = csv_format::parse_all(short.as_bytes()).unwrap_err();
+        assert!(
+            err.to_string().contains("Expected 8 fields, got 7"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_csv_reordered_lowercase_header_with_extra_columns() {
+        let csv = "status,Note,amount,tx_id,DESCRIPTION,timestamp,TX_TYPE,to_user_id,from_user_id\n\
+                   SUCCESS,ignored,100,7,\"a, b\",1633036800000,DEPOSIT,2,0\n";
+
+        let parsed = csv_format::parse_all_vec(csv.as_bytes()).unwrap();
+
+        assert_eq!(parsed.len(), 1);
+        let op = &parsed[0];
+        assert_eq!((op.tx_id, op.amount, op.to_user_id), (7, 100, 2));
+        assert_eq!(op.tx_type, OperationType::Deposit);
+        assert_eq!(op.description, "a, b");
+    }
+
+    #[test]
+    fn test_csv_header_missing_or_duplicate_columns() {
+        let missing = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,TIMESTAMP,DESCRIPTION\n";
+        let err = csv_format::parse_all(missing.as_bytes()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::InvalidFormat(m) if m.ends_with("missing columns AMOUNT, STATUS")),
+            "{}",
+            err
+        );
+
+        let duplicate =
+            "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION,amount\n";
+        let err = csv_format::parse_all(duplicate.as_bytes()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::InvalidFormat(m) if m == "Duplicate CSV column AMOUNT"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_csv_semicolon_and_tab_delimiters() {
+        let mut op = create_test_operation();
+        op.description = "a; b, \"c\"\td".to_string();
+        let operations: HashSet<Operation> = [op.clone()].into_iter().collect();
+
+        for delimiter in [';', '\t'] {
+            let csv = CsvOptions { delimiter };
+            let write_options = WriteOptions {
+                footer: true,
+                csv,
+                ..Default::default()
+            };
+            let parse_options = ParseOptions {
+                csv,
+                ..Default::default()
+            };
+
+            let mut buf = Vec::new();
+            csv_format::write_all_with_options(&mut buf, &operations, &write_options).unwrap();
+            let text = String::from_utf8(buf.clone()).unwrap();
+            assert!(text.starts_with(&format!("TX_ID{}TX_TYPE{}", delimiter, delimiter)));
+            assert!(text.ends_with(&format!("#TOTAL{}1{}10000\n", delimiter, delimiter)));
+
+            let parsed =
+                csv_format::parse_all_vec_with_options(buf.as_slice(), &parse_options).unwrap();
+            assert_eq!(parsed.len(), 1);
+            assert_eq!(parsed[0].description, op.description);
+            assert_eq!(parsed[0].amount, op.amount);
+
+            // С запятой по умолчанию заголовок не распознается
+            assert!(csv_format::parse_all(buf.as_slice()).is_err());
+        }
+
+        let quote = WriteOptions {
+            csv: CsvOptions { delimiter: '"' },
+            ..Default::default()
+        };
+        assert!(csv_format::write_all_with_options(Vec::new(), &operations, &quote).is_err());
+    }
+
     #[test]
     fn test_text_round_trip() {
         let operations: HashSet<Operation> = vec![create_test_operation()].into_iter().collect();
@@ -71,4 +367,1237 @@ mod tests {
 
         assert_eq!(operations, parsed);
     }
+
+    const TEXT_RECORD: &str = "TX_TYPE: DEPOSIT\nFROM_USER_ID: 0\nTO_USER_ID: 2\nAMOUNT: 100\n\
+                               TIMESTAMP: 1633036800000\nSTATUS: SUCCESS\nDESCRIPTION: \"x\"";
+
+    #[test]
+    fn test_text_records_separated_by_comments() {
+        // Последняя запись без перевода строки в конце
+        let text = format!(
+            "TX_ID: 1\n{r}\n# --- next ---\nTX_ID: 2\n{r}\n# --- next ---\n# again\nTX_ID: 3\n{r}",
+            r = TEXT_RECORD
+        );
+
+        let parsed =
+            text_format::parse_all_with_provenance(text.as_bytes(), None, &ParseOptions::default())
+                .unwrap();
+
+        let ids: Vec<u64> = parsed.iter().map(|(op, _)| op.tx_id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        let lines: Vec<Option<u64>> = parsed.iter().map(|(_, p)| p.line).collect();
+        assert_eq!(lines, [Some(1), Some(10), Some(20)]);
+    }
+
+    #[test]
+    fn test_text_iter_yields_in_order_and_stops_after_error() {
+        assert!(text_format::iter_operations("".as_bytes()).next().is_none());
+
+        let text = format!("TX_ID: 2\n{r}\n\nTX_ID: 1\n{r}\n", r = TEXT_RECORD);
+        let ids: Vec<u64> = text_format::iter_operations(text.as_bytes())
+            .map(|op| op.unwrap().tx_id)
+            .collect();
+        assert_eq!(ids, [2, 1]);
+
+        // Битая вторая запись: третья уже не читается
+        let text = format!(
+            "TX_ID: 1\n{r}\n\nTX_ID: 2\n{bad}\n\nTX_ID: 3\n{r}\n",
+            r = TEXT_RECORD,
+            bad = TEXT_RECORD.replace("AMOUNT: 100", "AMOUNT: lots")
+        );
+        let mut iter = text_format::iter_operations(text.as_bytes());
+        assert_eq!(iter.next().unwrap().unwrap().tx_id, 1);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+        assert_eq!(iter.report().records, 1);
+
+        // Расхождение футера отдается после последней записи
+        let text = format!(
+            "TX_ID: 1\n{}\n# RECORDS: 2\n# TOTAL_AMOUNT: 100\n",
+            TEXT_RECORD
+        );
+        let mut iter = text_format::iter_operations(text.as_bytes());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ParseError::FooterMismatch { .. }))
+        ));
+        assert!(iter.next().is_none());
+    }
+
+    /// Поток, в котором пока есть только `ready`: как `tail -f`, где остальное еще не дописано
+    struct Tail<'a> {
+        ready: &'a [u8],
+    }
+
+    impl std::io::Read for Tail<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert!(!self.ready.is_empty(), "read past the data written so far");
+            self.ready.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_text_iter_yields_record_at_blank_line() {
+        // Запись отдается по пустой строке, не дожидаясь следующих данных
+        let text = format!("TX_ID: 7\n{}\n\n", TEXT_RECORD);
+        let mut iter = text_format::iter_operations(Tail {
+            ready: text.as_bytes(),
+        });
+        assert_eq!(iter.next().unwrap().unwrap().tx_id, 7);
+        assert_eq!(iter.report().records, 1);
+    }
+
+    #[test]
+    fn test_csv_iter_reads_header_lazily_and_stops_after_error() {
+        let mut iter = csv_format::iter_operations("".as_bytes());
+        assert!(matches!(iter.next(), Some(Err(ParseError::UnexpectedEof))));
+        assert!(iter.next().is_none());
+
+        let header = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n";
+        assert!(
+            csv_format::iter_operations(header.as_bytes())
+                .next()
+                .is_none()
+        );
+
+        let row = |tx_id: u64, amount: &str| {
+            format!(
+                "{},DEPOSIT,0,2,{},1633036800000,SUCCESS,\"x\"\n",
+                tx_id, amount
+            )
+        };
+        let csv = format!(
+            "{}{}{}{}",
+            header,
+            row(3, "100"),
+            row(1, "oops"),
+            row(2, "100")
+        );
+        let mut iter = csv_format::iter_operations(csv.as_bytes());
+        assert_eq!(iter.next().unwrap().unwrap().tx_id, 3);
+        let err = iter.next().unwrap().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("line 3, record #1, field AMOUNT: "),
+            "{}",
+            err
+        );
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), err.without_context().to_string());
+        assert!(iter.next().is_none());
+        assert_eq!(iter.report().records, 1);
+    }
+
+    #[test]
+    fn test_iterators_match_parse_all() {
+        let ops: HashSet<Operation> = (1..=5)
+            .map(|tx_id| Operation {
+                tx_id,
+                ..create_test_operation()
+            })
+            .collect();
+
+        let mut csv = Vec::new();
+        csv_format::write_all(&mut csv, &ops).unwrap();
+        let parsed: HashSet<Operation> = csv_format::iter_operations(csv.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(parsed, ops);
+
+        let mut text = Vec::new();
+        text_format::write_all(&mut text, &ops).unwrap();
+        let parsed: HashSet<Operation> = text_format::iter_operations(text.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(parsed, ops);
+
+        let mut bin = Vec::new();
+        bin_format::write_all(&mut bin, &ops).unwrap();
+        let parsed: HashSet<Operation> = bin_format::iter_operations(bin.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(parsed, ops);
+    }
+
+    #[test]
+    fn test_incremental_writers_match_batch_output() {
+        let ops: Vec<Operation> = (1..=4)
+            .map(|tx_id| Operation {
+                tx_id,
+                ..create_test_operation()
+            })
+            .collect();
+        let options = WriteOptions {
+            footer: true,
+            ..Default::default()
+        };
+
+        let mut batch = Vec::new();
+        csv_format::write_all_with_options(&mut batch, &ops, &options).unwrap();
+        let mut writer = csv_format::CsvWriter::new(Vec::new()).with_options(options.clone());
+        for op in &ops {
+            writer.write(op).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), batch);
+
+        let mut batch = Vec::new();
+        text_format::write_all_with_options(&mut batch, &ops, &options).unwrap();
+        let mut writer = text_format::TextWriter::new(Vec::new()).with_options(options.clone());
+        for op in &ops {
+            writer.write(op).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), batch);
+
+        let mut batch = Vec::new();
+        bin_format::write_all_with_options(&mut batch, &ops, &options).unwrap();
+        let mut writer = bin_format::BinWriter::new(Vec::new()).with_options(options.clone());
+        for op in &ops {
+            writer.write(op).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), batch);
+
+        // Без записей CSV все равно получает заголовок
+        let mut batch = Vec::new();
+        csv_format::write_all(&mut batch, &HashSet::new()).unwrap();
+        assert_eq!(
+            csv_format::CsvWriter::new(Vec::new()).finish().unwrap(),
+            batch
+        );
+        assert!(batch.starts_with(b"TX_ID,"));
+    }
+
+    #[test]
+    fn test_incremental_writers_validate_each_operation() {
+        let bad = Operation {
+            tx_type: OperationType::Deposit,
+            from_user_id: 5,
+            ..create_test_operation()
+        };
+
+        let mut writer = text_format::TextWriter::new(Vec::new());
+        writer.write(&create_test_operation()).unwrap();
+        assert!(writer.write(&bad).is_err());
+        assert!(csv_format::CsvWriter::new(Vec::new()).write(&bad).is_err());
+        assert!(bin_format::BinWriter::new(Vec::new()).write(&bad).is_err());
+
+        // Отклоненная запись ничего не оставляет в выводе, разделитель тоже
+        let out = writer.finish().unwrap();
+        assert_eq!(text_format::parse_all(out.as_slice()).unwrap().len(), 1);
+        assert!(!out.ends_with(b"\n\n"));
+    }
+
+    #[test]
+    fn test_parse_all_ordered_keeps_file_order_and_first_duplicate() {
+        let op = |tx_id: u64, amount: i64| Operation {
+            tx_id,
+            amount,
+            ..create_test_operation()
+        };
+        let ops = [op(30, 1), op(10, 2), op(20, 3), op(10, 4)];
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &ops, &WriteOptions::default())
+                .unwrap();
+
+            let (parsed, report) = format
+                .parse_all_ordered(buf.as_slice(), &ParseOptions::default())
+                .unwrap();
+            let pairs: Vec<(u64, i64)> = parsed.iter().map(|op| (op.tx_id, op.amount)).collect();
+            assert_eq!(pairs, [(30, 1), (10, 2), (20, 3)], "{}", format.name());
+            assert_eq!(report.records, 4, "{}", format.name());
+
+            // Тот же порядок на выходе: перезапись дает те же байты
+            let mut rewritten = Vec::new();
+            format
+                .write_all_with_options(&mut rewritten, &parsed, &WriteOptions::default())
+                .unwrap();
+            let mut expected = Vec::new();
+            format
+                .write_all_with_options(&mut expected, &ops[..3], &WriteOptions::default())
+                .unwrap();
+            assert_eq!(rewritten, expected, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn test_text_accepts_hand_written_rfc3339() {
+        let record = |timestamp: &str| {
+            TEXT_RECORD.replace(
+                "TIMESTAMP: 1633036800000",
+                &format!("TIMESTAMP: {}", timestamp),
+            )
+        };
+        let text = format!(
+            "TX_ID: 1\n{}\n\nTX_ID: 2\n{}\n",
+            record("2021-10-01T00:00:00Z"),
+            record("2021-10-01T03:00:00.250+03:00")
+        );
+
+        let parsed = text_format::parse_all_vec(text.as_bytes()).unwrap();
+        let timestamps: Vec<u64> = parsed.iter().map(|op| op.timestamp).collect();
+        assert_eq!(timestamps, [1633046400000, 1633046400250]);
+
+        let text = format!("TX_ID: 1\n{}\n", record("2021-02-30T00:00:00Z"));
+        let err = text_format::parse_all(text.as_bytes()).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidField { field, reason } if field == "TIMESTAMP" && reason.contains("out of range")),
+            "{}",
+            err
+        );
+        // Строка самого поля, а не начала записи
+        assert_eq!(err.location().unwrap().line, Some(6));
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_text_keys_and_csv_columns() {
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        let text = format!("TX_ID: 1\n{}\nNOTE: dropped\n", TEXT_RECORD);
+        assert_eq!(text_format::parse_all(text.as_bytes()).unwrap().len(), 1);
+        let err = text_format::parse_all_with_options(text.as_bytes(), &strict).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidField { field, .. } if field == "NOTE"),
+            "{}",
+            err
+        );
+        assert_eq!(err.location().unwrap().line, Some(9));
+
+        let csv = "#VERSION: 1\nTX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION,Note\n\
+                   1,DEPOSIT,0,2,100,1633036800000,SUCCESS,\"x\",dropped\n";
+        assert_eq!(csv_format::parse_all(csv.as_bytes()).unwrap().len(), 1);
+        let err = csv_format::parse_all_with_options(csv.as_bytes(), &strict).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidField { field, .. } if field == "Note"),
+            "{}",
+            err
+        );
+        assert_eq!(err.location().unwrap().line, Some(2));
+    }
+
+    #[test]
+    fn test_text_duplicate_key_is_an_error() {
+        let text = format!("TX_ID: 1\n{}\nAMOUNT: 200\n", TEXT_RECORD);
+
+        let err = text_format::parse_all(text.as_bytes()).unwrap_err();
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidFormat(m) if m == "duplicate key AMOUNT"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            err.to_string(),
+            "line 9, field AMOUNT: Invalid format: duplicate key AMOUNT"
+        );
+
+        let outcome = partition(text.as_bytes(), Format::Txt, &ParseOptions::default()).unwrap();
+        assert!(outcome.accepted.is_empty());
+        assert_eq!(outcome.rejected.len(), 1);
+    }
+
+    #[test]
+    fn test_text_and_bin_descriptions_agree() {
+        let cases = conformance::edge_cases();
+        let parse = |format: Format| {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &cases, &WriteOptions::default())
+                .unwrap();
+            format.parse_all_vec(buf.as_slice()).unwrap()
+        };
+
+        let (text, bin) = (parse(Format::Txt), parse(Format::Bin));
+
+        assert_eq!(text.len(), cases.len());
+        for ((expected, text), bin) in cases.iter().zip(&text).zip(&bin) {
+            assert_eq!(text.description, expected.description);
+            assert_eq!(bin.description, text.description);
+        }
+    }
+
+    /// Каждая операция отдельно в каждом формате: так сравнение по байтам не зависит от порядка HashSet
+    fn encode_each(operations: &HashSet<Operation>, format: Format) -> Vec<(u64, Vec<u8>)> {
+        let mut encoded: Vec<(u64, Vec<u8>)> = operations
+            .iter()
+            .map(|op| {
+                let single: HashSet<Operation> = [op.clone()].into_iter().collect();
+                let mut buf = Vec::new();
+                format.write_all(&mut buf, &single).unwrap();
+                (op.tx_id, buf)
+            })
+            .collect();
+        encoded.sort();
+        encoded
+    }
+
+    fn convert_set(
+        operations: &HashSet<Operation>,
+        from: Format,
+        to: Format,
+    ) -> HashSet<Operation> {
+        let mut input = Vec::new();
+        from.write_all(&mut input, operations).unwrap();
+        let mut output = Vec::new();
+        convert(input.as_slice(), from, &mut output, to).unwrap();
+        to.parse_all(&mut output.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_cross_format_cycle_is_idempotent() {
+        let original: HashSet<Operation> = conformance::edge_cases().into_iter().collect();
+
+        let cycle = |start: &HashSet<Operation>| {
+            let csv = convert_set(start, Format::Bin, Format::Csv);
+            let txt = convert_set(&csv, Format::Csv, Format::Txt);
+            let bin = convert_set(&txt, Format::Txt, Format::Bin);
+            (csv, txt, bin)
+        };
+
+        let (csv1, txt1, bin1) = cycle(&original);
+        let (csv2, txt2, bin2) = cycle(&bin1);
+
+        assert_eq!(
+            encode_each(&csv1, Format::Csv),
+            encode_each(&csv2, Format::Csv)
+        );
+        assert_eq!(
+            encode_each(&txt1, Format::Txt),
+            encode_each(&txt2, Format::Txt)
+        );
+        assert_eq!(
+            encode_each(&bin1, Format::Bin),
+            encode_each(&bin2, Format::Bin)
+        );
+        // И ничего не потерялось уже на первом круге
+        assert_eq!(
+            encode_each(&original, Format::Bin),
+            encode_each(&bin1, Format::Bin)
+        );
+    }
+
+    #[test]
+    fn test_lenient_unknown_values_round_trip_in_every_format() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let mut refund = create_test_operation();
+        refund.tx_type = OperationType::Unknown("REFUND".to_string());
+        refund.status = OperationStatus::Unknown("CHARGEBACK".to_string());
+        // В бинарном формате неизвестное значение — это байт
+        let mut coded = create_test_operation();
+        coded.tx_type = OperationType::Unknown("3".to_string());
+        coded.status = OperationStatus::Unknown("9".to_string());
+
+        for (format, operation) in [
+            (Format::Csv, &refund),
+            (Format::Txt, &refund),
+            #[cfg(feature = "serde")]
+            (Format::Json, &refund),
+            #[cfg(feature = "serde")]
+            (Format::Jsonl, &refund),
+            (Format::Bin, &coded),
+        ] {
+            let mut written = Vec::new();
+            format
+                .write_all_with_options(
+                    &mut written,
+                    std::slice::from_ref(operation),
+                    &WriteOptions::default(),
+                )
+                .unwrap();
+
+            // По умолчанию неизвестное значение — ошибка, как и раньше
+            let err = format.parse_all_vec(written.as_slice()).unwrap_err();
+            assert!(
+                matches!(err.without_context(), ParseError::InvalidField { field, .. } if field == "TX_TYPE"),
+                "{:?}: {}",
+                format,
+                err
+            );
+
+            let parsed = format
+                .parse_all_vec_with_options(written.as_slice(), &lenient)
+                .unwrap();
+            assert_eq!(parsed.len(), 1);
+            assert!(
+                parsed[0].eq_full(operation),
+                "{:?}: {:?}",
+                format,
+                parsed[0]
+            );
+
+            let mut rewritten = Vec::new();
+            format
+                .write_all_with_options(&mut rewritten, &parsed, &WriteOptions::default())
+                .unwrap();
+            assert_eq!(rewritten, written, "{:?}", format);
+        }
+
+        // Нечисловое значение в байт не записать
+        let mut buf = Vec::new();
+        assert!(
+            Format::Bin
+                .write_all_with_options(&mut buf, &[refund.clone()], &WriteOptions::default())
+                .is_err()
+        );
+        // Правила пользователей для неизвестного типа не проверяются
+        refund.from_user_id = 5;
+        assert!(refund.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_all_vec_keeps_order_and_repeats() {
+        let mut pending = create_test_operation();
+        pending.status = OperationStatus::Pending;
+        let mut other = create_test_operation();
+        other.tx_id = 7;
+        let done = create_test_operation();
+        let ops = [pending, other, done];
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut input = Vec::new();
+            for (i, op) in ops.iter().enumerate() {
+                let single: HashSet<Operation> = [op.clone()].into_iter().collect();
+                let mut buf = Vec::new();
+                format.write_all(&mut buf, &single).unwrap();
+                if format == Format::Csv && i > 0 {
+                    // Заголовок CSV нужен только один раз
+                    let header_end = buf.iter().position(|&b| b == b'\n').unwrap() + 1;
+                    buf.drain(..header_end);
+                }
+                if format == Format::Txt && i > 0 {
+                    input.push(b'\n');
+                }
+                input.extend(buf);
+            }
+
+            let parsed = format.parse_all_vec(input.as_slice()).unwrap();
+            let statuses: Vec<(u64, OperationStatus)> = parsed
+                .iter()
+                .map(|op| (op.tx_id, op.status.clone()))
+                .collect();
+            assert_eq!(
+                statuses,
+                ops.iter()
+                    .map(|op| (op.tx_id, op.status.clone()))
+                    .collect::<Vec<_>>(),
+                "{}",
+                format.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_timestamp_unit_seconds_and_auto() {
+        let mut op = create_test_operation();
+        op.timestamp = 1633036800;
+        let in_seconds: HashSet<Operation> = [op].into_iter().collect();
+
+        for format in [Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format.write_all(&mut buf, &in_seconds).unwrap();
+
+            let seconds = ParseOptions {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..Default::default()
+            };
+            let (parsed, report) = format
+                .parse_all_with_report(buf.as_slice(), &seconds)
+                .unwrap();
+            assert_eq!(parsed.iter().next().unwrap().timestamp, 1633036800000);
+            assert!(report.warnings.is_empty());
+
+            let auto = ParseOptions {
+                timestamp_unit: TimestampUnit::Auto,
+                ..Default::default()
+            };
+            let (parsed, report) = format.parse_all_with_report(buf.as_slice(), &auto).unwrap();
+            assert_eq!(parsed.iter().next().unwrap().timestamp, 1633036800000);
+            assert_eq!(report.records, 1);
+            assert_eq!(report.warnings.len(), 1);
+            assert_eq!(report.warnings[0].tx_id, 1234567890123456);
+
+            // Обратно в секунды при записи
+            let mut out = Vec::new();
+            let write_options = WriteOptions {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..Default::default()
+            };
+            format
+                .write_all_with_options(&mut out, &parsed, &write_options)
+                .unwrap();
+            assert_eq!(out, buf, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn test_time_zone_display_reads_back_as_utc() {
+        let operations: HashSet<Operation> = [create_test_operation()].into_iter().collect();
+        let moscow = WriteOptions {
+            time_zone: Some("+03:00".parse().unwrap()),
+            ..Default::default()
+        };
+
+        for format in [Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &moscow)
+                .unwrap();
+            let text = String::from_utf8(buf.clone()).unwrap();
+            assert!(
+                text.contains("2021-10-01T00:20:00.000+03:00"),
+                "{}: {}",
+                format.name(),
+                text
+            );
+
+            // Явный пояс в строке важнее единиц из настроек
+            let seconds = ParseOptions {
+                timestamp_unit: TimestampUnit::Seconds,
+                ..Default::default()
+            };
+            let parsed = format
+                .parse_all_with_options(buf.as_slice(), &seconds)
+                .unwrap();
+            assert_eq!(parsed, operations);
+            assert_eq!(parsed.iter().next().unwrap().timestamp, 1633036800000);
+        }
+    }
+
+    #[test]
+    fn test_auto_keeps_millis_and_bin_ignores_unit() {
+        let operations: HashSet<Operation> = [create_test_operation()].into_iter().collect();
+        let seconds = ParseOptions {
+            timestamp_unit: TimestampUnit::Seconds,
+            ..Default::default()
+        };
+
+        let mut csv = Vec::new();
+        Format::Csv.write_all(&mut csv, &operations).unwrap();
+        let auto_csv = ParseOptions {
+            timestamp_unit: TimestampUnit::Auto,
+            ..Default::default()
+        };
+        let (parsed, report) = Format::Csv
+            .parse_all_with_report(csv.as_slice(), &auto_csv)
+            .unwrap();
+        assert_eq!(parsed, operations);
+        assert_eq!(parsed.iter().next().unwrap().timestamp, 1633036800000);
+        assert!(report.warnings.is_empty());
+
+        let mut bin = Vec::new();
+        Format::Bin.write_all(&mut bin, &operations).unwrap();
+        let parsed = Format::Bin
+            .parse_all_with_options(bin.as_slice(), &seconds)
+            .unwrap();
+        assert_eq!(parsed.iter().next().unwrap().timestamp, 1633036800000);
+    }
+
+    #[test]
+    fn test_decimal_amounts_in_csv_and_text() {
+        let decimal_read = ParseOptions {
+            amount_style: AmountStyle::Decimal,
+            ..Default::default()
+        };
+        let decimal_write = WriteOptions {
+            amount_style: AmountStyle::Decimal,
+            ..Default::default()
+        };
+        let csv = "TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n\
+                   1,DEPOSIT,0,7,1050.25,1633036800000,SUCCESS,\"Salary\"\n";
+        let text =
+            format!("TX_ID: 1\n{}", TEXT_RECORD).replace("AMOUNT: 100\n", "AMOUNT: 1050.25\n");
+
+        for (format, input) in [(Format::Csv, csv.to_string()), (Format::Txt, text)] {
+            // В минимальных единицах дробь — ошибка, как и раньше
+            let err = format.parse_all_vec(input.as_bytes()).unwrap_err();
+            assert!(
+                matches!(err.without_context(), ParseError::InvalidField { field, .. } if field == "AMOUNT"),
+                "{}",
+                err
+            );
+
+            let parsed = format
+                .parse_all_vec_with_options(input.as_bytes(), &decimal_read)
+                .unwrap();
+            assert_eq!(parsed[0].amount, 105025);
+            assert_eq!(parsed[0].amount_decimal_string(), "1050.25");
+
+            let mut out = Vec::new();
+            format
+                .write_all_with_options(&mut out, &parsed, &decimal_write)
+                .unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains("1050.25"), "{}", out);
+            let again = format
+                .parse_all_vec_with_options(out.as_bytes(), &decimal_read)
+                .unwrap();
+            assert!(again[0].eq_full(&parsed[0]));
+        }
+
+        // Запятая вместо точки — с подсказкой
+        let text =
+            format!("TX_ID: 1\n{}", TEXT_RECORD).replace("AMOUNT: 100\n", "AMOUNT: 1050,25\n");
+        let err = Format::Txt
+            .parse_all_vec_with_options(text.as_bytes(), &decimal_read)
+            .unwrap_err();
+        assert!(err.to_string().contains("not ','"), "{}", err);
+    }
+
+    #[test]
+    fn test_description_limit_in_all_writers() {
+        let mut op = create_test_operation();
+        op.description = "Перевод по счету 42".to_string();
+        let operations: HashSet<Operation> = [op].into_iter().collect();
+
+        let truncate = WriteOptions {
+            max_description_len: Some(16),
+            description_policy: DescriptionPolicy::TruncateAtCharBoundary,
+            ellipsis: "…".to_string(),
+            ..Default::default()
+        };
+        let error = WriteOptions {
+            description_policy: DescriptionPolicy::Error,
+            ..truncate.clone()
+        };
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &truncate)
+                .unwrap();
+            let parsed = format.parse_all(&mut buf.as_slice()).unwrap();
+            let description = &parsed.iter().next().unwrap().description;
+            // "Перево" — 12 байт, многоточие — 3
+            assert_eq!(description, "Перево…", "{}", format.name());
+
+            let result = format.write_all_with_options(&mut Vec::new(), &operations, &error);
+            assert!(
+                matches!(result, Err(ParseError::InvalidField { ref field, .. }) if field == "DESCRIPTION"),
+                "{}",
+                format.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_validation_rules_surface_as_warnings() {
+        let mut zero = create_test_operation();
+        zero.tx_id = 2;
+        zero.amount = 0;
+        let operations: HashSet<Operation> = [create_test_operation(), zero].into_iter().collect();
+        let checked = ParseOptions {
+            validation: Some(ValidationRules::default()),
+            ..Default::default()
+        };
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &WriteOptions::default())
+                .unwrap();
+
+            let (_, report) = format
+                .parse_all_with_report(buf.as_slice(), &ParseOptions::default())
+                .unwrap();
+            assert!(report.warnings.is_empty(), "{}", format.name());
+
+            // Запись не отбрасывается, нарушение попадает в отчет
+            let (parsed, report) = format
+                .parse_all_with_report(buf.as_slice(), &checked)
+                .unwrap();
+            assert_eq!(parsed.len(), 2, "{}", format.name());
+            assert_eq!(
+                report.warnings,
+                [ParseWarning {
+                    tx_id: 2,
+                    message: "error: AMOUNT: Must be positive, got 0".to_string(),
+                }],
+                "{}",
+                format.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_footer_round_trip_and_mismatch() {
+        let mut second = create_test_operation();
+        second.tx_id = 2;
+        second.amount = -300;
+        let operations: HashSet<Operation> =
+            [create_test_operation(), second].into_iter().collect();
+        let with_footer = WriteOptions {
+            footer: true,
+            ..Default::default()
+        };
+        let expected = Footer {
+            records: 2,
+            total_amount: 9700,
+        };
+
+        for format in [Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &with_footer)
+                .unwrap();
+
+            let (parsed, report) = format
+                .parse_all_with_report(buf.as_slice(), &ParseOptions::default())
+                .unwrap();
+            assert_eq!(parsed, operations);
+            assert_eq!(report.footer, Some(expected), "{}", format.name());
+
+            // Срезаем последнюю запись, оставляя футер
+            let text = String::from_utf8(buf).unwrap();
+            let truncated = match format {
+                Format::Csv => {
+                    let mut lines: Vec<&str> = text.lines().collect();
+                    lines.remove(2);
+                    lines.join("\n")
+                }
+                _ => {
+                    let (_, rest) = text.split_once("\n\n").unwrap();
+                    rest.to_string()
+                }
+            };
+            let result = format.parse_all(&mut truncated.as_bytes());
+            assert!(
+                matches!(result, Err(ParseError::FooterMismatch { expected: e, actual }) if e == expected && actual.records == 1),
+                "{}: {:?}",
+                format.name(),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_files_without_footer_parse_as_before() {
+        let operations: HashSet<Operation> = [create_test_operation()].into_iter().collect();
+
+        for format in [Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format.write_all(&mut buf, &operations).unwrap();
+            let (_, report) = format
+                .parse_all_with_report(buf.as_slice(), &ParseOptions::default())
+                .unwrap();
+            assert_eq!(report.footer, None);
+        }
+    }
+
+    #[test]
+    fn test_text_footer_only_at_end() {
+        let record = |id: u64| format!("TX_ID: {}\n{}\n", id, TEXT_RECORD);
+        let parse = |text: String| {
+            Format::Txt
+                .parse_all_with_report(text.as_bytes(), &ParseOptions::default())
+                .map(|(parsed, report)| (parsed.len(), report.footer))
+        };
+
+        // Ключи футера перед записями, неполный или нечисловой футер — просто комментарии
+        for text in [
+            format!("# RECORDS: 5\n# TOTAL_AMOUNT: 1\n\n{}", record(1)),
+            format!(
+                "{}\n# RECORDS: 5\n# TOTAL_AMOUNT: 1\n\n{}",
+                record(1),
+                record(2)
+            ),
+            format!("{}\n# RECORDS: 1\n", record(1)),
+            format!("{}\n# RECORDS: one\n# TOTAL_AMOUNT: 100\n", record(1)),
+        ] {
+            let (parsed, footer) = parse(text.clone()).unwrap();
+            assert!(parsed >= 1, "{}", text);
+            assert_eq!(footer, None, "{}", text);
+        }
+
+        // Оба ключа в конце файла — футер, и он сверяется
+        let footer = Footer {
+            records: 1,
+            total_amount: 100,
+        };
+        let text = format!("{}\n# RECORDS: 1\n# TOTAL_AMOUNT: 100\n", record(1));
+        assert_eq!(parse(text).unwrap(), (1, Some(footer)));
+        let text = format!("{}\n# RECORDS: 2\n# TOTAL_AMOUNT: 100\n", record(1));
+        assert!(matches!(
+            parse(text),
+            Err(ParseError::FooterMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_all_counted_in_every_format() {
+        let op = create_test_operation();
+        let mut changed = op.clone();
+        changed.amount += 1;
+        let ops = vec![op.clone(), changed.clone(), op.clone(), op.clone()];
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &ops, &WriteOptions::default())
+                .unwrap();
+
+            let counts = format.parse_all_counted(buf.as_slice()).unwrap();
+            assert_eq!(counts.len(), 2, "{}", format.name());
+            assert_eq!(counts[&FullOperation(op.clone())], 3);
+            assert_eq!(counts[&FullOperation(changed.clone())], 1);
+        }
+    }
+
+    #[test]
+    fn test_random_access_by_offset() {
+        let ops: Vec<Operation> = conformance::golden_fixture();
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            let with_footer = WriteOptions {
+                footer: true,
+                ..Default::default()
+            };
+            format
+                .write_all_with_options(&mut buf, &ops, &with_footer)
+                .unwrap();
+
+            let mut offsets = Vec::new();
+            let report = format
+                .for_each_record(buf.as_slice(), &ParseOptions::default(), |_, provenance| {
+                    offsets.push(provenance.byte_offset.unwrap());
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(report.records, ops.len());
+
+            // С конца к началу, чтобы чтение не опиралось на порядок
+            for (i, &offset) in offsets.iter().enumerate().rev() {
+                let mut cursor = Cursor::new(&buf);
+                cursor.set_position(offset);
+                let op = format
+                    .parse_one(&mut cursor, &ParseOptions::default())
+                    .unwrap();
+                assert_eq!(op.tx_id, ops[i].tx_id, "{} #{}", format.name(), i);
+                assert!(op.differing_fields(&ops[i]).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_utf8_bom_is_skipped_in_csv_and_text() {
+        let mut second = create_test_operation();
+        second.tx_id = 2;
+        let ops = vec![create_test_operation(), second];
+
+        for format in [Format::Csv, Format::Txt] {
+            let mut buf = b"\xEF\xBB\xBF".to_vec();
+            format
+                .write_all_with_options(&mut buf, &ops, &WriteOptions::default())
+                .unwrap();
+
+            assert_eq!(Format::sniff(&buf), Some(format));
+            let parsed = format
+                .parse_all_with_provenance(buf.as_slice(), None, &ParseOptions::default())
+                .unwrap();
+            assert_eq!(parsed.len(), 2, "{}", format.name());
+
+            // Смещения считаются от начала файла, вместе с BOM
+            for (op, provenance) in &parsed {
+                let mut cursor = Cursor::new(&buf);
+                cursor.set_position(provenance.byte_offset.unwrap());
+                let reread = format
+                    .parse_one(&mut cursor, &ParseOptions::default())
+                    .unwrap();
+                assert_eq!(reread.tx_id, op.tx_id, "{}", format.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect_format_tells_csv_from_text() {
+        let cases: &[(&str, Format)] = &[
+            ("TX_ID,TX_TYPE,AMOUNT\n1,DEPOSIT,100\n", Format::Csv),
+            ("tx_id;amount;tx_type\n", Format::Csv),
+            ("#VERSION: 2\nAMOUNT\tTX_ID\n", Format::Csv),
+            ("\u{FEFF}\"TX_ID\",\"AMOUNT\"\n", Format::Csv),
+            ("# export\nTX_ID: 1\nDESCRIPTION: \"a, b\"\n", Format::Txt),
+            ("\u{FEFF}\n\nAMOUNT: 100\n", Format::Txt),
+            #[cfg(feature = "serde")]
+            ("  [{\"TX_ID\": 1}]", Format::Json),
+        ];
+        for &(text, format) in cases {
+            assert_eq!(
+                detect_format(text.as_bytes()).unwrap(),
+                format,
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(detect_format(b"YPBF\0\0").unwrap(), Format::Bin);
+
+        for empty in ["", "\u{FEFF}", " \n\t"] {
+            assert!(matches!(
+                detect_format(empty.as_bytes()),
+                Err(ParseError::EmptyInput)
+            ));
+        }
+        for garbage in ["hello there", "a,b,c\n1,2,3", "\x01\x02"] {
+            assert!(matches!(
+                detect_format(garbage.as_bytes()),
+                Err(ParseError::InvalidFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_bin_file_header_is_sniffed_and_offsets_stay_absolute() {
+        let mut second = create_test_operation();
+        second.tx_id = 2;
+        let ops: HashSet<Operation> = [create_test_operation(), second].into_iter().collect();
+        let mut buf = Vec::new();
+        bin_format::write_all_with_header(&mut buf, &ops).unwrap();
+
+        assert_eq!(Format::sniff(&buf), Some(Format::Bin));
+        let parsed = Format::Bin
+            .parse_all_with_provenance(buf.as_slice(), None, &ParseOptions::default())
+            .unwrap();
+        assert_eq!(parsed.len(), 2);
+        for (op, provenance) in &parsed {
+            let mut cursor = Cursor::new(&buf);
+            cursor.set_position(provenance.byte_offset.unwrap());
+            let reread = Format::Bin
+                .parse_one(&mut cursor, &ParseOptions::default())
+                .unwrap();
+            assert_eq!(reread.tx_id, op.tx_id);
+        }
+    }
+
+    #[test]
+    fn test_bin_reports_bom_as_wrong_format() {
+        let csv =
+            "\u{FEFF}TX_ID,TX_TYPE,FROM_USER_ID,TO_USER_ID,AMOUNT,TIMESTAMP,STATUS,DESCRIPTION\n";
+
+        let err = bin_format::parse_all(csv.as_bytes()).unwrap_err();
+
+        assert!(
+            matches!(err.without_context(), ParseError::InvalidFormat(m) if m.contains("BOM")),
+            "{}",
+            err
+        );
+    }
+
+    /// Две операции с описанием в несколько строк, с футером и BOM, и их порча:
+    /// `amount` второй записи меняется на `bad_amount`, `records` в футере — на `bad_records`
+    #[cfg(feature = "async")]
+    fn async_inputs(
+        format: Format,
+        (amount, bad_amount): (&str, &str),
+        (records, bad_records): (&str, &str),
+    ) -> Vec<Vec<u8>> {
+        let mut second = create_test_operation();
+        second.tx_id = 2;
+        second.description = "multi\nline, \"quoted\"".to_string();
+        let options = WriteOptions {
+            footer: true,
+            ..WriteOptions::default()
+        };
+        let mut buf = b"\xEF\xBB\xBF".to_vec();
+        format
+            .write_all_with_options(&mut buf, &[create_test_operation(), second], &options)
+            .unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains(amount) && text.contains(records), "{}", text);
+
+        let broken_amount = text
+            .replacen(amount, bad_amount, 2)
+            .replacen(bad_amount, amount, 1);
+        let broken_footer = text.replace(records, bad_records);
+        vec![
+            text.into_bytes(),
+            broken_amount.into_bytes(),
+            broken_footer.into_bytes(),
+        ]
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_csv_matches_sync() {
+        let fixture = include_bytes!("../golden/fixture.csv");
+        let options = ParseOptions::default();
+        let expected = csv_format::parse_all(&fixture[..]).unwrap();
+        let parsed = csv_format::parse_all_async(&fixture[..], &options)
+            .await
+            .unwrap();
+        let full = |ops: HashSet<Operation>| -> HashSet<FullOperation> {
+            ops.into_iter().map(FullOperation).collect()
+        };
+        assert_eq!(full(parsed), full(expected.clone()));
+
+        for input in async_inputs(Format::Csv, (",10000,", ",x,"), ("#TOTAL,2,", "#TOTAL,3,")) {
+            let sync = csv_format::parse_all(input.as_slice()).map_err(|e| e.to_string());
+            let parsed = csv_format::parse_all_async(input.as_slice(), &options)
+                .await
+                .map_err(|e| e.to_string());
+            assert_eq!(parsed.map(full), sync.map(full));
+        }
+
+        let operations = csv_format::parse_all_vec(&fixture[..]).unwrap();
+        let write_options = WriteOptions {
+            footer: true,
+            ..WriteOptions::default()
+        };
+        let mut sync_out = Vec::new();
+        csv_format::write_all_with_options(&mut sync_out, &operations, &write_options).unwrap();
+        let mut async_out = Vec::new();
+        csv_format::write_all_async(&mut async_out, &operations, &write_options)
+            .await
+            .unwrap();
+        assert_eq!(async_out, sync_out);
+
+        let (mut client, server) = tokio::io::duplex(64);
+        let write = async move {
+            csv_format::write_all_async(&mut client, &operations, &write_options).await
+        };
+        let (written, read) = tokio::join!(write, csv_format::parse_all_async(server, &options));
+        written.unwrap();
+        assert_eq!(full(read.unwrap()), full(expected));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_text_matches_sync() {
+        let fixture = include_bytes!("../golden/fixture.txt");
+        let options = ParseOptions::default();
+        let expected = text_format::parse_all(&fixture[..]).unwrap();
+        let parsed = text_format::parse_all_async(&fixture[..], &options)
+            .await
+            .unwrap();
+        let full = |ops: HashSet<Operation>| -> HashSet<FullOperation> {
+            ops.into_iter().map(FullOperation).collect()
+        };
+        assert_eq!(full(parsed), full(expected.clone()));
+
+        for input in async_inputs(
+            Format::Txt,
+            ("AMOUNT: 10000", "AMOUNT: x"),
+            ("# RECORDS: 2", "# RECORDS: 3"),
+        ) {
+            let sync = text_format::parse_all(input.as_slice()).map_err(|e| e.to_string());
+            let parsed = text_format::parse_all_async(input.as_slice(), &options)
+                .await
+                .map_err(|e| e.to_string());
+            assert_eq!(parsed.map(full), sync.map(full));
+        }
+
+        let operations = text_format::parse_all_vec(&fixture[..]).unwrap();
+        let write_options = WriteOptions {
+            footer: true,
+            ..WriteOptions::default()
+        };
+        let mut sync_out = Vec::new();
+        text_format::write_all_with_options(&mut sync_out, &operations, &write_options).unwrap();
+        let mut async_out = Vec::new();
+        text_format::write_all_async(&mut async_out, &operations, &write_options)
+            .await
+            .unwrap();
+        assert_eq!(async_out, sync_out);
+
+        let (mut client, server) = tokio::io::duplex(64);
+        let write = async move {
+            text_format::write_all_async(&mut client, &operations, &write_options).await
+        };
+        let (written, read) = tokio::join!(write, text_format::parse_all_async(server, &options));
+        written.unwrap();
+        assert_eq!(full(read.unwrap()), full(expected));
+    }
+
+    #[test]
+    fn test_format_writer_matches_write_all() {
+        let mut second = create_test_operation();
+        second.tx_id += 1;
+        second.description = "Второй, \"с кавычками\"".to_string();
+        let operations = [create_test_operation(), second];
+
+        for footer in [false, true] {
+            let options = WriteOptions {
+                footer,
+                ..WriteOptions::default()
+            };
+            for format in [
+                Format::Bin,
+                Format::Csv,
+                Format::Txt,
+                #[cfg(feature = "serde")]
+                Format::Json,
+                #[cfg(feature = "serde")]
+                Format::Jsonl,
+            ] {
+                for operations in [&operations[..], &[]] {
+                    let mut expected = Vec::new();
+                    format
+                        .write_all_with_options(&mut expected, operations, &options)
+                        .unwrap();
+
+                    let mut writer =
+                        FormatWriter::new(Vec::new(), format).with_options(options.clone());
+                    for operation in operations {
+                        writer.write(operation).unwrap();
+                    }
+                    assert_eq!(writer.totals().records, operations.len() as u64);
+                    let written = writer.finish().unwrap();
+                    assert_eq!(written, expected, "{} footer={}", format.name(), footer);
+                }
+            }
+        }
+    }
 }
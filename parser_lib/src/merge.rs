@@ -0,0 +1,430 @@
+//! Слияние нескольких наборов операций в один
+//!
+//! Одна и та же выгрузка часто приходит из двух систем. Записи с одним TX_ID
+//! и одинаковыми полями просто схлопываются; если поля расходятся, это
+//! конфликт, и его решает [`MergePolicy`]. [`merge_with_provenance`] вдобавок
+//! помнит, откуда взята каждая запись, и пишет это в отчет о конфликтах.
+
+use crate::error::{ParseError, Result};
+use crate::operation::{FieldDiff, Operation};
+use crate::provenance::Provenance;
+use std::collections::{HashMap, HashSet};
+
+/// Как решать конфликт: две записи с одним TX_ID расходятся хотя бы в одном поле
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    /// Вернуть [`ParseError::MergeConflict`]
+    #[default]
+    FailOnConflict,
+    /// Оставить запись из входа с меньшим номером
+    PreferFirst,
+    /// Оставить запись из входа с большим номером
+    PreferLast,
+    /// Оставить запись с большим TIMESTAMP; при равенстве — из входа с меньшим номером
+    PreferNewestTimestamp,
+}
+
+/// Решенный конфликт
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub tx_id: u64,
+    /// Номер входа (с 0), чья запись осталась
+    pub kept: usize,
+    /// Номер входа, чья запись отброшена
+    pub dropped: usize,
+    /// Расхождения: `left` — оставленная запись, `right` — отброшенная
+    pub differences: Vec<FieldDiff>,
+    /// Откуда оставленная запись; только у [`merge_with_provenance`]
+    pub kept_provenance: Option<Provenance>,
+    /// Откуда отброшенная запись; только у [`merge_with_provenance`]
+    pub dropped_provenance: Option<Provenance>,
+}
+
+/// Итоги [`merge`] и [`merge_ordered`]
+#[derive(Debug, Default)]
+pub struct MergeResult<C = HashSet<Operation>> {
+    /// По одной записи на TX_ID
+    pub operations: C,
+    /// Решенные конфликты по возрастанию TX_ID
+    pub conflicts: Vec<MergeConflict>,
+    /// Сколько записей совпали с уже взятыми во всех полях и схлопнулись
+    pub identical: usize,
+}
+
+/// Сливает наборы операций в один
+///
+/// Для TX_ID, который есть в трех входах и больше, конфликты решаются по
+/// очереди: каждая следующая запись сравнивается с победителем предыдущих.
+///
+/// # Возвращает
+/// * `Ok(MergeResult)` - Объединенный набор и список решенных конфликтов
+/// * `Err(ParseError::MergeConflict)` - Конфликт при [`MergePolicy::FailOnConflict`]
+pub fn merge(inputs: Vec<HashSet<Operation>>, policy: MergePolicy) -> Result<MergeResult> {
+    let result = merge_slots(without_provenance(inputs), policy)?;
+    Ok(MergeResult {
+        operations: result.operations.into_iter().map(|(op, _)| op).collect(),
+        conflicts: result.conflicts,
+        identical: result.identical,
+    })
+}
+
+/// То же, что [`merge`], но с порядком: входы по очереди, внутри — в порядке записей
+///
+/// Каждый TX_ID стоит там, где он встретился впервые, даже если по `policy`
+/// победила запись из более позднего входа.
+pub fn merge_ordered(
+    inputs: Vec<Vec<Operation>>,
+    policy: MergePolicy,
+) -> Result<MergeResult<Vec<Operation>>> {
+    let result = merge_slots(without_provenance(inputs), policy)?;
+    Ok(MergeResult {
+        operations: result.operations.into_iter().map(|(op, _)| op).collect(),
+        conflicts: result.conflicts,
+        identical: result.identical,
+    })
+}
+
+/// То же, что [`merge_ordered`], но записи несут происхождение, например из
+/// [`crate::read_file_with_provenance`]
+///
+/// У каждой оставленной записи — происхождение победителя, а у каждого
+/// конфликта — обеих записей: [`MergeConflict::kept_provenance`] и
+/// [`MergeConflict::dropped_provenance`].
+pub fn merge_with_provenance(
+    inputs: Vec<Vec<(Operation, Provenance)>>,
+    policy: MergePolicy,
+) -> Result<MergeResult<Vec<(Operation, Provenance)>>> {
+    let inputs = inputs.into_iter().map(|operations| {
+        operations
+            .into_iter()
+            .map(|(op, provenance)| (op, Some(provenance)))
+    });
+    let result = merge_slots(inputs.collect(), policy)?;
+    Ok(MergeResult {
+        operations: result
+            .operations
+            .into_iter()
+            .map(|(op, provenance)| (op, provenance.unwrap_or_default()))
+            .collect(),
+        conflicts: result.conflicts,
+        identical: result.identical,
+    })
+}
+
+/// Запись слияния и ее происхождение, если его отслеживают
+type Tagged = (Operation, Option<Provenance>);
+
+/// Входы без происхождения: `None` ничего не стоит
+fn without_provenance<I>(inputs: Vec<I>) -> Vec<impl Iterator<Item = Tagged>>
+where
+    I: IntoIterator<Item = Operation>,
+{
+    inputs
+        .into_iter()
+        .map(|operations| operations.into_iter().map(|op| (op, None)))
+        .collect()
+}
+
+/// Общая часть слияний: записи в порядке первого появления TX_ID
+fn merge_slots<I>(inputs: Vec<I>, policy: MergePolicy) -> Result<MergeResult<Vec<Tagged>>>
+where
+    I: IntoIterator<Item = Tagged>,
+{
+    // TX_ID -> место в `slots`; в месте — номер входа, запись и ее происхождение
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    let mut slots: Vec<(usize, Operation, Option<Provenance>)> = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut identical = 0;
+
+    for (input, operations) in inputs.into_iter().enumerate() {
+        for (op, provenance) in operations {
+            let Some(&position) = positions.get(&op.tx_id) else {
+                positions.insert(op.tx_id, slots.len());
+                slots.push((input, op, provenance));
+                continue;
+            };
+            let (kept_input, kept, kept_provenance) = &mut slots[position];
+            if kept.eq_full(&op) {
+                identical += 1;
+                continue;
+            }
+
+            let replace = match policy {
+                MergePolicy::FailOnConflict => {
+                    return Err(ParseError::MergeConflict {
+                        tx_id: op.tx_id,
+                        first: *kept_input,
+                        second: input,
+                        fields: kept.diff_fields(&op).iter().map(|d| d.field).collect(),
+                    });
+                }
+                MergePolicy::PreferFirst => false,
+                MergePolicy::PreferLast => true,
+                MergePolicy::PreferNewestTimestamp => op.timestamp > kept.timestamp,
+            };
+            let conflict = if replace {
+                let conflict = MergeConflict {
+                    tx_id: op.tx_id,
+                    kept: input,
+                    dropped: *kept_input,
+                    differences: op.diff_fields(kept),
+                    kept_provenance: provenance.clone(),
+                    dropped_provenance: kept_provenance.take(),
+                };
+                *kept_input = input;
+                *kept = op;
+                *kept_provenance = provenance;
+                conflict
+            } else {
+                MergeConflict {
+                    tx_id: op.tx_id,
+                    kept: *kept_input,
+                    dropped: input,
+                    differences: kept.diff_fields(&op),
+                    kept_provenance: kept_provenance.clone(),
+                    dropped_provenance: provenance,
+                }
+            };
+            conflicts.push(conflict);
+        }
+    }
+
+    conflicts.sort_by_key(|c| (c.tx_id, c.dropped.min(c.kept), c.dropped.max(c.kept)));
+    Ok(MergeResult {
+        operations: slots
+            .into_iter()
+            .map(|(_, op, provenance)| (op, provenance))
+            .collect(),
+        conflicts,
+        identical,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn op(tx_id: u64, amount: i64, timestamp: u64) -> Operation {
+        Operation::deposit(7, amount)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(OperationStatus::Success)
+            .description(format!("deposit {}", tx_id))
+            .build()
+            .unwrap()
+    }
+
+    fn set(ops: &[Operation]) -> HashSet<Operation> {
+        ops.iter().cloned().collect()
+    }
+
+    fn amount_of(result: &MergeResult, tx_id: u64) -> i64 {
+        result
+            .operations
+            .iter()
+            .find(|op| op.tx_id == tx_id)
+            .unwrap()
+            .amount
+    }
+
+    #[test]
+    fn test_disjoint_sets_are_unioned() {
+        let result = merge(
+            vec![
+                set(&[op(1, 100, 10)]),
+                set(&[op(2, 200, 20), op(3, 300, 30)]),
+            ],
+            MergePolicy::FailOnConflict,
+        )
+        .unwrap();
+
+        assert_eq!(result.operations.len(), 3);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.identical, 0);
+    }
+
+    #[test]
+    fn test_identical_duplicates_are_not_conflicts() {
+        let ops = [op(1, 100, 10), op(2, 200, 20)];
+
+        let result = merge(
+            vec![set(&ops), set(&ops), set(&ops[..1])],
+            MergePolicy::FailOnConflict,
+        )
+        .unwrap();
+
+        assert_eq!(result.operations.len(), 2);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.identical, 3);
+    }
+
+    #[test]
+    fn test_fail_on_conflict_names_inputs_and_fields() {
+        let err = merge(
+            vec![set(&[op(1, 100, 10)]), set(&[op(1, 150, 10)])],
+            MergePolicy::FailOnConflict,
+        )
+        .unwrap_err();
+
+        match err {
+            ParseError::MergeConflict {
+                tx_id,
+                first,
+                second,
+                fields,
+            } => {
+                assert_eq!((tx_id, first, second), (1, 0, 1));
+                assert_eq!(fields, ["AMOUNT"]);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_policies_pick_the_winner() {
+        // Вход 1 новее по времени, вход 2 — последний
+        let inputs = || {
+            vec![
+                set(&[op(1, 100, 10), op(2, 200, 20)]),
+                set(&[op(1, 111, 50)]),
+                set(&[op(1, 122, 30), op(2, 200, 20)]),
+            ]
+        };
+
+        let first = merge(inputs(), MergePolicy::PreferFirst).unwrap();
+        assert_eq!(amount_of(&first, 1), 100);
+        assert_eq!(first.identical, 1);
+        let kept: Vec<(usize, usize)> = first
+            .conflicts
+            .iter()
+            .map(|c| (c.kept, c.dropped))
+            .collect();
+        assert_eq!(kept, [(0, 1), (0, 2)]);
+        assert_eq!(
+            first.conflicts[0].differences[0].to_string(),
+            "AMOUNT: 100 != 111"
+        );
+
+        let last = merge(inputs(), MergePolicy::PreferLast).unwrap();
+        assert_eq!(amount_of(&last, 1), 122);
+        assert_eq!(last.conflicts.last().unwrap().kept, 2);
+
+        let newest = merge(inputs(), MergePolicy::PreferNewestTimestamp).unwrap();
+        assert_eq!(amount_of(&newest, 1), 111);
+        let kept: Vec<(usize, usize)> = newest
+            .conflicts
+            .iter()
+            .map(|c| (c.kept, c.dropped))
+            .collect();
+        assert_eq!(kept, [(1, 0), (1, 2)]);
+    }
+
+    #[test]
+    fn test_newest_timestamp_tie_keeps_first() {
+        let result = merge(
+            vec![set(&[op(1, 100, 10)]), set(&[op(1, 150, 10)])],
+            MergePolicy::PreferNewestTimestamp,
+        )
+        .unwrap();
+
+        assert_eq!(amount_of(&result, 1), 100);
+        assert_eq!(result.conflicts[0].kept, 0);
+    }
+
+    #[test]
+    fn test_merge_with_provenance_tracks_winner_and_dropped() {
+        let tagged = |source: &str, ops: &[Operation]| -> Vec<(Operation, Provenance)> {
+            ops.iter()
+                .enumerate()
+                .map(|(index, op)| {
+                    let provenance = Provenance {
+                        source: Some(source.to_string()),
+                        line: Some(index as u64 + 2),
+                        byte_offset: None,
+                        record_index: index as u64,
+                    };
+                    (op.clone(), provenance)
+                })
+                .collect()
+        };
+        let inputs = || {
+            vec![
+                tagged("a.csv", &[op(1, 100, 10), op(2, 200, 20)]),
+                tagged("b.csv", &[op(2, 222, 50), op(3, 300, 30)]),
+            ]
+        };
+
+        let result = merge_with_provenance(inputs(), MergePolicy::PreferNewestTimestamp).unwrap();
+        let sources: Vec<(u64, &str, u64)> = result
+            .operations
+            .iter()
+            .map(|(op, p)| (op.tx_id, p.source.as_deref().unwrap(), p.record_index))
+            .collect();
+        assert_eq!(sources, [(1, "a.csv", 0), (2, "b.csv", 0), (3, "b.csv", 1)]);
+
+        let conflict = &result.conflicts[0];
+        let kept = conflict.kept_provenance.as_ref().unwrap();
+        let dropped = conflict.dropped_provenance.as_ref().unwrap();
+        assert_eq!(
+            (kept.source.as_deref(), kept.line),
+            (Some("b.csv"), Some(2))
+        );
+        assert_eq!(
+            (dropped.source.as_deref(), dropped.line),
+            (Some("a.csv"), Some(3))
+        );
+
+        // Победитель остался прежним: отброшена запись второго входа
+        let first = merge_with_provenance(inputs(), MergePolicy::PreferFirst).unwrap();
+        let conflict = &first.conflicts[0];
+        assert_eq!(conflict.kept_provenance.as_ref().unwrap().record_index, 1);
+        assert_eq!(
+            conflict
+                .dropped_provenance
+                .as_ref()
+                .unwrap()
+                .source
+                .as_deref(),
+            Some("b.csv")
+        );
+
+        // Без происхождения отчет его не содержит
+        let plain = merge(
+            vec![set(&[op(2, 200, 20)]), set(&[op(2, 222, 50)])],
+            MergePolicy::PreferFirst,
+        )
+        .unwrap();
+        assert_eq!(plain.conflicts[0].kept_provenance, None);
+    }
+
+    #[test]
+    fn test_merge_ordered_keeps_first_appearance_order() {
+        let inputs = vec![
+            vec![op(3, 300, 1), op(1, 100, 1)],
+            vec![op(2, 200, 1), op(1, 111, 2), op(3, 300, 1)],
+        ];
+
+        let result = merge_ordered(inputs.clone(), MergePolicy::PreferLast).unwrap();
+        let order: Vec<(u64, i64)> = result
+            .operations
+            .iter()
+            .map(|op| (op.tx_id, op.amount))
+            .collect();
+        // TX_ID 1 остался на своем месте, но с записью второго входа
+        assert_eq!(order, [(3, 300), (1, 111), (2, 200)]);
+        assert_eq!(result.identical, 1);
+        assert_eq!(result.conflicts.len(), 1);
+
+        let err = merge_ordered(inputs, MergePolicy::FailOnConflict).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::MergeConflict {
+                tx_id: 1,
+                first: 0,
+                second: 1,
+                ..
+            }
+        ));
+    }
+}
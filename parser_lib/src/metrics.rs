@@ -0,0 +1,287 @@
+//! Метрики разбора для мониторинга (фича `metrics`)
+//!
+//! Приемник [`MetricsSink`] кладется в [`crate::ParseOptions::metrics`], и парсеры
+//! зовут его на границах записей. Число выделений памяти видно, только если
+//! в программе установлен [`CountingAllocator`], иначе оно нулевое.
+
+use crate::error::Result;
+use crate::report::per_sec;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Системный аллокатор, считающий выделения памяти
+///
+/// Ставится в программе через `#[global_allocator]`. Счетчики общие на процесс:
+/// выделения в других потоках во время разбора тоже попадут в метрики.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+/// Показания счетчиков [`CountingAllocator`] на момент начала разбора
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AllocationMark {
+    allocations: u64,
+    allocated_bytes: u64,
+}
+
+impl AllocationMark {
+    pub(crate) fn now() -> Self {
+        AllocationMark {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Выделений и байт с момента отметки
+    pub(crate) fn since(&self) -> (u64, u64) {
+        let now = AllocationMark::now();
+        (
+            now.allocations - self.allocations,
+            now.allocated_bytes - self.allocated_bytes,
+        )
+    }
+}
+
+/// Счетчики разбора на момент вызова приемника
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Формат потока ("bin", "csv", "txt")
+    pub format: &'static str,
+    /// Прочитано записей
+    pub records: u64,
+    /// Прочитано байт (с учетом буфера чтения, поэтому может опережать записи)
+    pub bytes: u64,
+    /// Время от начала разбора
+    pub elapsed: Duration,
+    /// Выделений памяти с начала разбора
+    pub allocations: u64,
+    /// Байт выделено с начала разбора
+    pub allocated_bytes: u64,
+}
+
+impl MetricsSnapshot {
+    /// Записей в секунду
+    pub fn records_per_sec(&self) -> f64 {
+        per_sec(self.records as usize, self.elapsed)
+    }
+}
+
+/// Приемник метрик разбора
+///
+/// Вызывается из потока, который разбирает; без приемника парсеры его не трогают.
+pub trait MetricsSink: Send + Sync + fmt::Debug {
+    /// После каждой записи. По умолчанию ничего не делает: снимок на каждую
+    /// запись нужен редко, а итог приходит в [`MetricsSink::on_finish`]
+    fn on_record(&self, _snapshot: &MetricsSnapshot) {}
+
+    /// В конце успешного разбора
+    fn on_finish(&self, snapshot: &MetricsSnapshot);
+}
+
+/// Копит итоги разборов по форматам и отдает их в текстовом формате Prometheus
+///
+/// Файл из [`PrometheusTextSink::write_to`] годится для textfile collector у node exporter.
+#[derive(Debug, Default)]
+pub struct PrometheusTextSink {
+    totals: Mutex<BTreeMap<&'static str, Totals>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    runs: u64,
+    records: u64,
+    bytes: u64,
+    seconds: f64,
+    allocations: u64,
+    allocated_bytes: u64,
+}
+
+/// Имя, тип и описание метрики и как ее получить из итогов
+type Metric = (&'static str, &'static str, &'static str, fn(&Totals) -> f64);
+
+impl PrometheusTextSink {
+    /// Пустой приемник
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Все счетчики в формате экспозиции Prometheus
+    pub fn render(&self) -> String {
+        let totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let metrics: [Metric; 7] = [
+            (
+                "ypbank_parse_runs_total",
+                "counter",
+                "Finished parses",
+                |t| t.runs as f64,
+            ),
+            (
+                "ypbank_parse_records_total",
+                "counter",
+                "Records parsed",
+                |t| t.records as f64,
+            ),
+            ("ypbank_parse_bytes_total", "counter", "Bytes read", |t| {
+                t.bytes as f64
+            }),
+            (
+                "ypbank_parse_duration_seconds_total",
+                "counter",
+                "Wall-clock time spent parsing",
+                |t| t.seconds,
+            ),
+            (
+                "ypbank_parse_records_per_second",
+                "gauge",
+                "Average parse throughput",
+                |t| {
+                    if t.seconds > 0.0 {
+                        t.records as f64 / t.seconds
+                    } else {
+                        0.0
+                    }
+                },
+            ),
+            (
+                "ypbank_parse_allocations_total",
+                "counter",
+                "Heap allocations during parsing (needs CountingAllocator)",
+                |t| t.allocations as f64,
+            ),
+            (
+                "ypbank_parse_allocated_bytes_total",
+                "counter",
+                "Heap bytes allocated during parsing (needs CountingAllocator)",
+                |t| t.allocated_bytes as f64,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (format, totals) in totals.iter() {
+                out.push_str(&format!(
+                    "{}{{format=\"{}\"}} {}\n",
+                    name,
+                    format,
+                    value(totals)
+                ));
+            }
+        }
+        out
+    }
+
+    /// Пишет [`PrometheusTextSink::render`] в поток
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.render().as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl MetricsSink for PrometheusTextSink {
+    fn on_finish(&self, snapshot: &MetricsSnapshot) {
+        let mut totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let totals = totals.entry(snapshot.format).or_default();
+        totals.runs += 1;
+        totals.records += snapshot.records;
+        totals.bytes += snapshot.bytes;
+        totals.seconds += snapshot.elapsed.as_secs_f64();
+        totals.allocations += snapshot.allocations;
+        totals.allocated_bytes += snapshot.allocated_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::golden_fixture;
+    use crate::format::Format;
+    use crate::options::{ParseOptions, WriteOptions};
+    use std::sync::Arc;
+
+    #[derive(Debug, Default)]
+    struct Boundaries(Mutex<Vec<u64>>);
+
+    impl MetricsSink for Boundaries {
+        fn on_record(&self, snapshot: &MetricsSnapshot) {
+            self.0.lock().unwrap().push(snapshot.records);
+        }
+
+        fn on_finish(&self, _snapshot: &MetricsSnapshot) {}
+    }
+
+    #[test]
+    fn test_sink_sees_every_record_and_renders_prometheus() {
+        let operations = golden_fixture();
+        let prometheus = Arc::new(PrometheusTextSink::new());
+        let boundaries = Arc::new(Boundaries::default());
+
+        for format in [Format::Bin, Format::Csv, Format::Txt] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &WriteOptions::default())
+                .unwrap();
+            for sink in [
+                prometheus.clone() as Arc<dyn MetricsSink>,
+                boundaries.clone() as Arc<dyn MetricsSink>,
+            ] {
+                let options = ParseOptions {
+                    metrics: Some(sink),
+                    ..Default::default()
+                };
+                let (_, report) = format
+                    .parse_all_with_report(buf.as_slice(), &options)
+                    .unwrap();
+                assert_eq!(report.bytes, buf.len() as u64);
+            }
+        }
+
+        let n = operations.len() as u64;
+        let expected: Vec<u64> = (0..3).flat_map(|_| 1..=n).collect();
+        assert_eq!(*boundaries.0.lock().unwrap(), expected);
+
+        let text = prometheus.render();
+        assert!(text.contains("# TYPE ypbank_parse_records_total counter\n"));
+        assert!(text.contains(&format!(
+            "ypbank_parse_records_total{{format=\"csv\"}} {}\n",
+            n
+        )));
+        assert!(text.contains("ypbank_parse_runs_total{format=\"bin\"} 1\n"));
+        assert!(
+            text.lines()
+                .filter(|line| !line.starts_with('#'))
+                .all(|line| line.rsplit_once(' ').unwrap().1.parse::<f64>().is_ok()),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Версии схемы записей и хуки для чтения будущих раскладок
+//!
+//! Файл может объявить версию схемы прагмой `#VERSION: n` (CSV — строкой перед
+//! заголовком, текст — комментарием). Без прагмы файл считается версией 1.
+//! Парсер отказывается читать версии новее [`crate::ParseOptions::max_supported_version`].
+//! Для записей новых версий, разобранных в поля "имя -> значение", есть
+//! [`upgrade`] и [`Migrations`]: известные поля собираются в [`Operation`],
+//! незнакомые уходят в [`Upgraded::extras`] вместо ошибки. Парсеры CSV и текста
+//! собирают записи так же: хуки берут из [`ParseOptions::migrations`], а лишние
+//! колонки и ключи кладут в [`ParseReport::extras`].
+
+use crate::error::{ParseError, Result};
+use crate::operation::Operation;
+use crate::options::ParseOptions;
+use crate::report::ParseReport;
+use crate::text_format;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Поля операции версии 1 (имена как в заголовке CSV)
+const V1_FIELDS: [&str; 8] = [
+    "TX_ID",
+    "TX_TYPE",
+    "FROM_USER_ID",
+    "TO_USER_ID",
+    "AMOUNT",
+    "TIMESTAMP",
+    "STATUS",
+    "DESCRIPTION",
+];
+
+/// Версия схемы записей
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SchemaVersion(pub u32);
+
+impl SchemaVersion {
+    /// Исходная раскладка из спецификации
+    pub const V1: SchemaVersion = SchemaVersion(1);
+    /// Версия, которую пишет и полностью понимает эта библиотека
+    pub const CURRENT: SchemaVersion = SchemaVersion::V1;
+
+    /// Разбирает значение прагмы `#VERSION: n`
+    pub(crate) fn parse_pragma(value: &str) -> Result<SchemaVersion> {
+        match value.trim().parse::<u32>() {
+            Ok(version) if version > 0 => Ok(SchemaVersion(version)),
+            _ => Err(ParseError::InvalidField {
+                field: "#VERSION".to_string(),
+                reason: format!("invalid schema version '{}'", value.trim()),
+            }),
+        }
+    }
+
+    /// Проверяет, что версию можно читать с такими настройками
+    pub(crate) fn check_supported(self, options: &ParseOptions) -> Result<()> {
+        if self > options.max_supported_version {
+            return Err(ParseError::UnsupportedVersion {
+                found: self.0,
+                supported: options.max_supported_version.0,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        SchemaVersion::V1
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Запись произвольной версии в виде полей "имя -> значение"
+///
+/// Значения в текстовом виде, как в YPBankText (описание — в кавычках или без).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionedRecord {
+    /// Версия схемы, в которой записаны поля
+    pub version: SchemaVersion,
+    /// Поля записи
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Результат [`upgrade`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upgraded {
+    /// Операция текущей схемы
+    pub operation: Operation,
+    /// Поля, которых в текущей схеме нет
+    pub extras: BTreeMap<String, String>,
+}
+
+/// Хук миграции: переписывает поля записи своей версии перед сборкой операции
+pub type MigrationHook = Box<dyn Fn(&mut VersionedRecord) -> Result<()> + Send + Sync>;
+
+/// Набор хуков по версиям
+///
+/// Хук версии `n` вызывается для записей версии `n` и может, например,
+/// переименовать поле или пересчитать значение. После хука поля текущей
+/// схемы собираются в [`Operation`], остальные остаются в `extras`.
+#[derive(Default)]
+pub struct Migrations {
+    hooks: HashMap<SchemaVersion, MigrationHook>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut versions: Vec<&SchemaVersion> = self.hooks.keys().collect();
+        versions.sort();
+        f.debug_struct("Migrations")
+            .field("versions", &versions)
+            .finish()
+    }
+}
+
+impl Migrations {
+    /// Пустой набор: записи любых версий собираются по полям текущей схемы
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Добавляет (или заменяет) хук для версии
+    pub fn register(
+        mut self,
+        version: SchemaVersion,
+        hook: impl Fn(&mut VersionedRecord) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.insert(version, Box::new(hook));
+        self
+    }
+
+    /// Приводит запись к текущей схеме
+    ///
+    /// # Возвращает
+    /// * `Ok(Upgraded)` - Операция (уже провалидированная) и незнакомые поля
+    /// * `Err(ParseError)` - Нет обязательного поля, поле некорректно или хук вернул ошибку
+    pub fn upgrade(&self, record: VersionedRecord) -> Result<Upgraded> {
+        let upgraded = self.assemble(
+            record,
+            &ParseOptions::default(),
+            &mut ParseReport::default(),
+        )?;
+        upgraded.operation.validate()?;
+        Ok(upgraded)
+    }
+
+    /// Хук версии записи, затем сборка по полям текущей схемы, без проверки операции
+    fn assemble(
+        &self,
+        mut record: VersionedRecord,
+        options: &ParseOptions,
+        report: &mut ParseReport,
+    ) -> Result<Upgraded> {
+        if let Some(hook) = self.hooks.get(&record.version) {
+            hook(&mut record)?;
+        }
+
+        let mut known = HashMap::new();
+        let mut extras = BTreeMap::new();
+        for (name, value) in record.fields {
+            if V1_FIELDS.contains(&name.as_str()) {
+                known.insert(name, value);
+            } else {
+                extras.insert(name, value);
+            }
+        }
+
+        let operation = text_format::parse_record(&known, options, report)?;
+        Ok(Upgraded { operation, extras })
+    }
+}
+
+/// Собирает операцию из полей записи CSV или текста файла версии `version`
+///
+/// Хук версии берется из [`ParseOptions::migrations`], поля не из текущей схемы
+/// возвращаются в [`Upgraded::extras`]. Запись без хука и без лишних полей
+/// собирается сразу, без копирования. Операцию проверяет вызывающий.
+pub(crate) fn assemble(
+    fields: &HashMap<String, String>,
+    version: SchemaVersion,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+) -> Result<Upgraded> {
+    let migrations = options
+        .migrations
+        .as_deref()
+        .filter(|migrations| migrations.hooks.contains_key(&version));
+    let has_extras = fields
+        .keys()
+        .any(|name| !V1_FIELDS.contains(&name.as_str()));
+    if migrations.is_none() && !has_extras {
+        let operation = text_format::parse_record(fields, options, report)?;
+        return Ok(Upgraded {
+            operation,
+            extras: BTreeMap::new(),
+        });
+    }
+
+    let record = VersionedRecord {
+        version,
+        fields: fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+    };
+    match migrations {
+        Some(migrations) => migrations.assemble(record, options, report),
+        None => Migrations::new().assemble(record, options, report),
+    }
+}
+
+/// [`Migrations::upgrade`] без хуков
+pub fn upgrade(record: VersionedRecord) -> Result<Upgraded> {
+    Migrations::new().upgrade(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Format, OperationFormat};
+    use crate::operation::{FIELD_NAMES, OperationStatus};
+    use std::sync::Arc;
+
+    fn encoded(format: Format) -> Vec<u8> {
+        let op = Operation::deposit(7, 100)
+            .tx_id(1)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("Deposit")
+            .build()
+            .unwrap();
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, [&op], &Default::default())
+            .unwrap();
+        buf
+    }
+
+    fn with_pragma(format: Format, pragma: &str) -> Vec<u8> {
+        let mut input = format!("{}\n", pragma).into_bytes();
+        input.extend(encoded(format));
+        input
+    }
+
+    #[test]
+    fn test_version_pragma() {
+        for format in [Format::Csv, Format::Txt] {
+            let (_, report) = format
+                .parse_all_with_report(encoded(format).as_slice(), &ParseOptions::default())
+                .unwrap();
+            assert_eq!(report.schema_version, SchemaVersion::V1);
+
+            let v1 = with_pragma(format, "#VERSION: 1");
+            let (parsed, report) = format
+                .parse_all_with_report(v1.as_slice(), &ParseOptions::default())
+                .unwrap();
+            assert_eq!(parsed.len(), 1);
+            assert_eq!(report.schema_version, SchemaVersion::V1);
+
+            let v2 = with_pragma(format, "#VERSION: 2");
+            let result = format.parse_all_with_report(v2.as_slice(), &ParseOptions::default());
+            assert!(
+                matches!(
+                    result,
+                    Err(ParseError::UnsupportedVersion {
+                        found: 2,
+                        supported: 1
+                    })
+                ),
+                "{}",
+                format.name()
+            );
+
+            let newer = ParseOptions {
+                max_supported_version: SchemaVersion(2),
+                ..Default::default()
+            };
+            let (_, report) = format.parse_all_with_report(v2.as_slice(), &newer).unwrap();
+            assert_eq!(report.schema_version, SchemaVersion(2));
+
+            let garbage = with_pragma(format, "#VERSION: next");
+            assert!(format.parse_all(&mut garbage.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_text_pragma_after_record() {
+        let mut input = encoded(Format::Txt);
+        input.extend_from_slice(b"\n# VERSION: 1\n");
+        assert!(matches!(
+            Format::Txt.parse_all(&mut input.as_slice()),
+            Err(ParseError::InvalidFormat(_))
+        ));
+    }
+
+    fn v1_fields() -> BTreeMap<String, String> {
+        [
+            ("TX_ID", "5"),
+            ("TX_TYPE", "TRANSFER"),
+            ("FROM_USER_ID", "1"),
+            ("TO_USER_ID", "2"),
+            ("AMOUNT", "300"),
+            ("TIMESTAMP", "1633036800000"),
+            ("STATUS", "PENDING"),
+            ("DESCRIPTION", "\"Rent\""),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_upgrade_keeps_unknown_fields() {
+        let mut fields = v1_fields();
+        fields.insert("CURRENCY".to_string(), "EUR".to_string());
+
+        let upgraded = upgrade(VersionedRecord {
+            version: SchemaVersion(2),
+            fields,
+        })
+        .unwrap();
+        assert_eq!(upgraded.operation.tx_id, 5);
+        assert_eq!(upgraded.operation.description, "Rent");
+        assert_eq!(
+            upgraded.extras.into_iter().collect::<Vec<_>>(),
+            vec![("CURRENCY".to_string(), "EUR".to_string())]
+        );
+
+        let mut missing = v1_fields();
+        missing.remove("AMOUNT");
+        assert!(
+            upgrade(VersionedRecord {
+                version: SchemaVersion::V1,
+                fields: missing,
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_migration_hook() {
+        // Допустим, в v3 AMOUNT переименовали в AMOUNT_MINOR
+        let migrations = Migrations::new().register(SchemaVersion(3), |record| {
+            if let Some(amount) = record.fields.remove("AMOUNT_MINOR") {
+                record.fields.insert("AMOUNT".to_string(), amount);
+            }
+            Ok(())
+        });
+
+        let mut fields = v1_fields();
+        let amount = fields.remove("AMOUNT").unwrap();
+        fields.insert("AMOUNT_MINOR".to_string(), amount);
+
+        let upgraded = migrations
+            .upgrade(VersionedRecord {
+                version: SchemaVersion(3),
+                fields: fields.clone(),
+            })
+            .unwrap();
+        assert_eq!(upgraded.operation.amount, 300);
+        assert!(upgraded.extras.is_empty());
+
+        // Хук v3 не трогает записи других версий
+        assert!(
+            migrations
+                .upgrade(VersionedRecord {
+                    version: SchemaVersion(2),
+                    fields,
+                })
+                .is_err()
+        );
+    }
+
+    /// Одна запись `v1_fields` с лишним полем CURRENCY
+    fn with_currency(format: Format, pragma: &str) -> Vec<u8> {
+        let fields = v1_fields();
+        match format {
+            Format::Csv => {
+                let names: Vec<&str> = FIELD_NAMES.to_vec();
+                let values: Vec<&str> = FIELD_NAMES
+                    .iter()
+                    .map(|name| fields[*name].as_str())
+                    .collect();
+                format!(
+                    "{}\n{},CURRENCY\n{},EUR\n",
+                    pragma,
+                    names.join(","),
+                    values.join(",")
+                )
+                .into_bytes()
+            }
+            _ => {
+                let mut text = format!("{}\n", pragma);
+                for name in FIELD_NAMES {
+                    text.push_str(&format!("{}: {}\n", name, fields[name]));
+                }
+                text.push_str("CURRENCY: EUR\n");
+                text.into_bytes()
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsers_report_extra_fields() {
+        for format in [Format::Csv, Format::Txt] {
+            let input = with_currency(format, "#VERSION: 1");
+            let (parsed, report) = format
+                .parse_all_with_report(input.as_slice(), &ParseOptions::default())
+                .unwrap();
+            let operation = parsed.into_iter().next().unwrap();
+            assert_eq!(operation.amount, 300, "{}", format.name());
+            assert_eq!(operation.description, "Rent", "{}", format.name());
+            assert_eq!(
+                report.extras[&5].iter().collect::<Vec<_>>(),
+                vec![(&"CURRENCY".to_string(), &"EUR".to_string())],
+                "{}",
+                format.name()
+            );
+
+            // Со strict лишнее поле — по-прежнему ошибка
+            let strict = ParseOptions {
+                strict: true,
+                ..Default::default()
+            };
+            assert!(
+                format
+                    .parse_all_with_report(input.as_slice(), &strict)
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parsers_run_migration_hook() {
+        // Допустим, в v2 AMOUNT пишут в сотнях и добавили CURRENCY
+        let migrations = Migrations::new().register(SchemaVersion(2), |record| {
+            record.fields.remove("CURRENCY");
+            let amount = record.fields["AMOUNT"].parse::<u64>().unwrap_or_default();
+            record
+                .fields
+                .insert("AMOUNT".to_string(), (amount * 100).to_string());
+            Ok(())
+        });
+        let options = ParseOptions {
+            max_supported_version: SchemaVersion(2),
+            migrations: Some(Arc::new(migrations)),
+            ..Default::default()
+        };
+
+        for format in [Format::Csv, Format::Txt] {
+            let (parsed, report) = format
+                .parse_all_with_report(with_currency(format, "#VERSION: 2").as_slice(), &options)
+                .unwrap();
+            let operation = parsed.into_iter().next().unwrap();
+            assert_eq!(operation.amount, 30000, "{}", format.name());
+            assert!(report.extras.is_empty(), "{}", format.name());
+
+            // Файл v1 хук v2 не трогает
+            let (parsed, report) = format
+                .parse_all_with_report(with_currency(format, "#VERSION: 1").as_slice(), &options)
+                .unwrap();
+            assert_eq!(parsed.into_iter().next().unwrap().amount, 300);
+            assert_eq!(report.extras[&5].len(), 1);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Суммы в десятичном виде: "1050.25" вместо 105025 копеек
+//!
+//! Внутри [`Operation::amount`] всегда в минимальных единицах (центах, копейках);
+//! здесь только перевод в строку с двумя знаками после точки и обратно.
+
+use crate::error::{ParseError, Result};
+use crate::operation::Operation;
+
+/// Знаков после точки: сумма хранится в сотых долях
+const FRACTION_DIGITS: usize = 2;
+const MINOR_PER_MAJOR: i128 = 100;
+
+/// Разбирает десятичную сумму в минимальные единицы: "1050.25" — 105025
+///
+/// Допускаются знак (`+` или `-`), целая часть и не больше двух цифр после
+/// точки: "0.5" — 50, "-0.05" — -5, "7" — 700.
+///
+/// # Возвращает
+/// * `Ok(i64)` - Сумма в минимальных единицах
+/// * `Err(ParseError::InvalidField)` - Поле AMOUNT: три и больше знаков после точки,
+///   запятая вместо точки, мусор или сумма вне `i64`
+pub fn parse_decimal_amount(s: &str) -> Result<i64> {
+    let invalid = |reason: String| ParseError::InvalidField {
+        field: "AMOUNT".to_string(),
+        reason,
+    };
+
+    let (negative, unsigned) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    if unsigned.contains(',') {
+        return Err(invalid(format!(
+            "{:?}: the decimal separator is '.', not ',' (write 1050.25, not 1050,25)",
+            s
+        )));
+    }
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(whole) || fraction.is_some_and(|fraction| !is_digits(fraction)) {
+        return Err(invalid(format!("{:?} is not a decimal amount", s)));
+    }
+    let fraction = fraction.unwrap_or("");
+    if fraction.len() > FRACTION_DIGITS {
+        return Err(invalid(format!(
+            "{:?} has {} digits after the point, at most {} are allowed",
+            s,
+            fraction.len(),
+            FRACTION_DIGITS
+        )));
+    }
+
+    let out_of_range = || invalid(format!("{:?} does not fit in i64 minor units", s));
+    // Целая часть длиннее i128 — заведомо вне i64
+    let whole: i128 = whole.parse().map_err(|_| out_of_range())?;
+    let cents: i128 = format!("{:0<width$}", fraction, width = FRACTION_DIGITS)
+        .parse()
+        .unwrap();
+    let minor = whole
+        .checked_mul(MINOR_PER_MAJOR)
+        .and_then(|minor| minor.checked_add(cents))
+        .ok_or_else(out_of_range)?;
+    let minor = if negative { -minor } else { minor };
+    i64::try_from(minor).map_err(|_| out_of_range())
+}
+
+/// Сумма в минимальных единицах как десятичная строка: 105025 — "1050.25", -5 — "-0.05"
+pub fn format_decimal_amount(amount: i64) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let abs = amount.unsigned_abs();
+    let scale = MINOR_PER_MAJOR as u64;
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        abs / scale,
+        abs % scale,
+        width = FRACTION_DIGITS
+    )
+}
+
+impl Operation {
+    /// AMOUNT в десятичном виде, см. [`format_decimal_amount`]
+    pub fn amount_decimal_string(&self) -> String {
+        format_decimal_amount(self.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(s: &str) -> String {
+        match parse_decimal_amount(s).unwrap_err() {
+            ParseError::InvalidField { field, reason } => {
+                assert_eq!(field, "AMOUNT");
+                reason
+            }
+            other => panic!("{}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_decimal_amount() {
+        let cases = [
+            ("1050.25", 105025),
+            ("0.5", 50),
+            ("-0.05", -5),
+            ("+3.1", 310),
+            ("7", 700),
+            ("-0", 0),
+            ("00012.00", 1200),
+            ("92233720368547758.07", i64::MAX),
+            ("-92233720368547758.08", i64::MIN),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(parse_decimal_amount(s).unwrap(), expected, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_parse_decimal_amount_errors() {
+        assert!(reason("1.005").contains("3 digits after the point"));
+        assert!(reason("1050,25").contains("not ','"));
+        assert!(reason("92233720368547758.08").contains("does not fit"));
+        assert!(reason("-92233720368547758.09").contains("does not fit"));
+        assert!(reason(&"9".repeat(50)).contains("does not fit"));
+        for garbage in ["", "-", ".5", "5.", "1.2.3", "1 000", "1e3", "--1", "12a"] {
+            assert!(
+                reason(garbage).contains("is not a decimal amount"),
+                "{}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn test_format_decimal_amount_round_trips() {
+        for amount in [0, 5, -5, 50, 105025, -105025, i64::MAX, i64::MIN] {
+            let s = format_decimal_amount(amount);
+            assert_eq!(parse_decimal_amount(&s).unwrap(), amount, "{}", s);
+        }
+        assert_eq!(format_decimal_amount(-5), "-0.05");
+        assert_eq!(format_decimal_amount(105025), "1050.25");
+        assert_eq!(format_decimal_amount(i64::MIN), "-92233720368547758.08");
+    }
+}
@@ -1,8 +1,14 @@
 use crate::error::{ParseError, Result};
+use crate::options::ParseOptions;
+use std::fmt;
 use std::hash::Hash;
+use std::str::FromStr;
 
 /// Тип финансовой операции
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// С фичей `serde` сериализуется строкой, как в файлах: `"DEPOSIT"`;
+/// [`OperationType::Unknown`] — своим значением, но обратно не читается.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum OperationType {
     /// Пополнение счета
     Deposit,
@@ -10,9 +16,22 @@ pub enum OperationType {
     Transfer,
     /// Снятие средств
     Withdrawal,
+    /// Тип, которого мы еще не знаем (из файла более новой системы), как он записан:
+    /// строкой из CSV/текста/JSON или десятичной записью байта из бинарника
+    ///
+    /// Появляется только при [`crate::ParseOptions::lenient`] и пишется обратно
+    /// тем же значением; правила [`Operation::validate`] для него не действуют.
+    Unknown(String),
 }
 
 impl OperationType {
+    /// Все известные типы операций
+    pub const ALL: [OperationType; 3] = [
+        OperationType::Deposit,
+        OperationType::Transfer,
+        OperationType::Withdrawal,
+    ];
+
     /// Парсит тип операции из строки
     ///
     /// # Аргументы
@@ -21,18 +40,37 @@ impl OperationType {
     /// # Возвращает
     /// * `Ok(OperationType)` - Если строка корректна
     /// * `Err(ParseError)` - Если строка не распознана
+    ///
+    /// Обертка над [`FromStr`], то же самое, что `s.parse()`.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self> {
-        match s {
-            "DEPOSIT" => Ok(OperationType::Deposit),
-            "TRANSFER" => Ok(OperationType::Transfer),
-            "WITHDRAWAL" => Ok(OperationType::Withdrawal),
-            _ => Err(ParseError::InvalidField {
-                field: "TX_TYPE".to_string(),
-                reason: format!("Unknown transaction type: {}", s),
-            }),
+        s.parse()
+    }
+
+    /// Как [`OperationType::from_str`], но незнакомое непустое значение
+    /// становится [`OperationType::Unknown`]
+    pub fn from_str_lenient(s: &str) -> Result<Self> {
+        match s.parse() {
+            Err(_) if !s.is_empty() => Ok(OperationType::Unknown(s.to_string())),
+            parsed => parsed,
+        }
+    }
+
+    /// Разбор поля TX_TYPE с учетом [`ParseOptions::lenient`]
+    pub(crate) fn parse_field(s: &str, options: &ParseOptions) -> Result<Self> {
+        if options.lenient {
+            OperationType::from_str_lenient(s)
+        } else {
+            s.parse()
         }
     }
 
+    /// Как [`OperationType::from_u8`], но незнакомый байт становится
+    /// [`OperationType::Unknown`] с его десятичной записью
+    pub fn from_u8_lenient(value: u8) -> Self {
+        OperationType::from_u8(value).unwrap_or_else(|_| OperationType::Unknown(value.to_string()))
+    }
+
     /// Создает тип операции из числового значения
     ///
     /// # Аргументы
@@ -59,29 +97,87 @@ impl OperationType {
     /// * `0` для Deposit
     /// * `1` для Transfer
     /// * `2` для Withdrawal
-    pub fn to_u8(&self) -> u8 {
+    /// * байт из записи [`OperationType::Unknown`], если это число 3..=255;
+    ///   иначе `Err(ParseError)`: такой тип в бинарник не записать
+    pub fn to_u8(&self) -> Result<u8> {
+        match self.known_code() {
+            Some(code) => Ok(code),
+            None => unknown_code(self.as_str(), "TX_TYPE"),
+        }
+    }
+
+    /// Байт известного значения; `None` у [`OperationType::Unknown`]
+    pub(crate) fn known_code(&self) -> Option<u8> {
         match self {
-            OperationType::Deposit => 0,
-            OperationType::Transfer => 1,
-            OperationType::Withdrawal => 2,
+            OperationType::Deposit => Some(0),
+            OperationType::Transfer => Some(1),
+            OperationType::Withdrawal => Some(2),
+            OperationType::Unknown(_) => None,
         }
     }
 
     /// Возвращает строковое представление типа операции
     ///
     /// # Возвращает
-    /// Строку "DEPOSIT", "TRANSFER" или "WITHDRAWAL"
+    /// Строку "DEPOSIT", "TRANSFER" или "WITHDRAWAL", у [`OperationType::Unknown`] — значение как есть
     pub fn as_str(&self) -> &str {
         match self {
             OperationType::Deposit => "DEPOSIT",
             OperationType::Transfer => "TRANSFER",
             OperationType::Withdrawal => "WITHDRAWAL",
+            OperationType::Unknown(value) => value,
+        }
+    }
+
+    /// Незнакомый тип, прочитанный в мягком режиме
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, OperationType::Unknown(_))
+    }
+}
+
+impl FromStr for OperationType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "DEPOSIT" => Ok(OperationType::Deposit),
+            "TRANSFER" => Ok(OperationType::Transfer),
+            "WITHDRAWAL" => Ok(OperationType::Withdrawal),
+            _ => Err(ParseError::InvalidField {
+                field: "TX_TYPE".to_string(),
+                reason: format!("Unknown transaction type: {}", s),
+            }),
         }
     }
 }
 
+impl fmt::Display for OperationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<u8> for OperationType {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        OperationType::from_u8(value)
+    }
+}
+
+impl TryFrom<OperationType> for u8 {
+    type Error = ParseError;
+
+    fn try_from(value: OperationType) -> Result<Self> {
+        value.to_u8()
+    }
+}
+
 /// Статус выполнения операции
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// С фичей `serde` сериализуется строкой, как в файлах: `"SUCCESS"`;
+/// [`OperationStatus::Unknown`] — своим значением, но обратно не читается.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum OperationStatus {
     /// Операция успешно выполнена
     Success,
@@ -89,9 +185,18 @@ pub enum OperationStatus {
     Failure,
     /// Операция в процессе выполнения
     Pending,
+    /// Незнакомый статус как он записан, см. [`OperationType::Unknown`]
+    Unknown(String),
 }
 
 impl OperationStatus {
+    /// Все известные статусы операций
+    pub const ALL: [OperationStatus; 3] = [
+        OperationStatus::Success,
+        OperationStatus::Failure,
+        OperationStatus::Pending,
+    ];
+
     /// Парсит статус операции из строки
     ///
     /// # Аргументы
@@ -100,18 +205,38 @@ impl OperationStatus {
     /// # Возвращает
     /// * `Ok(OperationStatus)` - Если строка корректна
     /// * `Err(ParseError)` - Если строка не распознана
+    ///
+    /// Обертка над [`FromStr`], то же самое, что `s.parse()`.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self> {
-        match s {
-            "SUCCESS" => Ok(OperationStatus::Success),
-            "FAILURE" => Ok(OperationStatus::Failure),
-            "PENDING" => Ok(OperationStatus::Pending),
-            _ => Err(ParseError::InvalidField {
-                field: "STATUS".to_string(),
-                reason: format!("Unknown status: {}", s),
-            }),
+        s.parse()
+    }
+
+    /// Как [`OperationStatus::from_str`], но незнакомое непустое значение
+    /// становится [`OperationStatus::Unknown`]
+    pub fn from_str_lenient(s: &str) -> Result<Self> {
+        match s.parse() {
+            Err(_) if !s.is_empty() => Ok(OperationStatus::Unknown(s.to_string())),
+            parsed => parsed,
+        }
+    }
+
+    /// Разбор поля STATUS с учетом [`ParseOptions::lenient`]
+    pub(crate) fn parse_field(s: &str, options: &ParseOptions) -> Result<Self> {
+        if options.lenient {
+            OperationStatus::from_str_lenient(s)
+        } else {
+            s.parse()
         }
     }
 
+    /// Как [`OperationStatus::from_u8`], но незнакомый байт становится
+    /// [`OperationStatus::Unknown`] с его десятичной записью
+    pub fn from_u8_lenient(value: u8) -> Self {
+        OperationStatus::from_u8(value)
+            .unwrap_or_else(|_| OperationStatus::Unknown(value.to_string()))
+    }
+
     /// Создает статус операции из числового значения
     ///
     /// # Аргументы
@@ -138,29 +263,202 @@ impl OperationStatus {
     /// * `0` для Success
     /// * `1` для Failure
     /// * `2` для Pending
-    pub fn to_u8(&self) -> u8 {
+    /// * байт из записи [`OperationStatus::Unknown`], как у [`OperationType::to_u8`]
+    pub fn to_u8(&self) -> Result<u8> {
+        match self.known_code() {
+            Some(code) => Ok(code),
+            None => unknown_code(self.as_str(), "STATUS"),
+        }
+    }
+
+    /// Байт известного значения; `None` у [`OperationStatus::Unknown`]
+    pub(crate) fn known_code(&self) -> Option<u8> {
         match self {
-            OperationStatus::Success => 0,
-            OperationStatus::Failure => 1,
-            OperationStatus::Pending => 2,
+            OperationStatus::Success => Some(0),
+            OperationStatus::Failure => Some(1),
+            OperationStatus::Pending => Some(2),
+            OperationStatus::Unknown(_) => None,
         }
     }
 
     /// Возвращает строковое представление статуса операции
     ///
     /// # Возвращает
-    /// Строку "SUCCESS", "FAILURE" или "PENDING"
+    /// Строку "SUCCESS", "FAILURE" или "PENDING", у [`OperationStatus::Unknown`] — значение как есть
     pub fn as_str(&self) -> &str {
         match self {
             OperationStatus::Success => "SUCCESS",
             OperationStatus::Failure => "FAILURE",
             OperationStatus::Pending => "PENDING",
+            OperationStatus::Unknown(value) => value,
+        }
+    }
+
+    /// Незнакомый статус, прочитанный в мягком режиме
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, OperationStatus::Unknown(_))
+    }
+}
+
+impl FromStr for OperationStatus {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "SUCCESS" => Ok(OperationStatus::Success),
+            "FAILURE" => Ok(OperationStatus::Failure),
+            "PENDING" => Ok(OperationStatus::Pending),
+            _ => Err(ParseError::InvalidField {
+                field: "STATUS".to_string(),
+                reason: format!("Unknown status: {}", s),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<u8> for OperationStatus {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        OperationStatus::from_u8(value)
+    }
+}
+
+impl TryFrom<OperationStatus> for u8 {
+    type Error = ParseError;
+
+    fn try_from(value: OperationStatus) -> Result<Self> {
+        value.to_u8()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for OperationType {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Только известные типы: мягкий режим есть у парсеров, а не у serde
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for OperationType {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| {
+            serde::de::Error::unknown_variant(&s, &["DEPOSIT", "TRANSFER", "WITHDRAWAL"])
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for OperationStatus {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Только известные статусы, как у [`OperationType`]
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for OperationStatus {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::unknown_variant(&s, &["SUCCESS", "FAILURE", "PENDING"]))
+    }
+}
+
+/// Байт незнакомого значения для бинарника: только число вне известных кодов 0..=2
+fn unknown_code(value: &str, field: &str) -> Result<u8> {
+    match value.parse::<u8>() {
+        Ok(code) if code > 2 => Ok(code),
+        _ => Err(ParseError::InvalidField {
+            field: field.to_string(),
+            reason: format!("Unknown value {} has no binary code", value),
+        }),
+    }
+}
+
+/// Расширения для сравнения: `тег=значение в hex` через пробел
+fn extensions_to_string(extensions: &[Extension]) -> String {
+    let items: Vec<String> = extensions
+        .iter()
+        .map(|extension| {
+            let hex: String = extension
+                .value
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("{}={}", extension.tag, hex)
+        })
+        .collect();
+    items.join(" ")
+}
+
+/// Имена полей в текстовых форматах (колонки CSV, ключи текста и JSON) в порядке полей [`Operation`]
+pub(crate) const FIELD_NAMES: [&str; 8] = [
+    "TX_ID",
+    "TX_TYPE",
+    "FROM_USER_ID",
+    "TO_USER_ID",
+    "AMOUNT",
+    "TIMESTAMP",
+    "STATUS",
+    "DESCRIPTION",
+];
+
+/// Поле-расширение записи: тег и значение как есть
+///
+/// Расширения переносит только бинарный формат (блок TLV записи v2, см.
+/// [`crate::bin_format::write_operation_v2`]). Незнакомые теги читаются и
+/// пишутся обратно без изменений, порядок сохраняется. CSV, текст и JSON
+/// расширений не знают: при конвертации в них расширения теряются.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Extension {
+    pub tag: u16,
+    pub value: Vec<u8>,
+}
+
+impl Extension {
+    /// Код валюты, ASCII (ISO 4217, например `USD`)
+    pub const CURRENCY: u16 = 1;
+    /// Идентификатор операции во внешней системе, UTF-8
+    pub const EXTERNAL_REF: u16 = 2;
+
+    pub fn new(tag: u16, value: impl Into<Vec<u8>>) -> Self {
+        Extension {
+            tag,
+            value: value.into(),
         }
     }
 }
 
 /// Структура, представляющая финансовую операцию
+///
+/// С фичей `serde` поля называются как в формате JSON (`TX_ID`, `TX_TYPE`, ...),
+/// а десериализация проходит [`Operation::validate`]: DEPOSIT от пользователя
+/// через serde не собрать.
 #[derive(Debug, Clone, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE", try_from = "OperationFields")
+)]
 pub struct Operation {
     /// Уникальный идентификатор транзакции
     pub tx_id: u64,
@@ -178,6 +476,45 @@ pub struct Operation {
     pub status: OperationStatus,
     /// Описание операции
     pub description: String,
+    /// Расширения ([`Extension`]); в JSON через serde не попадают
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub extensions: Vec<Extension>,
+}
+
+/// Поля операции до проверки: через нее serde собирает [`Operation`]
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct OperationFields {
+    tx_id: u64,
+    tx_type: OperationType,
+    from_user_id: u64,
+    to_user_id: u64,
+    amount: i64,
+    timestamp: u64,
+    status: OperationStatus,
+    description: String,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<OperationFields> for Operation {
+    type Error = ParseError;
+
+    fn try_from(fields: OperationFields) -> Result<Self> {
+        let operation = Operation {
+            tx_id: fields.tx_id,
+            tx_type: fields.tx_type,
+            from_user_id: fields.from_user_id,
+            to_user_id: fields.to_user_id,
+            amount: fields.amount,
+            timestamp: fields.timestamp,
+            status: fields.status,
+            description: fields.description,
+            extensions: Vec::new(),
+        };
+        operation.validate()?;
+        Ok(operation)
+    }
 }
 
 impl Operation {
@@ -187,39 +524,280 @@ impl Operation {
     /// * **DEPOSIT**: `from_user_id` должен быть равен 0
     /// * **WITHDRAWAL**: `to_user_id` должен быть равен 0
     /// * **TRANSFER**: `from_user_id` и `to_user_id` не должны быть равны 0
+    /// * **Неизвестный тип** ([`OperationType::Unknown`]): правила не проверяются
     ///
     /// # Возвращает
     /// * `Ok(())` - Если операция валидна
     /// * `Err(ParseError)` - Если обнаружены некорректные поля
+    ///
+    /// Остальные правила ([`crate::ValidationRules`]) смотрит только [`Operation::validate_all`].
     pub fn validate(&self) -> Result<()> {
-        match self.tx_type {
-            OperationType::Deposit => {
-                if self.from_user_id != 0 {
-                    return Err(ParseError::InvalidField {
-                        field: "FROM_USER_ID".to_string(),
-                        reason: "Must be 0 for DEPOSIT".to_string(),
-                    });
-                }
-            }
-            OperationType::Withdrawal => {
-                if self.to_user_id != 0 {
-                    return Err(ParseError::InvalidField {
-                        field: "TO_USER_ID".to_string(),
-                        reason: "Must be 0 for WITHDRAWAL".to_string(),
-                    });
-                }
+        match crate::validation::user_id_issues(self).into_iter().next() {
+            Some(issue) => Err(issue.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Значение первого расширения с тегом `tag`
+    pub fn extension(&self, tag: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|extension| extension.tag == tag)
+            .map(|extension| extension.value.as_slice())
+    }
+
+    /// Совпадают ли все поля, включая TX_ID
+    ///
+    /// `==` у [`Operation`] смотрит только на TX_ID.
+    pub fn eq_full(&self, other: &Operation) -> bool {
+        self.tx_id == other.tx_id && self.differing_fields(other).is_empty()
+    }
+
+    /// Поля, которыми операция отличается от `other`, вместе со значениями обеих сторон
+    pub fn diff_fields(&self, other: &Operation) -> Vec<FieldDiff> {
+        let mut diffs = Vec::new();
+        let mut check = |field: &'static str, left: String, right: String| {
+            if left != right {
+                diffs.push(FieldDiff { field, left, right });
             }
-            OperationType::Transfer => {
-                if self.from_user_id == 0 || self.to_user_id == 0 {
-                    return Err(ParseError::InvalidField {
-                        field: "FROM_USER_ID/TO_USER_ID".to_string(),
-                        reason: "Cannot be 0 for TRANSFER".to_string(),
-                    });
+        };
+
+        check("TX_ID", self.tx_id.to_string(), other.tx_id.to_string());
+        check(
+            "TX_TYPE",
+            self.tx_type.as_str().to_string(),
+            other.tx_type.as_str().to_string(),
+        );
+        check(
+            "FROM_USER_ID",
+            self.from_user_id.to_string(),
+            other.from_user_id.to_string(),
+        );
+        check(
+            "TO_USER_ID",
+            self.to_user_id.to_string(),
+            other.to_user_id.to_string(),
+        );
+        check("AMOUNT", self.amount.to_string(), other.amount.to_string());
+        check(
+            "TIMESTAMP",
+            self.timestamp.to_string(),
+            other.timestamp.to_string(),
+        );
+        check(
+            "STATUS",
+            self.status.as_str().to_string(),
+            other.status.as_str().to_string(),
+        );
+        check(
+            "DESCRIPTION",
+            escape_description(&self.description),
+            escape_description(&other.description),
+        );
+        check(
+            "EXTENSIONS",
+            extensions_to_string(&self.extensions),
+            extensions_to_string(&other.extensions),
+        );
+
+        diffs
+    }
+
+    /// Имена полей, которыми операция отличается от `other` (TX_ID не сравнивается)
+    ///
+    /// `==` у [`Operation`] смотрит только на TX_ID; здесь — все остальные поля.
+    pub fn differing_fields(&self, other: &Operation) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+
+        if self.tx_type != other.tx_type {
+            fields.push("TX_TYPE");
+        }
+        if self.from_user_id != other.from_user_id {
+            fields.push("FROM_USER_ID");
+        }
+        if self.to_user_id != other.to_user_id {
+            fields.push("TO_USER_ID");
+        }
+        if self.amount != other.amount {
+            fields.push("AMOUNT");
+        }
+        if self.timestamp != other.timestamp {
+            fields.push("TIMESTAMP");
+        }
+        if self.status != other.status {
+            fields.push("STATUS");
+        }
+        if self.description != other.description {
+            fields.push("DESCRIPTION");
+        }
+        if self.extensions != other.extensions {
+            fields.push("EXTENSIONS");
+        }
+
+        fields
+    }
+
+    /// Операция в виде JSON для разбора руками (фича `serde`)
+    ///
+    /// Кроме полей записи содержит производные: время в RFC 3339, сумму
+    /// в старших единицах (2 знака после точки), строковые тип и статус
+    /// и результат [`Operation::validate_all`].
+    #[cfg(feature = "serde")]
+    pub fn to_debug_json(&self) -> String {
+        let validation: Vec<String> = match self.validate_all() {
+            Ok(()) => Vec::new(),
+            Err(report) => report.issues.iter().map(|i| i.to_string()).collect(),
+        };
+
+        let value = serde_json::json!({
+            "tx_id": self.tx_id,
+            "tx_type": self.tx_type.as_str(),
+            "from_user_id": self.from_user_id,
+            "to_user_id": self.to_user_id,
+            "amount": self.amount,
+            "timestamp": self.timestamp,
+            "status": self.status.as_str(),
+            "description": self.description,
+            "derived": {
+                "timestamp_rfc3339": crate::timestamp::to_rfc3339(self.timestamp),
+                "amount_decimal": crate::stats::format_amount(self.amount, Some(2)),
+                "description_bytes": self.description.len(),
+                "valid": validation.is_empty(),
+                "validation_errors": validation,
+            },
+        });
+
+        serde_json::to_string_pretty(&value).expect("json! value always serializes")
+    }
+
+    /// Проверяет, что описание не длиннее `max_len` байт UTF-8
+    ///
+    /// Тот же предел, что и [`crate::WriteOptions::max_description_len`]:
+    /// можно поймать длинные описания до выгрузки.
+    pub fn check_description_len(&self, max_len: usize) -> Result<()> {
+        let len = self.description.len();
+        if len > max_len {
+            return Err(ParseError::InvalidField {
+                field: "DESCRIPTION".to_string(),
+                reason: format!("{} bytes exceeds the limit of {} bytes", len, max_len),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Обрезает строку до `max_len` байт, не разрывая символ UTF-8, и дописывает `ellipsis`
+///
+/// Результат вместе с `ellipsis` не длиннее `max_len`. Если сам `ellipsis`
+/// не влезает, строка обрезается без него.
+pub fn truncate_at_char_boundary(s: &str, max_len: usize, ellipsis: &str) -> String {
+    if s.len() <= max_len {
+        return s.to_string();
+    }
+
+    let (budget, suffix) = if ellipsis.len() <= max_len {
+        (max_len - ellipsis.len(), ellipsis)
+    } else {
+        (max_len, "")
+    };
+
+    let mut cut = budget;
+    while !s.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    format!("{}{}", &s[..cut], suffix)
+}
+
+/// Приводит описание из файла к каноническому виду
+///
+/// Канонический вид описания в [`Operation`] — сам текст: UTF-8 без внешних
+/// кавычек и без экранирования. Все парсеры отдают описание только в нем:
+/// bin и txt хранят описание в кавычках с экранированием `\"`, `\\`, `\n`, `\t`, `\r`
+/// и раскрывают его этой функцией, csv снимает свой слой RFC 4180 (удвоенные кавычки).
+/// Писатели делают обратное: bin и txt — [`escape_description`], csv — RFC 4180.
+///
+/// # Аргументы
+/// * `raw` - Описание как оно лежит в файле. Пробелы по краям отбрасываются,
+///   снимается одна пара внешних кавычек, затем раскрывается экранирование
+///
+/// # Возвращает
+/// Каноническое описание
+pub fn canonicalize_description(raw: &str) -> String {
+    let trimmed = raw.trim();
+
+    let unquoted = if trimmed.starts_with('"') && trimmed.ends_with('"') && trimmed.len() >= 2 {
+        &trimmed[1..trimmed.len() - 1]
+    } else {
+        trimmed
+    };
+
+    unescape_string(unquoted)
+}
+
+/// Раскрываем \-экранирование, неизвестные последовательности оставляем как есть
+fn unescape_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(&next_ch) = chars.peek() {
+                match next_ch {
+                    '"' => {
+                        result.push('"');
+                        chars.next();
+                    }
+                    '\\' => {
+                        result.push('\\');
+                        chars.next();
+                    }
+                    'n' => {
+                        result.push('\n');
+                        chars.next();
+                    }
+                    't' => {
+                        result.push('\t');
+                        chars.next();
+                    }
+                    'r' => {
+                        result.push('\r');
+                        chars.next();
+                    }
+                    _ => {
+                        result.push(ch);
+                    }
                 }
+            } else {
+                result.push(ch);
             }
+        } else {
+            result.push(ch);
         }
-        Ok(())
     }
+
+    result
+}
+
+/// Обратное к [`canonicalize_description`]: оборачиваем в кавычки и экранируем
+///
+/// Для любого `s` выполняется `canonicalize_description(&escape_description(s)) == s`.
+pub fn escape_description(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+
+    for ch in s.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(ch),
+        }
+    }
+
+    result.push('"');
+    result
 }
 
 impl Hash for Operation {
@@ -233,3 +811,343 @@ impl PartialEq for Operation {
         self.tx_id == other.tx_id
     }
 }
+
+/// Краткая строка для логов и сообщений: `tx_id 1: TRANSFER 100 SUCCESS`
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx_id {}: {} {} {}",
+            self.tx_id, self.tx_type, self.amount, self.status
+        )
+    }
+}
+
+/// Поле, которым различаются две операции, см. [`Operation::diff_fields`]
+///
+/// Значения — как в CSV: тип и статус строками, описание в кавычках и с экранированием.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} != {}", self.field, self.left, self.right)
+    }
+}
+
+/// Операция, которая сравнивается и хешируется по всем полям, а не только по TX_ID
+///
+/// Ключ для подсчета одинаковых записей: две записи с одним TX_ID, но разной
+/// суммой — это разные ключи.
+#[derive(Debug, Clone)]
+pub struct FullOperation(pub Operation);
+
+impl PartialEq for FullOperation {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_full(&other.0)
+    }
+}
+
+impl Eq for FullOperation {}
+
+impl Hash for FullOperation {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let op = &self.0;
+        op.tx_id.hash(state);
+        op.tx_type.hash(state);
+        op.from_user_id.hash(state);
+        op.to_user_id.hash(state);
+        op.amount.hash(state);
+        op.timestamp.hash(state);
+        op.status.hash(state);
+        op.description.hash(state);
+        op.extensions.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Operation {
+        Operation::transfer(2, 3, 100)
+            .tx_id(1)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("rent")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff_fields_names_each_field() {
+        let base = sample();
+        assert!(base.eq_full(&base.clone()));
+        assert!(base.diff_fields(&base.clone()).is_empty());
+
+        type Change = fn(&mut Operation);
+        let changes: [(&str, Change); 8] = [
+            ("TX_ID", |op| op.tx_id = 9),
+            ("TX_TYPE", |op| op.tx_type = OperationType::Deposit),
+            ("FROM_USER_ID", |op| op.from_user_id = 9),
+            ("TO_USER_ID", |op| op.to_user_id = 9),
+            ("AMOUNT", |op| op.amount = 200),
+            ("TIMESTAMP", |op| op.timestamp += 1),
+            ("STATUS", |op| op.status = OperationStatus::Pending),
+            ("DESCRIPTION", |op| op.description.push('!')),
+        ];
+        for (field, change) in changes {
+            let mut other = base.clone();
+            change(&mut other);
+
+            let diffs = base.diff_fields(&other);
+            assert_eq!(diffs.len(), 1, "{}", field);
+            assert_eq!(diffs[0].field, field);
+            assert!(!base.eq_full(&other), "{}", field);
+            assert_ne!(
+                FullOperation(base.clone()),
+                FullOperation(other),
+                "{}",
+                field
+            );
+        }
+
+        let mut other = base.clone();
+        other.amount = 250;
+        other.status = OperationStatus::Failure;
+        let diffs: Vec<String> = base
+            .diff_fields(&other)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(diffs, ["AMOUNT: 100 != 250", "STATUS: SUCCESS != FAILURE"]);
+        // Обычное `==` по-прежнему смотрит только на TX_ID
+        assert_eq!(base, other);
+    }
+
+    #[test]
+    fn test_unescape_string() {
+        assert_eq!(unescape_string(r#"Record number 1"#), "Record number 1");
+        assert_eq!(
+            unescape_string(r#"\"Record number 1\""#),
+            r#""Record number 1""#
+        );
+        assert_eq!(unescape_string(r#"Line1\nLine2"#), "Line1\nLine2");
+        assert_eq!(unescape_string(r#"Tab\there"#), "Tab\there");
+        assert_eq!(unescape_string(r#"Backslash\\"#), r#"Backslash\"#);
+    }
+
+    #[test]
+    fn test_canonicalize_description() {
+        assert_eq!(
+            canonicalize_description(r#""Нормализуй 1""#),
+            "Нормализуй 1"
+        );
+        assert_eq!(
+            canonicalize_description(r#""\"Нормализуй 1\"""#),
+            r#""Нормализуй 1""#
+        );
+        assert_eq!(canonicalize_description("Нормализуй 1"), "Нормализуй 1");
+        assert_eq!(canonicalize_description(r#"  "trimmed"  "#), "trimmed");
+    }
+
+    #[test]
+    fn test_escape_is_inverse_of_canonicalize() {
+        let samples = [
+            "",
+            "plain",
+            r#""quoted""#,
+            r#"\"escaped\""#,
+            "line1\nline2\r\n\ttab",
+            r"trailing\",
+            "  spaces  ",
+            "Юникод 🎉",
+        ];
+
+        for sample in samples {
+            assert_eq!(
+                canonicalize_description(&escape_description(sample)),
+                sample
+            );
+        }
+    }
+
+    #[test]
+    fn test_escape_description() {
+        assert_eq!(escape_description("Record 1"), r#""Record 1""#);
+        assert_eq!(escape_description(r#"a "b" \ c"#), r#""a \"b\" \\ c""#);
+        assert_eq!(escape_description("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn test_truncate_at_char_boundary() {
+        assert_eq!(truncate_at_char_boundary("short", 10, "..."), "short");
+        assert_eq!(
+            truncate_at_char_boundary("abcdefghij", 8, "..."),
+            "abcde..."
+        );
+        // "я" занимает 2 байта: режем перед ним, а не посередине
+        assert_eq!(truncate_at_char_boundary("abcdяz", 6, "~"), "abcd~");
+        assert_eq!(truncate_at_char_boundary("🎉🎉", 5, ""), "🎉");
+        assert_eq!(truncate_at_char_boundary("abcdef", 2, "..."), "ab");
+    }
+
+    #[test]
+    fn test_check_description_len() {
+        let mut op = Operation::deposit(1, 1)
+            .tx_id(1)
+            .timestamp(1)
+            .status(OperationStatus::Success)
+            .description("я".repeat(128))
+            .build()
+            .unwrap();
+        assert!(op.check_description_len(256).is_ok());
+        assert!(op.check_description_len(255).is_err());
+        op.description.clear();
+        assert!(op.check_description_len(0).is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_debug_json() {
+        let op = Operation {
+            tx_id: 42,
+            tx_type: OperationType::Deposit,
+            from_user_id: 7,
+            to_user_id: 1,
+            amount: -12345,
+            timestamp: 1633046400000,
+            status: OperationStatus::Pending,
+            description: "Перевод \"x\"".to_string(),
+            extensions: Vec::new(),
+        };
+
+        let value: serde_json::Value = serde_json::from_str(&op.to_debug_json()).unwrap();
+        assert_eq!(value["tx_id"], 42);
+        assert_eq!(value["tx_type"], "DEPOSIT");
+        assert_eq!(value["status"], "PENDING");
+        assert_eq!(value["description"], "Перевод \"x\"");
+        assert_eq!(
+            value["derived"]["timestamp_rfc3339"],
+            "2021-10-01T00:00:00.000Z"
+        );
+        assert_eq!(value["derived"]["amount_decimal"], "-123.45");
+        assert_eq!(value["derived"]["valid"], false);
+        assert_eq!(
+            value["derived"]["validation_errors"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            value["derived"]["validation_errors"][1],
+            "error: AMOUNT: Must be positive, got -12345"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let op = sample();
+
+        let json = serde_json::to_string(&op).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["TX_ID"], op.tx_id);
+        assert_eq!(value["TX_TYPE"], op.tx_type.as_str());
+        assert_eq!(value["STATUS"], op.status.as_str());
+
+        let back: Operation = serde_json::from_str(&json).unwrap();
+        assert!(back.eq_full(&op));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_uses_canonical_names() {
+        // Строки, а не as_str(): смена имени в JSON должна ломать этот тест
+        let names = [
+            (OperationType::Deposit, "\"DEPOSIT\""),
+            (OperationType::Transfer, "\"TRANSFER\""),
+            (OperationType::Withdrawal, "\"WITHDRAWAL\""),
+        ];
+        for (tx_type, json) in names {
+            assert_eq!(serde_json::to_string(&tx_type).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<OperationType>(json).unwrap(),
+                tx_type
+            );
+        }
+        let names = [
+            (OperationStatus::Success, "\"SUCCESS\""),
+            (OperationStatus::Failure, "\"FAILURE\""),
+            (OperationStatus::Pending, "\"PENDING\""),
+        ];
+        for (status, json) in names {
+            assert_eq!(serde_json::to_string(&status).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<OperationStatus>(json).unwrap(),
+                status
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_unknown_type_and_invalid_operation() {
+        let value = serde_json::to_value(sample()).unwrap();
+
+        let mut unknown = value.clone();
+        unknown["TX_TYPE"] = "GIFT".into();
+        let err = serde_json::from_value::<Operation>(unknown).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown variant `GIFT`"),
+            "{}",
+            err
+        );
+
+        // DEPOSIT от пользователя не проходит validate()
+        let mut invalid = value;
+        invalid["TX_TYPE"] = "DEPOSIT".into();
+        invalid["FROM_USER_ID"] = 5.into();
+        invalid["TO_USER_ID"] = 7.into();
+        let err = serde_json::from_value::<Operation>(invalid).unwrap_err();
+        assert!(err.to_string().contains("FROM_USER_ID"), "{}", err);
+    }
+
+    #[test]
+    fn test_std_traits_agree_with_inherent_methods() {
+        for tx_type in OperationType::ALL {
+            assert_eq!(tx_type.to_string(), tx_type.as_str());
+            assert_eq!(tx_type.as_str().parse::<OperationType>().unwrap(), tx_type);
+            let code = tx_type.to_u8().unwrap();
+            assert_eq!(u8::try_from(tx_type.clone()).unwrap(), code);
+            assert_eq!(OperationType::try_from(code).unwrap(), tx_type);
+        }
+        for status in OperationStatus::ALL {
+            assert_eq!(status.to_string(), status.as_str());
+            assert_eq!(status.as_str().parse::<OperationStatus>().unwrap(), status);
+            let code = status.to_u8().unwrap();
+            assert_eq!(u8::try_from(status.clone()).unwrap(), code);
+            assert_eq!(OperationStatus::try_from(code).unwrap(), status);
+        }
+
+        // Ошибки тоже совпадают
+        assert_eq!(
+            "GIFT".parse::<OperationType>().unwrap_err().to_string(),
+            OperationType::from_str("GIFT").unwrap_err().to_string()
+        );
+        assert_eq!(
+            OperationStatus::try_from(3).unwrap_err().to_string(),
+            OperationStatus::from_u8(3).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_operation_display() {
+        assert_eq!(sample().to_string(), "tx_id 1: TRANSFER 100 SUCCESS");
+    }
+}
@@ -0,0 +1,495 @@
+use crate::error::{ParseError, Result};
+use crate::migration::{Migrations, SchemaVersion};
+use crate::money::{format_decimal_amount, parse_decimal_amount};
+use crate::operation::{Operation, truncate_at_char_boundary};
+use crate::timestamp::{TimeZoneSpec, to_rfc3339_in};
+use crate::validation::ValidationRules;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Токен отмены долгого разбора/конвертации
+///
+/// Клонируется между потоками: все клоны смотрят на один флаг.
+/// Парсеры проверяют его между записями и возвращают [`ParseError::Cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Создает неотмененный токен
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Просит остановить все операции, которые держат этот токен
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Была ли запрошена отмена
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// В каких единицах записан TIMESTAMP в CSV и текстовом формате
+///
+/// Внутри [`crate::Operation`] время всегда в миллисекундах,
+/// бинарный формат тоже всегда в миллисекундах и эту настройку не смотрит.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TimestampUnit {
+    /// Миллисекунды (как в спецификации)
+    #[default]
+    Millis,
+    /// Целые секунды: при чтении умножаем на 1000, при записи делим (доли секунды теряются)
+    Seconds,
+    /// При чтении значения меньше [`TimestampUnit::AUTO_THRESHOLD`] считаем секундами
+    /// и пишем предупреждение в отчет; при записи — миллисекунды
+    Auto,
+}
+
+impl TimestampUnit {
+    /// Порог для `Auto`: 10^12 мс — это сентябрь 2001, а 10^12 с — далекое будущее
+    pub const AUTO_THRESHOLD: u64 = 1_000_000_000_000;
+
+    /// Переводит прочитанное значение в миллисекунды
+    ///
+    /// # Возвращает
+    /// Миллисекунды и признак того, что `Auto` решил пересчитать значение из секунд
+    pub(crate) fn normalize(self, raw: u64) -> Result<(u64, bool)> {
+        let from_seconds = match self {
+            TimestampUnit::Millis => false,
+            TimestampUnit::Seconds => true,
+            TimestampUnit::Auto => raw < Self::AUTO_THRESHOLD,
+        };
+        if !from_seconds {
+            return Ok((raw, false));
+        }
+
+        let millis = raw
+            .checked_mul(1000)
+            .ok_or_else(|| ParseError::InvalidField {
+                field: "TIMESTAMP".to_string(),
+                reason: format!("{} seconds does not fit in milliseconds", raw),
+            })?;
+        Ok((millis, self == TimestampUnit::Auto))
+    }
+
+    /// Переводит миллисекунды в значение для записи
+    pub(crate) fn denormalize(self, millis: u64) -> u64 {
+        match self {
+            TimestampUnit::Seconds => millis / 1000,
+            TimestampUnit::Millis | TimestampUnit::Auto => millis,
+        }
+    }
+}
+
+/// Как записан AMOUNT в CSV и текстовом формате
+///
+/// Внутри [`crate::Operation`] сумма всегда в минимальных единицах (центах, копейках),
+/// бинарный формат и JSON эту настройку не смотрят.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AmountStyle {
+    /// Целое число минимальных единиц (как в спецификации)
+    #[default]
+    MinorUnits,
+    /// Десятичная дробь с точкой и не больше чем двумя знаками после нее:
+    /// `1050.25` — это 105025, см. [`crate::money::parse_decimal_amount`]
+    Decimal,
+}
+
+impl AmountStyle {
+    /// Разбирает AMOUNT в минимальные единицы
+    pub(crate) fn parse(self, raw: &str) -> Result<i64> {
+        match self {
+            AmountStyle::MinorUnits => raw.parse::<i64>().map_err(|e| ParseError::InvalidField {
+                field: "AMOUNT".to_string(),
+                reason: e.to_string(),
+            }),
+            AmountStyle::Decimal => parse_decimal_amount(raw),
+        }
+    }
+
+    /// AMOUNT для записи
+    pub(crate) fn format(self, amount: i64) -> String {
+        match self {
+            AmountStyle::MinorUnits => amount.to_string(),
+            AmountStyle::Decimal => format_decimal_amount(amount),
+        }
+    }
+}
+
+/// Настройки диалекта CSV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CsvOptions {
+    /// Разделитель полей: `,` по спецификации, `\t` для TSV, `;` у выгрузок Excel
+    pub delimiter: char,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions { delimiter: ',' }
+    }
+}
+
+impl CsvOptions {
+    /// Кавычка и перевод строки разделителем быть не могут
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.delimiter {
+            '"' | '\n' | '\r' => Err(ParseError::InvalidFormat(format!(
+                "CSV delimiter {:?} is not allowed",
+                self.delimiter
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Ограничения при чтении YPBankBin
+///
+/// Длины в бинарнике приходят из самого файла, поэтому битый или чужой файл
+/// может объявить гигабайтное описание. Записи больше лимита отклоняются
+/// с [`ParseError::InvalidField`] еще до чтения тела.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BinOptions {
+    /// Максимальная длина DESCRIPTION в байтах (1 МиБ по умолчанию);
+    /// RECORD_SIZE ограничен тем же лимитом плюс фиксированные поля.
+    /// Старые записи без байта версии узнаются только до 16 МиБ
+    pub max_description_len: usize,
+    /// Максимальный размер блока расширений записи v2 в байтах (64 КиБ по умолчанию);
+    /// тоже входит в предел RECORD_SIZE
+    pub max_extensions_len: usize,
+}
+
+impl Default for BinOptions {
+    fn default() -> Self {
+        BinOptions {
+            max_description_len: 1024 * 1024,
+            max_extensions_len: 64 * 1024,
+        }
+    }
+}
+
+/// Настройки разбора, общие для всех форматов
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Токен отмены; `None` — разбор нельзя прервать
+    pub cancel: Option<CancelToken>,
+    /// Единицы TIMESTAMP в CSV и текстовом формате
+    pub timestamp_unit: TimestampUnit,
+    /// Вид AMOUNT в CSV и текстовом формате
+    pub amount_style: AmountStyle,
+    /// Самая новая версия схемы (прагма `#VERSION`), которую можно читать;
+    /// файлы новее дают [`ParseError::UnsupportedVersion`]
+    pub max_supported_version: SchemaVersion,
+    /// Хуки миграции записей CSV и текста по версии схемы файла; `None` — без хуков
+    pub migrations: Option<Arc<Migrations>>,
+    /// Разделитель и прочий диалект CSV; другие форматы его не смотрят
+    pub csv: CsvOptions,
+    /// Лимиты размеров бинарных записей; другие форматы их не смотрят
+    pub bin: BinOptions,
+    /// Неизвестный ключ текста или лишняя колонка CSV — ошибка
+    /// [`ParseError::InvalidField`], а не молча пропущенное поле
+    pub strict: bool,
+    /// Незнакомые значения TX_TYPE и STATUS (из файлов более новой системы)
+    /// читать как [`crate::OperationType::Unknown`] и [`crate::OperationStatus::Unknown`],
+    /// а не отклонять с [`ParseError::InvalidField`]. Такие записи пишутся обратно
+    /// тем же значением
+    pub lenient: bool,
+    /// Проверять каждую запись еще и этими правилами; нарушения уходят
+    /// в [`crate::ParseReport::warnings`], запись при этом не отбрасывается.
+    /// `None` — только обычный [`Operation::validate`]
+    pub validation: Option<ValidationRules>,
+    /// Длина потока в байтах, если известна (например, из метаданных файла);
+    /// по ней [`crate::Progress::fraction`] считает долю прочитанного
+    pub known_size: Option<u64>,
+    /// Приемник метрик; парсеры зовут его на каждой записи и в конце разбора
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Arc<dyn crate::metrics::MetricsSink>>,
+}
+
+/// Что делать с описанием длиннее [`WriteOptions::max_description_len`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DescriptionPolicy {
+    /// Не писать файл, вернуть ошибку
+    #[default]
+    Error,
+    /// Обрезать по границе символа UTF-8 и дописать [`WriteOptions::ellipsis`]
+    TruncateAtCharBoundary,
+}
+
+/// Настройки записи
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// Делать fsync перед атомарной подменой файла в [`crate::write_file`]
+    pub sync: bool,
+    /// Единицы TIMESTAMP в CSV и текстовом формате
+    pub timestamp_unit: TimestampUnit,
+    /// Вид AMOUNT в CSV и текстовом формате; итоги футера всегда в минимальных единицах
+    pub amount_style: AmountStyle,
+    /// Предел длины описания в байтах UTF-8 (без кавычек и экранирования); `None` — без предела
+    pub max_description_len: Option<usize>,
+    /// Что делать с описанием длиннее предела
+    pub description_policy: DescriptionPolicy,
+    /// Маркер обрезки; входит в предел длины
+    pub ellipsis: String,
+    /// Дописать в конец CSV/текста контрольные итоги ([`crate::Footer`])
+    pub footer: bool,
+    /// Писать TIMESTAMP в CSV/текст как RFC 3339 по времени этого пояса
+    /// вместо числа; `timestamp_unit` тогда не действует. Читается такой
+    /// файл обратно в те же миллисекунды UTC.
+    pub time_zone: Option<TimeZoneSpec>,
+    /// Разделитель и прочий диалект CSV; другие форматы его не смотрят
+    pub csv: CsvOptions,
+    /// Писать YPBankBin в раскладке v2, с CRC32 каждой записи
+    /// ([`crate::bin_format::write_operation_v2`]); другие форматы его не смотрят
+    pub checksum: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            sync: true,
+            timestamp_unit: TimestampUnit::Millis,
+            amount_style: AmountStyle::MinorUnits,
+            max_description_len: None,
+            description_policy: DescriptionPolicy::Error,
+            ellipsis: "...".to_string(),
+            footer: false,
+            time_zone: None,
+            csv: CsvOptions::default(),
+            checksum: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// TIMESTAMP для CSV/текста: число в `timestamp_unit` или RFC 3339 в `time_zone`
+    pub(crate) fn format_timestamp(&self, millis: u64) -> String {
+        match &self.time_zone {
+            Some(tz) => to_rfc3339_in(millis, tz),
+            None => self.timestamp_unit.denormalize(millis).to_string(),
+        }
+    }
+
+    /// Применяет предел длины описания перед записью
+    ///
+    /// # Возвращает
+    /// Операцию как есть, копию с обрезанным описанием или ошибку по политике
+    pub(crate) fn limit_description<'a>(
+        &self,
+        operation: &'a Operation,
+    ) -> Result<Cow<'a, Operation>> {
+        let max_len = match self.max_description_len {
+            Some(max_len) if operation.description.len() > max_len => max_len,
+            _ => return Ok(Cow::Borrowed(operation)),
+        };
+
+        match self.description_policy {
+            DescriptionPolicy::Error => {
+                operation.check_description_len(max_len)?;
+                Ok(Cow::Borrowed(operation))
+            }
+            DescriptionPolicy::TruncateAtCharBoundary => {
+                let mut truncated = operation.clone();
+                truncated.description =
+                    truncate_at_char_boundary(&operation.description, max_len, &self.ellipsis);
+                Ok(Cow::Owned(truncated))
+            }
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Возвращает `Err(Cancelled)`, если отмена уже запрошена
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => Err(ParseError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use crate::operation::{Operation, OperationStatus};
+    use crate::{bin_format, csv_format, text_format};
+    #[cfg(feature = "serde")]
+    use crate::{json_format, jsonl_format};
+    use std::collections::HashSet;
+    use std::io::Read;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Бесконечный поток: `prefix`, затем `unit` по кругу
+    struct Endless {
+        prefix: Vec<u8>,
+        unit: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut written = 0;
+            while written < buf.len() {
+                let byte = if self.pos < self.prefix.len() {
+                    self.prefix[self.pos]
+                } else {
+                    self.unit[(self.pos - self.prefix.len()) % self.unit.len()]
+                };
+                buf[written] = byte;
+                written += 1;
+                self.pos += 1;
+            }
+            Ok(written)
+        }
+    }
+
+    fn endless(format: Format) -> Endless {
+        let op = Operation::deposit(2, 100)
+            .tx_id(1)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("Endless")
+            .build()
+            .unwrap();
+        let operations: HashSet<Operation> = [op].into_iter().collect();
+
+        let mut written = Vec::new();
+        match format {
+            Format::Bin => bin_format::write_all(&mut written, &operations).unwrap(),
+            Format::Csv => csv_format::write_all(&mut written, &operations).unwrap(),
+            Format::Txt => text_format::write_all(&mut written, &operations).unwrap(),
+            #[cfg(feature = "serde")]
+            Format::Json => json_format::write_all(&mut written, &operations).unwrap(),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => jsonl_format::write_all(&mut written, &operations).unwrap(),
+        }
+
+        let (prefix, mut unit) = match format {
+            Format::Csv => {
+                let header_end = written.iter().position(|&b| b == b'\n').unwrap() + 1;
+                (
+                    written[..header_end].to_vec(),
+                    written[header_end..].to_vec(),
+                )
+            }
+            #[cfg(feature = "serde")]
+            Format::Json => {
+                let start = written.iter().position(|&b| b == b'{').unwrap();
+                let end = written.iter().rposition(|&b| b == b'}').unwrap() + 1;
+                let mut unit = written[start..end].to_vec();
+                unit.push(b',');
+                (b"[".to_vec(), unit)
+            }
+            _ => (Vec::new(), written),
+        };
+        if format == Format::Txt {
+            unit.push(b'\n');
+        }
+
+        Endless {
+            prefix,
+            unit,
+            pos: 0,
+        }
+    }
+
+    fn assert_cancels(format: Format) {
+        let token = CancelToken::new();
+        let options = ParseOptions {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        });
+
+        let started = Instant::now();
+        let result = format.parse_all_with_options(endless(format), &options);
+        canceller.join().unwrap();
+
+        assert!(matches!(result, Err(ParseError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_bin_parse_cancelled_from_other_thread() {
+        assert_cancels(Format::Bin);
+    }
+
+    #[test]
+    fn test_csv_parse_cancelled_from_other_thread() {
+        assert_cancels(Format::Csv);
+    }
+
+    #[test]
+    fn test_text_parse_cancelled_from_other_thread() {
+        assert_cancels(Format::Txt);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_parse_cancelled_from_other_thread() {
+        assert_cancels(Format::Json);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_jsonl_parse_cancelled_from_other_thread() {
+        assert_cancels(Format::Jsonl);
+    }
+
+    #[test]
+    fn test_convert_with_cancelled_token_writes_nothing() {
+        let token = CancelToken::new();
+        token.cancel();
+        let options = ParseOptions {
+            cancel: Some(token),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        let result = crate::convert_with_options(
+            endless(Format::Bin),
+            Format::Bin,
+            &mut output,
+            Format::Csv,
+            &options,
+        );
+
+        assert!(matches!(result, Err(ParseError::Cancelled)));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_token_clones_share_flag() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+
+    #[test]
+    fn test_timestamp_unit_conversion() {
+        assert_eq!(TimestampUnit::Millis.normalize(5).unwrap(), (5, false));
+        assert_eq!(TimestampUnit::Seconds.normalize(5).unwrap(), (5000, false));
+        assert_eq!(TimestampUnit::Auto.normalize(5).unwrap(), (5000, true));
+        assert_eq!(
+            TimestampUnit::Auto
+                .normalize(TimestampUnit::AUTO_THRESHOLD)
+                .unwrap(),
+            (TimestampUnit::AUTO_THRESHOLD, false)
+        );
+        assert!(TimestampUnit::Seconds.normalize(u64::MAX).is_err());
+
+        assert_eq!(TimestampUnit::Seconds.denormalize(1999), 1);
+        assert_eq!(TimestampUnit::Auto.denormalize(1999), 1999);
+    }
+}
@@ -0,0 +1,200 @@
+//! Порядок и группировка операций после разбора
+//!
+//! `HashSet` из `parse_all` порядка не хранит, поэтому для воспроизводимых
+//! файлов и отчетов записи нужно упорядочить явно.
+
+use crate::operation::{Operation, OperationType};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Поле, по которому сортируются операции
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SortField {
+    TxId,
+    Timestamp,
+    Amount,
+}
+
+/// Направление сортировки
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SortOrder {
+    /// По возрастанию
+    #[default]
+    Ascending,
+    /// По убыванию
+    Descending,
+}
+
+/// Ключ сортировки: поле и направление
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SortKey {
+    pub field: SortField,
+    pub order: SortOrder,
+}
+
+impl SortKey {
+    /// По возрастанию `field`
+    pub fn ascending(field: SortField) -> Self {
+        SortKey {
+            field,
+            order: SortOrder::Ascending,
+        }
+    }
+
+    /// По убыванию `field`
+    pub fn descending(field: SortField) -> Self {
+        SortKey {
+            field,
+            order: SortOrder::Descending,
+        }
+    }
+
+    /// Сравнивает две операции по ключу
+    pub fn compare(&self, a: &Operation, b: &Operation) -> Ordering {
+        let ordering = match self.field {
+            SortField::TxId => a.tx_id.cmp(&b.tx_id),
+            SortField::Timestamp => a.timestamp.cmp(&b.timestamp),
+            SortField::Amount => a.amount.cmp(&b.amount),
+        };
+        match self.order {
+            SortOrder::Ascending => ordering,
+            SortOrder::Descending => ordering.reverse(),
+        }
+    }
+}
+
+/// Сортирует операции по ключу
+///
+/// Сортировка устойчивая в обоих направлениях: записи с равным ключом
+/// остаются в порядке входа. Чтобы повторный запуск дал тот же файл, вход
+/// тоже должен иметь стабильный порядок (`parse_all_vec`, а не `HashSet`).
+pub fn sort_operations(ops: impl IntoIterator<Item = Operation>, key: SortKey) -> Vec<Operation> {
+    let mut sorted: Vec<Operation> = ops.into_iter().collect();
+    sorted.sort_by(|a, b| key.compare(a, b));
+    sorted
+}
+
+/// Операции каждого пользователя в порядке входа
+///
+/// Операция попадает и к FROM_USER_ID, и к TO_USER_ID (один раз, если это
+/// один и тот же пользователь). Пользователь 0 — внешняя сторона, его группы нет.
+pub fn group_by_user(ops: &[Operation]) -> HashMap<u64, Vec<&Operation>> {
+    let mut groups: HashMap<u64, Vec<&Operation>> = HashMap::new();
+    for op in ops {
+        if op.from_user_id != 0 {
+            groups.entry(op.from_user_id).or_default().push(op);
+        }
+        if op.to_user_id != 0 && op.to_user_id != op.from_user_id {
+            groups.entry(op.to_user_id).or_default().push(op);
+        }
+    }
+    groups
+}
+
+/// Операции каждого типа в порядке входа; типов без операций в карте нет
+pub fn group_by_type(ops: &[Operation]) -> HashMap<OperationType, Vec<&Operation>> {
+    let mut groups: HashMap<OperationType, Vec<&Operation>> = HashMap::new();
+    for op in ops {
+        groups.entry(op.tx_type.clone()).or_default().push(op);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn op(tx_id: u64, amount: i64, timestamp: u64) -> Operation {
+        Operation::transfer(1, 2, amount)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(OperationStatus::Success)
+            .build()
+            .unwrap()
+    }
+
+    fn ids(ops: &[Operation]) -> Vec<u64> {
+        ops.iter().map(|op| op.tx_id).collect()
+    }
+
+    /// Суммы с повторами и отрицательными значениями, время с повторами
+    fn fixture() -> Vec<Operation> {
+        vec![
+            op(4, 100, 20),
+            op(1, -50, 10),
+            op(3, 100, 10),
+            op(2, -200, 30),
+            op(5, 0, 20),
+        ]
+    }
+
+    #[test]
+    fn test_sort_by_amount_handles_negatives_and_ties() {
+        let asc = sort_operations(fixture(), SortKey::ascending(SortField::Amount));
+        assert_eq!(ids(&asc), [2, 1, 5, 4, 3]);
+
+        // Равные суммы остаются в порядке входа и при убывании
+        let desc = sort_operations(fixture(), SortKey::descending(SortField::Amount));
+        assert_eq!(ids(&desc), [4, 3, 5, 1, 2]);
+    }
+
+    #[test]
+    fn test_sort_by_timestamp_is_stable() {
+        let asc = sort_operations(fixture(), SortKey::ascending(SortField::Timestamp));
+        assert_eq!(ids(&asc), [1, 3, 4, 5, 2]);
+
+        let desc = sort_operations(fixture(), SortKey::descending(SortField::Timestamp));
+        assert_eq!(ids(&desc), [2, 4, 5, 1, 3]);
+    }
+
+    #[test]
+    fn test_sort_by_tx_id_keeps_repeats_in_input_order() {
+        let mut ops = fixture();
+        let mut repeat = op(3, 999, 0);
+        repeat.description = "repeat".to_string();
+        ops.push(repeat);
+
+        let sorted = sort_operations(ops, SortKey::ascending(SortField::TxId));
+
+        assert_eq!(ids(&sorted), [1, 2, 3, 3, 4, 5]);
+        assert_eq!(sorted[2].amount, 100);
+        assert_eq!(sorted[3].description, "repeat");
+    }
+
+    #[test]
+    fn test_group_by_user() {
+        let mut ops = fixture();
+        ops.push(Operation {
+            tx_type: OperationType::Deposit,
+            from_user_id: 0,
+            to_user_id: 3,
+            ..op(6, 10, 40)
+        });
+
+        let groups = group_by_user(&ops);
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&1].len(), 5);
+        assert_eq!(
+            groups[&2].iter().map(|op| op.tx_id).collect::<Vec<_>>(),
+            [4, 1, 3, 2, 5]
+        );
+        assert_eq!(groups[&3][0].tx_id, 6);
+        assert!(!groups.contains_key(&0));
+    }
+
+    #[test]
+    fn test_group_by_type() {
+        let mut ops = fixture();
+        ops[1].tx_type = OperationType::Withdrawal;
+        ops[1].to_user_id = 0;
+
+        let groups = group_by_type(&ops);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[&OperationType::Transfer].len(), 4);
+        assert_eq!(groups[&OperationType::Withdrawal][0].tx_id, 1);
+        assert!(!groups.contains_key(&OperationType::Deposit));
+    }
+}
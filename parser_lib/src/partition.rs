@@ -0,0 +1,491 @@
+//! Карантин: делим грязный файл на годные записи и отбракованные
+
+use crate::csv_format;
+use crate::error::{Location, ParseError, Result};
+use crate::format::Format;
+use crate::operation::Operation;
+use crate::options::{CsvOptions, ParseOptions, WriteOptions};
+use crate::provenance::Provenance;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+/// Запись, не прошедшая разбор или проверку
+#[derive(Debug)]
+pub struct RejectedRecord {
+    /// Исходные байты записи (строки CSV/текста или кусок бинарника между MAGIC)
+    pub raw: Vec<u8>,
+    /// Где запись лежала во входном потоке
+    pub provenance: Provenance,
+    /// Ошибка разбора или все ошибки (не предупреждения) [`Operation::validate_all`]
+    pub errors: Vec<ParseError>,
+}
+
+impl RejectedRecord {
+    /// Причины отбраковки одной строкой
+    pub fn reason(&self) -> String {
+        self.errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Итоги [`partition`]
+#[derive(Debug)]
+pub struct PartitionOutcome {
+    /// Формат входа; в нем же сырые записи карантина
+    pub format: Format,
+    /// Диалект CSV входа; с ним же пишется заголовок карантина
+    pub csv: CsvOptions,
+    /// Годные записи в порядке файла (с повторами TX_ID)
+    pub accepted: Vec<Operation>,
+    /// Отбракованные записи в порядке файла
+    pub rejected: Vec<RejectedRecord>,
+}
+
+impl PartitionOutcome {
+    /// Ничего не отбраковано
+    pub fn is_clean(&self) -> bool {
+        self.rejected.is_empty()
+    }
+
+    /// Всего записей во входе: годные и отбракованные
+    pub fn records(&self) -> usize {
+        self.accepted.len() + self.rejected.len()
+    }
+
+    /// TX_ID, встреченные среди годных записей больше одного раза
+    ///
+    /// # Возвращает
+    /// Пары (TX_ID, число записей) в порядке первого появления
+    pub fn duplicate_tx_ids(&self) -> Vec<(u64, usize)> {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        let mut order = Vec::new();
+        for op in &self.accepted {
+            let count = counts.entry(op.tx_id).or_insert(0);
+            if *count == 0 {
+                order.push(op.tx_id);
+            }
+            *count += 1;
+        }
+        order
+            .into_iter()
+            .map(|tx_id| (tx_id, counts[&tx_id]))
+            .filter(|&(_, count)| count > 1)
+            .collect()
+    }
+
+    /// Отчет о проверке в виде JSON (фича `serde`)
+    ///
+    /// Исходные байты отбракованных записей в отчет не попадают.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let rejected: Vec<serde_json::Value> = self
+            .rejected
+            .iter()
+            .map(|record| {
+                let errors: Vec<String> = record.errors.iter().map(|e| e.to_string()).collect();
+                serde_json::json!({
+                    "record": record.provenance.record_index,
+                    "line": record.provenance.line,
+                    "byte_offset": record.provenance.byte_offset,
+                    "errors": errors,
+                })
+            })
+            .collect();
+        let duplicates: Vec<serde_json::Value> = self
+            .duplicate_tx_ids()
+            .into_iter()
+            .map(|(tx_id, count)| serde_json::json!({ "tx_id": tx_id, "count": count }))
+            .collect();
+        let value = serde_json::json!({
+            "format": crate::format::OperationFormat::name(&self.format),
+            "records": self.records(),
+            "valid": self.accepted.len(),
+            "rejected": rejected,
+            "duplicate_tx_ids": duplicates,
+        });
+        serde_json::to_string_pretty(&value).expect("json! value always serializes")
+    }
+
+    /// Пишет годные записи в заданном формате
+    pub fn write_accepted<W: Write>(
+        &self,
+        writer: W,
+        format: Format,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        format.write_all_with_options(writer, &self.accepted, options)
+    }
+
+    /// Пишет отбракованные записи как есть, перед каждой — комментарий с причиной
+    ///
+    /// CSV получает заголовок, текстовые записи разделяются пустой строкой,
+    /// объекты JSON и JSONL идут по одному на строку (без обрамляющего массива).
+    /// Бинарные записи в текстовый файл как есть не лягут, поэтому
+    /// выводятся шестнадцатеричной строкой в комментарии.
+    pub fn write_quarantine<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.format == Format::Csv {
+            csv_format::write_header(&mut writer, &self.csv)?;
+        }
+
+        for record in &self.rejected {
+            writeln!(writer, "# {}: {}", record.provenance, record.reason())?;
+            match self.format {
+                Format::Bin => {
+                    let hex: String = record.raw.iter().map(|b| format!("{:02x}", b)).collect();
+                    writeln!(writer, "# {}", hex)?;
+                }
+                Format::Csv => writer.write_all(&record.raw)?,
+                #[cfg(feature = "serde")]
+                Format::Json | Format::Jsonl => writer.write_all(&record.raw)?,
+                Format::Txt => {
+                    writer.write_all(&record.raw)?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Делит поток на годные и отбракованные записи
+///
+/// В отличие от обычного разбора, ошибка в записи его не прерывает:
+/// запись уходит в [`PartitionOutcome::rejected`] вместе с исходными байтами,
+/// и чтение продолжается со следующей. У годной записи [`Operation::validate_all`]
+/// не находит ошибок; предупреждения ее не отбраковывают.
+///
+/// # Возвращает
+/// * `Ok(PartitionOutcome)` - Поток дочитан до конца
+/// * `Err(ParseError)` - Файл целиком непригоден (нет заголовка CSV, неподдерживаемая
+///   версия), ошибка ввода-вывода или отмена
+pub fn partition<R: Read>(
+    reader: R,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<PartitionOutcome> {
+    let mut outcome = PartitionOutcome {
+        format,
+        csv: options.csv,
+        accepted: Vec::new(),
+        rejected: Vec::new(),
+    };
+    let mut record_index = 0;
+
+    format.parse_each_raw(reader, options, |result, raw, position| {
+        let errors = match result {
+            Ok(operation) => {
+                let errors = match operation.validate_all() {
+                    Ok(()) => Vec::new(),
+                    Err(report) => report.into_errors(),
+                };
+                if errors.is_empty() {
+                    outcome.accepted.push(operation);
+                }
+                errors
+            }
+            Err(e) => vec![e],
+        };
+        if !errors.is_empty() {
+            outcome.rejected.push(RejectedRecord {
+                raw,
+                provenance: Provenance::new(None, record_index, position),
+                errors,
+            });
+        }
+        record_index += 1;
+        Ok(())
+    })?;
+
+    Ok(outcome)
+}
+
+/// Итоги [`parse_all_lossy`]
+#[derive(Debug, Default)]
+pub struct ParseOutcome {
+    /// Годные записи в порядке файла; при повторе TX_ID остается первая
+    pub operations: Vec<Operation>,
+    /// Пропущенные записи: где они и почему пропущены
+    pub errors: Vec<(Location, ParseError)>,
+    /// Всего записей во входе, включая пропущенные и повторы
+    pub records: usize,
+}
+
+/// Разбор, который пропускает битые записи вместо того, чтобы остановиться на первой
+///
+/// Годной считается запись, которую принял бы и обычный разбор: она разобралась
+/// и прошла [`Operation::validate`]. CSV и JSONL пропускают строку, текст — запись
+/// до пустой строки, бинарный формат ищет следующий MAGIC. В отличие от
+/// [`partition`], сырые байты не хранятся.
+///
+/// # Возвращает
+/// * `Ok(ParseOutcome)` - Поток дочитан до конца
+/// * `Err(ParseError)` - Файл целиком непригоден, ошибка ввода-вывода или отмена
+pub fn parse_all_lossy<R: Read>(
+    reader: R,
+    format: Format,
+    options: &ParseOptions,
+) -> Result<ParseOutcome> {
+    let mut outcome = ParseOutcome::default();
+    let mut seen = HashSet::new();
+
+    format.parse_each_raw(reader, options, |result, _, position| {
+        let location = position.location(Some(outcome.records as u64));
+        match result.and_then(|operation| operation.validate().map(|()| operation)) {
+            Ok(operation) => {
+                if seen.insert(operation.tx_id) {
+                    outcome.operations.push(operation);
+                }
+            }
+            Err(e) => outcome.errors.push(split_location(e, location)),
+        }
+        outcome.records += 1;
+        Ok(())
+    })?;
+
+    Ok(outcome)
+}
+
+/// Отделяет место от ошибки; место, приложенное парсером (строка ключа текста), точнее
+fn split_location(error: ParseError, mut location: Location) -> (Location, ParseError) {
+    let error = match error {
+        ParseError::WithContext {
+            location: inner,
+            source,
+        } => {
+            location.line = inner.line.or(location.line);
+            location.field = inner.field;
+            *source
+        }
+        other => other,
+    };
+    if let ParseError::InvalidField { field, .. } = &error {
+        location.field.get_or_insert_with(|| field.clone());
+    }
+    (location, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn ops() -> Vec<Operation> {
+        (1..=4)
+            .map(|tx_id| {
+                Operation::transfer(1, 2, 100 * tx_id as i64)
+                    .tx_id(tx_id)
+                    .timestamp(1633036800000)
+                    .status(OperationStatus::Success)
+                    .description(format!("Payment {}", tx_id))
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn write(format: Format, operations: &[Operation]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, operations, &WriteOptions::default())
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_csv_keeps_good_and_quarantines_bad() {
+        let mut input = write(Format::Csv, &ops());
+        // Битая сумма и нарушение правил DEPOSIT посреди файла
+        input.extend_from_slice(b"5,TRANSFER,1,2,lots,1633036800000,SUCCESS,\"Bad\"\n");
+        input.extend_from_slice(b"6,DEPOSIT,7,2,100,1633036800000,SUCCESS,\"From user\"\n");
+        input.extend_from_slice(b"7,TRANSFER,1,2,700,1633036800000,SUCCESS,\"Good\"\n");
+
+        let outcome = partition(input.as_slice(), Format::Csv, &ParseOptions::default()).unwrap();
+
+        let accepted: Vec<u64> = outcome.accepted.iter().map(|op| op.tx_id).collect();
+        assert_eq!(accepted, vec![1, 2, 3, 4, 7]);
+        assert_eq!(outcome.rejected.len(), 2);
+        assert_eq!(outcome.rejected[0].provenance.line, Some(6));
+        assert!(outcome.rejected[0].reason().contains("AMOUNT"));
+
+        let mut quarantine = Vec::new();
+        outcome.write_quarantine(&mut quarantine).unwrap();
+        let quarantine = String::from_utf8(quarantine).unwrap();
+        let lines: Vec<&str> = quarantine.lines().collect();
+        assert!(lines[0].starts_with("TX_ID,"));
+        assert!(lines[1].starts_with("# <input>:6 (record #4"));
+        assert_eq!(
+            lines[2],
+            "5,TRANSFER,1,2,lots,1633036800000,SUCCESS,\"Bad\""
+        );
+        assert_eq!(
+            lines[4],
+            "6,DEPOSIT,7,2,100,1633036800000,SUCCESS,\"From user\""
+        );
+    }
+
+    #[test]
+    fn test_text_record_is_quarantined_whole() {
+        let mut input = write(Format::Txt, &ops()[..2]);
+        input.extend_from_slice(b"\nTX_ID: 9\nTX_TYPE: GIFT\n# kept with the record\n\n");
+        input.extend_from_slice(&write(Format::Txt, &ops()[2..]));
+
+        let outcome = partition(input.as_slice(), Format::Txt, &ParseOptions::default()).unwrap();
+
+        assert_eq!(outcome.accepted, ops());
+        assert_eq!(outcome.rejected.len(), 1);
+        assert_eq!(
+            outcome.rejected[0].raw,
+            b"TX_ID: 9\nTX_TYPE: GIFT\n# kept with the record\n"
+        );
+    }
+
+    #[test]
+    fn test_bin_resyncs_after_garbage() {
+        let good = write(Format::Bin, &ops());
+        let first_len = write(Format::Bin, &ops()[..1]).len();
+        let mut input = good[..first_len].to_vec();
+        input.extend_from_slice(b"garbage");
+        input.extend_from_slice(&good[first_len..]);
+        // Обрезанная последняя запись
+        input.extend_from_slice(&good[..first_len - 3]);
+
+        let outcome = partition(input.as_slice(), Format::Bin, &ParseOptions::default()).unwrap();
+
+        assert_eq!(outcome.accepted, ops());
+        assert_eq!(outcome.rejected.len(), 2);
+        assert_eq!(outcome.rejected[0].raw, b"garbage");
+        assert!(matches!(
+            outcome.rejected[0].errors[..],
+            [ParseError::InvalidMagic]
+        ));
+        assert_eq!(
+            outcome.rejected[0].provenance.byte_offset,
+            Some(first_len as u64)
+        );
+        assert_eq!(outcome.rejected[1].raw.len(), first_len - 3);
+    }
+
+    #[test]
+    fn test_report_counts_and_duplicate_tx_ids() {
+        let mut operations = ops();
+        operations.push(ops()[1].clone());
+        operations.push(ops()[0].clone());
+        operations.push(ops()[1].clone());
+        let mut input = write(Format::Csv, &operations);
+        input.extend_from_slice(b"9,TRANSFER,1,2,lots,1633036800000,SUCCESS,\"Bad\"\n");
+
+        let outcome = partition(input.as_slice(), Format::Csv, &ParseOptions::default()).unwrap();
+
+        assert_eq!(outcome.records(), 8);
+        assert_eq!(outcome.duplicate_tx_ids(), vec![(1, 2), (2, 3)]);
+
+        #[cfg(feature = "serde")]
+        {
+            let value: serde_json::Value = serde_json::from_str(&outcome.to_json()).unwrap();
+            assert_eq!(value["format"], "csv");
+            assert_eq!(value["records"], 8);
+            assert_eq!(value["valid"], 7);
+            assert_eq!(value["rejected"][0]["line"], 9);
+            assert!(
+                value["rejected"][0]["errors"][0]
+                    .as_str()
+                    .unwrap()
+                    .contains("AMOUNT")
+            );
+            assert_eq!(value["duplicate_tx_ids"][1]["tx_id"], 2);
+            assert_eq!(value["duplicate_tx_ids"][1]["count"], 3);
+        }
+    }
+
+    #[test]
+    fn test_lossy_csv_skips_each_kind_of_bad_record() {
+        let mut input = write(Format::Csv, &ops()[..2]);
+        // Битое число, неизвестный тип, DEPOSIT от пользователя и нехватка колонок
+        // вперемешку с годными записями и повтором TX_ID
+        input.extend_from_slice(b"5,TRANSFER,1,2,lots,1633036800000,SUCCESS,\"Bad\"\n");
+        input.extend_from_slice(b"3,TRANSFER,1,2,300,1633036800000,SUCCESS,\"Payment 3\"\n");
+        input.extend_from_slice(b"6,GIFT,1,2,100,1633036800000,SUCCESS,\"Gift\"\n");
+        input.extend_from_slice(b"7,DEPOSIT,7,2,100,1633036800000,SUCCESS,\"From user\"\n");
+        input.extend_from_slice(b"8,TRANSFER,1\n");
+        input.extend_from_slice(b"1,TRANSFER,1,2,999,1633036800000,SUCCESS,\"Repeat\"\n");
+        input.extend_from_slice(b"4,TRANSFER,1,2,400,1633036800000,SUCCESS,\"Payment 4\"\n");
+
+        let outcome =
+            crate::csv_format::parse_all_lossy(input.as_slice(), &ParseOptions::default()).unwrap();
+
+        assert_eq!(outcome.operations, ops());
+        assert_eq!(outcome.operations[0].amount, 100);
+        assert_eq!(outcome.records, 9);
+        let lines: Vec<_> = outcome.errors.iter().map(|(l, _)| l.line).collect();
+        assert_eq!(lines, [Some(4), Some(6), Some(7), Some(8)]);
+        let fields: Vec<_> = outcome
+            .errors
+            .iter()
+            .map(|(l, _)| l.field.as_deref())
+            .collect();
+        assert_eq!(
+            fields,
+            [Some("AMOUNT"), Some("TX_TYPE"), Some("FROM_USER_ID"), None]
+        );
+        assert_eq!(outcome.errors[0].0.record_index, Some(2));
+        // Ошибка отдается без обертки с местом
+        assert!(matches!(
+            outcome.errors[0].1,
+            ParseError::InvalidField { .. }
+        ));
+    }
+
+    #[test]
+    fn test_lossy_text_jsonl_and_bin() {
+        let mut text = write(Format::Txt, &ops()[..2]);
+        text.extend_from_slice(b"\nTX_ID: 9\nTX_TYPE: GIFT\n\n");
+        text.extend_from_slice(b"TX_ID: 10\nAMOUNT: 1\nAMOUNT: 2\n\n");
+        text.extend_from_slice(&write(Format::Txt, &ops()[2..]));
+        let outcome =
+            parse_all_lossy(text.as_slice(), Format::Txt, &ParseOptions::default()).unwrap();
+        assert_eq!(outcome.operations, ops());
+        assert_eq!(outcome.errors.len(), 2);
+        // Повтор ключа — на строке второго AMOUNT
+        let (location, error) = &outcome.errors[1];
+        assert_eq!(location.field.as_deref(), Some("AMOUNT"));
+        assert_eq!(location.line.unwrap(), location_of(&text, b"AMOUNT: 2\n"));
+        assert!(error.to_string().contains("duplicate key"));
+
+        #[cfg(feature = "serde")]
+        {
+            let mut jsonl = write(Format::Jsonl, &ops()[..1]);
+            jsonl.extend_from_slice(b"{\"TX_ID\": 9,\n");
+            jsonl.extend_from_slice(&write(Format::Jsonl, &ops()[1..]));
+            let outcome =
+                parse_all_lossy(jsonl.as_slice(), Format::Jsonl, &ParseOptions::default()).unwrap();
+            assert_eq!(outcome.operations, ops());
+            assert_eq!(outcome.errors[0].0.line, Some(2));
+        }
+
+        let good = write(Format::Bin, &ops());
+        let first_len = write(Format::Bin, &ops()[..1]).len();
+        let mut bin = good[..first_len].to_vec();
+        bin.extend_from_slice(b"garbage");
+        bin.extend_from_slice(&good[first_len..]);
+        let outcome =
+            parse_all_lossy(bin.as_slice(), Format::Bin, &ParseOptions::default()).unwrap();
+        assert_eq!(outcome.operations, ops());
+        assert_eq!(outcome.records, 5);
+        assert_eq!(outcome.errors[0].0.byte_offset, Some(first_len as u64));
+        assert!(matches!(outcome.errors[0].1, ParseError::InvalidMagic));
+    }
+
+    /// Номер строки с 1, на которой начинается `needle`
+    fn location_of(input: &[u8], needle: &[u8]) -> u64 {
+        let at = input
+            .windows(needle.len())
+            .position(|w| w == needle)
+            .unwrap();
+        input[..at].iter().filter(|&&b| b == b'\n').count() as u64 + 1
+    }
+}
@@ -0,0 +1,211 @@
+//! Ход долгого разбора: сколько прочитано и сколько осталось
+//!
+//! [`crate::Format::parse_all_with_progress`] зовет обработчик не на каждой записи,
+//! а раз в [`Progress::EVERY_RECORDS`] записей или [`Progress::EVERY_BYTES`] байт,
+//! и еще раз в конце. Обработчик может прервать разбор, вернув
+//! [`ControlFlow::Break`]: тогда разбор заканчивается [`ParseError::Cancelled`].
+
+use crate::error::{ParseError, Result};
+use std::ops::ControlFlow;
+
+/// Сколько разобрано к моменту вызова обработчика
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Байт прочитано из потока (вместе с тем, что парсер успел прочитать наперед)
+    pub bytes: u64,
+    /// Записей разобрано
+    pub records: u64,
+    /// Длина потока из [`crate::ParseOptions::known_size`], если она известна
+    pub total_bytes: Option<u64>,
+}
+
+impl Progress {
+    /// Обработчик зовется не реже, чем раз в столько записей
+    pub const EVERY_RECORDS: u64 = 10_000;
+    /// И не реже, чем раз в столько прочитанных байт
+    pub const EVERY_BYTES: u64 = 4 << 20;
+
+    /// Доля прочитанного от 0 до 1; `None`, если длина потока неизвестна
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total_bytes {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+/// Решает, когда звать обработчик хода разбора
+pub(crate) struct ProgressTicker {
+    records: u64,
+    next_records: u64,
+    next_bytes: u64,
+    total_bytes: Option<u64>,
+}
+
+impl ProgressTicker {
+    pub(crate) fn new(total_bytes: Option<u64>) -> Self {
+        ProgressTicker {
+            records: 0,
+            next_records: Progress::EVERY_RECORDS,
+            next_bytes: Progress::EVERY_BYTES,
+            total_bytes,
+        }
+    }
+
+    /// Граница записи; `bytes` — сколько прочитано к этому моменту
+    pub(crate) fn record(
+        &mut self,
+        bytes: u64,
+        on_progress: &mut impl FnMut(Progress) -> ControlFlow<()>,
+    ) -> Result<()> {
+        self.records += 1;
+        if self.records < self.next_records && bytes < self.next_bytes {
+            return Ok(());
+        }
+        self.next_records = self.records + Progress::EVERY_RECORDS;
+        self.next_bytes = bytes.saturating_add(Progress::EVERY_BYTES);
+        match on_progress(self.progress(bytes)) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(ParseError::Cancelled),
+        }
+    }
+
+    /// Последний вызов после успешного разбора; прерывать уже нечего
+    pub(crate) fn finish(
+        self,
+        bytes: u64,
+        on_progress: &mut impl FnMut(Progress) -> ControlFlow<()>,
+    ) {
+        let _ = on_progress(self.progress(bytes));
+    }
+
+    fn progress(&self, bytes: u64) -> Progress {
+        Progress {
+            bytes,
+            records: self.records,
+            total_bytes: self.total_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use crate::operation::{Operation, OperationStatus};
+    use crate::options::{ParseOptions, WriteOptions};
+
+    const RECORDS: usize = 12_000;
+
+    fn encoded(format: Format) -> Vec<u8> {
+        let operations: Vec<Operation> = (1..=RECORDS as u64)
+            .map(|tx_id| {
+                Operation::deposit(tx_id % 100 + 1, 100)
+                    .tx_id(tx_id)
+                    .timestamp(1_633_036_800_000 + tx_id)
+                    .status(OperationStatus::Success)
+                    .description(format!("Deposit {}", tx_id))
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, &operations, &WriteOptions::default())
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_progress_is_bounded_and_reaches_the_end() {
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let data = encoded(format);
+            let options = ParseOptions {
+                known_size: Some(data.len() as u64),
+                ..Default::default()
+            };
+            let mut calls = Vec::new();
+            let (operations, report) = format
+                .parse_all_with_progress(data.as_slice(), &options, |progress| {
+                    calls.push(progress);
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+
+            assert_eq!(operations.len(), RECORDS, "{:?}", format);
+            // По записям — не чаще раза в EVERY_RECORDS, по байтам — раз в EVERY_BYTES, и финал
+            let bound = RECORDS as u64 / Progress::EVERY_RECORDS
+                + data.len() as u64 / Progress::EVERY_BYTES
+                + 1;
+            assert!(
+                calls.len() as u64 <= bound,
+                "{:?}: {} calls",
+                format,
+                calls.len()
+            );
+            assert!(calls.windows(2).all(|w| w[0].records <= w[1].records));
+
+            let last = calls.last().unwrap();
+            assert_eq!(last.records, report.records as u64);
+            assert_eq!(last.bytes, data.len() as u64);
+            assert_eq!(last.fraction(), Some(1.0));
+        }
+    }
+
+    #[test]
+    fn test_break_cancels_promptly() {
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let data = encoded(format);
+            let mut seen = 0;
+            let result = format.parse_all_with_progress(
+                data.as_slice(),
+                &ParseOptions::default(),
+                |progress| {
+                    seen = progress.records;
+                    ControlFlow::Break(())
+                },
+            );
+
+            assert!(matches!(result, Err(ParseError::Cancelled)), "{:?}", format);
+            assert!(seen <= Progress::EVERY_RECORDS, "{:?}: {}", format, seen);
+        }
+    }
+
+    #[test]
+    fn test_fraction() {
+        let progress = Progress {
+            bytes: 250,
+            records: 3,
+            total_bytes: None,
+        };
+        assert_eq!(progress.fraction(), None);
+        let known = Progress {
+            total_bytes: Some(1000),
+            ..progress
+        };
+        assert_eq!(known.fraction(), Some(0.25));
+        // Поток оказался длиннее заявленного
+        let longer = Progress {
+            bytes: 2000,
+            ..known
+        };
+        assert_eq!(longer.fraction(), Some(1.0));
+    }
+}
@@ -0,0 +1,164 @@
+//! Откуда взялась запись: источник, строка, смещение в байтах
+
+use crate::error::Location;
+use std::fmt;
+
+/// Положение записи в потоке, которое парсеры отдают вместе с операцией
+///
+/// Только счетчики без аллокаций: обычный разбор просто выбрасывает их.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RecordPosition {
+    pub(crate) line: Option<u64>,
+    pub(crate) byte_offset: Option<u64>,
+}
+
+impl RecordPosition {
+    /// Место для ошибки в записи, которая начинается здесь
+    pub(crate) fn location(self, record_index: Option<u64>) -> Location {
+        Location {
+            line: self.line,
+            record_index,
+            byte_offset: self.byte_offset,
+            field: None,
+        }
+    }
+}
+
+/// Происхождение одной записи
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Имя источника (обычно путь к файлу), если его передали
+    pub source: Option<String>,
+    /// Номер первой строки записи с 1 (CSV и текст; у бинарника строк нет)
+    pub line: Option<u64>,
+    /// Смещение начала записи от начала потока в байтах
+    pub byte_offset: Option<u64>,
+    /// Порядковый номер записи в источнике с 0
+    pub record_index: u64,
+}
+
+impl Provenance {
+    pub(crate) fn new(source: Option<&str>, record_index: u64, position: RecordPosition) -> Self {
+        Provenance {
+            source: source.map(str::to_string),
+            line: position.line,
+            byte_offset: position.byte_offset,
+            record_index,
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source.as_deref().unwrap_or("<input>"))?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(f, " (record #{}", self.record_index)?;
+        if let Some(offset) = self.byte_offset {
+            write!(f, ", byte {}", offset)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Format, OperationFormat};
+    use crate::operation::{Operation, OperationStatus};
+    use crate::options::ParseOptions;
+
+    fn ops() -> Vec<Operation> {
+        (1..=3)
+            .map(|tx_id| {
+                Operation::deposit(1, 100)
+                    .tx_id(tx_id)
+                    .timestamp(1633036800000)
+                    .status(OperationStatus::Success)
+                    // Перевод строки внутри описания: в CSV запись займет две строки
+                    .description(if tx_id == 2 { "two\nlines" } else { "one" })
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_positions_point_at_record_starts() {
+        let ops = ops();
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &ops, &Default::default())
+                .unwrap();
+
+            let parsed = format
+                .parse_all_with_provenance(
+                    buf.as_slice(),
+                    Some("day.src"),
+                    &ParseOptions::default(),
+                )
+                .unwrap();
+            assert_eq!(parsed.len(), 3);
+
+            for (i, (op, provenance)) in parsed.iter().enumerate() {
+                assert_eq!(op.tx_id, ops[i].tx_id);
+                assert_eq!(provenance.record_index, i as u64);
+                assert_eq!(provenance.source.as_deref(), Some("day.src"));
+
+                let rest = &buf[provenance.byte_offset.unwrap() as usize..];
+                let expected = match format {
+                    Format::Bin => b"YPBN".to_vec(),
+                    Format::Csv => format!("{},", op.tx_id).into_bytes(),
+                    Format::Txt => format!("TX_ID: {}", op.tx_id).into_bytes(),
+                    #[cfg(feature = "serde")]
+                    Format::Json | Format::Jsonl => {
+                        format!("{{\"TX_ID\": {},", op.tx_id).into_bytes()
+                    }
+                };
+                assert!(rest.starts_with(&expected), "{} #{}", format.name(), i);
+            }
+
+            let lines: Vec<Option<u64>> = parsed.iter().map(|(_, p)| p.line).collect();
+            let expected = match format {
+                Format::Bin => vec![None, None, None],
+                Format::Csv => vec![Some(2), Some(3), Some(5)],
+                Format::Txt => vec![Some(1), Some(10), Some(19)],
+                #[cfg(feature = "serde")]
+                Format::Json => vec![Some(2), Some(3), Some(4)],
+                #[cfg(feature = "serde")]
+                Format::Jsonl => vec![Some(1), Some(2), Some(3)],
+            };
+            assert_eq!(lines, expected, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let provenance = Provenance {
+            source: Some("a.csv".to_string()),
+            line: Some(7),
+            byte_offset: Some(120),
+            record_index: 5,
+        };
+        assert_eq!(provenance.to_string(), "a.csv:7 (record #5, byte 120)");
+
+        let provenance = Provenance {
+            source: None,
+            line: None,
+            byte_offset: Some(0),
+            record_index: 0,
+        };
+        assert_eq!(provenance.to_string(), "<input> (record #0, byte 0)");
+    }
+}
@@ -0,0 +1,305 @@
+//! Обезличивание операций перед передачей файла наружу (подрядчику, в баг-репорт)
+//!
+//! Пользователи заменяются псевдонимами через HMAC-SHA256 (крейты hmac и sha2)
+//! с ключом [`RedactPolicy::key`]:
+//! с одним ключом один и тот же пользователь всегда получает один и тот же псевдоним,
+//! а без ключа исходные id не восстановить. Внешняя сторона 0 остается 0, а ненулевой
+//! id никогда не становится 0, так что обезличенные операции проходят
+//! [`Operation::validate`]. TX_ID, тип и статус не меняются.
+
+use crate::digest::to_hex;
+use crate::error::Result;
+use crate::operation::Operation;
+use crate::transform::Transform;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+/// Метки, чтобы псевдонимы пользователей и описаний не совпадали при одном ключе
+const USER_DOMAIN: &[u8] = b"YPBank redact user\0";
+const DESCRIPTION_DOMAIN: &[u8] = b"YPBank redact description\0";
+
+/// Шестнадцатеричных символов в хеше описания
+const DESCRIPTION_HASH_LEN: usize = 16;
+
+/// Что делать с описанием
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum DescriptionRedaction {
+    /// Оставить как есть
+    Keep,
+    /// Заменить непустое описание этим текстом
+    Replace(String),
+    /// Заменить непустое описание ключевым хешом: одинаковые описания остаются
+    /// одинаковыми, но прочитать их нельзя
+    #[default]
+    Hash,
+}
+
+/// Что и как обезличивать, см. [`redact_operations`]
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RedactPolicy {
+    /// Ключ псевдонимов; с другим ключом получаются другие псевдонимы
+    pub key: Vec<u8>,
+    /// Заменять FROM_USER_ID/TO_USER_ID псевдонимами
+    pub pseudonymize_users: bool,
+    /// Что делать с описанием
+    pub description: DescriptionRedaction,
+    /// Округлять TIMESTAMP вниз до кратного стольким миллисекундам
+    /// (например, [`RedactPolicy::HOUR`]); `None` — не трогать
+    pub timestamp_bucket: Option<u64>,
+    /// Округлять AMOUNT до ближайшего кратного этому шагу в минимальных единицах;
+    /// ненулевая сумма не становится нулем. `None` — не трогать
+    pub amount_step: Option<i64>,
+    /// Убрать расширения: в них может быть что угодно
+    pub drop_extensions: bool,
+}
+
+impl RedactPolicy {
+    /// Час в миллисекундах, для [`RedactPolicy::timestamp_bucket`]
+    pub const HOUR: u64 = 60 * 60 * 1000;
+
+    /// Псевдонимы пользователей, хеши описаний, без расширений; время и суммы как есть
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        RedactPolicy {
+            key: key.into(),
+            pseudonymize_users: true,
+            description: DescriptionRedaction::Hash,
+            timestamp_bucket: None,
+            amount_step: None,
+            drop_extensions: true,
+        }
+    }
+
+    /// Обезличенная копия одной операции
+    pub fn redact(&self, mut op: Operation) -> Operation {
+        if self.pseudonymize_users {
+            op.from_user_id = self.pseudonym(op.from_user_id);
+            op.to_user_id = self.pseudonym(op.to_user_id);
+        }
+        match &self.description {
+            DescriptionRedaction::Keep => {}
+            _ if op.description.is_empty() => {}
+            DescriptionRedaction::Replace(text) => op.description = text.clone(),
+            DescriptionRedaction::Hash => {
+                let mut hex = to_hex(&self.mac(DESCRIPTION_DOMAIN, op.description.as_bytes()));
+                hex.truncate(DESCRIPTION_HASH_LEN);
+                op.description = hex;
+            }
+        }
+        if let Some(bucket) = self.timestamp_bucket.filter(|&bucket| bucket > 0) {
+            op.timestamp -= op.timestamp % bucket;
+        }
+        if let Some(step) = self.amount_step.filter(|&step| step > 0) {
+            op.amount = round_amount(op.amount, step);
+        }
+        if self.drop_extensions {
+            op.extensions.clear();
+        }
+        op
+    }
+
+    /// Псевдоним пользователя: 0 остается 0, остальные — первые 8 байт HMAC, но не 0
+    ///
+    /// Два разных пользователя могут совпасть только при коллизии 64-битного хеша.
+    pub fn pseudonym(&self, user_id: u64) -> u64 {
+        if user_id == 0 {
+            return 0;
+        }
+        let mac = self.mac(USER_DOMAIN, &user_id.to_be_bytes());
+        let pseudonym = u64::from_be_bytes(mac[..8].try_into().unwrap());
+        pseudonym.max(1)
+    }
+
+    /// HMAC-SHA256 с ключом политики от метки `domain` и самого значения
+    fn mac(&self, domain: &[u8], value: &[u8]) -> [u8; 32] {
+        // HMAC принимает ключ любой длины
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key length");
+        mac.update(domain);
+        mac.update(value);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+impl fmt::Debug for RedactPolicy {
+    /// Ключ в отладочный вывод не попадает
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedactPolicy")
+            .field("key", &"<hidden>")
+            .field("pseudonymize_users", &self.pseudonymize_users)
+            .field("description", &self.description)
+            .field("timestamp_bucket", &self.timestamp_bucket)
+            .field("amount_step", &self.amount_step)
+            .field("drop_extensions", &self.drop_extensions)
+            .finish()
+    }
+}
+
+/// Шаг в [`crate::Pipeline`], например после фильтра в конвертере
+impl Transform for RedactPolicy {
+    fn name(&self) -> &str {
+        "anonymize"
+    }
+
+    fn apply(&self, op: Operation) -> Result<Option<Operation>> {
+        Ok(Some(self.redact(op)))
+    }
+}
+
+/// Обезличивает операции на лету по политике `policy`
+///
+/// Порядок и количество операций не меняются.
+pub fn redact_operations<'a>(
+    ops: impl IntoIterator<Item = Operation> + 'a,
+    policy: &'a RedactPolicy,
+) -> impl Iterator<Item = Operation> + 'a {
+    ops.into_iter().map(move |op| policy.redact(op))
+}
+
+/// До ближайшего кратного `step` (половина — от нуля), но не в ноль из ненулевой суммы
+fn round_amount(amount: i64, step: i64) -> i64 {
+    let rem = amount % step;
+    let down = amount - rem;
+    let rounded = if rem.unsigned_abs() * 2 >= step.unsigned_abs() {
+        down.saturating_add(step * amount.signum())
+    } else {
+        down
+    };
+    if rounded == 0 && amount != 0 {
+        step * amount.signum()
+    } else {
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::edge_cases;
+    use crate::operation::{Extension, OperationStatus, OperationType};
+    use crate::transform::Pipeline;
+
+    fn transfer(from: u64, to: u64) -> Operation {
+        Operation {
+            tx_id: 1,
+            tx_type: OperationType::Transfer,
+            from_user_id: from,
+            to_user_id: to,
+            amount: 12_345,
+            timestamp: 1_633_039_999_999,
+            status: OperationStatus::Success,
+            description: "Rent for flat 12".to_string(),
+            extensions: vec![Extension {
+                tag: 1,
+                value: b"secret".to_vec(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_same_key_is_deterministic() {
+        let policy = RedactPolicy::new("k1");
+        let ops = edge_cases();
+        let first: Vec<Operation> = redact_operations(ops.clone(), &policy).collect();
+        let second: Vec<Operation> = redact_operations(ops, &RedactPolicy::new("k1")).collect();
+
+        assert_eq!(first.len(), second.len());
+        assert!(first.iter().zip(&second).all(|(a, b)| a.eq_full(b)));
+
+        // Один пользователь в разных операциях — один псевдоним
+        let a = policy.redact(transfer(5, 6));
+        let b = policy.redact(transfer(6, 5));
+        assert_eq!(a.from_user_id, b.to_user_id);
+        assert_eq!(a.to_user_id, b.from_user_id);
+        assert_ne!(a.from_user_id, 5);
+    }
+
+    #[test]
+    fn test_mac_is_hmac_sha256_of_domain_and_value() {
+        // RFC 4231, случай 2: метка и значение хешируются как одно сообщение
+        let policy = RedactPolicy::new("Jefe");
+        assert_eq!(
+            to_hex(&policy.mac(b"what do ya want ", b"for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_different_keys_give_different_output() {
+        let op = transfer(5, 6);
+        let a = RedactPolicy::new("k1").redact(op.clone());
+        let b = RedactPolicy::new("k2").redact(op);
+
+        assert_ne!(a.from_user_id, b.from_user_id);
+        assert_ne!(a.to_user_id, b.to_user_id);
+        assert_ne!(a.description, b.description);
+    }
+
+    #[test]
+    fn test_redacted_operations_validate() {
+        let policy = RedactPolicy {
+            timestamp_bucket: Some(RedactPolicy::HOUR),
+            amount_step: Some(1000),
+            ..RedactPolicy::new("key")
+        };
+        let mut ops = edge_cases();
+        ops.extend((1..500).map(|user| transfer(user, u64::MAX - user)));
+
+        for (original, redacted) in ops.iter().zip(redact_operations(ops.clone(), &policy)) {
+            redacted.validate().unwrap();
+            assert_eq!(redacted.tx_id, original.tx_id);
+            assert_eq!(redacted.tx_type, original.tx_type);
+            assert_eq!(original.from_user_id == 0, redacted.from_user_id == 0);
+            assert_eq!(original.to_user_id == 0, redacted.to_user_id == 0);
+            assert_eq!(original.amount == 0, redacted.amount == 0);
+            assert_eq!(redacted.timestamp % RedactPolicy::HOUR, 0);
+            assert!(redacted.extensions.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_description_timestamp_and_amount() {
+        let op = transfer(5, 6);
+        let hashed = RedactPolicy::new("key").redact(op.clone());
+        assert_eq!(hashed.description.len(), DESCRIPTION_HASH_LEN);
+        assert_eq!(hashed.amount, op.amount);
+        assert_eq!(hashed.timestamp, op.timestamp);
+
+        let policy = RedactPolicy {
+            pseudonymize_users: false,
+            description: DescriptionRedaction::Replace("[removed]".to_string()),
+            timestamp_bucket: Some(RedactPolicy::HOUR),
+            amount_step: Some(100),
+            drop_extensions: false,
+            ..RedactPolicy::new("key")
+        };
+        let redacted = policy.redact(op.clone());
+        assert_eq!((redacted.from_user_id, redacted.to_user_id), (5, 6));
+        assert_eq!(redacted.description, "[removed]");
+        assert_eq!(redacted.timestamp, 1_633_039_200_000);
+        assert_eq!(redacted.amount, 12_300);
+        assert_eq!(redacted.extensions, op.extensions);
+
+        // Пустое описание остается пустым
+        let mut empty = op;
+        empty.description.clear();
+        assert!(policy.redact(empty).description.is_empty());
+
+        assert_eq!(round_amount(150, 100), 200);
+        assert_eq!(round_amount(-150, 100), -200);
+        assert_eq!(round_amount(49, 100), 100);
+        assert_eq!(round_amount(-1, 100), -100);
+        assert_eq!(round_amount(0, 100), 0);
+        assert_eq!(round_amount(i64::MAX, 100), i64::MAX - 7);
+    }
+
+    #[test]
+    fn test_policy_in_pipeline_and_debug_hides_key() {
+        let policy = RedactPolicy::new("top secret");
+        let redacted = Pipeline::new()
+            .then(policy.clone())
+            .apply_all(vec![transfer(5, 6)])
+            .unwrap();
+        assert_eq!(redacted[0].from_user_id, policy.pseudonym(5));
+        assert!(!format!("{:?}", policy).contains("top secret"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::{ParseError, Result};
+use crate::footer::Footer;
+#[cfg(feature = "metrics")]
+use crate::metrics::{AllocationMark, MetricsSink, MetricsSnapshot};
+use crate::migration::SchemaVersion;
+use crate::operation::Operation;
+use crate::options::{ParseOptions, TimestampUnit};
+use crate::timestamp::parse_rfc3339;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+/// Некритичная находка при разборе: запись прочитана, но стоит обратить внимание
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// Операция, к которой относится предупреждение
+    pub tx_id: u64,
+    /// Что случилось
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tx_id {}: {}", self.tx_id, self.message)
+    }
+}
+
+/// Итоги разбора
+#[derive(Debug, Clone, Default)]
+pub struct ParseReport {
+    /// Сколько записей прочитано (с повторами TX_ID)
+    pub records: usize,
+    /// Предупреждения в порядке записей
+    pub warnings: Vec<ParseWarning>,
+    /// Контрольные итоги из файла, если они там были (уже сверены с записями)
+    pub footer: Option<Footer>,
+    /// Число записей из заголовка бинарного файла, если он был (уже сверено с записями)
+    pub declared_records: Option<u64>,
+    /// Версия схемы из прагмы `#VERSION` (без прагмы — [`SchemaVersion::V1`])
+    pub schema_version: SchemaVersion,
+    /// Поля записей CSV и текста, которых нет в текущей схеме (лишние колонки,
+    /// незнакомые ключи), по TX_ID; со [`ParseOptions::strict`] такие поля — ошибка
+    pub extras: BTreeMap<u64, BTreeMap<String, String>>,
+    /// Сколько байт прочитано из потока
+    pub bytes: u64,
+    /// Сколько длился разбор
+    pub duration: Duration,
+}
+
+impl ParseReport {
+    /// Записей в секунду; 0, если разбор не занял измеримого времени
+    pub fn records_per_sec(&self) -> f64 {
+        per_sec(self.records, self.duration)
+    }
+
+    /// Разбирает поле TIMESTAMP из CSV/текста в миллисекунды UTC
+    ///
+    /// Число приводится по `options.timestamp_unit`, а строка RFC 3339 с поясом
+    /// (`2021-10-01T03:00:00+03:00`) — это уже точный момент, единицы на нее не влияют.
+    pub(crate) fn parse_timestamp(
+        &mut self,
+        tx_id: u64,
+        field: &str,
+        options: &ParseOptions,
+    ) -> Result<u64> {
+        match field.parse::<u64>() {
+            Ok(raw) => self.normalize_timestamp(tx_id, raw, options),
+            Err(_) if field.find('-').is_some_and(|dash| dash >= 4) => parse_rfc3339(field),
+            Err(e) => Err(ParseError::InvalidField {
+                field: "TIMESTAMP".to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    /// Проверяет прочитанную запись правилами `options.validation`, если они заданы
+    pub(crate) fn check_rules(&mut self, operation: &Operation, options: &ParseOptions) {
+        let Some(rules) = &options.validation else {
+            return;
+        };
+        if let Err(report) = operation.validate_with(rules) {
+            self.warnings
+                .extend(report.issues.iter().map(|issue| ParseWarning {
+                    tx_id: operation.tx_id,
+                    message: issue.to_string(),
+                }));
+        }
+    }
+
+    /// Приводит TIMESTAMP из CSV/текста к миллисекундам по `options.timestamp_unit`
+    pub(crate) fn normalize_timestamp(
+        &mut self,
+        tx_id: u64,
+        raw: u64,
+        options: &ParseOptions,
+    ) -> Result<u64> {
+        let (millis, rescaled) = options.timestamp_unit.normalize(raw)?;
+        if rescaled {
+            self.warnings.push(ParseWarning {
+                tx_id,
+                message: format!(
+                    "TIMESTAMP {} is below {} and was treated as seconds",
+                    raw,
+                    TimestampUnit::AUTO_THRESHOLD
+                ),
+            });
+        }
+        Ok(millis)
+    }
+}
+
+/// Сколько `count` приходится на секунду `duration`
+pub(crate) fn per_sec(count: usize, duration: Duration) -> f64 {
+    let secs = duration.as_secs_f64();
+    if secs > 0.0 { count as f64 / secs } else { 0.0 }
+}
+
+/// Замер одного потокового разбора: время, прочитанные байты и приемник метрик
+///
+/// Без приемника на запись тратится только увеличение счетчика.
+pub(crate) struct Meter {
+    #[cfg(feature = "metrics")]
+    format: &'static str,
+    started: Instant,
+    bytes: Cell<u64>,
+    records: Cell<u64>,
+    #[cfg(feature = "metrics")]
+    sink: Option<(std::sync::Arc<dyn MetricsSink>, AllocationMark)>,
+}
+
+impl Meter {
+    pub(crate) fn start(format: &'static str, options: &ParseOptions) -> Self {
+        #[cfg(not(feature = "metrics"))]
+        let _ = (format, options);
+        Meter {
+            #[cfg(feature = "metrics")]
+            format,
+            started: Instant::now(),
+            bytes: Cell::new(0),
+            records: Cell::new(0),
+            #[cfg(feature = "metrics")]
+            sink: options
+                .metrics
+                .clone()
+                .map(|sink| (sink, AllocationMark::now())),
+        }
+    }
+
+    /// Оборачивает поток, чтобы считать прочитанные байты
+    pub(crate) fn reader<R: Read>(&self, inner: R) -> CountingReader<'_, R> {
+        CountingReader::new(inner, &self.bytes)
+    }
+
+    /// Граница записи
+    pub(crate) fn record(&self) {
+        self.records.set(self.records.get() + 1);
+        #[cfg(feature = "metrics")]
+        if let Some((sink, mark)) = &self.sink {
+            sink.on_record(&self.snapshot(mark));
+        }
+    }
+
+    /// Дописывает в отчет время и байты и отдает итог приемнику
+    pub(crate) fn finish(self, mut report: ParseReport) -> ParseReport {
+        report.bytes = self.bytes.get();
+        report.duration = self.started.elapsed();
+        #[cfg(feature = "metrics")]
+        if let Some((sink, mark)) = &self.sink {
+            sink.on_finish(&self.snapshot(mark));
+        }
+        report
+    }
+
+    #[cfg(feature = "metrics")]
+    fn snapshot(&self, mark: &AllocationMark) -> MetricsSnapshot {
+        let (allocations, allocated_bytes) = mark.since();
+        MetricsSnapshot {
+            format: self.format,
+            records: self.records.get(),
+            bytes: self.bytes.get(),
+            elapsed: self.started.elapsed(),
+            allocations,
+            allocated_bytes,
+        }
+    }
+}
+
+/// Поток, считающий прочитанные байты в [`Meter`]
+pub(crate) struct CountingReader<'a, R> {
+    inner: R,
+    bytes: &'a Cell<u64>,
+}
+
+impl<'a, R> CountingReader<'a, R> {
+    pub(crate) fn new(inner: R, bytes: &'a Cell<u64>) -> Self {
+        CountingReader { inner, bytes }
+    }
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.set(self.bytes.get() + read as u64);
+        Ok(read)
+    }
+}
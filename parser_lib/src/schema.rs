@@ -0,0 +1,129 @@
+//! JSON Schema операции
+//!
+//! Схема собирается из тех же перечислений и правил, что использует
+//! [`Operation::validate`](crate::Operation::validate), а выгруженная копия
+//! `schema/operation.schema.json` сверяется с ней тестом.
+//! Имена полей совпадают с заголовком CSV.
+
+use crate::operation::{OperationStatus, OperationType};
+
+/// Возвращает JSON Schema (draft 2020-12) объекта операции
+///
+/// Описывает имена полей, диапазоны целых, допустимые строки TX_TYPE/STATUS
+/// и правила по типам через `if/then`:
+/// * **DEPOSIT**: `FROM_USER_ID` равен 0
+/// * **WITHDRAWAL**: `TO_USER_ID` равен 0
+/// * **TRANSFER**: `FROM_USER_ID` и `TO_USER_ID` не равны 0
+pub fn json_schema() -> String {
+    let types: Vec<&str> = OperationType::ALL.iter().map(|t| t.as_str()).collect();
+    let statuses: Vec<&str> = OperationStatus::ALL.iter().map(|s| s.as_str()).collect();
+
+    format!(
+        r#"{{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "YPBank operation",
+  "type": "object",
+  "properties": {{
+    "TX_ID": {u64},
+    "TX_TYPE": {{ "type": "string", "enum": {types} }},
+    "FROM_USER_ID": {u64},
+    "TO_USER_ID": {u64},
+    "AMOUNT": {{
+      "description": "Amount in minor units",
+      "type": "integer",
+      "minimum": {i64_min},
+      "maximum": {i64_max}
+    }},
+    "TIMESTAMP": {{
+      "description": "Unix time in milliseconds",
+      "type": "integer",
+      "minimum": 0,
+      "maximum": {u64_max}
+    }},
+    "STATUS": {{ "type": "string", "enum": {statuses} }},
+    "DESCRIPTION": {{ "type": "string" }}
+  }},
+  "required": [
+    "TX_ID",
+    "TX_TYPE",
+    "FROM_USER_ID",
+    "TO_USER_ID",
+    "AMOUNT",
+    "TIMESTAMP",
+    "STATUS",
+    "DESCRIPTION"
+  ],
+  "additionalProperties": false,
+  "allOf": [
+    {{
+      "if": {{ "properties": {{ "TX_TYPE": {{ "const": "{deposit}" }} }} }},
+      "then": {{ "properties": {{ "FROM_USER_ID": {{ "const": 0 }} }} }}
+    }},
+    {{
+      "if": {{ "properties": {{ "TX_TYPE": {{ "const": "{withdrawal}" }} }} }},
+      "then": {{ "properties": {{ "TO_USER_ID": {{ "const": 0 }} }} }}
+    }},
+    {{
+      "if": {{ "properties": {{ "TX_TYPE": {{ "const": "{transfer}" }} }} }},
+      "then": {{
+        "properties": {{
+          "FROM_USER_ID": {{ "minimum": 1 }},
+          "TO_USER_ID": {{ "minimum": 1 }}
+        }}
+      }}
+    }}
+  ]
+}}
+"#,
+        u64 = format!(
+            r#"{{ "type": "integer", "minimum": 0, "maximum": {} }}"#,
+            u64::MAX
+        ),
+        u64_max = u64::MAX,
+        i64_min = i64::MIN,
+        i64_max = i64::MAX,
+        types = json_string_array(&types),
+        statuses = json_string_array(&statuses),
+        deposit = OperationType::Deposit.as_str(),
+        withdrawal = OperationType::Withdrawal.as_str(),
+        transfer = OperationType::Transfer.as_str(),
+    )
+}
+
+fn json_string_array(values: &[&str]) -> String {
+    let quoted: Vec<String> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_in_schema_is_up_to_date() {
+        let checked_in = include_str!("../schema/operation.schema.json");
+        assert_eq!(
+            checked_in,
+            json_schema(),
+            "schema/operation.schema.json is stale, regenerate it from parser::json_schema()"
+        );
+    }
+
+    #[test]
+    fn test_schema_lists_every_enum_value() {
+        let schema = json_schema();
+        for tx_type in OperationType::ALL {
+            assert!(schema.contains(&format!("\"{}\"", tx_type.as_str())));
+        }
+        for status in OperationStatus::ALL {
+            assert!(schema.contains(&format!("\"{}\"", status.as_str())));
+        }
+    }
+
+    #[test]
+    fn test_schema_braces_are_balanced() {
+        let schema = json_schema();
+        assert_eq!(schema.matches('{').count(), schema.matches('}').count());
+        assert_eq!(schema.matches('[').count(), schema.matches(']').count());
+    }
+}
@@ -0,0 +1,1166 @@
+//! Проверки и сводки по набору операций
+//!
+//! В отличие от разбора в `HashSet`, здесь работаем со срезом в порядке
+//! записей: один TX_ID может встречаться несколько раз (файлы исправлений).
+
+use crate::error::{ParseError, Result};
+use crate::ledger::Ledger;
+use crate::operation::{FullOperation, Operation, OperationStatus, OperationType};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Range;
+
+pub(crate) const BALANCES_HEADER: &str = "USER_ID,BALANCE";
+
+/// Недопустимая смена статуса одной транзакции
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionViolation {
+    /// Идентификатор транзакции
+    pub tx_id: u64,
+    /// Статус предыдущей записи
+    pub from: OperationStatus,
+    /// Статус следующей записи
+    pub to: OperationStatus,
+    /// TIMESTAMP предыдущей записи
+    pub from_timestamp: u64,
+    /// TIMESTAMP следующей записи
+    pub to_timestamp: u64,
+}
+
+impl fmt::Display for TransitionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx_id {}: {} ({}) -> {} ({})",
+            self.tx_id,
+            self.from.as_str(),
+            self.from_timestamp,
+            self.to.as_str(),
+            self.to_timestamp
+        )
+    }
+}
+
+/// Разрешена ли смена статуса `from` -> `to`
+///
+/// PENDING может стать чем угодно, а SUCCESS и FAILURE окончательные:
+/// их можно только повторить тем же статусом.
+fn is_legal_transition(from: &OperationStatus, to: &OperationStatus) -> bool {
+    *from == OperationStatus::Pending || from == to
+}
+
+/// Проверяет смены статусов у повторяющихся TX_ID
+///
+/// # Аргументы
+/// * `ops_in_order` - Операции, упорядоченные по времени (например, из `parse_all_vec`)
+///
+/// # Возвращает
+/// Все недопустимые переходы в порядке их появления; каждая запись
+/// сравнивается с предыдущей записью того же TX_ID
+pub fn check_status_transitions(ops_in_order: &[Operation]) -> Vec<TransitionViolation> {
+    let mut last_seen: HashMap<u64, &Operation> = HashMap::new();
+    let mut violations = Vec::new();
+
+    for op in ops_in_order {
+        if let Some(prev) = last_seen.insert(op.tx_id, op)
+            && !is_legal_transition(&prev.status, &op.status)
+        {
+            violations.push(TransitionViolation {
+                tx_id: op.tx_id,
+                from: prev.status.clone(),
+                to: op.status.clone(),
+                from_timestamp: prev.timestamp,
+                to_timestamp: op.timestamp,
+            });
+        }
+    }
+
+    violations
+}
+
+/// Оставляет последнее состояние каждой транзакции
+///
+/// Последним считается запись с наибольшим TIMESTAMP, при равенстве —
+/// встретившаяся позже. Результат можно грузить туда, где операции
+/// уникальны по TX_ID.
+pub fn resolve_final_states(ops: &[Operation]) -> HashMap<u64, Operation> {
+    let mut states: HashMap<u64, Operation> = HashMap::new();
+
+    for op in ops {
+        match states.get(&op.tx_id) {
+            Some(current) if current.timestamp > op.timestamp => {}
+            _ => {
+                states.insert(op.tx_id, op.clone());
+            }
+        }
+    }
+
+    states
+}
+
+/// Критерии поиска подозрительных повторов
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Window {
+    /// Наибольший разрыв по TIMESTAMP между соседними операциями группы (мс)
+    pub max_time_gap_ms: u64,
+    /// Требовать совпадения AMOUNT
+    pub same_amount: bool,
+    /// Требовать совпадения FROM_USER_ID и TO_USER_ID
+    pub same_users: bool,
+}
+
+/// Ищет группы операций с одинаковым описанием, близких по времени
+///
+/// Операции сортируются по (описание, сумма, пользователи, время), после чего
+/// группой считается цепочка соседей, у которых разрыв по времени не больше
+/// `window.max_time_gap_ms`. Так выходит O(n log n) вместо попарного сравнения.
+/// Пустые описания не группируются: они ничего не говорят о повторе запроса.
+///
+/// # Возвращает
+/// Группы TX_ID (от двух операций) по времени внутри группы;
+/// сами группы упорядочены по времени первой операции
+pub fn find_suspicious_duplicates<'a>(
+    ops: impl IntoIterator<Item = &'a Operation>,
+    window: Window,
+) -> Vec<Vec<u64>> {
+    let key = |op: &'a Operation| {
+        (
+            op.description.as_str(),
+            if window.same_amount { op.amount } else { 0 },
+            if window.same_users {
+                (op.from_user_id, op.to_user_id)
+            } else {
+                (0, 0)
+            },
+        )
+    };
+
+    let mut sorted: Vec<&Operation> = ops
+        .into_iter()
+        .filter(|op| !op.description.is_empty())
+        .collect();
+    sorted.sort_by(|a, b| {
+        key(a)
+            .cmp(&key(b))
+            .then(a.timestamp.cmp(&b.timestamp))
+            .then(a.tx_id.cmp(&b.tx_id))
+    });
+
+    let mut groups: Vec<Vec<&Operation>> = Vec::new();
+    let mut current: Vec<&Operation> = Vec::new();
+    for op in sorted {
+        let continues = current.last().is_some_and(|prev| {
+            key(prev) == key(op) && op.timestamp - prev.timestamp <= window.max_time_gap_ms
+        });
+        if !continues && current.len() > 1 {
+            groups.push(std::mem::take(&mut current));
+        } else if !continues {
+            current.clear();
+        }
+        current.push(op);
+    }
+    if current.len() > 1 {
+        groups.push(current);
+    }
+
+    groups.sort_by_key(|group| (group[0].timestamp, group[0].tx_id));
+    groups
+        .into_iter()
+        .map(|group| group.into_iter().map(|op| op.tx_id).collect())
+        .collect()
+}
+
+/// Запись, которая целиком (по всем полям) встречается больше одного раза
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatedRecord {
+    /// Сама запись
+    pub operation: Operation,
+    /// Сколько раз она встретилась
+    pub count: u64,
+    /// Номер первого появления с 0, в порядке входа
+    pub first: usize,
+    /// Номер последнего появления с 0
+    pub last: usize,
+}
+
+/// Точные повторы записей: воспроизведенный трафик, двойная выгрузка
+///
+/// В отличие от [`find_suspicious_duplicates`], записи совпадают по всем
+/// полям ([`FullOperation`]). Памяти нужно по копии каждой различной записи:
+/// для большого файла почти без повторов — примерно как весь файл в `Vec`.
+///
+/// # Возвращает
+/// Только записи, встреченные больше одного раза, по первому появлению
+pub fn duplicate_report<'a>(ops: impl IntoIterator<Item = &'a Operation>) -> Vec<RepeatedRecord> {
+    let mut seen: HashMap<FullOperation, RepeatedRecord> = HashMap::new();
+    for (index, op) in ops.into_iter().enumerate() {
+        seen.entry(FullOperation(op.clone()))
+            .and_modify(|record| {
+                record.count += 1;
+                record.last = index;
+            })
+            .or_insert_with(|| RepeatedRecord {
+                operation: op.clone(),
+                count: 1,
+                first: index,
+                last: index,
+            });
+    }
+
+    let mut repeated: Vec<RepeatedRecord> = seen
+        .into_values()
+        .filter(|record| record.count > 1)
+        .collect();
+    repeated.sort_by_key(|record| record.first);
+    repeated
+}
+
+/// Настройки [`find_gaps`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GapOptions {
+    /// С какого TX_ID нумерация должна идти без пропусков; `None` — с наименьшего
+    /// в наборе. TX_ID меньше этого значения не проверяются и не считаются.
+    pub expect_contiguous_from: Option<u64>,
+}
+
+/// Пропуски в нумерации TX_ID и сводка по ним
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapReport {
+    /// Недостающие TX_ID полуинтервалами `start..end`, по возрастанию
+    pub gaps: Vec<Range<u64>>,
+    /// Сколько TX_ID должно быть от начала нумерации до наибольшего
+    pub expected: u64,
+    /// Сколько различных TX_ID из этого диапазона есть в наборе
+    pub present: u64,
+}
+
+impl GapReport {
+    /// Сколько TX_ID пропущено
+    pub fn missing(&self) -> u64 {
+        self.expected - self.present
+    }
+}
+
+/// Пропущенные TX_ID в последовательной нумерации
+///
+/// Повторы TX_ID допустимы. Пустой набор пропусков не дает: без наибольшего
+/// TX_ID неизвестно, где нумерация кончается.
+pub fn find_gaps<'a>(
+    ops: impl IntoIterator<Item = &'a Operation>,
+    options: GapOptions,
+) -> Vec<Range<u64>> {
+    find_gaps_in_ids(ops.into_iter().map(|op| op.tx_id), options).gaps
+}
+
+/// То же, что [`find_gaps`], но по голым TX_ID и со сводкой
+///
+/// Держит в памяти только сами TX_ID (8 байт на запись), поэтому годится
+/// для потокового разбора: например, собирать их из [`crate::Format::for_each_record`].
+pub fn find_gaps_in_ids(ids: impl IntoIterator<Item = u64>, options: GapOptions) -> GapReport {
+    let mut ids: Vec<u64> = ids.into_iter().collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let start = match options.expect_contiguous_from {
+        Some(start) => {
+            ids.retain(|&id| id >= start);
+            start
+        }
+        None => ids.first().copied().unwrap_or_default(),
+    };
+    let Some(&last) = ids.last() else {
+        return GapReport {
+            gaps: Vec::new(),
+            expected: 0,
+            present: 0,
+        };
+    };
+
+    let mut gaps = Vec::new();
+    let mut next = start;
+    for &id in &ids {
+        if id > next {
+            gaps.push(next..id);
+        }
+        next = id.saturating_add(1);
+    }
+
+    GapReport {
+        gaps,
+        // Весь диапазон u64 не помещается в u64; такой набор все равно не собрать
+        expected: (last - start).saturating_add(1),
+        present: ids.len() as u64,
+    }
+}
+
+/// Балансы пользователей по успешным операциям
+///
+/// То же, что [`Ledger::balances`]: DEPOSIT зачисляет TO_USER_ID, WITHDRAWAL
+/// списывает с FROM_USER_ID, TRANSFER делает и то и другое, пользователь 0 —
+/// внешняя сторона. Суммы в i128, так что переполнения нет на любом файле.
+pub fn compute_balances<'a>(ops: impl IntoIterator<Item = &'a Operation>) -> HashMap<u64, i128> {
+    Ledger::from_operations(ops).balances().clone()
+}
+
+/// Настройки CSV с балансами
+#[derive(Debug, Clone)]
+pub struct BalanceCsvOptions {
+    /// Писать (и ожидать при чтении) строку `USER_ID,BALANCE`
+    pub header: bool,
+    /// Сколько младших единиц в одной старшей, в разрядах: `Some(2)` пишет
+    /// 12345 как `123.45`; `None` — целое число как есть
+    pub decimal_places: Option<u32>,
+}
+
+impl Default for BalanceCsvOptions {
+    fn default() -> Self {
+        Self {
+            header: true,
+            decimal_places: None,
+        }
+    }
+}
+
+/// Пишет балансы в CSV `USER_ID,BALANCE`, по возрастанию USER_ID
+pub fn write_balances_csv<W: Write>(
+    mut writer: W,
+    balances: &HashMap<u64, i128>,
+    options: BalanceCsvOptions,
+) -> Result<()> {
+    if options.header {
+        writeln!(writer, "{}", BALANCES_HEADER)?;
+    }
+
+    let sorted: BTreeMap<u64, i128> = balances.iter().map(|(&k, &v)| (k, v)).collect();
+    for (user_id, balance) in sorted {
+        writeln!(
+            writer,
+            "{},{}",
+            user_id,
+            format_amount(balance, options.decimal_places)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Читает CSV, записанный [`write_balances_csv`] с теми же настройками
+pub fn read_balances_csv<R: Read>(
+    reader: R,
+    options: BalanceCsvOptions,
+) -> Result<HashMap<u64, i128>> {
+    let mut balances = HashMap::new();
+
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_num = i + 1;
+
+        if i == 0 && options.header {
+            if line.trim() != BALANCES_HEADER {
+                return Err(ParseError::InvalidFormat(format!(
+                    "Invalid balances header. Expected: {}",
+                    BALANCES_HEADER
+                )));
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (user_id, balance) = line.split_once(',').ok_or_else(|| {
+            ParseError::InvalidFormat(format!("Line {}: expected USER_ID,BALANCE", line_num))
+        })?;
+        let user_id: u64 = user_id
+            .trim()
+            .parse()
+            .map_err(|_| ParseError::InvalidField {
+                field: "USER_ID".to_string(),
+                reason: format!("Line {}: invalid number", line_num),
+            })?;
+        let balance = parse_amount(balance.trim(), options.decimal_places).map_err(|reason| {
+            ParseError::InvalidField {
+                field: "BALANCE".to_string(),
+                reason: format!("Line {}: {}", line_num, reason),
+            }
+        })?;
+
+        if balances.insert(user_id, balance).is_some() {
+            return Err(ParseError::InvalidFormat(format!(
+                "Line {}: duplicate USER_ID {}",
+                line_num, user_id
+            )));
+        }
+    }
+
+    Ok(balances)
+}
+
+/// Расхождение баланса одного пользователя
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceDelta {
+    /// Пользователь
+    pub user_id: u64,
+    /// Баланс в снимке (0, если пользователя там нет)
+    pub expected: i128,
+    /// Свежепосчитанный баланс (0, если пользователя нет)
+    pub actual: i128,
+    /// `actual - expected`
+    pub delta: i128,
+}
+
+/// Сравнивает снимок балансов со свежепосчитанными
+///
+/// # Возвращает
+/// Только пользователей с ненулевой разницей, по возрастанию USER_ID
+pub fn compare_balances(
+    expected: &HashMap<u64, i128>,
+    actual: &HashMap<u64, i128>,
+) -> Vec<BalanceDelta> {
+    let mut users: Vec<u64> = expected.keys().chain(actual.keys()).copied().collect();
+    users.sort_unstable();
+    users.dedup();
+
+    users
+        .into_iter()
+        .filter_map(|user_id| {
+            let expected = expected.get(&user_id).copied().unwrap_or(0);
+            let actual = actual.get(&user_id).copied().unwrap_or(0);
+            let delta = actual.saturating_sub(expected);
+            (expected != actual).then_some(BalanceDelta {
+                user_id,
+                expected,
+                actual,
+                delta,
+            })
+        })
+        .collect()
+}
+
+/// Как считать квантили в [`amount_distribution_with_options`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuantileMode {
+    /// Точно, сортировкой всех сумм
+    #[default]
+    Exact,
+    /// Приблизительно, по равномерной выборке не больше `sample_size` сумм на тип
+    ///
+    /// Память не растет с размером входа; COUNT/MIN/MAX/MEAN и корзины остаются точными.
+    Approximate { sample_size: usize },
+}
+
+/// Настройки [`amount_distribution_with_options`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DistributionOptions {
+    /// Точные или приблизительные квантили
+    pub quantiles: QuantileMode,
+}
+
+/// Сводка по суммам одного типа операций
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountSummary {
+    /// Число операций
+    pub count: u64,
+    pub min: i64,
+    pub max: i64,
+    /// Среднее (точное при любом [`QuantileMode`])
+    pub mean: f64,
+    /// Квантили по методу ближайшего ранга
+    pub median: i64,
+    pub p95: i64,
+    pub p99: i64,
+}
+
+/// Распределение сумм одного типа операций
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDistribution {
+    /// Тип операций
+    pub tx_type: OperationType,
+    /// `None`, если операций этого типа нет
+    pub summary: Option<AmountSummary>,
+    /// Счетчики корзин в порядке [`Distribution::bucket_labels`]
+    pub buckets: Vec<u64>,
+}
+
+/// Итог [`amount_distribution`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    /// Границы корзин по возрастанию
+    pub edges: Vec<i64>,
+    /// По одной записи на каждый [`OperationType`], даже если операций нет
+    pub by_type: Vec<TypeDistribution>,
+}
+
+impl Distribution {
+    /// Подписи корзин: `underflow <e0`, `e0..e1`, ..., `overflow >=en`
+    ///
+    /// Нижняя граница корзины входит в нее, верхняя — нет.
+    pub fn bucket_labels(&self) -> Vec<String> {
+        let (Some(first), Some(last)) = (self.edges.first(), self.edges.last()) else {
+            return Vec::new();
+        };
+
+        let mut labels = vec![format!("underflow <{}", first)];
+        labels.extend(
+            self.edges
+                .windows(2)
+                .map(|pair| format!("{}..{}", pair[0], pair[1])),
+        );
+        labels.push(format!("overflow >={}", last));
+        labels
+    }
+
+    /// Пишет распределение в CSV: строка на тип, сводка и счетчики корзин
+    ///
+    /// Для типа без операций поля сводки пустые, COUNT равен 0.
+    pub fn to_csv<W: Write>(&self, mut writer: W) -> Result<()> {
+        let labels = self.bucket_labels();
+        writeln!(
+            writer,
+            "TX_TYPE,COUNT,MIN,MAX,MEAN,MEDIAN,P95,P99,{}",
+            labels.join(",")
+        )?;
+
+        for row in &self.by_type {
+            let summary = match &row.summary {
+                Some(s) => format!(
+                    "{},{},{},{:.2},{},{},{}",
+                    s.count, s.min, s.max, s.mean, s.median, s.p95, s.p99
+                ),
+                None => "0,,,,,,".to_string(),
+            };
+            let buckets: Vec<String> = row.buckets.iter().map(|c| c.to_string()).collect();
+            writeln!(
+                writer,
+                "{},{},{}",
+                row.tx_type.as_str(),
+                summary,
+                buckets.join(",")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Распределение в виде JSON (фича `serde`)
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let labels = self.bucket_labels();
+        let by_type: Vec<serde_json::Value> = self
+            .by_type
+            .iter()
+            .map(|row| {
+                let buckets: Vec<serde_json::Value> = labels
+                    .iter()
+                    .zip(&row.buckets)
+                    .map(|(label, count)| serde_json::json!({ "label": label, "count": count }))
+                    .collect();
+                let summary = row.summary.map(|s| {
+                    serde_json::json!({
+                        "count": s.count,
+                        "min": s.min,
+                        "max": s.max,
+                        "mean": s.mean,
+                        "median": s.median,
+                        "p95": s.p95,
+                        "p99": s.p99,
+                    })
+                });
+                serde_json::json!({
+                    "tx_type": row.tx_type.as_str(),
+                    "summary": summary,
+                    "buckets": buckets,
+                })
+            })
+            .collect();
+
+        let value = serde_json::json!({ "edges": self.edges, "by_type": by_type });
+        serde_json::to_string_pretty(&value).expect("json! value always serializes")
+    }
+}
+
+/// Распределение сумм по типам операций с точными квантилями
+///
+/// # Аргументы
+/// * `ops` - Операции
+/// * `buckets` - Границы корзин по строгому возрастанию, например `[0, 1000, 10000]`
+///
+/// Суммы меньше первой границы (в том числе отрицательные) попадают в корзину
+/// `underflow`, не меньше последней — в `overflow`.
+pub fn amount_distribution<'a>(
+    ops: impl IntoIterator<Item = &'a Operation>,
+    buckets: &[i64],
+) -> Result<Distribution> {
+    amount_distribution_with_options(ops, buckets, DistributionOptions::default())
+}
+
+/// То же, что [`amount_distribution`], но с настройками (например, приблизительными квантилями)
+pub fn amount_distribution_with_options<'a>(
+    ops: impl IntoIterator<Item = &'a Operation>,
+    buckets: &[i64],
+    options: DistributionOptions,
+) -> Result<Distribution> {
+    if buckets.is_empty() || buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(ParseError::InvalidField {
+            field: "buckets".to_string(),
+            reason: "bucket edges must be non-empty and strictly increasing".to_string(),
+        });
+    }
+
+    let types = [
+        OperationType::Deposit,
+        OperationType::Transfer,
+        OperationType::Withdrawal,
+    ];
+    let mut accumulators: Vec<AmountAccumulator> = types
+        .iter()
+        .map(|_| AmountAccumulator::new(buckets.len() + 1, options.quantiles))
+        .collect();
+
+    for op in ops {
+        let index = types.iter().position(|t| *t == op.tx_type).unwrap_or(0);
+        accumulators[index].add(op.amount, buckets);
+    }
+
+    Ok(Distribution {
+        edges: buckets.to_vec(),
+        by_type: types
+            .into_iter()
+            .zip(accumulators)
+            .map(|(tx_type, acc)| acc.finish(tx_type))
+            .collect(),
+    })
+}
+
+struct AmountAccumulator {
+    mode: QuantileMode,
+    count: u64,
+    min: i64,
+    max: i64,
+    sum: i128,
+    samples: Vec<i64>,
+    buckets: Vec<u64>,
+    // Детерминированный xorshift для выборки: одинаковый вход дает одинаковый результат
+    rng: u64,
+}
+
+impl AmountAccumulator {
+    fn new(buckets: usize, mode: QuantileMode) -> Self {
+        AmountAccumulator {
+            mode,
+            count: 0,
+            min: i64::MAX,
+            max: i64::MIN,
+            sum: 0,
+            samples: Vec::new(),
+            buckets: vec![0; buckets],
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn add(&mut self, amount: i64, edges: &[i64]) {
+        self.count += 1;
+        self.min = self.min.min(amount);
+        self.max = self.max.max(amount);
+        self.sum += amount as i128;
+        self.buckets[edges.partition_point(|&edge| edge <= amount)] += 1;
+
+        match self.mode {
+            QuantileMode::Exact => self.samples.push(amount),
+            QuantileMode::Approximate { sample_size } => {
+                // Reservoir sampling (алгоритм R)
+                let sample_size = sample_size.max(1);
+                if self.samples.len() < sample_size {
+                    self.samples.push(amount);
+                } else {
+                    self.rng ^= self.rng << 13;
+                    self.rng ^= self.rng >> 7;
+                    self.rng ^= self.rng << 17;
+                    let slot = (self.rng % self.count) as usize;
+                    if slot < sample_size {
+                        self.samples[slot] = amount;
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(mut self, tx_type: OperationType) -> TypeDistribution {
+        self.samples.sort_unstable();
+        let summary = (self.count > 0).then(|| AmountSummary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.sum as f64 / self.count as f64,
+            median: nearest_rank(&self.samples, 50),
+            p95: nearest_rank(&self.samples, 95),
+            p99: nearest_rank(&self.samples, 99),
+        });
+
+        TypeDistribution {
+            tx_type,
+            summary,
+            buckets: self.buckets,
+        }
+    }
+}
+
+/// Перцентиль по методу ближайшего ранга; `sorted` не пустой
+fn nearest_rank(sorted: &[i64], percent: usize) -> i64 {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+pub(crate) fn format_amount(amount: impl Into<i128>, decimal_places: Option<u32>) -> String {
+    let amount: i128 = amount.into();
+    let places = match decimal_places {
+        Some(places) if places > 0 => places,
+        _ => return amount.to_string(),
+    };
+
+    let scale = 10u128.pow(places);
+    let abs = amount.unsigned_abs();
+    let sign = if amount < 0 { "-" } else { "" };
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        abs / scale,
+        abs % scale,
+        width = places as usize
+    )
+}
+
+fn parse_amount(s: &str, decimal_places: Option<u32>) -> std::result::Result<i128, String> {
+    let places = decimal_places.unwrap_or(0);
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid amount '{}'", s));
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) || fraction.len() > places as usize {
+        return Err(format!(
+            "invalid amount '{}' (at most {} decimal places)",
+            s, places
+        ));
+    }
+
+    let padded = format!("{}{:0<width$}", whole, fraction, width = places as usize);
+    let magnitude: i128 = padded
+        .parse()
+        .map_err(|_| format!("amount '{}' is out of range", s))?;
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationType;
+
+    fn op(tx_id: u64, status: OperationStatus, timestamp: u64) -> Operation {
+        Operation::deposit(1, 100)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(status)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_legal_transitions() {
+        let ops = vec![
+            op(1, OperationStatus::Pending, 10),
+            op(2, OperationStatus::Pending, 11),
+            op(1, OperationStatus::Success, 20),
+            op(2, OperationStatus::Failure, 21),
+            op(1, OperationStatus::Success, 30),
+        ];
+
+        assert!(check_status_transitions(&ops).is_empty());
+    }
+
+    #[test]
+    fn test_illegal_transitions_report_both_timestamps() {
+        let ops = vec![
+            op(1, OperationStatus::Success, 10),
+            op(1, OperationStatus::Pending, 20),
+            op(2, OperationStatus::Failure, 30),
+            op(2, OperationStatus::Success, 40),
+        ];
+
+        let violations = check_status_transitions(&ops);
+        assert_eq!(
+            violations,
+            vec![
+                TransitionViolation {
+                    tx_id: 1,
+                    from: OperationStatus::Success,
+                    to: OperationStatus::Pending,
+                    from_timestamp: 10,
+                    to_timestamp: 20,
+                },
+                TransitionViolation {
+                    tx_id: 2,
+                    from: OperationStatus::Failure,
+                    to: OperationStatus::Success,
+                    from_timestamp: 30,
+                    to_timestamp: 40,
+                },
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "tx_id 1: SUCCESS (10) -> PENDING (20)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_final_states_keeps_latest() {
+        let ops = vec![
+            op(1, OperationStatus::Pending, 10),
+            op(1, OperationStatus::Success, 20),
+            op(2, OperationStatus::Failure, 50),
+            op(2, OperationStatus::Pending, 40),
+            op(3, OperationStatus::Pending, 60),
+            op(3, OperationStatus::Failure, 60),
+        ];
+
+        let states = resolve_final_states(&ops);
+        assert_eq!(states.len(), 3);
+        assert_eq!(states[&1].status, OperationStatus::Success);
+        assert_eq!(states[&2].status, OperationStatus::Failure);
+        assert_eq!(states[&3].status, OperationStatus::Failure);
+    }
+
+    #[test]
+    fn test_compute_balances() {
+        let mut transfer = op(2, OperationStatus::Success, 20);
+        transfer.tx_type = OperationType::Transfer;
+        transfer.from_user_id = 1;
+        transfer.to_user_id = 2;
+        transfer.amount = 30;
+        let mut failed = op(3, OperationStatus::Failure, 30);
+        failed.amount = 1000;
+
+        let balances = compute_balances(&[op(1, OperationStatus::Success, 10), transfer, failed]);
+        assert_eq!(balances, HashMap::from([(1, 70), (2, 30)]));
+    }
+
+    #[test]
+    fn test_compute_balances_does_not_overflow() {
+        let mut big = op(1, OperationStatus::Success, 10);
+        big.amount = i64::MAX;
+        let mut bigger = big.clone();
+        bigger.tx_id = 2;
+
+        let balances = compute_balances(&[big, bigger]);
+        assert_eq!(balances[&1], 2 * i128::from(i64::MAX));
+    }
+
+    #[test]
+    fn test_balances_csv_round_trip() {
+        let balances = HashMap::from([(3, -5), (1, 12345), (2, 0), (4, 3 * i128::from(i64::MIN))]);
+
+        for options in [
+            BalanceCsvOptions::default(),
+            BalanceCsvOptions {
+                header: false,
+                decimal_places: Some(2),
+            },
+        ] {
+            let mut buf = Vec::new();
+            write_balances_csv(&mut buf, &balances, options.clone()).unwrap();
+            let parsed = read_balances_csv(buf.as_slice(), options).unwrap();
+            assert_eq!(parsed, balances);
+        }
+    }
+
+    #[test]
+    fn test_balances_csv_is_sorted_with_decimals() {
+        let balances = HashMap::from([(3, -5), (1, 12345), (2, 100)]);
+        let mut buf = Vec::new();
+        let options = BalanceCsvOptions {
+            header: true,
+            decimal_places: Some(2),
+        };
+        write_balances_csv(&mut buf, &balances, options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "USER_ID,BALANCE\n1,123.45\n2,1.00\n3,-0.05\n"
+        );
+    }
+
+    #[test]
+    fn test_read_balances_rejects_bad_rows() {
+        let options = BalanceCsvOptions::default;
+        assert!(read_balances_csv("USER,BAL\n".as_bytes(), options()).is_err());
+        assert!(read_balances_csv("USER_ID,BALANCE\n1,1.5\n".as_bytes(), options()).is_err());
+        assert!(read_balances_csv("USER_ID,BALANCE\n1,1\n1,2\n".as_bytes(), options()).is_err());
+    }
+
+    #[test]
+    fn test_compare_balances() {
+        let snapshot = HashMap::from([(1, 100), (2, 50), (3, 7)]);
+        let current = HashMap::from([(1, 100), (2, 80), (4, -1)]);
+
+        let deltas = compare_balances(&snapshot, &current);
+        assert_eq!(
+            deltas,
+            vec![
+                BalanceDelta {
+                    user_id: 2,
+                    expected: 50,
+                    actual: 80,
+                    delta: 30
+                },
+                BalanceDelta {
+                    user_id: 3,
+                    expected: 7,
+                    actual: 0,
+                    delta: -7
+                },
+                BalanceDelta {
+                    user_id: 4,
+                    expected: 0,
+                    actual: -1,
+                    delta: -1
+                },
+            ]
+        );
+    }
+
+    fn payment(tx_id: u64, description: &str, amount: i64, timestamp: u64) -> Operation {
+        Operation::transfer(1, 2, amount)
+            .tx_id(tx_id)
+            .timestamp(timestamp)
+            .status(OperationStatus::Success)
+            .description(description)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_find_suspicious_duplicates_among_noise() {
+        let mut ops = Vec::new();
+        // Шум: уникальные описания
+        for i in 0..50 {
+            ops.push(payment(1000 + i, &format!("noise {}", i), 100, i * 10));
+        }
+        // Повтор запроса: три раза за секунду
+        ops.push(payment(1, "Invoice 42", 500, 10_000));
+        ops.push(payment(2, "Invoice 42", 500, 10_400));
+        ops.push(payment(3, "Invoice 42", 500, 10_900));
+        // То же описание, но через час — не группа
+        ops.push(payment(4, "Invoice 42", 500, 3_610_000));
+        // Та же пара с другой суммой
+        ops.push(payment(5, "Invoice 42", 700, 10_100));
+        // Вторая группа, раньше первой
+        ops.push(payment(6, "Refund", 10, 5_000));
+        ops.push(payment(7, "Refund", 10, 5_000));
+        // Пустые описания не считаются
+        ops.push(payment(8, "", 10, 5_000));
+        ops.push(payment(9, "", 10, 5_000));
+
+        let window = Window {
+            max_time_gap_ms: 1000,
+            same_amount: true,
+            same_users: true,
+        };
+        assert_eq!(
+            find_suspicious_duplicates(&ops, window),
+            vec![vec![6, 7], vec![1, 2, 3]]
+        );
+
+        // Без требования той же суммы tx 5 встает в цепочку
+        let any_amount = Window {
+            same_amount: false,
+            ..window
+        };
+        assert_eq!(
+            find_suspicious_duplicates(&ops, any_amount),
+            vec![vec![6, 7], vec![1, 5, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn test_find_suspicious_duplicates_same_users() {
+        let mut other_users = payment(2, "Invoice", 500, 100);
+        other_users.to_user_id = 3;
+        let ops = [payment(1, "Invoice", 500, 0), other_users];
+
+        let strict = Window {
+            max_time_gap_ms: 1000,
+            same_amount: true,
+            same_users: true,
+        };
+        assert!(find_suspicious_duplicates(&ops, strict).is_empty());
+
+        let loose = Window {
+            same_users: false,
+            ..strict
+        };
+        assert_eq!(find_suspicious_duplicates(&ops, loose), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_duplicate_report_counts_exact_repeats() {
+        let original = payment(1, "Invoice", 500, 0);
+        let mut corrected = original.clone();
+        corrected.amount = 600;
+        let ops = vec![
+            payment(9, "Other", 1, 0),
+            original.clone(),
+            corrected.clone(),
+            original.clone(),
+            payment(10, "Other", 1, 0),
+            original.clone(),
+            corrected,
+        ];
+
+        let report = duplicate_report(&ops);
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(
+            (report[0].count, report[0].first, report[0].last),
+            (3, 1, 5)
+        );
+        assert_eq!(report[0].operation.amount, 500);
+        assert_eq!(
+            (report[1].count, report[1].first, report[1].last),
+            (2, 2, 6)
+        );
+        assert_eq!(report[1].operation.amount, 600);
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let ops: Vec<Operation> = [3, 4, 4, 7, 10, 11]
+            .into_iter()
+            .map(|id| payment(id, "", 1, 0))
+            .collect();
+
+        assert_eq!(find_gaps(&ops, GapOptions::default()), vec![5..7, 8..10]);
+
+        let from_one = GapOptions {
+            expect_contiguous_from: Some(1),
+        };
+        let report = find_gaps_in_ids(ops.iter().map(|op| op.tx_id), from_one);
+        assert_eq!(report.gaps, vec![1..3, 5..7, 8..10]);
+        assert_eq!(
+            (report.expected, report.present, report.missing()),
+            (11, 5, 6)
+        );
+
+        let from_five = GapOptions {
+            expect_contiguous_from: Some(5),
+        };
+        assert_eq!(find_gaps(&ops, from_five), vec![5..7, 8..10]);
+    }
+
+    #[test]
+    fn test_find_gaps_empty_and_edges() {
+        let empty = find_gaps_in_ids([], GapOptions::default());
+        assert_eq!(empty.gaps, Vec::<Range<u64>>::new());
+        assert_eq!((empty.expected, empty.present), (0, 0));
+        let from = GapOptions {
+            expect_contiguous_from: Some(100),
+        };
+        assert!(find_gaps_in_ids([1, 2], from).gaps.is_empty());
+
+        let report = find_gaps_in_ids([0, u64::MAX], GapOptions::default());
+        assert_eq!(report.gaps, vec![1..u64::MAX]);
+        assert_eq!(report.expected, u64::MAX);
+    }
+
+    #[test]
+    fn test_amount_distribution_exact() {
+        let mut ops: Vec<Operation> = (1..=100).map(|i| payment(i as u64, "", i, 0)).collect();
+        let mut refund = payment(101, "", -5, 0);
+        refund.tx_type = OperationType::Withdrawal;
+        ops.push(refund);
+
+        let distribution = amount_distribution(&ops, &[0, 10, 50]).unwrap();
+        assert_eq!(
+            distribution.bucket_labels(),
+            vec!["underflow <0", "0..10", "10..50", "overflow >=50"]
+        );
+
+        let transfers = &distribution.by_type[1];
+        assert_eq!(transfers.tx_type, OperationType::Transfer);
+        assert_eq!(
+            transfers.summary,
+            Some(AmountSummary {
+                count: 100,
+                min: 1,
+                max: 100,
+                mean: 50.5,
+                median: 50,
+                p95: 95,
+                p99: 99,
+            })
+        );
+        assert_eq!(transfers.buckets, vec![0, 9, 40, 51]);
+
+        // Отрицательная сумма не теряется, а попадает в underflow
+        let withdrawals = &distribution.by_type[2];
+        assert_eq!(withdrawals.buckets, vec![1, 0, 0, 0]);
+        assert_eq!(distribution.by_type[0].summary, None);
+
+        assert!(amount_distribution(&ops, &[]).is_err());
+        assert!(amount_distribution(&ops, &[10, 10]).is_err());
+    }
+
+    #[test]
+    fn test_amount_distribution_approximate() {
+        let ops: Vec<Operation> = (1..=10_000).map(|i| payment(i as u64, "", i, 0)).collect();
+        let options = DistributionOptions {
+            quantiles: QuantileMode::Approximate { sample_size: 500 },
+        };
+
+        let distribution = amount_distribution_with_options(&ops, &[5000], options).unwrap();
+        let summary = distribution.by_type[1].summary.unwrap();
+        assert_eq!(
+            (summary.count, summary.min, summary.max),
+            (10_000, 1, 10_000)
+        );
+        assert_eq!(summary.mean, 5000.5);
+        assert!(
+            (4000..=6000).contains(&summary.median),
+            "{}",
+            summary.median
+        );
+        assert!(summary.p99 >= 9000, "{}", summary.p99);
+        assert_eq!(distribution.by_type[1].buckets, vec![4999, 5001]);
+    }
+
+    #[test]
+    fn test_distribution_to_csv() {
+        let ops = [payment(1, "", 10, 0), payment(2, "", 2000, 0)];
+        let distribution = amount_distribution(&ops, &[0, 1000]).unwrap();
+
+        let mut buf = Vec::new();
+        distribution.to_csv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "TX_TYPE,COUNT,MIN,MAX,MEAN,MEDIAN,P95,P99,underflow <0,0..1000,overflow >=1000\n\
+             DEPOSIT,0,,,,,,,0,0,0\n\
+             TRANSFER,2,10,2000,1005.00,10,2000,2000,0,1,1\n\
+             WITHDRAWAL,0,,,,,,,0,0,0\n"
+        );
+
+        #[cfg(feature = "serde")]
+        {
+            let value: serde_json::Value = serde_json::from_str(&distribution.to_json()).unwrap();
+            assert_eq!(value["by_type"][1]["summary"]["median"], 10);
+            assert_eq!(
+                value["by_type"][1]["buckets"][2]["label"],
+                "overflow >=1000"
+            );
+            assert!(value["by_type"][0]["summary"].is_null());
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Сводка по партии операций: сколько, каких, на какую сумму и за какой период
+//!
+//! [`Summary`] собирается по одной операции, поэтому подходит и для потокового
+//! разбора ([`Summary::from_reader`], [`Format::for_each_record`]), и для
+//! параллельного: частичные сводки складываются через [`Summary::merge`].
+
+use crate::error::Result;
+use crate::format::Format;
+use crate::operation::{Operation, OperationStatus, OperationType};
+use crate::options::ParseOptions;
+use std::collections::HashSet;
+use std::io::Read;
+
+/// Итоги одного типа операций в [`Summary`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeTotals {
+    /// Тип операций
+    pub tx_type: OperationType,
+    /// Число операций
+    pub count: u64,
+    /// Сумма AMOUNT
+    pub total_amount: i128,
+}
+
+impl TypeTotals {
+    /// Средний AMOUNT; `None`, если операций этого типа нет
+    pub fn mean_amount(&self) -> Option<f64> {
+        mean(self.total_amount, self.count)
+    }
+}
+
+/// Сводка по операциям
+///
+/// Повторы TX_ID считаются каждый: это сводка по записям, а не по транзакциям.
+/// Суммы в i128, чтобы не переполниться ни на каком реальном файле.
+/// Памяти нужно по `u64` на каждого различного пользователя.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// Всего операций
+    pub operations: u64,
+    /// По одной записи на каждый [`OperationType::ALL`], даже если операций нет;
+    /// незнакомые типы ([`OperationType::Unknown`]) — следом, в порядке появления
+    pub by_type: Vec<TypeTotals>,
+    /// Число операций в каждом [`OperationStatus::ALL`], затем в незнакомых статусах
+    pub by_status: Vec<(OperationStatus, u64)>,
+    /// Сумма AMOUNT всех операций
+    pub total_amount: i128,
+    /// Наименьший и наибольший AMOUNT; `None`, если операций нет
+    pub min_amount: Option<i64>,
+    pub max_amount: Option<i64>,
+    /// Самый ранний и самый поздний TIMESTAMP; `None`, если операций нет
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    from_users: HashSet<u64>,
+    to_users: HashSet<u64>,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary::new()
+    }
+}
+
+impl Summary {
+    /// Пустая сводка
+    pub fn new() -> Self {
+        Summary {
+            operations: 0,
+            by_type: OperationType::ALL
+                .into_iter()
+                .map(|tx_type| TypeTotals {
+                    tx_type,
+                    count: 0,
+                    total_amount: 0,
+                })
+                .collect(),
+            by_status: OperationStatus::ALL
+                .into_iter()
+                .map(|status| (status, 0))
+                .collect(),
+            total_amount: 0,
+            min_amount: None,
+            max_amount: None,
+            first_timestamp: None,
+            last_timestamp: None,
+            from_users: HashSet::new(),
+            to_users: HashSet::new(),
+        }
+    }
+
+    /// Сводка по потоку без загрузки записей в память
+    ///
+    /// Первая же ошибка разбора прерывает чтение.
+    pub fn from_reader<R: Read>(reader: R, format: Format, options: &ParseOptions) -> Result<Self> {
+        let mut summary = Summary::new();
+        format.for_each_record(reader, options, |op, _| {
+            summary.add(&op);
+            Ok(())
+        })?;
+        Ok(summary)
+    }
+
+    /// Учитывает одну операцию
+    pub fn add(&mut self, op: &Operation) {
+        self.operations += 1;
+        let totals = self.type_totals(&op.tx_type);
+        totals.count += 1;
+        totals.total_amount += i128::from(op.amount);
+        *self.status_count(&op.status) += 1;
+        self.total_amount += i128::from(op.amount);
+        self.min_amount = min(self.min_amount, Some(op.amount));
+        self.max_amount = self.max_amount.max(Some(op.amount));
+        self.first_timestamp = min(self.first_timestamp, Some(op.timestamp));
+        self.last_timestamp = self.last_timestamp.max(Some(op.timestamp));
+        // Пользователь 0 — внешняя сторона, а не клиент
+        if op.from_user_id != 0 {
+            self.from_users.insert(op.from_user_id);
+        }
+        if op.to_user_id != 0 {
+            self.to_users.insert(op.to_user_id);
+        }
+    }
+
+    /// Добавляет сводку по другой части данных
+    ///
+    /// Результат тот же, что при подсчете обеих частей одной сводкой.
+    pub fn merge(&mut self, other: Summary) {
+        self.operations += other.operations;
+        for theirs in other.by_type {
+            let totals = self.type_totals(&theirs.tx_type);
+            totals.count += theirs.count;
+            totals.total_amount += theirs.total_amount;
+        }
+        for (status, theirs) in other.by_status {
+            *self.status_count(&status) += theirs;
+        }
+        self.total_amount += other.total_amount;
+        self.min_amount = min(self.min_amount, other.min_amount);
+        self.max_amount = self.max_amount.max(other.max_amount);
+        self.first_timestamp = min(self.first_timestamp, other.first_timestamp);
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+        self.from_users.extend(other.from_users);
+        self.to_users.extend(other.to_users);
+    }
+
+    /// Итоги типа; незнакомый тип получает новую запись в конце
+    fn type_totals(&mut self, tx_type: &OperationType) -> &mut TypeTotals {
+        match self.by_type.iter().position(|t| t.tx_type == *tx_type) {
+            Some(i) => &mut self.by_type[i],
+            None => {
+                self.by_type.push(TypeTotals {
+                    tx_type: tx_type.clone(),
+                    count: 0,
+                    total_amount: 0,
+                });
+                self.by_type.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Счетчик статуса; незнакомый статус получает новый счетчик в конце
+    fn status_count(&mut self, status: &OperationStatus) -> &mut u64 {
+        match self.by_status.iter().position(|(s, _)| s == status) {
+            Some(i) => &mut self.by_status[i].1,
+            None => {
+                self.by_status.push((status.clone(), 0));
+                &mut self.by_status.last_mut().unwrap().1
+            }
+        }
+    }
+
+    /// Средний AMOUNT всех операций; `None`, если операций нет
+    pub fn mean_amount(&self) -> Option<f64> {
+        mean(self.total_amount, self.operations)
+    }
+
+    /// Различные FROM_USER_ID, кроме внешней стороны 0
+    pub fn distinct_from_users(&self) -> usize {
+        self.from_users.len()
+    }
+
+    /// Различные TO_USER_ID, кроме внешней стороны 0
+    pub fn distinct_to_users(&self) -> usize {
+        self.to_users.len()
+    }
+
+    /// Различные пользователи с любой стороны, кроме внешней стороны 0
+    pub fn distinct_users(&self) -> usize {
+        self.from_users.union(&self.to_users).count()
+    }
+
+    /// Сводка в виде JSON (фича `serde`)
+    ///
+    /// Суммы идут строками: i128 не помещается в число JSON без потери точности.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let by_type: Vec<serde_json::Value> = self
+            .by_type
+            .iter()
+            .map(|totals| {
+                serde_json::json!({
+                    "tx_type": totals.tx_type.as_str(),
+                    "count": totals.count,
+                    "total_amount": totals.total_amount.to_string(),
+                    "mean_amount": totals.mean_amount(),
+                })
+            })
+            .collect();
+        let by_status: serde_json::Map<String, serde_json::Value> = self
+            .by_status
+            .iter()
+            .map(|(status, count)| (status.as_str().to_string(), (*count).into()))
+            .collect();
+        let value = serde_json::json!({
+            "operations": self.operations,
+            "by_type": by_type,
+            "by_status": by_status,
+            "total_amount": self.total_amount.to_string(),
+            "min_amount": self.min_amount,
+            "max_amount": self.max_amount,
+            "mean_amount": self.mean_amount(),
+            "first_timestamp": self.first_timestamp,
+            "last_timestamp": self.last_timestamp,
+            "distinct_from_users": self.distinct_from_users(),
+            "distinct_to_users": self.distinct_to_users(),
+            "distinct_users": self.distinct_users(),
+        });
+        serde_json::to_string_pretty(&value).expect("json! value always serializes")
+    }
+}
+
+impl<'a> FromIterator<&'a Operation> for Summary {
+    fn from_iter<I: IntoIterator<Item = &'a Operation>>(ops: I) -> Self {
+        let mut summary = Summary::new();
+        for op in ops {
+            summary.add(op);
+        }
+        summary
+    }
+}
+
+fn mean(total: i128, count: u64) -> Option<f64> {
+    (count > 0).then(|| total as f64 / count as f64)
+}
+
+/// Меньшее из двух значений, где `None` — "еще ничего не видели"
+///
+/// Для большего хватает `Option::max`: `None` меньше любого `Some`.
+fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::OperationBuilder;
+    use crate::options::WriteOptions;
+
+    fn op(
+        tx_id: u64,
+        tx_type: OperationType,
+        users: (u64, u64),
+        amount: i64,
+        status: OperationStatus,
+    ) -> Operation {
+        OperationBuilder::new(tx_type, users.0, users.1, amount)
+            .tx_id(tx_id)
+            .timestamp(1633036800000 + tx_id)
+            .status(status)
+            .description(format!("op {}", tx_id))
+            .build()
+            .unwrap()
+    }
+
+    /// Пополнения 100 и 300, перевод 50 (неудачный), снятие 20 (в ожидании)
+    fn fixture() -> Vec<Operation> {
+        vec![
+            op(
+                3,
+                OperationType::Deposit,
+                (0, 1),
+                100,
+                OperationStatus::Success,
+            ),
+            op(
+                1,
+                OperationType::Deposit,
+                (0, 2),
+                300,
+                OperationStatus::Success,
+            ),
+            op(
+                4,
+                OperationType::Transfer,
+                (1, 3),
+                50,
+                OperationStatus::Failure,
+            ),
+            op(
+                2,
+                OperationType::Withdrawal,
+                (2, 0),
+                20,
+                OperationStatus::Pending,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_every_field_of_a_known_fixture() {
+        let summary: Summary = fixture().iter().collect();
+
+        assert_eq!(summary.operations, 4);
+        assert_eq!(
+            summary.by_type,
+            vec![
+                TypeTotals {
+                    tx_type: OperationType::Deposit,
+                    count: 2,
+                    total_amount: 400,
+                },
+                TypeTotals {
+                    tx_type: OperationType::Transfer,
+                    count: 1,
+                    total_amount: 50,
+                },
+                TypeTotals {
+                    tx_type: OperationType::Withdrawal,
+                    count: 1,
+                    total_amount: 20,
+                },
+            ]
+        );
+        assert_eq!(summary.by_type[0].mean_amount(), Some(200.0));
+        assert_eq!(
+            summary.by_status,
+            vec![
+                (OperationStatus::Success, 2),
+                (OperationStatus::Failure, 1),
+                (OperationStatus::Pending, 1),
+            ]
+        );
+        assert_eq!(summary.total_amount, 470);
+        assert_eq!(summary.min_amount, Some(20));
+        assert_eq!(summary.max_amount, Some(300));
+        assert_eq!(summary.mean_amount(), Some(117.5));
+        assert_eq!(summary.first_timestamp, Some(1633036800001));
+        assert_eq!(summary.last_timestamp, Some(1633036800004));
+        // Внешняя сторона 0 не считается
+        assert_eq!(summary.distinct_from_users(), 2);
+        assert_eq!(summary.distinct_to_users(), 3);
+        assert_eq!(summary.distinct_users(), 3);
+    }
+
+    #[test]
+    fn test_empty_summary() {
+        let summary = Summary::new();
+
+        assert_eq!(summary.operations, 0);
+        assert!(
+            summary
+                .by_type
+                .iter()
+                .all(|t| t.count == 0 && t.mean_amount().is_none())
+        );
+        assert_eq!(summary.mean_amount(), None);
+        assert_eq!(summary.min_amount, None);
+        assert_eq!(summary.first_timestamp, None);
+        assert_eq!(summary.distinct_users(), 0);
+    }
+
+    #[test]
+    fn test_amounts_do_not_overflow() {
+        let ops: Vec<Operation> = (1..=3)
+            .map(|tx_id| {
+                op(
+                    tx_id,
+                    OperationType::Deposit,
+                    (0, 1),
+                    i64::MAX,
+                    OperationStatus::Success,
+                )
+            })
+            .collect();
+
+        let summary = Summary::from_iter(&ops);
+
+        assert_eq!(summary.total_amount, 3 * i128::from(i64::MAX));
+        assert_eq!(summary.by_type[0].total_amount, 3 * i128::from(i64::MAX));
+    }
+
+    #[test]
+    fn test_merge_matches_a_single_pass() {
+        let ops = fixture();
+        let whole: Summary = ops.iter().collect();
+
+        for split in 0..=ops.len() {
+            let mut left: Summary = ops[..split].iter().collect();
+            left.merge(ops[split..].iter().collect());
+            assert_eq!(left, whole, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_from_reader_matches_from_iter() {
+        let ops = fixture();
+        let mut buf = Vec::new();
+        Format::Csv
+            .write_all_with_options(&mut buf, &ops, &WriteOptions::default())
+            .unwrap();
+
+        let streamed =
+            Summary::from_reader(buf.as_slice(), Format::Csv, &ParseOptions::default()).unwrap();
+
+        assert_eq!(streamed, ops.iter().collect());
+
+        #[cfg(feature = "serde")]
+        {
+            let value: serde_json::Value = serde_json::from_str(&streamed.to_json()).unwrap();
+            assert_eq!(value["operations"], 4);
+            assert_eq!(value["by_type"][0]["total_amount"], "400");
+            assert_eq!(value["by_status"]["PENDING"], 1);
+            assert_eq!(value["total_amount"], "470");
+            assert_eq!(value["min_amount"], 20);
+            assert_eq!(value["last_timestamp"], 1633036800004u64);
+            assert_eq!(value["distinct_to_users"], 3);
+        }
+    }
+}
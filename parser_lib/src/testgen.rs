@@ -0,0 +1,260 @@
+//! Правдоподобные синтетические операции для нагрузочных тестов, бенчмарков и фаззинга
+//!
+//! Каждая сгенерированная операция проходит [`Operation::validate`], а TX_ID внутри
+//! одной пачки ([`generate_operations`]) не повторяются. Что и в каких долях
+//! получится, задает [`GenProfile`]; с одним и тем же зерном ГСЧ результат одинаковый.
+//!
+//! С фичей `arbitrary` для [`Operation`] есть `Arbitrary`: фаззер получает сразу
+//! валидные операции, а не байты, которые почти все отсеет `validate`.
+
+use crate::operation::{Operation, OperationStatus, OperationType};
+use std::ops::RangeInclusive;
+
+pub use rand;
+use rand::Rng;
+
+/// Символы обычных описаний
+const PLAIN_CHARS: &[char] = &[
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
+    't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B', 'C', 'D', 'E', 'P', 'R', 'S', 'T', '0', '1', '2',
+    '3', '4', '5', '6', '7', '8', '9', ' ', ' ', ' ',
+];
+
+/// Символы «трудных» описаний: юникод и все, что должны экранировать писатели
+const TRICKY_CHARS: &[char] = &[
+    'ж', 'Ё', 'ü', '€', '日', '🎉', '"', '"', ',', ',', ';', '\\', '\n', '\r', '\t', '#', ':',
+];
+
+/// Каких операций и сколько генерировать
+///
+/// Пустой диапазон (`start > end`) или профиль без весов — паника, как у самого rand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenProfile {
+    /// Веса типов в порядке [`OperationType::ALL`]: DEPOSIT, TRANSFER, WITHDRAWAL
+    pub type_weights: [u32; 3],
+    /// Веса статусов в порядке [`OperationStatus::ALL`]: SUCCESS, FAILURE, PENDING
+    pub status_weights: [u32; 3],
+    /// Пользователи берутся из `1..=users`
+    pub users: u64,
+    /// AMOUNT в минимальных единицах
+    pub amount: RangeInclusive<i64>,
+    /// TIMESTAMP в миллисекундах
+    pub timestamp: RangeInclusive<u64>,
+    /// Длина описания в символах
+    pub description_len: RangeInclusive<usize>,
+    /// Доля описаний с юникодом, кавычками, запятыми, обратной косой чертой и
+    /// переводами строк, от 0.0 до 1.0
+    pub tricky_descriptions: f64,
+    /// TX_ID первой операции пачки, дальше по порядку
+    pub first_tx_id: u64,
+}
+
+impl Default for GenProfile {
+    /// Больше пополнений, почти все успешные, 1000 пользователей, суммы до 10 000.00,
+    /// 2021 год, описания до 40 символов, каждое десятое — «трудное»
+    fn default() -> Self {
+        GenProfile {
+            type_weights: [5, 3, 2],
+            status_weights: [90, 5, 5],
+            users: 1000,
+            amount: 1..=1_000_000,
+            timestamp: 1_609_459_200_000..=1_640_995_199_999,
+            description_len: 0..=40,
+            tricky_descriptions: 0.1,
+            first_tx_id: 1,
+        }
+    }
+}
+
+/// Одна операция по профилю; TX_ID случайный
+pub fn generate_operation(rng: &mut impl Rng, profile: &GenProfile) -> Operation {
+    let tx_type = OperationType::ALL[pick_weighted(rng, &profile.type_weights)].clone();
+    let status = OperationStatus::ALL[pick_weighted(rng, &profile.status_weights)].clone();
+
+    let users = profile.users.max(1);
+    let from = rng.random_range(1..=users);
+    // Перевод самому себе — только если пользователь один
+    let mut to = rng.random_range(1..=users);
+    if to == from && users > 1 {
+        to = to % users + 1;
+    }
+    let (from_user_id, to_user_id) = users_for(&tx_type, from, to);
+
+    let len = rng.random_range(profile.description_len.clone());
+    let tricky = rng.random_bool(profile.tricky_descriptions.clamp(0.0, 1.0));
+    let description = (0..len)
+        .map(|_| {
+            // В «трудном» описании обычные буквы тоже есть, иначе не видно, что вокруг
+            let chars = if tricky && rng.random_bool(0.5) {
+                TRICKY_CHARS
+            } else {
+                PLAIN_CHARS
+            };
+            chars[rng.random_range(0..chars.len())]
+        })
+        .collect();
+
+    Operation {
+        tx_id: rng.random(),
+        tx_type,
+        from_user_id,
+        to_user_id,
+        amount: rng.random_range(profile.amount.clone()),
+        timestamp: rng.random_range(profile.timestamp.clone()),
+        status,
+        description,
+        extensions: Vec::new(),
+    }
+}
+
+/// `n` операций по профилю с TX_ID подряд от [`GenProfile::first_tx_id`]
+pub fn generate_operations(rng: &mut impl Rng, n: usize, profile: &GenProfile) -> Vec<Operation> {
+    (0..n as u64)
+        .map(|i| {
+            let mut operation = generate_operation(rng, profile);
+            operation.tx_id = profile.first_tx_id.wrapping_add(i);
+            operation
+        })
+        .collect()
+}
+
+/// Индекс по весам
+fn pick_weighted(rng: &mut impl Rng, weights: &[u32]) -> usize {
+    let total: u64 = weights.iter().map(|&w| u64::from(w)).sum();
+    assert!(total > 0, "GenProfile: all weights are zero");
+    let mut point = rng.random_range(0..total);
+    for (i, &weight) in weights.iter().enumerate() {
+        if point < u64::from(weight) {
+            return i;
+        }
+        point -= u64::from(weight);
+    }
+    unreachable!("point is below the total weight")
+}
+
+/// Пользователи, которые допускает тип (см. [`Operation::validate`])
+fn users_for(tx_type: &OperationType, from: u64, to: u64) -> (u64, u64) {
+    match tx_type {
+        OperationType::Deposit => (0, to),
+        OperationType::Withdrawal => (from, 0),
+        _ => (from.max(1), to.max(1)),
+    }
+}
+
+/// Любые значения полей, но пользователи подогнаны под тип: операция всегда валидна
+/// и читается обратно из любого формата. Расширений нет
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Operation {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let tx_type = u.choose(&OperationType::ALL)?.clone();
+        let (from_user_id, to_user_id) = users_for(&tx_type, u.arbitrary()?, u.arbitrary()?);
+        Ok(Operation {
+            tx_id: u.arbitrary()?,
+            tx_type,
+            from_user_id,
+            to_user_id,
+            amount: u.arbitrary()?,
+            timestamp: u.arbitrary()?,
+            status: u.choose(&OperationStatus::ALL)?.clone(),
+            description: u.arbitrary()?,
+            extensions: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Format, OperationFormat};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_batch_is_valid_unique_and_within_profile() {
+        let profile = GenProfile {
+            users: 5,
+            first_tx_id: 100,
+            ..GenProfile::default()
+        };
+        let operations = generate_operations(&mut StdRng::seed_from_u64(7), 2000, &profile);
+
+        let ids: HashSet<u64> = operations.iter().map(|op| op.tx_id).collect();
+        assert_eq!(ids.len(), 2000);
+        assert_eq!(*ids.iter().min().unwrap(), 100);
+        for op in &operations {
+            op.validate().unwrap();
+            assert!(profile.amount.contains(&op.amount));
+            assert!(profile.timestamp.contains(&op.timestamp));
+            assert!(op.from_user_id <= 5 && op.to_user_id <= 5);
+            assert!(op.description.chars().count() <= 40);
+        }
+        for tx_type in OperationType::ALL {
+            assert!(operations.iter().any(|op| op.tx_type == tx_type));
+        }
+        assert!(operations.iter().any(|op| op.description.contains('"')));
+        assert!(operations.iter().any(|op| op.description.contains('\n')));
+
+        // То же зерно — та же пачка
+        let again = generate_operations(&mut StdRng::seed_from_u64(7), 2000, &profile);
+        assert!(operations.iter().zip(&again).all(|(a, b)| a.eq_full(b)));
+    }
+
+    #[test]
+    fn test_weights_select_only_allowed_values() {
+        let profile = GenProfile {
+            type_weights: [0, 0, 1],
+            status_weights: [0, 1, 0],
+            tricky_descriptions: 1.0,
+            description_len: 5..=5,
+            ..GenProfile::default()
+        };
+        for op in generate_operations(&mut StdRng::seed_from_u64(1), 100, &profile) {
+            assert_eq!(op.tx_type, OperationType::Withdrawal);
+            assert_eq!(op.status, OperationStatus::Failure);
+            assert_eq!(op.to_user_id, 0);
+            assert_eq!(op.description.chars().count(), 5);
+        }
+    }
+
+    #[test]
+    fn test_generated_operations_survive_every_format() {
+        let profile = GenProfile {
+            tricky_descriptions: 0.5,
+            ..GenProfile::default()
+        };
+        let operations = generate_operations(&mut StdRng::seed_from_u64(42), 300, &profile);
+
+        for format in [
+            Format::Bin,
+            Format::Csv,
+            Format::Txt,
+            #[cfg(feature = "serde")]
+            Format::Json,
+            #[cfg(feature = "serde")]
+            Format::Jsonl,
+        ] {
+            let mut buf = Vec::new();
+            format
+                .write_all_with_options(&mut buf, &operations, &Default::default())
+                .unwrap();
+            let parsed = format.parse_all_vec(buf.as_slice()).unwrap();
+            assert_eq!(parsed.len(), operations.len(), "{}", format.name());
+            for (expected, actual) in operations.iter().zip(&parsed) {
+                assert!(expected.eq_full(actual), "{}: {:?}", format.name(), actual);
+            }
+        }
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_operations_are_valid() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let bytes: Vec<u8> = (0..4096u32).map(|i| (i * 37 % 251) as u8).collect();
+        let mut u = Unstructured::new(&bytes);
+        for _ in 0..50 {
+            Operation::arbitrary(&mut u).unwrap().validate().unwrap();
+        }
+    }
+}
@@ -1,53 +1,575 @@
-use crate::error::{ParseError, Result};
-use crate::operation::{Operation, OperationStatus, OperationType};
+use crate::dedup::{DedupOutcome, DuplicatePolicy};
+use crate::error::{Location, ParseError, Result};
+use crate::footer::Footer;
+use crate::format::Format;
+use crate::format::skip_bom;
+#[cfg(feature = "async")]
+use crate::format::skip_bom_async;
+use crate::migration::{self, SchemaVersion};
+use crate::operation::{
+    FIELD_NAMES, FullOperation, Operation, OperationStatus, OperationType,
+    canonicalize_description, escape_description,
+};
+use crate::options::{ParseOptions, WriteOptions};
+use crate::partition::ParseOutcome;
+use crate::provenance::{Provenance, RecordPosition};
+use crate::report::{Meter, ParseReport};
 use std::collections::{HashMap, HashSet};
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::iter::FusedIterator;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// Читаем с txt файла
 pub fn parse_all<R: Read>(reader: R) -> Result<HashSet<Operation>> {
-    let buf_reader = BufReader::new(reader);
-    let lines = buf_reader.lines().peekable();
-    let mut operations = HashSet::new();
+    parse_all_with_options(reader, &ParseOptions::default())
+}
 
-    let mut current_record: HashMap<String, String> = HashMap::new();
+/// Разбор с пропуском битых записей, см. [`crate::parse_all_lossy`]
+pub fn parse_all_lossy<R: Read>(reader: R, options: &ParseOptions) -> Result<ParseOutcome> {
+    crate::partition::parse_all_lossy(reader, Format::Txt, options)
+}
+
+/// То же, что [`parse_all`], но с настройками (например, токеном отмены)
+pub fn parse_all_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    Format::Txt.parse_all_with_options(reader, options)
+}
+
+/// То же, что [`parse_all_with_options`], плюс отчет с предупреждениями
+pub fn parse_all_with_report<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(HashSet<Operation>, ParseReport)> {
+    Format::Txt.parse_all_with_report(reader, options)
+}
+
+/// Читает все операции в порядке файла, см. [`Format::parse_all_vec`]
+pub fn parse_all_vec<R: Read>(reader: R) -> Result<Vec<Operation>> {
+    Format::Txt.parse_all_vec(reader)
+}
+
+/// То же, что [`parse_all_vec`], но с настройками разбора
+pub fn parse_all_vec_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Vec<Operation>> {
+    Format::Txt.parse_all_vec_with_options(reader, options)
+}
 
-    for line in lines {
-        let line = line?;
+/// Разбор с политикой для повторов TX_ID, см. [`Format::parse_all_with_policy`]
+pub fn parse_all_with_policy<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    policy: DuplicatePolicy,
+) -> Result<DedupOutcome> {
+    Format::Txt.parse_all_with_policy(reader, options, policy)
+}
+
+/// Число повторов каждой записи, см. [`Format::parse_all_counted`]
+pub fn parse_all_counted<R: Read>(reader: R) -> Result<HashMap<FullOperation, u64>> {
+    Format::Txt.parse_all_counted(reader)
+}
+
+/// Записи вместе с происхождением, см. [`Format::parse_all_with_provenance`]
+pub fn parse_all_with_provenance<R: Read>(
+    reader: R,
+    source: Option<&str>,
+    options: &ParseOptions,
+) -> Result<Vec<(Operation, Provenance)>> {
+    Format::Txt.parse_all_with_provenance(reader, source, options)
+}
+
+/// Читает одну запись с текущей позиции потока
+///
+/// Поток должен стоять на начале записи, например по [`Provenance::byte_offset`].
+/// Пустые строки и комментарии перед записью пропускаются, запись кончается
+/// пустой строкой, следующим `TX_ID:` (эта строка тогда уже прочитана) или концом
+/// потока; EOF до записи дает [`ParseError::UnexpectedEof`].
+pub fn parse_one<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Operation> {
+    let mut record: HashMap<String, String> = HashMap::new();
+    let mut line = String::new();
+    let mut line_num = 0u64;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_num += 1;
         let trimmed = line.trim();
+        if trimmed.is_empty() && !record.is_empty() {
+            break;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = parse_key_value(trimmed) {
+            if starts_next_record(&record, key) {
+                break;
+            }
+            insert_field(&mut record, key, value, line_num, options)?;
+        }
+    }
+
+    if record.is_empty() {
+        return Err(ParseError::UnexpectedEof);
+    }
+    let operation = parse_record(&record, options, &mut ParseReport::default())?;
+    operation.validate()?;
+    Ok(operation)
+}
+
+/// Потоковый разбор: отдает записи по одной, ошибка из `on_operation` прерывает разбор
+pub(crate) fn parse_each<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let meter = Meter::start("txt", options);
+    let report = parse_records(meter.reader(reader), options, |operation, position| {
+        meter.record();
+        on_operation(operation, position)
+    })?;
+    Ok(meter.finish(report))
+}
+
+/// Сам разбор для [`parse_each`], без замеров
+fn parse_records<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_operation: impl FnMut(Operation, RecordPosition) -> Result<()>,
+) -> Result<ParseReport> {
+    let mut iter = iter_operations_with_options(reader, options);
+    while let Some(record) = iter.next_record() {
+        let (operation, position) = record?;
+        on_operation(operation, position)?;
+    }
+
+    Ok(iter.state.report)
+}
+
+/// Ленивый разбор: операции по одной в порядке файла, без накопления в памяти
+///
+/// Футер сверяется в конце потока; его расхождение, как и любая другая ошибка,
+/// отдается один раз, после нее итератор больше ничего не отдает.
+pub fn iter_operations<R: Read>(reader: R) -> TextIter<R> {
+    iter_operations_with_options(reader, &ParseOptions::default())
+}
+
+/// То же, что [`iter_operations`], но с настройками разбора
+pub fn iter_operations_with_options<R: Read>(reader: R, options: &ParseOptions) -> TextIter<R> {
+    TextIter {
+        reader: BufReader::new(reader),
+        state: TextState::new(options),
+        started: false,
+        eof: false,
+        done: false,
+    }
+}
+
+/// Итератор из [`iter_operations`]
+pub struct TextIter<R> {
+    reader: BufReader<R>,
+    state: TextState,
+    /// BOM уже пропущен
+    started: bool,
+    /// Поток дочитан, осталось отдать последнюю запись и сверить футер
+    eof: bool,
+    done: bool,
+}
+
+/// Разбор по строкам без самого чтения: его делят синхронный и асинхронный разбор
+struct TextState {
+    options: ParseOptions,
+    report: ParseReport,
+    line_num: u64,
+    byte_offset: u64,
+    current_record: HashMap<String, String>,
+    /// Строка каждого поля текущей записи, для места ошибки
+    field_lines: HashMap<String, u64>,
+    record_start: RecordPosition,
+    totals: Footer,
+    footer: FooterLines,
+}
+
+impl<R: Read> TextIter<R> {
+    /// Отчет о уже прочитанных записях (счетчик, предупреждения, версия схемы)
+    pub fn report(&self) -> &ParseReport {
+        &self.state.report
+    }
+
+    /// Следующая запись вместе с ее началом в потоке
+    fn next_record(&mut self) -> Option<Result<(Operation, RecordPosition)>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_record().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_record(&mut self) -> Result<Option<(Operation, RecordPosition)>> {
+        if !self.started {
+            self.state.byte_offset = skip_bom(&mut self.reader)?;
+            self.started = true;
+        }
+
+        let mut line = String::new();
+        while !self.eof {
+            self.state.options.check_cancelled()?;
+            line.clear();
+            let read = self.reader.read_line(&mut line)?;
+            if read == 0 {
+                self.eof = true;
+                break;
+            }
+            if let Some(record) = self.state.push_line(&line, read as u64)? {
+                return Ok(Some(record));
+            }
+        }
+
+        self.state.finish()
+    }
+}
+
+impl TextState {
+    fn new(options: &ParseOptions) -> Self {
+        TextState {
+            options: options.clone(),
+            report: ParseReport::default(),
+            line_num: 0,
+            byte_offset: 0,
+            current_record: HashMap::new(),
+            field_lines: HashMap::new(),
+            record_start: RecordPosition::default(),
+            totals: Footer::default(),
+            footer: FooterLines::default(),
+        }
+    }
+
+    /// Следующая строка потока длиной `read` байт
+    ///
+    /// # Возвращает
+    /// Запись, которую эта строка закончила (пустая строка или следующий `TX_ID:`)
+    fn push_line(&mut self, line: &str, read: u64) -> Result<Option<(Operation, RecordPosition)>> {
+        let position = RecordPosition {
+            line: Some(self.line_num + 1),
+            byte_offset: Some(self.byte_offset),
+        };
+        self.line_num += 1;
+        self.byte_offset += read;
+        let trimmed = line.trim();
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some(("VERSION", value)) = parse_key_value(comment.trim()) {
+                if self.report.records > 0 || !self.current_record.is_empty() {
+                    return Err(ParseError::InvalidFormat(
+                        "#VERSION must come before the first record".to_string(),
+                    ));
+                }
+                self.report.schema_version = SchemaVersion::parse_pragma(value)?;
+                self.report.schema_version.check_supported(&self.options)?;
+            }
+            self.footer.read(comment);
+        }
 
         // Скип комменты и пуст стр
         if trimmed.is_empty() || trimmed.starts_with('#') {
             // Если до пустой строки чтот читали то считаем что экз операции кончился
-            if !current_record.is_empty() && trimmed.is_empty() {
-                let operation = parse_record(&current_record)?;
-                operation.validate()?;
-                operations.insert(operation);
-                current_record.clear();
+            if !self.current_record.is_empty() && trimmed.is_empty() {
+                return self.finish_record().map(Some);
             }
-            continue;
+            return Ok(None);
         }
+        // Футер только в хвосте файла: комментарии перед данными — не он
+        self.footer = FooterLines::default();
 
         // Парсим клю-значение
-        if let Some((key, value)) = parse_key_value(trimmed) {
-            current_record.insert(key.to_string(), value.to_string());
+        let Some((key, value)) = parse_key_value(trimmed) else {
+            return Ok(None);
+        };
+        // Новый TX_ID без пустой строки перед ним (например, после комментария) — новая запись
+        let finished = if starts_next_record(&self.current_record, key) {
+            Some(self.finish_record()?)
+        } else {
+            None
+        };
+        if self.current_record.is_empty() {
+            self.record_start = position;
         }
+        insert_field(
+            &mut self.current_record,
+            key,
+            value,
+            self.line_num,
+            &self.options,
+        )?;
+        self.field_lines.insert(key.to_string(), self.line_num);
+        Ok(finished)
     }
 
-    // На случай если в конце файла нет пустой стр
-    if !current_record.is_empty() {
-        let operation = parse_record(&current_record)?;
-        operation.validate()?;
+    /// Конец потока: сначала отдает последнюю запись, потом сверяет футер и дает `None`
+    fn finish(&mut self) -> Result<Option<(Operation, RecordPosition)>> {
+        // На случай если в конце файла нет пустой стр
+        if !self.current_record.is_empty() {
+            return self.finish_record().map(Some);
+        }
+
+        self.report.footer = std::mem::take(&mut self.footer).finish();
+        Footer::verify(self.report.footer, self.totals)?;
+        Ok(None)
+    }
+
+    /// Проверяет накопленную запись, добавляет ее в итоги и очищает место под следующую
+    fn finish_record(&mut self) -> Result<(Operation, RecordPosition)> {
+        let version = self.report.schema_version;
+        let upgraded = migration::assemble(
+            &self.current_record,
+            version,
+            &self.options,
+            &mut self.report,
+        )
+        .and_then(|upgraded| upgraded.operation.validate().map(|()| upgraded))
+        .map_err(|e| {
+            let mut location = self.record_start.location(Some(self.report.records as u64));
+            if let ParseError::InvalidField { field, .. } = &e
+                && let Some(&line) = self.field_lines.get(field)
+            {
+                location.line = Some(line);
+            }
+            e.at(location)
+        })?;
+        let operation = upgraded.operation;
+        if !upgraded.extras.is_empty() {
+            self.report.extras.insert(operation.tx_id, upgraded.extras);
+        }
+        self.report.check_rules(&operation, &self.options);
+        self.report.records += 1;
+        self.totals.add(&operation);
+        self.current_record.clear();
+        self.field_lines.clear();
+        Ok((operation, self.record_start))
+    }
+}
+
+impl<R: Read> Iterator for TextIter<R> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+            .map(|result| result.map(|(operation, _)| operation))
+    }
+}
+
+impl<R: Read> FusedIterator for TextIter<R> {}
+
+/// Асинхронный [`parse_all_with_options`]
+///
+/// Строки читаются через `read_line`, а разбирает их тот же код, что и
+/// [`iter_operations`]: ошибки, их места и сверка футера совпадают.
+#[cfg(feature = "async")]
+pub async fn parse_all_async<R: AsyncRead + Unpin>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<HashSet<Operation>> {
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut state = TextState::new(options);
+    state.byte_offset = skip_bom_async(&mut reader).await?;
+
+    let mut operations = HashSet::new();
+    let mut line = String::new();
+    loop {
+        options.check_cancelled()?;
+        line.clear();
+        let read = reader.read_line(&mut line).await?;
+        if read == 0 {
+            break;
+        }
+        if let Some((operation, _)) = state.push_line(&line, read as u64)? {
+            operations.insert(operation);
+        }
+    }
+    while let Some((operation, _)) = state.finish()? {
         operations.insert(operation);
     }
 
     Ok(operations)
 }
 
+/// Строка с `key` открывает следующую запись: в текущей уже есть TX_ID
+fn starts_next_record(record: &HashMap<String, String>, key: &str) -> bool {
+    key == "TX_ID" && record.contains_key("TX_ID")
+}
+
+/// Кладет поле в запись; повтор ключа внутри записи — ошибка,
+/// неизвестный ключ — ошибка с [`ParseOptions::strict`]
+fn insert_field(
+    record: &mut HashMap<String, String>,
+    key: &str,
+    value: &str,
+    line_num: u64,
+    options: &ParseOptions,
+) -> Result<()> {
+    let location = Location {
+        line: Some(line_num),
+        field: Some(key.to_string()),
+        ..Location::default()
+    };
+    if options.strict && !FIELD_NAMES.contains(&key) {
+        return Err(ParseError::InvalidField {
+            field: key.to_string(),
+            reason: "unknown key".to_string(),
+        }
+        .at(location));
+    }
+    if record.insert(key.to_string(), value.to_string()).is_some() {
+        return Err(ParseError::InvalidFormat(format!("duplicate key {}", key)).at(location));
+    }
+    Ok(())
+}
+
+/// Потоковый разбор для карантина: каждая запись вместе с ее сырыми строками
+///
+/// Ошибка разбора записи уходит в `on_record`, а не прерывает чтение.
+/// Комментарии внутри записи попадают в ее сырые строки; футер не сверяется.
+pub(crate) fn parse_each_raw<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+    mut on_record: impl FnMut(Result<Operation>, Vec<u8>, RecordPosition) -> Result<()>,
+) -> Result<()> {
+    let mut report = ParseReport::default();
+    let mut buf_reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut line_num = 0u64;
+    let mut byte_offset = skip_bom(&mut buf_reader)?;
+    let mut seen_records = false;
+
+    let mut current_record: HashMap<String, String> = HashMap::new();
+    let mut raw = String::new();
+    let mut record_start = RecordPosition::default();
+    // Ошибка, найденная еще при сборе записи (повтор ключа)
+    let mut broken: Option<ParseError> = None;
+
+    let mut flush = |current_record: &mut HashMap<String, String>,
+                     raw: &mut String,
+                     broken: &mut Option<ParseError>,
+                     record_start: RecordPosition|
+     -> Result<()> {
+        let result = match broken.take() {
+            Some(e) => Err(e),
+            None => parse_record(current_record, options, &mut report),
+        };
+        if !raw.ends_with('\n') {
+            raw.push('\n');
+        }
+        current_record.clear();
+        on_record(result, std::mem::take(raw).into_bytes(), record_start)
+    };
+
+    loop {
+        options.check_cancelled()?;
+        line.clear();
+        let read = buf_reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        let position = RecordPosition {
+            line: Some(line_num + 1),
+            byte_offset: Some(byte_offset),
+        };
+        line_num += 1;
+        byte_offset += read as u64;
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            if !current_record.is_empty() {
+                flush(&mut current_record, &mut raw, &mut broken, record_start)?;
+                seen_records = true;
+            }
+            continue;
+        }
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some(("VERSION", value)) = parse_key_value(comment.trim()) {
+                if seen_records || !current_record.is_empty() {
+                    return Err(ParseError::InvalidFormat(
+                        "#VERSION must come before the first record".to_string(),
+                    ));
+                }
+                SchemaVersion::parse_pragma(value)?.check_supported(options)?;
+            }
+            if !current_record.is_empty() {
+                raw.push_str(&line);
+            }
+            continue;
+        }
+
+        // Строка без `KEY:` тоже открывает запись (под пустым ключом, который
+        // parse_record не смотрит): иначе мусор между записями пропал бы бесследно
+        let (key, value) = parse_key_value(trimmed).unwrap_or(("", trimmed));
+        if starts_next_record(&current_record, key) {
+            flush(&mut current_record, &mut raw, &mut broken, record_start)?;
+            seen_records = true;
+        }
+        if current_record.is_empty() {
+            record_start = position;
+        }
+        raw.push_str(&line);
+        if key.is_empty() {
+            current_record.insert(String::new(), value.to_string());
+        } else if let Err(e) = insert_field(&mut current_record, key, value, line_num, options) {
+            broken.get_or_insert(e);
+        }
+    }
+
+    if !current_record.is_empty() {
+        flush(&mut current_record, &mut raw, &mut broken, record_start)?;
+    }
+    Ok(())
+}
+
+/// Комментарии футера `# RECORDS: n` и `# TOTAL_AMOUNT: s`
+///
+/// Футер — только оба ключа с числами в комментариях после последней записи;
+/// все прочее (один ключ, не число, комментарий между записями) — обычные комментарии.
+#[derive(Default)]
+struct FooterLines {
+    records: Option<u64>,
+    total_amount: Option<i128>,
+}
+
+impl FooterLines {
+    /// Запоминает ключ футера из текста комментария после `#`; прочие комментарии пропускает
+    fn read(&mut self, comment: &str) {
+        match parse_key_value(comment.trim()) {
+            Some(("RECORDS", value)) => self.records = value.parse().ok(),
+            Some(("TOTAL_AMOUNT", value)) => self.total_amount = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> Option<Footer> {
+        Some(Footer {
+            records: self.records?,
+            total_amount: self.total_amount?,
+        })
+    }
+}
+
 fn parse_key_value(line: &str) -> Option<(&str, &str)> {
     line.split_once(':').map(|(k, v)| (k.trim(), v.trim()))
 }
 
-fn parse_record(record: &HashMap<String, String>) -> Result<Operation> {
+pub(crate) fn parse_record(
+    record: &HashMap<String, String>,
+    options: &ParseOptions,
+    report: &mut ParseReport,
+) -> Result<Operation> {
     let tx_id = record
         .get("TX_ID")
         .ok_or_else(|| ParseError::InvalidFormat("Missing TX_ID".to_string()))?
@@ -57,10 +579,11 @@ fn parse_record(record: &HashMap<String, String>) -> Result<Operation> {
             reason: e.to_string(),
         })?;
 
-    let tx_type = OperationType::from_str(
+    let tx_type = OperationType::parse_field(
         record
             .get("TX_TYPE")
             .ok_or_else(|| ParseError::InvalidFormat("Missing TX_TYPE".to_string()))?,
+        options,
     )?;
 
     let from_user_id = record
@@ -81,35 +604,29 @@ fn parse_record(record: &HashMap<String, String>) -> Result<Operation> {
             reason: e.to_string(),
         })?;
 
-    let amount = record
-        .get("AMOUNT")
-        .ok_or_else(|| ParseError::InvalidFormat("Missing AMOUNT".to_string()))?
-        .parse::<i64>()
-        .map_err(|e| ParseError::InvalidField {
-            field: "AMOUNT".to_string(),
-            reason: e.to_string(),
-        })?;
+    let amount = options.amount_style.parse(
+        record
+            .get("AMOUNT")
+            .ok_or_else(|| ParseError::InvalidFormat("Missing AMOUNT".to_string()))?,
+    )?;
 
     let timestamp = record
         .get("TIMESTAMP")
-        .ok_or_else(|| ParseError::InvalidFormat("Missing TIMESTAMP".to_string()))?
-        .parse::<u64>()
-        .map_err(|e| ParseError::InvalidField {
-            field: "TIMESTAMP".to_string(),
-            reason: e.to_string(),
-        })?;
+        .ok_or_else(|| ParseError::InvalidFormat("Missing TIMESTAMP".to_string()))?;
+    let timestamp = report.parse_timestamp(tx_id, timestamp, options)?;
 
-    let status = OperationStatus::from_str(
+    let status = OperationStatus::parse_field(
         record
             .get("STATUS")
             .ok_or_else(|| ParseError::InvalidFormat("Missing STATUS".to_string()))?,
+        options,
     )?;
 
-    let description = record
-        .get("DESCRIPTION")
-        .ok_or_else(|| ParseError::InvalidFormat("Missing DESCRIPTION".to_string()))?
-        .trim_matches('"')
-        .to_string();
+    let description = canonicalize_description(
+        record
+            .get("DESCRIPTION")
+            .ok_or_else(|| ParseError::InvalidFormat("Missing DESCRIPTION".to_string()))?,
+    );
 
     Ok(Operation {
         tx_id,
@@ -120,27 +637,159 @@ fn parse_record(record: &HashMap<String, String>) -> Result<Operation> {
         timestamp,
         status,
         description,
+        extensions: Vec::new(),
     })
 }
 
 /// Записываем всё в txt
-pub fn write_all<W: Write>(mut writer: W, operations: &HashSet<Operation>) -> Result<()> {
-    for (i, operation) in operations.iter().enumerate() {
-        operation.validate()?;
+pub fn write_all<W: Write>(writer: W, operations: &HashSet<Operation>) -> Result<()> {
+    write_all_with_options(writer, operations, &WriteOptions::default())
+}
 
-        if i > 0 {
-            writeln!(writer)?;
+/// То же, что [`write_all`], но с настройками (например, единицами TIMESTAMP)
+///
+/// Операции пишутся в порядке `operations`, так что можно передать и отсортированный срез.
+/// `writer` оборачивается в [`BufWriter`], так что голый `File` не означает
+/// системный вызов на запись; все дописывается и сбрасывается до возврата.
+pub fn write_all_with_options<'a, W: Write>(
+    writer: W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut writer = TextWriter::new(BufWriter::new(writer)).with_options(options.clone());
+    for operation in operations {
+        writer.write(operation)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Асинхронный [`write_all_with_options`]
+///
+/// Записи кодирует тот же [`TextWriter`] в буфер, в поток они уходят по одной.
+#[cfg(feature = "async")]
+pub async fn write_all_async<'a, W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    operations: impl IntoIterator<Item = &'a Operation>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let mut encoder = TextWriter::new(Vec::new()).with_options(options.clone());
+    for operation in operations {
+        encoder.write(operation)?;
+        writer.write_all(&encoder.writer).await?;
+        encoder.writer.clear();
+    }
+    writer.write_all(&encoder.finish()?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Пишет операции по одной, по мере их появления
+///
+/// Записи разделяются пустой строкой; футер, если он включен, пишется в [`TextWriter::finish`],
+/// так что писатель нужно завершать им, а не бросать.
+///
+/// Запись из восьми строк собирается в памяти и уходит в поток одним `write_all`;
+/// сам поток не буферизуется.
+pub struct TextWriter<W> {
+    writer: W,
+    options: WriteOptions,
+    totals: Footer,
+    /// Запись собирается здесь, буфер переиспользуется
+    buf: Vec<u8>,
+}
+
+impl<W: Write> TextWriter<W> {
+    /// Писатель с настройками по умолчанию
+    pub fn new(writer: W) -> Self {
+        TextWriter {
+            writer,
+            options: WriteOptions::default(),
+            totals: Footer::default(),
+            buf: Vec::new(),
         }
+    }
+
+    /// Настройки записи (футер, единицы TIMESTAMP)
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Проверяет операцию и пишет ее, отделив от предыдущей пустой строкой
+    pub fn write(&mut self, operation: &Operation) -> Result<()> {
+        self.buf.clear();
+        write_record(&mut self.buf, operation, &self.options, &mut self.totals)?;
+        self.writer.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Сбрасывает уже записанное в нижележащий поток
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Дописывает футер и возвращает поток
+    pub fn finish(mut self) -> Result<W> {
+        write_footer(&mut self.writer, self.totals, &self.options)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Пишет одну запись, проверив операцию, и добавляет ее в итоги
+///
+/// Перед всеми записями, кроме первой (по `totals`), ставит пустую строку-разделитель.
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    operation: &Operation,
+    options: &WriteOptions,
+    totals: &mut Footer,
+) -> Result<()> {
+    let operation = options.limit_description(operation)?;
+    operation.validate()?;
 
-        writeln!(writer, "TX_ID: {}", operation.tx_id)?;
-        writeln!(writer, "TX_TYPE: {}", operation.tx_type.as_str())?;
-        writeln!(writer, "FROM_USER_ID: {}", operation.from_user_id)?;
-        writeln!(writer, "TO_USER_ID: {}", operation.to_user_id)?;
-        writeln!(writer, "AMOUNT: {}", operation.amount)?;
-        writeln!(writer, "TIMESTAMP: {}", operation.timestamp)?;
-        writeln!(writer, "STATUS: {}", operation.status.as_str())?;
-        writeln!(writer, "DESCRIPTION: \"{}\"", operation.description)?;
+    if totals.records > 0 {
+        writeln!(writer)?;
     }
+    totals.add(&operation);
 
+    writeln!(writer, "TX_ID: {}", operation.tx_id)?;
+    writeln!(writer, "TX_TYPE: {}", operation.tx_type.as_str())?;
+    writeln!(writer, "FROM_USER_ID: {}", operation.from_user_id)?;
+    writeln!(writer, "TO_USER_ID: {}", operation.to_user_id)?;
+    writeln!(
+        writer,
+        "AMOUNT: {}",
+        options.amount_style.format(operation.amount)
+    )?;
+    writeln!(
+        writer,
+        "TIMESTAMP: {}",
+        options.format_timestamp(operation.timestamp)
+    )?;
+    writeln!(writer, "STATUS: {}", operation.status.as_str())?;
+    writeln!(
+        writer,
+        "DESCRIPTION: {}",
+        escape_description(&operation.description)
+    )?;
+    Ok(())
+}
+
+/// Пишет комментарии футера, если он включен в настройках
+pub(crate) fn write_footer<W: Write>(
+    writer: &mut W,
+    totals: Footer,
+    options: &WriteOptions,
+) -> Result<()> {
+    if options.footer {
+        if totals.records > 0 {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "# RECORDS: {}", totals.records)?;
+        writeln!(writer, "# TOTAL_AMOUNT: {}", totals.total_amount)?;
+    }
     Ok(())
 }
@@ -0,0 +1,512 @@
+//! Перевод TIMESTAMP (миллисекунды Unix) в RFC 3339 и обратно без внешних зависимостей
+//!
+//! Хранится время всегда в UTC; [`TimeZoneSpec`] влияет только на то, как оно показано.
+//! Пояса по имени (`Europe/Moscow`) — за фичей `chrono-tz`.
+//!
+//! С фичей `chrono` RFC 3339 в обычном виде читает chrono, а TIMESTAMP операции
+//! можно получить и задать как `DateTime<Utc>` ([`Operation::datetime`]).
+//! Свой разбор остается для того, чего chrono не читает: годов после 9999
+//! (так [`to_rfc3339`] печатает далекие значения) и смещений без двоеточия.
+
+use crate::error::{ParseError, Result};
+#[cfg(feature = "chrono")]
+use crate::operation::Operation;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, TimeZone, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+/// В каком поясе показывать время
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TimeZoneSpec {
+    /// UTC, суффикс `Z`
+    #[default]
+    Utc,
+    /// Постоянное смещение от UTC в минутах (`+03:00` — это 180)
+    Fixed(i32),
+    /// Пояс IANA с переходами на летнее время (фича `chrono-tz`)
+    #[cfg(feature = "chrono-tz")]
+    Named(chrono_tz::Tz),
+}
+
+impl TimeZoneSpec {
+    /// Смещение от UTC в минутах в момент `millis`
+    #[cfg_attr(not(feature = "chrono-tz"), allow(unused_variables))]
+    pub fn offset_minutes_at(&self, millis: u64) -> i32 {
+        match self {
+            TimeZoneSpec::Utc => 0,
+            TimeZoneSpec::Fixed(minutes) => *minutes,
+            #[cfg(feature = "chrono-tz")]
+            TimeZoneSpec::Named(tz) => {
+                use chrono::{Offset, TimeZone};
+
+                i64::try_from(millis)
+                    .ok()
+                    .and_then(chrono::DateTime::from_timestamp_millis)
+                    .map(|utc| {
+                        tz.offset_from_utc_datetime(&utc.naive_utc())
+                            .fix()
+                            .local_minus_utc()
+                            / 60
+                    })
+                    // За пределами дат chrono показываем UTC
+                    .unwrap_or(0)
+            }
+        }
+    }
+}
+
+impl FromStr for TimeZoneSpec {
+    type Err = ParseError;
+
+    /// `UTC`, `Z`, смещение `+03:00` / `-0530` или имя пояса IANA (с фичей `chrono-tz`)
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(TimeZoneSpec::Utc);
+        }
+        if s.starts_with(['+', '-']) {
+            return parse_offset(s).map(TimeZoneSpec::Fixed);
+        }
+
+        #[cfg(feature = "chrono-tz")]
+        if let Ok(tz) = s.parse::<chrono_tz::Tz>() {
+            return Ok(TimeZoneSpec::Named(tz));
+        }
+
+        let hint = if cfg!(feature = "chrono-tz") {
+            ""
+        } else {
+            " (named zones need the chrono-tz feature)"
+        };
+        Err(ParseError::InvalidFormat(format!(
+            "Unknown time zone '{}'{}",
+            s, hint
+        )))
+    }
+}
+
+impl fmt::Display for TimeZoneSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeZoneSpec::Utc => write!(f, "UTC"),
+            TimeZoneSpec::Fixed(minutes) => write!(f, "{}", format_offset(*minutes)),
+            #[cfg(feature = "chrono-tz")]
+            TimeZoneSpec::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+/// Форматирует миллисекунды от эпохи как RFC 3339 в UTC: `2021-10-01T00:00:00.000Z`
+///
+/// Годы после 9999 выходят за RFC 3339 и печатаются всеми цифрами
+/// (например, для `u64::MAX`), чтобы значение все равно можно было прочитать.
+pub fn to_rfc3339(millis: u64) -> String {
+    let (date_time, ms) = split_millis(millis);
+    format!("{}.{:03}Z", date_time, ms)
+}
+
+/// Форматирует миллисекунды от эпохи как RFC 3339 по местному времени пояса `tz`
+///
+/// Для UTC результат тот же, что у [`to_rfc3339`]. Местное время до 1970 года
+/// (отрицательное смещение у самой эпохи) не показать без знака у года,
+/// поэтому такие моменты печатаются в UTC.
+pub fn to_rfc3339_in(millis: u64, tz: &TimeZoneSpec) -> String {
+    let offset = tz.offset_minutes_at(millis);
+    let shift = i64::from(offset) * 60_000;
+    let local = if shift >= 0 {
+        millis.checked_add(shift as u64)
+    } else {
+        millis.checked_sub(shift.unsigned_abs())
+    };
+
+    match local {
+        Some(local) if offset != 0 => {
+            let (date_time, ms) = split_millis(local);
+            format!("{}.{:03}{}", date_time, ms, format_offset(offset))
+        }
+        _ => to_rfc3339(millis),
+    }
+}
+
+/// Разбирает RFC 3339 с явным поясом (`Z` или `±hh:mm`) в миллисекунды UTC
+///
+/// Дробная часть секунды необязательна; цифры после миллисекунд отбрасываются.
+///
+/// # Возвращает
+/// * `Ok(u64)` - Миллисекунды от эпохи Unix
+/// * `Err(ParseError::InvalidField)` - Строка не RFC 3339, нет пояса или момент раньше 1970 года
+pub fn parse_rfc3339(s: &str) -> Result<u64> {
+    let invalid = |reason: &str| ParseError::InvalidField {
+        field: "TIMESTAMP".to_string(),
+        reason: format!("'{}': {}", s, reason),
+    };
+
+    #[cfg(feature = "chrono")]
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return from_datetime(&datetime).map_err(|_| invalid("before 1970-01-01"));
+    }
+
+    // Год — от 4 цифр: так же, как его печатает to_rfc3339 для дат после 9999
+    let y = s.find('-').filter(|y| (4..=9).contains(y));
+    let bytes = s.as_bytes();
+    let Some(y) = y.filter(|y| {
+        bytes.len() >= y + 16
+            && bytes[y + 3] == b'-'
+            && matches!(bytes[y + 6], b'T' | b't' | b' ')
+            && bytes[y + 9] == b':'
+            && bytes[y + 12] == b':'
+    }) else {
+        return Err(invalid("expected YYYY-MM-DDTHH:MM:SS with a zone"));
+    };
+    let number = |range: std::ops::Range<usize>| -> Result<u64> {
+        let digits = &s[range];
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid("expected digits"));
+        }
+        digits.parse().map_err(|_| invalid("expected digits"))
+    };
+
+    let (year, month, day) = (number(0..y)?, number(y + 1..y + 3)?, number(y + 4..y + 6)?);
+    let (hour, minute, second) = (
+        number(y + 7..y + 9)?,
+        number(y + 10..y + 12)?,
+        number(y + 13..y + 15)?,
+    );
+    if !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid("date or time out of range"));
+    }
+
+    let mut rest = &s[y + 15..];
+    let mut ms = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return Err(invalid("empty fraction of a second"));
+        }
+        for (i, b) in fraction.bytes().take(digits.min(3)).enumerate() {
+            ms += u64::from(b - b'0') * 10u64.pow(2 - i as u32);
+        }
+        rest = &fraction[digits..];
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => parse_offset(rest).map_err(|_| invalid("expected Z or +hh:mm"))?,
+    };
+
+    let local_secs =
+        days_from_civil(year, month, day) * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    let utc_secs = local_secs - i64::from(offset) * 60;
+    let utc_secs = u64::try_from(utc_secs).map_err(|_| invalid("before 1970-01-01"))?;
+    utc_secs
+        .checked_mul(1000)
+        .and_then(|millis| millis.checked_add(ms))
+        .ok_or_else(|| invalid("does not fit in milliseconds"))
+}
+
+/// Миллисекунды от эпохи как момент UTC
+///
+/// # Возвращает
+/// * `Ok(DateTime<Utc>)` - Тот же момент
+/// * `Err(ParseError::InvalidField)` - Значение за пределами дат chrono (после 262143 года)
+#[cfg(feature = "chrono")]
+pub fn to_datetime(millis: u64) -> Result<DateTime<Utc>> {
+    i64::try_from(millis)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+        .ok_or_else(|| ParseError::InvalidField {
+            field: "TIMESTAMP".to_string(),
+            reason: format!("{} is out of the chrono date range", millis),
+        })
+}
+
+/// Момент в любом поясе как миллисекунды от эпохи; доли миллисекунды отбрасываются
+///
+/// # Возвращает
+/// * `Ok(u64)` - Миллисекунды от эпохи Unix
+/// * `Err(ParseError::InvalidField)` - Момент раньше 1970 года: TIMESTAMP беззнаковый
+#[cfg(feature = "chrono")]
+pub fn from_datetime<Tz: TimeZone>(datetime: &DateTime<Tz>) -> Result<u64> {
+    u64::try_from(datetime.timestamp_millis()).map_err(|_| ParseError::InvalidField {
+        field: "TIMESTAMP".to_string(),
+        reason: format!("{} is before 1970-01-01", datetime.to_utc().to_rfc3339()),
+    })
+}
+
+#[cfg(feature = "chrono")]
+impl Operation {
+    /// TIMESTAMP как момент UTC, см. [`to_datetime`]
+    pub fn datetime(&self) -> Result<DateTime<Utc>> {
+        to_datetime(self.timestamp)
+    }
+
+    /// Задает TIMESTAMP моментом в любом поясе, см. [`from_datetime`];
+    /// при ошибке операция не меняется
+    pub fn set_datetime<Tz: TimeZone>(&mut self, datetime: &DateTime<Tz>) -> Result<()> {
+        self.timestamp = from_datetime(datetime)?;
+        Ok(())
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SS` и миллисекунды
+fn split_millis(millis: u64) -> (String, u64) {
+    let secs = millis / 1000;
+    let ms = millis % 1000;
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    let (year, month, day) = civil_from_days(days);
+    let date_time = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    );
+    (date_time, ms)
+}
+
+/// `+03:00`, `-05:30`
+fn format_offset(minutes: i32) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let minutes = minutes.unsigned_abs();
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Смещение `±hh:mm` или `±hhmm` в минутах
+fn parse_offset(s: &str) -> Result<i32> {
+    let invalid = || ParseError::InvalidFormat(format!("Invalid UTC offset '{}'", s));
+
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.len() {
+        5 if rest.as_bytes()[2] == b':' => (&rest[..2], &rest[3..]),
+        4 => (&rest[..2], &rest[2..]),
+        _ => return Err(invalid()),
+    };
+    if !hours
+        .bytes()
+        .chain(minutes.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(sign * (hours * 60 + minutes))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Число дней от 1970-01-01 до даты (обратное к [`civil_from_days`]; годы до 1970 дают
+/// «отрицательные» дни, поэтому результат знаковый)
+fn days_from_civil(year: u64, month: u64, day: u64) -> i64 {
+    let year = year as i64 - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Дата по числу дней от 1970-01-01 (алгоритм Говарда Хиннанта, только для дат после эпохи)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rfc3339() {
+        assert_eq!(to_rfc3339(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(to_rfc3339(1633046400000), "2021-10-01T00:00:00.000Z");
+        assert_eq!(to_rfc3339(951782400123), "2000-02-29T00:00:00.123Z");
+        assert_eq!(to_rfc3339(4102444799999), "2099-12-31T23:59:59.999Z");
+        assert_eq!(to_rfc3339(u64::MAX), "584556019-04-03T14:25:51.615Z");
+    }
+
+    #[test]
+    fn test_fixed_offset_round_trip() {
+        let moscow: TimeZoneSpec = "+03:00".parse().unwrap();
+        assert_eq!(moscow, TimeZoneSpec::Fixed(180));
+        assert_eq!(
+            to_rfc3339_in(1633046400000, &moscow),
+            "2021-10-01T03:00:00.000+03:00"
+        );
+        assert_eq!(
+            to_rfc3339_in(1633046400000, &TimeZoneSpec::Fixed(-330)),
+            "2021-09-30T18:30:00.000-05:30"
+        );
+        assert_eq!(
+            to_rfc3339_in(1633046400000, &TimeZoneSpec::Utc),
+            "2021-10-01T00:00:00.000Z"
+        );
+        // До эпохи по местному времени — показываем в UTC
+        assert_eq!(
+            to_rfc3339_in(0, &TimeZoneSpec::Fixed(-60)),
+            "1970-01-01T00:00:00.000Z"
+        );
+
+        for millis in [0, 951782400123, 1633046400000, 4102444799999, u64::MAX] {
+            for tz in [TimeZoneSpec::Utc, moscow, TimeZoneSpec::Fixed(-330)] {
+                assert_eq!(parse_rfc3339(&to_rfc3339_in(millis, &tz)).unwrap(), millis);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(
+            parse_rfc3339("2021-10-01T00:00:00Z").unwrap(),
+            1633046400000
+        );
+        assert_eq!(
+            parse_rfc3339("2021-10-01T03:00:00.5+03:00").unwrap(),
+            1633046400500
+        );
+        assert_eq!(
+            parse_rfc3339("2021-09-30 19:00:00.123456-0500").unwrap(),
+            1633046400123
+        );
+
+        for bad in [
+            "2021-10-01T00:00:00",
+            "2021-02-29T00:00:00Z",
+            "2021-10-01T24:00:00Z",
+            "2021-10-01T00:00:00.Z",
+            "2021-10-01T00:00:00+3:00",
+            "1970-01-01T00:30:00+01:00",
+            "584556019-04-03T14:25:51.616Z",
+            "21-10-01T00:00:00Z",
+        ] {
+            assert!(parse_rfc3339(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_datetime_epoch_and_before() {
+        use crate::builder::OperationBuilder;
+        use chrono::FixedOffset;
+
+        let mut op = Operation::deposit(7, 100).build().unwrap();
+        assert_eq!(op.datetime().unwrap(), DateTime::UNIX_EPOCH);
+
+        let moscow = FixedOffset::east_opt(3 * 3600).unwrap();
+        let datetime = moscow.with_ymd_and_hms(2021, 10, 1, 3, 0, 0).unwrap();
+        op.set_datetime(&datetime).unwrap();
+        assert_eq!(op.timestamp, 1633046400000);
+        assert_eq!(op.datetime().unwrap(), datetime);
+
+        // До 1970 года TIMESTAMP не бывает
+        let before = Utc.with_ymd_and_hms(1969, 12, 31, 23, 59, 59).unwrap();
+        let err = op.set_datetime(&before).unwrap_err();
+        assert!(err.to_string().contains("before 1970-01-01"), "{}", err);
+        assert_eq!(op.timestamp, 1633046400000);
+        let builder: OperationBuilder = Operation::deposit(7, 100);
+        assert!(builder.timestamp_datetime(&before).is_err());
+
+        op.timestamp = u64::MAX;
+        assert!(op.datetime().is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_datetime_round_trips_through_bin() {
+        use crate::bin_format::{parse_operation, write_operation};
+
+        let datetime = Utc.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap()
+            + chrono::TimeDelta::milliseconds(123);
+        let op = Operation::deposit(7, 100)
+            .tx_id(1)
+            .timestamp_datetime(&datetime)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut buf = Vec::new();
+        write_operation(&mut buf, &op).unwrap();
+        let parsed = parse_operation(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(parsed.datetime().unwrap(), datetime);
+        assert_eq!(
+            parse_rfc3339(&datetime.to_rfc3339()).unwrap(),
+            parsed.timestamp
+        );
+    }
+
+    #[cfg(feature = "chrono-tz")]
+    #[test]
+    fn test_named_zone_across_dst() {
+        let berlin: TimeZoneSpec = "Europe/Berlin".parse().unwrap();
+        // Весной 2021 в 01:00 UTC часы перевели с 02:00 на 03:00
+        let spring = 1616893200000;
+        assert_eq!(
+            to_rfc3339_in(spring - 1, &berlin),
+            "2021-03-28T01:59:59.999+01:00"
+        );
+        assert_eq!(
+            to_rfc3339_in(spring, &berlin),
+            "2021-03-28T03:00:00.000+02:00"
+        );
+        // Осенью в 01:00 UTC — с 03:00 обратно на 02:00: местный час 02:xx повторяется
+        let autumn = 1635642000000;
+        assert_eq!(
+            to_rfc3339_in(autumn - 1, &berlin),
+            "2021-10-31T02:59:59.999+02:00"
+        );
+        assert_eq!(
+            to_rfc3339_in(autumn, &berlin),
+            "2021-10-31T02:00:00.000+01:00"
+        );
+
+        for millis in [spring - 1, spring, autumn - 1, autumn, autumn + 1_800_000] {
+            assert_eq!(
+                parse_rfc3339(&to_rfc3339_in(millis, &berlin)).unwrap(),
+                millis
+            );
+        }
+
+        let moscow: TimeZoneSpec = "Europe/Moscow".parse().unwrap();
+        assert_eq!(moscow.to_string(), "Europe/Moscow");
+        assert_eq!(moscow.offset_minutes_at(spring), 180);
+        assert!("Mars/Olympus".parse::<TimeZoneSpec>().is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! Преобразования набора операций (например, при миграции между системами)
+//!
+//! Поштучные преобразования реализуют [`Transform`] и собираются в [`Pipeline`],
+//! который можно применить к набору или на лету при конвертации
+//! ([`crate::convert::convert_with_pipeline`]).
+
+use crate::error::{ParseError, Result};
+use crate::filter::OperationFilter;
+use crate::operation::{Operation, truncate_at_char_boundary};
+use crate::options::TimestampUnit;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+/// Что делать с пользователем, которого нет в таблице переименования
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MissingUserPolicy {
+    /// Вернуть ошибку
+    #[default]
+    Error,
+    /// Оставить старый id
+    Keep,
+    /// Выбросить операцию целиком
+    Drop,
+}
+
+/// Переписывает FROM_USER_ID/TO_USER_ID по таблице `map` (старый id -> новый)
+///
+/// Пользователь 0 — внешняя сторона, он не переименовывается.
+/// После замены каждая операция заново проходит [`Operation::validate`]:
+/// например, переименование в 0 может нарушить правила для TRANSFER.
+///
+/// # Аргументы
+/// * `ops` - Операции (`Vec`, `HashSet` и т.п.)
+/// * `map` - Таблица переименования
+/// * `policy` - Что делать с id, которых нет в таблице
+pub fn remap_users<C>(ops: C, map: &HashMap<u64, u64>, policy: MissingUserPolicy) -> Result<C>
+where
+    C: IntoIterator<Item = Operation> + FromIterator<Operation>,
+{
+    let mut remapped = Vec::new();
+
+    for mut op in ops {
+        let from = remap_one(op.tx_id, "FROM_USER_ID", op.from_user_id, map, policy)?;
+        let to = remap_one(op.tx_id, "TO_USER_ID", op.to_user_id, map, policy)?;
+
+        let (Some(from), Some(to)) = (from, to) else {
+            continue;
+        };
+        op.from_user_id = from;
+        op.to_user_id = to;
+        op.validate()?;
+        remapped.push(op);
+    }
+
+    Ok(remapped.into_iter().collect())
+}
+
+/// Новый id пользователя; `None` — операцию нужно выбросить
+fn remap_one(
+    tx_id: u64,
+    field: &str,
+    user_id: u64,
+    map: &HashMap<u64, u64>,
+    policy: MissingUserPolicy,
+) -> Result<Option<u64>> {
+    if user_id == 0 {
+        return Ok(Some(0));
+    }
+
+    match (map.get(&user_id), policy) {
+        (Some(&new_id), _) => Ok(Some(new_id)),
+        (None, MissingUserPolicy::Keep) => Ok(Some(user_id)),
+        (None, MissingUserPolicy::Drop) => Ok(None),
+        (None, MissingUserPolicy::Error) => Err(ParseError::InvalidField {
+            field: field.to_string(),
+            reason: format!("tx_id {}: user {} is not in the mapping", tx_id, user_id),
+        }),
+    }
+}
+
+/// Преобразование одной операции
+pub trait Transform: Send + Sync {
+    /// Имя для сообщений об ошибках
+    fn name(&self) -> &str;
+
+    /// Преобразует операцию
+    ///
+    /// # Возвращает
+    /// * `Ok(Some(op))` - Операция после преобразования
+    /// * `Ok(None)` - Операцию нужно выбросить
+    /// * `Err(ParseError)` - Преобразование невозможно
+    fn apply(&self, op: Operation) -> Result<Option<Operation>>;
+}
+
+/// Цепочка преобразований в порядке добавления
+///
+/// После всей цепочки операция заново проходит [`Operation::validate`]:
+/// промежуточные шаги могут временно нарушать правила.
+/// Ошибки оборачиваются в [`ParseError::Transform`] с именем шага и TX_ID.
+#[derive(Default)]
+pub struct Pipeline(pub Vec<Box<dyn Transform>>);
+
+impl Pipeline {
+    /// Пустая цепочка: пропускает операции как есть (но проверяет их)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Добавляет шаг в конец цепочки
+    pub fn then(mut self, transform: impl Transform + 'static) -> Self {
+        self.0.push(Box::new(transform));
+        self
+    }
+
+    /// Есть ли в цепочке шаги
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Прогоняет одну операцию через все шаги
+    pub fn apply(&self, mut op: Operation) -> Result<Option<Operation>> {
+        let tx_id = op.tx_id;
+        for transform in &self.0 {
+            op = match transform.apply(op) {
+                Ok(Some(op)) => op,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(wrap(transform.name(), tx_id, e)),
+            };
+        }
+
+        op.validate().map_err(|e| wrap("validate", tx_id, e))?;
+        Ok(Some(op))
+    }
+
+    /// Прогоняет набор операций; выброшенные шагами операции в результат не попадают
+    pub fn apply_all<C>(&self, ops: C) -> Result<C>
+    where
+        C: IntoIterator<Item = Operation> + FromIterator<Operation>,
+    {
+        let mut transformed = Vec::new();
+        for op in ops {
+            if let Some(op) = self.apply(op)? {
+                transformed.push(op);
+            }
+        }
+        Ok(transformed.into_iter().collect())
+    }
+}
+
+fn wrap(name: &str, tx_id: u64, source: ParseError) -> ParseError {
+    ParseError::Transform {
+        name: name.to_string(),
+        tx_id,
+        source: Box::new(source),
+    }
+}
+
+/// Переименование пользователей, как в [`remap_users`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemapUsers {
+    /// Таблица переименования (старый id -> новый)
+    pub map: HashMap<u64, u64>,
+    /// Что делать с id, которых нет в таблице
+    pub policy: MissingUserPolicy,
+}
+
+impl Transform for RemapUsers {
+    fn name(&self) -> &str {
+        "remap-users"
+    }
+
+    fn apply(&self, mut op: Operation) -> Result<Option<Operation>> {
+        let from = remap_one(
+            op.tx_id,
+            "FROM_USER_ID",
+            op.from_user_id,
+            &self.map,
+            self.policy,
+        )?;
+        let to = remap_one(
+            op.tx_id,
+            "TO_USER_ID",
+            op.to_user_id,
+            &self.map,
+            self.policy,
+        )?;
+
+        let (Some(from), Some(to)) = (from, to) else {
+            return Ok(None);
+        };
+        op.from_user_id = from;
+        op.to_user_id = to;
+        Ok(Some(op))
+    }
+}
+
+/// Заменяет непустое описание на `replacement` (например, перед передачей наружу)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactDescription {
+    /// Чем заменить описание
+    pub replacement: String,
+}
+
+impl Transform for RedactDescription {
+    fn name(&self) -> &str {
+        "redact"
+    }
+
+    fn apply(&self, mut op: Operation) -> Result<Option<Operation>> {
+        if !op.description.is_empty() {
+            op.description = self.replacement.clone();
+        }
+        Ok(Some(op))
+    }
+}
+
+/// Обрезает описание, как [`crate::DescriptionPolicy::TruncateAtCharBoundary`] при записи
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruncateDescription {
+    /// Предел длины в байтах UTF-8 вместе с маркером
+    pub max_len: usize,
+    /// Маркер обрезки
+    pub ellipsis: String,
+}
+
+impl Transform for TruncateDescription {
+    fn name(&self) -> &str {
+        "truncate"
+    }
+
+    fn apply(&self, mut op: Operation) -> Result<Option<Operation>> {
+        op.description = truncate_at_char_boundary(&op.description, self.max_len, &self.ellipsis);
+        Ok(Some(op))
+    }
+}
+
+/// Считает TIMESTAMP записанным в `unit` и переводит его в миллисекунды
+///
+/// Для файлов, которые уже прочитаны как миллисекунды, а на деле были в секундах.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RescaleTimestamps {
+    /// В каких единицах TIMESTAMP на самом деле
+    pub unit: TimestampUnit,
+}
+
+impl Transform for RescaleTimestamps {
+    fn name(&self) -> &str {
+        "rescale"
+    }
+
+    fn apply(&self, mut op: Operation) -> Result<Option<Operation>> {
+        op.timestamp = self.unit.normalize(op.timestamp)?.0;
+        Ok(Some(op))
+    }
+}
+
+/// Отбор как шаг цепочки: неподходящие операции выбрасываются
+impl Transform for OperationFilter {
+    fn name(&self) -> &str {
+        "filter"
+    }
+
+    fn apply(&self, op: Operation) -> Result<Option<Operation>> {
+        Ok(self.matches(&op).then_some(op))
+    }
+}
+
+/// Читает таблицу переименования из CSV `old_id,new_id`
+///
+/// Первая строка может быть заголовком (если в ней не числа). Повтор
+/// старого id — ошибка, чтобы не гадать, какая строка главнее.
+pub fn read_user_map<R: Read>(reader: R) -> Result<HashMap<u64, u64>> {
+    let mut map = HashMap::new();
+
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_num = i + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let (old, new) = trimmed.split_once(',').ok_or_else(|| {
+            ParseError::InvalidFormat(format!("Line {}: expected old_id,new_id", line_num))
+        })?;
+        let (old, new) = match (old.trim().parse::<u64>(), new.trim().parse::<u64>()) {
+            (Ok(old), Ok(new)) => (old, new),
+            _ if i == 0 => continue,
+            _ => {
+                return Err(ParseError::InvalidFormat(format!(
+                    "Line {}: user ids must be numbers",
+                    line_num
+                )));
+            }
+        };
+
+        if map.insert(old, new).is_some() {
+            return Err(ParseError::InvalidFormat(format!(
+                "Line {}: duplicate old_id {}",
+                line_num, old
+            )));
+        }
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn transfer(tx_id: u64, from: u64, to: u64) -> Operation {
+        Operation::transfer(from, to, 10)
+            .tx_id(tx_id)
+            .timestamp(1)
+            .status(OperationStatus::Success)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_remap_with_policies() {
+        let map = HashMap::from([(1, 101), (2, 102)]);
+        let ops = vec![transfer(1, 1, 2), transfer(2, 1, 3)];
+
+        let kept = remap_users(ops.clone(), &map, MissingUserPolicy::Keep).unwrap();
+        let ids: Vec<(u64, u64)> = kept
+            .iter()
+            .map(|op| (op.from_user_id, op.to_user_id))
+            .collect();
+        assert_eq!(ids, vec![(101, 102), (101, 3)]);
+
+        let dropped = remap_users(ops.clone(), &map, MissingUserPolicy::Drop).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].tx_id, 1);
+
+        let err = remap_users(ops, &map, MissingUserPolicy::Error).unwrap_err();
+        assert!(err.to_string().contains("user 3"));
+    }
+
+    #[test]
+    fn test_remap_revalidates() {
+        let map = HashMap::from([(1, 0), (2, 102)]);
+        let result = remap_users(vec![transfer(1, 1, 2)], &map, MissingUserPolicy::Error);
+        assert!(matches!(result, Err(ParseError::InvalidField { .. })));
+    }
+
+    #[test]
+    fn test_pipeline_applies_in_order_and_drops() {
+        let pipeline = Pipeline::new()
+            .then(OperationFilter::new().min_amount(10))
+            .then(RemapUsers {
+                map: HashMap::from([(1, 101), (2, 102)]),
+                policy: MissingUserPolicy::Drop,
+            })
+            .then(RedactDescription {
+                replacement: "[redacted]".to_string(),
+            })
+            .then(TruncateDescription {
+                max_len: 5,
+                ellipsis: "~".to_string(),
+            })
+            .then(RescaleTimestamps {
+                unit: TimestampUnit::Seconds,
+            });
+
+        let mut cheap = transfer(3, 1, 2);
+        cheap.amount = 5;
+        let mut described = transfer(1, 1, 2);
+        described.description = "secret".to_string();
+        let ops = vec![described, transfer(2, 1, 3), cheap];
+
+        let transformed = pipeline.apply_all(ops).unwrap();
+
+        assert_eq!(transformed.len(), 1);
+        let op = &transformed[0];
+        assert_eq!((op.tx_id, op.from_user_id, op.to_user_id), (1, 101, 102));
+        assert_eq!(op.description, "[red~");
+        assert_eq!(op.timestamp, 1000);
+    }
+
+    #[test]
+    fn test_pipeline_errors_name_step_and_tx_id() {
+        let strict = Pipeline::new().then(RemapUsers {
+            map: HashMap::new(),
+            policy: MissingUserPolicy::Error,
+        });
+        let err = strict.apply(transfer(7, 1, 2)).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Transform { name, tx_id: 7, .. } if name == "remap-users")
+        );
+
+        // Шаг сам по себе прошел, но сломал правила TRANSFER
+        let to_zero = Pipeline::new().then(RemapUsers {
+            map: HashMap::from([(1, 0)]),
+            policy: MissingUserPolicy::Keep,
+        });
+        let err = to_zero.apply(transfer(8, 1, 2)).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Transform 'validate' failed on tx_id 8")
+        );
+    }
+
+    #[test]
+    fn test_read_user_map() {
+        let map = read_user_map("old_id,new_id\n1,101\n\n2, 102\n".as_bytes()).unwrap();
+        assert_eq!(map, HashMap::from([(1, 101), (2, 102)]));
+
+        assert!(read_user_map("1,2\n1,3\n".as_bytes()).is_err());
+        assert!(read_user_map("1,2\nx,3\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Полная проверка операции: все нарушения сразу, а не первое
+//!
+//! [`Operation::validate`] остается строгим барьером парсеров и писателей и
+//! смотрит только на пользователей. [`Operation::validate_all`] добавляет
+//! к этому правила [`ValidationRules`] и собирает все находки в [`ValidationReport`].
+
+use crate::error::ParseError;
+use crate::operation::{Operation, OperationType};
+use crate::options::TimestampUnit;
+use std::fmt;
+
+/// 2100-01-01T00:00:00Z в миллисекундах: все, что позже, — явно битое время
+pub const DEFAULT_MAX_TIMESTAMP: u64 = 4_102_444_800_000;
+
+/// Дополнительные правила [`Operation::validate_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValidationRules {
+    /// AMOUNT должен быть больше нуля (для всех нынешних типов)
+    pub positive_amount: bool,
+    /// Самый поздний допустимый TIMESTAMP в миллисекундах; `None` — без предела.
+    /// Нулевой TIMESTAMP отмечается всегда
+    pub max_timestamp: Option<u64>,
+    /// TIMESTAMP меньше этого (но не ноль) похож на секунды вместо миллисекунд;
+    /// `None` — не проверять. По умолчанию [`TimestampUnit::AUTO_THRESHOLD`] (сентябрь 2001)
+    pub min_timestamp: Option<u64>,
+    /// Предел длины описания в байтах UTF-8; `None` — без предела
+    pub max_description_len: Option<usize>,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        ValidationRules {
+            positive_amount: true,
+            max_timestamp: Some(DEFAULT_MAX_TIMESTAMP),
+            min_timestamp: Some(TimestampUnit::AUTO_THRESHOLD),
+            max_description_len: Some(1024 * 1024),
+        }
+    }
+}
+
+/// Насколько серьезна находка
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// Запись неверна: карантин ее отбраковывает
+    Error,
+    /// Запись годна, но подозрительна
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Одно нарушение правила
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// Поле (или поля через `/`), к которому относится нарушение
+    pub field: &'static str,
+    pub reason: String,
+    pub severity: Severity,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.severity.as_str(),
+            self.field,
+            self.reason
+        )
+    }
+}
+
+impl From<ValidationIssue> for ParseError {
+    fn from(issue: ValidationIssue) -> Self {
+        ParseError::InvalidField {
+            field: issue.field.to_string(),
+            reason: issue.reason,
+        }
+    }
+}
+
+/// Все нарушения одной операции в порядке полей
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Есть ли нарушения с [`Severity::Error`]
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// Нарушения с [`Severity::Error`]
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    /// Нарушения с [`Severity::Error`] как ошибки разбора
+    pub fn into_errors(self) -> Vec<ParseError> {
+        self.issues
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(ParseError::from)
+            .collect()
+    }
+}
+
+/// По нарушению на строку
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+impl Operation {
+    /// Все нарушения правил [`Operation::validate`] и [`ValidationRules::default`]
+    ///
+    /// # Возвращает
+    /// * `Ok(())` - Нарушений нет
+    /// * `Err(ValidationReport)` - Все нарушения, включая предупреждения
+    pub fn validate_all(&self) -> std::result::Result<(), ValidationReport> {
+        self.validate_with(&ValidationRules::default())
+    }
+
+    /// То же, что [`Operation::validate_all`], но со своими правилами
+    pub fn validate_with(
+        &self,
+        rules: &ValidationRules,
+    ) -> std::result::Result<(), ValidationReport> {
+        let mut issues = user_id_issues(self);
+
+        if rules.positive_amount && self.amount <= 0 {
+            issues.push(ValidationIssue {
+                field: "AMOUNT",
+                reason: format!("Must be positive, got {}", self.amount),
+                severity: Severity::Error,
+            });
+        }
+        if self.timestamp == 0 {
+            issues.push(ValidationIssue {
+                field: "TIMESTAMP",
+                reason: "Is 0 (not set?)".to_string(),
+                severity: Severity::Warning,
+            });
+        }
+        if let Some(min) = rules.min_timestamp
+            && self.timestamp != 0
+            && self.timestamp < min
+        {
+            issues.push(ValidationIssue {
+                field: "TIMESTAMP",
+                reason: format!(
+                    "{} is earlier than {}: seconds instead of milliseconds?",
+                    self.timestamp, min
+                ),
+                severity: Severity::Warning,
+            });
+        }
+        if let Some(max) = rules.max_timestamp
+            && self.timestamp > max
+        {
+            issues.push(ValidationIssue {
+                field: "TIMESTAMP",
+                reason: format!("{} is later than {}", self.timestamp, max),
+                severity: Severity::Warning,
+            });
+        }
+        if let Some(max_len) = rules.max_description_len
+            && self.description.len() > max_len
+        {
+            issues.push(ValidationIssue {
+                field: "DESCRIPTION",
+                reason: format!("{} bytes, the limit is {}", self.description.len(), max_len),
+                severity: Severity::Warning,
+            });
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationReport { issues })
+        }
+    }
+}
+
+/// Правила пользователей по типу операции: их проверяет и [`Operation::validate`]
+pub(crate) fn user_id_issues(operation: &Operation) -> Vec<ValidationIssue> {
+    let (field, reason) = match operation.tx_type {
+        OperationType::Deposit if operation.from_user_id != 0 => {
+            ("FROM_USER_ID", "Must be 0 for DEPOSIT")
+        }
+        OperationType::Withdrawal if operation.to_user_id != 0 => {
+            ("TO_USER_ID", "Must be 0 for WITHDRAWAL")
+        }
+        OperationType::Transfer if operation.from_user_id == 0 || operation.to_user_id == 0 => {
+            ("FROM_USER_ID/TO_USER_ID", "Cannot be 0 for TRANSFER")
+        }
+        _ => return Vec::new(),
+    };
+    vec![ValidationIssue {
+        field,
+        reason: reason.to_string(),
+        severity: Severity::Error,
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::OperationStatus;
+
+    fn deposit() -> Operation {
+        Operation::deposit(7, 100)
+            .tx_id(1)
+            .timestamp(1633036800000)
+            .status(OperationStatus::Success)
+            .description("salary")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_valid_operation_has_no_issues() {
+        assert!(deposit().validate_all().is_ok());
+    }
+
+    #[test]
+    fn test_collects_every_issue() {
+        let mut op = deposit();
+        op.from_user_id = 5;
+        op.amount = 0;
+        op.timestamp = DEFAULT_MAX_TIMESTAMP + 1;
+        op.description = "x".repeat(11);
+
+        let rules = ValidationRules {
+            max_description_len: Some(10),
+            ..ValidationRules::default()
+        };
+        let report = op.validate_with(&rules).unwrap_err();
+
+        let fields: Vec<_> = report.issues.iter().map(|i| i.field).collect();
+        assert_eq!(
+            fields,
+            ["FROM_USER_ID", "AMOUNT", "TIMESTAMP", "DESCRIPTION"]
+        );
+        assert_eq!(report.errors().count(), 2);
+        // Строгая проверка по-прежнему видит только пользователей
+        assert!(
+            op.validate()
+                .unwrap_err()
+                .to_string()
+                .contains("FROM_USER_ID")
+        );
+        assert_eq!(report.into_errors().len(), 2);
+    }
+
+    #[test]
+    fn test_rules_can_be_relaxed() {
+        let mut op = deposit();
+        op.amount = -5;
+        op.timestamp = u64::MAX;
+
+        let rules = ValidationRules {
+            positive_amount: false,
+            max_timestamp: None,
+            min_timestamp: None,
+            max_description_len: None,
+        };
+        assert!(op.validate_with(&rules).is_ok());
+        assert!(op.validate_all().is_err());
+
+        op.timestamp = 0;
+        let report = op.validate_with(&rules).unwrap_err();
+        assert!(!report.has_errors());
+        assert_eq!(report.issues[0].field, "TIMESTAMP");
+    }
+
+    #[test]
+    fn test_seconds_timestamp_is_a_warning() {
+        let mut op = deposit();
+        op.timestamp = 1633036800;
+
+        let report = op.validate_all().unwrap_err();
+        assert!(!report.has_errors());
+        assert_eq!(report.issues.len(), 1);
+        assert!(
+            report.issues[0]
+                .reason
+                .contains("seconds instead of milliseconds"),
+            "{}",
+            report
+        );
+
+        // Ноль отмечается своим предупреждением, без второго
+        op.timestamp = 0;
+        assert_eq!(op.validate_all().unwrap_err().issues.len(), 1);
+    }
+
+    #[test]
+    fn test_display_lists_issue_per_line() {
+        let mut op = deposit();
+        op.from_user_id = 5;
+        op.amount = -1;
+
+        let report = op.validate_all().unwrap_err();
+
+        assert_eq!(
+            report.to_string(),
+            "error: FROM_USER_ID: Must be 0 for DEPOSIT\nerror: AMOUNT: Must be positive, got -1"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Проверка, что конвертация ничего не потеряла и не исказила
+
+use crate::digest::digest_hex;
+use crate::error::Result;
+use crate::format::Format;
+use crate::operation::Operation;
+use crate::options::ParseOptions;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+
+/// Операция, которая есть с обеих сторон, но с разными полями
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedOperation {
+    /// TX_ID операции
+    pub tx_id: u64,
+    /// Имена отличающихся полей
+    pub fields: Vec<&'static str>,
+}
+
+/// Итоги [`verify_conversion`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// Сколько записей в исходном файле (с повторами TX_ID)
+    pub original_records: usize,
+    /// Сколько записей в результате конвертации
+    pub converted_records: usize,
+    /// [`crate::digest_hex`] исходного набора
+    pub original_digest: String,
+    /// [`crate::digest_hex`] сконвертированного набора
+    pub converted_digest: String,
+    /// TX_ID, которые пропали при конвертации (по возрастанию)
+    pub missing: Vec<u64>,
+    /// TX_ID, которых не было в исходном файле (по возрастанию)
+    pub extra: Vec<u64>,
+    /// Операции с измененными полями (по возрастанию TX_ID)
+    pub changed: Vec<ChangedOperation>,
+}
+
+impl VerificationReport {
+    /// Конвертация без потерь: те же записи, те же поля, тот же дайджест
+    pub fn is_ok(&self) -> bool {
+        self.original_records == self.converted_records
+            && self.original_digest == self.converted_digest
+            && self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return write!(
+                f,
+                "OK: {} records, digest {}",
+                self.original_records, self.original_digest
+            );
+        }
+
+        write!(
+            f,
+            "MISMATCH: {} records -> {} records",
+            self.original_records, self.converted_records
+        )?;
+        if !self.missing.is_empty() {
+            write!(f, "; missing tx_ids {:?}", self.missing)?;
+        }
+        if !self.extra.is_empty() {
+            write!(f, "; extra tx_ids {:?}", self.extra)?;
+        }
+        for changed in &self.changed {
+            write!(
+                f,
+                "; tx_id {} changed {}",
+                changed.tx_id,
+                changed.fields.join(", ")
+            )?;
+        }
+        write!(
+            f,
+            "; digest {} -> {}",
+            self.original_digest, self.converted_digest
+        )
+    }
+}
+
+/// Сравнивает файл с результатом его конвертации
+///
+/// Разбирает обе стороны и сверяет число записей, все поля каждой операции
+/// по TX_ID и дайджесты наборов. Допустимых различий представления нет:
+/// любое несовпадение попадает в отчет.
+///
+/// Повторы TX_ID в исходном файле конвертер схлопывает, поэтому такой файл
+/// проверку не пройдет — по числу записей будет видно, что часть потеряна.
+///
+/// # Возвращает
+/// * `Ok(VerificationReport)` - Обе стороны разобрались, итог в [`VerificationReport::is_ok`]
+/// * `Err(ParseError)` - Одну из сторон не удалось разобрать
+pub fn verify_conversion<R1: Read, R2: Read>(
+    original: R1,
+    original_format: Format,
+    converted: R2,
+    converted_format: Format,
+) -> Result<VerificationReport> {
+    verify_conversion_with_options(
+        original,
+        original_format,
+        converted,
+        converted_format,
+        &ParseOptions::default(),
+    )
+}
+
+/// То же, что [`verify_conversion`], но с настройками разбора для обеих сторон
+///
+/// Например, единицы TIMESTAMP, с которыми файлы читались и писались при конвертации.
+pub fn verify_conversion_with_options<R1: Read, R2: Read>(
+    original: R1,
+    original_format: Format,
+    converted: R2,
+    converted_format: Format,
+    options: &ParseOptions,
+) -> Result<VerificationReport> {
+    let original = original_format.parse_all_vec_with_options(original, options)?;
+    let converted = converted_format.parse_all_vec_with_options(converted, options)?;
+
+    // Как и в HashSet при конвертации, из повторов TX_ID остается первый
+    let original_set: HashSet<Operation> = original.iter().cloned().collect();
+    let converted_set: HashSet<Operation> = converted.iter().cloned().collect();
+    let converted_by_id: HashMap<u64, &Operation> =
+        converted_set.iter().map(|op| (op.tx_id, op)).collect();
+
+    let mut missing = Vec::new();
+    let mut changed = Vec::new();
+    for op in &original_set {
+        match converted_by_id.get(&op.tx_id) {
+            None => missing.push(op.tx_id),
+            Some(other) => {
+                let fields = op.differing_fields(other);
+                if !fields.is_empty() {
+                    changed.push(ChangedOperation {
+                        tx_id: op.tx_id,
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    let mut extra: Vec<u64> = converted_set
+        .difference(&original_set)
+        .map(|op| op.tx_id)
+        .collect();
+
+    missing.sort_unstable();
+    extra.sort_unstable();
+    changed.sort_by_key(|c| c.tx_id);
+
+    Ok(VerificationReport {
+        original_records: original.len(),
+        converted_records: converted.len(),
+        original_digest: digest_hex(&original_set),
+        converted_digest: digest_hex(&converted_set),
+        missing,
+        extra,
+        changed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::golden_fixture;
+    use crate::convert;
+
+    fn write(format: Format, operations: &[Operation]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        format
+            .write_all_with_options(&mut buf, operations, &Default::default())
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_lossless_conversion_verifies() {
+        let cases = golden_fixture();
+        let original = write(Format::Bin, &cases);
+
+        for to in [Format::Csv, Format::Txt] {
+            let mut converted = Vec::new();
+            convert(original.as_slice(), Format::Bin, &mut converted, to).unwrap();
+
+            let report =
+                verify_conversion(original.as_slice(), Format::Bin, converted.as_slice(), to)
+                    .unwrap();
+            assert!(report.is_ok(), "{}", report);
+            assert_eq!(report.converted_records, cases.len());
+        }
+    }
+
+    #[test]
+    fn test_reports_missing_extra_and_changed() {
+        let cases = golden_fixture();
+        let original = write(Format::Bin, &cases);
+
+        let mut altered = cases.clone();
+        let dropped = altered.remove(0).tx_id;
+        altered[0].amount += 1;
+        altered[1].description.push('!');
+        let mut added = altered[2].clone();
+        added.tx_id = u64::MAX - 1;
+        altered.push(added);
+        let converted = write(Format::Csv, &altered);
+
+        let report = verify_conversion(
+            original.as_slice(),
+            Format::Bin,
+            converted.as_slice(),
+            Format::Csv,
+        )
+        .unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.missing, vec![dropped]);
+        assert_eq!(report.extra, vec![u64::MAX - 1]);
+        assert_eq!(
+            report.changed,
+            vec![
+                ChangedOperation {
+                    tx_id: altered[0].tx_id,
+                    fields: vec!["AMOUNT"],
+                },
+                ChangedOperation {
+                    tx_id: altered[1].tx_id,
+                    fields: vec!["DESCRIPTION"],
+                },
+            ]
+        );
+        assert_ne!(report.original_digest, report.converted_digest);
+    }
+
+    #[test]
+    fn test_collapsed_duplicates_fail() {
+        let cases = golden_fixture();
+        let mut with_repeat = cases.clone();
+        with_repeat.push(cases[0].clone());
+        let original = write(Format::Bin, &with_repeat);
+
+        let mut converted = Vec::new();
+        convert(
+            original.as_slice(),
+            Format::Bin,
+            &mut converted,
+            Format::Txt,
+        )
+        .unwrap();
+
+        let report = verify_conversion(
+            original.as_slice(),
+            Format::Bin,
+            converted.as_slice(),
+            Format::Txt,
+        )
+        .unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.original_records, report.converted_records + 1);
+        assert!(report.changed.is_empty());
+    }
+}